@@ -1,10 +1,11 @@
 use crate::fallible::Fallible;
 use crate::hh::HhGoal;
-use crate::{DelayedLiteral, ExClause, SimplifiedAnswer};
+use crate::table::Answer;
+use crate::{AnswerStreamState, DelayedLiteral, ExClause, SimplifiedAnswer};
 use std::fmt::Debug;
 use std::hash::Hash;
 
-crate mod prelude;
+pub(crate) mod prelude;
 
 /// The "context" in which the SLG solver operates. It defines all the
 /// types that the SLG solver may need to refer to, as well as a few
@@ -103,6 +104,11 @@ pub trait Context: Clone + Debug {
     /// A vector of program clauses.
     type ProgramClauses: Debug;
 
+    /// The program clauses an ex-clause was proven with so far, if the
+    /// context records them. They are carried along into the answer
+    /// the ex-clause yields (see `canonicalize_constrained_subst`).
+    type ClauseSources: Clone + Debug + Default;
+
     /// The successful result from unification: contains new subgoals
     /// and things that can be attached to an ex-clause.
     type UnificationResult;
@@ -125,6 +131,22 @@ pub trait ContextOps<C: Context>: Sized + Clone + Debug + AggregateOps<C> {
     /// True if this is a coinductive goal -- e.g., proving an auto trait.
     fn is_coinductive(&self, goal: &C::UCanonicalGoalInEnvironment) -> bool;
 
+    /// True if this goal normalizes a projection, like `<T as
+    /// Iterator>::Item`. Solving one such goal often requires solving
+    /// another, for a nested projection; see `max_projection_depth`.
+    fn is_projection_goal(&self, goal: &C::UCanonicalGoalInEnvironment) -> bool;
+
+    /// The greatest number of projection goals that may be nested
+    /// directly within one another. A projection subgoal that would
+    /// exceed it is not pursued: the strand flounders instead, with
+    /// `CannotProveReason::ProjectionDepth`.
+    fn max_projection_depth(&self) -> usize;
+
+    /// True if `goal` has types nested more deeply than the context
+    /// can deal with. A subgoal for which this holds is not pursued:
+    /// the strand flounders instead, with `CannotProveReason::TooDeep`.
+    fn is_too_deep(&self, goal: &C::UCanonicalGoalInEnvironment) -> bool;
+
     /// Create an inference table for processing a new goal and instantiate that goal
     /// in that context, returning "all the pieces".
     ///
@@ -160,6 +182,14 @@ pub trait ContextOps<C: Context>: Sized + Clone + Debug + AggregateOps<C> {
         canon_ex_clause: &C::CanonicalConstrainedSubst,
     ) -> &C::InferenceNormalizedSubst;
 
+    /// The number of subgoals that remain to be proven in a canonical
+    /// ex-clause. Used to schedule strands (see `Scheduling`).
+    fn num_subgoals(canon_ex_clause: &C::CanonicalExClause) -> usize;
+
+    /// True if a canonical ex-clause has no free inference variables.
+    /// Used to schedule strands (see `Scheduling`).
+    fn is_ground_ex_clause(canon_ex_clause: &C::CanonicalExClause) -> bool;
+
     /// True if this solution has no region constraints.
     fn empty_constraints(ccs: &C::CanonicalConstrainedSubst) -> bool;
 
@@ -184,6 +214,29 @@ pub trait ContextOps<C: Context>: Sized + Clone + Debug + AggregateOps<C> {
         &C::UniverseMap,
         value: &C::CanonicalConstrainedSubst,
     ) -> C::CanonicalConstrainedSubst;
+
+    /// Renumbers the universes of the names in an answer to a goal
+    /// with `num_universes` universes, preserving their order. Two
+    /// answers that differ only in which universes their names landed
+    /// in -- which depends on the order in which `forall` goals were
+    /// instantiated, not on what the answers mean -- are renumbered to
+    /// the same thing, and the second is treated as a duplicate of the
+    /// first. The universes of the goal itself are not renumbered:
+    /// they tell its names apart.
+    fn u_canonicalize_answer(
+        num_universes: usize,
+        value: &C::CanonicalConstrainedSubst,
+    ) -> C::CanonicalConstrainedSubst;
+
+    /// True if `answer`, a new answer to some table, is to be recorded
+    /// alongside the `existing` answers of that table. The forest
+    /// already drops the answers that the table has, or better ones
+    /// (see `Table::push_answer`); a context may drop more -- say,
+    /// those which one of the existing answers subsumes. By default,
+    /// every other answer is recorded.
+    fn should_record_answer(&self, _answer: &Answer<C>, _existing: &[Answer<C>]) -> bool {
+        true
+    }
 }
 
 /// Callback trait for `instantiate_ucanonical_goal`. Unlike the other
@@ -269,7 +322,7 @@ pub trait UnificationOps<C: Context, I: Context> {
     fn instantiate_binders_existentially(&mut self, arg: &I::BindersGoal) -> I::Goal;
 
     // Used by: logic (but for debugging only)
-    fn debug_ex_clause(&mut self, value: &'v ExClause<I>) -> Box<dyn Debug + 'v>;
+    fn debug_ex_clause<'v>(&mut self, value: &'v ExClause<I>) -> Box<dyn Debug + 'v>;
 
     // Used by: logic
     fn canonicalize_goal(&mut self, value: &I::GoalInEnvironment) -> C::CanonicalGoalInEnvironment;
@@ -282,6 +335,7 @@ pub trait UnificationOps<C: Context, I: Context> {
         &mut self,
         subst: I::Substitution,
         constraints: Vec<I::RegionConstraint>,
+        sources: I::ClauseSources,
     ) -> C::CanonicalConstrainedSubst;
 
     // Used by: logic
@@ -303,6 +357,13 @@ pub trait UnificationOps<C: Context, I: Context> {
     // Used by: logic
     fn invert_goal(&mut self, value: &I::GoalInEnvironment) -> Option<I::GoalInEnvironment>;
 
+    /// True if no clause can ever apply to `goal`, whatever the values of
+    /// its inference variables turn out to be: a strand with `goal` as a
+    /// subgoal then fails without the goal being canonicalized or given
+    /// a table. `false` is always a correct answer.
+    // Used by: logic
+    fn never_applies(&mut self, goal: &I::GoalInEnvironment) -> bool;
+
     // Used by: simplify
     fn unify_parameters(
         &mut self,
@@ -352,13 +413,20 @@ pub trait ResolventOps<C: Context, I: Context> {
         clause: &I::ProgramClause,
     ) -> Fallible<C::CanonicalExClause>;
 
+    /// Incorporates an answer to `answer_table_goal`, the canonical
+    /// form of `selected_goal`, into `ex_clause`, which was waiting on
+    /// it. Along with the resulting ex-clause, returns the inference
+    /// variables of `selected_goal` which the answer constrained --
+    /// bound, or unified with one another -- each as a parameter
+    /// referencing the root its unification class had before the
+    /// answer was applied.
     fn apply_answer_subst(
         &mut self,
         ex_clause: ExClause<I>,
         selected_goal: &I::GoalInEnvironment,
         answer_table_goal: &C::CanonicalGoalInEnvironment,
         canonical_answer_subst: &C::CanonicalConstrainedSubst,
-    ) -> Fallible<ExClause<I>>;
+    ) -> Fallible<(ExClause<I>, Vec<I::Parameter>)>;
 }
 
 pub trait AnswerStream<C: Context> {
@@ -369,4 +437,8 @@ pub trait AnswerStream<C: Context> {
     /// if we find any answer for which `test` returns true.
     fn any_future_answer(&mut self, test: impl FnMut(&C::InferenceNormalizedSubst) -> bool)
         -> bool;
+
+    /// Once there are no more answers (`peek_answer` has returned
+    /// `None`), whether every answer was found; `None` until then.
+    fn end_state(&self) -> Option<AnswerStreamState>;
 }
@@ -27,8 +27,8 @@ impl<C: Context> PartialEq for DelayedLiteral<C> {
         }
 
         match (self, other) {
-            (DelayedLiteral::CannotProve(()), DelayedLiteral::CannotProve(())) =>
-                true,
+            (DelayedLiteral::CannotProve(a1), DelayedLiteral::CannotProve(a2)) =>
+                a1 == a2,
 
             (DelayedLiteral::Negative(a1), DelayedLiteral::Negative(a2)) =>
                 a1 == a2,
@@ -49,7 +49,9 @@ impl<C: Context> Hash for DelayedLiteral<C> {
         mem::discriminant(self).hash(hasher);
 
         match self {
-            DelayedLiteral::CannotProve(()) => (),
+            DelayedLiteral::CannotProve(a) => {
+                a.hash(hasher);
+            }
 
             DelayedLiteral::Negative(a) => {
                 a.hash(hasher);
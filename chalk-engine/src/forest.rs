@@ -1,4 +1,4 @@
-use crate::{DepthFirstNumber, SimplifiedAnswer, TableIndex};
+use crate::{AnswerStreamState, DepthFirstNumber, SimplifiedAnswer, TableIndex};
 use crate::context::prelude::*;
 use crate::context::AnswerStream;
 use crate::logic::RootSearchFail;
@@ -8,23 +8,125 @@ use crate::table::{Answer, AnswerIndex};
 
 pub struct Forest<C: Context, CO: ContextOps<C>> {
     #[allow(dead_code)]
-    crate context: CO,
-    crate tables: Tables<C>,
-    crate stack: Stack,
+    pub(crate) context: CO,
+    pub(crate) tables: Tables<C>,
+    pub(crate) stack: Stack,
+    pub(crate) scheduling: Scheduling,
 
     dfn: DepthFirstNumber,
+
+    /// Counts the queries made to the forest, so that `gc` can tell
+    /// which tables were used recently.
+    pub(crate) generation: u64,
+
+    /// Counts the tables created, including those dropped since.
+    pub(crate) tables_created: u64,
+
+    /// Counts the times a strand has been pursued, as a measure of the
+    /// work done by the forest.
+    pub(crate) strand_steps: u64,
+
+    /// Counts the subgoals selected, each of which is looked up in a
+    /// table (created for it if there was none).
+    pub(crate) subgoal_selections: u64,
+
+    /// Counts the answers that were found again, and so discarded.
+    pub(crate) duplicate_answers: u64,
+
+    /// Counts the answers that the context declined to record (see
+    /// `ContextOps::should_record_answer`).
+    pub(crate) declined_answers: u64,
+
+    /// How many tables the forest keeps between queries, if it is
+    /// bounded (see `with_max_cached_tables`).
+    pub(crate) max_cached_tables: Option<usize>,
+
+    /// Counts the tables evicted to stay within `max_cached_tables`.
+    pub(crate) evicted_tables: u64,
+}
+
+/// The order in which a table picks the next strand to pursue.
+///
+/// The scheduling policy never changes *which* answers a goal has,
+/// only the order in which they are discovered. That order is only
+/// observable through the sequence of answers that `force_answers`
+/// (or the answer stream handed to `make_solution`) produces, so a
+/// caller that only looks at the aggregated solution may see it
+/// arrive sooner or later, but not differently.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Scheduling {
+    /// Pursue strands in the order in which they were queued, which
+    /// follows the order of the program clauses. A strand is followed
+    /// down into its subgoals until it yields an answer or fails.
+    DepthFirst,
+
+    /// Pursue first the strand with the fewest subgoals left -- that
+    /// is, the one that is closest to yielding an answer -- so that a
+    /// fact is tried before a sibling clause with a deep proof. This is
+    /// not a breadth-first search: the strand picked is still followed
+    /// down into its subgoals, as under `DepthFirst`.
+    FewestSubgoalsFirst,
+
+    /// Pursue first the strands whose ex-clause has no unresolved
+    /// inference variables, falling back to queue order.
+    PreferGroundClauses,
+}
+
+impl Default for Scheduling {
+    fn default() -> Self {
+        Scheduling::DepthFirst
+    }
+}
+
+/// What a call to `Forest::step` achieved.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Step {
+    /// The requested answer has been found; see `Forest::answers`.
+    Answer,
+
+    /// Some strands were pursued, but the requested answer is not
+    /// found yet: step again to do more work towards it.
+    Pending,
+
+    /// The table has fewer answers than requested, and will never
+    /// find more.
+    NoMoreAnswers,
 }
 
 impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
     pub fn new(context: CO) -> Self {
+        Self::with_scheduling(context, Scheduling::default())
+    }
+
+    pub fn with_scheduling(context: CO, scheduling: Scheduling) -> Self {
         Forest {
             context,
             tables: Tables::new(),
             stack: Stack::default(),
+            scheduling,
             dfn: DepthFirstNumber::MIN,
+            generation: 0,
+            tables_created: 0,
+            strand_steps: 0,
+            subgoal_selections: 0,
+            duplicate_answers: 0,
+            declined_answers: 0,
+            max_cached_tables: None,
+            evicted_tables: 0,
         }
     }
 
+    /// Bounds the number of tables the forest keeps from one query to
+    /// the next, for forests that are used to solve many goals: as each
+    /// query starts, the least recently used tables are evicted until
+    /// there are at most `max_cached_tables` left (see `evict_tables`).
+    /// An evicted table only loses the work done for it: if its goal is
+    /// asked again, a new table is created.
+    pub fn with_max_cached_tables(mut self, max_cached_tables: usize) -> Self {
+        self.max_cached_tables = Some(max_cached_tables);
+        self
+    }
+
     // Gets the next depth-first number. This number never decreases.
     pub(super) fn next_dfn(&mut self) -> DepthFirstNumber {
         self.dfn.next()
@@ -40,7 +142,7 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
         goal: C::UCanonicalGoalInEnvironment,
         num_answers: usize,
     ) -> Vec<Answer<C>> {
-        let table = self.get_or_create_table_for_ucanonical_goal(goal);
+        let table = self.get_or_create_root_table(goal);
         let mut answers = Vec::with_capacity(num_answers);
         for i in 0..num_answers {
             let i = AnswerIndex::from(i);
@@ -62,16 +164,20 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
     /// iterator. Each time you invoke `next`, it will do the work to
     /// extract one more answer. These answers are cached in between
     /// invocations. Invoking `next` fewer times is preferable =)
-    fn iter_answers<'f>(
+    ///
+    /// Trivial answers are not skipped, but they are flagged as such
+    /// (see `SimplifiedAnswer::trivial`).
+    pub fn iter_answers<'f>(
         &'f mut self,
         goal: &C::UCanonicalGoalInEnvironment,
     ) -> impl AnswerStream<C> + 'f {
-        let table = self.get_or_create_table_for_ucanonical_goal(goal.clone());
+        let table = self.get_or_create_root_table(goal.clone());
         let answer = AnswerIndex::ZERO;
         ForestSolver {
             forest: self,
             table,
             answer,
+            end_state: None,
         }
     }
 
@@ -82,6 +188,90 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
         self.context.clone().make_solution(CO::canonical(&goal), self.iter_answers(goal))
     }
 
+    /// Returns the table for `goal`, creating it if needed, so that it
+    /// can be driven by hand with `step` rather than through
+    /// `force_answers` or `solve`. This counts as a query (see `gc`),
+    /// and the table is never dropped, nor evicted, so its index
+    /// remains valid for as long as the forest lives.
+    pub fn table(&mut self, goal: C::UCanonicalGoalInEnvironment) -> TableIndex {
+        let table = self.get_or_create_root_table(goal);
+        self.tables[table].pinned = true;
+        table
+    }
+
+    /// Does one quantum of work towards the answer of `table` with
+    /// index `n`: pursues the strands of `table`, and of the tables it
+    /// depends on, until one of them yields an answer or has to wait.
+    /// Answers are found in order, so the answers before the `n`th
+    /// are found first.
+    ///
+    /// The forest is in a consistent state between two steps: every
+    /// strand that has not been pursued to its end is queued in its
+    /// table (see `pending_strands`).
+    pub fn step(&mut self, table: TableIndex, n: usize) -> Step {
+        match self.ensure_root_answer(table, AnswerIndex::from(n)) {
+            Ok(()) => Step::Answer,
+            Err(RootSearchFail::QuantumExceeded) => Step::Pending,
+            Err(RootSearchFail::NoMoreSolutions) => Step::NoMoreAnswers,
+        }
+    }
+
+    /// Steps until the answer of `table` with index `n` is found.
+    /// Returns false if there is no such answer.
+    pub fn ensure_answer(&mut self, table: TableIndex, n: usize) -> bool {
+        loop {
+            match self.step(table, n) {
+                Step::Answer => return true,
+                Step::NoMoreAnswers => return false,
+                Step::Pending => {}
+            }
+        }
+    }
+
+    /// The answers `table` has found so far, in the order in which they
+    /// were found. Answers are never changed or removed once found, so
+    /// the answers returned now are a prefix of those returned later.
+    pub fn answers(&self, table: TableIndex) -> &[Answer<C>] {
+        self.tables[table].answers()
+    }
+
+    /// The goal that `table` solves.
+    pub fn table_goal(&self, table: TableIndex) -> &C::UCanonicalGoalInEnvironment {
+        &self.tables[table].table_goal
+    }
+
+    /// The ex-clauses of the strands that `table` has yet to pursue, in
+    /// the order in which they were queued (which is not necessarily
+    /// the order in which they will be pursued; see `Scheduling`).
+    pub fn pending_strands(&self, table: TableIndex) -> impl Iterator<Item = &C::CanonicalExClause> {
+        self.tables[table].strands().map(|strand| &strand.canonical_ex_clause)
+    }
+
+    /// True if `table` has no strands left to pursue, and hence will
+    /// never find more answers than it has.
+    pub fn is_exhausted(&self, table: TableIndex) -> bool {
+        self.tables[table].is_exhausted()
+    }
+
+    /// All the tables in the forest, including those created for
+    /// subgoals. Tables that are not root tables may be dropped by
+    /// `gc`, and those not returned by `table` may be evicted (see
+    /// `with_max_cached_tables`), after which their indices must not
+    /// be used.
+    pub fn tables(&self) -> impl Iterator<Item = TableIndex> + '_ {
+        self.tables.indices()
+    }
+
+    /// Starts a new query (and hence a new generation, see `gc`) for
+    /// `goal`, returning its table.
+    fn get_or_create_root_table(&mut self, goal: C::UCanonicalGoalInEnvironment) -> TableIndex {
+        self.generation += 1;
+        let table = self.get_or_create_table_for_ucanonical_goal(goal);
+        self.tables[table].root = true;
+        self.evict_tables(table);
+        table
+    }
+
     /// True if all the tables on the stack starting from `depth` and
     /// continuing until the top of the stack are coinductive.
     ///
@@ -115,6 +305,70 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
         })
     }
 
+    /// True if `table` is on the stack, and some table between it and
+    /// the top of the stack is coinductive. A negative subgoal on
+    /// `table` would then close a cycle through a coinductive table.
+    pub(super) fn closes_coinductive_cycle(&self, table: TableIndex) -> bool {
+        match self.stack.is_active(table) {
+            Some(depth) => self.stack.top_of_stack_from(depth).any(|d| {
+                let table = self.stack[d].table;
+                self.tables[table].coinductive_goal
+            }),
+            None => false,
+        }
+    }
+
+    /// True if `answer`, an answer to `table`, conveys no information
+    /// beyond the fact that the table goal holds: its substitution is
+    /// the identity and it has no region constraints or delayed
+    /// literals.
+    pub(crate) fn is_trivial_answer(&self, table: TableIndex, answer: &Answer<C>) -> bool {
+        answer.delayed_literals.is_empty()
+            && CO::is_trivial_substitution(&self.tables[table].table_goal, &answer.subst)
+            && CO::empty_constraints(&answer.subst)
+    }
+
+    /// The number of tables in the forest. Useful for testing.
+    pub fn num_tables(&self) -> usize {
+        self.tables.len()
+    }
+
+    /// The number of tables created so far, including those that were
+    /// dropped or evicted since.
+    pub fn num_tables_created(&self) -> u64 {
+        self.tables_created
+    }
+
+    /// The number of times a strand has been pursued so far. Useful
+    /// for comparing how much work different strategies take.
+    pub fn num_strand_steps(&self) -> u64 {
+        self.strand_steps
+    }
+
+    /// The number of subgoals that strands have selected so far, and
+    /// looked up in a table.
+    pub fn num_subgoal_selections(&self) -> u64 {
+        self.subgoal_selections
+    }
+
+    /// The number of answers that were discarded because some table
+    /// already had them, or better ones (see `Table::push_answer`).
+    pub fn num_duplicate_answers(&self) -> u64 {
+        self.duplicate_answers
+    }
+
+    /// The number of answers that were discarded because the context
+    /// did not record them (see `ContextOps::should_record_answer`).
+    pub fn num_declined_answers(&self) -> u64 {
+        self.declined_answers
+    }
+
+    /// The number of tables evicted so far to stay within the bound
+    /// given to `with_max_cached_tables`.
+    pub fn num_evicted_tables(&self) -> u64 {
+        self.evicted_tables
+    }
+
     /// Useful for testing.
     pub fn num_cached_answers_for_goal(&mut self, goal: &C::UCanonicalGoalInEnvironment) -> usize {
         let table = self.get_or_create_table_for_ucanonical_goal(goal.clone());
@@ -126,6 +380,9 @@ struct ForestSolver<'forest, C: Context + 'forest, CO: ContextOps<C> + 'forest>
     forest: &'forest mut Forest<C, CO>,
     table: TableIndex,
     answer: AnswerIndex,
+
+    /// Set once the table has no more answers.
+    end_state: Option<AnswerStreamState>,
 }
 
 impl<'forest, C, CO: ContextOps<C>> AnswerStream<C> for ForestSolver<'forest, C, CO>
@@ -151,12 +408,19 @@ where
                     let simplified_answer = SimplifiedAnswer {
                         subst: answer.subst.clone(),
                         ambiguous: !answer.delayed_literals.is_empty(),
+                        trivial: self.forest.is_trivial_answer(self.table, answer),
+                        cannot_prove: answer.delayed_literals.cannot_prove_reason(),
                     };
 
                     return Some(simplified_answer);
                 }
 
                 Err(RootSearchFail::NoMoreSolutions) => {
+                    // Giving up on a strand always leaves an ambiguous
+                    // answer behind, so the answers tell whether the
+                    // search was exhaustive.
+                    let reason = self.forest.tables[self.table].cannot_prove_reason();
+                    self.end_state = Some(AnswerStreamState::from_cannot_prove_reason(reason));
                     return None;
                 }
 
@@ -178,4 +442,8 @@ where
     ) -> bool {
         self.forest.any_future_answer(self.table, self.answer, test)
     }
+
+    fn end_state(&self) -> Option<AnswerStreamState> {
+        self.end_state
+    }
 }
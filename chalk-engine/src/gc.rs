@@ -0,0 +1,196 @@
+use crate::{DelayedLiteral, ExClause, TableIndex};
+use crate::context::prelude::*;
+use crate::context::{InferenceTable, WithInstantiatedExClause};
+use crate::forest::Forest;
+use rustc_hash::FxHashSet;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
+    /// Drops the tables that are no longer needed, so that a forest
+    /// used to solve many goals does not keep growing. A table is kept
+    /// if:
+    ///
+    /// - it was created for a goal given to `force_answers`,
+    ///   `iter_answers` or `solve` (it is a *root* table);
+    /// - it may still find more answers (it has strands left);
+    /// - it was looked up during one of the last `keep_generations`
+    ///   queries; or
+    /// - a table that is kept depends on it, either because one of
+    ///   its strands is waiting on the table's answers, or because
+    ///   one of its answers or strands has a delayed literal on it.
+    ///
+    /// Dropping a table only loses the work done for it: if its goal
+    /// is asked again, a new table is created. Returns the number of
+    /// tables dropped.
+    pub fn gc(&mut self, keep_generations: u64) -> usize {
+        let mut stack: Vec<TableIndex> = self.tables
+            .indices()
+            .filter(|&table| self.must_keep(table, keep_generations))
+            .collect();
+        let mut kept: FxHashSet<TableIndex> = stack.iter().cloned().collect();
+        while let Some(table) = stack.pop() {
+            for referenced in self.tables_referenced_by(table) {
+                if kept.insert(referenced) {
+                    stack.push(referenced);
+                }
+            }
+        }
+
+        let dropped: Vec<TableIndex> = self.tables
+            .indices()
+            .filter(|table| !kept.contains(table))
+            .collect();
+        info!("gc: dropping tables {:?}", dropped);
+        for &table in &dropped {
+            self.remove_table(table);
+        }
+        dropped.len()
+    }
+
+    /// Evicts the least recently used tables, until there are no more
+    /// than `max_cached_tables` (see `with_max_cached_tables`), or no
+    /// more can be evicted. Unlike `gc`, this may evict root tables --
+    /// but not `current`, the table of the query being started, nor
+    /// those of `Forest::table`. A table is only evicted if it is
+    /// complete (it has no strands left), and no table that remains
+    /// depends on it, so evicting tables never changes an answer.
+    ///
+    /// The answers that depend on a table are counted as they are
+    /// found (see `Table::answer_referrers`); the strands that do are
+    /// only looked at here, but only tables that are not complete have
+    /// any.
+    pub(crate) fn evict_tables(&mut self, current: TableIndex) {
+        let max_cached_tables = match self.max_cached_tables {
+            Some(max_cached_tables) => max_cached_tables,
+            None => return,
+        };
+        if self.tables.len() <= max_cached_tables {
+            return;
+        }
+
+        let waited_on: FxHashSet<TableIndex> = self.tables
+            .indices()
+            .filter(|&table| !self.tables[table].is_exhausted())
+            .flat_map(|table| self.tables_referenced_by_strands(table))
+            .collect();
+        let evictable = |forest: &Self, table: TableIndex| {
+            table != current
+                && forest.may_evict(table)
+                && forest.tables[table].answer_referrers == 0
+                && !waited_on.contains(&table)
+        };
+
+        // The least recently used tables come first.
+        let mut candidates: BinaryHeap<Reverse<(u64, TableIndex)>> = self.tables
+            .indices()
+            .filter(|&table| evictable(self, table))
+            .map(|table| Reverse((self.tables[table].last_use, table)))
+            .collect();
+
+        let mut evicted = vec![];
+        while self.tables.len() > max_cached_tables {
+            let table = match candidates.pop() {
+                Some(Reverse((_, table))) => table,
+                None => break,
+            };
+            for referenced in self.remove_table(table) {
+                if evictable(self, referenced) {
+                    candidates.push(Reverse((self.tables[referenced].last_use, referenced)));
+                }
+            }
+            evicted.push(table);
+        }
+        info!("evict_tables: evicting tables {:?}", evicted);
+        self.evicted_tables += evicted.len() as u64;
+    }
+
+    /// Drops `table`, and returns the tables that no answer depends on
+    /// any more as a result.
+    fn remove_table(&mut self, table: TableIndex) -> Vec<TableIndex> {
+        let referenced: Vec<TableIndex> = self.tables[table]
+            .answers()
+            .iter()
+            .flat_map(|answer| answer.delayed_literals.tables())
+            .collect();
+        self.tables.remove(table);
+
+        let mut unreferenced = vec![];
+        for referenced in referenced {
+            if !self.tables.contains(referenced) {
+                continue;
+            }
+            let table_data = &mut self.tables[referenced];
+            table_data.answer_referrers -= 1;
+            if table_data.answer_referrers == 0 {
+                unreferenced.push(referenced);
+            }
+        }
+        unreferenced
+    }
+
+    fn may_evict(&self, table: TableIndex) -> bool {
+        let table_data = &self.tables[table];
+        !table_data.pinned && table_data.is_exhausted() && self.stack.is_active(table).is_none()
+    }
+
+    fn must_keep(&self, table: TableIndex, keep_generations: u64) -> bool {
+        let table_data = &self.tables[table];
+        table_data.root
+            || !table_data.is_exhausted()
+            || self.stack.is_active(table).is_some()
+            || self.generation - table_data.last_use < keep_generations
+    }
+
+    fn tables_referenced_by(&self, table: TableIndex) -> Vec<TableIndex> {
+        let mut referenced = self.tables_referenced_by_strands(table);
+        referenced.extend(
+            self.tables[table]
+                .answers()
+                .iter()
+                .flat_map(|answer| answer.delayed_literals.tables()),
+        );
+        referenced
+    }
+
+    /// The tables that the strands of `table` are waiting on, or have
+    /// delayed literals on.
+    fn tables_referenced_by_strands(&self, table: TableIndex) -> Vec<TableIndex> {
+        let table_data = &self.tables[table];
+        let mut referenced: Vec<TableIndex> = table_data
+            .strands()
+            .filter_map(|strand| strand.selected_subgoal.as_ref())
+            .map(|selected_subgoal| selected_subgoal.subgoal_table)
+            .collect();
+
+        // The delayed literals of a strand are only visible once it is
+        // instantiated.
+        let num_universes = CO::num_universes(&table_data.table_goal);
+        for strand in table_data.strands() {
+            self.context.instantiate_ex_clause(
+                num_universes,
+                &strand.canonical_ex_clause,
+                DelayedTables { tables: &mut referenced },
+            );
+        }
+
+        referenced
+    }
+}
+
+struct DelayedTables<'a> {
+    tables: &'a mut Vec<TableIndex>,
+}
+
+impl<'a, C: Context> WithInstantiatedExClause<C> for DelayedTables<'a> {
+    type Output = ();
+
+    fn with<I: Context>(
+        self,
+        _infer: &mut dyn InferenceTable<C, I>,
+        ex_clause: ExClause<I>,
+    ) {
+        let tables = ex_clause.delayed_literals.iter().filter_map(DelayedLiteral::table);
+        self.tables.extend(tables);
+    }
+}
@@ -49,14 +49,6 @@
 //! - HH: Hereditary harrop predicates. What Chalk deals in.
 //!   Popularized by Lambda Prolog.
 
-#![feature(crate_in_paths)]
-#![feature(crate_visibility_modifier)]
-#![feature(in_band_lifetimes)]
-#![feature(macro_vis_matcher)]
-#![feature(step_trait)]
-#![feature(non_modrs_mods)]
-#![feature(rustc_private)]
-
 #[macro_use]
 extern crate chalk_macros;
 
@@ -74,6 +66,7 @@ pub mod context;
 mod derived;
 pub mod fallible;
 pub mod forest;
+mod gc;
 pub mod hh;
 mod logic;
 mod simplify;
@@ -81,6 +74,9 @@ mod stack;
 mod strand;
 mod table;
 mod tables;
+mod test;
+
+pub use crate::table::Answer;
 
 index_struct! {
     pub struct TableIndex { // FIXME: pub b/c Fold
@@ -124,11 +120,10 @@ pub struct ExClause<C: Context> {
 
     /// Subgoals: literals that must be proven
     pub subgoals: Vec<Literal<C>>,
-}
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
-struct SimplifiedAnswers<C: Context> {
-    answers: Vec<SimplifiedAnswer<C>>,
+    /// The program clauses used to prove this ex-clause so far, if
+    /// they are recorded (see `Context::ClauseSources`).
+    pub sources: C::ClauseSources,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -143,6 +138,20 @@ pub struct SimplifiedAnswer<C: Context> {
     /// either because the size of the answer exceeded `max_size` or
     /// because of a negative loop (e.g., `P :- not { P }`).
     pub ambiguous: bool,
+
+    /// If this flag is set, then the answer is *trivial*: its
+    /// substitution maps each variable of the goal to itself, and it
+    /// carries no region constraints and no delayed literals. Such an
+    /// answer tells us that the goal holds, but nothing about the
+    /// values of its variables (consider `exists<T> { T = T }`), so
+    /// callers enumerating or counting solutions may want to skip it.
+    pub trivial: bool,
+
+    /// If the answer is ambiguous because the strand that produced it
+    /// floundered, the reason why. When it floundered for several
+    /// reasons, the greatest one is reported, so that
+    /// `NegativeCoinductive` is never masked by the others.
+    pub cannot_prove: Option<CannotProveReason>,
 }
 
 #[derive(Debug)]
@@ -176,8 +185,8 @@ struct DelayedLiteralSet<C: Context> {
 #[derive(Clone, Debug)]
 pub enum DelayedLiteral<C: Context> {
     /// Something which can never be proven nor disproven. Inserted
-    /// when truncation triggers; doesn't arise normally.
-    CannotProve(()),
+    /// when a strand flounders; see `CannotProveReason`.
+    CannotProve(CannotProveReason),
 
     /// We are blocked on a negative literal `~G`, where `G` is the
     /// goal of the given table. Because negative goals must always be
@@ -191,6 +200,74 @@ pub enum DelayedLiteral<C: Context> {
     Positive(TableIndex, C::CanonicalConstrainedSubst),
 }
 
+/// Why a strand floundered, producing a `DelayedLiteral::CannotProve`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CannotProveReason {
+    /// We could not create a table for a subgoal: either it was a
+    /// negative goal with free existential variables, or it was too
+    /// large.
+    Floundered,
+
+    /// A subgoal normalizing a projection was nested in too many
+    /// others (see `ContextOps::max_projection_depth`), so it was not
+    /// pursued.
+    ProjectionDepth,
+
+    /// The answer exceeded the maximum size and was truncated.
+    Truncated,
+
+    /// A negative subgoal closed a cycle through a coinductive
+    /// table. The well-founded semantics assume that negative
+    /// dependencies are inductive, so we cannot give any answer in
+    /// that case: a coinductive table assumes itself to hold, which
+    /// would let `not { G }` be disproven by `G` itself.
+    NegativeCoinductive,
+
+    /// A goal had types nested more deeply than the solver allows (see
+    /// `ContextOps::is_too_deep`), so it was not solved at all. Users
+    /// of the engine check their root goals themselves before handing
+    /// them over; the engine checks the subgoals.
+    TooDeep,
+}
+
+/// Why an `AnswerStream` has no more answers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AnswerStreamState {
+    /// Every answer to the goal has been found.
+    Complete,
+
+    /// The search gave up on some strands, which produced ambiguous
+    /// answers instead (see `SimplifiedAnswer::cannot_prove`): there
+    /// may be answers that were not found.
+    Floundered(CannotProveReason),
+
+    /// Some answers were too large and were truncated: they stand for
+    /// more specific answers, which were not enumerated. When the
+    /// search also gave up for other reasons, the greatest of them all
+    /// (in the order of `CannotProveReason`) is reported, so this takes
+    /// precedence over `Floundered(Floundered)` and
+    /// `Floundered(ProjectionDepth)`, but not over the reasons declared
+    /// after `Truncated`.
+    TruncatedAnswersPossible,
+}
+
+impl AnswerStreamState {
+    /// The state of a stream that ended after answers for which
+    /// the search gave up for `reason` -- or for none, if `None`.
+    fn from_cannot_prove_reason(reason: Option<CannotProveReason>) -> Self {
+        match reason {
+            None => AnswerStreamState::Complete,
+            Some(CannotProveReason::Truncated) => AnswerStreamState::TruncatedAnswersPossible,
+            Some(reason @ CannotProveReason::Floundered)
+            | Some(reason @ CannotProveReason::ProjectionDepth)
+            | Some(reason @ CannotProveReason::NegativeCoinductive)
+            | Some(reason @ CannotProveReason::TooDeep) => {
+                AnswerStreamState::Floundered(reason)
+            }
+        }
+    }
+}
+
 /// Either `A` or `~A`, where `A` is a `Env |- Goal`.
 #[derive(Clone, Debug)]
 pub enum Literal<C: Context> { // FIXME: pub b/c fold
@@ -281,6 +358,33 @@ impl<C: Context> DelayedLiteralSet<C> {
             .iter()
             .all(|elem| other.delayed_literals.contains(elem))
     }
+
+    /// The tables that the literals of this set are waiting on.
+    fn tables(&self) -> impl Iterator<Item = TableIndex> + '_ {
+        self.delayed_literals.iter().filter_map(DelayedLiteral::table)
+    }
+
+    /// The greatest reason among the `CannotProve` literals of this
+    /// set, if there are any.
+    fn cannot_prove_reason(&self) -> Option<CannotProveReason> {
+        self.delayed_literals
+            .iter()
+            .filter_map(|literal| match literal {
+                DelayedLiteral::CannotProve(reason) => Some(*reason),
+                _ => None,
+            })
+            .max()
+    }
+}
+
+impl<C: Context> DelayedLiteral<C> {
+    /// The table this literal is waiting on, if any.
+    fn table(&self) -> Option<TableIndex> {
+        match self {
+            DelayedLiteral::CannotProve(_) => None,
+            DelayedLiteral::Negative(table) | DelayedLiteral::Positive(table, _) => Some(*table),
+        }
+    }
 }
 
 impl Minimums {
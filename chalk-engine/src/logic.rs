@@ -1,8 +1,8 @@
-use crate::{DelayedLiteral, DelayedLiteralSet, DepthFirstNumber, ExClause, Literal, Minimums,
-            TableIndex};
+use crate::{CannotProveReason, DelayedLiteral, DelayedLiteralSet, DepthFirstNumber, ExClause,
+            Literal, Minimums, TableIndex};
 use crate::fallible::NoSolution;
 use crate::context::{WithInstantiatedExClause, WithInstantiatedUCanonicalGoal, prelude::*};
-use crate::forest::Forest;
+use crate::forest::{Forest, Scheduling};
 use crate::hh::HhGoal;
 use crate::stack::StackIndex;
 use crate::strand::{CanonicalStrand, SelectedSubgoal, Strand};
@@ -183,10 +183,24 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
         result.map(|()| EnsureSuccess::AnswerAvailable)
     }
 
-    crate fn answer(&self, table: TableIndex, answer: AnswerIndex) -> &Answer<C> {
+    pub(crate) fn answer(&self, table: TableIndex, answer: AnswerIndex) -> &Answer<C> {
         self.tables[table].answer(answer).unwrap()
     }
 
+    /// Removes the strand that `table` should pursue next, according
+    /// to the scheduling policy of the forest.
+    fn select_next_strand(&mut self, table: TableIndex) -> Option<CanonicalStrand<C>> {
+        match self.scheduling {
+            Scheduling::DepthFirst => self.tables[table].pop_next_strand(),
+            Scheduling::FewestSubgoalsFirst => self.tables[table].pop_strand_min_by_key(|strand| {
+                CO::num_subgoals(&strand.canonical_ex_clause)
+            }),
+            Scheduling::PreferGroundClauses => self.tables[table].pop_strand_min_by_key(|strand| {
+                !CO::is_ground_ex_clause(&strand.canonical_ex_clause)
+            }),
+        }
+    }
+
     /// Selects the next eligible strand from the table at depth
     /// `depth` and pursues it. If that strand encounters a cycle,
     /// then this function will loop and keep trying strands until it
@@ -204,7 +218,7 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
         let mut cyclic_minimums = Minimums::MAX;
 
         loop {
-            match self.tables[table].pop_next_strand() {
+            match self.select_next_strand(table) {
                 Some(canonical_strand) => {
                     let num_universes = CO::num_universes(&self.tables[table].table_goal);
                     let result = Self::with_instantiated_strand(
@@ -469,6 +483,7 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
         depth: StackIndex,
         mut strand: Strand<'_, C, impl Context>,
     ) -> StrandResult<C, ()> {
+        self.strand_steps += 1;
         info_heading!(
             "pursue_strand(table={:?}, depth={:?}, ex_clause={:#?}, selected_subgoal={:?})",
             self.stack[depth].table,
@@ -492,12 +507,23 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
             // to pick things like `?T: Sized` if we can help it.
             let subgoal_index = strand.ex_clause.subgoals.len() - 1;
 
+            // A positive subgoal that no clause can apply to fails the
+            // strand, and there is no need for a table to find that out.
+            if let Literal::Positive(subgoal) = &strand.ex_clause.subgoals[subgoal_index] {
+                if strand.infer.never_applies(subgoal) {
+                    info!("no clause can apply to the subgoal, failing the strand");
+                    return Err(StrandFail::NoSolution);
+                }
+            }
+
             // Get or create table for this subgoal.
+            self.subgoal_selections += 1;
             match self.get_or_create_table_for_subgoal(
+                depth,
                 &mut *strand.infer,
                 &strand.ex_clause.subgoals[subgoal_index],
             ) {
-                Some((subgoal_table, universe_map)) => {
+                Ok((subgoal_table, universe_map)) => {
                     strand.selected_subgoal = Some(SelectedSubgoal {
                         subgoal_index,
                         subgoal_table,
@@ -506,7 +532,7 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
                     });
                 }
 
-                None => {
+                Err(reason) => {
                     // If we failed to create a table for the subgoal,
                     // then the execution has "floundered" (cannot yield
                     // a complete result). We choose to handle this by
@@ -517,7 +543,7 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
                     strand
                         .ex_clause
                         .delayed_literals
-                        .push(DelayedLiteral::CannotProve(()));
+                        .push(DelayedLiteral::CannotProve(reason));
                 }
             }
         }
@@ -552,16 +578,17 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
                     constraints,
                     delayed_literals,
                     subgoals,
+                    sources,
                 },
             selected_subgoal: _,
         } = strand;
         assert!(subgoals.is_empty());
 
-        let answer_subst = infer.canonicalize_constrained_subst(subst, constraints);
+        let answer_subst = infer.canonicalize_constrained_subst(subst, constraints, sources);
         debug!("answer: table={:?}, answer_subst={:?}", table, answer_subst);
 
         let delayed_literals = {
-            let mut delayed_literals: FxHashSet<_> = delayed_literals.into_iter()
+            let delayed_literals: FxHashSet<_> = delayed_literals.into_iter()
                 .map(|dl| infer.lift_delayed_literal(dl))
                 .collect();
             DelayedLiteralSet { delayed_literals }
@@ -631,13 +658,21 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
         // of proving things from the environment (though the latter
         // is a *bit* suspect; e.g., those things in the environment
         // must be backed by an impl *eventually*).
-        let is_trivial_answer = {
-            answer.delayed_literals.is_empty()
-                && CO::is_trivial_substitution(&self.tables[table].table_goal, &answer.subst)
-                && CO::empty_constraints(&answer.subst)
-        };
+        let is_trivial_answer = self.is_trivial_answer(table, &answer);
 
-        if self.tables[table].push_answer(answer) {
+        if !self.context.should_record_answer(&answer, self.tables[table].answers()) {
+            info!("answer: not recorded by the context, returning StrandFail::NoSolution");
+            self.declined_answers += 1;
+            return Err(StrandFail::NoSolution);
+        }
+
+        let num_universes = CO::num_universes(&self.tables[table].table_goal);
+        let key = CO::u_canonicalize_answer(num_universes, &answer.subst);
+        let delayed_tables: Vec<TableIndex> = answer.delayed_literals.tables().collect();
+        if self.tables[table].push_answer(answer, key) {
+            for delayed_table in delayed_tables {
+                self.tables[delayed_table].answer_referrers += 1;
+            }
             if is_trivial_answer {
                 self.tables[table].take_strands();
             }
@@ -645,6 +680,7 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
             Ok(())
         } else {
             info!("answer: not a new answer, returning StrandFail::NoSolution");
+            self.duplicate_answers += 1;
             Err(StrandFail::NoSolution)
         }
     }
@@ -654,34 +690,61 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
     /// returned, but otherwise a new table is created (and populated
     /// with its initial set of strands).
     ///
-    /// Returns `None` if the literal cannot be converted into a table
+    /// Returns an error if the literal cannot be converted into a table
     /// -- for example, this can occur when we have selected a
-    /// negative literal with free existential variables, in which
-    /// case the execution is said to "flounder".
+    /// negative literal with free existential variables, or a
+    /// projection goal nested in too many others (the strand at
+    /// `depth` being the innermost), or a goal with types nested too
+    /// deeply, in which case the execution is said to "flounder".
     ///
     /// In terms of the NFTD paper, creating a new table corresponds
     /// to the *New Subgoal* step as well as the *Program Clause
     /// Resolution* steps.
     fn get_or_create_table_for_subgoal<I: Context>(
         &mut self,
+        depth: StackIndex,
         infer: &mut dyn InferenceTable<C, I>,
         subgoal: &Literal<I>,
-    ) -> Option<(TableIndex, C::UniverseMap)> {
+    ) -> Result<(TableIndex, C::UniverseMap), CannotProveReason> {
         debug_heading!("get_or_create_table_for_subgoal(subgoal={:?})", subgoal);
 
         // Subgoal abstraction:
         let canonical_subgoal = match subgoal {
             Literal::Positive(subgoal) => self.abstract_positive_literal(infer, subgoal),
-            Literal::Negative(subgoal) => self.abstract_negative_literal(infer, subgoal)?,
+            Literal::Negative(subgoal) => self
+                .abstract_negative_literal(infer, subgoal)
+                .ok_or(CannotProveReason::Floundered)?,
         };
 
         debug!("canonical_subgoal={:?}", canonical_subgoal);
 
         let (ucanonical_subgoal, universe_map) = infer.u_canonicalize_goal(&canonical_subgoal);
 
+        if self.context.is_projection_goal(&ucanonical_subgoal)
+            && self.projection_depth(depth) >= self.context.max_projection_depth()
+        {
+            info!("projection depth exceeded, floundering");
+            return Err(CannotProveReason::ProjectionDepth);
+        }
+
+        if self.context.is_too_deep(&ucanonical_subgoal) {
+            info!("type depth exceeded, floundering");
+            return Err(CannotProveReason::TooDeep);
+        }
+
         let table = self.get_or_create_table_for_ucanonical_goal(ucanonical_subgoal);
 
-        Some((table, universe_map))
+        Ok((table, universe_map))
+    }
+
+    /// The number of projection goals (see
+    /// `ContextOps::is_projection_goal`) directly nested within one
+    /// another at the top of the stack, starting from `depth`.
+    fn projection_depth(&self, depth: StackIndex) -> usize {
+        self.stack
+            .tables_from(depth)
+            .take_while(|&table| self.context.is_projection_goal(&self.tables[table].table_goal))
+            .count()
     }
 
     /// Given a u-canonical goal, searches for an existing table. If
@@ -691,7 +754,7 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
     /// In terms of the NFTD paper, creating a new table corresponds
     /// to the *New Subgoal* step as well as the *Program Clause
     /// Resolution* steps.
-    crate fn get_or_create_table_for_ucanonical_goal(
+    pub(crate) fn get_or_create_table_for_ucanonical_goal(
         &mut self,
         goal: C::UCanonicalGoalInEnvironment,
     ) -> TableIndex {
@@ -699,6 +762,7 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
 
         if let Some(table) = self.tables.index_of(&goal) {
             debug!("found existing table {:?}", table);
+            self.tables[table].last_use = self.generation;
             return table;
         }
 
@@ -709,6 +773,8 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
         );
         let coinductive_goal = self.context.is_coinductive(&goal);
         let table = self.tables.insert(goal, coinductive_goal);
+        self.tables_created += 1;
+        self.tables[table].last_use = self.generation;
         self.push_initial_strands(table);
         table
     }
@@ -737,7 +803,7 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
             this: &'a mut Forest<C, CO>,
         }
 
-        impl<C: Context, CO: ContextOps<C>> WithInstantiatedUCanonicalGoal<C> 
+        impl<'a, C: Context, CO: ContextOps<C>> WithInstantiatedUCanonicalGoal<C>
                                                 for PushInitialStrandsInstantiated<'a, C, CO> {
             type Output = ();
 
@@ -1056,11 +1122,15 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
         let answer_subst =
             &CO::map_subst_from_canonical(&universe_map, &self.answer(subgoal_table, answer_index).subst);
         match infer.apply_answer_subst(ex_clause, &subgoal, table_goal, answer_subst) {
-            Ok(mut ex_clause) => {
+            Ok((mut ex_clause, constrained)) => {
+                info!("pursue_positive_subgoal: answer constrained {:?}", constrained);
+
                 // If the answer had delayed literals, we have to
                 // ensure that `ex_clause` is also delayed. This is
                 // the SLG FACTOR operation, though NFTD just makes it
-                // part of computing the SLG resolvent.
+                // part of computing the SLG resolvent. If the search
+                // gave up on the answer, it gives up on `ex_clause`
+                // too, for the same reason.
                 {
                     let answer = self.answer(subgoal_table, answer_index);
                     if !answer.delayed_literals.is_empty() {
@@ -1069,6 +1139,9 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
                             infer.sink_answer_subset(&answer.subst),
                         ));
                     }
+                    if let Some(reason) = answer.delayed_literals.cannot_prove_reason() {
+                        ex_clause.delayed_literals.push(DelayedLiteral::CannotProve(reason));
+                    }
                 }
 
                 // Apply answer abstraction.
@@ -1151,9 +1224,10 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
 
                 ExClause {
                     subst: truncated_subst,
-                    delayed_literals: vec![DelayedLiteral::CannotProve(())],
+                    delayed_literals: vec![DelayedLiteral::CannotProve(CannotProveReason::Truncated)],
                     constraints: vec![],
                     subgoals: vec![],
+                    sources: ex_clause.sources,
                 }
             }
         }
@@ -1166,7 +1240,7 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
         depth: StackIndex,
         strand: Strand<'_, C, impl Context>,
     ) -> StrandResult<C, ()> {
-        ::crate::maybe_grow_stack(|| self.pursue_strand(depth, strand))
+        crate::maybe_grow_stack(|| self.pursue_strand(depth, strand))
     }
 
     /// Invoked when we have found a successful answer to the given
@@ -1202,6 +1276,32 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
             universe_map: _,
         } = *selected_subgoal;
 
+        // A negative edge that closes a cycle through a coinductive
+        // table cannot be followed: the coinductive table would be
+        // assumed to hold while we are trying to disprove it, and the
+        // well-founded semantics only account for inductive negative
+        // dependencies. So the strand flounders instead.
+        if self.closes_coinductive_cycle(subgoal_table) {
+            info!("pursue_negative_subgoal: negative edge into coinductive cycle -> CannotProve");
+            let Strand {
+                infer,
+                mut ex_clause,
+                selected_subgoal: _,
+            } = strand;
+            ex_clause.subgoals.remove(selected_subgoal.subgoal_index);
+            ex_clause
+                .delayed_literals
+                .push(DelayedLiteral::CannotProve(CannotProveReason::NegativeCoinductive));
+            return self.pursue_strand_recursively(
+                depth,
+                Strand {
+                    infer,
+                    ex_clause,
+                    selected_subgoal: None,
+                },
+            );
+        }
+
         // In the match below, we will either (a) return early with an
         // error or some kind or (b) continue on to pursue this strand
         // further. We continue onward in the case where we either
@@ -1240,13 +1340,9 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
             }
 
             Ok(EnsureSuccess::Coinductive) => {
-                // This is a co-inductive cycle. That is, this table
-                // appears somewhere higher on the stack, and has now
-                // recursively requested an answer for itself. That
-                // means that our subgoal is unconditionally true, so
-                // our negative goal fails.
-                info!("pursue_negative_subgoal: found coinductive answer to neg literal -> NoSolution");
-                return Err(StrandFail::NoSolution);
+                // A co-inductive cycle would have been caught by
+                // `closes_coinductive_cycle` above.
+                panic!("pursue_negative_subgoal: unexpected coinductive cycle")
             }
 
             Err(RecursiveSearchFail::Cycle(minimums)) => {
@@ -1319,7 +1415,7 @@ struct PursueStrand<'a, C: Context + 'a, CO: ContextOps<C> + 'a> {
     depth: StackIndex,
 }
 
-impl<C: Context, CO: ContextOps<C>> WithInstantiatedStrand<C, CO> for PursueStrand<'a, C, CO> {
+impl<'a, C: Context, CO: ContextOps<C>> WithInstantiatedStrand<C, CO> for PursueStrand<'a, C, CO> {
     type Output = StrandResult<C, ()>;
 
     fn with(self, strand: Strand<'_, C, impl Context>) -> Self::Output {
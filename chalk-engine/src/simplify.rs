@@ -19,6 +19,7 @@ impl<C: Context, CO: ContextOps<C>> Forest<C, CO> {
             delayed_literals: vec![],
             constraints: vec![],
             subgoals: vec![],
+            sources: Default::default(),
         };
 
         // A stack of higher-level goals to process.
@@ -1,9 +1,9 @@
 use crate::{DepthFirstNumber, TableIndex};
-use std::ops::{Index, IndexMut, Range};
+use std::ops::{Index, IndexMut};
 
 /// See `Forest`.
 #[derive(Default)]
-crate struct Stack {
+pub(crate) struct Stack {
     /// Stack: as described above, stores the in-progress goals.
     stack: Vec<StackEntry>,
 }
@@ -13,12 +13,12 @@ crate struct Stack {
 /// table is completely evaluated, it may be popped from the stack,
 /// and hence no longer have a stack index.
 index_struct! {
-    crate struct StackIndex {
+    pub(crate) struct StackIndex {
         value: usize,
     }
 }
 
-crate struct StackEntry {
+pub(crate) struct StackEntry {
     /// The goal G from the stack entry `A :- G` represented here.
     pub(super) table: TableIndex,
 
@@ -47,8 +47,14 @@ impl Stack {
             .next()
     }
 
-    pub(super) fn top_of_stack_from(&self, depth: StackIndex) -> Range<StackIndex> {
-        depth .. StackIndex::from(self.stack.len())
+    pub(super) fn top_of_stack_from(&self, depth: StackIndex) -> impl Iterator<Item = StackIndex> {
+        (depth.value..self.stack.len()).map(StackIndex::from)
+    }
+
+    /// The tables from `depth` down to the bottom of the stack,
+    /// innermost first.
+    pub(super) fn tables_from(&self, depth: StackIndex) -> impl Iterator<Item = TableIndex> + '_ {
+        self.stack[..=depth.value].iter().rev().map(|stack_entry| stack_entry.table)
     }
 
     pub(super) fn push(&mut self, table: TableIndex, dfn: DepthFirstNumber) -> StackIndex {
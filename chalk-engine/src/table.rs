@@ -1,4 +1,4 @@
-use crate::{DelayedLiteralSet, DelayedLiteralSets};
+use crate::{CannotProveReason, DelayedLiteralSet, DelayedLiteralSets};
 use crate::context::prelude::*;
 use crate::strand::CanonicalStrand;
 use rustc_hash::FxHashMap;
@@ -6,33 +6,52 @@ use std::collections::VecDeque;
 use std::collections::hash_map::Entry;
 use std::mem;
 
-crate struct Table<C: Context> {
+pub(crate) struct Table<C: Context> {
     /// The goal this table is trying to solve (also the key to look
     /// it up).
-    crate table_goal: C::UCanonicalGoalInEnvironment,
+    pub(crate) table_goal: C::UCanonicalGoalInEnvironment,
 
     /// A goal is coinductive if it can assume itself to be true, more
     /// or less. This is true for auto traits.
-    crate coinductive_goal: bool,
+    pub(crate) coinductive_goal: bool,
 
     /// Stores the answers that we have found thus far. When we get a request
     /// for an answer N, we will first check this vector.
     answers: Vec<Answer<C>>,
 
     /// An alternative storage for the answers we have so far, used to
-    /// detect duplicates. Not every answer in `answers` will be
-    /// represented here -- we discard answers from `answers_hash`
-    /// (but not `answers`) when better answers arrive (in particular,
-    /// answers with fewer delayed literals).
+    /// detect duplicates, keyed by the u-canonical form of their
+    /// substitutions (see `ContextOps::u_canonicalize_answer`). Not
+    /// every answer in `answers` will be represented here -- we
+    /// discard answers from `answers_hash` (but not `answers`) when
+    /// better answers arrive (in particular, answers with fewer
+    /// delayed literals).
     answers_hash: FxHashMap<C::CanonicalConstrainedSubst, DelayedLiteralSets<C>>,
 
     /// Stores the active strands that we can "pull on" to find more
     /// answers.
     strands: VecDeque<CanonicalStrand<C>>,
+
+    /// True if this table was created for a goal that the user asked
+    /// directly (rather than for a subgoal). Root tables are never
+    /// dropped by `Forest::gc`.
+    pub(crate) root: bool,
+
+    /// The generation of the forest (see `Forest::gc`) in which this
+    /// table was last looked up.
+    pub(crate) last_use: u64,
+
+    /// True if this table was handed out by `Forest::table`, to be
+    /// driven by hand: it is never evicted (see `Forest::evict_tables`).
+    pub(crate) pinned: bool,
+
+    /// The number of answers that are conditional on this table,
+    /// including its own (see `Forest::evict_tables`).
+    pub(crate) answer_referrers: usize,
 }
 
 index_struct! {
-    crate struct AnswerIndex {
+    pub(crate) struct AnswerIndex {
         value: usize,
     }
 }
@@ -43,55 +62,83 @@ index_struct! {
 /// a substitution
 #[derive(Clone, Debug)]
 pub struct Answer<C: Context> {
-    crate subst: C::CanonicalConstrainedSubst,
-    crate delayed_literals: DelayedLiteralSet<C>,
+    pub(crate) subst: C::CanonicalConstrainedSubst,
+    pub(crate) delayed_literals: DelayedLiteralSet<C>,
 }
 
 impl<C: Context> Table<C> {
-    crate fn new(table_goal: C::UCanonicalGoalInEnvironment, coinductive_goal: bool) -> Table<C> {
+    pub(crate) fn new(table_goal: C::UCanonicalGoalInEnvironment, coinductive_goal: bool) -> Table<C> {
         Table {
             table_goal,
             coinductive_goal,
             answers: Vec::new(),
             answers_hash: FxHashMap::default(),
             strands: VecDeque::new(),
+            root: false,
+            last_use: 0,
+            pinned: false,
+            answer_referrers: 0,
         }
     }
 
-    crate fn push_strand(&mut self, strand: CanonicalStrand<C>) {
+    pub(crate) fn push_strand(&mut self, strand: CanonicalStrand<C>) {
         self.strands.push_back(strand);
     }
 
-    crate fn extend_strands(&mut self, strands: impl IntoIterator<Item = CanonicalStrand<C>>) {
+    pub(crate) fn extend_strands(&mut self, strands: impl IntoIterator<Item = CanonicalStrand<C>>) {
         self.strands.extend(strands);
     }
 
-    crate fn strands_mut(&mut self) -> impl Iterator<Item = &mut CanonicalStrand<C>> {
+    pub(crate) fn strands_mut(&mut self) -> impl Iterator<Item = &mut CanonicalStrand<C>> {
         self.strands.iter_mut()
     }
 
-    crate fn take_strands(&mut self) -> VecDeque<CanonicalStrand<C>> {
+    pub(crate) fn take_strands(&mut self) -> VecDeque<CanonicalStrand<C>> {
         mem::replace(&mut self.strands, VecDeque::new())
     }
 
-    crate fn pop_next_strand(&mut self) -> Option<CanonicalStrand<C>> {
+    pub(crate) fn strands(&self) -> impl Iterator<Item = &CanonicalStrand<C>> {
+        self.strands.iter()
+    }
+
+    /// True if this table has no strands left to pursue, and hence
+    /// will never find any more answers.
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.strands.is_empty()
+    }
+
+    pub(crate) fn pop_next_strand(&mut self) -> Option<CanonicalStrand<C>> {
         self.strands.pop_front()
     }
 
+    /// Removes the first strand for which `key` is minimal.
+    pub(crate) fn pop_strand_min_by_key<K: Ord>(
+        &mut self,
+        mut key: impl FnMut(&CanonicalStrand<C>) -> K,
+    ) -> Option<CanonicalStrand<C>> {
+        let index = self.strands
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, strand)| key(strand))
+            .map(|(index, _)| index)?;
+        self.strands.remove(index)
+    }
+
     /// Adds `answer` to our list of answers, unless it (or some
     /// better answer) is already present. An answer A is better than
     /// an answer B if their substitutions are the same, but A has a subset
-    /// of the delayed literals that B does.
+    /// of the delayed literals that B does. Substitutions are compared
+    /// by their u-canonical form `key`.
     ///
     /// Returns true if `answer` was added.
-    pub(super) fn push_answer(&mut self, answer: Answer<C>) -> bool {
+    pub(super) fn push_answer(&mut self, answer: Answer<C>, key: C::CanonicalConstrainedSubst) -> bool {
         debug_heading!("push_answer(answer={:?})", answer);
         debug!(
             "pre-existing entry: {:?}",
-            self.answers_hash.get(&answer.subst)
+            self.answers_hash.get(&key)
         );
 
-        let added = match self.answers_hash.entry(answer.subst.clone()) {
+        let added = match self.answers_hash.entry(key) {
             Entry::Vacant(entry) => {
                 entry.insert(DelayedLiteralSets::singleton(answer.delayed_literals.clone()));
                 true
@@ -116,6 +163,19 @@ impl<C: Context> Table<C> {
         self.answers.get(index.value)
     }
 
+    /// The greatest reason for which the search gave up on any of the
+    /// answers found so far, if it did on any.
+    pub(super) fn cannot_prove_reason(&self) -> Option<CannotProveReason> {
+        self.answers
+            .iter()
+            .filter_map(|answer| answer.delayed_literals.cannot_prove_reason())
+            .max()
+    }
+
+    pub(crate) fn answers(&self) -> &[Answer<C>] {
+        &self.answers
+    }
+
     /// Useful for testing.
     pub fn num_cached_answers(&self) -> usize {
         self.answers.len()
@@ -127,13 +187,19 @@ impl<C: Context> Table<C> {
 }
 
 impl AnswerIndex {
-    crate const ZERO: AnswerIndex = AnswerIndex { value: 0 };
+    pub(crate) const ZERO: AnswerIndex = AnswerIndex { value: 0 };
 }
 
 impl<C: Context> Answer<C> {
+    /// The substitution, with its region constraints, under which the
+    /// goal of the table holds.
+    pub fn subst(&self) -> &C::CanonicalConstrainedSubst {
+        &self.subst
+    }
+
     /// An "unconditional" answer is one that must be true -- this is
     /// the case so long as we have no delayed literals.
-    pub(super) fn is_unconditional(&self) -> bool {
+    pub fn is_unconditional(&self) -> bool {
         self.delayed_literals.is_empty()
     }
 }
@@ -2,20 +2,23 @@ use crate::TableIndex;
 use crate::context::prelude::*;
 use crate::table::Table;
 use rustc_hash::FxHashMap;
+use std::iter::Flatten;
 use std::ops::{Index, IndexMut};
+use std::slice::IterMut;
 
 /// See `Forest`.
-crate struct Tables<C: Context> {
+pub(crate) struct Tables<C: Context> {
     /// Maps from a canonical goal to the index of its table.
     table_indices: FxHashMap<C::UCanonicalGoalInEnvironment, TableIndex>,
 
     /// Table: as described above, stores the key information for each
-    /// tree in the forest.
-    tables: Vec<Table<C>>,
+    /// tree in the forest. Tables dropped by `Forest::gc` leave a `None`
+    /// behind, so that the indices of the other tables do not change.
+    tables: Vec<Option<Table<C>>>,
 }
 
 impl<C: Context> Tables<C> {
-    crate fn new() -> Tables<C> {
+    pub(crate) fn new() -> Tables<C> {
         Tables {
             table_indices: FxHashMap::default(),
             tables: Vec::default(),
@@ -31,7 +34,7 @@ impl<C: Context> Tables<C> {
 
     pub(super) fn insert(&mut self, goal: C::UCanonicalGoalInEnvironment, coinductive_goal: bool) -> TableIndex {
         let index = self.next_index();
-        self.tables.push(Table::new(goal.clone(), coinductive_goal));
+        self.tables.push(Some(Table::new(goal.clone(), coinductive_goal)));
         self.table_indices.insert(goal, index);
         index
     }
@@ -39,28 +42,56 @@ impl<C: Context> Tables<C> {
     pub(super) fn index_of(&self, literal: &C::UCanonicalGoalInEnvironment) -> Option<TableIndex> {
         self.table_indices.get(literal).cloned()
     }
+
+    /// The number of tables in the forest, not counting those that
+    /// were dropped.
+    pub(super) fn len(&self) -> usize {
+        self.table_indices.len()
+    }
+
+    /// The indices of the tables that have not been dropped.
+    pub(super) fn indices(&self) -> impl Iterator<Item = TableIndex> + '_ {
+        self.tables
+            .iter()
+            .enumerate()
+            .filter(|(_, table)| table.is_some())
+            .map(|(value, _)| TableIndex { value })
+    }
+
+    /// True if the table has not been dropped.
+    pub(super) fn contains(&self, index: TableIndex) -> bool {
+        self.tables[index.value].is_some()
+    }
+
+    /// Drops a table. If its goal is asked again, a new table will be
+    /// created for it.
+    pub(super) fn remove(&mut self, index: TableIndex) {
+        if let Some(table) = self.tables[index.value].take() {
+            self.table_indices.remove(&table.table_goal);
+        }
+    }
 }
 
 impl<C: Context> Index<TableIndex> for Tables<C> {
     type Output = Table<C>;
 
     fn index(&self, index: TableIndex) -> &Table<C> {
-        &self.tables[index.value]
+        self.tables[index.value].as_ref().expect("table was dropped")
     }
 }
 
 impl<C: Context> IndexMut<TableIndex> for Tables<C> {
     fn index_mut(&mut self, index: TableIndex) -> &mut Table<C> {
-        &mut self.tables[index.value]
+        self.tables[index.value].as_mut().expect("table was dropped")
     }
 }
 
 impl<'a, C: Context> IntoIterator for &'a mut Tables<C> {
-    type IntoIter = <&'a mut Vec<Table<C>> as IntoIterator>::IntoIter;
-    type Item = <&'a mut Vec<Table<C>> as IntoIterator>::Item;
+    type IntoIter = Flatten<IterMut<'a, Option<Table<C>>>>;
+    type Item = &'a mut Table<C>;
 
     fn into_iter(self) -> Self::IntoIter {
-        IntoIterator::into_iter(&mut self.tables)
+        self.tables.iter_mut().flatten()
     }
 }
 
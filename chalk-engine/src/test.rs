@@ -0,0 +1,863 @@
+#![cfg(test)]
+
+//! Unit tests for the forest, run against a mock context whose goals
+//! are propositional atoms. There are no variables, so unification is
+//! trivial and every goal is its own canonical form; this lets us
+//! exercise the tabling machinery (cycles, negation, delayed
+//! literals) without going through the full chalk stack.
+
+use crate::context::{self, AnswerStream, WithInstantiatedExClause,
+                     WithInstantiatedUCanonicalGoal};
+use crate::context::prelude::*;
+use crate::fallible::{Fallible, NoSolution};
+use crate::forest::{Forest, Scheduling, Step};
+use crate::hh::HhGoal;
+use crate::table::Answer;
+use crate::{CannotProveReason, DelayedLiteral, ExClause, Literal, TableIndex};
+use std::fmt::Debug;
+
+type Atom = &'static str;
+
+/// Stands in for the value that an output variable would be bound
+/// to. Each program clause may carry one, and answers produced
+/// through distinct witnesses are distinct answers.
+type Witness = Option<Atom>;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Goal {
+    Atom(Atom),
+    Not(Box<Goal>),
+    And(Box<Goal>, Box<Goal>),
+    CannotProve,
+}
+
+/// `head :- body`. A `Goal::Not` in the body becomes a negative
+/// literal; anything else becomes a positive one.
+#[derive(Clone, Debug)]
+struct Clause {
+    head: Atom,
+    body: Vec<Goal>,
+    witness: Witness,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct MockContext;
+
+impl Context for MockContext {
+    type CanonicalExClause = ExClause<MockContext>;
+    type UniverseMap = ();
+    type InferenceNormalizedSubst = Witness;
+    type CanonicalGoalInEnvironment = Goal;
+    type UCanonicalGoalInEnvironment = Goal;
+    type Solution = ();
+    type CanonicalConstrainedSubst = Witness;
+    type Substitution = Witness;
+    type RegionConstraint = ();
+    type GoalInEnvironment = Goal;
+    type Environment = ();
+    type Goal = Goal;
+    type DomainGoal = Atom;
+    type BindersGoal = Goal;
+    type Parameter = ();
+    type ProgramClause = Clause;
+    type ProgramClauses = Vec<Clause>;
+    type ClauseSources = ();
+    type UnificationResult = ();
+
+    fn goal_in_environment(_environment: &(), goal: Goal) -> Goal {
+        goal
+    }
+
+    fn into_goal(domain_goal: Atom) -> Goal {
+        Goal::Atom(domain_goal)
+    }
+
+    fn cannot_prove() -> Goal {
+        Goal::CannotProve
+    }
+}
+
+#[derive(Clone, Debug)]
+struct MockContextOps {
+    clauses: Vec<Clause>,
+    coinductive: Vec<Atom>,
+    projections: Vec<Atom>,
+    max_projection_depth: usize,
+
+    /// If true, an unconditional answer with the witness `_` stands
+    /// for the answers with any witness, and the answers that it
+    /// subsumes are not recorded (see `should_record_answer`).
+    subsumption: bool,
+}
+
+impl ContextOps<MockContext> for MockContextOps {
+    fn is_coinductive(&self, goal: &Goal) -> bool {
+        match goal {
+            Goal::Atom(atom) => self.coinductive.contains(atom),
+            _ => false,
+        }
+    }
+
+    fn is_projection_goal(&self, goal: &Goal) -> bool {
+        match goal {
+            Goal::Atom(atom) => self.projections.contains(atom),
+            _ => false,
+        }
+    }
+
+    fn max_projection_depth(&self) -> usize {
+        self.max_projection_depth
+    }
+
+    fn is_too_deep(&self, _goal: &Goal) -> bool {
+        false
+    }
+
+    fn instantiate_ucanonical_goal<R>(
+        &self,
+        arg: &Goal,
+        op: impl WithInstantiatedUCanonicalGoal<MockContext, Output = R>,
+    ) -> R {
+        let infer = &mut MockInferenceTable { clauses: &self.clauses };
+        op.with(infer, None, (), arg.clone())
+    }
+
+    fn instantiate_ex_clause<R>(
+        &self,
+        _num_universes: usize,
+        canonical_ex_clause: &ExClause<MockContext>,
+        op: impl WithInstantiatedExClause<MockContext, Output = R>,
+    ) -> R {
+        let infer = &mut MockInferenceTable { clauses: &self.clauses };
+        op.with(infer, canonical_ex_clause.clone())
+    }
+
+    fn inference_normalized_subst_from_ex_clause(
+        canon_ex_clause: &ExClause<MockContext>,
+    ) -> &Witness {
+        &canon_ex_clause.subst
+    }
+
+    fn inference_normalized_subst_from_subst(canon_subst: &Witness) -> &Witness {
+        canon_subst
+    }
+
+    fn num_subgoals(canon_ex_clause: &ExClause<MockContext>) -> usize {
+        canon_ex_clause.subgoals.len()
+    }
+
+    fn is_ground_ex_clause(_canon_ex_clause: &ExClause<MockContext>) -> bool {
+        true
+    }
+
+    fn empty_constraints(_ccs: &Witness) -> bool {
+        true
+    }
+
+    fn canonical(u_canon: &Goal) -> &Goal {
+        u_canon
+    }
+
+    fn is_trivial_substitution(_u_canon: &Goal, canonical_subst: &Witness) -> bool {
+        canonical_subst.is_none()
+    }
+
+    fn num_universes(_u_canon: &Goal) -> usize {
+        1
+    }
+
+    fn map_goal_from_canonical(_map: &(), value: &Goal) -> Goal {
+        value.clone()
+    }
+
+    fn map_subst_from_canonical(_map: &(), value: &Witness) -> Witness {
+        *value
+    }
+
+    fn u_canonicalize_answer(_num_universes: usize, value: &Witness) -> Witness {
+        *value
+    }
+
+    fn should_record_answer(
+        &self,
+        _answer: &Answer<MockContext>,
+        existing: &[Answer<MockContext>],
+    ) -> bool {
+        !self.subsumption
+            || !existing
+                .iter()
+                .any(|answer| answer.subst == Some("_") && answer.is_unconditional())
+    }
+}
+
+impl AggregateOps<MockContext> for MockContextOps {
+    fn make_solution(
+        &self,
+        _root_goal: &Goal,
+        mut simplified_answers: impl AnswerStream<MockContext>,
+    ) -> Option<()> {
+        simplified_answers.next_answer().map(|_| ())
+    }
+}
+
+struct MockInferenceTable<'p> {
+    clauses: &'p [Clause],
+}
+
+impl<'p> InferenceTable<MockContext, MockContext> for MockInferenceTable<'p> {
+    fn into_hh_goal(&mut self, goal: Goal) -> HhGoal<MockContext> {
+        match goal {
+            Goal::Atom(atom) => HhGoal::DomainGoal(atom),
+            Goal::Not(goal) => HhGoal::Not(*goal),
+            Goal::And(a, b) => HhGoal::And(*a, *b),
+            Goal::CannotProve => HhGoal::CannotProve,
+        }
+    }
+
+    fn add_clauses(&mut self, _env: &(), _clauses: Vec<Clause>) {
+        panic!("the mock context has no implication goals")
+    }
+}
+
+impl<'p> context::UnificationOps<MockContext, MockContext> for MockInferenceTable<'p> {
+    fn program_clauses(&self, _environment: &(), goal: &Atom) -> Vec<Clause> {
+        self.clauses
+            .iter()
+            .filter(|clause| clause.head == *goal)
+            .cloned()
+            .collect()
+    }
+
+    fn instantiate_binders_universally(&mut self, _arg: &Goal) -> Goal {
+        panic!("the mock context has no binders")
+    }
+
+    fn instantiate_binders_existentially(&mut self, _arg: &Goal) -> Goal {
+        panic!("the mock context has no binders")
+    }
+
+    fn debug_ex_clause<'v>(&mut self, value: &'v ExClause<MockContext>) -> Box<dyn Debug + 'v> {
+        Box::new(value)
+    }
+
+    fn canonicalize_goal(&mut self, value: &Goal) -> Goal {
+        value.clone()
+    }
+
+    fn canonicalize_ex_clause(&mut self, value: &ExClause<MockContext>) -> ExClause<MockContext> {
+        value.clone()
+    }
+
+    fn canonicalize_constrained_subst(
+        &mut self,
+        subst: Witness,
+        _constraints: Vec<()>,
+        _sources: (),
+    ) -> Witness {
+        subst
+    }
+
+    fn u_canonicalize_goal(&mut self, value: &Goal) -> (Goal, ()) {
+        (value.clone(), ())
+    }
+
+    fn sink_answer_subset(&self, value: &Witness) -> Witness {
+        *value
+    }
+
+    fn lift_delayed_literal(&self, value: DelayedLiteral<MockContext>) -> DelayedLiteral<MockContext> {
+        value
+    }
+
+    fn invert_goal(&mut self, value: &Goal) -> Option<Goal> {
+        Some(value.clone())
+    }
+
+    fn never_applies(&mut self, _goal: &Goal) -> bool {
+        false
+    }
+
+    fn unify_parameters(&mut self, _environment: &(), _a: &(), _b: &()) -> Fallible<()> {
+        Ok(())
+    }
+
+    fn into_ex_clause(&mut self, _result: (), _ex_clause: &mut ExClause<MockContext>) {}
+}
+
+impl<'p> TruncateOps<MockContext, MockContext> for MockInferenceTable<'p> {
+    fn truncate_goal(&mut self, _subgoal: &Goal) -> Option<Goal> {
+        None
+    }
+
+    fn truncate_answer(&mut self, _subst: &Witness) -> Option<Witness> {
+        None
+    }
+}
+
+impl<'p> ResolventOps<MockContext, MockContext> for MockInferenceTable<'p> {
+    fn resolvent_clause(
+        &mut self,
+        _environment: &(),
+        goal: &Atom,
+        _subst: &Witness,
+        clause: &Clause,
+    ) -> Fallible<ExClause<MockContext>> {
+        if clause.head != *goal {
+            return Err(NoSolution);
+        }
+
+        Ok(ExClause {
+            subst: clause.witness,
+            delayed_literals: vec![],
+            constraints: vec![],
+            sources: (),
+            subgoals: clause
+                .body
+                .iter()
+                .map(|goal| match goal {
+                    Goal::Not(goal) => Literal::Negative((**goal).clone()),
+                    goal => Literal::Positive(goal.clone()),
+                })
+                .collect(),
+        })
+    }
+
+    fn apply_answer_subst(
+        &mut self,
+        ex_clause: ExClause<MockContext>,
+        _selected_goal: &Goal,
+        _answer_table_goal: &Goal,
+        _canonical_answer_subst: &Witness,
+    ) -> Fallible<(ExClause<MockContext>, Vec<()>)> {
+        // Answers to subgoals never bind anything in the clause head.
+        Ok((ex_clause, vec![]))
+    }
+}
+
+fn atom(name: Atom) -> Goal {
+    Goal::Atom(name)
+}
+
+fn not(name: Atom) -> Goal {
+    Goal::Not(Box::new(atom(name)))
+}
+
+fn clause(head: Atom, body: Vec<Goal>) -> Clause {
+    Clause { head, body, witness: None }
+}
+
+fn fact_with_witness(head: Atom, witness: Atom) -> Clause {
+    Clause { head, body: vec![], witness: Some(witness) }
+}
+
+fn forest(clauses: Vec<Clause>) -> Forest<MockContext, MockContextOps> {
+    Forest::new(MockContextOps {
+        clauses,
+        coinductive: vec![],
+        projections: vec![],
+        max_projection_depth: usize::MAX,
+        subsumption: false,
+    })
+}
+
+fn table(forest: &Forest<MockContext, MockContextOps>, name: Atom) -> TableIndex {
+    forest.tables.index_of(&atom(name)).unwrap()
+}
+
+fn delayed_literals(answer: &Answer<MockContext>) -> Vec<DelayedLiteral<MockContext>> {
+    answer.delayed_literals.delayed_literals.iter().cloned().collect()
+}
+
+#[test]
+fn fact() {
+    let mut forest = forest(vec![clause("p", vec![])]);
+    let answers = forest.force_answers(atom("p"), 10);
+    assert_eq!(answers.len(), 1);
+    assert!(answers[0].delayed_literals.is_empty());
+}
+
+#[test]
+fn duplicate_answers_are_dropped() {
+    let mut forest = forest(vec![
+        fact_with_witness("p", "a"),
+        Clause { head: "p", body: vec![atom("q")], witness: Some("a") },
+        clause("q", vec![]),
+    ]);
+    let answers = forest.force_answers(atom("p"), 10);
+    assert_eq!(answers.len(), 1);
+    assert_eq!(answers[0].subst, Some("a"));
+}
+
+/// Answers are produced in the order of the clauses that yield them,
+/// whether they come straight from a fact or through a subgoal.
+#[test]
+fn answer_ordering() {
+    let mut forest = forest(vec![
+        fact_with_witness("p", "a"),
+        Clause { head: "p", body: vec![atom("q")], witness: Some("b") },
+        fact_with_witness("p", "c"),
+        clause("q", vec![]),
+    ]);
+    let answers = forest.force_answers(atom("p"), 10);
+    let witnesses: Vec<_> = answers.iter().map(|answer| answer.subst).collect();
+    assert_eq!(witnesses, vec![Some("a"), Some("b"), Some("c")]);
+}
+
+/// `p :- q. q :- p.` -- neither can ever be proven.
+#[test]
+fn positive_cycle() {
+    let mut forest = forest(vec![
+        clause("p", vec![atom("q")]),
+        clause("q", vec![atom("p")]),
+    ]);
+    assert!(forest.force_answers(atom("p"), 10).is_empty());
+    assert!(forest.force_answers(atom("q"), 10).is_empty());
+}
+
+#[test]
+fn positive_cycle_with_base_case() {
+    let mut forest = forest(vec![
+        clause("p", vec![atom("q")]),
+        clause("q", vec![atom("p")]),
+        clause("q", vec![]),
+    ]);
+    let answers = forest.force_answers(atom("p"), 10);
+    assert_eq!(answers.len(), 1);
+    assert!(answers[0].delayed_literals.is_empty());
+    assert_eq!(forest.force_answers(atom("q"), 10).len(), 1);
+}
+
+/// The same cycle as `positive_cycle`, but between coinductive goals,
+/// each of which may assume itself.
+#[test]
+fn coinductive_cycle() {
+    let mut forest = Forest::new(MockContextOps {
+        clauses: vec![
+            clause("p", vec![atom("q")]),
+            clause("q", vec![atom("p")]),
+        ],
+        coinductive: vec!["p", "q"],
+        projections: vec![],
+        max_projection_depth: usize::MAX,
+        subsumption: false,
+    });
+    let answers = forest.force_answers(atom("p"), 10);
+    assert_eq!(answers.len(), 1);
+    assert!(answers[0].delayed_literals.is_empty());
+}
+
+/// `p :- not p.` -- `p` is neither true nor false, so its only answer
+/// is delayed on itself.
+#[test]
+fn contradiction() {
+    let mut forest = forest(vec![clause("p", vec![not("p")])]);
+    let answers = forest.force_answers(atom("p"), 10);
+    assert_eq!(answers.len(), 1);
+    assert_eq!(
+        delayed_literals(&answers[0]),
+        vec![DelayedLiteral::Negative(table(&forest, "p"))]
+    );
+}
+
+#[test]
+fn negative_loop() {
+    let mut forest = forest(vec![
+        clause("p", vec![not("q")]),
+        clause("q", vec![not("p")]),
+    ]);
+    let answers = forest.force_answers(atom("p"), 10);
+    assert_eq!(answers.len(), 1);
+    assert_eq!(
+        delayed_literals(&answers[0]),
+        vec![DelayedLiteral::Negative(table(&forest, "q"))]
+    );
+}
+
+/// `p :- not p.`, where `p` is coinductive: rather than assuming `p`
+/// in order to disprove it, the strand flounders.
+#[test]
+fn negative_coinductive_cycle() {
+    let mut forest = Forest::new(MockContextOps {
+        clauses: vec![clause("p", vec![not("p")])],
+        coinductive: vec!["p"],
+        projections: vec![],
+        max_projection_depth: usize::MAX,
+        subsumption: false,
+    });
+    let answers = forest.force_answers(atom("p"), 10);
+    assert_eq!(answers.len(), 1);
+    assert_eq!(
+        delayed_literals(&answers[0]),
+        vec![DelayedLiteral::CannotProve(CannotProveReason::NegativeCoinductive)]
+    );
+}
+
+/// A conditional answer to a positive subgoal makes the answer that
+/// uses it conditional too (the SLG FACTOR operation).
+#[test]
+fn delayed_positive_literal() {
+    let mut forest = forest(vec![
+        clause("p", vec![atom("q")]),
+        clause("q", vec![not("q")]),
+    ]);
+    let answers = forest.force_answers(atom("p"), 10);
+    assert_eq!(answers.len(), 1);
+    assert_eq!(
+        delayed_literals(&answers[0]),
+        vec![DelayedLiteral::Positive(table(&forest, "q"), None)]
+    );
+}
+
+#[test]
+fn cannot_prove() {
+    let mut forest = forest(vec![clause("p", vec![Goal::CannotProve])]);
+    let answers = forest.force_answers(atom("p"), 10);
+    assert_eq!(answers.len(), 1);
+    assert!(!answers[0].delayed_literals.is_empty());
+}
+
+/// Example 2.2 from EWFS, for the constant `c`: `p` is false because
+/// it only depends on itself, positively, so `m` holds outright.
+#[test]
+#[allow(non_snake_case)]
+fn example_2_2_EWFS() {
+    let mut forest = forest(vec![
+        clause("m", vec![not("p")]),
+        clause("p", vec![atom("q")]),
+        clause("q", vec![atom("p")]),
+    ]);
+    let answers = forest.force_answers(atom("m"), 10);
+    assert_eq!(answers.len(), 1);
+    assert!(answers[0].delayed_literals.is_empty());
+}
+
+/// Example 3.3 from EWFS. Under the well-founded semantics `s` is
+/// true, since `p` and `q` are false: they only depend positively on
+/// each other, so their tables complete without answers, and `s` does
+/// not have to be delayed on them.
+#[test]
+#[allow(non_snake_case)]
+fn example_3_3_EWFS() {
+    let mut forest = forest(vec![
+        clause("s", vec![not("p"), not("q")]),
+        clause("p", vec![not("s"), atom("q")]),
+        clause("q", vec![not("s"), atom("p")]),
+    ]);
+    let answers = forest.force_answers(atom("s"), 10);
+    assert_eq!(answers.len(), 1);
+    assert!(delayed_literals(&answers[0]).is_empty());
+}
+
+#[test]
+fn and_goal() {
+    let mut forest = forest(vec![clause("p", vec![]), clause("q", vec![])]);
+    let goal = Goal::And(Box::new(atom("p")), Box::new(atom("q")));
+    assert_eq!(forest.force_answers(goal.clone(), 10).len(), 1);
+    assert_eq!(forest.solve(&goal), Some(()));
+
+    let goal = Goal::And(Box::new(atom("p")), Box::new(atom("r")));
+    assert_eq!(forest.solve(&goal), None);
+}
+
+/// Enumerates the answers to `p` through the answer iterator, as
+/// `(witness, trivial)` pairs.
+fn iterated_answers(clauses: Vec<Clause>) -> Vec<(Witness, bool)> {
+    let mut forest = forest(clauses);
+    let mut answers = forest.iter_answers(&atom("p"));
+    let mut result = vec![];
+    while let Some(answer) = answers.next_answer() {
+        result.push((answer.subst, answer.trivial));
+    }
+    result
+}
+
+#[test]
+fn trivial_answer() {
+    assert_eq!(iterated_answers(vec![clause("p", vec![])]), vec![(None, true)]);
+}
+
+/// Trivial answers are flagged, not skipped. (The trivial answer comes
+/// last here, since finding it cuts off the remaining strands.)
+#[test]
+fn trivial_and_nontrivial_answers() {
+    assert_eq!(
+        iterated_answers(vec![
+            fact_with_witness("p", "a"),
+            fact_with_witness("p", "b"),
+            clause("p", vec![]),
+        ]),
+        vec![(Some("a"), false), (Some("b"), false), (None, true)]
+    );
+}
+
+/// `p` has an answer from a fact, and another one at the end of a
+/// chain of clauses that comes first in the program.
+fn deep_and_shallow_answers() -> Vec<Clause> {
+    vec![
+        Clause { head: "p", body: vec![atom("q")], witness: Some("deep") },
+        fact_with_witness("p", "shallow"),
+        clause("q", vec![atom("r")]),
+        clause("r", vec![atom("s")]),
+        clause("s", vec![]),
+    ]
+}
+
+fn witnesses(scheduling: Scheduling) -> Vec<Witness> {
+    let ops = MockContextOps {
+        clauses: deep_and_shallow_answers(),
+        coinductive: vec![],
+        projections: vec![],
+        max_projection_depth: usize::MAX,
+        subsumption: false,
+    };
+    let mut forest = Forest::with_scheduling(ops, scheduling);
+    forest.force_answers(atom("p"), 10).iter().map(|answer| answer.subst).collect()
+}
+
+#[test]
+fn depth_first_scheduling() {
+    assert_eq!(witnesses(Scheduling::DepthFirst), vec![Some("deep"), Some("shallow")]);
+}
+
+/// The same answers as under `DepthFirst`, but the fact is found
+/// without first walking the chain.
+#[test]
+fn fewest_subgoals_first_scheduling() {
+    assert_eq!(witnesses(Scheduling::FewestSubgoalsFirst), vec![Some("shallow"), Some("deep")]);
+}
+
+/// Without inference variables every strand is ground, so this falls
+/// back to queue order.
+#[test]
+fn prefer_ground_clauses_scheduling() {
+    assert_eq!(witnesses(Scheduling::PreferGroundClauses), vec![Some("deep"), Some("shallow")]);
+}
+
+fn chain() -> Vec<Clause> {
+    vec![
+        clause("p", vec![atom("q")]),
+        clause("q", vec![atom("r")]),
+        clause("r", vec![]),
+    ]
+}
+
+/// `p` needs `q` needs `r`, where `q` and `r` stand for projections:
+/// nesting two projection goals is fine, but not under a limit of one.
+#[test]
+fn projection_depth() {
+    let solve = |max_projection_depth| {
+        let mut forest = Forest::new(MockContextOps {
+            clauses: chain(),
+            coinductive: vec![],
+            projections: vec!["q", "r"],
+            max_projection_depth,
+            subsumption: false,
+        });
+        let answers = forest.force_answers(atom("p"), 10);
+        assert_eq!(answers.len(), 1);
+        (
+            answers[0].delayed_literals.cannot_prove_reason(),
+            forest.answers(table(&forest, "q")).to_vec(),
+        )
+    };
+    let (cannot_prove, _) = solve(2);
+    assert_eq!(cannot_prove, None);
+
+    // `q` gives up on `r`, and so `p` gives up too.
+    let (cannot_prove, q_answers) = solve(1);
+    assert_eq!(cannot_prove, Some(CannotProveReason::ProjectionDepth));
+    assert_eq!(
+        delayed_literals(&q_answers[0]),
+        vec![DelayedLiteral::CannotProve(CannotProveReason::ProjectionDepth)]
+    );
+}
+
+/// Once `p` is solved, the tables for its subgoals are complete and
+/// can be dropped; the root table stays.
+#[test]
+fn gc_drops_completed_subgoal_tables() {
+    let mut forest = forest(chain());
+    assert_eq!(forest.force_answers(atom("p"), 10).len(), 1);
+    assert_eq!(forest.num_tables(), 3);
+
+    assert_eq!(forest.gc(0), 2);
+    assert_eq!(forest.num_tables(), 1);
+    assert!(forest.tables.index_of(&atom("q")).is_none());
+
+    // Asking again answers from the root table, or recreates the
+    // dropped tables.
+    assert_eq!(forest.force_answers(atom("p"), 10).len(), 1);
+    assert_eq!(forest.num_tables(), 1);
+    assert_eq!(forest.force_answers(atom("q"), 10).len(), 1);
+    assert_eq!(forest.num_tables(), 3);
+}
+
+/// Tables used by the last `keep_generations` queries are kept.
+#[test]
+fn gc_keeps_recent_tables() {
+    let mut forest = forest(chain());
+    forest.force_answers(atom("p"), 10);
+    assert_eq!(forest.gc(1), 0);
+    assert_eq!(forest.num_tables(), 3);
+
+    // Another query starts a new generation.
+    forest.force_answers(atom("r"), 10);
+    assert_eq!(forest.gc(1), 1);
+    assert!(forest.tables.index_of(&atom("q")).is_none());
+    assert!(forest.tables.index_of(&atom("r")).is_some());
+}
+
+/// The answer to `p` is conditional on `q`, so the table for `q` must
+/// outlive it.
+#[test]
+fn gc_keeps_tables_of_delayed_literals() {
+    let mut forest = forest(vec![
+        clause("p", vec![atom("q")]),
+        clause("q", vec![not("q")]),
+    ]);
+    forest.force_answers(atom("p"), 10);
+    assert_eq!(forest.gc(0), 0);
+
+    let answers = forest.force_answers(atom("p"), 10);
+    assert_eq!(answers.len(), 1);
+    assert_eq!(
+        delayed_literals(&answers[0]),
+        vec![DelayedLiteral::Positive(table(&forest, "q"), None)]
+    );
+}
+
+/// With a bound on the tables kept between queries, asking many goals
+/// evicts the least recently used complete tables; a goal whose table
+/// was evicted is solved again from scratch.
+#[test]
+fn evicts_least_recently_used_tables() {
+    let goals: Vec<Atom> = (0..20)
+        .map(|i| &*Box::leak(format!("g{}", i).into_boxed_str()))
+        .collect();
+    let mut clauses: Vec<Clause> =
+        goals.iter().map(|&goal| clause(goal, vec![atom("r")])).collect();
+    clauses.push(clause("r", vec![]));
+    let mut forest = forest(clauses).with_max_cached_tables(5);
+
+    for &goal in &goals {
+        assert_eq!(forest.force_answers(atom(goal), 10).len(), 1);
+        // The tables of the last query are only evicted as the next
+        // one starts.
+        assert!(forest.num_tables() <= 5 + 2, "{} tables", forest.num_tables());
+    }
+    assert!(forest.num_evicted_tables() >= 15);
+    assert!(forest.tables.index_of(&atom("g0")).is_none());
+
+    // `r` is looked up by each query, so it is never the least recently
+    // used table.
+    assert!(forest.tables.index_of(&atom("r")).is_some());
+
+    let evicted = forest.num_evicted_tables();
+    assert_eq!(forest.force_answers(atom("g0"), 10).len(), 1);
+    assert!(forest.tables.index_of(&atom("g0")).is_some());
+    assert!(forest.num_evicted_tables() > evicted);
+}
+
+/// Tables handed out by `Forest::table`, and those that an answer is
+/// conditional on, are not evicted.
+#[test]
+fn eviction_keeps_pinned_and_referenced_tables() {
+    let mut forest = forest(vec![
+        clause("p", vec![atom("q")]),
+        clause("q", vec![not("q")]),
+        clause("a", vec![]),
+        clause("b", vec![]),
+        clause("c", vec![]),
+    ]).with_max_cached_tables(0);
+
+    let a = forest.table(atom("a"));
+    assert_eq!(forest.step(a, 0), Step::Answer);
+    forest.force_answers(atom("p"), 10);
+    forest.force_answers(atom("b"), 10);
+    forest.force_answers(atom("c"), 10);
+
+    assert!(forest.tables.index_of(&atom("a")).is_some());
+    assert!(forest.tables.index_of(&atom("q")).is_some());
+    assert!(forest.tables.index_of(&atom("b")).is_none());
+    assert_eq!(forest.answers(a).len(), 1);
+}
+
+/// Stepping a table by hand finds the same answers as `force_answers`,
+/// and the tables of the subgoals can be inspected along the way.
+#[test]
+fn manual_stepping() {
+    let clauses = || vec![
+        fact_with_witness("p", "a"),
+        Clause { head: "p", body: vec![atom("q")], witness: Some("b") },
+        fact_with_witness("p", "c"),
+        clause("q", vec![atom("r")]),
+        clause("r", vec![]),
+    ];
+    let expected: Vec<_> = forest(clauses())
+        .force_answers(atom("p"), 10)
+        .iter()
+        .map(|answer| *answer.subst())
+        .collect();
+
+    let mut forest = forest(clauses());
+    let p = forest.table(atom("p"));
+    assert!(forest.answers(p).is_empty());
+    assert_eq!(forest.pending_strands(p).count(), 3);
+
+    let mut n = 0;
+    let mut steps = 0;
+    loop {
+        steps += 1;
+        assert!(steps < 100, "stepping does not terminate");
+        match forest.step(p, n) {
+            Step::Answer => {
+                assert_eq!(forest.answers(p).len(), n + 1);
+                n += 1;
+            }
+            Step::Pending => {}
+            Step::NoMoreAnswers => break,
+        }
+    }
+
+    let found: Vec<_> = forest.answers(p).iter().map(|answer| *answer.subst()).collect();
+    assert_eq!(found, expected);
+    assert!(forest.answers(p).iter().all(|answer| answer.is_unconditional()));
+    assert!(forest.is_exhausted(p));
+    assert_eq!(forest.pending_strands(p).count(), 0);
+
+    let goals: Vec<_> = forest.tables().map(|table| forest.table_goal(table).clone()).collect();
+    assert_eq!(goals, vec![atom("p"), atom("q"), atom("r")]);
+
+    // Stepping a finished table finds nothing more.
+    assert_eq!(forest.step(p, n), Step::NoMoreAnswers);
+    assert!(forest.ensure_answer(p, 0));
+    assert!(!forest.ensure_answer(p, n));
+}
+
+/// A context may keep fewer answers than the forest would: here, those
+/// which an answer with the witness `_` subsumes are dropped.
+#[test]
+fn subsumed_answers_are_not_recorded() {
+    let clauses = || {
+        vec![
+            fact_with_witness("p", "_"),
+            fact_with_witness("p", "a"),
+            Clause { head: "p", body: vec![atom("q")], witness: Some("b") },
+            clause("q", vec![]),
+        ]
+    };
+    assert_eq!(forest(clauses()).force_answers(atom("p"), 10).len(), 3);
+
+    let mut forest = Forest::new(MockContextOps {
+        clauses: clauses(),
+        coinductive: vec![],
+        projections: vec![],
+        max_projection_depth: usize::MAX,
+        subsumption: true,
+    });
+    let answers = forest.force_answers(atom("p"), 10);
+    let witnesses: Vec<_> = answers.iter().map(|answer| answer.subst).collect();
+    assert_eq!(witnesses, vec![Some("_")]);
+    assert_eq!(forest.num_declined_answers(), 2);
+    assert_eq!(forest.num_duplicate_answers(), 0);
+}
@@ -30,40 +30,6 @@ macro_rules! index_struct {
             }
         }
 
-        impl ::std::iter::Step for $n {
-            fn steps_between(start: &Self, end: &Self) -> Option<usize> {
-                usize::steps_between(&start.value, &end.value)
-            }
-
-            fn replace_one(&mut self) -> Self {
-                Self {
-                    value: usize::replace_one(&mut self.value),
-                }
-            }
-
-            fn replace_zero(&mut self) -> Self {
-                Self {
-                    value: usize::replace_zero(&mut self.value),
-                }
-            }
-
-            fn add_one(&self) -> Self {
-                Self {
-                    value: usize::add_one(&self.value),
-                }
-            }
-
-            fn sub_one(&self) -> Self {
-                Self {
-                    value: usize::sub_one(&self.value),
-                }
-            }
-
-            fn add_usize(&self, n: usize) -> Option<Self> {
-                usize::add_usize(&self.value, n).map(|value| Self { value })
-            }
-        }
-
         impl From<usize> for $n {
             fn from(value: usize) -> Self {
                 Self { value: value }
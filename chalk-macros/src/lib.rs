@@ -1,7 +1,3 @@
-#![feature(crate_visibility_modifier)]
-#![feature(macro_vis_matcher)]
-#![feature(non_modrs_mods)]
-
 use std::cell::RefCell;
 
 #[macro_use]
@@ -31,7 +27,7 @@ lazy_static! {
 }
 
 thread_local! {
-    crate static INDENT: RefCell<Vec<String>> = RefCell::new(vec![]);
+    pub(crate) static INDENT: RefCell<Vec<String>> = RefCell::new(vec![]);
 }
 
 // When CHALK_DEBUG is enabled, we only allow this many frames of
@@ -1,5 +1,7 @@
-use lalrpop_intern::InternedString;
+use lalrpop_intern::{intern, InternedString};
+use std::collections::hash_map::DefaultHasher;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub struct Span {
@@ -13,53 +15,105 @@ impl Span {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+/// Spans are left out of the hash of the AST: they record where an
+/// item was written rather than what it says, so moving an item
+/// around the source does not change its `content_hash`.
+impl Hash for Span {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Program {
-    pub items: Vec<Item>
+    pub items: Vec<Item>,
+
+    /// Whether the program starts with `#[test_reflection]`, which
+    /// allows goals to ask about the program itself (see
+    /// `ReflectionGoal`).
+    pub test_reflection: bool,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+/// One of the crates of a program that is split across several
+/// files. Names are shared between all the crates of a program, but
+/// each crate sees the items of the others as upstream.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Crate {
+    pub name: InternedString,
+    pub program: Program,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum Item {
     StructDefn(StructDefn),
     TraitDefn(TraitDefn),
     Impl(Impl),
     Clause(Clause),
+    ExternalPredicateDefn(ExternalPredicateDefn),
+    ConstDefn(ConstDefn),
+    SyntheticTyDefn(SyntheticTyDefn),
+}
+
+impl Item {
+    /// A hash of what the item says, ignoring where it was written.
+    /// Lowering uses it to tell which items are unchanged when a
+    /// program is lowered again after its source was edited.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct StructDefn {
     pub name: Identifier,
     pub parameter_kinds: Vec<ParameterKind>,
+    /// The default of each parameter, like the `0` in
+    /// `struct Foo<const N = 0>`. Only const parameters can have one.
+    pub const_defaults: Vec<Option<Const>>,
     pub where_clauses: Vec<QuantifiedWhereClause>,
     pub fields: Vec<Field>,
+    /// The auto traits named by `#[phantom_no_auto(...)]`, for which
+    /// the struct gets no default impl.
+    pub no_auto_traits: Vec<Identifier>,
     pub flags: StructFlags,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct StructFlags {
     pub upstream: bool,
     pub fundamental: bool,
+    pub interior_mut: bool,
+    pub lang_box: bool,
+    pub lang_phantom_data: bool,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct TraitDefn {
     pub name: Identifier,
     pub parameter_kinds: Vec<ParameterKind>,
+    /// Whether each parameter is marked `#[fundamental]`, as the `T` of
+    /// `trait Fn<#[fundamental] T>`.
+    pub fundamental_parameters: Vec<bool>,
+    /// The bounds written after a colon, as in `trait Foo: Bar + Baz`.
+    /// These are kept apart from the `where` clauses they stand for, so
+    /// that the trait can be printed back the way it was written.
+    pub supertraits: Vec<QuantifiedInlineBound>,
     pub where_clauses: Vec<QuantifiedWhereClause>,
     pub assoc_ty_defns: Vec<AssocTyDefn>,
     pub flags: TraitFlags,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct TraitFlags {
     pub auto: bool,
     pub marker: bool,
     pub upstream: bool,
     pub fundamental: bool,
     pub deref: bool,
+    pub sync: bool,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct AssocTyDefn {
     pub name: Identifier,
     pub parameter_kinds: Vec<ParameterKind>,
@@ -67,40 +121,212 @@ pub struct AssocTyDefn {
     pub where_clauses: Vec<QuantifiedWhereClause>,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+/// A predicate whose goals are answered by the embedder of the solver
+/// rather than by program clauses, e.g. `external predicate Foo(type);`.
+pub struct ExternalPredicateDefn {
+    pub name: Identifier,
+    pub parameter_kinds: Vec<Kind>,
+}
+
+/// A named constant, like `const FOO: usize;`, whose value is only
+/// known to the embedder of the solver.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ConstDefn {
+    pub name: Identifier,
+    pub parameter_kinds: Vec<ParameterKind>,
+    /// The type of the constant, like `usize`. Consts are untyped once
+    /// lowered, so this is only checked to name a type.
+    pub ty: Ty,
+}
+
+/// A rigid type constructor that is not declared as a struct, like the
+/// type of a function definition: `synthetic type FnDef<type, lifetime>;`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct SyntheticTyDefn {
+    pub name: Identifier,
+    pub parameter_kinds: Vec<Kind>,
+    /// The types the synthetic type is made of, as far as auto traits
+    /// are concerned, like the `T` of
+    /// `synthetic type Witness<type> constituents<T> { T }`.
+    pub constituents: Option<Constituents>,
+}
+
+/// The constituent types of a synthetic type. The parameters of the
+/// type are unnamed where it is declared, so they are named here.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Constituents {
+    pub parameter_kinds: Vec<ParameterKind>,
+    pub tys: Vec<Ty>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum ParameterKind {
     Ty(Identifier),
     Lifetime(Identifier),
+    Const(Identifier),
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+/// A parameter bound by a `forall` or `exists` goal. To control the
+/// universes of the goals they solve, tests may ascribe a universe to
+/// it, as in `exists<T in U2> { ... }`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct GoalBinder {
+    pub kind: ParameterKind,
+    pub universe: Option<Identifier>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum Parameter {
     Ty(Ty),
     Lifetime(Lifetime),
+    Const(Const),
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 /// An inline bound, e.g. `: Foo<K>` in `impl<K, T: Foo<K>> SomeType<T>`.
 pub enum InlineBound {
     TraitBound(TraitBound),
     ProjectionEqBound(ProjectionEqBound),
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct QuantifiedInlineBound {
     pub parameter_kinds: Vec<ParameterKind>,
     pub bound: InlineBound,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 /// Represents a trait bound on e.g. a type or type parameter.
 /// Does not know anything about what it's binding.
 pub struct TraitBound {
     pub trait_name: Identifier,
     pub args_no_self: Vec<Parameter>,
+    /// The bounds on the associated types of the trait, like the
+    /// `Item: Clone` of `Iterator<Item: Clone>`.
+    pub assoc_ty_bounds: Vec<AssocTyBound>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+/// Bounds on an associated type, written where the trait is named, like
+/// the `Item: Clone` of `T: Iterator<Item: Clone>`. They are sugar for
+/// the same bounds on the projection: `<T as Iterator>::Item: Clone`.
+pub struct AssocTyBound {
+    pub name: Identifier,
+    pub args: Vec<Parameter>,
+    pub bounds: Vec<TraitBound>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+/// An argument of a trait in a bound, as it is parsed: a parameter, a
+/// bound on one of the associated types of the trait, like `Item:
+/// Clone`, or the value of one, like `Item = u32`. They are parsed as
+/// one list, which `BoundArgs::split` then sorts out.
+pub enum BoundArg {
+    Parameter(Parameter),
+    AssocTyBound(AssocTyBound),
+    AssocTyValue { name: Identifier, args: Vec<Parameter>, value: Ty },
+}
+
+/// The arguments of a trait in a bound, sorted by kind (see `BoundArg`).
+pub struct BoundArgs {
+    pub parameters: Vec<Parameter>,
+    pub assoc_ty_bounds: Vec<AssocTyBound>,
+    pub assoc_ty_values: Vec<(Identifier, Vec<Parameter>, Ty)>,
+}
+
+impl BoundArgs {
+    /// Sorts out the arguments of a trait in a bound, whose parameters
+    /// must come before its associated types.
+    pub fn split(args: Vec<BoundArg>) -> Result<BoundArgs, &'static str> {
+        let mut bound_args = BoundArgs {
+            parameters: vec![],
+            assoc_ty_bounds: vec![],
+            assoc_ty_values: vec![],
+        };
+        for arg in args {
+            match arg {
+                BoundArg::Parameter(parameter) => {
+                    if !bound_args.assoc_ty_bounds.is_empty()
+                        || !bound_args.assoc_ty_values.is_empty()
+                    {
+                        return Err(
+                            "the parameters of a trait must come before its associated types",
+                        );
+                    }
+                    bound_args.parameters.push(parameter);
+                }
+                BoundArg::AssocTyBound(bound) => bound_args.assoc_ty_bounds.push(bound),
+                BoundArg::AssocTyValue { name, args, value } => {
+                    bound_args.assoc_ty_values.push((name, args, value))
+                }
+            }
+        }
+        Ok(bound_args)
+    }
+
+    /// The bound `trait_name<..>`, which may put bounds on the
+    /// associated types of the trait, but not give their values.
+    pub fn into_trait_bound(self, trait_name: Identifier) -> Result<TraitBound, &'static str> {
+        if !self.assoc_ty_values.is_empty() {
+            return Err("the value of an associated type cannot be given in this bound");
+        }
+        Ok(TraitBound {
+            trait_name,
+            args_no_self: self.parameters,
+            assoc_ty_bounds: self.assoc_ty_bounds,
+        })
+    }
+
+    /// The inline bound `trait_name<..>`, which may either give the
+    /// value of one associated type or put bounds on them.
+    pub fn into_inline_bound(self, trait_name: Identifier) -> Result<InlineBound, &'static str> {
+        let BoundArgs { parameters, assoc_ty_bounds, mut assoc_ty_values } = self;
+        let trait_bound = TraitBound {
+            trait_name,
+            args_no_self: parameters,
+            assoc_ty_bounds,
+        };
+        match assoc_ty_values.pop() {
+            None => Ok(InlineBound::TraitBound(trait_bound)),
+            Some((name, args, value))
+                if assoc_ty_values.is_empty() && trait_bound.assoc_ty_bounds.is_empty() =>
+            {
+                Ok(InlineBound::ProjectionEqBound(ProjectionEqBound {
+                    trait_bound,
+                    name,
+                    args,
+                    value,
+                }))
+            }
+            Some(_) => Err("a bound can only give the value of one associated type"),
+        }
+    }
+
+    /// The where clause `self_ty: trait_name<..>`, which may either
+    /// give the value of one associated type or put bounds on them.
+    pub fn into_where_clause(
+        self,
+        self_ty: Ty,
+        trait_name: Identifier,
+    ) -> Result<WhereClause, &'static str> {
+        let BoundArgs { parameters, assoc_ty_bounds, mut assoc_ty_values } = self;
+        let mut args = vec![Parameter::Ty(self_ty)];
+        args.extend(parameters);
+        let trait_ref = TraitRef { trait_name, args };
+        match assoc_ty_values.pop() {
+            None if assoc_ty_bounds.is_empty() => Ok(WhereClause::Implemented { trait_ref }),
+            None => Ok(WhereClause::AssocTyBounds { trait_ref, assoc_ty_bounds }),
+            Some((name, args, ty)) if assoc_ty_values.is_empty() && assoc_ty_bounds.is_empty() => {
+                let projection = ProjectionTy { trait_ref, name, args };
+                Ok(WhereClause::ProjectionEq { projection, ty })
+            }
+            Some(_) => Err("a where clause can only give the value of one associated type"),
+        }
+    }
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 /// Represents a projection equality bound on e.g. a type or type parameter.
 /// Does not know anything about what it's binding.
 pub struct ProjectionEqBound {
@@ -110,10 +336,11 @@ pub struct ProjectionEqBound {
     pub value: Ty,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum Kind {
     Ty,
     Lifetime,
+    Const,
 }
 
 impl fmt::Display for Kind {
@@ -122,6 +349,7 @@ impl fmt::Display for Kind {
             match *self {
                 Kind::Ty => "type",
                 Kind::Lifetime => "lifetime",
+                Kind::Const => "const",
             }
         )
     }
@@ -136,6 +364,7 @@ impl Kinded for ParameterKind {
         match *self {
             ParameterKind::Ty(_) => Kind::Ty,
             ParameterKind::Lifetime(_) => Kind::Lifetime,
+            ParameterKind::Const(_) => Kind::Const,
         }
     }
 }
@@ -145,33 +374,37 @@ impl Kinded for Parameter {
         match *self {
             Parameter::Ty(_) => Kind::Ty,
             Parameter::Lifetime(_) => Kind::Lifetime,
+            Parameter::Const(_) => Kind::Const,
         }
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Impl {
     pub parameter_kinds: Vec<ParameterKind>,
     pub trait_ref: PolarizedTraitRef,
     pub where_clauses: Vec<QuantifiedWhereClause>,
     pub assoc_ty_values: Vec<AssocTyValue>,
     pub impl_type: ImplType,
+
+    /// The name given with `#[name = "..."]`, if any.
+    pub name: Option<String>,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum ImplType {
     Local,
     External,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct AssocTyValue {
     pub name: Identifier,
     pub parameter_kinds: Vec<ParameterKind>,
     pub value: Ty,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum Ty {
     Id {
         name: Identifier,
@@ -192,33 +425,162 @@ pub enum Ty {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum Lifetime {
     Id {
         name: Identifier,
+    },
+
+    /// `'static`, which outlives every other lifetime.
+    Static,
+}
+
+/// A const argument, like the `3` in `Foo<3>`. Note that a reference
+/// to a const parameter `N` is parsed as a type (`Ty::Id`), since the
+/// two are syntactically indistinguishable; lowering sorts them out.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Const {
+    Value(ConstValue),
+    /// `_`, a const to be inferred. This is only allowed in goals.
+    Elided,
+    /// `{FOO}` or `{FOO<T>}`, a use of a named constant.
+    Unevaluated {
+        name: Identifier,
+        args: Vec<Parameter>,
+    },
+    /// `{255u8 + 1}`, an arithmetic expression, evaluated when lowered.
+    Expr(ConstExpr),
+}
+
+/// A const literal: an integer like `3`, `3u8` or `-1i32`, or a `bool`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ConstValue {
+    Int {
+        negative: bool,
+        magnitude: u128,
+        /// The type of the literal, like the `u8` of `3u8`, if given.
+        suffix: Option<InternedString>,
+    },
+    Bool(bool),
+}
+
+impl ConstValue {
+    /// The integer literal written `text`, a number possibly followed by
+    /// the name of an integer type. The number must fit in a `u128`.
+    pub fn int(negative: bool, text: &str) -> Result<ConstValue, &'static str> {
+        let digits = text.find(|c: char| !c.is_digit(10)).unwrap_or(text.len());
+        let (magnitude, suffix) = text.split_at(digits);
+        let magnitude = magnitude
+            .parse()
+            .map_err(|_| "the integer literal is too large for any integer type")?;
+        Ok(ConstValue::Int {
+            negative,
+            magnitude,
+            suffix: if suffix.is_empty() {
+                None
+            } else {
+                Some(intern(suffix))
+            },
+        })
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+impl fmt::Display for ConstValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConstValue::Int {
+                negative,
+                magnitude,
+                suffix,
+            } => {
+                if negative {
+                    write!(f, "-")?;
+                }
+                write!(f, "{}", magnitude)?;
+                match suffix {
+                    Some(suffix) => write!(f, "{}", suffix),
+                    None => Ok(()),
+                }
+            }
+            ConstValue::Bool(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// An arithmetic expression on const literals.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ConstExpr {
+    Value(ConstValue),
+    Binary {
+        op: ConstBinOp,
+        lhs: Box<ConstExpr>,
+        rhs: Box<ConstExpr>,
+    },
+}
+
+impl fmt::Display for ConstExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConstExpr::Value(ref value) => write!(f, "{}", value),
+            ConstExpr::Binary {
+                op,
+                ref lhs,
+                ref rhs,
+            } => {
+                // Nested operations are parenthesized, whatever their
+                // precedence.
+                for (i, operand) in [lhs, rhs].iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " {} ", op)?;
+                    }
+                    match ***operand {
+                        ConstExpr::Value(_) => write!(f, "{}", operand)?,
+                        ConstExpr::Binary { .. } => write!(f, "({})", operand)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ConstBinOp {
+    Add,
+    Sub,
+    Mul,
+}
+
+impl fmt::Display for ConstBinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            ConstBinOp::Add => "+",
+            ConstBinOp::Sub => "-",
+            ConstBinOp::Mul => "*",
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct ProjectionTy {
     pub trait_ref: TraitRef,
     pub name: Identifier,
     pub args: Vec<Parameter>,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct UnselectedProjectionTy {
     pub name: Identifier,
     pub args: Vec<Parameter>,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct TraitRef {
     pub trait_name: Identifier,
     pub args: Vec<Parameter>,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum PolarizedTraitRef {
     Positive(TraitRef),
     Negative(TraitRef),
@@ -234,19 +596,23 @@ impl PolarizedTraitRef {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Identifier {
     pub str: InternedString,
     pub span: Span,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum WhereClause {
     Implemented { trait_ref: TraitRef },
     ProjectionEq { projection: ProjectionTy, ty: Ty },
+
+    /// `T: Iterator<Item: Clone>`: the trait reference is implemented,
+    /// and its associated types meet their bounds (see `AssocTyBound`).
+    AssocTyBounds { trait_ref: TraitRef, assoc_ty_bounds: Vec<AssocTyBound> },
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum DomainGoal {
     Holds { where_clause: WhereClause },
     Normalize { projection: ProjectionTy, ty: Ty },
@@ -262,40 +628,69 @@ pub enum DomainGoal {
     LocalImplAllowed { trait_ref: TraitRef },
     Compatible,
     DownstreamType { ty: Ty },
+    External { predicate: Identifier, args: Vec<Parameter> },
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum LeafGoal {
     DomainGoal { goal: DomainGoal },
     UnifyTys { a: Ty, b: Ty },
     UnifyLifetimes { a: Lifetime, b: Lifetime },
+    Reflection { goal: ReflectionGoal },
+}
+
+/// A goal about the program itself rather than about the types in it,
+/// answered by looking at the program instead of by solving. Only
+/// allowed in programs marked `#[test_reflection]`, for testing the
+/// clauses that programs lower to.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ReflectionGoal {
+    /// `HasImpl(Trait, Ty)`: the program has an impl of `Trait` whose
+    /// header could apply to `Ty`.
+    HasImpl { trait_name: Identifier, ty: Ty },
+
+    /// `ClauseCount(Trait, N)`: exactly `N` of the clauses of the
+    /// program are about `Trait`.
+    ClauseCount { trait_name: Identifier, count: usize },
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct QuantifiedWhereClause {
     pub parameter_kinds: Vec<ParameterKind>,
     pub where_clause: WhereClause,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub struct Field {
     pub name: Identifier,
     pub ty: Ty,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 /// This allows users to add arbitrary `A :- B` clauses into the
 /// logic; it has no equivalent in Rust, but it's useful for testing.
 pub struct Clause {
     pub parameter_kinds: Vec<ParameterKind>,
     pub consequence: DomainGoal,
     pub conditions: Vec<Box<Goal>>,
+
+    /// The name given with `#[name = "..."]`, if any. Only clauses
+    /// written as items may be named, not those assumed by goals.
+    pub name: Option<String>,
+}
+
+/// A goal together with the crate it is asked from, as in
+/// `in crate foo { T: Clone }`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct CrateGoal {
+    pub krate: Option<Identifier>,
+    pub goal: Box<Goal>,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum Goal {
-    ForAll(Vec<ParameterKind>, Box<Goal>),
-    Exists(Vec<ParameterKind>, Box<Goal>),
+    ForAll(Vec<GoalBinder>, Box<Goal>),
+    Exists(Vec<GoalBinder>, Box<Goal>),
     Implies(Vec<Clause>, Box<Goal>),
     And(Box<Goal>, Box<Goal>),
     Not(Box<Goal>),
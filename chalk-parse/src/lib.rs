@@ -1,5 +1,3 @@
-#![feature(non_modrs_mods, tool_attributes)]
-
 #![recursion_limit = "1024"]
 
 #[macro_use]
@@ -14,8 +12,9 @@ pub mod errors;
 mod parser;
 
 use errors::Result;
+use lalrpop_intern::intern;
 use lalrpop_util::ParseError;
-use std::fmt::Write;
+use std::fmt::{self, Write};
 
 pub fn parse_program(text: &str) -> Result<ast::Program> {
     match parser::parse_Program(text) {
@@ -31,30 +30,54 @@ pub fn parse_ty(text: &str) -> Result<ast::Ty> {
     }
 }
 
+/// Parses the text of one crate of a program that is split across
+/// several files.
+pub fn parse_crate(name: &str, text: &str) -> Result<ast::Crate> {
+    Ok(ast::Crate {
+        name: intern(name),
+        program: parse_program(text)?,
+    })
+}
+
 pub fn parse_goal(text: &str) -> Result<Box<ast::Goal>> {
     match parser::parse_Goal(text) {
         Ok(v) => Ok(v),
-        Err(e) => {
-            let position_string = |start: usize, end: usize| {
-                let mut output = String::new();
-                let text = text.replace("\n", " ").replace("\r", " ");
-                write!(output, "position: `{}`\n", text).expect("str-write cannot fail");
-                write!(output, "           ").expect("str-write cannot fail");
-                for _ in 0..start { output.push_str(" "); }
-                for _ in start..end { output.push_str("^"); }
-                output.push_str("\n");
-                output
-            };
-            match e {
-                ParseError::InvalidToken { location } =>
-                    bail!("parse error: {:?}\n{}", e, position_string(location, location+1)),
-                ParseError::UnrecognizedToken { token: Some((start, _, end)), .. } =>
-                    bail!("parse error: {:?}\n{}", e, position_string(start, end)),
-                ParseError::ExtraToken { token: (start, _, end), .. } =>
-                    bail!("parse error: {:?}\n{}", e, position_string(start, end)),
-                _ =>
-                    bail!("parse error: {:?}", e),
-            }
-        }
+        Err(e) => Err(goal_parse_error(text, e)),
     }
 }
+
+/// Parses a goal that may name the crate it is asked from, as in
+/// `in crate foo { T: Clone }`.
+pub fn parse_crate_goal(text: &str) -> Result<ast::CrateGoal> {
+    match parser::parse_CrateGoal(text) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(goal_parse_error(text, e)),
+    }
+}
+
+fn goal_parse_error<T: fmt::Debug, E: fmt::Debug>(
+    text: &str,
+    e: ParseError<usize, T, E>,
+) -> errors::Error {
+    let position_string = |start: usize, end: usize| {
+        let mut output = String::new();
+        let text = text.replace("\n", " ").replace("\r", " ");
+        write!(output, "position: `{}`\n", text).expect("str-write cannot fail");
+        write!(output, "           ").expect("str-write cannot fail");
+        for _ in 0..start { output.push_str(" "); }
+        for _ in start..end { output.push_str("^"); }
+        output.push_str("\n");
+        output
+    };
+    let message = match e {
+        ParseError::InvalidToken { location } =>
+            format!("parse error: {:?}\n{}", e, position_string(location, location+1)),
+        ParseError::UnrecognizedToken { token: Some((start, _, end)), .. } =>
+            format!("parse error: {:?}\n{}", e, position_string(start, end)),
+        ParseError::ExtraToken { token: (start, _, end), .. } =>
+            format!("parse error: {:?}\n{}", e, position_string(start, end)),
+        _ =>
+            format!("parse error: {:?}", e),
+    };
+    message.into()
+}
@@ -1,6 +1,7 @@
 extern crate chalk;
 extern crate chalk_parse;
 extern crate docopt;
+extern crate lalrpop_intern;
 extern crate rustyline;
 
 #[macro_use]
@@ -9,16 +10,28 @@ extern crate serde_derive;
 #[macro_use]
 extern crate error_chain;
 
-use std::io::Read;
-use std::fs::File;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{ErrorKind as IoErrorKind, Read};
+use std::fs::{File, OpenOptions};
 use std::sync::Arc;
 use std::process::exit;
 
+use chalk::completion::Completions;
 use chalk::ir;
 use chalk::ir::lowering::*;
-use chalk::solve::SolverChoice;
+use chalk::replay::{ProgramSource, RecordedSolve, Recorder, Recording};
+use chalk::solve::{
+    ConstOverflow, Scheduling, Solution, SolveOutcome, SolverChoice, SubgoalOrdering,
+    TruncationMeasure,
+};
 use docopt::Docopt;
+use lalrpop_intern::InternedString;
+use rustyline::completion::Completer;
 use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{CompletionType, Config, Context, Helper};
 
 const USAGE: &'static str = "
 chalk repl
@@ -30,18 +43,55 @@ Usage:
 Options:
   --help              Show this screen.
   --program=PATH      Specifies the path to the `.chalk` file containing traits/impls.
+  --crate=SPEC        Loads a `.chalk` file as a crate, given as NAME=PATH (may be given
+                      more than once). Goals are solved as seen from the last crate,
+                      unless they name another one: `in crate NAME { GOAL }`.
   --goal=GOAL         Specifies a goal to evaluate (may be given more than once).
   --overflow-depth=N  Specifies the overflow depth [default: 10].
+  --projection-depth=N
+                      Specifies how many projections may be normalized within one
+                      another [default: 64].
+  --type-depth=N      Specifies how deeply the types of goals may be nested
+                      [default: 256].
+  --truncate-by-depth
+                      Measure the overflow depth of types by their depth, rather than
+                      by the number of types they are made of.
   --no-cache          Disable caching.
+  --simplify-goals    Simplify goals before solving them.
+  --prioritize-subgoals
+                      Prove the trait conditions of clauses before their projections.
+  --provenance        Follow each lifetime constraint of a solution with the goal that
+                      imposed it, as a comment.
+  --overflowing-consts-cannot-prove
+                      Solve goals in which a const expression overflows as goals that
+                      cannot be proven, rather than rejecting them.
+  --no-ground-fast-path
+                      Solve goals without variables with tables, like any other goal,
+                      even those which simply hold by the clauses of the program.
+  --record=PATH       Appends each goal solved, with its program, the solver options
+                      and its solution, to the recording at PATH (for bug reports).
+  --replay=PATH       Solves the goals of the recording at PATH again, and reports those
+                      whose solutions differ from the recorded ones.
 ";
 
 /// This struct represents the various command line options available.
 #[derive(Debug, Deserialize)]
 struct Args {
     flag_program: Option<String>,
+    flag_crate: Vec<String>,
     flag_goal: Vec<String>,
     flag_overflow_depth: usize,
+    flag_projection_depth: usize,
+    flag_type_depth: usize,
+    flag_truncate_by_depth: bool,
     flag_no_cache: bool,
+    flag_simplify_goals: bool,
+    flag_prioritize_subgoals: bool,
+    flag_provenance: bool,
+    flag_overflowing_consts_cannot_prove: bool,
+    flag_no_ground_fast_path: bool,
+    flag_record: Option<String>,
+    flag_replay: Option<String>,
 }
 
 error_chain! {
@@ -56,22 +106,142 @@ error_chain! {
     }
 }
 
+/// The commands of the REPL, for completion (see `help`).
+const COMMANDS: &[&str] = &[
+    "debug", "disable", "enable", "env", "help", "load", "lowered", "print", "program",
+];
+
+type Editor = rustyline::Editor<ReplCompleter>;
+
+/// Completes the words of commands and goals, including the names of
+/// the items of the program currently loaded.
+struct ReplCompleter {
+    completions: Completions,
+}
+
+impl ReplCompleter {
+    fn new(prog: Option<&Program>) -> Self {
+        ReplCompleter {
+            completions: Completions::new(COMMANDS, prog.map(|prog| &*prog.ir)),
+        }
+    }
+}
+
+impl Completer for ReplCompleter {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context,
+    ) -> ::std::result::Result<(usize, Vec<String>), ReadlineError> {
+        Ok(self.completions.complete(line, pos))
+    }
+}
+
+// Completion is all the REPL uses the line editor's helper for.
+impl Helper for ReplCompleter {}
+
+impl Hinter for ReplCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for ReplCompleter {}
+
+impl Validator for ReplCompleter {}
+
 /// A loaded and parsed program.
 struct Program {
     text: String,
+    /// The text the program was loaded from, to record it.
+    source: ProgramSource,
     ir: Arc<ir::Program>,
     env: Arc<ir::ProgramEnvironment>,
+    /// For a program split across crates, the program as seen from
+    /// each of them; `ir` and `env` are seen from the last one.
+    crates: BTreeMap<InternedString, (Arc<ir::Program>, Arc<ir::ProgramEnvironment>)>,
+    /// The names of the impls and clauses whose clauses are left out
+    /// when solving goals (see the `disable` command).
+    disabled_clauses: HashSet<String>,
+    /// The last goal that was solved, whose environment the `env`
+    /// command prints.
+    last_goal: Option<String>,
 }
 
 impl Program {
     /// Creates a new Program struct, given a `.chalk` file as a String and
-    /// a [`SolverChoice`].
+    /// a [`SolverChoice`]. If a program was loaded before, the items it
+    /// shares with the new one are not lowered again.
     ///
     /// [`SolverChoice`]: struct.solve.SolverChoice.html
-    fn new(text: String, solver_choice: SolverChoice) -> Result<Program> {
-        let ir = Arc::new(chalk_parse::parse_program(&text)?.lower(solver_choice)?);
+    fn new(text: String, solver_choice: SolverChoice, previous: Option<&Program>) -> Result<Program> {
+        let source = ProgramSource::File(text.clone());
+        let ast = chalk_parse::parse_program(&text)?;
+        let ir = match previous {
+            // A program split across crates is seen from one of them,
+            // which a single file is not.
+            Some(previous) if previous.crates.is_empty() => ast.relower(&previous.ir, solver_choice)?,
+            _ => ast.lower(solver_choice)?,
+        };
+        let ir = Arc::new(ir);
         let env = Arc::new(ir.environment());
-        Ok(Program { text, ir, env })
+        Ok(Program {
+            text,
+            source,
+            ir,
+            env,
+            crates: BTreeMap::new(),
+            disabled_clauses: HashSet::new(),
+            last_goal: None,
+        })
+    }
+
+    /// Creates a new Program struct from the `(name, text)` of each of
+    /// its crates.
+    fn from_crates(crates: Vec<(String, String)>, solver_choice: SolverChoice) -> Result<Program> {
+        let mut text = String::new();
+        let mut asts = vec![];
+        for (name, crate_text) in &crates {
+            text += &format!("// crate {}\n{}\n", name, crate_text);
+            asts.push(chalk_parse::parse_crate(name, crate_text)?);
+        }
+        let source = ProgramSource::Crates(crates);
+        let last_crate = asts.last().ok_or("a program needs at least one crate")?.name;
+        let crates: BTreeMap<_, _> = asts.lower_all_crates(solver_choice)?
+            .into_iter()
+            .map(|(name, ir)| {
+                let env = Arc::new(ir.environment());
+                (name, (Arc::new(ir), env))
+            })
+            .collect();
+        let (ir, env) = crates[&last_crate].clone();
+        Ok(Program {
+            text,
+            source,
+            ir,
+            env,
+            crates,
+            disabled_clauses: HashSet::new(),
+            last_goal: None,
+        })
+    }
+
+    /// Loads a program from the `source` it was recorded with.
+    fn from_source(source: &ProgramSource, solver_choice: SolverChoice) -> Result<Program> {
+        match source {
+            ProgramSource::File(text) => Program::new(text.clone(), solver_choice, None),
+            ProgramSource::Crates(crates) => Program::from_crates(crates.clone(), solver_choice),
+        }
+    }
+
+    /// `env` without the clauses of the disabled impls and clauses.
+    fn without_disabled(&self, env: &Arc<ir::ProgramEnvironment>) -> Arc<ir::ProgramEnvironment> {
+        if self.disabled_clauses.is_empty() {
+            env.clone()
+        } else {
+            Arc::new(env.without_clauses(&self.disabled_clauses))
+        }
     }
 }
 
@@ -89,22 +259,45 @@ fn run() -> Result<()> {
         exit(1);
     }
 
-    // Load the .chalk file, if given.
+    if args.flag_program.is_some() && !args.flag_crate.is_empty() {
+        eprintln!("error: `--program` and `--crate` cannot be used together");
+        exit(1);
+    }
+
+    // A recording is replayed with the programs and goals recorded in it.
+    if let Some(path) = &args.flag_replay {
+        return replay(path);
+    }
+
+    let mut recorder = match &args.flag_record {
+        Some(path) => Some(open_recorder(path)?),
+        None => None,
+    };
+
+    // Load the .chalk file(s), if given.
     let mut prog = None;
-    if let Some(program) = &args.flag_program {
-        match load_program(args, program) {
-            Ok(p) => prog = Some(p),
-            Err(err) => {
-                eprintln!("error loading program: {}", err);
-                exit(1);
-            }
+    let loaded = match &args.flag_program {
+        Some(program) => Some(load_program(args, program, None)),
+        None if !args.flag_crate.is_empty() => Some(load_crates(args, &args.flag_crate)),
+        None => None,
+    };
+    match loaded {
+        Some(Ok(p)) => prog = Some(p),
+        Some(Err(err)) => {
+            eprintln!("error loading program: {}", err);
+            exit(1);
         }
+        None => {}
     }
 
     if args.flag_goal.is_empty() {
-        // The user specified no goal. Enter interactive mode.
-        readline_loop(&mut rustyline::Editor::new(), "?- ", |rl, line| {
-            if let Err(e) = process(args, line, rl, &mut prog) {
+        // The user specified no goal. Enter interactive mode. When a
+        // word has several completions, they are listed.
+        let config = Config::builder().completion_type(CompletionType::List).build();
+        let mut rl = Editor::with_config(config);
+        rl.set_helper(Some(ReplCompleter::new(prog.as_ref())));
+        readline_loop(&mut rl, "?- ", |rl, line| {
+            if let Err(e) = process(args, line, rl, &mut prog, &mut recorder) {
                 eprintln!("error: {}", e);
             }
         })
@@ -112,13 +305,15 @@ fn run() -> Result<()> {
         // Check that a program was provided.
         // TODO: It's customary to print Usage info when an error like this
         // happens.
-        let prog = prog.ok_or("error: cannot eval without a program; use `--program` to specify one.")?;
+        let prog = prog.ok_or(
+            "error: cannot eval without a program; use `--program` or `--crate` to specify one."
+        )?;
 
         // Evaluate the goal(s). If any goal returns an error, print the error
         // and exit.
         ir::tls::set_current_program(&prog.ir, || -> Result<()> {
             for g in &args.flag_goal {
-                if let Err(e) = goal(&args, g, &prog) {
+                if let Err(e) = goal(&args, g, &prog, &mut recorder) {
                     eprintln!("error: {}", e);
                     exit(1);
                 }
@@ -135,9 +330,9 @@ fn run() -> Result<()> {
 ///
 /// The loop terminates (and the program ends) when EOF is reached or if an error
 /// occurs while reading the next line.
-fn readline_loop<F>(rl: &mut rustyline::Editor<()>, prompt: &str, mut f: F) -> Result<()>
+fn readline_loop<F>(rl: &mut Editor, prompt: &str, mut f: F) -> Result<()>
 where
-    F: FnMut(&mut rustyline::Editor<()>, &str),
+    F: FnMut(&mut Editor, &str),
 {
     loop {
         match rl.readline(prompt) {
@@ -167,8 +362,9 @@ where
 fn process(
     args: &Args,
     command: &str,
-    rl: &mut rustyline::Editor<()>,
+    rl: &mut Editor,
     prog: &mut Option<Program>,
+    recorder: &mut Option<Recorder<File>>,
 ) -> Result<()> {
     if command == "help" || command == "h" {
         // Print out interpreter commands.
@@ -177,12 +373,32 @@ fn process(
 
     } else if command == "program" {
         // Load a .chalk file via stdin, until EOF is found.
-        *prog = Some(Program::new(read_program(rl)?, args.solver_choice())?);
+        let text = read_program(rl)?;
+        *prog = Some(Program::new(text, args.solver_choice(), prog.as_ref())?);
+        rl.set_helper(Some(ReplCompleter::new(prog.as_ref())));
 
     } else if command.starts_with("load ") {
         // Load a .chalk file.
         let filename = &command["load ".len()..];
-        *prog = Some(load_program(args, filename)?);
+        *prog = Some(load_program(args, filename, prog.as_ref())?);
+        rl.set_helper(Some(ReplCompleter::new(prog.as_ref())));
+
+    } else if command.starts_with("disable ") || command.starts_with("enable ") {
+        // Disable or re-enable the clauses of named impls and clauses.
+        let prog = prog.as_mut()
+            .ok_or("no program currently loaded; type 'help' to see available commands")?;
+        let mut words = command.split_whitespace();
+        let disable = words.next() == Some("disable");
+        for name in words {
+            if !prog.env.has_clause_named(name) {
+                bail!("no impl or clause is named `{}`", name);
+            }
+            if disable {
+                prog.disabled_clauses.insert(name.to_string());
+            } else {
+                prog.disabled_clauses.remove(name);
+            }
+        }
 
     } else if command.starts_with("debug ") {
         match command.split_whitespace().nth(1) {
@@ -190,14 +406,15 @@ fn process(
             None => println!("debug <level> set debug level to <level>")
         }
     } else {
-        // The command is either "print", "lowered", or a goal.
+        // The command is either "print", "lowered", "env", or a goal.
 
         // Check that a program has been loaded.
-        let prog = prog.as_ref()
+        let prog = prog.as_mut()
             .ok_or("no program currently loaded; type 'help' to see available commands")?;
 
         // Attempt to parse the program.
-        ir::tls::set_current_program(&prog.ir, || -> Result<()> {
+        let ir = prog.ir.clone();
+        ir::tls::set_current_program(&ir, || -> Result<()> {
             match command {
                 // Print out the loaded program.
                 "print" => println!("{}", prog.text),
@@ -205,10 +422,16 @@ fn process(
                 // TODO: Write a line of documentation here.
                 "lowered" => println!("{:#?}", prog.env),
 
+                // Print out the environment of the last goal, elaborated.
+                "env" => print!("{}", env(prog)?),
+
                 // Assume this is a goal.
                 // TODO: Print out "type 'help' to see available commands" if it
                 // fails to parse?
-                _ => goal(args, command, prog)?,
+                _ => {
+                    goal(args, command, prog, recorder)?;
+                    prog.last_goal = Some(command.to_string());
+                }
             }
             Ok(())
         })?
@@ -220,10 +443,26 @@ fn process(
 /// Load the file into a string, and parse it.
 // TODO: Could we pass in an Options struct or something? The Args struct
 // still has Strings where it should have Enums... (e.g. solver_choice)
-fn load_program(args: &Args, filename: &str) -> Result<Program> {
+fn load_program(args: &Args, filename: &str, previous: Option<&Program>) -> Result<Program> {
     let mut text = String::new();
     File::open(filename)?.read_to_string(&mut text)?;
-    Ok(Program::new(text, args.solver_choice())?)
+    Ok(Program::new(text, args.solver_choice(), previous)?)
+}
+
+/// Load each `NAME=PATH` crate into a string, and parse them together.
+fn load_crates(args: &Args, specs: &[String]) -> Result<Program> {
+    let mut crates = vec![];
+    for spec in specs {
+        let mut parts = spec.splitn(2, '=');
+        let (name, filename) = match (parts.next(), parts.next()) {
+            (Some(name), Some(filename)) if !name.is_empty() => (name, filename),
+            _ => bail!("expected `--crate=NAME=PATH`, found `--crate={}`", spec),
+        };
+        let mut text = String::new();
+        File::open(filename)?.read_to_string(&mut text)?;
+        crates.push((name.to_string(), text));
+    }
+    Ok(Program::from_crates(crates, args.solver_choice())?)
 }
 
 /// Print out help for commands in interpreter mode.
@@ -235,13 +474,21 @@ fn help() {
     println!("  load <file>   load program from <file>");
     println!("  print         print the current program");
     println!("  lowered       print the lowered program");
+    println!("  env           print the environment of the last goal, with all it implies");
     println!("  <goal>        attempt to solve <goal>");
+    println!("  in crate <name> {{ <goal> }}");
+    println!("                attempt to solve <goal> as seen from crate <name>");
     println!("  debug <level> set debug level to <level>");
+    println!("  disable <name>");
+    println!("                leave out the clauses of the impl or clause named <name>");
+    println!("  enable <name> stop leaving them out");
+    println!();
+    println!("Press tab to complete the names of items, keywords and commands.");
 }
 
 /// Read a program from the command-line. Stop reading when EOF is read. If
 /// an error occurs while reading, a Result::Err is returned.
-fn read_program(rl: &mut rustyline::Editor<()>) -> Result<String> {
+fn read_program(rl: &mut Editor) -> Result<String> {
     println!("Enter a program; press Ctrl-D when finished");
     let mut text = String::new();
     readline_loop(rl, "| ", |_, line| {
@@ -251,22 +498,217 @@ fn read_program(rl: &mut rustyline::Editor<()>) -> Result<String> {
     Ok(text)
 }
 
-/// Parse a goal and attempt to solve it, using the specified solver.
+/// Parse a goal and attempt to solve it, using the specified solver, and
+/// print the solution. The goal is recorded if a recording is being made.
 // TODO: Could we pass in an Options struct or something? The Args struct
 // still has Strings where it should have Enums... (e.g. solver_choice)
-fn goal(args: &Args, text: &str, prog: &Program) -> Result<()> {
-    let goal = chalk_parse::parse_goal(text)?.lower(&*prog.ir)?;
-    let peeled_goal = goal.into_peeled_goal();
-    match args.solver_choice().solve_root_goal(&prog.env, &peeled_goal) {
-        Ok(Some(v)) => println!("{}\n", v),
-        Ok(None) => println!("No possible solution.\n"),
-        Err(e) => println!("Solver failed: {}", e),
+fn goal(
+    args: &Args,
+    text: &str,
+    prog: &Program,
+    recorder: &mut Option<Recorder<File>>,
+) -> Result<()> {
+    let solver_choice = args.solver_choice();
+    let result = solve(solver_choice, text, prog);
+
+    if let Some(recorder) = recorder {
+        let mut disabled_clauses: Vec<_> = prog.disabled_clauses.iter().cloned().collect();
+        disabled_clauses.sort();
+        let solution = match &result {
+            Ok(solution) => solution.clone(),
+            Err(e) => format!("error: {}", e),
+        };
+        recorder.record(&prog.source, &RecordedSolve {
+            program: prog.source.fingerprint(),
+            solver_choice,
+            disabled_clauses,
+            goal: text.to_string(),
+            solution,
+        })?;
+    }
+
+    print!("{}", result?);
+    Ok(())
+}
+
+/// Parse a goal and attempt to solve it, returning the solution as it is
+/// to be printed.
+fn solve(solver_choice: SolverChoice, text: &str, prog: &Program) -> Result<String> {
+    let goal = chalk_parse::parse_crate_goal(text)?;
+    let (ir, env) = match goal.krate {
+        None => (&prog.ir, &prog.env),
+        Some(krate) => match prog.crates.get(&krate.str) {
+            Some((ir, env)) => (ir, env),
+            None => bail!("no crate named `{}` is loaded", krate.str),
+        },
+    };
+    let env = &prog.without_disabled(env);
+    ir::tls::set_current_program(ir, || -> Result<String> {
+        // Goals about the program itself are answered without solving.
+        if let Some(reflection) = goal.goal.lower_reflection(&**ir)? {
+            return Ok(match reflection.solve(ir, env) {
+                Some(v) => format!("{}\n\n", v),
+                None => format!("No possible solution.\n\n"),
+            });
+        }
+
+        let peeled = match goal.goal.lower_peeled(&**ir) {
+            Ok(peeled) => peeled,
+            Err(e) => {
+                if let chalk::errors::ErrorKind::InvalidTypeName(name) = *e.kind() {
+                    if let Some(similar) = ir.similar_name(&name.str.to_string()) {
+                        bail!("{}; did you mean `{}`?", e, similar);
+                    }
+                }
+                return Err(e.into());
+            }
+        };
+        Ok(match solver_choice.decide_root_goal(env, &peeled.goal) {
+            Ok(SolveOutcome::Disproven(hint)) => match hint {
+                Some(hint) => format!(
+                    "No possible solution.\nnote: {}\n\n",
+                    hint.display_with_names(&peeled.placeholder_names)
+                ),
+                None => format!("No possible solution.\n\n"),
+            },
+            Ok(outcome) => {
+                let v = outcome.into_solution().expect("only a disproven goal has no solution");
+                let ambiguity = match v {
+                    Solution::Ambig(_) => solver_choice.ambiguity_sources(env, &peeled.goal),
+                    _ => None,
+                };
+                let (solution, legend) = v.into_named_parts(&peeled.names);
+                let mut output = format!("{}\n", solution);
+                if !legend.is_empty() {
+                    output += &format!("where {}\n", legend);
+                }
+                if let Some(sources) = ambiguity {
+                    output += &format!(
+                        "{}\n",
+                        sources.display_with_names(&peeled.placeholder_names)
+                    );
+                }
+                output + "\n"
+            }
+            Err(e) => format!("Solver failed: {}\n", e),
+        })
+    })
+}
+
+/// The environment in which the last goal was solved -- that of the
+/// `if` goals it starts with, as in `forall<T> { if (T: Clone) { ... } }`
+/// -- along with all that it implies, one domain goal per line.
+fn env(prog: &Program) -> Result<String> {
+    let text = prog.last_goal.as_ref().ok_or("no goal has been solved yet")?;
+    let goal = chalk_parse::parse_crate_goal(text)?;
+    let ir = match goal.krate {
+        None => &prog.ir,
+        Some(krate) => match prog.crates.get(&krate.str) {
+            Some((ir, _)) => ir,
+            None => bail!("no crate named `{}` is loaded", krate.str),
+        },
+    };
+    ir::tls::set_current_program(ir, || -> Result<String> {
+        let peeled = goal.goal.lower_peeled(&**ir)?;
+        let implied = peeled.environment().implied_domain_goals(ir);
+        if implied.is_empty() {
+            return Ok(format!("The environment is empty.\n\n"));
+        }
+        let mut output = String::new();
+        for goal in implied {
+            output += &format!("{:?}\n", goal);
+        }
+        Ok(output + "\n")
+    })
+}
+
+/// Opens the recording at `path` to append to it, creating it if needed.
+fn open_recorder(path: &str) -> Result<Recorder<File>> {
+    let mut text = String::new();
+    match File::open(path) {
+        Ok(mut file) => {
+            file.read_to_string(&mut text)?;
+        }
+        Err(ref e) if e.kind() == IoErrorKind::NotFound => {}
+        Err(e) => Err(e)?,
+    }
+    let previous = if text.is_empty() {
+        None
+    } else {
+        Some(Recording::parse(&text)?)
+    };
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(Recorder::new(file, previous.as_ref())?)
+}
+
+/// Solves the goals of the recording at `path` again, and reports those
+/// whose solutions differ from the recorded ones.
+fn replay(path: &str) -> Result<()> {
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+    let recording = Recording::parse(&text)?;
+
+    // A program is only loaded again when the goals move on to another
+    // program, or to other solver options.
+    let mut loaded: Option<((u64, SolverChoice), std::result::Result<Program, String>)> = None;
+    let divergences = recording.replay(|source, recorded| {
+        let key = (recorded.program, recorded.solver_choice);
+        if loaded.as_ref().map(|(loaded_key, _)| *loaded_key) != Some(key) {
+            let prog = Program::from_source(source, recorded.solver_choice);
+            loaded = Some((key, prog.map_err(|e| e.to_string())));
+        }
+        match loaded.as_mut().unwrap() {
+            (_, Ok(prog)) => {
+                prog.disabled_clauses = recorded.disabled_clauses.iter().cloned().collect();
+                match solve(recorded.solver_choice, &recorded.goal, prog) {
+                    Ok(solution) => solution,
+                    Err(e) => format!("error: {}", e),
+                }
+            }
+            (_, Err(e)) => format!("error loading program: {}", e),
+        }
+    });
+
+    for divergence in &divergences {
+        println!("{}\n", divergence);
+    }
+    println!(
+        "replayed {} goals: {} diverged",
+        recording.solves.len(),
+        divergences.len()
+    );
+    if !divergences.is_empty() {
+        exit(1);
     }
     Ok(())
 }
 
 impl Args {
     fn solver_choice(&self) -> SolverChoice {
-        SolverChoice::SLG { max_size: self.flag_overflow_depth }
+        SolverChoice::SLG {
+            max_size: self.flag_overflow_depth,
+            truncation: if self.flag_truncate_by_depth {
+                TruncationMeasure::Depth
+            } else {
+                TruncationMeasure::NodeCount
+            },
+            scheduling: Scheduling::default(),
+            ordering: if self.flag_prioritize_subgoals {
+                SubgoalOrdering::ByPriority
+            } else {
+                SubgoalOrdering::AsWritten
+            },
+            simplify_goals: self.flag_simplify_goals,
+            max_projection_depth: self.flag_projection_depth,
+            max_type_depth: self.flag_type_depth,
+            track_provenance: self.flag_provenance,
+            const_overflow: if self.flag_overflowing_consts_cannot_prove {
+                ConstOverflow::CannotProve
+            } else {
+                ConstOverflow::Error
+            },
+            ground_fast_path: !self.flag_no_ground_fast_path,
+            max_cached_tables: usize::MAX,
+        }
     }
 }
@@ -26,7 +26,7 @@ use std::marker::PhantomData;
 /// as part of this, they should always use the same set of free
 /// variables (the `Canonical` implementation, for example, relies on
 /// that).
-crate trait Cast<T>: Sized {
+pub(crate) trait Cast<T>: Sized {
     fn cast(self) -> T;
 }
 
@@ -88,6 +88,12 @@ impl Cast<DomainGoal> for Normalize {
     }
 }
 
+impl Cast<DomainGoal> for ExternalGoal {
+    fn cast(self) -> DomainGoal {
+        DomainGoal::External(self)
+    }
+}
+
 impl Cast<DomainGoal> for UnselectedNormalize {
     fn cast(self) -> DomainGoal {
         DomainGoal::UnselectedNormalize(self)
@@ -149,6 +155,12 @@ impl Cast<Parameter> for Lifetime {
     }
 }
 
+impl Cast<Parameter> for Const {
+    fn cast(self) -> Parameter {
+        ParameterKind::Const(self)
+    }
+}
+
 impl<T> Cast<ProgramClause> for T where T: Cast<DomainGoal> {
     fn cast(self) -> ProgramClause {
         ProgramClause::Implies(ProgramClauseImplication {
@@ -224,7 +236,7 @@ where
     }
 }
 
-crate struct Casted<I, U> {
+pub(crate) struct Casted<I, U> {
     iterator: I,
     _cast: PhantomData<U>,
 }
@@ -246,7 +258,7 @@ where
 
 /// An iterator adapter that casts each element we are iterating over
 /// to some other type.
-crate trait Caster<U>: Sized {
+pub(crate) trait Caster<U>: Sized {
     fn casted(self) -> Casted<Self, U>;
 }
 
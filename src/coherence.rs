@@ -1,16 +1,75 @@
 use petgraph::prelude::*;
 
-use errors::Result;
-use ir::{self, ItemId, Program};
+use errors::{ErrorKind, Result};
+use ir::{self, ItemId, Program, ProgramEnvironment};
 use solve::SolverChoice;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 mod solve;
 mod orphan;
 mod test;
 
+/// Checks impls of a program one at a time, as lowering checks every
+/// impl of the program: an impl must not overlap with another impl of
+/// its trait (unless one specializes the other), it must be
+/// well-formed, and it must respect the orphan rules. Only the goals
+/// concerning the impl being checked are solved, so that an impl can be
+/// checked again on its own after it was edited; the first error found
+/// is the one lowering the program would report for that impl.
+///
+/// The environment of the program, and the impls of each trait, are
+/// computed once, when the checker is created.
+pub struct ImplChecker {
+    program: Arc<Program>,
+    env: Arc<ProgramEnvironment>,
+    solver_choice: SolverChoice,
+
+    /// For each trait, its impls, in increasing order.
+    trait_impls: BTreeMap<ItemId, Vec<ItemId>>,
+}
+
+impl ImplChecker {
+    pub fn new(program: Arc<Program>, solver_choice: SolverChoice) -> Self {
+        let env = Arc::new(program.environment());
+        let mut trait_impls = BTreeMap::new();
+        for (&impl_id, impl_datum) in program.impl_data.iter() {
+            let trait_id = impl_datum.binders.value.trait_ref.trait_ref().trait_id;
+            trait_impls.entry(trait_id).or_insert_with(Vec::new).push(impl_id);
+        }
+        ImplChecker {
+            program,
+            env,
+            solver_choice,
+            trait_impls,
+        }
+    }
+
+    /// Checks the impl `impl_id`, which is an error if it is not an
+    /// impl of the program.
+    pub fn check_impl(&self, impl_id: ItemId) -> Result<()> {
+        let program = &self.program;
+        let impl_datum = match program.impl_data.get(&impl_id) {
+            Some(impl_datum) => impl_datum,
+            None => bail!(ErrorKind::NotAnImpl(impl_id)),
+        };
+        let trait_id = impl_datum.binders.value.trait_ref.trait_ref().trait_id;
+        let trait_impls = &self.trait_impls[&trait_id];
+        ir::tls::set_current_program(program, || {
+            program.check_overlaps_of(
+                &self.env,
+                self.solver_choice,
+                (impl_id, impl_datum),
+                trait_impls,
+            )?;
+            program.verify_impl_well_formedness(&self.env, self.solver_choice, impl_datum)?;
+            program.orphan_check_impl(&self.env, self.solver_choice, impl_datum)
+        })
+    }
+}
+
 impl Program {
-    crate fn record_specialization_priorities(&mut self, solver_choice: SolverChoice) -> Result<()> {
+    pub(crate) fn record_specialization_priorities(&mut self, solver_choice: SolverChoice) -> Result<()> {
         ir::tls::set_current_program(&Arc::new(self.clone()), || {
             let forest = self.build_specialization_forest(solver_choice)?;
 
@@ -1,8 +1,10 @@
+use std::iter;
 use std::sync::Arc;
 
 use errors::*;
 use ir::*;
 use cast::*;
+use itertools::Itertools;
 use solve::SolverChoice;
 
 struct OrphanSolver {
@@ -11,7 +13,7 @@ struct OrphanSolver {
 }
 
 impl Program {
-    crate fn perform_orphan_check(&self, solver_choice: SolverChoice) -> Result<()> {
+    pub(crate) fn perform_orphan_check(&self, solver_choice: SolverChoice) -> Result<()> {
         let solver = OrphanSolver {
             env: Arc::new(self.environment()),
             solver_choice,
@@ -23,15 +25,40 @@ impl Program {
             .filter(|impl_datum| impl_datum.binders.value.impl_type == ImplType::Local);
 
         for impl_datum in local_impls {
-            if !solver.orphan_check(impl_datum) {
-                let trait_id = impl_datum.binders.value.trait_ref.trait_ref().trait_id;
-                let trait_id = self.type_kinds.get(&trait_id).unwrap().name;
-                return Err(Error::from_kind(ErrorKind::FailedOrphanCheck(trait_id)));
-            }
+            self.orphan_check_with(&solver, impl_datum)?;
         }
 
         Ok(())
     }
+
+    /// Checks the impl `impl_datum` alone against the orphan rules, as
+    /// `perform_orphan_check` would, in the environment `env` of this
+    /// program.
+    pub(crate) fn orphan_check_impl(
+        &self,
+        env: &Arc<ProgramEnvironment>,
+        solver_choice: SolverChoice,
+        impl_datum: &ImplDatum,
+    ) -> Result<()> {
+        if impl_datum.binders.value.impl_type != ImplType::Local {
+            return Ok(());
+        }
+
+        let solver = OrphanSolver {
+            env: env.clone(),
+            solver_choice,
+        };
+        self.orphan_check_with(&solver, impl_datum)
+    }
+
+    fn orphan_check_with(&self, solver: &OrphanSolver, impl_datum: &ImplDatum) -> Result<()> {
+        let trait_id = impl_datum.binders.value.trait_ref.trait_ref().trait_id;
+        if !solver.orphan_check(&self.trait_data[&trait_id], impl_datum) {
+            let trait_id = self.type_kinds.get(&trait_id).unwrap().name;
+            return Err(Error::from_kind(ErrorKind::FailedOrphanCheck(trait_id)));
+        }
+        Ok(())
+    }
 }
 
 impl OrphanSolver {
@@ -41,21 +68,67 @@ impl OrphanSolver {
     //
     //     forall<T> { LocalImplAllowed(MyType<T>: Trait) }
     //
-    // This must be provable in order to pass the orphan check.
-    fn orphan_check(&self, impl_datum: &ImplDatum) -> bool {
+    // This must be provable in order to pass the orphan check, unless the trait
+    // has `#[fundamental]` parameters. A local type appearing directly in such a
+    // parameter also satisfies the orphan rules, provided the parameters before it
+    // are fully visible. For `impl<T> Fn<Pair<Local, T>> for MyType` with `trait
+    // Fn<#[fundamental] Args>`, we also try:
+    //
+    //     forall<T> { IsFullyVisible(MyType), IsLocal(Local) }
+    //     forall<T> { IsFullyVisible(MyType), IsFullyVisible(Local), IsLocal(T) }
+    fn orphan_check(&self, trait_datum: &TraitDatum, impl_datum: &ImplDatum) -> bool {
         debug_heading!("orphan_check(impl={:#?})", impl_datum);
 
-        let impl_allowed: Goal = impl_datum.binders.map_ref(|bound_impl| {
-            // Ignoring the polarization of the impl's polarized trait ref
-            DomainGoal::LocalImplAllowed(bound_impl.trait_ref.trait_ref().clone())
-        }).cast();
+        // Ignoring the polarization of the impl's polarized trait ref
+        let trait_ref = impl_datum.binders.value.trait_ref.trait_ref();
+        let fundamental_parameters = &trait_datum.binders.value.fundamental_parameters;
 
-        let canonical_goal = &impl_allowed.into_closed_goal();
-        let result = self.solver_choice
-            .solve_root_goal(&self.env, canonical_goal)
-            .unwrap()
-            .is_some();
+        let result = iter::once(DomainGoal::LocalImplAllowed(trait_ref.clone()).cast())
+            .chain(fundamental_locality_goals(trait_ref, fundamental_parameters))
+            .any(|goal| {
+                let impl_allowed = goal
+                    .quantify(QuantifierKind::ForAll, impl_datum.binders.binders.clone());
+                let canonical_goal = &impl_allowed.into_closed_goal();
+                self.solver_choice
+                    .solve_root_goal(&self.env, canonical_goal)
+                    .unwrap()
+                    .is_some()
+            });
         debug!("overlaps: result = {:?}", result);
         result
     }
 }
+
+/// The goals under which a local type in one of the `#[fundamental]`
+/// parameters of `trait_ref` satisfies the orphan rules: one for each type
+/// argument of each such parameter, walking the parameters in order.
+fn fundamental_locality_goals(trait_ref: &TraitRef, fundamental_parameters: &[bool]) -> Vec<Goal> {
+    let mut goals = vec![];
+    let mut visible = vec![];
+    for (parameter, &fundamental) in trait_ref.parameters.iter().zip(fundamental_parameters) {
+        let ty = match parameter.clone().ty() {
+            Some(ty) => ty,
+            None => continue,
+        };
+
+        if fundamental {
+            if let Ty::Apply(ref apply) = ty {
+                let mut visible_args = visible.clone();
+                for arg in apply.type_parameters() {
+                    let goal = visible_args
+                        .iter()
+                        .cloned()
+                        .chain(iter::once(DomainGoal::IsLocal(arg.clone()).cast()))
+                        .fold1(|goal, leaf| Goal::And(Box::new(goal), Box::new(leaf)))
+                        .unwrap();
+                    goals.push(goal);
+                    visible_args.push(DomainGoal::IsFullyVisible(arg).cast());
+                }
+            }
+        }
+
+        let is_visible: Goal = DomainGoal::IsFullyVisible(ty).cast();
+        visible.push(is_visible);
+    }
+    goals
+}
@@ -1,11 +1,22 @@
 use std::sync::Arc;
 
+use fallible::Fallible;
+use fold::{DefaultTypeFolder, Fold, IdentityExistentialFolder, Subst, UniversalFolder};
 use fold::shift::Shift;
 use itertools::Itertools;
 use errors::*;
 use ir::*;
 use cast::*;
-use solve::{SolverChoice, Solution};
+use solve::{SolveOutcome, SolverChoice};
+use solve::infer::InferenceTable;
+
+/// The number of trait references at which two overlapping impls overlap that
+/// are reported, at most.
+const MAX_OVERLAP_WITNESSES: usize = 3;
+
+/// The number of answers to the overlap query looked at to find them: some
+/// of them may only show that the impls might overlap.
+const MAX_OVERLAP_ANSWERS: usize = 4 * MAX_OVERLAP_WITNESSES;
 
 struct DisjointSolver {
     env: Arc<ProgramEnvironment>,
@@ -51,30 +62,49 @@ impl Program {
 
 
         // Iterate over every pair of impls for the same trait.
-        for (trait_id, impls) in &impl_groupings {
+        for (_, impls) in &impl_groupings {
             let impls: Vec<(&ItemId, &ImplDatum)> = impls.collect();
 
             for ((&l_id, lhs), (&r_id, rhs)) in impls.into_iter().tuple_combinations() {
-                // Two negative impls never overlap.
-                if !lhs.binders.value.trait_ref.is_positive()
-                    && !rhs.binders.value.trait_ref.is_positive()
+                if let Some((less_special, more_special)) =
+                    solver.check_pair(self, (l_id, lhs), (r_id, rhs))?
                 {
-                    continue;
+                    record_specialization(less_special, more_special);
                 }
+            }
+        }
 
-                // Check if the impls overlap, then if they do, check if one specializes
-                // the other. Note that specialization can only run one way - if both
-                // specialization checks return *either* true or false, that's an error.
-                if !solver.disjoint(lhs, rhs) {
-                    match (solver.specializes(lhs, rhs), solver.specializes(rhs, lhs)) {
-                        (true, false) => record_specialization(l_id, r_id),
-                        (false, true) => record_specialization(r_id, l_id),
-                        (_, _) => {
-                            let trait_id = self.type_kinds.get(&trait_id).unwrap().name;
-                            return Err(Error::from_kind(ErrorKind::OverlappingImpls(trait_id)));
-                        }
-                    }
-                }
+        Ok(())
+    }
+
+    /// Checks the impl `impl_id` against the other impls of its trait,
+    /// `trait_impls`, as `visit_specializations` would, without
+    /// recording anything. `env` is the environment of this program.
+    pub(super) fn check_overlaps_of(
+        &self,
+        env: &Arc<ProgramEnvironment>,
+        solver_choice: SolverChoice,
+        (impl_id, impl_datum): (ItemId, &ImplDatum),
+        trait_impls: &[ItemId],
+    ) -> Result<()> {
+        let trait_id = impl_datum.binders.value.trait_ref.trait_ref().trait_id;
+        if self.trait_data[&trait_id].binders.value.flags.marker {
+            return Ok(());
+        }
+
+        let mut solver = DisjointSolver {
+            env: env.clone(),
+            solver_choice,
+        };
+
+        for &other_id in trait_impls.iter().filter(|&&id| id != impl_id) {
+            let other = &self.impl_data[&other_id];
+            // Keep the pair in the order in which `visit_specializations`
+            // would check it, so that the same error is reported.
+            if other_id < impl_id {
+                solver.check_pair(self, (other_id, other), (impl_id, impl_datum))?;
+            } else {
+                solver.check_pair(self, (impl_id, impl_datum), (other_id, other))?;
             }
         }
 
@@ -83,6 +113,41 @@ impl Program {
 }
 
 impl DisjointSolver {
+    /// Checks whether two impls of the same trait overlap. If they do,
+    /// one of them must specialize the other: the result is then the
+    /// less special impl and the more special one, in that order.
+    fn check_pair(
+        &mut self,
+        program: &Program,
+        (l_id, lhs): (ItemId, &ImplDatum),
+        (r_id, rhs): (ItemId, &ImplDatum),
+    ) -> Result<Option<(ItemId, ItemId)>> {
+        // Two negative impls never overlap.
+        if !lhs.binders.value.trait_ref.is_positive() && !rhs.binders.value.trait_ref.is_positive() {
+            return Ok(None);
+        }
+
+        // Check if the impls overlap, then if they do, check if one specializes
+        // the other. Note that specialization can only run one way - if both
+        // specialization checks return *either* true or false, that's an error.
+        if self.disjoint(lhs, rhs)? {
+            return Ok(None);
+        }
+        match (self.specializes(lhs, rhs)?, self.specializes(rhs, lhs)?) {
+            (true, false) => Ok(Some((l_id, r_id))),
+            (false, true) => Ok(Some((r_id, l_id))),
+            (_, _) => {
+                let trait_id = lhs.binders.value.trait_ref.trait_ref().trait_id;
+                let trait_id = program.type_kinds.get(&trait_id).unwrap().name;
+                let error = ErrorKind::OverlappingImpls(trait_id);
+                match self.overlap_witnesses(lhs, rhs) {
+                    Some(cause) => bail!(Error::with_chain(Error::from_kind(cause), error)),
+                    None => bail!(error),
+                }
+            }
+        }
+    }
+
     // Test if the set of types that these two impls apply to overlap. If the test succeeds, these
     // two impls are disjoint.
     //
@@ -116,63 +181,123 @@ impl DisjointSolver {
     //  Generates:
     //      not { compatible { exists<T, U> { Vec<T> = Vec<U>, T: Bar, U: Baz } } }
     //
-    fn disjoint(&self, lhs: &ImplDatum, rhs: &ImplDatum) -> bool {
+    fn disjoint(&self, lhs: &ImplDatum, rhs: &ImplDatum) -> Result<bool> {
         debug_heading!("overlaps(lhs={:#?}, rhs={:#?})", lhs, rhs);
 
-        let lhs_len = lhs.binders.len();
-
-        // Join the two impls' binders together
-        let mut binders = lhs.binders.binders.clone();
-        binders.extend(rhs.binders.binders.clone());
-
-        // Upshift the rhs variables in params to account for the joined binders
-        let lhs_params = params(lhs).iter().cloned();
-        let rhs_params = params(rhs).iter().map(|param| param.up_shift(lhs_len));
-
-        // Create an equality goal for every input type the trait, attempting
-        // to unify the inputs to both impls with one another
-        let params_goals = lhs_params
-            .zip(rhs_params)
-            .map(|(a, b)| Goal::Leaf(LeafGoal::EqGoal(EqGoal { a, b })));
-
-        // Upshift the rhs variables in where clauses
-        let lhs_where_clauses = lhs.binders.value.where_clauses.iter().cloned();
-        let rhs_where_clauses = rhs.binders
-            .value
-            .where_clauses
-            .iter()
-            .map(|wc| wc.up_shift(lhs_len));
-
-        // Create a goal for each clause in both where clauses
-        let wc_goals = lhs_where_clauses
-            .chain(rhs_where_clauses)
-            .map(|wc| wc.cast());
-
-        // Join all the goals we've created together with And, then quantify them
-        // over the joined binders. This is our query.
-        let goal = params_goals
-            .chain(wc_goals)
-            .fold1(|goal, leaf| Goal::And(Box::new(goal), Box::new(leaf)))
-            .expect("Every trait takes at least one input type")
+        let (binders, goal) = overlap_goal(lhs, rhs);
+        let goal = goal
             .quantify(QuantifierKind::Exists, binders)
             .compatible()
             .negate();
 
         let canonical_goal = &goal.into_closed_goal();
-        let solution = self.solver_choice
-            .solve_root_goal(&self.env, canonical_goal)
-            .unwrap(); // internal errors in the solver are fatal
-        let result = match solution {
+        let outcome = self.solver_choice.decide_root_goal(&self.env, canonical_goal)?;
+        let result = match outcome {
             // Goal was proven with a unique solution, so no impl was found that causes these two
             // to overlap
-            Some(Solution::Unique(_)) => true,
-            // Goal was ambiguous, so there *may* be overlap
-            Some(Solution::Ambig(_)) |
-            // Goal cannot be proven, so there is some impl that causes overlap
-            None => false,
+            SolveOutcome::Provable(_) => true,
+            // Goal was ambiguous, or the solver gave up on it, so there *may* be overlap
+            SolveOutcome::Unknown(_) |
+            // Goal was disproven, so there is some impl that causes overlap
+            SolveOutcome::Disproven(_) => false,
         };
         debug!("overlaps: result = {:?}", result);
-        result
+        Ok(result)
+    }
+
+    // Find the trait references at which two overlapping impls overlap, to explain the
+    // error: at most `MAX_OVERLAP_WITNESSES` of them, e.g. `A: Foo` and `B: Foo` for
+    // `impl<T: Bar> Foo for T` and `impl<T: Baz> Foo for T` if both `A` and `B` implement
+    // `Bar` and `Baz`.
+    //
+    // Rather than quantifying over the joined binders, we instantiate them with inference
+    // variables and enumerate the answers to the (positive) overlap query, in the same
+    // compatible world as `disjoint`. An answer which is not ambiguous, and binds every
+    // variable of the trait reference to a type of the program, is a witness. If there are
+    // none, the first other answer explains the overlap instead: it holds for any value of its
+    // unbound variables (or the downstream type) for which the where clauses of both impls
+    // hold, and so do the lifetime constraints of the answer.
+    fn overlap_witnesses(&self, lhs: &ImplDatum, rhs: &ImplDatum) -> Option<ErrorKind> {
+        let (binders, goal) = overlap_goal(lhs, rhs);
+
+        // Enter the compatible world by hand (see `Goal::compatible`), with the variables in
+        // the universe of the downstream type, so that they can be bound to it.
+        let mut infer = InferenceTable::new();
+        let universe = infer.new_universe();
+        let downstream = PlaceholderIndex { ui: universe, idx: 0 };
+        let clauses: Vec<ProgramClause> = vec![
+            DomainGoal::Compatible(()).cast(),
+            DomainGoal::DownstreamType(downstream.to_ty()).cast(),
+        ];
+        let environment = Environment::add_clauses(&Environment::new(), clauses);
+        let universes: Vec<_> = binders
+            .into_iter()
+            .map(|kind| kind.map(|()| universe))
+            .collect();
+        let vars = infer.fresh_subst(&universes).parameters;
+        let goal = InEnvironment::new(&environment, Subst::apply(&vars, &goal));
+
+        let trait_ref: Goal = Subst::apply(&vars, lhs.binders.value.trait_ref.trait_ref()).cast();
+        let where_clauses: Vec<Goal> = overlap_where_clauses(lhs, rhs)
+            .map(|wc| Subst::apply(&vars, &wc).cast())
+            .collect();
+
+        let mut witnesses = vec![];
+        let mut open_overlap = None;
+        let answers = self.solver_choice
+            .answers_in_table(&self.env, &infer, &goal, MAX_OVERLAP_ANSWERS);
+        for mut answer in answers {
+            let witness = answer.table.canonicalize(&trait_ref).quantified;
+            if !answer.ambiguous && witness.binders.is_empty()
+                && !mentions_placeholder(&witness.value, downstream)
+            {
+                let witness = format!("`{:?}`", witness.value);
+                if !witnesses.contains(&witness) {
+                    witnesses.push(witness);
+                }
+                if witnesses.len() == MAX_OVERLAP_WITNESSES {
+                    break;
+                }
+            } else if open_overlap.is_none() {
+                let conditions = (trait_ref.clone(), (where_clauses.clone(), answer.constraints));
+                open_overlap = Some(answer.table.canonicalize(&conditions).quantified);
+            }
+        }
+
+        if !witnesses.is_empty() {
+            witnesses.sort();
+            return Some(ErrorKind::ImplsOverlapAt(witnesses.join(" and ")));
+        }
+
+        // The variables of the canonical value are printed as `?0`, `?1` and so on.
+        let Canonical { binders, value } = open_overlap?;
+        let mut unknowns: Vec<_> = binders
+            .iter()
+            .enumerate()
+            .map(|(i, kind)| match kind {
+                ParameterKind::Lifetime(_) => format!("'?{}", i),
+                ParameterKind::Ty(_) | ParameterKind::Const(_) => format!("?{}", i),
+            })
+            .collect();
+        if mentions_placeholder(&value, downstream) {
+            unknowns.push(format!("downstream type {:?}", downstream));
+        }
+
+        let (witness, (where_clauses, constraints)) = value;
+        let conditions = where_clauses
+            .iter()
+            .map(|wc| format!("`{:?}`", wc))
+            .chain(constraints.iter().map(|c| format!("`{:?}`", c)))
+            .join(", ");
+        let mut explanation = String::new();
+        if !unknowns.is_empty() {
+            explanation.push_str(&format!(" for any {}", unknowns.join(", ")));
+        }
+        if !conditions.is_empty() {
+            let conjunction = if unknowns.is_empty() { "if" } else { "such that" };
+            explanation.push_str(&format!(" {} {}", conjunction, conditions));
+        }
+        Some(ErrorKind::ImplsMayOverlap(format!("{:?}", witness), explanation))
     }
 
     // Test for specialization.
@@ -191,7 +316,11 @@ impl DisjointSolver {
     //    }
     //  }
     // }
-    fn specializes(&mut self, less_special: &ImplDatum, more_special: &ImplDatum) -> bool {
+    fn specializes(
+        &mut self,
+        less_special: &ImplDatum,
+        more_special: &ImplDatum,
+    ) -> Result<bool> {
         debug_heading!(
             "specializes(less_special={:#?}, more_special={:#?})",
             less_special,
@@ -202,14 +331,15 @@ impl DisjointSolver {
         if !less_special.binders.value.trait_ref.is_positive()
             || !more_special.binders.value.trait_ref.is_positive()
         {
-            return false;
+            return Ok(false);
         }
 
-        let more_len = more_special.binders.len();
+        let less_len = less_special.binders.len();
 
-        // Create parameter equality goals.
-        let more_special_params = params(more_special).iter().cloned();
-        let less_special_params = params(less_special).iter().map(|p| p.up_shift(more_len));
+        // Create parameter equality goals. They are under the binders of the less special impl,
+        // so the parameters of the more special one are shifted across them.
+        let more_special_params = params(more_special).iter().map(|p| p.up_shift(less_len));
+        let less_special_params = params(less_special).iter().cloned();
         let params_goals = more_special_params
             .zip(less_special_params)
             .map(|(a, b)| Goal::Leaf(LeafGoal::EqGoal(EqGoal { a, b })));
@@ -228,7 +358,8 @@ impl DisjointSolver {
             .value
             .where_clauses
             .iter()
-            .map(|wc| wc.up_shift(more_len).cast());
+            .cloned()
+            .casted();
 
         // Join all of the goals together.
         let goal = params_goals
@@ -240,17 +371,111 @@ impl DisjointSolver {
             .quantify(QuantifierKind::ForAll, more_special.binders.binders.clone());
 
         let canonical_goal = &goal.into_closed_goal();
-        let result = match self.solver_choice
-            .solve_root_goal(&self.env, canonical_goal)
-            .unwrap()
-        {
+        let result = match self.solver_choice.solve_root_goal(&self.env, canonical_goal)? {
             Some(sol) => sol.is_unique(),
             None => false,
         };
 
         debug!("specializes: result = {:?}", result);
 
-        result
+        Ok(result)
+    }
+}
+
+// The goals that must hold for both impls to apply to the same trait reference, together
+// with the binders of both impls, joined together (those of `lhs` first). The goal refers
+// to the joined binders as free variables.
+fn overlap_goal(lhs: &ImplDatum, rhs: &ImplDatum) -> (Vec<ParameterKind<()>>, Goal) {
+    let lhs_len = lhs.binders.len();
+
+    // Join the two impls' binders together
+    let mut binders = lhs.binders.binders.clone();
+    binders.extend(rhs.binders.binders.clone());
+
+    // Upshift the rhs variables in params to account for the joined binders
+    let lhs_params = params(lhs).iter().cloned();
+    let rhs_params = params(rhs).iter().map(|param| param.up_shift(lhs_len));
+
+    // Create an equality goal for every input type the trait, attempting
+    // to unify the inputs to both impls with one another
+    let params_goals = lhs_params
+        .zip(rhs_params)
+        .map(|(a, b)| Goal::Leaf(LeafGoal::EqGoal(EqGoal { a, b })));
+
+    // Create a goal for each clause in both where clauses
+    let wc_goals = overlap_where_clauses(lhs, rhs).map(|wc| wc.cast());
+
+    // Join all the goals we've created together with And. Quantified over the
+    // joined binders, this is our query.
+    let goal = params_goals
+        .chain(wc_goals)
+        .fold1(|goal, leaf| Goal::And(Box::new(goal), Box::new(leaf)))
+        .expect("Every trait takes at least one input type");
+
+    (binders, goal)
+}
+
+// The where clauses of both impls, referring to the joined binders of `overlap_goal`.
+fn overlap_where_clauses<'a>(
+    lhs: &'a ImplDatum,
+    rhs: &'a ImplDatum,
+) -> impl Iterator<Item = QuantifiedWhereClause> + 'a {
+    let lhs_len = lhs.binders.len();
+
+    // Upshift the rhs variables in where clauses
+    let lhs_where_clauses = lhs.binders.value.where_clauses.iter().cloned();
+    let rhs_where_clauses = rhs.binders
+        .value
+        .where_clauses
+        .iter()
+        .map(move |wc| wc.up_shift(lhs_len));
+
+    lhs_where_clauses.chain(rhs_where_clauses)
+}
+
+// Whether `value` mentions the type `placeholder`.
+fn mentions_placeholder<T: Fold>(value: &T, placeholder: PlaceholderIndex) -> bool {
+    let mut finder = PlaceholderFinder {
+        placeholder,
+        found: false,
+    };
+    value.fold_with(&mut finder, 0).unwrap();
+    finder.found
+}
+
+struct PlaceholderFinder {
+    placeholder: PlaceholderIndex,
+    found: bool,
+}
+
+impl DefaultTypeFolder for PlaceholderFinder {}
+
+impl IdentityExistentialFolder for PlaceholderFinder {}
+
+impl UniversalFolder for PlaceholderFinder {
+    fn fold_free_universal_ty(
+        &mut self,
+        universe: PlaceholderIndex,
+        _binders: usize,
+    ) -> Fallible<Ty> {
+        self.found |= universe == self.placeholder;
+        Ok(universe.to_ty())
+    }
+
+    fn fold_free_universal_lifetime(
+        &mut self,
+        universe: PlaceholderIndex,
+        _binders: usize,
+    ) -> Fallible<Lifetime> {
+        Ok(universe.to_lifetime())
+    }
+
+    fn fold_free_universal_const(
+        &mut self,
+        universe: PlaceholderIndex,
+        _binders: usize,
+    ) -> Fallible<Const> {
+        Ok(universe.to_const())
     }
 }
 
@@ -1,5 +1,10 @@
 #![cfg(test)]
 
+use coherence::ImplChecker;
+use errors::{Error, ErrorKind};
+use ir::*;
+use solve::SolverChoice;
+use std::sync::Arc;
 use test_util::*;
 
 #[test]
@@ -290,6 +295,158 @@ fn fundamental_traits() {
 
 }
 
+#[test]
+fn fundamental_trait_parameters() {
+    // Adapted from rustc's treatment of the argument types of the `Fn` traits: a local type
+    // appearing in a `#[fundamental]` parameter of the trait satisfies the orphan rules, even
+    // when it is wrapped in a type that is not local.
+    lowering_error! {
+        program {
+            #[upstream] trait Fn<Args> { }
+            #[upstream] struct Pair<T, U> { }
+            #[upstream] struct Foo { }
+            struct Local { }
+
+            impl Fn<Pair<Local, Foo>> for Foo { }
+        } error_msg {
+            "impl for trait \"Fn\" violates the orphan rules"
+        }
+    }
+
+    lowering_success! {
+        program {
+            #[upstream] trait Fn<#[fundamental] Args> { }
+            #[upstream] struct Pair<T, U> { }
+            #[upstream] struct Foo { }
+            struct Local { }
+
+            impl Fn<Pair<Local, Foo>> for Foo { }
+            impl<T> Fn<Pair<Local, T>> for Pair<Foo, Foo> { }
+        }
+    }
+
+    // There must still be a local type somewhere.
+    lowering_error! {
+        program {
+            #[upstream] trait Fn<#[fundamental] Args> { }
+            #[upstream] struct Pair<T, U> { }
+            #[upstream] struct Foo { }
+
+            impl Fn<Pair<Foo, Foo>> for Foo { }
+        } error_msg {
+            "impl for trait \"Fn\" violates the orphan rules"
+        }
+    }
+
+    // The types before the local one, including those of the parameter itself, cannot be
+    // uncovered type parameters.
+    lowering_error! {
+        program {
+            #[upstream] trait Fn<#[fundamental] Args> { }
+            #[upstream] struct Pair<T, U> { }
+            #[upstream] struct Foo { }
+            struct Local { }
+
+            impl<T> Fn<Pair<T, Local>> for Foo { }
+        } error_msg {
+            "impl for trait \"Fn\" violates the orphan rules"
+        }
+    }
+
+    lowering_error! {
+        program {
+            #[upstream] trait Fn<#[fundamental] Args> { }
+            #[upstream] struct Pair<T, U> { }
+            struct Local { }
+
+            impl<T> Fn<Pair<Local, Local>> for T { }
+        } error_msg {
+            "impl for trait \"Fn\" violates the orphan rules"
+        }
+    }
+}
+
+#[test]
+fn fundamental_trait_parameters_downstream_impls() {
+    // The overlap check must account for the impls the orphan rules allow downstream through a
+    // `#[fundamental]` parameter. A crate depending on `app` may add:
+    //
+    //     struct Local { }
+    //     impl Fn<Pair<Box<Local>, Foo>> for Foo { }
+    //
+    // after which both impls of `Bar` apply to `Box<Local>`.
+    lowering_error! {
+        crates {
+            std {
+                trait Fn<#[fundamental] Args> { }
+                #[fundamental] struct Box<T> { }
+                struct Pair<T, U> { }
+                struct Foo { }
+            }
+            app {
+                trait Bar { }
+                impl<T> Bar for T where Foo: Fn<Pair<T, Foo>> { }
+                impl<T> Bar for Box<T> { }
+            }
+        } error_msg {
+            "overlapping impls of trait \"Bar\""
+        }
+    }
+
+    // Upstream crates cannot add impls of a `#[fundamental]` trait, but downstream crates still
+    // can, through its `#[fundamental]` parameter.
+    lowering_error! {
+        crates {
+            std {
+                #[fundamental] trait Fn<#[fundamental] Args> { }
+                #[fundamental] struct Box<T> { }
+                struct Pair<T, U> { }
+                struct Foo { }
+            }
+            app {
+                trait Bar { }
+                impl<T> Bar for T where Foo: Fn<Pair<T, Foo>> { }
+                impl<T> Bar for Box<T> { }
+            }
+        } error_msg {
+            "overlapping impls of trait \"Bar\""
+        }
+    }
+
+    // Neither can add one for `Pair<Foo, Foo>`.
+    lowering_success! {
+        crates {
+            std {
+                #[fundamental] trait Fn<#[fundamental] Args> { }
+                #[fundamental] struct Box<T> { }
+                struct Pair<T, U> { }
+                struct Foo { }
+            }
+            app {
+                trait Bar { }
+                impl<T> Bar for T where Foo: Fn<Pair<Foo, Foo>> { }
+                impl<T> Bar for Box<T> { }
+            }
+        }
+    }
+
+    // The impl the downstream crate adds is accepted by the orphan check.
+    lowering_success! {
+        crates {
+            std {
+                trait Fn<#[fundamental] Args> { }
+                #[fundamental] struct Box<T> { }
+                struct Pair<T, U> { }
+                struct Foo { }
+            }
+            app {
+                struct Local { }
+                impl Fn<Pair<Box<Local>, Foo>> for Foo { }
+            }
+        }
+    }
+}
+
 #[test]
 fn orphan_check() {
     // These tests are largely adapted from the compile-fail coherence-*.rs tests from rustc
@@ -462,3 +619,256 @@ fn orphan_check() {
         }
     }
 }
+
+#[test]
+fn orphan_check_across_crates() {
+    // `app` cannot implement a trait of `std` for a type of `std`...
+    lowering_error! {
+        crates {
+            std {
+                trait Clone { }
+                struct Vec<T> { }
+                struct u32 { }
+            }
+            app {
+                impl Clone for Vec<u32> { }
+            }
+        } error_msg {
+            "impl for trait \"Clone\" violates the orphan rules"
+        }
+    }
+
+    // ...but `std` can, since both are local to it.
+    lowering_success! {
+        crates {
+            std {
+                trait Clone { }
+                struct Vec<T> { }
+                struct u32 { }
+                impl Clone for Vec<u32> { }
+            }
+            app {
+                struct Foo { }
+                impl Clone for Foo { }
+            }
+        }
+    }
+}
+
+#[test]
+fn lang_box_is_fundamental() {
+    lowering_success! {
+        program {
+            #[upstream] trait Foo { }
+            #[upstream] #[lang_box] struct Box<T> { }
+            struct Local { }
+
+            impl Foo for Box<Local> { }
+        }
+    }
+
+    lowering_error! {
+        program {
+            #[upstream] trait Foo { }
+            #[upstream] struct Box<T> { }
+            struct Local { }
+
+            impl Foo for Box<Local> { }
+        } error_msg {
+            "impl for trait \"Foo\" violates the orphan rules"
+        }
+    }
+}
+
+#[test]
+fn disjoint_concrete_const_impls() {
+    lowering_success! {
+        program {
+            trait Foo { }
+            struct Array<const N> { }
+            impl Foo for Array<3> { }
+            impl Foo for Array<4> { }
+        }
+    }
+}
+
+#[test]
+fn concrete_and_generic_const_impls() {
+    // These overlap at `Array<3>`, but the first impl specializes the second.
+    lowering_success! {
+        program {
+            trait Foo { }
+            struct Array<const N> { }
+            impl Foo for Array<3> { }
+            impl<const N> Foo for Array<N> { }
+        }
+    }
+}
+
+#[test]
+fn overlap_at_specific_const_values() {
+    // The impls only overlap when both consts are `3`, and neither specializes the other.
+    lowering_error! {
+        program {
+            trait Foo<const M> { }
+            struct Array<const N> { }
+            impl<const M> Foo<M> for Array<3> { }
+            impl<const N> Foo<3> for Array<N> { }
+        } error_msg {
+            "overlapping impls of trait \"Foo\""
+        } caused_by {
+            "both impls apply to `Implemented(Array<3>: Foo<3>)`"
+        }
+    }
+}
+
+#[test]
+fn overlap_at_several_types() {
+    // Both `A` and `B` satisfy the where clauses of both impls.
+    lowering_error! {
+        program {
+            trait Foo { }
+            trait Bar { }
+            trait Baz { }
+            struct A { }
+            struct B { }
+            impl Bar for A { }
+            impl Bar for B { }
+            impl Baz for A { }
+            impl Baz for B { }
+            impl<T> Foo for T where T: Bar { }
+            impl<T> Foo for T where T: Baz { }
+        } error_msg {
+            "overlapping impls of trait \"Foo\""
+        } caused_by {
+            "both impls apply to `Implemented(A: Foo)` and `Implemented(B: Foo)`"
+        }
+    }
+}
+
+#[test]
+fn overlap_for_any_type() {
+    // The impls overlap whenever all three parameters are the same
+    // type, which no single type stands for.
+    lowering_error! {
+        program {
+            trait Choose<A, B, C> { }
+            struct Unit { }
+            impl<A, B> Choose<A, B, A> for Unit { }
+            impl<A, B> Choose<A, B, B> for Unit { }
+        } error_msg {
+            "overlapping impls of trait \"Choose\""
+        } caused_by {
+            "both impls may apply to `Implemented(Unit: Choose<?0, ?0, ?0>)` for any ?0"
+        }
+    }
+}
+
+/// The impl of the trait `trait_name` for the struct `self_name`.
+fn impl_of(program: &Program, trait_name: &str, self_name: &str) -> ItemId {
+    let trait_id = program.lookup(trait_name).unwrap();
+    let self_ty = TypeName::ItemId(program.lookup(self_name).unwrap()).to_ty();
+    let (&impl_id, _) = program
+        .impl_data
+        .iter()
+        .find(|(_, impl_datum)| {
+            let trait_ref = impl_datum.binders.value.trait_ref.trait_ref();
+            trait_ref.trait_id == trait_id && trait_ref.parameters[0] == ParameterKind::Ty(self_ty.clone())
+        })
+        .unwrap();
+    impl_id
+}
+
+fn messages(error: &Error) -> Vec<String> {
+    error.iter().map(|error| error.to_string()).collect()
+}
+
+/// Checks the impl `impl_id` of `program` on its own.
+fn check_impl(program: Program, impl_id: ItemId, solver_choice: SolverChoice) -> Result<(), Error> {
+    ImplChecker::new(Arc::new(program), solver_choice).check_impl(impl_id)
+}
+
+#[test]
+fn check_single_impl() {
+    let solver_choice = SolverChoice::default();
+    let fixture = "
+        trait Bar { }
+        trait Foo where Self: Bar { }
+        struct A { }
+        struct B { }
+        impl Bar for A { }
+        impl Bar for B { }
+        impl Foo for A { }
+        impl Foo for B { }
+    ";
+    let program = parse_and_lower_program(fixture, solver_choice).unwrap();
+    let foo_for_a = impl_of(&program, "Foo", "A");
+    let foo_for_b = impl_of(&program, "Foo", "B");
+    check_impl(program.clone(), foo_for_b, solver_choice).unwrap();
+
+    // Without `impl Bar for B`, `impl Foo for B` is no longer well-formed.
+    let mut edited = program.clone();
+    edited.impl_data.remove(&impl_of(&program, "Bar", "B"));
+    let single = check_impl(edited, foo_for_b, solver_choice).unwrap_err();
+    let full = parse_and_lower_program(&fixture.replace("impl Bar for B { }", ""), solver_choice)
+        .unwrap_err();
+    assert_eq!(messages(&single), messages(&full));
+
+    // Turning `impl Foo for B` into another `impl Foo for A` makes it
+    // overlap with the first one.
+    let mut edited = program.clone();
+    let duplicate = edited.impl_data[&foo_for_a].clone();
+    edited.impl_data.insert(foo_for_b, duplicate);
+    let single = check_impl(edited, foo_for_b, solver_choice).unwrap_err();
+    let full = parse_and_lower_program(
+        &fixture.replace("impl Foo for B { }", "impl Foo for A { }"),
+        solver_choice,
+    ).unwrap_err();
+    assert_eq!(messages(&single), messages(&full));
+
+    // Only the impl being checked matters: without `impl Bar for A`,
+    // `impl Foo for A` is ill-formed but `impl Foo for B` is fine.
+    let mut edited = program.clone();
+    edited.impl_data.remove(&impl_of(&program, "Bar", "A"));
+    let checker = ImplChecker::new(Arc::new(edited), solver_choice);
+    checker.check_impl(foo_for_b).unwrap();
+    assert!(checker.check_impl(foo_for_a).is_err());
+
+    // Items which are not impls are reported, rather than looked up.
+    let struct_a = program.lookup("A").unwrap();
+    match *check_impl(program, struct_a, solver_choice).unwrap_err().kind() {
+        ErrorKind::NotAnImpl(item_id) => assert_eq!(item_id, struct_a),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+#[test]
+fn check_single_impl_tables() {
+    // Twenty traits with three impls each: checking one impl only
+    // involves the impls of its own trait.
+    let solver_choice = SolverChoice::default();
+    let mut program_text = "struct A { } struct B { } struct C { }".to_string();
+    for i in 0..20 {
+        program_text.push_str(&format!(
+            " trait Foo{i} {{ }} impl Foo{i} for A {{ }} impl Foo{i} for B {{ }} \
+             impl Foo{i} for C {{ }}",
+            i = i,
+        ));
+    }
+
+    let tables_created = |check: &dyn Fn()| {
+        let before = ::solve::slg::implementation::tables_created();
+        check();
+        ::solve::slg::implementation::tables_created() - before
+    };
+    let all_impls = tables_created(&|| {
+        parse_and_lower_program(&program_text, solver_choice).unwrap();
+    });
+    let program = parse_and_lower_program(&program_text, solver_choice).unwrap();
+    let checker = ImplChecker::new(Arc::new(program.clone()), solver_choice);
+    let one_impl = tables_created(&|| {
+        checker.check_impl(impl_of(&program, "Foo7", "B")).unwrap();
+    });
+    assert_eq!(all_impls, 120);
+    assert_eq!(one_impl, 4);
+}
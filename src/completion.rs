@@ -0,0 +1,72 @@
+//! Completion of the words of the goals and commands typed into
+//! `chalki`: the names of the items of the program, the keywords that
+//! start a goal, and the commands themselves.
+
+use ir::Program;
+
+mod test;
+
+/// What the keywords that start a goal complete to: they are followed
+/// by their binders, conditions or goal.
+const KEYWORDS: &[&str] = &["exists<", "forall<", "if (", "in crate ", "not {"];
+
+#[derive(Clone, Debug, Default)]
+pub struct Completions {
+    /// Only completed at the start of a line.
+    commands: Vec<String>,
+
+    /// The keywords, and the names of the traits, structs and
+    /// associated types of the program.
+    words: Vec<String>,
+}
+
+impl Completions {
+    /// The completions for the items of `program`, if one is loaded,
+    /// and for `commands`.
+    pub fn new(commands: &[&str], program: Option<&Program>) -> Self {
+        let mut words: Vec<String> = KEYWORDS.iter().map(|keyword| keyword.to_string()).collect();
+        if let Some(program) = program {
+            words.extend(program.type_kinds.values().map(|kind| kind.name.to_string()));
+            words.extend(
+                program
+                    .associated_ty_data
+                    .values()
+                    .map(|datum| datum.name.to_string()),
+            );
+        }
+        Completions {
+            commands: commands.iter().map(|command| command.to_string()).collect(),
+            words,
+        }
+    }
+
+    /// Completes the word of `line` that ends at the byte offset `pos`.
+    /// Returns the offset at which the word starts, along with the
+    /// candidates it is a prefix of, ignoring case, in alphabetical
+    /// order (again ignoring case). An empty word is a prefix of every
+    /// candidate.
+    pub fn complete(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let before = &line[..pos];
+        let start = before
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| !(c.is_alphanumeric() || c == '_'))
+            .map_or(0, |(index, c)| index + c.len_utf8());
+        let prefix = before[start..].to_lowercase();
+
+        let commands = if before[..start].trim().is_empty() {
+            &self.commands[..]
+        } else {
+            &[]
+        };
+        let mut candidates: Vec<String> = commands
+            .iter()
+            .chain(&self.words)
+            .filter(|candidate| candidate.to_lowercase().starts_with(&prefix))
+            .cloned()
+            .collect();
+        candidates.sort_by(|a, b| a.to_lowercase().cmp(&b.to_lowercase()).then(a.cmp(b)));
+        candidates.dedup();
+        (start, candidates)
+    }
+}
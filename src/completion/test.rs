@@ -0,0 +1,76 @@
+#![cfg(test)]
+
+use solve::SolverChoice;
+use test_util::*;
+
+use super::*;
+
+const COMMANDS: &[&str] = &["help", "load", "lowered", "print"];
+
+fn completions() -> Completions {
+    let program = parse_and_lower_program(
+        "
+        trait Iterator { type Item; }
+        trait IntoIterator { type IntoIter; }
+        struct Vec<T> { }
+        struct VecDeque<T> { }
+        struct Item { }
+        ",
+        SolverChoice::default(),
+    ).unwrap();
+    Completions::new(COMMANDS, Some(&program))
+}
+
+/// Checks the completions of the word at the end of `line`.
+fn assert_candidates(completions: &Completions, line: &str, start: usize, expected: &[&str]) {
+    let (actual_start, actual) = completions.complete(line, line.len());
+    let actual: Vec<&str> = actual.iter().map(|candidate| &candidate[..]).collect();
+    assert_eq!((actual_start, actual), (start, expected.to_vec()), "completing {:?}", line);
+}
+
+#[test]
+fn item_names() {
+    let completions = completions();
+    assert_candidates(&completions, "Vec", 0, &["Vec", "VecDeque"]);
+    assert_candidates(
+        &completions,
+        "exists<T> { T: I",
+        15,
+        &["if (", "in crate ", "IntoIter", "IntoIterator", "Item", "Iterator"],
+    );
+
+    // Prefixes are matched ignoring case.
+    assert_candidates(&completions, "Vec<T>: iter", 8, &["Iterator"]);
+    assert_candidates(&completions, "Vec<T>: Zip", 8, &[]);
+
+    // The word need not be at the end of the line.
+    assert_eq!(
+        completions.complete("Vec<It>: Foo", 6),
+        (4, vec!["Item".to_string(), "Iterator".to_string()])
+    );
+}
+
+#[test]
+fn commands_and_keywords() {
+    let completions = completions();
+    assert_candidates(&completions, "lo", 0, &["load", "lowered"]);
+    assert_candidates(&completions, "  pr", 2, &["print"]);
+
+    // Commands only start lines.
+    assert_candidates(&completions, "Vec<T>: lo", 8, &[]);
+    assert_candidates(&completions, "for", 0, &["forall<"]);
+    assert_candidates(&completions, "not { ex", 6, &["exists<"]);
+}
+
+#[test]
+fn without_a_program() {
+    let completions = Completions::new(COMMANDS, None);
+    assert_candidates(&completions, "Vec", 0, &[]);
+    assert_candidates(&completions, "h", 0, &["help"]);
+    assert_candidates(
+        &completions,
+        "",
+        0,
+        &["exists<", "forall<", "help", "if (", "in crate ", "load", "lowered", "not {", "print"],
+    );
+}
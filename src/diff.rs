@@ -0,0 +1,174 @@
+//! Finds where two values diverge, for debugging. This walks the
+//! values like any other `Zipper`, but never gives up: every spot
+//! where the values fail to match is recorded, together with the path
+//! leading to it, and the walk carries on with the next sibling.
+
+use fallible::*;
+use fold::Fold;
+use ir::*;
+use std::fmt;
+use zip::{PathStep, Zip, Zipper};
+
+/// One spot where two values diverge.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiffEntry {
+    /// The steps leading from the root of the values to the spot.
+    pub path: Vec<PathStep>,
+
+    /// The subterms of each value at the spot.
+    pub a: String,
+    pub b: String,
+
+    /// True if the spot is beneath binders, in which case the bound
+    /// variables of `a` and `b` are numbered relative to them.
+    pub under_binder: bool,
+}
+
+impl fmt::Display for DiffEntry {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(fmt, "(root)")?;
+        }
+        for step in &self.path {
+            write!(fmt, "{}", step)?;
+        }
+        write!(fmt, ": `{}` vs `{}`", self.a, self.b)?;
+        if self.under_binder {
+            write!(fmt, " (under binder)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns every spot where `a` and `b` diverge; the result is empty
+/// if they are structurally identical.
+pub fn diff<T: Zip + ?Sized>(a: &T, b: &T) -> Vec<DiffEntry> {
+    diff_within(vec![], 0, a, b)
+}
+
+/// Like `diff`, for values found at `path` within some larger values,
+/// beneath `binders` binders.
+pub(crate) fn diff_within<T: Zip + ?Sized>(
+    path: Vec<PathStep>,
+    binders: usize,
+    a: &T,
+    b: &T,
+) -> Vec<DiffEntry> {
+    let mut zipper = DiffZipper {
+        path,
+        binders,
+        entries: vec![],
+    };
+    if Zip::zip_with(&mut zipper, a, b).is_err() {
+        zipper.record(a, b);
+    }
+    zipper.entries
+}
+
+/// Renders the entries of a diff, one per line.
+pub(crate) fn display_diff(entries: &[DiffEntry]) -> String {
+    entries
+        .iter()
+        .map(|entry| format!("{}\n", entry))
+        .collect()
+}
+
+struct DiffZipper {
+    path: Vec<PathStep>,
+    binders: usize,
+    entries: Vec<DiffEntry>,
+}
+
+impl DiffZipper {
+    fn record<T: Zip + ?Sized>(&mut self, a: &T, b: &T) {
+        self.entries.push(DiffEntry {
+            path: self.path.clone(),
+            a: format!("{:?}", a),
+            b: format!("{:?}", b),
+            under_binder: self.binders > 0,
+        });
+    }
+
+    fn under_binders<OP>(&mut self, binders: usize, op: OP) -> Fallible<()>
+    where
+        OP: FnOnce(&mut Self) -> Fallible<()>,
+    {
+        self.binders += binders;
+        let result = op(self);
+        self.binders -= binders;
+        result
+    }
+}
+
+impl Zipper for DiffZipper {
+    fn zip_tys(&mut self, a: &Ty, b: &Ty) -> Fallible<()> {
+        match (a, b) {
+            (Ty::Var(depth_a), Ty::Var(depth_b)) if depth_a == depth_b => Ok(()),
+            (Ty::Apply(a), Ty::Apply(b)) => Zip::zip_with(self, a, b),
+            (Ty::Projection(a), Ty::Projection(b)) => Zip::zip_with(self, a, b),
+            (Ty::UnselectedProjection(a), Ty::UnselectedProjection(b)) => {
+                Zip::zip_with(self, a, b)
+            }
+            (Ty::ForAll(a), Ty::ForAll(b)) if a.num_binders == b.num_binders => {
+                self.under_binders(a.num_binders, |this| {
+                    this.zip_child(PathStep::Field("ty"), &a.ty, &b.ty)
+                })
+            }
+            (Ty::Var(_), _)
+            | (Ty::Apply(_), _)
+            | (Ty::Projection(_), _)
+            | (Ty::UnselectedProjection(_), _)
+            | (Ty::ForAll(_), _) => Err(NoSolution),
+        }
+    }
+
+    fn zip_lifetimes(&mut self, a: &Lifetime, b: &Lifetime) -> Fallible<()> {
+        if a == b {
+            Ok(())
+        } else {
+            Err(NoSolution)
+        }
+    }
+
+    fn zip_consts(&mut self, a: &Const, b: &Const) -> Fallible<()> {
+        match (a, b) {
+            (Const::Unevaluated(a), Const::Unevaluated(b)) => Zip::zip_with(self, a, b),
+            (Const::Var(_), _)
+            | (Const::ForAll(_), _)
+            | (Const::Value(_), _)
+            | (Const::Unevaluated(_), _) => {
+                if a == b {
+                    Ok(())
+                } else {
+                    Err(NoSolution)
+                }
+            }
+        }
+    }
+
+    fn zip_binders<T>(&mut self, a: &Binders<T>, b: &Binders<T>) -> Fallible<()>
+    where
+        T: Zip + Fold<Result = T>,
+    {
+        if a.binders != b.binders {
+            return Err(NoSolution);
+        }
+        self.under_binders(a.binders.len(), |this| {
+            this.zip_child(PathStep::Field("value"), &a.value, &b.value)
+        })
+    }
+
+    /// Records the children that diverge instead of failing, so that
+    /// their siblings get compared too.
+    fn zip_child<T>(&mut self, step: PathStep, a: &T, b: &T) -> Fallible<()>
+    where
+        T: Zip + ?Sized,
+    {
+        self.path.push(step);
+        if Zip::zip_with(self, a, b).is_err() {
+            self.record(a, b);
+        }
+        self.path.pop();
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use chalk_parse::{self, ast};
 use ir;
+use std::fmt;
 
 error_chain! {
     links {
@@ -29,11 +30,36 @@ error_chain! {
             display("expected a trait, found `{}`, which is not a trait", identifier.str)
         }
 
+        NotExternalPredicate(identifier: ast::Identifier) {
+            description("not an external predicate")
+            display("expected an external predicate, found `{}`", identifier.str)
+        }
+
+        NotConst(identifier: ast::Identifier) {
+            description("not a named constant")
+            display("expected a named constant, found `{}`", identifier.str)
+        }
+
+        InvalidCrateName(name: ir::Identifier) {
+            description("invalid crate name")
+            display("invalid crate name `{}`", name)
+        }
+
         OverlappingImpls(trait_id: ir::Identifier) {
             description("overlapping impls")
             display("overlapping impls of trait {:?}", trait_id)
         }
 
+        ImplsOverlapAt(trait_refs: String) {
+            description("impls overlap")
+            display("both impls apply to {}", trait_refs)
+        }
+
+        ImplsMayOverlap(trait_ref: String, explanation: String) {
+            description("impls may overlap")
+            display("both impls may apply to `{}`{}", trait_ref, explanation)
+        }
+
         IllFormedTypeDecl(ty_id: ir::Identifier) {
             description("ill-formed type declaration")
             display("type declaration {:?} does not meet well-formedness requirements", ty_id)
@@ -44,6 +70,22 @@ error_chain! {
             display("trait impl for {:?} does not meet well-formedness requirements", trait_id)
         }
 
+        UnprovableWfGoal(goal: String, cause: String, assumptions: String, implied: String) {
+            description("well-formedness requirement does not hold")
+            display(
+                "`{}` does not hold (required by {}), assuming {}; environment contained: {}",
+                goal,
+                cause,
+                assumptions,
+                implied
+            )
+        }
+
+        JointlyUnprovableWfGoals {
+            description("well-formedness requirements do not hold together")
+            display("well-formedness requirements hold individually but not together")
+        }
+
         CouldNotMatch {
             description("could not match")
                 display("could not match")
@@ -54,9 +96,103 @@ error_chain! {
                 display("Duplicate lang item `{:?}`", item)
         }
 
+        DuplicateItemName(name: String) {
+            description("duplicate item name")
+                display("more than one item is named `{}`", name)
+        }
+
+        OverflowingConst(expr: String, ty: ir::ConstTy) {
+            description("overflow evaluating a const")
+                display("evaluating `{}` overflows `{}`", expr, ty)
+        }
+
+        ConstTypeMismatch(expected: ir::ConstTy, found: ir::ConstTy) {
+            description("mismatched const types")
+                display("mismatched const types: expected `{}`, found `{}`", expected, found)
+        }
+
+        KindError(error: KindError) {
+            description("parameters of the wrong kind")
+                display("{}", error)
+        }
+
+        IllFormedLangItem(item: ir::LangItem) {
+            description("ill-formed lang item")
+                display("lang item `{:?}` does not have the expected shape", item)
+        }
+
+        ParameterShadowsItem(parameter: ast::Identifier) {
+            description("parameter shadows an item")
+                display("parameter `{}` has the same name as an item", parameter.str)
+        }
+
+        InvalidUniverseAscription(universe: ast::Identifier, reason: &'static str) {
+            description("invalid universe ascription")
+                display("cannot ascribe universe `{}`: {}", universe.str, reason)
+        }
+
+        InvalidReflectionGoal(reason: &'static str) {
+            description("invalid reflection goal")
+                display("invalid reflection goal: {}", reason)
+        }
+
+        NotAnImpl(item_id: ir::ItemId) {
+            description("not an impl")
+                display("expected an impl, found {:?}, which is not an impl", item_id)
+        }
+
         FailedOrphanCheck(trait_id: ir::Identifier) {
             description("impl violates the orphan rules")
                 display("impl for trait {:?} violates the orphan rules", trait_id)
         }
     }
 }
+
+/// A parameter of the wrong kind given to an item -- or a parameter
+/// missing or given in excess, which counts as a parameter of no kind.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KindError {
+    /// The struct, trait, associated type, named constant or external
+    /// predicate to which the parameters were given.
+    pub item: ir::Identifier,
+
+    /// The kind of the parameter declared by the item, or `None` if
+    /// it declares fewer parameters.
+    pub expected: Option<ast::Kind>,
+
+    /// The kind of the parameter that was given, or `None` if it is
+    /// missing.
+    pub found: Option<ast::Kind>,
+
+    /// The index of the faulty parameter. The self type of a trait
+    /// (or of the trait of an associated type) counts as a parameter.
+    pub position: usize,
+
+    /// Where the item was named, when the error is found during
+    /// lowering (the IR does not keep track of spans).
+    pub span: Option<ast::Span>,
+}
+
+impl fmt::Display for KindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        struct KindName(Option<ast::Kind>);
+
+        impl fmt::Display for KindName {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self.0 {
+                    Some(kind) => write!(f, "{}", kind),
+                    None => write!(f, "nothing"),
+                }
+            }
+        }
+
+        write!(
+            f,
+            "incorrect parameter {} of `{}`: expected {}, found {}",
+            self.position,
+            self.item,
+            KindName(self.expected),
+            KindName(self.found)
+        )
+    }
+}
@@ -6,10 +6,12 @@ use ir::*;
 use std::fmt::Debug;
 use std::sync::Arc;
 
-crate mod shift;
+mod occurrences;
+pub(crate) mod shift;
 mod subst;
 
-crate use self::subst::Subst;
+pub use self::occurrences::VariableInfo;
+pub(crate) use self::subst::Subst;
 
 /// A "folder" is a transformer that can be used to make a copy of
 /// some term -- that is, some bit of IR, such as a `Goal` -- with
@@ -61,6 +63,7 @@ pub trait Folder: ExistentialFolder + UniversalFolder + TypeFolder {
 pub trait TypeFolder {
     fn fold_ty(&mut self, ty: &Ty, binders: usize) -> Fallible<Ty>;
     fn fold_lifetime(&mut self, lifetime: &Lifetime, binders: usize) -> Fallible<Lifetime>;
+    fn fold_const(&mut self, constant: &Const, binders: usize) -> Fallible<Const>;
 }
 
 impl<T: ExistentialFolder + UniversalFolder + TypeFolder> Folder for T {
@@ -84,6 +87,10 @@ impl<T: ExistentialFolder + UniversalFolder + DefaultTypeFolder> TypeFolder for
     fn fold_lifetime(&mut self, lifetime: &Lifetime, binders: usize) -> Fallible<Lifetime> {
         super_fold_lifetime(self.to_dyn(), lifetime, binders)
     }
+
+    fn fold_const(&mut self, constant: &Const, binders: usize) -> Fallible<Const> {
+        super_fold_const(self.to_dyn(), constant, binders)
+    }
 }
 
 /// The methods for folding free **existentially quantified
@@ -99,14 +106,21 @@ pub trait ExistentialFolder {
     /// - `binders` is the number of binders in scope.
     ///
     /// This should return a type suitable for a context with `binders` in scope.
-    fn fold_free_existential_ty(&mut self, depth: usize, binders: usize) -> Fallible<Ty>;
+    fn fold_free_existential_ty(&mut self, depth: DebruijnIndex, binders: usize) -> Fallible<Ty>;
 
     /// As `fold_free_existential_ty`, but for lifetimes.
     fn fold_free_existential_lifetime(
         &mut self,
-        depth: usize,
+        depth: DebruijnIndex,
         binders: usize,
     ) -> Fallible<Lifetime>;
+
+    /// As `fold_free_existential_ty`, but for consts.
+    fn fold_free_existential_const(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Const>;
 }
 
 /// A convenience trait. If you implement this, you get an
@@ -115,16 +129,24 @@ pub trait ExistentialFolder {
 pub trait IdentityExistentialFolder {}
 
 impl<T: IdentityExistentialFolder> ExistentialFolder for T {
-    fn fold_free_existential_ty(&mut self, depth: usize, binders: usize) -> Fallible<Ty> {
-        Ok(Ty::Var(depth + binders))
+    fn fold_free_existential_ty(&mut self, depth: DebruijnIndex, binders: usize) -> Fallible<Ty> {
+        Ok(Ty::Var(depth.shifted_in(binders).depth()))
     }
 
     fn fold_free_existential_lifetime(
         &mut self,
-        depth: usize,
+        depth: DebruijnIndex,
         binders: usize,
     ) -> Fallible<Lifetime> {
-        Ok(Lifetime::Var(depth + binders))
+        Ok(Lifetime::Var(depth.shifted_in(binders).depth()))
+    }
+
+    fn fold_free_existential_const(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Const> {
+        Ok(Const::Var(depth.shifted_in(binders).depth()))
     }
 }
 
@@ -132,16 +154,23 @@ pub trait UniversalFolder {
     /// Invoked for `Ty::Apply` instances where the type name is a `TypeName::ForAll`.
     /// Returns a type to use instead, which should be suitably shifted to account for `binders`.
     ///
-    /// - `universe` is the universe of the `TypeName::ForAll` that was found
+    /// - `universe` is the placeholder of the `TypeName::ForAll` that was found
     /// - `binders` is the number of binders in scope
-    fn fold_free_universal_ty(&mut self, universe: UniverseIndex, binders: usize) -> Fallible<Ty>;
+    fn fold_free_universal_ty(&mut self, universe: PlaceholderIndex, binders: usize) -> Fallible<Ty>;
 
     /// As with `fold_free_universal_ty`, but for lifetimes.
     fn fold_free_universal_lifetime(
         &mut self,
-        universe: UniverseIndex,
+        universe: PlaceholderIndex,
         binders: usize,
     ) -> Fallible<Lifetime>;
+
+    /// As with `fold_free_universal_ty`, but for consts.
+    fn fold_free_universal_const(
+        &mut self,
+        universe: PlaceholderIndex,
+        binders: usize,
+    ) -> Fallible<Const>;
 }
 
 /// A convenience trait. If you implement this, you get an
@@ -150,17 +179,25 @@ pub trait UniversalFolder {
 pub trait IdentityUniversalFolder {}
 
 impl<T: IdentityUniversalFolder> UniversalFolder for T {
-    fn fold_free_universal_ty(&mut self, universe: UniverseIndex, _binders: usize) -> Fallible<Ty> {
-        Ok(TypeName::ForAll(universe).to_ty())
+    fn fold_free_universal_ty(&mut self, universe: PlaceholderIndex, _binders: usize) -> Fallible<Ty> {
+        Ok(universe.to_ty())
     }
 
     fn fold_free_universal_lifetime(
         &mut self,
-        universe: UniverseIndex,
+        universe: PlaceholderIndex,
         _binders: usize,
     ) -> Fallible<Lifetime> {
         Ok(universe.to_lifetime())
     }
+
+    fn fold_free_universal_const(
+        &mut self,
+        universe: PlaceholderIndex,
+        _binders: usize,
+    ) -> Fallible<Const> {
+        Ok(universe.to_const())
+    }
 }
 
 /// Applies the given folder to a value.
@@ -178,13 +215,36 @@ pub trait Fold: Debug {
     /// we encounter `Binders<T>` in the IR or other similar
     /// constructs.
     fn fold_with(&self, folder: &mut dyn Folder, binders: usize) -> Fallible<Self::Result>;
+
+    /// Returns what folding `self` with a folder that changes nothing
+    /// would -- for most types, a clone. Folds which are known to
+    /// change nothing, like shifting by zero or substituting nothing,
+    /// use this instead. By default, this does go through such a
+    /// folder; the types of the IR override it with a plain clone.
+    fn clone_result(&self) -> Self::Result {
+        self.fold_with(&mut IdentityFolder, 0)
+            .expect("the identity folder is infallible")
+    }
 }
 
+/// A folder that changes nothing (see `Fold::clone_result`).
+struct IdentityFolder;
+
+impl DefaultTypeFolder for IdentityFolder {}
+
+impl IdentityExistentialFolder for IdentityFolder {}
+
+impl IdentityUniversalFolder for IdentityFolder {}
+
 impl<'a, T: Fold> Fold for &'a T {
     type Result = T::Result;
     fn fold_with(&self, folder: &mut dyn Folder, binders: usize) -> Fallible<Self::Result> {
         (**self).fold_with(folder, binders)
     }
+
+    fn clone_result(&self) -> Self::Result {
+        (**self).clone_result()
+    }
 }
 
 impl<T: Fold> Fold for Vec<T> {
@@ -192,6 +252,10 @@ impl<T: Fold> Fold for Vec<T> {
     fn fold_with(&self, folder: &mut dyn Folder, binders: usize) -> Fallible<Self::Result> {
         self.iter().map(|e| e.fold_with(folder, binders)).collect()
     }
+
+    fn clone_result(&self) -> Self::Result {
+        self.iter().map(|e| e.clone_result()).collect()
+    }
 }
 
 impl<T: Fold> Fold for Box<T> {
@@ -199,6 +263,10 @@ impl<T: Fold> Fold for Box<T> {
     fn fold_with(&self, folder: &mut dyn Folder, binders: usize) -> Fallible<Self::Result> {
         Ok(Box::new((**self).fold_with(folder, binders)?))
     }
+
+    fn clone_result(&self) -> Self::Result {
+        Box::new((**self).clone_result())
+    }
 }
 
 impl<T: Fold> Fold for Arc<T> {
@@ -206,6 +274,10 @@ impl<T: Fold> Fold for Arc<T> {
     fn fold_with(&self, folder: &mut dyn Folder, binders: usize) -> Fallible<Self::Result> {
         Ok(Arc::new((**self).fold_with(folder, binders)?))
     }
+
+    fn clone_result(&self) -> Self::Result {
+        Arc::new((**self).clone_result())
+    }
 }
 
 macro_rules! tuple_fold {
@@ -217,6 +289,12 @@ macro_rules! tuple_fold {
                 let &($(ref $n),*) = self;
                 Ok(($($n.fold_with(folder, binders)?,)*))
             }
+
+            fn clone_result(&self) -> Self::Result {
+                #[allow(non_snake_case)]
+                let &($(ref $n),*) = self;
+                ($($n.clone_result(),)*)
+            }
         }
     }
 }
@@ -234,6 +312,10 @@ impl<T: Fold> Fold for Option<T> {
             Some(e) => Ok(Some(e.fold_with(folder, binders)?)),
         }
     }
+
+    fn clone_result(&self) -> Self::Result {
+        self.as_ref().map(|e| e.clone_result())
+    }
 }
 
 impl Fold for Ty {
@@ -241,14 +323,17 @@ impl Fold for Ty {
     fn fold_with(&self, folder: &mut dyn Folder, binders: usize) -> Fallible<Self::Result> {
         folder.fold_ty(self, binders)
     }
+
+    fn clone_result(&self) -> Self::Result {
+        self.clone()
+    }
 }
 
-crate fn super_fold_ty(folder: &mut dyn Folder, ty: &Ty, binders: usize) -> Fallible<Ty> {
+pub(crate) fn super_fold_ty(folder: &mut dyn Folder, ty: &Ty, binders: usize) -> Fallible<Ty> {
     match *ty {
-        Ty::Var(depth) => if depth >= binders {
-            folder.fold_free_existential_ty(depth - binders, binders)
-        } else {
-            Ok(Ty::Var(depth))
+        Ty::Var(depth) => match DebruijnIndex::new(depth).shifted_out(binders) {
+            Some(free) => folder.fold_free_existential_ty(free, binders),
+            None => Ok(Ty::Var(depth)),
         },
         Ty::Apply(ref apply) => {
             let ApplicationTy {
@@ -266,7 +351,9 @@ crate fn super_fold_ty(folder: &mut dyn Folder, ty: &Ty, binders: usize) -> Fall
                     folder.fold_free_universal_ty(ui, binders)
                 }
 
-                TypeName::ItemId(_) | TypeName::AssociatedType(_) => {
+                TypeName::ItemId(_)
+                | TypeName::AssociatedType(_)
+                | TypeName::Synthetic { .. } => {
                     let parameters = parameters.fold_with(folder, binders)?;
                     Ok(ApplicationTy { name, parameters }.cast())
                 }
@@ -292,6 +379,10 @@ impl Fold for QuantifiedTy {
             ty: ty.fold_with(folder, binders + num_binders)?,
         })
     }
+
+    fn clone_result(&self) -> Self::Result {
+        self.clone()
+    }
 }
 
 impl<T> Fold for Binders<T>
@@ -310,6 +401,13 @@ where
             value: value,
         })
     }
+
+    fn clone_result(&self) -> Self::Result {
+        Binders {
+            binders: self.binders.clone(),
+            value: self.value.clone_result(),
+        }
+    }
 }
 
 impl<T> Fold for Canonical<T>
@@ -328,6 +426,13 @@ where
             value: value,
         })
     }
+
+    fn clone_result(&self) -> Self::Result {
+        Canonical {
+            binders: self.binders.clone(),
+            value: self.value.clone_result(),
+        }
+    }
 }
 
 impl Fold for Lifetime {
@@ -335,29 +440,69 @@ impl Fold for Lifetime {
     fn fold_with(&self, folder: &mut dyn Folder, binders: usize) -> Fallible<Self::Result> {
         folder.fold_lifetime(self, binders)
     }
+
+    fn clone_result(&self) -> Self::Result {
+        self.clone()
+    }
 }
 
-crate fn super_fold_lifetime(
+pub(crate) fn super_fold_lifetime(
     folder: &mut dyn Folder,
     lifetime: &Lifetime,
     binders: usize,
 ) -> Fallible<Lifetime> {
     match *lifetime {
-        Lifetime::Var(depth) => if depth >= binders {
-            folder.fold_free_existential_lifetime(depth - binders, binders)
-        } else {
-            Ok(Lifetime::Var(depth))
+        Lifetime::Var(depth) => match DebruijnIndex::new(depth).shifted_out(binders) {
+            Some(free) => folder.fold_free_existential_lifetime(free, binders),
+            None => Ok(Lifetime::Var(depth)),
         },
         Lifetime::ForAll(universe) => folder.fold_free_universal_lifetime(universe, binders),
+        Lifetime::Static => Ok(Lifetime::Static),
+    }
+}
+
+impl Fold for Const {
+    type Result = Self;
+    fn fold_with(&self, folder: &mut dyn Folder, binders: usize) -> Fallible<Self::Result> {
+        folder.fold_const(self, binders)
+    }
+
+    fn clone_result(&self) -> Self::Result {
+        self.clone()
+    }
+}
+
+pub(crate) fn super_fold_const(
+    folder: &mut dyn Folder,
+    constant: &Const,
+    binders: usize,
+) -> Fallible<Const> {
+    match *constant {
+        Const::Var(depth) => match DebruijnIndex::new(depth).shifted_out(binders) {
+            Some(free) => folder.fold_free_existential_const(free, binders),
+            None => Ok(Const::Var(depth)),
+        },
+        Const::ForAll(universe) => folder.fold_free_universal_const(universe, binders),
+        Const::Value(value) => Ok(Const::Value(value)),
+        Const::Unevaluated(ref unevaluated) => {
+            Ok(Const::Unevaluated(unevaluated.fold_with(folder, binders)?))
+        }
     }
 }
 
 impl Fold for Substitution {
     type Result = Substitution;
     fn fold_with(&self, folder: &mut dyn Folder, binders: usize) -> Fallible<Self::Result> {
-        let parameters = self.parameters.fold_with(folder, binders)?;
+        let parameters = self.parameters
+            .iter()
+            .map(|p| p.fold_with(folder, binders))
+            .collect::<Fallible<_>>()?;
         Ok(Substitution { parameters })
     }
+
+    fn clone_result(&self) -> Self::Result {
+        self.clone()
+    }
 }
 
 macro_rules! copy_fold {
@@ -370,6 +515,10 @@ macro_rules! copy_fold {
                          -> ::fallible::Fallible<Self::Result> {
                 Ok(*self)
             }
+
+            fn clone_result(&self) -> Self::Result {
+                *self
+            }
         }
     }
 }
@@ -398,6 +547,16 @@ macro_rules! enum_fold {
                     )*
                 }
             }
+
+            fn clone_result(&self) -> Self::Result {
+                match self {
+                    $(
+                        $s::$variant( $($name),* ) => {
+                            $s::$variant( $($name.clone_result()),* )
+                        }
+                    )*
+                }
+            }
         }
     };
 
@@ -417,20 +576,30 @@ macro_rules! enum_fold {
                     )*
                 }
             }
+
+            fn clone_result(&self) -> Self::Result {
+                match self {
+                    $(
+                        $p::$variant( $($name),* ) => {
+                            $p::$variant( $($name.clone_result()),* )
+                        }
+                    )*
+                }
+            }
         }
     }
 }
 
 enum_fold!(PolarizedTraitRef[] { Positive(a), Negative(a) });
-enum_fold!(ParameterKind[T,L] { Ty(a), Lifetime(a) } where T: Fold, L: Fold);
+enum_fold!(ParameterKind[T,L,C] { Ty(a), Lifetime(a), Const(a) } where T: Fold, L: Fold, C: Fold);
 enum_fold!(WhereClause[] { Implemented(a), ProjectionEq(a) });
 enum_fold!(WellFormed[] { Trait(a), Ty(a) });
 enum_fold!(FromEnv[] { Trait(a), Ty(a) });
 enum_fold!(DomainGoal[] { Holds(a), WellFormed(a), FromEnv(a), Normalize(a), UnselectedNormalize(a),
                           InScope(a), Derefs(a), IsLocal(a), IsUpstream(a), IsFullyVisible(a),
-                          LocalImplAllowed(a), Compatible(a), DownstreamType(a) });
+                          LocalImplAllowed(a), Compatible(a), DownstreamType(a), External(a) });
 enum_fold!(LeafGoal[] { EqGoal(a), DomainGoal(a) });
-enum_fold!(Constraint[] { LifetimeEq(a, b) });
+enum_fold!(Constraint[] { LifetimeEq(a, b), TypeOutlives(a, b), LifetimeOutlives(a, b) });
 enum_fold!(Goal[] { Quantified(qkind, subgoal), Implies(wc, subgoal), And(g1, g2), Not(g),
                     Leaf(wc), CannotProve(a) });
 enum_fold!(ProgramClause[] { Implies(a), ForAll(a) });
@@ -548,6 +717,12 @@ macro_rules! struct_fold {
                     $($field_name: self.$field_name.fold_with(folder, binders)?),*
                 })
             }
+
+            fn clone_result(&self) -> Self::Result {
+                $s {
+                    $($field_name: self.$field_name.clone_result()),*
+                }
+            }
         }
     };
 }
@@ -572,18 +747,79 @@ struct_fold!(AssociatedTyValue {
     value,
 });
 struct_fold!(AssociatedTyValueBound { ty });
-struct_fold!(Environment { clauses });
+
+/// Folds each segment of the environment separately, so that the
+/// result keeps the same chain of segments as the original, sharing the
+/// environments that folding leaves unchanged.
+impl Fold for Environment {
+    type Result = Environment;
+    fn fold_with(&self, folder: &mut dyn Folder, binders: usize) -> Fallible<Self::Result> {
+        self.fold_segments(folder, binders)
+    }
+
+    fn clone_result(&self) -> Self::Result {
+        self.clone()
+    }
+}
+
 struct_fold!(InEnvironment[F] { environment, goal } where F: Fold<Result = F>);
 struct_fold!(EqGoal { a, b });
 struct_fold!(Derefs { source, target });
+struct_fold!(ExternalGoal { predicate, parameters });
+struct_fold!(UnevaluatedConst { const_id, parameters });
 struct_fold!(ProgramClauseImplication {
     consequence,
     conditions,
 });
 
+/// The goal of a provenance is left as it is: it is only there to be
+/// shown, and refers to the variables of its own table.
+impl Fold for Provenance {
+    type Result = Provenance;
+    fn fold_with(&self, _folder: &mut dyn Folder, _binders: usize) -> Fallible<Self::Result> {
+        Ok(self.clone())
+    }
+
+    fn clone_result(&self) -> Self::Result {
+        self.clone()
+    }
+}
+
+struct_fold!(RegionConstraint {
+    constraint,
+    provenance,
+});
+
+impl Fold for ClauseSource {
+    type Result = ClauseSource;
+    fn fold_with(&self, folder: &mut dyn Folder, binders: usize) -> Fallible<Self::Result> {
+        Ok(match self {
+            ClauseSource::WhereClause(clause) => {
+                ClauseSource::WhereClause(clause.fold_with(folder, binders)?)
+            }
+            ClauseSource::Impl(impl_id, trait_ref) => {
+                ClauseSource::Impl(*impl_id, trait_ref.fold_with(folder, binders)?)
+            }
+            ClauseSource::Program(clause) => ClauseSource::Program(clause.clone()),
+        })
+    }
+
+    fn clone_result(&self) -> Self::Result {
+        self.clone()
+    }
+}
+
+struct_fold!(ClauseSources { sources });
+
 struct_fold!(ConstrainedSubst {
     subst, /* NB: The `is_trivial` routine relies on the fact that `subst` is folded first. */
     constraints,
+    sources,
+});
+
+struct_fold!(SolutionSubst {
+    subst,
+    constraints,
 });
 
 struct_fold!(TraitBound {
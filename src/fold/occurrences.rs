@@ -0,0 +1,114 @@
+use chalk_parse::ast::Kinded;
+
+use super::*;
+
+/// Describes one of the variables bound by a `Canonical` value: see
+/// `Canonical::variable_occurrences`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VariableInfo {
+    /// The kind of the variable, and the universe it belongs to.
+    pub kind: ParameterKind<UniverseIndex>,
+
+    /// The number of times the variable occurs in the value.
+    pub occurrences: usize,
+
+    /// Where the variable first occurs, counting the occurrences of
+    /// all free variables in the order in which they are folded over.
+    /// `None` if the variable does not occur at all.
+    pub first_occurrence: Option<usize>,
+}
+
+impl<T: Fold> Canonical<T> {
+    /// Describes each of the bound variables of this canonical value,
+    /// in order: its kind and universe (which come straight from the
+    /// binders) as well as where it occurs in the value. This is
+    /// useful to map a canonical answer back into some other
+    /// inference context.
+    pub fn variable_occurrences(&self) -> Vec<VariableInfo> {
+        self.count_occurrences().infos
+    }
+
+    /// True if every variable of this canonical value is bound by it:
+    /// a free variable would refer to the inference table the value was
+    /// taken out of, which the value cannot be used with.
+    pub(crate) fn is_closed(&self) -> bool {
+        let counter = self.count_occurrences();
+        let bound: usize = counter.infos.iter().map(|info| info.occurrences).sum();
+        bound == counter.num_occurrences
+    }
+
+    fn count_occurrences(&self) -> OccurrenceCounter {
+        let mut counter = OccurrenceCounter {
+            infos: self.binders
+                .iter()
+                .map(|&kind| VariableInfo {
+                    kind,
+                    occurrences: 0,
+                    first_occurrence: None,
+                })
+                .collect(),
+            num_occurrences: 0,
+        };
+        self.value.fold_with(&mut counter, 0).unwrap();
+        counter
+    }
+
+    /// True if `subst` could be used to instantiate this canonical
+    /// value: it must have exactly one parameter per bound variable,
+    /// of the same kind. Substituting with a mismatched parameter
+    /// would otherwise panic.
+    pub fn substitution_kinds_match(&self, subst: &Substitution) -> bool {
+        self.binders.len() == subst.parameters.len()
+            && self
+                .binders
+                .iter()
+                .zip(&subst.parameters)
+                .all(|(binder, parameter)| binder.kind() == parameter.kind())
+    }
+}
+
+struct OccurrenceCounter {
+    infos: Vec<VariableInfo>,
+    num_occurrences: usize,
+}
+
+impl OccurrenceCounter {
+    fn record(&mut self, depth: DebruijnIndex) {
+        if let Some(info) = self.infos.get_mut(depth.depth()) {
+            info.occurrences += 1;
+            if info.first_occurrence.is_none() {
+                info.first_occurrence = Some(self.num_occurrences);
+            }
+        }
+        self.num_occurrences += 1;
+    }
+}
+
+impl DefaultTypeFolder for OccurrenceCounter {}
+
+impl ExistentialFolder for OccurrenceCounter {
+    fn fold_free_existential_ty(&mut self, depth: DebruijnIndex, binders: usize) -> Fallible<Ty> {
+        self.record(depth);
+        Ok(Ty::Var(depth.shifted_in(binders).depth()))
+    }
+
+    fn fold_free_existential_lifetime(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Lifetime> {
+        self.record(depth);
+        Ok(Lifetime::Var(depth.shifted_in(binders).depth()))
+    }
+
+    fn fold_free_existential_const(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Const> {
+        self.record(depth);
+        Ok(Const::Var(depth.shifted_in(binders).depth()))
+    }
+}
+
+impl IdentityUniversalFolder for OccurrenceCounter {}
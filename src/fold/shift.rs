@@ -4,7 +4,7 @@ use super::{DefaultTypeFolder, ExistentialFolder, Fold, IdentityUniversalFolder}
 
 /// Methods for converting debruijn indices to move values into or out
 /// of binders.
-crate trait Shift: Fold {
+pub(crate) trait Shift: Fold {
     /// Shifts debruijn indices in `self` **up**, which is used when a
     /// value is being placed under additional levels of binders.
     ///
@@ -62,10 +62,18 @@ crate trait Shift: Fold {
 
 impl<T: Fold> Shift for T {
     fn up_shift(&self, adjustment: usize) -> T::Result {
+        // Values are shifted by zero all the time, e.g. when they are
+        // substituted outside of any binder.
+        if adjustment == 0 {
+            return self.clone_result();
+        }
         self.fold_with(&mut Shifter { adjustment }, 0).unwrap()
     }
 
     fn down_shift(&self, adjustment: usize) -> Fallible<T::Result> {
+        if adjustment == 0 {
+            return Ok(self.clone_result());
+        }
         self.fold_with(&mut DownShifter { adjustment }, 0)
     }
 }
@@ -80,25 +88,33 @@ impl Shifter {
     /// Given a free variable at `depth`, shifts that depth to `depth
     /// + self.adjustment`, and then wraps *that* within the internal
     /// set `binders`.
-    fn adjust(&self, depth: usize, binders: usize) -> usize {
-        depth + self.adjustment + binders
+    fn adjust(&self, depth: DebruijnIndex, binders: usize) -> usize {
+        depth.shifted_in(self.adjustment + binders).depth()
     }
 }
 
 impl DefaultTypeFolder for Shifter {}
 
 impl ExistentialFolder for Shifter {
-    fn fold_free_existential_ty(&mut self, depth: usize, binders: usize) -> Fallible<Ty> {
+    fn fold_free_existential_ty(&mut self, depth: DebruijnIndex, binders: usize) -> Fallible<Ty> {
         Ok(Ty::Var(self.adjust(depth, binders)))
     }
 
     fn fold_free_existential_lifetime(
         &mut self,
-        depth: usize,
+        depth: DebruijnIndex,
         binders: usize,
     ) -> Fallible<Lifetime> {
         Ok(Lifetime::Var(self.adjust(depth, binders)))
     }
+
+    fn fold_free_existential_const(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Const> {
+        Ok(Const::Var(self.adjust(depth, binders)))
+    }
 }
 
 impl IdentityUniversalFolder for Shifter {}
@@ -119,9 +135,9 @@ impl DownShifter {
     /// by one of those internal binders (i.e., `depth < self.adjustment`) the this will
     /// fail with `Err`. Otherwise, returns the variable at this new depth (but adjusted to
     /// appear within `binders`).
-    fn adjust(&self, depth: usize, binders: usize) -> Fallible<usize> {
-        match depth.checked_sub(self.adjustment) {
-            Some(new_depth) => Ok(new_depth + binders),
+    fn adjust(&self, depth: DebruijnIndex, binders: usize) -> Fallible<usize> {
+        match depth.shifted_out(self.adjustment) {
+            Some(new_depth) => Ok(new_depth.shifted_in(binders).depth()),
             None => Err(NoSolution),
         }
     }
@@ -130,17 +146,25 @@ impl DownShifter {
 impl DefaultTypeFolder for DownShifter {}
 
 impl ExistentialFolder for DownShifter {
-    fn fold_free_existential_ty(&mut self, depth: usize, binders: usize) -> Fallible<Ty> {
+    fn fold_free_existential_ty(&mut self, depth: DebruijnIndex, binders: usize) -> Fallible<Ty> {
         Ok(Ty::Var(self.adjust(depth, binders)?))
     }
 
     fn fold_free_existential_lifetime(
         &mut self,
-        depth: usize,
+        depth: DebruijnIndex,
         binders: usize,
     ) -> Fallible<Lifetime> {
         Ok(Lifetime::Var(self.adjust(depth, binders)?))
     }
+
+    fn fold_free_existential_const(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Const> {
+        Ok(Const::Var(self.adjust(depth, binders)?))
+    }
 }
 
 impl IdentityUniversalFolder for DownShifter {}
@@ -3,7 +3,7 @@ use ir::*;
 
 use super::*;
 
-crate struct Subst<'s> {
+pub(crate) struct Subst<'s> {
     /// Values to substitute. A reference to a free variable with
     /// index `i` will be mapped to `parameters[i]` -- if `i >
     /// parameters.len()`, then we will leave the variable untouched.
@@ -11,13 +11,41 @@ crate struct Subst<'s> {
 }
 
 impl<'s> Subst<'s> {
-    crate fn apply<T: Fold>(parameters: &[Parameter], value: &T) -> T::Result {
+    pub(crate) fn apply<T: Fold>(parameters: &[Parameter], value: &T) -> T::Result {
+        // Substituting nothing changes nothing. Note that this is not true
+        // of other identity substitutions (see `is_identity_subst`): they
+        // still shift the variables they do not replace down by
+        // `parameters.len()`, as they take `value` out of its binders.
+        if parameters.is_empty() {
+            return value.clone_result();
+        }
         value.fold_with(&mut Subst { parameters }, 0).unwrap()
     }
+
+    /// The type substituted for the variable `depth`.
+    fn ty(&self, depth: DebruijnIndex) -> &Ty {
+        self.parameters[depth.depth()].as_ref().ty().expect("mismatched kinds in substitution")
+    }
+
+    /// The lifetime substituted for the variable `depth`.
+    fn lifetime(&self, depth: DebruijnIndex) -> &Lifetime {
+        self.parameters[depth.depth()]
+            .as_ref()
+            .lifetime()
+            .expect("mismatched kinds in substitution")
+    }
+
+    /// The constant substituted for the variable `depth`.
+    fn constant(&self, depth: DebruijnIndex) -> &Const {
+        self.parameters[depth.depth()]
+            .as_ref()
+            .constant()
+            .expect("mismatched kinds in substitution")
+    }
 }
 
 impl QuantifiedTy {
-    crate fn substitute(&self, parameters: &[Parameter]) -> Ty {
+    pub(crate) fn substitute(&self, parameters: &[Parameter]) -> Ty {
         assert_eq!(self.num_binders, parameters.len());
         Subst::apply(parameters, &self.ty)
     }
@@ -26,29 +54,32 @@ impl QuantifiedTy {
 impl<'b> DefaultTypeFolder for Subst<'b> {}
 
 impl<'b> ExistentialFolder for Subst<'b> {
-    fn fold_free_existential_ty(&mut self, depth: usize, binders: usize) -> Fallible<Ty> {
-        if depth >= self.parameters.len() {
-            Ok(Ty::Var(depth - self.parameters.len() + binders))
-        } else {
-            match self.parameters[depth] {
-                ParameterKind::Ty(ref t) => Ok(t.up_shift(binders)),
-                _ => panic!("mismatched kinds in substitution"),
-            }
+    fn fold_free_existential_ty(&mut self, depth: DebruijnIndex, binders: usize) -> Fallible<Ty> {
+        match depth.shifted_out(self.parameters.len()) {
+            Some(free) => Ok(Ty::Var(free.shifted_in(binders).depth())),
+            None => Ok(self.ty(depth).up_shift(binders)),
         }
     }
 
     fn fold_free_existential_lifetime(
         &mut self,
-        depth: usize,
+        depth: DebruijnIndex,
         binders: usize,
     ) -> Fallible<Lifetime> {
-        if depth >= self.parameters.len() {
-            Ok(Lifetime::Var(depth - self.parameters.len() + binders))
-        } else {
-            match self.parameters[depth] {
-                ParameterKind::Lifetime(ref l) => Ok(l.up_shift(binders)),
-                _ => panic!("mismatched kinds in substitution"),
-            }
+        match depth.shifted_out(self.parameters.len()) {
+            Some(free) => Ok(Lifetime::Var(free.shifted_in(binders).depth())),
+            None => Ok(self.lifetime(depth).up_shift(binders)),
+        }
+    }
+
+    fn fold_free_existential_const(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Const> {
+        match depth.shifted_out(self.parameters.len()) {
+            Some(free) => Ok(Const::Var(free.shifted_in(binders).depth())),
+            None => Ok(self.constant(depth).up_shift(binders)),
         }
     }
 }
@@ -1,59 +1,126 @@
 use chalk_parse::ast;
 use fallible::*;
-use fold::{DefaultTypeFolder, ExistentialFolder, Fold, IdentityUniversalFolder};
+use fold::{DefaultTypeFolder, ExistentialFolder, Fold, Folder, IdentityUniversalFolder};
 use fold::shift::Shift;
-use lalrpop_intern::InternedString;
-use std::collections::{BTreeMap, BTreeSet};
+use fold::Subst;
+use lalrpop_intern::{intern, InternedString};
+use smallvec::SmallVec;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
 use std::sync::Arc;
-use std::iter;
+use std::{iter, ptr, slice};
 use cast::Cast;
+use ir::could_match::{MatchKey, NegativeCache};
+use zip::Zip;
 
 #[macro_use]
 mod macros;
 
-crate mod could_match;
-crate mod debug;
+mod checked;
+pub use self::checked::GoalBuildError;
+mod const_value;
+pub use self::const_value::{ConstTy, ConstValue};
+pub use fold::VariableInfo;
+pub(crate) mod could_match;
+pub(crate) mod debug;
+mod shared;
+pub(crate) use self::shared::Shared;
+mod simplify;
 pub mod lowering;
 pub mod tls;
+mod test;
 
-crate type Identifier = InternedString;
+pub(crate) type Identifier = InternedString;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Program {
     /// From type-name to item-id. Used during lowering only.
-    crate type_ids: BTreeMap<Identifier, ItemId>,
+    pub(crate) type_ids: Shared<BTreeMap<Identifier, ItemId>>,
 
     /// For each struct/trait:
-    crate type_kinds: BTreeMap<ItemId, TypeKind>,
+    pub(crate) type_kinds: Shared<BTreeMap<ItemId, TypeKind>>,
 
     /// For each struct:
-    crate struct_data: BTreeMap<ItemId, StructDatum>,
+    pub(crate) struct_data: Shared<BTreeMap<ItemId, StructDatum>>,
+
+    /// For each synthetic type:
+    pub(crate) synthetic_ty_data: Shared<BTreeMap<ItemId, SyntheticTyDatum>>,
 
     /// For each impl:
-    crate impl_data: BTreeMap<ItemId, ImplDatum>,
+    pub(crate) impl_data: Shared<BTreeMap<ItemId, ImplDatum>>,
 
     /// For each trait:
-    crate trait_data: BTreeMap<ItemId, TraitDatum>,
+    pub(crate) trait_data: Shared<BTreeMap<ItemId, TraitDatum>>,
 
     /// For each associated ty:
-    crate associated_ty_data: BTreeMap<ItemId, AssociatedTyDatum>,
+    pub(crate) associated_ty_data: Shared<BTreeMap<ItemId, AssociatedTyDatum>>,
 
     /// For each default impl (automatically generated for auto traits):
-    crate default_impl_data: Vec<DefaultImplDatum>,
+    pub(crate) default_impl_data: Shared<Vec<DefaultImplDatum>>,
 
     /// For each user-specified clause
-    crate custom_clauses: Vec<ProgramClause>,
+    pub(crate) custom_clauses: Shared<Vec<ProgramClause>>,
 
     /// Special types and traits.
-    crate lang_items: BTreeMap<LangItem, ItemId>,
+    pub(crate) lang_items: BTreeMap<LangItem, ItemId>,
+
+    /// For each trait, the where clauses implied by its header, following
+    /// supertraits transitively (see `Program::elaborate_supertraits`).
+    pub(crate) elaborated_where_clauses: Shared<BTreeMap<ItemId, Arc<Vec<QuantifiedWhereClause>>>>,
+
+    /// The name of each crate the program was loaded from. A program
+    /// written as a single file is a single crate.
+    pub(crate) crate_names: BTreeMap<CrateId, Identifier>,
+
+    /// For each struct/trait/impl/associated ty, the crate defining it:
+    pub(crate) item_crates: Shared<BTreeMap<ItemId, CrateId>>,
+
+    /// The crate the program is seen from. The items of every other
+    /// crate are upstream of it, as if marked `#[upstream]`.
+    pub(crate) local_crate: CrateId,
+
+    /// For each struct/trait/impl, the `content_hash` of the item it
+    /// was lowered from (see `LowerProgram::relower`).
+    pub(crate) item_hashes: Shared<BTreeMap<ItemId, u64>>,
+
+    /// Whether the program is marked `#[test_reflection]`, so that
+    /// goals may ask about the program itself (see `ReflectionGoal`).
+    pub(crate) test_reflection: bool,
+
+    /// What becomes of goals in which a const expression overflows,
+    /// as chosen by the solver choice the program was lowered with.
+    pub(crate) const_overflow: ::solve::ConstOverflow,
+
+    /// The names given to impls and clauses with `#[name = "..."]`,
+    /// so that their clauses can be disabled while debugging (see
+    /// `ProgramEnvironment::without_clauses`).
+    pub(crate) item_names: Shared<BTreeMap<String, NamedItem>>,
+}
+
+/// An item named with `#[name = "..."]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum NamedItem {
+    Impl(ItemId),
+
+    /// The clauses lowered from a clause item, as a range of
+    /// `Program::custom_clauses`.
+    CustomClauses(Range<usize>),
 }
 
 impl Program {
-    /// Used for debugging output
-    crate fn split_projection<'p>(
+    /// Splits the parameters of a projection into those of the trait
+    /// and those of the associated type itself. Fails, rather than
+    /// mis-slicing, if the projection was built with parameters that do
+    /// not match the kinds the associated type declares.
+    pub(crate) fn split_projection<'p>(
         &self,
         projection: &'p ProjectionTy,
-    ) -> (&AssociatedTyDatum, &'p [Parameter], &'p [Parameter]) {
+    ) -> ::errors::Result<(&AssociatedTyDatum, &'p [Parameter], &'p [Parameter])> {
+        self.check_projection_kinds(projection)?;
         let ProjectionTy {
             associated_ty_id,
             ref parameters,
@@ -63,65 +130,511 @@ impl Program {
         let trait_num_params = trait_datum.binders.len();
         let split_point = parameters.len() - trait_num_params;
         let (other_params, trait_params) = parameters.split_at(split_point);
-        (associated_ty_data, trait_params, other_params)
+        Ok((associated_ty_data, trait_params, other_params))
+    }
+
+    /// The fully-qualified name of an item, like `Vec` for a struct or
+    /// `Iterator::Item` for an associated type. Unlike the `ItemId`,
+    /// which depends on the order in which the items were written, the
+    /// name identifies the item across edits of the program. Impls
+    /// have no name.
+    pub fn name_of(&self, id: ItemId) -> Option<String> {
+        if let Some(datum) = self.associated_ty_data.get(&id) {
+            let trait_name = self.type_kinds[&datum.trait_id].name;
+            return Some(format!("{}::{}", trait_name, datum.name));
+        }
+        self.type_kinds.get(&id).map(|type_kind| type_kind.name.to_string())
+    }
+
+    /// The item with the fully-qualified name `path`, as returned by
+    /// `name_of`, if there is one.
+    pub fn lookup(&self, path: &str) -> Option<ItemId> {
+        let mut segments = path.split("::");
+        let item_id = self.type_ids.get(&intern(segments.next()?)).cloned()?;
+        match (segments.next(), segments.next()) {
+            (None, _) => Some(item_id),
+            (Some(name), None) => self.associated_ty_data
+                .values()
+                .find(|datum| datum.trait_id == item_id && datum.name == intern(name))
+                .map(|datum| datum.id),
+            (Some(_), Some(_)) => None,
+        }
+    }
+
+    /// The name of the struct, trait or other named item closest to
+    /// `name`, if one is close enough to be what was meant: used to
+    /// suggest a fix when `name` does not name any item.
+    pub fn similar_name(&self, name: &str) -> Option<Identifier> {
+        let max_distance = (name.chars().count() + 2) / 3;
+        self.type_kinds
+            .values()
+            .map(|type_kind| (edit_distance(name, &type_kind.name.to_string()), type_kind.name))
+            .filter(|&(distance, _)| distance <= max_distance)
+            .min_by_key(|&(distance, candidate)| (distance, candidate.to_string()))
+            .map(|(_, candidate)| candidate)
+    }
+}
+
+/// The Levenshtein distance between `a` and `b`: the number of
+/// characters to insert, delete or replace to turn one into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &b_char) in b.iter().enumerate() {
+            let replace = previous[j] + if a_char == b_char { 0 } else { 1 };
+            let insert = current[j] + 1;
+            let delete = previous[j + 1] + 1;
+            current.push(replace.min(insert).min(delete));
+        }
+        previous = current;
     }
+    previous[b.len()]
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ProgramEnvironment {
     /// For each trait (used for debugging):
-    crate trait_data: BTreeMap<ItemId, TraitDatum>,
+    pub(crate) trait_data: Shared<BTreeMap<ItemId, TraitDatum>>,
 
     /// For each associated type (used for debugging):
-    crate associated_ty_data: BTreeMap<ItemId, AssociatedTyDatum>,
+    pub(crate) associated_ty_data: Shared<BTreeMap<ItemId, AssociatedTyDatum>>,
 
     /// Compiled forms of the above:
-    crate program_clauses: Vec<ProgramClause>,
+    pub(crate) program_clauses: Vec<ProgramClause>,
+
+    /// For each name given with `#[name = "..."]`, the clauses
+    /// compiled from the item it names, as a range of `program_clauses`.
+    pub(crate) named_clauses: BTreeMap<String, Range<usize>>,
+
+    /// For each of the clauses compiled from an impl, that impl (used
+    /// to explain solutions, see `FailureHint` and `ClauseSource`).
+    pub(crate) impl_clauses: BTreeMap<ProgramClause, ItemId>,
+
+    /// For each trait, its impls, each with the clauses compiled from
+    /// it as a range of `program_clauses`.
+    pub(crate) trait_impl_clauses: BTreeMap<ItemId, Vec<(ItemId, Range<usize>)>>,
+
+    /// The goals which no clause of the program can ever prove, as they
+    /// are looked up (see `NegativeCache`).
+    pub(crate) negative_cache: NegativeCache,
+}
+
+impl ProgramEnvironment {
+    /// True if some impl or clause was given `name` with
+    /// `#[name = "..."]`.
+    pub fn has_clause_named(&self, name: &str) -> bool {
+        self.named_clauses.contains_key(name)
+    }
+
+    /// This environment without the clauses of the items named in
+    /// `disabled`, so that the solver never considers them. Tables are
+    /// per environment, so no answer found with the clauses is reused
+    /// without them, nor the other way around.
+    /// Clauses are removed by their position, so a clause equal to a
+    /// disabled one but written or generated elsewhere is kept.
+    pub fn without_clauses(&self, disabled: &HashSet<String>) -> ProgramEnvironment {
+        let mut removed = vec![false; self.program_clauses.len()];
+        for (name, range) in &self.named_clauses {
+            if disabled.contains(name) {
+                for index in range.clone() {
+                    removed[index] = true;
+                }
+            }
+        }
+
+        // The index each clause has once the removed ones are gone
+        // (with one more entry, for the end of the last range).
+        let mut new_indices = Vec::with_capacity(removed.len() + 1);
+        let mut next_index = 0;
+        for &removed in &removed {
+            new_indices.push(next_index);
+            if !removed {
+                next_index += 1;
+            }
+        }
+        new_indices.push(next_index);
+
+        ProgramEnvironment {
+            trait_data: self.trait_data.clone(),
+            associated_ty_data: self.associated_ty_data.clone(),
+            program_clauses: self
+                .program_clauses
+                .iter()
+                .zip(&removed)
+                .filter(|&(_, &removed)| !removed)
+                .map(|(clause, _)| clause.clone())
+                .collect(),
+            named_clauses: self
+                .named_clauses
+                .iter()
+                .map(|(name, range)| {
+                    (name.clone(), new_indices[range.start]..new_indices[range.end])
+                })
+                .collect(),
+            impl_clauses: self.impl_clauses.clone(),
+            trait_impl_clauses: self
+                .trait_impl_clauses
+                .iter()
+                .map(|(&trait_id, impls)| {
+                    let impls = impls
+                        .iter()
+                        .map(|(impl_id, range)| {
+                            (*impl_id, new_indices[range.start]..new_indices[range.end])
+                        })
+                        .collect();
+                    (trait_id, impls)
+                })
+                .collect(),
+            negative_cache: NegativeCache::new(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum LangItem {
     DerefTrait,
+    BoxStruct,
+    SyncTrait,
+    PhantomDataStruct,
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Clone)]
 /// The set of assumptions we've made so far, and the current number of
 /// universal (forall) quantifiers we're within.
+///
+/// Environments are persistent: extending one only stores the new
+/// clauses, together with a pointer to the environment being extended,
+/// so the environments of nested `if` goals share their common
+/// prefix. Each segment is sorted, and a clause is never stored in
+/// more than one segment of a chain, so merging the segments yields
+/// the clauses in sorted order, without duplicates.
+///
+/// Folding an environment folds each of its segments, and keeps the
+/// clauses of each in the order they had, sorted or not (see
+/// `fold_with`): an answer and the goal it answers are zipped clause by
+/// clause, so the clauses must not move when the variables in them are
+/// substituted or truncated. The environments it extends are shared
+/// with the original where folding leaves them unchanged.
 pub struct Environment {
-    crate clauses: Vec<ProgramClause>,
+    /// The environment that this one extends, if any.
+    parent: Option<Arc<Environment>>,
+
+    /// The clauses added on top of `parent`; sorted, and disjoint from
+    /// the clauses of `parent`, unless they were folded.
+    segment: Vec<ProgramClause>,
+
+    /// False if this environment, or one it extends, was folded, so
+    /// that its segments may be out of order.
+    sorted: bool,
+
+    /// The indices of the clauses in `segment`, by the key of their
+    /// consequence (see `candidate_clauses`).
+    segment_index: HashMap<MatchKey, Vec<usize>>,
+
+    /// Total number of clauses, including those of `parent`.
+    len: usize,
+
+    /// A hash of the clauses that does not depend on the order in which
+    /// they were added, so that environments with the same clauses hash
+    /// alike however they were built.
+    clauses_hash: u64,
 }
 
 impl Environment {
-    crate fn new() -> Arc<Self> {
-        Arc::new(Environment { clauses: vec![] })
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Environment {
+            parent: None,
+            segment: vec![],
+            sorted: true,
+            segment_index: HashMap::new(),
+            len: 0,
+            clauses_hash: 0,
+        })
     }
 
-    crate fn add_clauses<I>(&self, clauses: I) -> Arc<Self>
+    /// Extends `env` with `clauses`. This is proportional to the number
+    /// of clauses added (and the depth of `env`), not to the size of
+    /// `env`.
+    pub(crate) fn add_clauses<I>(env: &Arc<Self>, clauses: I) -> Arc<Self>
     where
         I: IntoIterator<Item = ProgramClause>,
     {
-        let mut env = self.clone();
-        let env_clauses: BTreeSet<_> = env.clauses.into_iter().chain(clauses).collect();
-        env.clauses = env_clauses.into_iter().collect();
-        Arc::new(env)
+        let new_env = Environment::extend(Some(env.clone()), clauses);
+        if new_env.segment.is_empty() {
+            env.clone()
+        } else {
+            Arc::new(new_env)
+        }
+    }
+
+    /// Creates an environment with the clauses of `parent`, if any, and
+    /// `clauses`.
+    pub(crate) fn extend<I>(parent: Option<Arc<Self>>, clauses: I) -> Self
+    where
+        I: IntoIterator<Item = ProgramClause>,
+    {
+        let segment: Vec<_> = clauses
+            .into_iter()
+            .filter(|clause| parent.as_ref().map_or(true, |parent| !parent.contains(clause)))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        Environment::with_segment(parent, segment, true)
+    }
+
+    /// Creates an environment with the clauses of `parent`, if any, and
+    /// `segment`, which is taken as it is: folded clauses, for one, must
+    /// not be reordered. `sorted` tells whether `segment` is sorted and
+    /// disjoint from the clauses of `parent`.
+    fn with_segment(parent: Option<Arc<Self>>, segment: Vec<ProgramClause>, sorted: bool) -> Self {
+        let (parent_len, parent_hash, parent_sorted) = parent.as_ref().map_or((0, 0, true), |parent| {
+            (parent.len, parent.clauses_hash, parent.sorted)
+        });
+        let clauses_hash = segment
+            .iter()
+            .fold(parent_hash, |h, clause| h.wrapping_add(hash_clause(clause)));
+
+        let mut segment_index = HashMap::new();
+        for (index, clause) in segment.iter().enumerate() {
+            segment_index
+                .entry(clause.match_key())
+                .or_insert_with(Vec::new)
+                .push(index);
+        }
+
+        Environment {
+            parent,
+            len: parent_len + segment.len(),
+            segment,
+            sorted: sorted && parent_sorted,
+            segment_index,
+            clauses_hash,
+        }
+    }
+
+    /// Folds the clauses of this environment segment by segment (see
+    /// `Fold for Environment`).
+    pub(crate) fn fold_segments(
+        &self,
+        folder: &mut dyn Folder,
+        binders: usize,
+    ) -> Fallible<Environment> {
+        let (parent, segment) = self.fold_parts(folder, binders)?;
+        Ok(Environment::with_segment(parent, segment, false))
+    }
+
+    /// `env` folded, or `env` itself if folding leaves it unchanged, as
+    /// it does the environments that have no variables.
+    fn fold_shared(
+        env: &Arc<Environment>,
+        folder: &mut dyn Folder,
+        binders: usize,
+    ) -> Fallible<Arc<Environment>> {
+        let (parent, segment) = env.fold_parts(folder, binders)?;
+        let same_parent = match (&parent, &env.parent) {
+            (Some(folded), Some(parent)) => Arc::ptr_eq(folded, parent),
+            (None, None) => true,
+            (Some(_), None) | (None, Some(_)) => false,
+        };
+        if same_parent && segment == env.segment {
+            Ok(env.clone())
+        } else {
+            Ok(Arc::new(Environment::with_segment(parent, segment, false)))
+        }
+    }
+
+    /// The parent of this environment and the clauses of its segment,
+    /// folded.
+    fn fold_parts(
+        &self,
+        folder: &mut dyn Folder,
+        binders: usize,
+    ) -> Fallible<(Option<Arc<Environment>>, Vec<ProgramClause>)> {
+        let parent = match &self.parent {
+            Some(parent) => Some(Environment::fold_shared(parent, folder, binders)?),
+            None => None,
+        };
+        let segment = self
+            .segment
+            .iter()
+            .map(|clause| clause.fold_with(folder, binders))
+            .collect::<Fallible<Vec<_>>>()?;
+        Ok((parent, segment))
+    }
+
+    pub(crate) fn contains(&self, clause: &ProgramClause) -> bool {
+        let key = clause.match_key();
+        self.chain().any(|env| {
+            env.segment_index
+                .get(&key)
+                .map_or(false, |indices| indices.iter().any(|&index| env.segment[index] == *clause))
+        })
+    }
+
+    /// The clauses of this environment, in sorted order unless they
+    /// were folded.
+    pub(crate) fn clauses(&self) -> EnvironmentClauses {
+        let mut heads = BinaryHeap::new();
+        let mut rest = vec![];
+        for (index, segment) in self.segments().enumerate() {
+            let mut iter = segment.iter();
+            if let Some(head) = iter.next() {
+                heads.push(Reverse((head, index)));
+            }
+            rest.push(iter);
+        }
+        EnvironmentClauses { heads, rest }
+    }
+
+    /// The clauses of this environment that could be used to prove
+    /// `goal`, in sorted order. Every clause that `could_match` the goal
+    /// is among them, but they are found without looking at the other
+    /// clauses, so the environments of goals under many hypotheses stay
+    /// cheap to search.
+    pub(crate) fn candidate_clauses(&self, goal: &DomainGoal) -> Vec<&ProgramClause> {
+        let key = goal.match_key();
+        let mut candidates = vec![];
+        for env in self.chain() {
+            let mut push = |indices: &Vec<usize>| {
+                candidates.extend(indices.iter().map(|&index| &env.segment[index]))
+            };
+
+            if key.item.is_some() {
+                // Only clauses about the same item, or about an unknown
+                // one, can match.
+                for key in &[key, key.without_item()] {
+                    if let Some(indices) = env.segment_index.get(key) {
+                        push(indices);
+                    }
+                }
+            } else {
+                for (clause_key, indices) in &env.segment_index {
+                    if clause_key.could_match(&key) {
+                        push(indices);
+                    }
+                }
+            }
+        }
+        candidates.sort();
+        candidates
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    #[cfg(test)]
+    pub(crate) fn parent(&self) -> Option<&Arc<Environment>> {
+        self.parent.as_ref()
+    }
+
+    /// The clauses added on top of `parent`.
+    #[cfg(test)]
+    pub(crate) fn segment(&self) -> &[ProgramClause] {
+        &self.segment
+    }
+
+    /// False if this environment, or one it extends, was folded, so
+    /// that `clauses` need not yield its clauses in sorted order.
+    pub(crate) fn is_sorted(&self) -> bool {
+        self.sorted
+    }
+
+    /// The segments of this environment, starting with the most recent.
+    pub(crate) fn segments(&self) -> impl Iterator<Item = &[ProgramClause]> {
+        self.chain().map(|env| &env.segment[..])
+    }
+
+    /// This environment and the ones it extends, starting with this one.
+    fn chain(&self) -> impl Iterator<Item = &Environment> {
+        let mut env = Some(self);
+        iter::from_fn(move || {
+            let current = env?;
+            env = current.parent.as_ref().map(|parent| &**parent);
+            Some(current)
+        })
+    }
+}
+
+fn hash_clause(clause: &ProgramClause) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    clause.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Iterator over the clauses of an `Environment`, merging its sorted
+/// segments.
+pub(crate) struct EnvironmentClauses<'a> {
+    /// The next clause of each segment that has any left, along with the
+    /// index of that segment.
+    heads: BinaryHeap<Reverse<(&'a ProgramClause, usize)>>,
+
+    /// The remaining clauses of each segment.
+    rest: Vec<slice::Iter<'a, ProgramClause>>,
+}
+
+impl<'a> Iterator for EnvironmentClauses<'a> {
+    type Item = &'a ProgramClause;
+
+    fn next(&mut self) -> Option<&'a ProgramClause> {
+        let Reverse((clause, index)) = self.heads.pop()?;
+        if let Some(head) = self.rest[index].next() {
+            self.heads.push(Reverse((head, index)));
+        }
+        Some(clause)
+    }
+}
+
+impl PartialEq for Environment {
+    fn eq(&self, other: &Environment) -> bool {
+        ptr::eq(self, other)
+            || (self.len == other.len
+                && self.clauses_hash == other.clauses_hash
+                && self.clauses().eq(other.clauses()))
+    }
+}
+
+impl Eq for Environment {}
+
+impl PartialOrd for Environment {
+    fn partial_cmp(&self, other: &Environment) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Environment {
+    fn cmp(&self, other: &Environment) -> Ordering {
+        if ptr::eq(self, other) {
+            return Ordering::Equal;
+        }
+        self.clauses().cmp(other.clauses())
+    }
+}
+
+impl Hash for Environment {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len.hash(state);
+        self.clauses_hash.hash(state);
     }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct InEnvironment<G> {
-    crate environment: Arc<Environment>,
-    crate goal: G,
+    pub(crate) environment: Arc<Environment>,
+    pub(crate) goal: G,
 }
 
 impl<G> InEnvironment<G> {
-    crate fn new(environment: &Arc<Environment>, goal: G) -> Self {
+    pub(crate) fn new(environment: &Arc<Environment>, goal: G) -> Self {
         InEnvironment {
             environment: environment.clone(),
             goal,
         }
     }
 
-    crate fn map<OP, H>(self, op: OP) -> InEnvironment<H>
+    pub(crate) fn map<OP, H>(self, op: OP) -> InEnvironment<H>
     where
         OP: FnOnce(G) -> H,
     {
@@ -138,14 +651,28 @@ pub enum TypeName {
     ItemId(ItemId),
 
     /// skolemized form of a type parameter like `T`
-    ForAll(UniverseIndex),
+    ForAll(PlaceholderIndex),
 
     /// an associated type like `Iterator::Item`; see `AssociatedType` for details
     AssociatedType(ItemId),
+
+    /// a rigid type that is not a struct, like the type of a function
+    /// definition, declared with `synthetic type FnDef<type, lifetime>;`
+    /// (see `SyntheticTyDatum`)
+    Synthetic { id: ItemId, kind: SyntheticKind },
+}
+
+/// What is known of a synthetic type from its name alone. The kinds of
+/// its parameters are in the `type_kinds` of the program, as for any
+/// other type.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SyntheticKind {
+    /// The number of parameters the type takes.
+    pub(crate) arity: usize,
 }
 
 impl TypeName {
-    crate fn to_ty(self) -> Ty {
+    pub(crate) fn to_ty(self) -> Ty {
         Ty::Apply(ApplicationTy {
             name: self,
             parameters: vec![],
@@ -158,65 +685,155 @@ impl TypeName {
 /// An example chain of transformations would be:
 /// `forall<T> { Goal(T) }` (syntatical representation)
 /// `forall { Goal(?0) }` (used a DeBruijn index)
-/// `Goal(!U1)` (the quantifier was moved to the environment and replaced with a universe index)
+/// `Goal(!U1_0)` (the quantifier was moved to the environment and replaced with a placeholder
+/// in a new universe, see `PlaceholderIndex`)
 /// See https://rust-lang-nursery.github.io/rustc-guide/mir/regionck.html#skol for more.
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct UniverseIndex {
-    crate counter: usize,
+    pub(crate) counter: usize,
 }
 
 impl UniverseIndex {
-    crate const ROOT: UniverseIndex = UniverseIndex { counter: 0 };
+    pub(crate) const ROOT: UniverseIndex = UniverseIndex { counter: 0 };
 
-    crate fn root() -> UniverseIndex {
+    pub(crate) fn root() -> UniverseIndex {
         Self::ROOT
     }
 
-    crate fn can_see(self, ui: UniverseIndex) -> bool {
+    pub(crate) fn can_see(self, ui: UniverseIndex) -> bool {
         self.counter >= ui.counter
     }
 
-    crate fn to_lifetime(self) -> Lifetime {
-        Lifetime::ForAll(self)
+    pub(crate) fn next(self) -> UniverseIndex {
+        self.shifted_in(1)
     }
 
-    crate fn next(self) -> UniverseIndex {
+    /// The universe `n` universes after this one.
+    pub(crate) fn shifted_in(self, n: usize) -> UniverseIndex {
         UniverseIndex {
-            counter: self.counter + 1,
+            counter: self.counter + n,
         }
     }
+
+    /// The universe `n` universes before this one, if there is one.
+    pub(crate) fn shifted_out(self, n: usize) -> Option<UniverseIndex> {
+        let counter = self.counter.checked_sub(n)?;
+        Some(UniverseIndex { counter })
+    }
+}
+
+/// The depth of a variable -- the `d` of `Ty::Var(d)` -- counted from
+/// some binder: bound variables count the binders between themselves
+/// and the binder which binds them, while free (inference) variables
+/// are numbered as if bound outside all of the binders of the value,
+/// in order. Depths are therefore adjusted as values are moved into
+/// and out of binders, and doing that arithmetic on raw `usize`s has
+/// led to underflows; this does it with checked operations instead.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DebruijnIndex {
+    depth: usize,
+}
+
+impl DebruijnIndex {
+    pub fn new(depth: usize) -> DebruijnIndex {
+        DebruijnIndex { depth }
+    }
+
+    pub fn depth(self) -> usize {
+        self.depth
+    }
+
+    /// The depth of this variable from within `n` more binders.
+    pub fn shifted_in(self, n: usize) -> DebruijnIndex {
+        DebruijnIndex {
+            depth: self.depth + n,
+        }
+    }
+
+    /// The depth of this variable from outside of `n` of the binders it
+    /// is within, or `None` if it is bound by one of them.
+    pub fn shifted_out(self, n: usize) -> Option<DebruijnIndex> {
+        let depth = self.depth.checked_sub(n)?;
+        Some(DebruijnIndex { depth })
+    }
+}
+
+/// A placeholder is a universally quantified parameter once its binder
+/// has been moved into the environment. All the parameters of a single
+/// binder (e.g. `forall<'a, 'b, T>`) live in the same universe, so each
+/// placeholder is identified by the index *of* its universe together
+/// with its index *in* that universe.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PlaceholderIndex {
+    pub(crate) ui: UniverseIndex,
+    pub(crate) idx: usize,
+}
+
+impl PlaceholderIndex {
+    pub(crate) fn to_ty(self) -> Ty {
+        TypeName::ForAll(self).to_ty()
+    }
+
+    pub(crate) fn to_lifetime(self) -> Lifetime {
+        Lifetime::ForAll(self)
+    }
+
+    pub(crate) fn to_const(self) -> Const {
+        Const::ForAll(self)
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ItemId {
-    crate index: usize,
+    pub(crate) index: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CrateId {
+    pub(crate) index: usize,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct TypeKind {
-    crate sort: TypeSort,
-    crate name: Identifier,
-    crate binders: Binders<()>,
+    pub(crate) sort: TypeSort,
+    pub(crate) name: Identifier,
+    pub(crate) binders: Binders<()>,
+
+    /// The defaults of the trailing parameters that have one, like the
+    /// `0` in `struct Foo<T, const N = 0>`. These are filled in when
+    /// the parameters are omitted.
+    pub(crate) const_defaults: Vec<Const>,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum TypeSort {
     Struct,
     Trait,
+
+    /// An external predicate (see `ExternalGoal`). It is not a type,
+    /// but shares the namespace of types and traits.
+    ExternalPredicate,
+
+    /// A named constant (see `UnevaluatedConst`). Like external
+    /// predicates, it shares the namespace of types.
+    Const,
+
+    /// A synthetic type (see `TypeName::Synthetic`).
+    Synthetic,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct ImplDatum {
-    crate binders: Binders<ImplDatumBound>,
+    pub(crate) binders: Binders<ImplDatumBound>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct ImplDatumBound {
-    crate trait_ref: PolarizedTraitRef,
-    crate where_clauses: Vec<QuantifiedWhereClause>,
-    crate associated_ty_values: Vec<AssociatedTyValue>,
-    crate specialization_priority: usize,
-    crate impl_type: ImplType,
+    pub(crate) trait_ref: PolarizedTraitRef,
+    pub(crate) where_clauses: Vec<QuantifiedWhereClause>,
+    pub(crate) associated_ty_values: Vec<AssociatedTyValue>,
+    pub(crate) specialization_priority: usize,
+    pub(crate) impl_type: ImplType,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -227,52 +844,95 @@ pub enum ImplType {
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct DefaultImplDatum {
-    crate binders: Binders<DefaultImplDatumBound>,
+    pub(crate) binders: Binders<DefaultImplDatumBound>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct DefaultImplDatumBound {
-    crate trait_ref: TraitRef,
-    crate accessible_tys: Vec<Ty>,
+    pub(crate) trait_ref: TraitRef,
+    pub(crate) accessible_tys: Vec<Ty>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct StructDatum {
-    crate binders: Binders<StructDatumBound>,
+    pub(crate) binders: Binders<StructDatumBound>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct StructDatumBound {
-    crate self_ty: ApplicationTy,
-    crate fields: Vec<Ty>,
-    crate where_clauses: Vec<QuantifiedWhereClause>,
-    crate flags: StructFlags,
+    pub(crate) self_ty: ApplicationTy,
+    pub(crate) fields: Vec<Ty>,
+    pub(crate) where_clauses: Vec<QuantifiedWhereClause>,
+    pub(crate) flags: StructFlags,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct StructFlags {
-    crate upstream: bool,
-    crate fundamental: bool,
+    pub(crate) upstream: bool,
+    pub(crate) fundamental: bool,
+
+    /// Set for `#[interior_mut]` structs, like `UnsafeCell`, whose
+    /// contents can be mutated through a shared reference. Such a
+    /// struct is never `Sync` unless an impl says so, and its
+    /// parameters are invariant.
+    pub(crate) interior_mut: bool,
+
+    /// The auto traits named by `#[phantom_no_auto(...)]`, for which
+    /// the struct gets no default impl. A marker like `PhantomPinned`
+    /// uses this to opt out of `Unpin`, and so, through their default
+    /// impls, do the structs that contain it.
+    pub(crate) no_auto_traits: Vec<ItemId>,
+}
+
+/// A synthetic type: like a struct, it unifies only with itself, but it
+/// has no fields and no where clauses. Given
+///
+/// ```notrust
+/// synthetic type Witness<type, type> constituents<T, U> { T, Vec<U> }
+/// ```
+///
+/// the `self_ty` is `Witness<^0, ^1>` and the `constituents` are `^0`
+/// and `Vec<^1>`; these stand in for the fields of a struct where
+/// auto traits are concerned. A synthetic type declared without
+/// constituents has none.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SyntheticTyDatum {
+    pub(crate) binders: Binders<SyntheticTyDatumBound>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SyntheticTyDatumBound {
+    pub(crate) self_ty: ApplicationTy,
+    pub(crate) constituents: Vec<Ty>,
+
+    /// Set when the type is declared in an upstream crate.
+    pub(crate) upstream: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct TraitDatum {
-    crate binders: Binders<TraitDatumBound>,
+    pub(crate) binders: Binders<TraitDatumBound>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct TraitDatumBound {
-    crate trait_ref: TraitRef,
-    crate where_clauses: Vec<QuantifiedWhereClause>,
-    crate flags: TraitFlags,
+    pub(crate) trait_ref: TraitRef,
+    pub(crate) where_clauses: Vec<QuantifiedWhereClause>,
+    pub(crate) flags: TraitFlags,
+
+    /// Whether each of the parameters of `trait_ref` is a
+    /// `#[fundamental]` type parameter. A local type appearing directly
+    /// in such a parameter, like the `Local` of `impl Fn<(Local,)> for
+    /// Foo`, is enough to satisfy the orphan rules.
+    pub(crate) fundamental_parameters: Vec<bool>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct TraitFlags {
-    crate auto: bool,
-    crate marker: bool,
-    crate upstream: bool,
-    crate fundamental: bool,
+    pub(crate) auto: bool,
+    pub(crate) marker: bool,
+    pub(crate) upstream: bool,
+    pub(crate) fundamental: bool,
     pub deref: bool,
 }
 
@@ -299,7 +959,7 @@ impl InlineBound {
 }
 
 impl QuantifiedInlineBound {
-    crate fn into_where_clauses(&self, self_ty: Ty) -> Vec<QuantifiedWhereClause> {
+    pub(crate) fn into_where_clauses(&self, self_ty: Ty) -> Vec<QuantifiedWhereClause> {
         let self_ty = self_ty.up_shift(self.binders.len());
         self.value.into_where_clauses(self_ty).into_iter().map(|wc| {
             Binders {
@@ -315,8 +975,8 @@ impl QuantifiedInlineBound {
 /// Does not know anything about what it's binding.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct TraitBound {
-    crate trait_id: ItemId,
-    crate args_no_self: Vec<Parameter>,
+    pub(crate) trait_id: ItemId,
+    pub(crate) args_no_self: Vec<Parameter>,
 }
 
 impl TraitBound {
@@ -325,7 +985,7 @@ impl TraitBound {
         vec![WhereClause::Implemented(trait_ref)]
     }
 
-    crate fn as_trait_ref(&self, self_ty: Ty) -> TraitRef {
+    pub(crate) fn as_trait_ref(&self, self_ty: Ty) -> TraitRef {
         let self_ty = ParameterKind::Ty(self_ty);
         TraitRef {
             trait_id: self.trait_id,
@@ -337,11 +997,11 @@ impl TraitBound {
 /// Does not know anything about what it's binding.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct ProjectionEqBound {
-    crate trait_bound: TraitBound,
-    crate associated_ty_id: ItemId,
+    pub(crate) trait_bound: TraitBound,
+    pub(crate) associated_ty_id: ItemId,
     /// Does not include trait parameters.
-    crate parameters: Vec<Parameter>,
-    crate value: Ty,
+    pub(crate) parameters: Vec<Parameter>,
+    pub(crate) value: Ty,
 }
 
 impl ProjectionEqBound {
@@ -367,26 +1027,26 @@ impl ProjectionEqBound {
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct AssociatedTyDatum {
     /// The trait this associated type is defined in.
-    crate trait_id: ItemId,
+    pub(crate) trait_id: ItemId,
 
     /// The ID of this associated type
-    crate id: ItemId,
+    pub(crate) id: ItemId,
 
     /// Name of this associated type.
-    crate name: Identifier,
+    pub(crate) name: Identifier,
 
     /// Parameters on this associated type, beginning with those from the trait,
     /// but possibly including more.
-    crate parameter_kinds: Vec<ParameterKind<Identifier>>,
+    pub(crate) parameter_kinds: Vec<ParameterKind<Identifier>>,
 
     /// Bounds on the associated type itself.
     ///
     /// These must be proven by the implementer, for all possible parameters that
     /// would result in a well-formed projection.
-    crate bounds: Vec<QuantifiedInlineBound>,
+    pub(crate) bounds: Vec<QuantifiedInlineBound>,
 
     /// Where clauses that must hold for the projection to be well-formed.
-    crate where_clauses: Vec<QuantifiedWhereClause>,
+    pub(crate) where_clauses: Vec<QuantifiedWhereClause>,
 }
 
 impl AssociatedTyDatum {
@@ -395,7 +1055,7 @@ impl AssociatedTyDatum {
     /// ```notrust
     /// Implemented(<?0 as Foo>::Item<?1>: Sized)
     /// ```
-    crate fn bounds_on_self(&self) -> Vec<QuantifiedWhereClause> {
+    pub(crate) fn bounds_on_self(&self) -> Vec<QuantifiedWhereClause> {
         let parameters = self.parameter_kinds
                              .anonymize()
                              .iter()
@@ -410,18 +1070,47 @@ impl AssociatedTyDatum {
     }
 }
 
+/// The value of an associated type in an impl, like `type Item<'a> =
+/// Ref<'a, T>` in `impl<T> Iterable for Vec<T>`. The value is bound by
+/// two nested lists of binders: those of the impl (`T`) and, inside
+/// them, its own (`'a`). In the example, the value is thus `for<type>
+/// for<lifetime> Ref<'?0, ?1>`. The outer binders are a copy of those
+/// of the impl, so the inner value may also be read from within the
+/// impl, as its trait-ref and where clauses are. Use `Binders::fuse`
+/// to bind the value by a single list.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct AssociatedTyValue {
-    crate associated_ty_id: ItemId,
+    pub(crate) associated_ty_id: ItemId,
+
+    /// The value, under the binders of the impl and then its own.
+    pub(crate) value: Binders<Binders<AssociatedTyValueBound>>,
+}
+
+impl AssociatedTyValue {
+    /// The value under its own binders only, as seen from within the
+    /// impl.
+    pub(crate) fn in_impl(&self) -> &Binders<AssociatedTyValueBound> {
+        &self.value.value
+    }
 
-    // note: these binders are in addition to those from the impl
-    crate value: Binders<AssociatedTyValueBound>,
+    /// The parameters of the projection normalized by this value, as
+    /// seen from the fused value (see `Binders::fuse`): the value's own
+    /// parameters, followed by those of `impl_trait_ref`.
+    /// `impl_trait_ref` must be the trait-ref of the impl, with the
+    /// variables of the impl shifted in past the value's own binders.
+    pub(crate) fn projection_parameters(&self, impl_trait_ref: &TraitRef) -> Vec<Parameter> {
+        self.in_impl()
+            .bound_parameters()
+            .into_iter()
+            .chain(impl_trait_ref.parameters.iter().cloned())
+            .collect()
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct AssociatedTyValueBound {
     /// Type that we normalize to. The X in `type Foo<'a> = X`.
-    crate ty: Ty,
+    pub(crate) ty: Ty,
 }
 
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -437,7 +1126,7 @@ pub enum Ty {
 }
 
 impl Ty {
-    crate fn as_projection_ty_enum(&self) -> ProjectionTyRefEnum {
+    pub(crate) fn as_projection_ty_enum(&self) -> ProjectionTyRefEnum {
         match *self {
             Ty::Projection(ref proj) => ProjectionTyEnum::Selected(proj),
             Ty::UnselectedProjection(ref proj) => ProjectionTyEnum::Unselected(proj),
@@ -457,106 +1146,153 @@ impl Ty {
 /// and we use deBruijn indices within `self.ty`
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct QuantifiedTy {
-    crate num_binders: usize,
-    crate ty: Ty,
+    pub(crate) num_binders: usize,
+    pub(crate) ty: Ty,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Lifetime {
     /// See Ty::Var(_).
     Var(usize),
-    ForAll(UniverseIndex),
+    ForAll(PlaceholderIndex),
+
+    /// `'static`, which outlives every other lifetime. It belongs to the
+    /// root universe, so any inference variable can be bound to it;
+    /// equating it with a placeholder yields a constraint, as equating
+    /// two distinct placeholders does.
+    Static,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Const {
+    /// See Ty::Var(_).
+    Var(usize),
+
+    /// Skolemized form of a const parameter like `N`, cf `TypeName::ForAll`.
+    ForAll(PlaceholderIndex),
+
+    /// A concrete value, like the `3` in `Foo<3>`.
+    Value(ConstValue),
+
+    /// A named constant, like the `{FOO}` in `Foo<{FOO}>`, whose value
+    /// is computed by the embedder of the solver (see `ConstEval`).
+    Unevaluated(UnevaluatedConst),
+}
+
+/// A use `{FOO<P0, ..., Pn>}` of a constant declared with `const FOO<..>:
+/// usize;`. Its value is only known to the embedder: when unifying it,
+/// we first try to evaluate it, and otherwise compare it structurally.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UnevaluatedConst {
+    pub(crate) const_id: ItemId,
+    pub(crate) parameters: Vec<Parameter>,
 }
 
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct ApplicationTy {
-    crate name: TypeName,
-    crate parameters: Vec<Parameter>,
+    pub(crate) name: TypeName,
+    pub(crate) parameters: Vec<Parameter>,
 }
 
 impl ApplicationTy {
-    crate fn type_parameters<'a>(&'a self) -> impl Iterator<Item=Ty> + 'a {
+    pub(crate) fn type_parameters<'a>(&'a self) -> impl Iterator<Item=Ty> + 'a {
         // This unwrap() is safe because is_ty ensures that we definitely have a Ty
         self.parameters.iter().filter(|p| p.is_ty()).map(|p| p.clone().ty().unwrap())
     }
 
-    crate fn first_type_parameter(&self) -> Option<Ty> {
+    pub(crate) fn first_type_parameter(&self) -> Option<Ty> {
         self.type_parameters().next()
     }
 
-    crate fn len_type_parameters(&self) -> usize {
+    pub(crate) fn len_type_parameters(&self) -> usize {
         self.type_parameters().count()
     }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
-pub enum ParameterKind<T, L = T> {
+pub enum ParameterKind<T, L = T, C = T> {
     Ty(T),
     Lifetime(L),
+    Const(C),
 }
 
 impl<T> ParameterKind<T> {
-    crate fn into_inner(self) -> T {
+    pub(crate) fn into_inner(self) -> T {
         match self {
             ParameterKind::Ty(t) => t,
             ParameterKind::Lifetime(t) => t,
+            ParameterKind::Const(t) => t,
         }
     }
 
-    crate fn map<OP, U>(self, op: OP) -> ParameterKind<U>
+    pub(crate) fn map<OP, U>(self, op: OP) -> ParameterKind<U>
     where
         OP: FnOnce(T) -> U,
     {
         match self {
             ParameterKind::Ty(t) => ParameterKind::Ty(op(t)),
             ParameterKind::Lifetime(t) => ParameterKind::Lifetime(op(t)),
+            ParameterKind::Const(t) => ParameterKind::Const(op(t)),
         }
     }
 }
 
-impl<T, L> ParameterKind<T, L> {
-    crate fn assert_ty_ref(&self) -> &T {
+impl<T, L, C> ParameterKind<T, L, C> {
+    pub(crate) fn assert_ty_ref(&self) -> &T {
         self.as_ref().ty().unwrap()
     }
 
-    crate fn assert_lifetime_ref(&self) -> &L {
+    pub(crate) fn assert_lifetime_ref(&self) -> &L {
         self.as_ref().lifetime().unwrap()
     }
 
-    crate fn as_ref(&self) -> ParameterKind<&T, &L> {
+    pub(crate) fn assert_const_ref(&self) -> &C {
+        self.as_ref().constant().unwrap()
+    }
+
+    pub(crate) fn as_ref(&self) -> ParameterKind<&T, &L, &C> {
         match *self {
             ParameterKind::Ty(ref t) => ParameterKind::Ty(t),
             ParameterKind::Lifetime(ref l) => ParameterKind::Lifetime(l),
+            ParameterKind::Const(ref c) => ParameterKind::Const(c),
         }
     }
 
-    crate fn is_ty(&self) -> bool {
+    pub(crate) fn is_ty(&self) -> bool {
         match self {
             ParameterKind::Ty(_) => true,
-            ParameterKind::Lifetime(_) => false,
+            ParameterKind::Lifetime(_) | ParameterKind::Const(_) => false,
         }
     }
 
-    crate fn ty(self) -> Option<T> {
+    pub(crate) fn ty(self) -> Option<T> {
         match self {
             ParameterKind::Ty(t) => Some(t),
             _ => None,
         }
     }
 
-    crate fn lifetime(self) -> Option<L> {
+    pub(crate) fn lifetime(self) -> Option<L> {
         match self {
             ParameterKind::Lifetime(t) => Some(t),
             _ => None,
         }
     }
+
+    pub(crate) fn constant(self) -> Option<C> {
+        match self {
+            ParameterKind::Const(c) => Some(c),
+            _ => None,
+        }
+    }
 }
 
-impl<T, L> ast::Kinded for ParameterKind<T, L> {
+impl<T, L, C> ast::Kinded for ParameterKind<T, L, C> {
     fn kind(&self) -> ast::Kind {
         match *self {
             ParameterKind::Ty(_) => ast::Kind::Ty,
             ParameterKind::Lifetime(_) => ast::Kind::Lifetime,
+            ParameterKind::Const(_) => ast::Kind::Const,
         }
     }
 }
@@ -583,25 +1319,154 @@ pub trait ToParameter {
 impl<'a> ToParameter for (&'a ParameterKind<()>, usize) {
     fn to_parameter(&self) -> Parameter {
         let &(binder, index) = self;
-        match *binder {
-            ParameterKind::Lifetime(_) => ParameterKind::Lifetime(Lifetime::Var(index)),
-            ParameterKind::Ty(_) => ParameterKind::Ty(Ty::Var(index)),
+        map_kind!(*binder, |(), V| V::from_var(index))
+    }
+}
+
+pub(crate) type Parameter = ParameterKind<Ty, Lifetime, Const>;
+
+/// The values of one kind of parameter: `Ty`, `Lifetime` or `Const`.
+/// Operations that do the same thing for each kind of parameter are
+/// written once, generic over this trait, and dispatched on the kind
+/// with `map_kind!`.
+pub(crate) trait ParameterValue: Clone + Debug + Fold<Result = Self> + Zip {
+    /// Tags `data` with this kind.
+    fn kind<T>(data: T) -> ParameterKind<T>;
+
+    /// The variable with the given De Bruijn index.
+    fn from_var(depth: usize) -> Self;
+
+    /// If this value is a variable, its De Bruijn index.
+    fn var_depth(&self) -> Option<usize>;
+
+    /// The placeholder `placeholder`, as found when instantiating a
+    /// `forall` binder universally.
+    fn placeholder(placeholder: PlaceholderIndex) -> Self;
+
+    /// The value of `parameter`, if it is of this kind.
+    fn from_parameter(parameter: &Parameter) -> Option<&Self>;
+
+    fn into_parameter(self) -> Parameter;
+}
+
+impl ParameterValue for Ty {
+    fn kind<T>(data: T) -> ParameterKind<T> {
+        ParameterKind::Ty(data)
+    }
+
+    fn from_var(depth: usize) -> Self {
+        Ty::Var(depth)
+    }
+
+    fn var_depth(&self) -> Option<usize> {
+        match *self {
+            Ty::Var(depth) => Some(depth),
+            Ty::Apply(_) | Ty::Projection(_) | Ty::UnselectedProjection(_) | Ty::ForAll(_) => {
+                None
+            }
+        }
+    }
+
+    fn placeholder(placeholder: PlaceholderIndex) -> Self {
+        placeholder.to_ty()
+    }
+
+    fn from_parameter(parameter: &Parameter) -> Option<&Self> {
+        parameter.as_ref().ty()
+    }
+
+    fn into_parameter(self) -> Parameter {
+        ParameterKind::Ty(self)
+    }
+}
+
+impl ParameterValue for Lifetime {
+    fn kind<T>(data: T) -> ParameterKind<T> {
+        ParameterKind::Lifetime(data)
+    }
+
+    fn from_var(depth: usize) -> Self {
+        Lifetime::Var(depth)
+    }
+
+    fn var_depth(&self) -> Option<usize> {
+        match *self {
+            Lifetime::Var(depth) => Some(depth),
+            Lifetime::ForAll(_) | Lifetime::Static => None,
         }
     }
+
+    fn placeholder(placeholder: PlaceholderIndex) -> Self {
+        placeholder.to_lifetime()
+    }
+
+    fn from_parameter(parameter: &Parameter) -> Option<&Self> {
+        parameter.as_ref().lifetime()
+    }
+
+    fn into_parameter(self) -> Parameter {
+        ParameterKind::Lifetime(self)
+    }
 }
 
-crate type Parameter = ParameterKind<Ty, Lifetime>;
+impl ParameterValue for Const {
+    fn kind<T>(data: T) -> ParameterKind<T> {
+        ParameterKind::Const(data)
+    }
+
+    fn from_var(depth: usize) -> Self {
+        Const::Var(depth)
+    }
+
+    fn var_depth(&self) -> Option<usize> {
+        match *self {
+            Const::Var(depth) => Some(depth),
+            Const::ForAll(_) | Const::Value(_) | Const::Unevaluated(_) => None,
+        }
+    }
+
+    fn placeholder(placeholder: PlaceholderIndex) -> Self {
+        placeholder.to_const()
+    }
+
+    fn from_parameter(parameter: &Parameter) -> Option<&Self> {
+        parameter.as_ref().constant()
+    }
+
+    fn into_parameter(self) -> Parameter {
+        ParameterKind::Const(self)
+    }
+}
 
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct ProjectionTy {
-    crate associated_ty_id: ItemId,
-    crate parameters: Vec<Parameter>,
+    pub(crate) associated_ty_id: ItemId,
+    pub(crate) parameters: Vec<Parameter>,
+}
+
+impl ProjectionTy {
+    /// Creates a projection of the associated type `associated_ty_id`,
+    /// whose `parameters` are those of the associated type followed by
+    /// those of its trait, checking them against the kinds the
+    /// associated type declares.
+    pub(crate) fn new(
+        associated_ty_id: ItemId,
+        parameters: Vec<Parameter>,
+        program: &Program,
+    ) -> ::errors::Result<ProjectionTy> {
+        let projection = ProjectionTy {
+            associated_ty_id,
+            parameters,
+        };
+        program.check_projection_kinds(&projection)?;
+        Ok(projection)
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct UnselectedProjectionTy {
-    crate type_name: Identifier,
-    crate parameters: Vec<Parameter>,
+    pub(crate) type_name: Identifier,
+    pub(crate) parameters: Vec<Parameter>,
 }
 
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -610,16 +1475,16 @@ pub enum ProjectionTyEnum<S = ProjectionTy, U = UnselectedProjectionTy> {
     Unselected(U),
 }
 
-crate type ProjectionTyRefEnum<'a> = ProjectionTyEnum<&'a ProjectionTy, &'a UnselectedProjectionTy>;
+pub(crate) type ProjectionTyRefEnum<'a> = ProjectionTyEnum<&'a ProjectionTy, &'a UnselectedProjectionTy>;
 
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct TraitRef {
-    crate trait_id: ItemId,
-    crate parameters: Vec<Parameter>,
+    pub(crate) trait_id: ItemId,
+    pub(crate) parameters: Vec<Parameter>,
 }
 
 impl TraitRef {
-    crate fn type_parameters<'a>(&'a self) -> impl Iterator<Item=Ty> + 'a {
+    pub(crate) fn type_parameters<'a>(&'a self) -> impl Iterator<Item=Ty> + 'a {
         // This unwrap() is safe because is_ty ensures that we definitely have a Ty
         self.parameters.iter().filter(|p| p.is_ty()).map(|p| p.clone().ty().unwrap())
     }
@@ -632,14 +1497,14 @@ pub enum PolarizedTraitRef {
 }
 
 impl PolarizedTraitRef {
-    crate fn is_positive(&self) -> bool {
+    pub(crate) fn is_positive(&self) -> bool {
         match *self {
             PolarizedTraitRef::Positive(_) => true,
             PolarizedTraitRef::Negative(_) => false,
         }
     }
 
-    crate fn trait_ref(&self) -> &TraitRef {
+    pub(crate) fn trait_ref(&self) -> &TraitRef {
         match *self {
             PolarizedTraitRef::Positive(ref tr) | PolarizedTraitRef::Negative(ref tr) => tr,
         }
@@ -659,6 +1524,16 @@ pub struct Derefs {
     pub target: Ty,
 }
 
+/// A goal `Foo(P0, ..., Pn)` of an external predicate `Foo`, declared
+/// with `external predicate Foo(..);`. There are no program clauses
+/// for external predicates: their goals are answered by the embedder
+/// of the solver, through an `ExternalSolver`.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ExternalGoal {
+    pub predicate: ItemId,
+    pub parameters: Vec<Parameter>,
+}
+
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum WellFormed {
     /// A predicate which is true is some trait ref is well-formed.
@@ -788,6 +1663,10 @@ pub enum DomainGoal {
     ///
     /// This makes a new type `T` available and makes `DownstreamType(T)` provable for that type.
     DownstreamType(Ty),
+
+    /// A goal of an external predicate, answered by the embedder of the
+    /// solver (see `ExternalGoal`).
+    External(ExternalGoal),
 }
 
 pub type QuantifiedWhereClause = Binders<WhereClause>;
@@ -797,24 +1676,29 @@ impl WhereClause {
     /// * `Implemented(T: Trait)` maps to `WellFormed(T: Trait)`
     /// * `ProjectionEq(<T as Trait>::Item = Foo)` maps to `WellFormed(<T as Trait>::Item = Foo)`
     /// * any other clause maps to itself
-    crate fn into_well_formed_goal(self) -> DomainGoal {
+    pub(crate) fn into_well_formed_goal(self) -> DomainGoal {
         match self {
             WhereClause::Implemented(trait_ref) => WellFormed::Trait(trait_ref).cast(),
             wc => wc.cast(),
         }
     }
 
-    /// Same as `into_well_formed_goal` but with the `FromEnv` predicate instead of `WellFormed`.
-    crate fn into_from_env_goal(self) -> DomainGoal {
+    /// Same as `into_well_formed_goal` but with the `FromEnv` predicate instead of `WellFormed`,
+    /// except that `ProjectionEq(<T as Trait>::Item = Foo)` maps to
+    /// `Normalize(<T as Trait>::Item -> Foo)`: a bound on an associated type that we assume
+    /// acts as a normalization of the projection, which disables the fallback rule.
+    pub(crate) fn into_from_env_goal(self) -> DomainGoal {
         match self {
             WhereClause::Implemented(trait_ref) => FromEnv::Trait(trait_ref).cast(),
-            wc => wc.cast(),
+            WhereClause::ProjectionEq(ProjectionEq { projection, ty }) => {
+                Normalize { projection, ty }.cast()
+            }
         }
     }
 }
 
 impl DomainGoal {
-    crate fn into_from_env_goal(self) -> DomainGoal {
+    pub(crate) fn into_from_env_goal(self) -> DomainGoal {
         match self {
             DomainGoal::Holds(wc) => wc.into_from_env_goal(),
             goal => goal,
@@ -831,10 +1715,25 @@ pub enum LeafGoal {
     DomainGoal(DomainGoal),
 }
 
+/// A goal about the program itself rather than about the types in it
+/// (see `chalk_parse::ast::ReflectionGoal`). These are never given to
+/// the solver: they are answered by looking at the program directly
+/// (see `ReflectionGoal::solve`).
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum ReflectionGoal {
+    /// The program has an impl of the trait whose self type could be
+    /// the given type.
+    HasImpl(ItemId, Ty),
+
+    /// Exactly this many of the clauses of the program are about the
+    /// trait.
+    ClauseCount(ItemId, usize),
+}
+
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct EqGoal {
-    crate a: Parameter,
-    crate b: Parameter,
+    pub(crate) a: Parameter,
+    pub(crate) b: Parameter,
 }
 
 /// Proves that the given projection **normalizes** to the given
@@ -843,8 +1742,8 @@ pub struct EqGoal {
 /// `U = V`.
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Normalize {
-    crate projection: ProjectionTy,
-    crate ty: Ty,
+    pub(crate) projection: ProjectionTy,
+    pub(crate) ty: Ty,
 }
 
 /// Proves **equality** between a projection `T::Foo` and a type
@@ -852,8 +1751,8 @@ pub struct Normalize {
 /// prove that `T::Foo = V::Foo` if `T = V` without normalizing.
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct ProjectionEq {
-    crate projection: ProjectionTy,
-    crate ty: Ty,
+    pub(crate) projection: ProjectionTy,
+    pub(crate) ty: Ty,
 }
 
 /// Indicates that the trait where the associated type belongs to is
@@ -864,19 +1763,25 @@ pub struct ProjectionEq {
 /// `Vec<T>::Item` instead of `<Vec<T> as Iterator>::Item`. The
 /// corresponding `UnselectedNormalize` is `Vec<T>::Item -> T`.
 ///
-/// For each associated type we encounter in an `impl`, we generate
-/// rules to derive an `UnselectedNormalize` from a `Normalize`. For
-/// example, implementing `Iterator` for `Vec<T>` yields the rule:
+/// For each associated type declared in a trait, we generate a rule
+/// selecting that trait when it is in scope and implemented. For
+/// example, the `Item` of `Iterator` yields the rule:
 ///
 /// ```text
-/// Vec<T>::Item -> T :-
-///     InScope(Iterator),
-///     <Vec<T> as Iterator>::Item -> T
+/// forall<T, U> {
+///     T::Item -> U :-
+///         InScope(Iterator),
+///         T: Iterator,
+///         <T as Iterator>::Item = U
+/// }
 /// ```
+///
+/// If several traits in scope have an `Item` and are implemented, the
+/// goal is ambiguous.
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct UnselectedNormalize {
-    crate projection: UnselectedProjectionTy,
-    crate ty: Ty,
+    pub(crate) projection: UnselectedProjectionTy,
+    pub(crate) ty: Ty,
 }
 
 /// Indicates that the `value` is universally quantified over `N`
@@ -888,12 +1793,12 @@ pub struct UnselectedNormalize {
 /// of `self.binders`.)
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Binders<T> {
-    crate binders: Vec<ParameterKind<()>>,
-    crate value: T,
+    pub(crate) binders: Vec<ParameterKind<()>>,
+    pub(crate) value: T,
 }
 
 impl<T> Binders<T> {
-    crate fn map<U, OP>(self, op: OP) -> Binders<U> where OP: FnOnce(T) -> U {
+    pub(crate) fn map<U, OP>(self, op: OP) -> Binders<U> where OP: FnOnce(T) -> U {
         let value = op(self.value);
         Binders {
             binders: self.binders,
@@ -901,7 +1806,7 @@ impl<T> Binders<T> {
         }
     }
 
-    crate fn map_ref<U, OP>(&self, op: OP) -> Binders<U> where OP: FnOnce(&T) -> U {
+    pub(crate) fn map_ref<U, OP>(&self, op: OP) -> Binders<U> where OP: FnOnce(&T) -> U {
         let value = op(&self.value);
         Binders {
             binders: self.binders.clone(),
@@ -913,7 +1818,7 @@ impl<T> Binders<T> {
     /// the result of the operator function applied.
     ///
     /// forall<?0, ?1> will become forall<?0, ?1, ?2> where ?0 is the fresh variable
-    crate fn with_fresh_type_var<U, OP>(self, op: OP) -> Binders<U>
+    pub(crate) fn with_fresh_type_var<U, OP>(self, op: OP) -> Binders<U>
     where
         OP: FnOnce(<T as Fold>::Result, Ty) -> U,
         T: Shift
@@ -929,9 +1834,31 @@ impl<T> Binders<T> {
         }
     }
 
-    crate fn len(&self) -> usize {
+    pub(crate) fn len(&self) -> usize {
         self.binders.len()
     }
+
+    /// References to the parameters bound by these binders, as seen
+    /// from the value: `[?0, ?1, ...]`, each of the kind of its binder.
+    pub(crate) fn bound_parameters(&self) -> Vec<Parameter> {
+        self.binders.iter().zip(0..).map(|p| p.to_parameter()).collect()
+    }
+
+}
+
+impl<T: Clone> Binders<Binders<T>> {
+    /// Binds the value by a single list of binders: the inner binders,
+    /// which are the innermost, followed by the outer ones. The
+    /// variables of the value thus refer to the same parameters as
+    /// before: for example, the value of an associated type lies under
+    /// its own binders, within those of its impl (see
+    /// `AssociatedTyValue`).
+    pub(crate) fn fuse(&self) -> Binders<T> {
+        Binders {
+            binders: self.value.binders.iter().chain(&self.binders).cloned().collect(),
+            value: self.value.value.clone(),
+        }
+    }
 }
 
 /// Allows iterating over a Binders<Vec<T>>, for instance.
@@ -962,8 +1889,8 @@ impl<V: IntoIterator> Iterator for BindersIntoIterator<V> {
 /// conditions.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ProgramClauseImplication {
-    crate consequence: DomainGoal,
-    crate conditions: Vec<Goal>,
+    pub(crate) consequence: DomainGoal,
+    pub(crate) conditions: Vec<Goal>,
 }
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -973,7 +1900,15 @@ pub enum ProgramClause {
 }
 
 impl ProgramClause {
-    crate fn into_from_env_clause(self) -> ProgramClause {
+    /// The goal the clause proves, beneath its binders if it has any.
+    pub(crate) fn consequence(&self) -> &DomainGoal {
+        match self {
+            ProgramClause::Implies(implication) => &implication.consequence,
+            ProgramClause::ForAll(implication) => &implication.value.consequence,
+        }
+    }
+
+    pub(crate) fn into_from_env_clause(self) -> ProgramClause {
         match self {
             ProgramClause::Implies(implication) => {
                 if implication.conditions.is_empty() {
@@ -997,8 +1932,8 @@ impl ProgramClause {
 /// `binders` field.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Canonical<T> {
-    crate value: T,
-    crate binders: Vec<ParameterKind<UniverseIndex>>,
+    pub(crate) value: T,
+    pub(crate) binders: Vec<ParameterKind<UniverseIndex>>,
 }
 
 impl<T> Canonical<T> {
@@ -1009,7 +1944,7 @@ impl<T> Canonical<T> {
     /// inference context) are used in place of the quantified free
     /// variables. The result should be in terms of those same
     /// inference variables and will be re-canonicalized.
-    crate fn map<OP, U>(self, op: OP) -> Canonical<U::Result>
+    pub(crate) fn map<OP, U>(self, op: OP) -> Canonical<U::Result>
     where
         OP: FnOnce(T::Result) -> U,
         T: Fold,
@@ -1035,6 +1970,32 @@ impl<T> Canonical<T> {
     }
 }
 
+/// A goal in canonical form, as produced by `Goal::into_peeled_goal`,
+/// along with the names that the user gave to its variables.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeeledGoal {
+    pub goal: UCanonical<InEnvironment<Goal>>,
+
+    /// The name of each of the binders of `goal`, if it comes from an
+    /// `exists<>` binder of the goal the user wrote. Used to print
+    /// solutions with `Solution::display_with_names`.
+    pub names: Vec<Option<String>>,
+
+    /// The name of each of the placeholders of `goal`, from the
+    /// `forall<>` binder of the goal the user wrote that it comes from.
+    /// Used to print failure hints with `FailureHint::display_with_names`.
+    pub placeholder_names: BTreeMap<PlaceholderIndex, String>,
+}
+
+impl PeeledGoal {
+    /// The environment in which the goal is to be solved: the
+    /// hypotheses of the `if` goals that were peeled, like `T: Clone`
+    /// in `forall<T> { if (T: Clone) { ... } }`.
+    pub fn environment(&self) -> &Arc<Environment> {
+        &self.goal.canonical.value.environment
+    }
+}
+
 /// A "universe canonical" value. This is a wrapper around a
 /// `Canonical`, indicating that the universes within have been
 /// "renumbered" to start from 0 and collapse unimportant
@@ -1043,12 +2004,12 @@ impl<T> Canonical<T> {
 /// To produce one of these values, use the `u_canonicalize` method.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct UCanonical<T> {
-    crate canonical: Canonical<T>,
-    crate universes: usize,
+    pub(crate) canonical: Canonical<T>,
+    pub(crate) universes: usize,
 }
 
 impl<T> UCanonical<T> {
-    crate fn is_trivial_substitution(&self, canonical_subst: &Canonical<ConstrainedSubst>) -> bool {
+    pub(crate) fn is_trivial_substitution(&self, canonical_subst: &Canonical<ConstrainedSubst>) -> bool {
         let subst = &canonical_subst.value.subst;
         assert_eq!(self.canonical.binders.len(), subst.parameters.len());
         subst.is_identity_subst()
@@ -1060,9 +2021,21 @@ impl UCanonical<InEnvironment<Goal>> {
     /// form `WellFormed(T: Trait)` where `Trait` is any trait. The latter is needed for dealing
     /// with WF requirements and cyclic traits, which generates cycles in the proof tree which must
     /// not be rejected but instead must be treated as a success.
-    crate fn is_coinductive(&self, program: &ProgramEnvironment) -> bool {
+    pub(crate) fn is_coinductive(&self, program: &ProgramEnvironment) -> bool {
         self.canonical.value.goal.is_coinductive(program)
     }
+
+    /// True if the goal is to normalize a projection, either as a
+    /// `Normalize` or a `ProjectionEq` goal.
+    pub(crate) fn is_projection_goal(&self) -> bool {
+        match self.canonical.value.goal {
+            Goal::Leaf(LeafGoal::DomainGoal(DomainGoal::Normalize(_)))
+            | Goal::Leaf(LeafGoal::DomainGoal(DomainGoal::Holds(WhereClause::ProjectionEq(_)))) => {
+                true
+            }
+            _ => false,
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -1089,7 +2062,7 @@ pub enum Goal {
 }
 
 impl Goal {
-    crate fn quantify(
+    pub(crate) fn quantify(
         self,
         kind: QuantifierKind,
         binders: Vec<ParameterKind<()>>,
@@ -1104,12 +2077,12 @@ impl Goal {
     }
 
     /// Takes a goal `G` and turns it into `not { G }`
-    crate fn negate(self) -> Self {
+    pub(crate) fn negate(self) -> Self {
         Goal::Not(Box::new(self))
     }
 
     /// Takes a goal `G` and turns it into `compatible { G }`
-    crate fn compatible(self) -> Self {
+    pub(crate) fn compatible(self) -> Self {
         // compatible { G } desugars into: forall<T> { if (Compatible, DownstreamType(T)) { G } }
         // This activates the compatible modality rules and introduces an anonymous downstream type
         Goal::Quantified(
@@ -1129,7 +2102,7 @@ impl Goal {
         )
     }
 
-    crate fn implied_by(self, predicates: Vec<ProgramClause>) -> Goal {
+    pub(crate) fn implied_by(self, predicates: Vec<ProgramClause>) -> Goal {
         Goal::Implies(predicates, Box::new(self))
     }
 
@@ -1140,25 +2113,75 @@ impl Goal {
     /// does not -- at present -- contain any variables. Useful for
     /// REPLs and tests but not much else.
     pub fn into_peeled_goal(self) -> UCanonical<InEnvironment<Goal>> {
+        self.into_named_peeled_goal(vec![], vec![], vec![]).goal
+    }
+
+    /// Like `into_peeled_goal`, but also records which canonical
+    /// variable each of the peeled `exists<>` binders became, and which
+    /// placeholder each of the peeled `forall<>` binders became.
+    /// `existential_names` and `universal_names` are the names of those
+    /// binders, in the order in which they are peeled.
+    ///
+    /// `universes` may give, for each of the quantifiers that are
+    /// peeled in order, the universe its binders are put in instead of
+    /// the usual one. The universe of a `forall` must be later than
+    /// all of the universes before it (the universes in between are
+    /// created empty), and that of an `exists` must already exist.
+    pub(crate) fn into_named_peeled_goal(
+        self,
+        existential_names: Vec<String>,
+        universal_names: Vec<String>,
+        universes: Vec<Option<UniverseIndex>>,
+    ) -> PeeledGoal {
         use solve::infer::InferenceTable;
         let mut infer = InferenceTable::new();
+        let mut existentials = vec![];
+        let mut placeholders = vec![];
+        let mut universal_names = universal_names.into_iter();
+        let mut universes = universes.into_iter();
         let peeled_goal = {
             let mut env_goal = InEnvironment::new(&Environment::new(), self);
             loop {
                 let InEnvironment { environment, goal } = env_goal;
                 match goal {
                     Goal::Quantified(QuantifierKind::ForAll, subgoal) => {
+                        if let Some(Some(universe)) = universes.next() {
+                            assert!(infer.max_universe() < universe);
+                            while infer.max_universe().counter + 1 < universe.counter {
+                                infer.new_universe();
+                            }
+                        }
+                        let num_binders = subgoal.binders.len();
                         let subgoal = infer.instantiate_binders_universally(&subgoal);
+                        let ui = infer.max_universe();
+                        placeholders.extend(
+                            (0..num_binders)
+                                .map(|idx| PlaceholderIndex { ui, idx })
+                                .zip(universal_names.by_ref()),
+                        );
                         env_goal = InEnvironment::new(&environment, *subgoal);
                     }
 
                     Goal::Quantified(QuantifierKind::Exists, subgoal) => {
-                        let subgoal = infer.instantiate_binders_existentially(&subgoal);
+                        let universe = match universes.next() {
+                            Some(Some(universe)) => {
+                                assert!(universe <= infer.max_universe());
+                                universe
+                            }
+                            _ => infer.max_universe(),
+                        };
+                        let binders: Vec<_> = subgoal.binders
+                            .iter()
+                            .map(|pk| pk.map(|()| universe))
+                            .collect();
+                        let vars = infer.fresh_subst(&binders).parameters;
+                        let subgoal = Subst::apply(&vars, &subgoal.value);
+                        existentials.extend(vars);
                         env_goal = InEnvironment::new(&environment, *subgoal);
                     }
 
                     Goal::Implies(wc, subgoal) => {
-                        let new_environment = &environment.add_clauses(wc);
+                        let new_environment = &Environment::add_clauses(&environment, wc);
                         env_goal = InEnvironment::new(&new_environment, *subgoal);
                     }
 
@@ -1166,8 +2189,31 @@ impl Goal {
                 }
             }
         };
-        let canonical = infer.canonicalize(&peeled_goal).quantified;
-        infer.u_canonicalize(&canonical).quantified
+        let canonicalized = infer.canonicalize(&peeled_goal);
+
+        let mut names = vec![None; canonicalized.quantified.binders.len()];
+        for (var, name) in existentials.iter().zip(existential_names) {
+            if let Some(index) = canonicalized.canonical_index_of(var) {
+                names[index] = Some(name);
+            }
+        }
+
+        // The placeholders whose universe does not appear in the goal
+        // do not appear in it either.
+        let u_canonicalized = infer.u_canonicalize(&canonicalized.quantified);
+        let placeholder_names = placeholders
+            .into_iter()
+            .filter_map(|(PlaceholderIndex { ui, idx }, name)| {
+                let ui = u_canonicalized.universes.canonical_universe_of(ui)?;
+                Some((PlaceholderIndex { ui, idx }, name))
+            })
+            .collect();
+
+        PeeledGoal {
+            goal: u_canonicalized.quantified,
+            names,
+            placeholder_names,
+        }
     }
 
     /// Given a goal with no free variables (a "closed" goal), creates
@@ -1179,7 +2225,7 @@ impl Goal {
     /// # Panics
     ///
     /// Will panic if this goal does in fact contain free variables.
-    crate fn into_closed_goal(self) -> UCanonical<InEnvironment<Goal>> {
+    pub(crate) fn into_closed_goal(self) -> UCanonical<InEnvironment<Goal>> {
         use solve::infer::InferenceTable;
         let mut infer = InferenceTable::new();
         let env_goal = InEnvironment::new(&Environment::new(), self);
@@ -1187,7 +2233,7 @@ impl Goal {
         infer.u_canonicalize(&canonical_goal).quantified
     }
 
-    crate fn is_coinductive(&self, program: &ProgramEnvironment) -> bool {
+    pub(crate) fn is_coinductive(&self, program: &ProgramEnvironment) -> bool {
         match self {
             Goal::Leaf(LeafGoal::DomainGoal(DomainGoal::Holds(wca))) => {
                 match wca {
@@ -1222,6 +2268,153 @@ pub enum QuantifierKind {
 #[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Constraint {
     LifetimeEq(Lifetime, Lifetime),
+
+    /// `T: 'a`: every lifetime in the type outlives the lifetime.
+    TypeOutlives(Ty, Lifetime),
+
+    /// `'a: 'b`: the first lifetime outlives the second.
+    LifetimeOutlives(Lifetime, Lifetime),
+}
+
+impl Constraint {
+    /// Breaks an outlives constraint down into the constraints it
+    /// amounts to. A type outlives a lifetime if all of its lifetime
+    /// and type parameters do, so `Ref<'a, Vec<T>>: 'b` becomes `'a:
+    /// 'b, T: 'b`, and a type without any, like `Foo`, outlives every
+    /// lifetime -- `'static` included. The lifetimes bound by a
+    /// higher-ranked type impose nothing. Type variables, placeholders
+    /// and projections cannot be broken down; the constraint on a type
+    /// containing one under the binders of a higher-ranked type is left
+    /// as it is, as are equalities. `'static: 'a` and `'a: 'a` always
+    /// hold, and yield no constraint at all.
+    pub(crate) fn decompose(self) -> Vec<Constraint> {
+        let mut constraints = vec![];
+        match self {
+            Constraint::TypeOutlives(ty, lifetime) => {
+                if !decompose_type_outlives(&ty, lifetime, 0, &mut constraints) {
+                    return vec![Constraint::TypeOutlives(ty, lifetime)];
+                }
+            }
+            Constraint::LifetimeOutlives(a, b) => push_lifetime_outlives(a, b, &mut constraints),
+            Constraint::LifetimeEq(..) => constraints.push(self),
+        }
+        constraints
+    }
+}
+
+/// Pushes onto `constraints` those that `ty: lifetime` amounts to,
+/// where `ty` is found under `binders` binders which `lifetime` is not.
+/// Returns false if some part of `ty` which cannot be broken down
+/// refers to those binders.
+fn decompose_type_outlives(
+    ty: &Ty,
+    lifetime: Lifetime,
+    binders: usize,
+    constraints: &mut Vec<Constraint>,
+) -> bool {
+    match ty {
+        Ty::Apply(ApplicationTy { name: TypeName::ItemId(_), parameters })
+        | Ty::Apply(ApplicationTy { name: TypeName::Synthetic { .. }, parameters }) => {
+            parameters.iter().all(|parameter| match parameter {
+                ParameterKind::Ty(ty) => {
+                    decompose_type_outlives(ty, lifetime, binders, constraints)
+                }
+                ParameterKind::Lifetime(a) => {
+                    if let Ok(a) = a.down_shift(binders) {
+                        push_lifetime_outlives(a, lifetime, constraints);
+                    }
+                    true
+                }
+                ParameterKind::Const(_) => true,
+            })
+        }
+        Ty::ForAll(quantified) => decompose_type_outlives(
+            &quantified.ty,
+            lifetime,
+            binders + quantified.num_binders,
+            constraints,
+        ),
+        Ty::Var(_)
+        | Ty::Apply(ApplicationTy { name: TypeName::ForAll(_), .. })
+        | Ty::Apply(ApplicationTy { name: TypeName::AssociatedType(_), .. })
+        | Ty::Projection(_)
+        | Ty::UnselectedProjection(_) => match ty.down_shift(binders) {
+            Ok(ty) => {
+                constraints.push(Constraint::TypeOutlives(ty, lifetime));
+                true
+            }
+            Err(NoSolution) => false,
+        },
+    }
+}
+
+/// Pushes `a: b` onto `constraints`, unless it always holds.
+fn push_lifetime_outlives(a: Lifetime, b: Lifetime, constraints: &mut Vec<Constraint>) {
+    if a != Lifetime::Static && a != b {
+        constraints.push(Constraint::LifetimeOutlives(a, b));
+    }
+}
+
+/// A constraint on lifetimes, in the environment where it must hold,
+/// along with where it came from.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RegionConstraint {
+    pub(crate) constraint: InEnvironment<Constraint>,
+    pub(crate) provenance: Provenance,
+}
+
+impl RegionConstraint {
+    /// `constraint`, of unknown provenance.
+    pub(crate) fn new(constraint: InEnvironment<Constraint>) -> Self {
+        RegionConstraint {
+            constraint,
+            provenance: Provenance::default(),
+        }
+    }
+}
+
+/// Where a region constraint came from: the goal of the table whose
+/// answers first carried it, which is the goal whose proof imposed it.
+/// Only tracked if the solver is asked to (see `SolverChoice::SLG`).
+///
+/// The provenance of a constraint is left out when comparing or
+/// hashing it, so that answers which impose the same constraints are
+/// still seen as duplicates. Nor is the goal folded along with the
+/// constraint: it stays as it was when the constraint was tagged.
+#[derive(Clone, Debug, Default)]
+pub struct Provenance {
+    pub(crate) goal: Option<Arc<Canonical<InEnvironment<Goal>>>>,
+}
+
+impl Provenance {
+    /// The goal that imposed the constraint, if known.
+    pub fn goal(&self) -> Option<&Canonical<InEnvironment<Goal>>> {
+        self.goal.as_ref().map(|goal| &**goal)
+    }
+}
+
+impl PartialEq for Provenance {
+    fn eq(&self, _: &Provenance) -> bool {
+        true
+    }
+}
+
+impl Eq for Provenance {}
+
+impl PartialOrd for Provenance {
+    fn partial_cmp(&self, other: &Provenance) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Provenance {
+    fn cmp(&self, _: &Provenance) -> Ordering {
+        Ordering::Equal
+    }
+}
+
+impl Hash for Provenance {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
 }
 
 /// A mapping of inference variables to instantiations thereof.
@@ -1234,11 +2427,16 @@ pub struct Substitution {
     /// This is a map because the substitution is not necessarily
     /// complete. We use a btree map to ensure that the result is in a
     /// deterministic order.
-    crate parameters: Vec<Parameter>,
+    pub(crate) parameters: SubstParameters,
 }
 
+/// The parameters of a `Substitution`. Substitutions are cloned all the
+/// time while solving, but rarely have more than a few parameters, so
+/// we store those inline rather than on the heap.
+pub(crate) type SubstParameters = SmallVec<[Parameter; 4]>;
+
 impl Substitution {
-    crate fn is_empty(&self) -> bool {
+    pub(crate) fn is_empty(&self) -> bool {
         self.parameters.is_empty()
     }
 
@@ -1252,13 +2450,14 @@ impl Substitution {
     /// ...
     /// ```
     ///
-    /// Basically, each value is mapped to a type or lifetime with its
+    /// Basically, each value is mapped to a type, lifetime or const with its
     /// same index.
-    crate fn is_identity_subst(&self) -> bool {
+    pub(crate) fn is_identity_subst(&self) -> bool {
         self.parameters.iter().zip(0..).all(|(parameter, index)| {
             match parameter {
                 ParameterKind::Ty(Ty::Var(depth)) => index == *depth,
                 ParameterKind::Lifetime(Lifetime::Var(depth)) => index == *depth,
+                ParameterKind::Const(Const::Var(depth)) => index == *depth,
                 _ => false,
             }
         })
@@ -1268,27 +2467,196 @@ impl Substitution {
 impl<'a> DefaultTypeFolder for &'a Substitution {}
 
 impl<'a> ExistentialFolder for &'a Substitution {
-    fn fold_free_existential_ty(&mut self, depth: usize, binders: usize) -> Fallible<Ty> {
-        let ty = &self.parameters[depth];
+    fn fold_free_existential_ty(&mut self, depth: DebruijnIndex, binders: usize) -> Fallible<Ty> {
+        let ty = &self.parameters[depth.depth()];
         let ty = ty.assert_ty_ref();
         Ok(ty.up_shift(binders))
     }
 
     fn fold_free_existential_lifetime(
         &mut self,
-        depth: usize,
+        depth: DebruijnIndex,
         binders: usize,
     ) -> Fallible<Lifetime> {
-        let l = &self.parameters[depth];
+        let l = &self.parameters[depth.depth()];
         let l = l.assert_lifetime_ref();
         Ok(l.up_shift(binders))
     }
+
+    fn fold_free_existential_const(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Const> {
+        let c = &self.parameters[depth.depth()];
+        let c = c.assert_const_ref();
+        Ok(c.up_shift(binders))
+    }
 }
 
 impl<'a> IdentityUniversalFolder for &'a Substitution {}
 
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// Where a clause that proves a goal comes from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClauseSource {
+    /// A hypothesis of the environment, such as a where clause.
+    WhereClause(ProgramClause),
+
+    /// A clause of the impl with the given id, whose trait reference is
+    /// given as it applies to the goal.
+    Impl(ItemId, TraitRef),
+
+    /// Any other clause of the program.
+    Program(ProgramClause),
+}
+
+/// The clauses an answer was proven with, in the order the solver used
+/// them: the clause chosen for the goal of the answer, followed by the
+/// sources of the answers to its conditions, in the order they were
+/// incorporated. Each is in canonical form, as it was when the clause
+/// was chosen. Only recorded if the solver is asked to (see
+/// `SolverChoice::ambiguity_sources`).
+///
+/// Like the provenance of a constraint, the sources of an answer are
+/// left out when comparing or hashing it, so that answers proven with
+/// different clauses are still seen as duplicates.
+#[derive(Clone, Debug, Default)]
+pub struct ClauseSources {
+    pub(crate) sources: Vec<Canonical<ClauseSource>>,
+}
+
+impl PartialEq for ClauseSources {
+    fn eq(&self, _: &ClauseSources) -> bool {
+        true
+    }
+}
+
+impl Eq for ClauseSources {}
+
+impl PartialOrd for ClauseSources {
+    fn partial_cmp(&self, other: &ClauseSources) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ClauseSources {
+    fn cmp(&self, _: &ClauseSources) -> Ordering {
+        Ordering::Equal
+    }
+}
+
+impl Hash for ClauseSources {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ConstrainedSubst {
-    crate subst: Substitution,
-    crate constraints: Vec<InEnvironment<Constraint>>,
+    pub(crate) subst: Substitution,
+    pub(crate) constraints: Vec<RegionConstraint>,
+    pub(crate) sources: ClauseSources,
+}
+
+impl ConstrainedSubst {
+    /// Normalizes the constraints, so that two substitutions that
+    /// impose the same set of constraints compare equal, whatever order
+    /// the constraints were found in and however they were stated.
+    ///
+    /// The lifetimes equated in each environment are gathered into
+    /// classes, and the `LifetimeEq` constraints are replaced by one
+    /// equating each member of a class with its least member: so
+    /// `'b == 'a` becomes `'a == 'b`, and `'a == 'b, 'b == 'c, 'c ==
+    /// 'a` becomes `'a == 'b, 'a == 'c`. Variables are less than
+    /// placeholders, so the variable comes first when one is equated
+    /// with a placeholder. The constraints are then sorted, and
+    /// duplicates removed.
+    ///
+    /// The equality of a lifetime with the least member of its class
+    /// keeps the provenance of the first constraint that mentioned the
+    /// lifetime. Outlives constraints are broken down into the
+    /// constraints they amount to (see `Constraint::decompose`).
+    pub(crate) fn normalize_constraints(&mut self) {
+        let mut classes: BTreeMap<Arc<Environment>, LifetimeClasses> = BTreeMap::new();
+        let mut provenances: BTreeMap<(Arc<Environment>, Lifetime), Provenance> = BTreeMap::new();
+        let mut constraints = Vec::with_capacity(self.constraints.len());
+        for RegionConstraint { constraint, provenance } in self.constraints.drain(..) {
+            match constraint.goal {
+                Constraint::LifetimeEq(a, b) => {
+                    for &lifetime in &[a, b] {
+                        provenances
+                            .entry((constraint.environment.clone(), lifetime))
+                            .or_insert_with(|| provenance.clone());
+                    }
+                    classes.entry(constraint.environment).or_default().union(a, b);
+                }
+
+                Constraint::TypeOutlives(..) | Constraint::LifetimeOutlives(..) => {
+                    let InEnvironment { environment, goal } = constraint;
+                    constraints.extend(goal.decompose().into_iter().map(|goal| RegionConstraint {
+                        constraint: InEnvironment::new(&environment, goal),
+                        provenance: provenance.clone(),
+                    }));
+                }
+            }
+        }
+        for (environment, classes) in classes {
+            constraints.extend(classes.equalities().map(|(a, b)| RegionConstraint {
+                constraint: InEnvironment::new(&environment, Constraint::LifetimeEq(a, b)),
+                provenance: provenances[&(environment.clone(), b)].clone(),
+            }));
+        }
+        constraints.sort();
+        constraints.dedup();
+        self.constraints = constraints;
+    }
+}
+
+/// Lifetimes gathered into classes of lifetimes that are equal to one
+/// another (a union-find structure). The root of each class is its
+/// least member.
+#[derive(Default)]
+struct LifetimeClasses {
+    parents: BTreeMap<Lifetime, Lifetime>,
+}
+
+impl LifetimeClasses {
+    fn find(&self, mut lifetime: Lifetime) -> Lifetime {
+        while let Some(&parent) = self.parents.get(&lifetime) {
+            if parent == lifetime {
+                break;
+            }
+            lifetime = parent;
+        }
+        lifetime
+    }
+
+    fn union(&mut self, a: Lifetime, b: Lifetime) {
+        let (a, b) = (self.find(a), self.find(b));
+        let (root, other) = if a <= b { (a, b) } else { (b, a) };
+        self.parents.insert(root, root);
+        self.parents.insert(other, root);
+    }
+
+    /// For each lifetime that is not the least of its class, the
+    /// least of its class and the lifetime.
+    fn equalities<'c>(&'c self) -> impl Iterator<Item = (Lifetime, Lifetime)> + 'c {
+        self.parents.keys().filter_map(move |&lifetime| {
+            let root = self.find(lifetime);
+            if root == lifetime {
+                None
+            } else {
+                Some((root, lifetime))
+            }
+        })
+    }
+}
+
+/// The substitution and region constraints of a unique `Solution`. Unlike
+/// in a `ConstrainedSubst`, the constraints are under binders: these stand
+/// for the names introduced by `forall` goals nested inside the root goal,
+/// which the caller cannot otherwise refer to (see
+/// `ConstrainedSubst::into_solution_subst`).
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SolutionSubst {
+    pub(crate) subst: Substitution,
+    pub(crate) constraints: Binders<Vec<RegionConstraint>>,
 }
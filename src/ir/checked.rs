@@ -0,0 +1,200 @@
+//! Checked constructors for values under binders. Building a goal by
+//! hand, it is easy to refer to a variable that no binder binds, or to
+//! use a variable as a type where its binder binds a lifetime; the
+//! mistake then only shows up as a panic when the goal is instantiated
+//! or canonicalized. These constructors check every reference to a
+//! variable against the binders in scope up front.
+
+use std::fmt;
+use fold::{DefaultTypeFolder, ExistentialFolder, Fold, IdentityUniversalFolder};
+use ir::*;
+
+/// Why a value could not be put under binders.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GoalBuildError {
+    /// A variable refers past all of the binders in scope. `depth` is
+    /// its index as seen from outside of the value being built.
+    UnboundVariable { depth: usize },
+
+    /// A variable is used as a `usage` (a type, say), but its binder
+    /// binds a `binder` (a lifetime, say).
+    KindMismatch {
+        binder: ParameterKind<()>,
+        usage: ParameterKind<()>,
+    },
+}
+
+impl fmt::Display for GoalBuildError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GoalBuildError::UnboundVariable { depth } => {
+                write!(fmt, "variable ^{} is not bound by any binder", depth)
+            }
+            GoalBuildError::KindMismatch { binder, usage } => write!(
+                fmt,
+                "variable bound as a {} is used as a {}",
+                kind_name(*binder),
+                kind_name(*usage)
+            ),
+        }
+    }
+}
+
+fn kind_name(kind: ParameterKind<()>) -> &'static str {
+    match kind {
+        ParameterKind::Ty(()) => "type",
+        ParameterKind::Lifetime(()) => "lifetime",
+        ParameterKind::Const(()) => "const",
+    }
+}
+
+impl Goal {
+    /// `kind<binders> { body }`, where `body` may only refer to the
+    /// variables of `binders` (and of the quantifiers within it), each
+    /// as the kind of its binder.
+    pub fn quantify_checked(
+        kind: QuantifierKind,
+        binders: Vec<ParameterKind<()>>,
+        body: Goal,
+    ) -> Result<Goal, GoalBuildError> {
+        let goal = body.quantify(kind, binders);
+        goal.check_variables()?;
+        Ok(goal)
+    }
+
+    /// Checks that this goal only refers to the variables bound by its
+    /// own quantifiers, each as the kind of its binder.
+    pub fn check_variables(&self) -> Result<(), GoalBuildError> {
+        self.check_variables_in(&mut Scope { binders: vec![] })
+    }
+
+    fn check_variables_in(&self, scope: &mut Scope) -> Result<(), GoalBuildError> {
+        match self {
+            Goal::Quantified(_, subgoal) => {
+                scope.binders.push(subgoal.binders.clone());
+                let result = subgoal.value.check_variables_in(scope);
+                scope.binders.pop();
+                result
+            }
+            Goal::Implies(clauses, subgoal) => {
+                scope.check(clauses)?;
+                subgoal.check_variables_in(scope)
+            }
+            Goal::And(left, right) => {
+                left.check_variables_in(scope)?;
+                right.check_variables_in(scope)
+            }
+            Goal::Not(subgoal) => subgoal.check_variables_in(scope),
+            Goal::Leaf(leaf) => scope.check(leaf),
+            Goal::CannotProve(()) => Ok(()),
+        }
+    }
+}
+
+impl<T: Fold> Binders<T> {
+    /// `value` under `binders`, where `value` may only refer to the
+    /// variables of `binders`, each as the kind of its binder. The
+    /// kinds of the variables bound within `value` itself are not
+    /// checked.
+    pub fn checked(binders: Vec<ParameterKind<()>>, value: T) -> Result<Self, GoalBuildError> {
+        Scope { binders: vec![binders.clone()] }.check(&value)?;
+        Ok(Binders { binders, value })
+    }
+}
+
+impl<T: Fold> Canonical<T> {
+    /// Like `Binders::checked`, for a canonical value: `value` may
+    /// only refer to the variables of `binders`.
+    pub fn checked(
+        binders: Vec<ParameterKind<UniverseIndex>>,
+        value: T,
+    ) -> Result<Self, GoalBuildError> {
+        let kinds = binders.iter().map(|binder| binder.map(|_| ())).collect();
+        Scope { binders: vec![kinds] }.check(&value)?;
+        Ok(Canonical { value, binders })
+    }
+}
+
+/// The binders in scope, outermost first.
+struct Scope {
+    binders: Vec<Vec<ParameterKind<()>>>,
+}
+
+impl Scope {
+    /// Checks the variables that `value` refers to.
+    fn check<T: Fold>(&self, value: &T) -> Result<(), GoalBuildError> {
+        let mut checker = Checker { scope: self, error: None };
+        match value.fold_with(&mut checker, 0) {
+            Ok(_) => Ok(()),
+            Err(NoSolution) => Err(checker.error.expect("the checker only fails with an error")),
+        }
+    }
+
+    /// Checks that the variable at `depth` is bound, as `usage`.
+    fn check_variable(
+        &self,
+        depth: DebruijnIndex,
+        usage: ParameterKind<()>,
+    ) -> Result<(), GoalBuildError> {
+        let mut depth = depth;
+        for binders in self.binders.iter().rev() {
+            match depth.shifted_out(binders.len()) {
+                Some(outer) => depth = outer,
+                None => {
+                    let binder = binders[depth.depth()];
+                    if binder == usage {
+                        return Ok(());
+                    }
+                    return Err(GoalBuildError::KindMismatch { binder, usage });
+                }
+            }
+        }
+        Err(GoalBuildError::UnboundVariable { depth: depth.depth() })
+    }
+}
+
+struct Checker<'s> {
+    scope: &'s Scope,
+
+    /// Set when a variable fails the check; the fold then fails with
+    /// `NoSolution`, to unwind.
+    error: Option<GoalBuildError>,
+}
+
+impl<'s> Checker<'s> {
+    fn check_variable(&mut self, depth: DebruijnIndex, usage: ParameterKind<()>) -> Fallible<()> {
+        self.scope.check_variable(depth, usage).map_err(|error| {
+            self.error = Some(error);
+            NoSolution
+        })
+    }
+}
+
+impl<'s> DefaultTypeFolder for Checker<'s> {}
+
+impl<'s> IdentityUniversalFolder for Checker<'s> {}
+
+impl<'s> ExistentialFolder for Checker<'s> {
+    fn fold_free_existential_ty(&mut self, depth: DebruijnIndex, binders: usize) -> Fallible<Ty> {
+        self.check_variable(depth, ParameterKind::Ty(()))?;
+        Ok(Ty::Var(depth.shifted_in(binders).depth()))
+    }
+
+    fn fold_free_existential_lifetime(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Lifetime> {
+        self.check_variable(depth, ParameterKind::Lifetime(()))?;
+        Ok(Lifetime::Var(depth.shifted_in(binders).depth()))
+    }
+
+    fn fold_free_existential_const(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Const> {
+        self.check_variable(depth, ParameterKind::Const(()))?;
+        Ok(Const::Var(depth.shifted_in(binders).depth()))
+    }
+}
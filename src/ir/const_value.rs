@@ -0,0 +1,211 @@
+//! The values of consts, like the `3` in `Foo<3>`. Each value is tagged
+//! with its type -- a `bool` or an integer type of up to 128 bits -- so
+//! that values of different types are never equal, and arithmetic on
+//! them is checked for overflow in that type.
+
+use std::fmt;
+
+/// The type of a const value.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ConstTy {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    Usize,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    Isize,
+}
+
+impl ConstTy {
+    /// The type named `name`, like `u8`.
+    pub fn from_name(name: &str) -> Option<ConstTy> {
+        Some(match name {
+            "bool" => ConstTy::Bool,
+            "u8" => ConstTy::U8,
+            "u16" => ConstTy::U16,
+            "u32" => ConstTy::U32,
+            "u64" => ConstTy::U64,
+            "u128" => ConstTy::U128,
+            "usize" => ConstTy::Usize,
+            "i8" => ConstTy::I8,
+            "i16" => ConstTy::I16,
+            "i32" => ConstTy::I32,
+            "i64" => ConstTy::I64,
+            "i128" => ConstTy::I128,
+            "isize" => ConstTy::Isize,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ConstTy::Bool => "bool",
+            ConstTy::U8 => "u8",
+            ConstTy::U16 => "u16",
+            ConstTy::U32 => "u32",
+            ConstTy::U64 => "u64",
+            ConstTy::U128 => "u128",
+            ConstTy::Usize => "usize",
+            ConstTy::I8 => "i8",
+            ConstTy::I16 => "i16",
+            ConstTy::I32 => "i32",
+            ConstTy::I64 => "i64",
+            ConstTy::I128 => "i128",
+            ConstTy::Isize => "isize",
+        }
+    }
+
+    /// The number of bits of an integer type, or `None` for `bool`.
+    /// Pointer-sized integers have 64 bits.
+    pub fn bits(self) -> Option<u32> {
+        match self {
+            ConstTy::Bool => None,
+            ConstTy::U8 | ConstTy::I8 => Some(8),
+            ConstTy::U16 | ConstTy::I16 => Some(16),
+            ConstTy::U32 | ConstTy::I32 => Some(32),
+            ConstTy::U64 | ConstTy::Usize | ConstTy::I64 | ConstTy::Isize => Some(64),
+            ConstTy::U128 | ConstTy::I128 => Some(128),
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        match self {
+            ConstTy::I8
+            | ConstTy::I16
+            | ConstTy::I32
+            | ConstTy::I64
+            | ConstTy::I128
+            | ConstTy::Isize => true,
+            ConstTy::Bool
+            | ConstTy::U8
+            | ConstTy::U16
+            | ConstTy::U32
+            | ConstTy::U64
+            | ConstTy::U128
+            | ConstTy::Usize => false,
+        }
+    }
+}
+
+impl fmt::Display for ConstTy {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(self.name())
+    }
+}
+
+/// A const value of type `ty`. The value of a signed integer is kept
+/// sign-extended to 128 bits, so that `bits as i128` is the value;
+/// `false` and `true` are `0` and `1`.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConstValue {
+    pub(crate) ty: ConstTy,
+    pub(crate) bits: u128,
+}
+
+impl ConstValue {
+    pub fn bool(value: bool) -> ConstValue {
+        ConstValue {
+            ty: ConstTy::Bool,
+            bits: value as u128,
+        }
+    }
+
+    /// The `usize` value `value`, the type of integer literals which
+    /// are not given one.
+    pub fn usize(value: u64) -> ConstValue {
+        ConstValue {
+            ty: ConstTy::Usize,
+            bits: u128::from(value),
+        }
+    }
+
+    /// The value `value` of the integer type `ty`, if it is in range.
+    pub fn from_u128(ty: ConstTy, value: u128) -> Option<ConstValue> {
+        let bits = ty.bits()?;
+        let max = if ty.is_signed() {
+            (1u128 << (bits - 1)) - 1
+        } else if bits == 128 {
+            u128::max_value()
+        } else {
+            (1u128 << bits) - 1
+        };
+        if value > max {
+            return None;
+        }
+        Some(ConstValue { ty, bits: value })
+    }
+
+    /// The value `value` of the integer type `ty`, if it is in range.
+    pub fn from_i128(ty: ConstTy, value: i128) -> Option<ConstValue> {
+        if value >= 0 {
+            return ConstValue::from_u128(ty, value as u128);
+        }
+        let bits = ty.bits()?;
+        if !ty.is_signed() || (bits < 128 && value < -(1i128 << (bits - 1))) {
+            return None;
+        }
+        Some(ConstValue {
+            ty,
+            bits: value as u128,
+        })
+    }
+
+    pub fn ty(self) -> ConstTy {
+        self.ty
+    }
+
+    /// `self + rhs`, or `None` if it overflows. Both values must be
+    /// integers of the same type.
+    pub fn checked_add(self, rhs: ConstValue) -> Option<ConstValue> {
+        self.checked_binary(rhs, i128::checked_add, u128::checked_add)
+    }
+
+    /// `self - rhs`, or `None` if it overflows (see `checked_add`).
+    pub fn checked_sub(self, rhs: ConstValue) -> Option<ConstValue> {
+        self.checked_binary(rhs, i128::checked_sub, u128::checked_sub)
+    }
+
+    /// `self * rhs`, or `None` if it overflows (see `checked_add`).
+    pub fn checked_mul(self, rhs: ConstValue) -> Option<ConstValue> {
+        self.checked_binary(rhs, i128::checked_mul, u128::checked_mul)
+    }
+
+    /// Applies `signed_op` or `unsigned_op` to the values, depending on
+    /// their type, checking that the result is in range of the type.
+    fn checked_binary(
+        self,
+        rhs: ConstValue,
+        signed_op: fn(i128, i128) -> Option<i128>,
+        unsigned_op: fn(u128, u128) -> Option<u128>,
+    ) -> Option<ConstValue> {
+        assert_eq!(self.ty, rhs.ty, "arithmetic on consts of different types");
+        assert!(self.ty != ConstTy::Bool, "arithmetic on `bool` consts");
+        if self.ty.is_signed() {
+            ConstValue::from_i128(self.ty, signed_op(self.bits as i128, rhs.bits as i128)?)
+        } else {
+            ConstValue::from_u128(self.ty, unsigned_op(self.bits, rhs.bits)?)
+        }
+    }
+}
+
+impl fmt::Display for ConstValue {
+    /// Integers are followed by their type, unless it is the one their
+    /// literal would have without it: `usize`, or `isize` for negative
+    /// integers.
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.ty {
+            ConstTy::Bool => write!(fmt, "{}", self.bits != 0),
+            ConstTy::Usize => write!(fmt, "{}", self.bits),
+            ConstTy::Isize if (self.bits as i128) < 0 => write!(fmt, "{}", self.bits as i128),
+            ty if ty.is_signed() => write!(fmt, "{}{}", self.bits as i128, ty),
+            ty => write!(fmt, "{}{}", self.bits, ty),
+        }
+    }
+}
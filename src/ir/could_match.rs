@@ -1,8 +1,13 @@
+use cast::Cast;
 use ir::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem::{self, Discriminant};
+use std::sync::Mutex;
 use zip::{Zip, Zipper};
 
 /// A fast check to see whether two things could ever possibly match.
-crate trait CouldMatch<T> {
+pub(crate) trait CouldMatch<T> {
     fn could_match(&self, other: &T) -> bool;
 }
 
@@ -39,6 +44,13 @@ impl<T: Zip> CouldMatch<T> for T {
                 Ok(())
             }
 
+            fn zip_consts(&mut self, a: &Const, b: &Const) -> Fallible<()> {
+                match (a, b) {
+                    (&Const::Value(a), &Const::Value(b)) if a != b => Err(NoSolution),
+                    _ => Ok(()),
+                }
+            }
+
             fn zip_binders<T>(&mut self, a: &Binders<T>, b: &Binders<T>) -> Fallible<()>
             where
                 T: Zip,
@@ -61,3 +73,195 @@ impl CouldMatch<DomainGoal> for ProgramClause {
         }
     }
 }
+
+/// A summary of a domain goal that is cheap to compare: its predicate
+/// and, if there is one, the trait, associated type or type it is
+/// about. Domain goals whose keys do not match can never match either,
+/// so clauses can be indexed by the key of their consequence.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct MatchKey {
+    pub(crate) predicate: Discriminant<DomainGoal>,
+
+    /// `None` if the item is not known, e.g. for `IsLocal(T)`: such a
+    /// key matches any key with the same predicate.
+    pub(crate) item: Option<ItemId>,
+}
+
+impl MatchKey {
+    pub(crate) fn could_match(&self, other: &MatchKey) -> bool {
+        self.predicate == other.predicate
+            && (self.item.is_none() || other.item.is_none() || self.item == other.item)
+    }
+
+    /// The key with the same predicate, but no item.
+    pub(crate) fn without_item(&self) -> MatchKey {
+        MatchKey {
+            item: None,
+            ..*self
+        }
+    }
+}
+
+impl DomainGoal {
+    pub(crate) fn match_key(&self) -> MatchKey {
+        fn ty_item(ty: &Ty) -> Option<ItemId> {
+            match ty {
+                Ty::Apply(ApplicationTy { name: TypeName::ItemId(id), .. })
+                | Ty::Apply(ApplicationTy { name: TypeName::Synthetic { id, .. }, .. }) => {
+                    Some(*id)
+                }
+                _ => None,
+            }
+        }
+
+        let item = match self {
+            DomainGoal::Holds(WhereClause::Implemented(trait_ref))
+            | DomainGoal::WellFormed(WellFormed::Trait(trait_ref))
+            | DomainGoal::FromEnv(FromEnv::Trait(trait_ref))
+            | DomainGoal::LocalImplAllowed(trait_ref) => Some(trait_ref.trait_id),
+            DomainGoal::Holds(WhereClause::ProjectionEq(ProjectionEq { projection, .. }))
+            | DomainGoal::Normalize(Normalize { projection, .. }) => {
+                Some(projection.associated_ty_id)
+            }
+            DomainGoal::WellFormed(WellFormed::Ty(ty))
+            | DomainGoal::FromEnv(FromEnv::Ty(ty))
+            | DomainGoal::IsLocal(ty)
+            | DomainGoal::IsUpstream(ty)
+            | DomainGoal::IsFullyVisible(ty)
+            | DomainGoal::DownstreamType(ty) => ty_item(ty),
+            DomainGoal::InScope(id) => Some(*id),
+            DomainGoal::External(goal) => Some(goal.predicate),
+            DomainGoal::UnselectedNormalize(_)
+            | DomainGoal::Derefs(_)
+            | DomainGoal::Compatible(()) => None,
+        };
+
+        MatchKey {
+            predicate: mem::discriminant(self),
+            item,
+        }
+    }
+}
+
+impl ProgramClause {
+    pub(crate) fn match_key(&self) -> MatchKey {
+        match self {
+            ProgramClause::Implies(implication) => implication.consequence.match_key(),
+            ProgramClause::ForAll(clause) => clause.value.consequence.match_key(),
+        }
+    }
+}
+
+/// For each trait and type constructor, whether no program clause can
+/// ever prove that a type with that constructor implements the trait:
+/// `Vec<?T>: Foo` fails whatever `?T` is if no clause about `Foo` is for
+/// a `Vec`. The solver looks here before it creates a table for such a
+/// goal (see `UnificationOps::never_applies`); the hypotheses of the
+/// environment, and whether it is in the `Compatible` modality, are for
+/// the caller to check.
+///
+/// The cache is filled as goals are looked up, and belongs to the
+/// `ProgramEnvironment` whose clauses it describes, so it goes away
+/// with them: clones start empty.
+pub(crate) struct NegativeCache {
+    never_implemented: Mutex<HashMap<(ItemId, TypeName), bool>>,
+
+    /// False for a cache which never tells that a goal fails, as if
+    /// there were none (see `NegativeCache::disabled`).
+    enabled: bool,
+}
+
+impl NegativeCache {
+    pub(crate) fn new() -> Self {
+        NegativeCache {
+            never_implemented: Mutex::new(HashMap::new()),
+            enabled: true,
+        }
+    }
+
+    /// A cache which never tells that a goal fails, so that the solver
+    /// creates a table for every goal, as it would without the cache.
+    #[cfg(test)]
+    pub(crate) fn disabled() -> Self {
+        NegativeCache {
+            enabled: false,
+            ..NegativeCache::new()
+        }
+    }
+
+    /// True if none of `program_clauses` can prove that a type built
+    /// with `head` implements the trait `trait_id`, nor that the
+    /// environment implies it (see `could_imply_trait`).
+    pub(crate) fn never_implemented(
+        &self,
+        program_clauses: &[ProgramClause],
+        trait_id: ItemId,
+        head: TypeName,
+    ) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let mut never_implemented = self.never_implemented.lock().unwrap();
+        *never_implemented.entry((trait_id, head)).or_insert_with(|| {
+            !program_clauses
+                .iter()
+                .any(|clause| could_imply_trait(clause, trait_id, head))
+        })
+    }
+}
+
+/// True if `clause` could prove that a type built with `head` implements
+/// the trait `trait_id`, or that the environment implies it. The clause
+/// `Implemented(T: Foo) :- FromEnv(T: Foo)`, which every trait has, is
+/// left out: it only applies through the `FromEnv` clauses, which are
+/// looked at instead. So are the clauses for the impls a compatible
+/// world may add, which only apply in the `Compatible` modality.
+fn could_imply_trait(clause: &ProgramClause, trait_id: ItemId, head: TypeName) -> bool {
+    let implication = match clause {
+        ProgramClause::Implies(implication) => implication,
+        ProgramClause::ForAll(implication) => &implication.value,
+    };
+    let compatible: Goal = DomainGoal::Compatible(()).cast();
+    if implication.conditions.contains(&compatible) {
+        return false;
+    }
+    let trait_ref = match &implication.consequence {
+        DomainGoal::Holds(WhereClause::Implemented(trait_ref)) => {
+            let from_env: Goal = FromEnv::Trait(trait_ref.clone()).cast();
+            if implication.conditions == [from_env] {
+                return false;
+            }
+            trait_ref
+        }
+        DomainGoal::FromEnv(FromEnv::Trait(trait_ref)) => trait_ref,
+        _ => return false,
+    };
+    trait_ref.trait_id == trait_id && match trait_ref.parameters[0].assert_ty_ref() {
+        Ty::Apply(application) => application.name == head,
+        _ => true,
+    }
+}
+
+impl Clone for NegativeCache {
+    fn clone(&self) -> Self {
+        NegativeCache {
+            enabled: self.enabled,
+            ..NegativeCache::new()
+        }
+    }
+}
+
+impl fmt::Debug for NegativeCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NegativeCache {{ .. }}")
+    }
+}
+
+/// Caches never tell programs apart.
+impl PartialEq for NegativeCache {
+    fn eq(&self, _other: &NegativeCache) -> bool {
+        true
+    }
+}
+
+impl Eq for NegativeCache {}
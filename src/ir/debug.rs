@@ -27,6 +27,21 @@ impl Display for UniverseIndex {
     }
 }
 
+impl Debug for PlaceholderIndex {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        let PlaceholderIndex { ui, idx } = self;
+        if let Some(name) = tls::placeholder_name(*self) {
+            return write!(fmt, "!{}", name);
+        }
+        if tls::lettered_variables() {
+            // Only renamed solutions are printed this way, and their
+            // placeholders are numbered regardless of universe.
+            return write!(fmt, "!T{}", idx + 1);
+        }
+        write!(fmt, "!{}_{}", ui.counter, idx)
+    }
+}
+
 impl Debug for UniverseIndex {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
         write!(fmt, "U{}", self.counter)
@@ -37,16 +52,41 @@ impl Debug for TypeName {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
         match self {
             TypeName::ItemId(id) => write!(fmt, "{:?}", id),
-            TypeName::ForAll(universe) => write!(fmt, "!{}", universe.counter),
+            TypeName::ForAll(placeholder) => write!(fmt, "{:?}", placeholder),
             TypeName::AssociatedType(assoc_ty) => write!(fmt, "{:?}", assoc_ty),
+            TypeName::Synthetic { id, kind: _ } => write!(fmt, "{:?}", id),
         }
     }
 }
 
+/// The name of the free variable with the given index: just the index
+/// itself, unless variables are lettered (see
+/// `tls::with_lettered_variables`), in which case `0` is `A`, `25` is
+/// `Z`, `26` is `AA` and so on.
+pub(crate) struct VariableName(pub(crate) usize);
+
+impl Display for VariableName {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        if !tls::lettered_variables() {
+            return write!(fmt, "{}", self.0);
+        }
+
+        let mut letters = vec![];
+        let mut index = self.0 + 1;
+        while index > 0 {
+            index -= 1;
+            letters.push((b'A' + (index % 26) as u8) as char);
+            index /= 26;
+        }
+        letters.reverse();
+        write!(fmt, "{}", letters.into_iter().collect::<String>())
+    }
+}
+
 impl Debug for Ty {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
         match self {
-            Ty::Var(depth) => write!(fmt, "?{}", depth),
+            Ty::Var(depth) => write!(fmt, "?{}", VariableName(*depth)),
             Ty::Apply(apply) => write!(fmt, "{:?}", apply),
             Ty::Projection(proj) => write!(fmt, "{:?}", proj),
             Ty::UnselectedProjection(proj) => write!(fmt, "{:?}", proj),
@@ -69,15 +109,62 @@ impl Debug for QuantifiedTy {
 impl Debug for Lifetime {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
         match self {
-            Lifetime::Var(depth) => write!(fmt, "'?{}", depth),
-            Lifetime::ForAll(universe) => write!(fmt, "'!{}", universe.counter),
+            Lifetime::Var(depth) => write!(fmt, "'?{}", VariableName(*depth)),
+            Lifetime::ForAll(placeholder) => write!(fmt, "'{:?}", placeholder),
+            Lifetime::Static => write!(fmt, "'static"),
+        }
+    }
+}
+
+impl Debug for Const {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        match self {
+            Const::Var(depth) => write!(fmt, "?{}", VariableName(*depth)),
+            Const::ForAll(placeholder) => write!(fmt, "{:?}", placeholder),
+            Const::Value(value) => write!(fmt, "{}", value),
+            Const::Unevaluated(unevaluated) => write!(fmt, "{:?}", unevaluated),
         }
     }
 }
 
+impl Debug for ConstValue {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        write!(fmt, "{}", self)
+    }
+}
+
+impl Debug for UnevaluatedConst {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        write!(fmt, "{{{:?}{:?}}}", self.const_id, Angle(&self.parameters))
+    }
+}
+
 impl Debug for ApplicationTy {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
-        write!(fmt, "{:?}{:?}", self.name, Angle(&self.parameters))
+        // Trailing parameters which are equal to their defaults are
+        // left out, as they would be in the source.
+        let num_parameters = tls::with_current_program(|p| match (p, self.name) {
+            (Some(prog), TypeName::ItemId(id)) => match prog.type_kinds.get(&id) {
+                Some(k) => {
+                    let num_required = self.parameters.len() - k.const_defaults.len();
+                    let num_defaulted = self.parameters[num_required..]
+                        .iter()
+                        .zip(&k.const_defaults)
+                        .rev()
+                        .take_while(|&(parameter, default)| {
+                            *parameter == ParameterKind::Const(default.clone())
+                        })
+                        .count();
+                    self.parameters.len() - num_defaulted
+                }
+                None => self.parameters.len(),
+            },
+            (None, _)
+            | (Some(_), TypeName::ForAll(_))
+            | (Some(_), TypeName::AssociatedType(_))
+            | (Some(_), TypeName::Synthetic { .. }) => self.parameters.len(),
+        });
+        write!(fmt, "{:?}{:?}", self.name, Angle(&self.parameters[..num_parameters]))
     }
 }
 
@@ -95,10 +182,8 @@ impl Debug for TraitRef {
 
 impl Debug for ProjectionTy {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
-        tls::with_current_program(|p| match p {
-            Some(program) => {
-                let (associated_ty_data, trait_params, other_params) =
-                    program.split_projection(self);
+        tls::with_current_program(|p| match p.map(|program| program.split_projection(self)) {
+            Some(Ok((associated_ty_data, trait_params, other_params))) => {
                 write!(
                     fmt,
                     "<{:?} as {:?}{:?}>::{}{:?}",
@@ -109,7 +194,10 @@ impl Debug for ProjectionTy {
                     Angle(&other_params)
                 )
             }
-            None => write!(
+
+            // Without a program, or if the parameters do not fit the
+            // associated type, print them as they are.
+            None | Some(Err(_)) => write!(
                 fmt,
                 "({:?}){:?}",
                 self.associated_ty_id,
@@ -132,7 +220,215 @@ impl Debug for UnselectedProjectionTy {
     }
 }
 
-crate struct Angle<'a, T: 'a>(pub &'a [T]);
+/// Prints a trait reference as a bound, `Vec<u32>: Foo` rather than
+/// `Vec<u32> as Foo`.
+pub(crate) struct WithColon<'a>(pub &'a TraitRef);
+
+impl<'a> Debug for WithColon<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        let TraitRef { trait_id, parameters } = self.0;
+        write!(fmt, "{:?}: {:?}{:?}", parameters[0], trait_id, Angle(&parameters[1..]))
+    }
+}
+
+/// Prints a trait reference as the header of an impl, without the
+/// `impl` keyword and parameters: `Foo for Vec<u32>`.
+pub(crate) struct ImplHeader<'a>(pub &'a TraitRef);
+
+impl<'a> Debug for ImplHeader<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        let TraitRef { trait_id, parameters } = self.0;
+        write!(fmt, "{:?}{:?} for {:?}", trait_id, Angle(&parameters[1..]), parameters[0])
+    }
+}
+
+/// Prints a trait reference under the binders of an impl as the whole
+/// header of the impl, its parameters named as its bound variables are
+/// printed: `impl<?0> Foo for Vec<?0>`.
+pub(crate) struct BoundImplHeader<'a>(pub &'a Binders<TraitRef>);
+
+impl<'a> Debug for BoundImplHeader<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        let Binders { binders, value } = self.0;
+        write!(fmt, "impl")?;
+        if !binders.is_empty() {
+            write!(fmt, "<")?;
+            for (index, binder) in binders.iter().enumerate() {
+                if index > 0 {
+                    write!(fmt, ", ")?;
+                }
+                match *binder {
+                    ParameterKind::Ty(()) => write!(fmt, "?{}", index)?,
+                    ParameterKind::Lifetime(()) => write!(fmt, "'?{}", index)?,
+                    ParameterKind::Const(()) => write!(fmt, "const ?{}", index)?,
+                }
+            }
+            write!(fmt, ">")?;
+        }
+        write!(fmt, " {:?}", ImplHeader(value))
+    }
+}
+
+/// Prints the equality of a projection with a type as the bound it
+/// comes from: `T: Foo<Assoc = u32>` for `<T as Foo>::Assoc = u32`.
+pub(crate) struct AssocBound<'a>(pub &'a ProjectionTy, pub &'a Ty);
+
+impl<'a> Debug for AssocBound<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        let AssocBound(projection, ty) = self;
+        tls::with_current_program(|p| match p.map(|program| program.split_projection(projection)) {
+            Some(Ok((associated_ty_data, trait_params, other_params))) => {
+                write!(fmt, "{:?}: {:?}<", &trait_params[0], associated_ty_data.trait_id)?;
+                for param in &trait_params[1..] {
+                    write!(fmt, "{:?}, ", param)?;
+                }
+                write!(
+                    fmt,
+                    "{}{:?} = {:?}>",
+                    associated_ty_data.name,
+                    Angle(&other_params),
+                    ty
+                )
+            }
+            None | Some(Err(_)) => write!(fmt, "{:?} = {:?}", projection, ty),
+        })
+    }
+}
+
+/// Prints a conjunction of `where` clauses with the bounds on associated
+/// types that lowering split off put back in the bounds they were written
+/// in: `Implemented(T: Iterator<Item: Clone>)` for `Implemented(T: Iterator)`
+/// and `Implemented(<T as Iterator>::Item: Clone)`. The other clauses are
+/// printed as they are.
+pub(crate) struct WhereClauses<'a>(pub &'a [&'a WhereClause]);
+
+impl<'a> WhereClauses<'a> {
+    /// Whether any of the clauses bounds an associated type of a trait
+    /// reference before it, and so is printed as part of it.
+    pub(crate) fn resugars(&self) -> bool {
+        tls::with_current_program(|p| match p {
+            Some(program) => (0..self.0.len())
+                .any(|index| !self.assoc_ty_bounds(program, index, &[]).is_empty()),
+            None => false,
+        })
+    }
+
+    fn implemented(&self, index: usize) -> Option<&'a TraitRef> {
+        match self.0[index] {
+            WhereClause::Implemented(trait_ref) => Some(trait_ref),
+            WhereClause::ProjectionEq(_) => None,
+        }
+    }
+
+    /// The clauses after the one at `index` that bound the associated
+    /// types of its trait reference and are not in `printed`, grouped by
+    /// the projection they bound.
+    fn assoc_ty_bounds(
+        &self,
+        program: &Program,
+        index: usize,
+        printed: &[usize],
+    ) -> Vec<(&'a ProjectionTy, Vec<usize>)> {
+        let mut groups: Vec<(&ProjectionTy, Vec<usize>)> = vec![];
+        let trait_ref = match self.implemented(index) {
+            Some(trait_ref) => trait_ref,
+            None => return groups,
+        };
+        for bound in index + 1..self.0.len() {
+            if printed.contains(&bound) {
+                continue;
+            }
+            let projection = match self.implemented(bound).map(|bound| &bound.parameters[0]) {
+                Some(ParameterKind::Ty(Ty::Projection(projection))) => projection,
+                Some(_) | None => continue,
+            };
+            match program.split_projection(projection) {
+                Ok((associated_ty_data, trait_params, _))
+                    if associated_ty_data.trait_id == trait_ref.trait_id
+                        && trait_params == &trait_ref.parameters[..] => {}
+                Ok(_) | Err(_) => continue,
+            }
+            match groups.iter().position(|&(bounded, _)| bounded == projection) {
+                Some(group) => groups[group].1.push(bound),
+                None => groups.push((projection, vec![bound])),
+            }
+        }
+        groups
+    }
+
+    /// Prints the trait reference of the clause at `index` as a bound,
+    /// with the bounds on its associated types.
+    fn fmt_bound(
+        &self,
+        fmt: &mut Formatter,
+        program: &Program,
+        index: usize,
+        printed: &mut Vec<usize>,
+    ) -> Result<(), Error> {
+        let trait_ref = self.implemented(index).unwrap();
+        printed.push(index);
+        let groups = self.assoc_ty_bounds(program, index, printed);
+        printed.extend(groups.iter().flat_map(|(_, bounds)| bounds.iter().cloned()));
+
+        write!(fmt, "{:?}", trait_ref.trait_id)?;
+        if trait_ref.parameters.len() == 1 && groups.is_empty() {
+            return Ok(());
+        }
+        write!(fmt, "<")?;
+        for (position, param) in trait_ref.parameters[1..].iter().enumerate() {
+            if position > 0 {
+                write!(fmt, ", ")?;
+            }
+            write!(fmt, "{:?}", param)?;
+        }
+        for (position, (projection, bounds)) in groups.iter().enumerate() {
+            if position > 0 || trait_ref.parameters.len() > 1 {
+                write!(fmt, ", ")?;
+            }
+            let (associated_ty_data, _, other_params) = program.split_projection(projection)
+                .unwrap();
+            write!(fmt, "{}{:?}: ", associated_ty_data.name, Angle(other_params))?;
+            for (position, &bound) in bounds.iter().enumerate() {
+                if position > 0 {
+                    write!(fmt, " + ")?;
+                }
+                self.fmt_bound(fmt, program, bound, printed)?;
+            }
+        }
+        write!(fmt, ">")
+    }
+}
+
+impl<'a> Debug for WhereClauses<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        tls::with_current_program(|p| {
+            let mut printed = vec![];
+            let mut first = true;
+            for (index, where_clause) in self.0.iter().enumerate() {
+                if printed.contains(&index) {
+                    continue;
+                }
+                if !first {
+                    write!(fmt, ", ")?;
+                }
+                first = false;
+                match (p, self.implemented(index)) {
+                    (Some(program), Some(trait_ref)) => {
+                        write!(fmt, "Implemented({:?}: ", trait_ref.parameters[0])?;
+                        self.fmt_bound(fmt, program, index, &mut printed)?;
+                        write!(fmt, ")")?;
+                    }
+                    (Some(_), None) | (None, Some(_)) | (None, None) => {
+                        write!(fmt, "{:?}", where_clause)?
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+pub(crate) struct Angle<'a, T: 'a>(pub &'a [T]);
 
 impl<'a, T: Debug> Debug for Angle<'a, T> {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
@@ -224,10 +520,24 @@ impl Debug for DomainGoal {
             ),
             DomainGoal::Compatible(_) => write!(fmt, "Compatible"),
             DomainGoal::DownstreamType(n) => write!(fmt, "DownstreamType({:?})", n),
+            DomainGoal::External(n) => write!(fmt, "{:?}", n),
         }
     }
 }
 
+impl Debug for ExternalGoal {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        write!(fmt, "{:?}(", self.predicate)?;
+        for (index, parameter) in self.parameters.iter().enumerate() {
+            if index > 0 {
+                write!(fmt, ", ")?;
+            }
+            write!(fmt, "{:?}", parameter)?;
+        }
+        write!(fmt, ")")
+    }
+}
+
 impl Debug for LeafGoal {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
         match *self {
@@ -237,6 +547,19 @@ impl Debug for LeafGoal {
     }
 }
 
+impl Debug for ReflectionGoal {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        match *self {
+            ReflectionGoal::HasImpl(trait_id, ref ty) => {
+                write!(fmt, "HasImpl({:?}, {:?})", trait_id, ty)
+            }
+            ReflectionGoal::ClauseCount(trait_id, count) => {
+                write!(fmt, "ClauseCount({:?}, {})", trait_id, count)
+            }
+        }
+    }
+}
+
 impl Debug for EqGoal {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
         write!(fmt, "({:?} = {:?})", self.a, self.b)
@@ -255,12 +578,22 @@ impl Debug for Goal {
                     match *binder {
                         ParameterKind::Ty(()) => write!(fmt, "type")?,
                         ParameterKind::Lifetime(()) => write!(fmt, "lifetime")?,
+                        ParameterKind::Const(()) => write!(fmt, "const")?,
                     }
                 }
                 write!(fmt, "> {{ {:?} }}", subgoal.value)
             }
             Goal::Implies(ref wc, ref g) => write!(fmt, "if ({:?}) {{ {:?} }}", wc, g),
-            Goal::And(ref g1, ref g2) => write!(fmt, "({:?}, {:?})", g1, g2),
+            Goal::And(ref g1, ref g2) => {
+                let mut where_clauses = vec![];
+                if self.conjoined_where_clauses(&mut where_clauses)
+                    && WhereClauses(&where_clauses).resugars()
+                {
+                    write!(fmt, "({:?})", WhereClauses(&where_clauses))
+                } else {
+                    write!(fmt, "({:?}, {:?})", g1, g2)
+                }
+            }
             Goal::Not(ref g) => write!(fmt, "not {{ {:?} }}", g),
             Goal::Leaf(ref wc) => write!(fmt, "{:?}", wc),
             Goal::CannotProve(()) => write!(fmt, r"¯\_(ツ)_/¯"),
@@ -268,6 +601,29 @@ impl Debug for Goal {
     }
 }
 
+impl Goal {
+    /// Collects the `where` clauses this goal is the conjunction of, in
+    /// order; false if it is not a conjunction of `where` clauses.
+    fn conjoined_where_clauses<'a>(&'a self, where_clauses: &mut Vec<&'a WhereClause>) -> bool {
+        match self {
+            Goal::And(g1, g2) => {
+                g1.conjoined_where_clauses(where_clauses)
+                    && g2.conjoined_where_clauses(where_clauses)
+            }
+            Goal::Leaf(LeafGoal::DomainGoal(DomainGoal::Holds(where_clause))) => {
+                where_clauses.push(where_clause);
+                true
+            }
+            Goal::Leaf(LeafGoal::DomainGoal(_))
+            | Goal::Leaf(LeafGoal::EqGoal(_))
+            | Goal::Quantified(..)
+            | Goal::Implies(..)
+            | Goal::Not(_)
+            | Goal::CannotProve(()) => false,
+        }
+    }
+}
+
 impl<T: Debug> Debug for Binders<T> {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
         let Binders {
@@ -283,6 +639,7 @@ impl<T: Debug> Debug for Binders<T> {
                 match *binder {
                     ParameterKind::Ty(()) => write!(fmt, "type")?,
                     ParameterKind::Lifetime(()) => write!(fmt, "lifetime")?,
+                    ParameterKind::Const(()) => write!(fmt, "const")?,
                 }
             }
             write!(fmt, "> ")?;
@@ -319,7 +676,9 @@ impl Debug for ProgramClauseImplication {
 
 impl Debug for Environment {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
-        write!(fmt, "Env({:?})", self.clauses)
+        write!(fmt, "Env(")?;
+        fmt.debug_list().entries(self.clauses()).finish()?;
+        write!(fmt, ")")
     }
 }
 
@@ -346,11 +705,14 @@ impl<T: Display> Display for Canonical<T> {
     }
 }
 
-impl<T: Debug, L: Debug> Debug for ParameterKind<T, L> {
-    default fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+/// The payload is printed as itself: types, lifetimes and consts can
+/// already be told apart by their syntax.
+impl<T: Debug, L: Debug, C: Debug> Debug for ParameterKind<T, L, C> {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
         match *self {
-            ParameterKind::Ty(ref n) => write!(fmt, "Ty({:?})", n),
-            ParameterKind::Lifetime(ref n) => write!(fmt, "Lifetime({:?})", n),
+            ParameterKind::Ty(ref n) => write!(fmt, "{:?}", n),
+            ParameterKind::Lifetime(ref n) => write!(fmt, "{:?}", n),
+            ParameterKind::Const(ref n) => write!(fmt, "{:?}", n),
         }
     }
 }
@@ -359,22 +721,53 @@ impl Debug for Constraint {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
         match self {
             Constraint::LifetimeEq(a, b) => write!(fmt, "{:?} == {:?}", a, b),
+            Constraint::TypeOutlives(ty, lifetime) => write!(fmt, "{:?}: {:?}", ty, lifetime),
+            Constraint::LifetimeOutlives(a, b) => write!(fmt, "{:?}: {:?}", a, b),
         }
     }
 }
 
-impl Debug for Parameter {
+/// The provenance, if known, follows the constraint as a comment.
+impl Debug for RegionConstraint {
     fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
-        match *self {
-            ParameterKind::Ty(ref n) => write!(fmt, "{:?}", n),
-            ParameterKind::Lifetime(ref n) => write!(fmt, "{:?}", n),
+        Debug::fmt(&self.constraint, fmt)?;
+        match self.provenance.goal() {
+            Some(goal) => write!(fmt, " /* from {:?} */", goal.value.goal),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The sources, if recorded, follow the constraints.
+impl Debug for ConstrainedSubst {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        let mut debug = fmt.debug_struct("ConstrainedSubst");
+        debug
+            .field("subst", &self.subst)
+            .field("constraints", &self.constraints);
+        if !self.sources.sources.is_empty() {
+            debug.field("sources", &self.sources.sources);
         }
+        debug.finish()
     }
 }
 
 impl Display for ConstrainedSubst {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        let ConstrainedSubst { subst, constraints } = self;
+        let ConstrainedSubst { subst, constraints, .. } = self;
+
+        write!(
+            f,
+            "substitution {}, lifetime constraints {:?}",
+            subst,
+            constraints,
+        )
+    }
+}
+
+impl Display for SolutionSubst {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        let SolutionSubst { subst, constraints } = self;
 
         write!(
             f,
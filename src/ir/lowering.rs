@@ -1,4 +1,6 @@
+use std::cell::Cell;
 use std::collections::BTreeMap;
+use std::iter;
 
 use chalk_parse::ast::*;
 use lalrpop_intern::intern;
@@ -7,8 +9,11 @@ use cast::{Cast, Caster};
 use errors::*;
 use ir::{self, Anonymize, ToParameter};
 use itertools::Itertools;
-use solve::SolverChoice;
+use solve::{ConstOverflow, SolverChoice};
+use self::kinds::{check_parameter_kinds, kinds};
 
+mod consts;
+mod kinds;
 mod test;
 
 type TypeIds = BTreeMap<ir::Identifier, ir::ItemId>;
@@ -24,6 +29,12 @@ struct Env<'k> {
     /// Parameter identifiers are used as keys, therefore
     /// all indentifiers in an environment must be unique (no shadowing).
     parameter_map: ParameterMap,
+    /// The number of elided consts (`_`) lowered so far, or `None`
+    /// outside of goals, where they are not allowed.
+    elided_consts: Option<&'k Cell<usize>>,
+    /// What becomes of the goals in which a const expression
+    /// overflows. Anything else in which one does is an error.
+    const_overflow: ConstOverflow,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -66,10 +77,38 @@ impl<'k> Env<'k> {
         bail!("invalid lifetime name: {:?}", name.str);
     }
 
+    /// Const parameters are referenced using the same syntax as types
+    /// (`Foo<N>`), so unlike `lookup` and `lookup_lifetime` this does
+    /// not fail: it returns `None` if `name` is not a const parameter,
+    /// and the caller falls back to treating it as a type.
+    fn lookup_const(&self, name: Identifier) -> Option<usize> {
+        self.parameter_map
+            .get(&ir::ParameterKind::Const(name.str))
+            .cloned()
+    }
+
     fn type_kind(&self, id: ir::ItemId) -> &ir::TypeKind {
         &self.type_kinds[&id]
     }
 
+    /// Checks that no type or const parameter among `parameters` is
+    /// named after a struct, trait or other named item: `lookup` would
+    /// resolve the name to the parameter within its binder, hiding the
+    /// item without a word.
+    fn check_parameter_names(&self, parameters: &[ParameterKind]) -> Result<()> {
+        for parameter in parameters {
+            match *parameter {
+                ParameterKind::Ty(name) | ParameterKind::Const(name) => {
+                    if self.type_ids.contains_key(&name.str) {
+                        bail!(ErrorKind::ParameterShadowsItem(name));
+                    }
+                }
+                ParameterKind::Lifetime(_) => {}
+            }
+        }
+        Ok(())
+    }
+
     /// Introduces new parameters, shifting the indices of existing
     /// parameters to accommodate them. The indices of the new binders
     /// will be assigned in order as they are iterated.
@@ -109,133 +148,513 @@ impl<'k> Env<'k> {
     }
 }
 
+#[cfg(test)]
+thread_local! {
+    /// Counts how many structs, traits and impls were lowered, as opposed
+    /// to reused from a previous program. Only used in tests.
+    pub(crate) static LOWERED_ITEMS: Cell<usize> = Cell::new(0)
+}
+
 pub trait LowerProgram {
     /// Lowers from a Program AST to the internal IR for a program.
     fn lower(&self, solver_choice: SolverChoice) -> Result<ir::Program>;
+
+    /// Lowers the program again after its source was edited, given the
+    /// program it was lowered to before. Structs, traits and impls
+    /// which did not change are not lowered again: their datums are
+    /// taken from `previous`. Named items keep their ids across the
+    /// two programs, wherever they moved to in the source.
+    ///
+    /// The whole program is still checked, as by `lower`.
+    fn relower(&self, previous: &ir::Program, solver_choice: SolverChoice)
+        -> Result<ir::Program>;
 }
 
 impl LowerProgram for Program {
     fn lower(&self, solver_choice: SolverChoice) -> Result<ir::Program> {
-        let mut index = 0;
-        let mut next_item_id = || -> ir::ItemId {
-            let i = index;
-            index += 1;
-            ir::ItemId { index: i }
+        let name = intern(SINGLE_CRATE);
+        lower_crates(&[(name, self)], name, solver_choice, None)
+    }
+
+    fn relower(&self, previous: &ir::Program, solver_choice: SolverChoice)
+        -> Result<ir::Program>
+    {
+        let name = intern(SINGLE_CRATE);
+        lower_crates(&[(name, self)], name, solver_choice, Some(previous))
+    }
+}
+
+pub trait LowerCrates {
+    /// Lowers a program split across several crates, as seen from
+    /// `local_crate`: the items of every other crate are upstream, so
+    /// only the impls of `local_crate` are subject to the orphan rules.
+    fn lower_in_crate(&self, local_crate: ir::Identifier, solver_choice: SolverChoice)
+        -> Result<ir::Program>;
+
+    /// Lowers the program as seen from each of its crates in turn, so
+    /// that the impls of every crate are checked.
+    fn lower_all_crates(&self, solver_choice: SolverChoice)
+        -> Result<BTreeMap<ir::Identifier, ir::Program>>;
+}
+
+impl LowerCrates for [Crate] {
+    fn lower_in_crate(&self, local_crate: ir::Identifier, solver_choice: SolverChoice)
+        -> Result<ir::Program>
+    {
+        let crates: Vec<_> = self.iter().map(|krate| (krate.name, &krate.program)).collect();
+        lower_crates(&crates, local_crate, solver_choice, None)
+    }
+
+    fn lower_all_crates(&self, solver_choice: SolverChoice)
+        -> Result<BTreeMap<ir::Identifier, ir::Program>>
+    {
+        self.iter()
+            .map(|krate| Ok((krate.name, self.lower_in_crate(krate.name, solver_choice)?)))
+            .collect()
+    }
+}
+
+/// The name of the only crate of a program written as a single file.
+/// `crate` is a keyword, so goals cannot name it.
+const SINGLE_CRATE: &str = "crate";
+
+/// Lowers `crates` as seen from `local_crate`. If `previous` is given,
+/// the datums of its items are reused where possible; see
+/// `LowerProgram::relower`.
+fn lower_crates(
+    crates: &[(ir::Identifier, &Program)],
+    local_crate: ir::Identifier,
+    solver_choice: SolverChoice,
+    previous: Option<&ir::Program>,
+) -> Result<ir::Program> {
+    let mut crate_names = BTreeMap::new();
+    let mut crate_ids = BTreeMap::new();
+    for (index, &(name, _)) in crates.iter().enumerate() {
+        let crate_id = ir::CrateId { index };
+        if crate_ids.insert(name, crate_id).is_some() {
+            bail!("duplicate crate `{}`", name);
+        }
+        crate_names.insert(crate_id, name);
+    }
+    let local_crate = match crate_ids.get(&local_crate) {
+        Some(&crate_id) => crate_id,
+        None => bail!(ErrorKind::InvalidCrateName(local_crate)),
+    };
+
+    // Names are resolved across all of the crates, so their items
+    // are lowered together, each remembering the crate defining it.
+    let items: Vec<(ir::CrateId, &Item)> = crates
+        .iter()
+        .enumerate()
+        .flat_map(|(index, &(_, program))| {
+            program.items.iter().map(move |item| (ir::CrateId { index }, item))
+        })
+        .collect();
+
+    let item_hashes: Vec<u64> = items.iter().map(|&(_, item)| item.content_hash()).collect();
+
+    // Make a vector mapping each thing in `items` to an id, based just
+    // on its position -- unless we are lowering again, in which case
+    // see `ItemIds`:
+    let mut ids = ItemIds::new(previous);
+    let item_ids: Vec<_> = items
+        .iter()
+        .zip(&item_hashes)
+        .map(|(&(_, item), &hash)| ids.item_id(item, hash))
+        .collect();
+    let mut item_crates: BTreeMap<_, _> = item_ids
+        .iter()
+        .zip(&items)
+        .map(|(&item_id, &(crate_id, _))| (item_id, crate_id))
+        .collect();
+
+    // Create ids for associated types
+    let mut associated_ty_infos = BTreeMap::new();
+    for (&(crate_id, item), &item_id) in items.iter().zip(&item_ids) {
+        if let Item::TraitDefn(ref d) = *item {
+            if d.flags.auto && !d.assoc_ty_defns.is_empty() {
+                bail!("auto trait cannot define associated types");
+            }
+            for defn in &d.assoc_ty_defns {
+                let addl_parameter_kinds = defn.all_parameters();
+                let info = AssociatedTyInfo {
+                    id: ids.associated_ty_id(item_id, defn.name.str),
+                    addl_parameter_kinds,
+                };
+                item_crates.insert(info.id, crate_id);
+                associated_ty_infos.insert((item_id, defn.name.str), info);
+            }
+        }
+    }
+
+    let mut type_ids = BTreeMap::new();
+    let mut type_kinds = BTreeMap::new();
+    for (&(_, item), &item_id) in items.iter().zip(&item_ids) {
+        let k = match *item {
+            Item::StructDefn(ref d) => d.lower_type_kind()?,
+            Item::TraitDefn(ref d) => d.lower_type_kind()?,
+            Item::ExternalPredicateDefn(ref d) => d.lower_type_kind()?,
+            Item::ConstDefn(ref d) => d.lower_type_kind()?,
+            Item::SyntheticTyDefn(ref d) => d.lower_type_kind()?,
+            Item::Impl(_) => continue,
+            Item::Clause(_) => continue,
         };
+        type_ids.insert(k.name, item_id);
+        type_kinds.insert(item_id, k);
+    }
 
-        // Make a vector mapping each thing in `items` to an id,
-        // based just on its position:
-        let item_ids: Vec<_> = self.items.iter().map(|_| next_item_id()).collect();
+    // The datums of the previous program can only be reused if every
+    // name still refers to the same item, with the same kinds, as they
+    // did when the datums were lowered.
+    let reusable = previous.filter(|previous| {
+        previous.crate_names == crate_names
+            && previous.local_crate == local_crate
+            && *previous.type_ids == type_ids
+            && *previous.type_kinds == type_kinds
+            && previous_associated_ty_infos(previous) == associated_ty_infos
+    });
+
+    let mut struct_data = BTreeMap::new();
+    let mut synthetic_ty_data = BTreeMap::new();
+    let mut trait_data = BTreeMap::new();
+    let mut impl_data = BTreeMap::new();
+    let mut associated_ty_data = BTreeMap::new();
+    let mut custom_clauses = Vec::new();
+    let mut lang_items = BTreeMap::new();
+    let mut item_names = BTreeMap::new();
+    let mut lowered_item_hashes = BTreeMap::new();
+    for ((&(crate_id, item), &item_id), &hash) in items.iter().zip(&item_ids).zip(&item_hashes) {
+        // Items of other crates are treated as if marked `#[upstream]`.
+        let upstream = crate_id != local_crate;
+        let previous = reusable.filter(|previous| {
+            previous.item_hashes.get(&item_id) == Some(&hash)
+                && previous.item_crates.get(&item_id) == Some(&crate_id)
+        });
+        let empty_env = Env {
+            type_ids: &type_ids,
+            type_kinds: &type_kinds,
+            associated_ty_infos: &associated_ty_infos,
+            parameter_map: BTreeMap::new(),
+            elided_consts: None,
+            const_overflow: ConstOverflow::Error,
+        };
 
-        // Create ids for associated types
-        let mut associated_ty_infos = BTreeMap::new();
-        for (item, &item_id) in self.items.iter().zip(&item_ids) {
-            if let Item::TraitDefn(ref d) = *item {
-                if d.flags.auto && !d.assoc_ty_defns.is_empty() {
-                    bail!("auto trait cannot define associated types");
+        match *item {
+            Item::StructDefn(ref d) => {
+                let struct_datum = match previous {
+                    Some(previous) => previous.struct_data[&item_id].clone(),
+                    None => {
+                        #[cfg(test)]
+                        LOWERED_ITEMS.with(|c| c.set(c.get() + 1));
+
+                        let mut struct_datum = d.lower_struct(item_id, &empty_env)?;
+                        struct_datum.binders.value.flags.upstream |= upstream;
+                        struct_datum
+                    }
+                };
+                struct_data.insert(item_id, struct_datum);
+                lowered_item_hashes.insert(item_id, hash);
+
+                if d.flags.lang_box {
+                    use std::collections::btree_map::Entry::*;
+                    match lang_items.entry(ir::LangItem::BoxStruct) {
+                        Vacant(entry) => { entry.insert(item_id); },
+                        Occupied(_) => {
+                            bail!(ErrorKind::DuplicateLangItem(ir::LangItem::BoxStruct))
+                        }
+                    }
                 }
-                for defn in &d.assoc_ty_defns {
-                    let addl_parameter_kinds = defn.all_parameters();
-                    let info = AssociatedTyInfo {
-                        id: next_item_id(),
-                        addl_parameter_kinds,
+
+                if d.flags.lang_phantom_data {
+                    // `PhantomData<T>` owns a `T` without any field.
+                    let well_formed = d.fields.is_empty() && match &d.parameter_kinds[..] {
+                        [parameter_kind] => parameter_kind.kind() == Kind::Ty,
+                        _ => false,
                     };
-                    associated_ty_infos.insert((item_id, defn.name.str), info);
+                    if !well_formed {
+                        bail!(ErrorKind::IllFormedLangItem(ir::LangItem::PhantomDataStruct))
+                    }
+
+                    use std::collections::btree_map::Entry::*;
+                    match lang_items.entry(ir::LangItem::PhantomDataStruct) {
+                        Vacant(entry) => { entry.insert(item_id); },
+                        Occupied(_) => {
+                            bail!(ErrorKind::DuplicateLangItem(ir::LangItem::PhantomDataStruct))
+                        }
+                    }
                 }
             }
-        }
+            Item::TraitDefn(ref d) => {
+                let trait_datum = match previous {
+                    Some(previous) => previous.trait_data[&item_id].clone(),
+                    None => {
+                        #[cfg(test)]
+                        LOWERED_ITEMS.with(|c| c.set(c.get() + 1));
+
+                        let mut trait_datum = d.lower_trait(item_id, &empty_env)?;
+                        trait_datum.binders.value.flags.upstream |= upstream;
+                        trait_datum
+                    }
+                };
+                trait_data.insert(item_id, trait_datum);
+                lowered_item_hashes.insert(item_id, hash);
 
-        let mut type_ids = BTreeMap::new();
-        let mut type_kinds = BTreeMap::new();
-        for (item, &item_id) in self.items.iter().zip(&item_ids) {
-            let k = match *item {
-                Item::StructDefn(ref d) => d.lower_type_kind()?,
-                Item::TraitDefn(ref d) => d.lower_type_kind()?,
-                Item::Impl(_) => continue,
-                Item::Clause(_) => continue,
-            };
-            type_ids.insert(k.name, item_id);
-            type_kinds.insert(item_id, k);
-        }
+                for defn in &d.assoc_ty_defns {
+                    let info = &associated_ty_infos[&(item_id, defn.name.str)];
+                    if let Some(previous) = previous {
+                        let datum = previous.associated_ty_data[&info.id].clone();
+                        associated_ty_data.insert(info.id, datum);
+                        continue;
+                    }
 
-        let mut struct_data = BTreeMap::new();
-        let mut trait_data = BTreeMap::new();
-        let mut impl_data = BTreeMap::new();
-        let mut associated_ty_data = BTreeMap::new();
-        let mut custom_clauses = Vec::new();
-        let mut lang_items = BTreeMap::new();
-        for (item, &item_id) in self.items.iter().zip(&item_ids) {
-            let empty_env = Env {
-                type_ids: &type_ids,
-                type_kinds: &type_kinds,
-                associated_ty_infos: &associated_ty_infos,
-                parameter_map: BTreeMap::new(),
-            };
+                    let mut parameter_kinds = defn.checked_parameters(&empty_env)?;
+                    parameter_kinds.extend(d.all_parameters());
+                    let env = empty_env.introduce(parameter_kinds.clone())?;
+
+                    associated_ty_data.insert(
+                        info.id,
+                        ir::AssociatedTyDatum {
+                            trait_id: item_id,
+                            id: info.id,
+                            name: defn.name.str,
+                            parameter_kinds: parameter_kinds,
+                            bounds: defn.bounds.lower(&env)?,
+                            where_clauses: defn.where_clauses.lower(&env)?,
+                        },
+                    );
+                }
+
+                if d.flags.deref {
+                    // The `Derefs` clauses project out of `Deref` with
+                    // nothing but the self type, so anything else would
+                    // build an ill-kinded projection.
+                    let well_formed = d.parameter_kinds.is_empty() && match &d.assoc_ty_defns[..] {
+                        [defn] => defn.parameter_kinds.is_empty(),
+                        _ => false,
+                    };
+                    if !well_formed {
+                        bail!(ErrorKind::IllFormedLangItem(ir::LangItem::DerefTrait))
+                    }
 
-            match *item {
-                Item::StructDefn(ref d) => {
-                    struct_data.insert(item_id, d.lower_struct(item_id, &empty_env)?);
+                    use std::collections::btree_map::Entry::*;
+                    match lang_items.entry(ir::LangItem::DerefTrait) {
+                        Vacant(entry) => { entry.insert(item_id); },
+                        Occupied(_) => {
+                            bail!(ErrorKind::DuplicateLangItem(ir::LangItem::DerefTrait))
+                        }
+                    }
                 }
-                Item::TraitDefn(ref d) => {
-                    trait_data.insert(item_id, d.lower_trait(item_id, &empty_env)?);
-
-                    for defn in &d.assoc_ty_defns {
-                        let info = &associated_ty_infos[&(item_id, defn.name.str)];
-
-                        let mut parameter_kinds = defn.all_parameters();
-                        parameter_kinds.extend(d.all_parameters());
-                        let env = empty_env.introduce(parameter_kinds.clone())?;
-
-                        associated_ty_data.insert(
-                            info.id,
-                            ir::AssociatedTyDatum {
-                                trait_id: item_id,
-                                id: info.id,
-                                name: defn.name.str,
-                                parameter_kinds: parameter_kinds,
-                                bounds: defn.bounds.lower(&env)?,
-                                where_clauses: defn.where_clauses.lower(&env)?,
-                            },
-                        );
+
+                if d.flags.sync {
+                    // `#[interior_mut]` structs only opt out of the
+                    // default impls of `Sync`, so it must have some.
+                    if !d.flags.auto || !d.parameter_kinds.is_empty() {
+                        bail!(ErrorKind::IllFormedLangItem(ir::LangItem::SyncTrait))
                     }
 
-                    if d.flags.deref {
-                        use std::collections::btree_map::Entry::*;
-                        match lang_items.entry(ir::LangItem::DerefTrait) {
-                            Vacant(entry) => { entry.insert(item_id); },
-                            Occupied(_) => {
-                                bail!(ErrorKind::DuplicateLangItem(ir::LangItem::DerefTrait))
-                            }
+                    use std::collections::btree_map::Entry::*;
+                    match lang_items.entry(ir::LangItem::SyncTrait) {
+                        Vacant(entry) => { entry.insert(item_id); },
+                        Occupied(_) => {
+                            bail!(ErrorKind::DuplicateLangItem(ir::LangItem::SyncTrait))
                         }
                     }
                 }
-                Item::Impl(ref d) => {
-                    impl_data.insert(item_id, d.lower_impl(&empty_env)?);
+            }
+            Item::Impl(ref d) => {
+                let impl_datum = match previous {
+                    Some(previous) => previous.impl_data[&item_id].clone(),
+                    None => {
+                        #[cfg(test)]
+                        LOWERED_ITEMS.with(|c| c.set(c.get() + 1));
+
+                        let mut impl_datum = d.lower_impl(&empty_env)?;
+                        if upstream {
+                            impl_datum.binders.value.impl_type = ir::ImplType::External;
+                        }
+                        impl_datum
+                    }
+                };
+                impl_data.insert(item_id, impl_datum);
+                lowered_item_hashes.insert(item_id, hash);
+                if let Some(ref name) = d.name {
+                    add_item_name(&mut item_names, name, ir::NamedItem::Impl(item_id))?;
                 }
-                Item::Clause(ref clause) => {
-                    custom_clauses.extend(clause.lower_clause(&empty_env)?);
+            }
+            Item::Clause(ref clause) => {
+                let start = custom_clauses.len();
+                custom_clauses.extend(clause.lower_clause(&empty_env)?);
+                if let Some(ref name) = clause.name {
+                    let clauses = ir::NamedItem::CustomClauses(start..custom_clauses.len());
+                    add_item_name(&mut item_names, name, clauses)?;
                 }
             }
+            Item::ExternalPredicateDefn(_) => {}
+            Item::ConstDefn(ref d) => {
+                let env = empty_env.introduce(d.checked_parameters(&empty_env)?)?;
+                d.ty.lower(&env)?;
+            }
+            Item::SyntheticTyDefn(ref d) => {
+                let synthetic_ty_datum = match previous {
+                    Some(previous) => previous.synthetic_ty_data[&item_id].clone(),
+                    None => {
+                        #[cfg(test)]
+                        LOWERED_ITEMS.with(|c| c.set(c.get() + 1));
+
+                        let mut synthetic_ty_datum = d.lower_synthetic_ty(item_id, &empty_env)?;
+                        synthetic_ty_datum.binders.value.upstream |= upstream;
+                        synthetic_ty_datum
+                    }
+                };
+                synthetic_ty_data.insert(item_id, synthetic_ty_datum);
+                lowered_item_hashes.insert(item_id, hash);
+            }
         }
+    }
 
-        let mut program = ir::Program {
-            type_ids,
-            type_kinds,
-            struct_data,
-            trait_data,
-            impl_data,
-            associated_ty_data,
-            custom_clauses,
-            lang_items,
-            default_impl_data: Vec::new(),
+    if solver_choice.simplifies_goals() {
+        for clause in &mut custom_clauses {
+            let implication = match clause {
+                ir::ProgramClause::Implies(implication) => implication,
+                ir::ProgramClause::ForAll(implication) => &mut implication.value,
+            };
+            for condition in &mut implication.conditions {
+                *condition = condition.simplify();
+            }
+        }
+    }
+
+    let mut program = ir::Program {
+        type_ids: type_ids.into(),
+        type_kinds: type_kinds.into(),
+        struct_data: struct_data.into(),
+        synthetic_ty_data: synthetic_ty_data.into(),
+        trait_data: trait_data.into(),
+        impl_data: impl_data.into(),
+        associated_ty_data: associated_ty_data.into(),
+        custom_clauses: custom_clauses.into(),
+        lang_items,
+        default_impl_data: Default::default(),
+        elaborated_where_clauses: Default::default(),
+        crate_names,
+        item_crates: item_crates.into(),
+        local_crate,
+        item_hashes: lowered_item_hashes.into(),
+        test_reflection: crates.iter().any(|&(_, program)| program.test_reflection),
+        item_names: item_names.into(),
+        const_overflow: solver_choice.const_overflow(),
+    };
+
+    program.check_kinds()?;
+    program.elaborate_supertraits();
+    program.add_default_impls();
+    program.record_specialization_priorities(solver_choice)?;
+    program.verify_well_formedness(solver_choice)?;
+    program.perform_orphan_check(solver_choice)?;
+    Ok(program)
+}
+
+/// Hands out the ids of the items of a program. When a program is
+/// lowered for the first time, items are numbered by their position.
+/// When it is lowered again, each named item keeps the id it had in the
+/// previous program, and so does each impl which is unchanged, so that
+/// the datums of the previous program -- which refer to items by id --
+/// remain valid. Any other item gets an id the previous program does
+/// not use.
+struct ItemIds {
+    next_index: usize,
+
+    /// The ids of the named items of the previous program, and of its
+    /// associated types, which are not claimed yet.
+    named: BTreeMap<ir::Identifier, ir::ItemId>,
+    associated_tys: BTreeMap<(ir::ItemId, ir::Identifier), ir::ItemId>,
+
+    /// The ids of the impls of the previous program which are not
+    /// claimed yet, by the `content_hash` of the impl.
+    impls: BTreeMap<u64, Vec<ir::ItemId>>,
+}
+
+impl ItemIds {
+    fn new(previous: Option<&ir::Program>) -> Self {
+        let mut ids = ItemIds {
+            next_index: 0,
+            named: BTreeMap::new(),
+            associated_tys: BTreeMap::new(),
+            impls: BTreeMap::new(),
         };
+        if let Some(previous) = previous {
+            // Every item of the program is defined by some crate.
+            ids.next_index = previous.item_crates.keys().map(|id| id.index + 1).max().unwrap_or(0);
+            ids.named = (*previous.type_ids).clone();
+            ids.associated_tys = previous
+                .associated_ty_data
+                .values()
+                .map(|datum| ((datum.trait_id, datum.name), datum.id))
+                .collect();
+            for &id in previous.impl_data.keys() {
+                let hash = previous.item_hashes[&id];
+                ids.impls.entry(hash).or_insert_with(Vec::new).push(id);
+            }
+        }
+        ids
+    }
+
+    fn fresh(&mut self) -> ir::ItemId {
+        let id = ir::ItemId { index: self.next_index };
+        self.next_index += 1;
+        id
+    }
 
-        program.add_default_impls();
-        program.record_specialization_priorities(solver_choice)?;
-        program.verify_well_formedness(solver_choice)?;
-        program.perform_orphan_check(solver_choice)?;
-        Ok(program)
+    fn item_id(&mut self, item: &Item, hash: u64) -> ir::ItemId {
+        let previous = match *item {
+            Item::StructDefn(StructDefn { name, .. })
+            | Item::TraitDefn(TraitDefn { name, .. })
+            | Item::ExternalPredicateDefn(ExternalPredicateDefn { name, .. })
+            | Item::ConstDefn(ConstDefn { name, .. })
+            | Item::SyntheticTyDefn(SyntheticTyDefn { name, .. }) => self.named.remove(&name.str),
+            Item::Impl(_) => self.impls.get_mut(&hash).and_then(|ids| ids.pop()),
+            Item::Clause(_) => None,
+        };
+        previous.unwrap_or_else(|| self.fresh())
     }
+
+    fn associated_ty_id(&mut self, trait_id: ir::ItemId, name: ir::Identifier) -> ir::ItemId {
+        match self.associated_tys.remove(&(trait_id, name)) {
+            Some(id) => id,
+            None => self.fresh(),
+        }
+    }
+}
+
+/// Records that `item` was given `name` with `#[name = "..."]`; no two
+/// items may share a name.
+fn add_item_name(
+    item_names: &mut BTreeMap<String, ir::NamedItem>,
+    name: &str,
+    item: ir::NamedItem,
+) -> Result<()> {
+    if item_names.insert(name.to_string(), item).is_some() {
+        bail!(ErrorKind::DuplicateItemName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// The `AssociatedTyInfos` the datums of `program` were lowered with.
+fn previous_associated_ty_infos(program: &ir::Program) -> AssociatedTyInfos {
+    program
+        .associated_ty_data
+        .values()
+        .map(|datum| {
+            // The parameters of the trait, including `Self`, come last.
+            let num_trait_parameters = program.type_kinds[&datum.trait_id].binders.binders.len() + 1;
+            let num_addl_parameters = datum.parameter_kinds.len() - num_trait_parameters;
+            let info = AssociatedTyInfo {
+                id: datum.id,
+                addl_parameter_kinds: datum.parameter_kinds[..num_addl_parameters].to_vec(),
+            };
+            ((datum.trait_id, datum.name), info)
+        })
+        .collect()
 }
 
 trait LowerTypeKind {
@@ -245,6 +664,13 @@ trait LowerTypeKind {
 trait LowerParameterMap {
     fn synthetic_parameters(&self) -> Option<ir::ParameterKind<ir::Identifier>>;
     fn declared_parameters(&self) -> &[ParameterKind];
+    /// Like `all_parameters`, once the declared parameters are checked
+    /// not to shadow any item named in `env`.
+    fn checked_parameters(&self, env: &Env) -> Result<Vec<ir::ParameterKind<ir::Identifier>>> {
+        env.check_parameter_names(self.declared_parameters())?;
+        Ok(self.all_parameters())
+    }
+
     fn all_parameters(&self) -> Vec<ir::ParameterKind<ir::Identifier>> {
         self.synthetic_parameters()
             .into_iter()
@@ -338,6 +764,16 @@ impl LowerParameterMap for TraitDefn {
     }
 }
 
+impl LowerParameterMap for ConstDefn {
+    fn synthetic_parameters(&self) -> Option<ir::ParameterKind<ir::Identifier>> {
+        None
+    }
+
+    fn declared_parameters(&self) -> &[ParameterKind] {
+        &self.parameter_kinds
+    }
+}
+
 impl LowerParameterMap for Clause {
     fn synthetic_parameters(&self) -> Option<ir::ParameterKind<ir::Identifier>> {
         None
@@ -357,6 +793,7 @@ impl LowerParameterKind for ParameterKind {
         match *self {
             ParameterKind::Ty(ref n) => ir::ParameterKind::Ty(n.str),
             ParameterKind::Lifetime(ref n) => ir::ParameterKind::Lifetime(n.str),
+            ParameterKind::Const(ref n) => ir::ParameterKind::Const(n.str),
         }
     }
 }
@@ -371,6 +808,22 @@ trait LowerWhereClauses {
 
 impl LowerTypeKind for StructDefn {
     fn lower_type_kind(&self) -> Result<ir::TypeKind> {
+        let const_defaults = self.const_defaults
+            .iter()
+            .skip_while(|default| default.is_none())
+            .map(|default| match *default {
+                Some(Const::Value(ref value)) => {
+                    Ok(ir::Const::Value(consts::evaluate(&ConstExpr::Value(value.clone()))?))
+                }
+                Some(Const::Expr(ref expr)) => Ok(ir::Const::Value(consts::evaluate(expr)?)),
+                Some(Const::Elided) => bail!("`_` is not allowed as a default"),
+                Some(Const::Unevaluated { .. }) => {
+                    bail!("named constants are not allowed as defaults")
+                }
+                None => bail!("parameters with defaults must come last"),
+            })
+            .collect::<Result<_>>()?;
+
         Ok(ir::TypeKind {
             sort: ir::TypeSort::Struct,
             name: self.name.str,
@@ -378,6 +831,7 @@ impl LowerTypeKind for StructDefn {
                 binders: self.all_parameters().anonymize(),
                 value: (),
             },
+            const_defaults,
         })
     }
 }
@@ -399,6 +853,62 @@ impl LowerTypeKind for TraitDefn {
                 binders: binders.anonymize(),
                 value: (),
             },
+            const_defaults: vec![],
+        })
+    }
+}
+
+/// The kinds of parameters declared without names, as in
+/// `external predicate Foo(type);`.
+fn lower_kinds(parameter_kinds: &[Kind]) -> Vec<ir::ParameterKind<()>> {
+    parameter_kinds
+        .iter()
+        .map(|kind| match kind {
+            Kind::Ty => ir::ParameterKind::Ty(()),
+            Kind::Lifetime => ir::ParameterKind::Lifetime(()),
+            Kind::Const => ir::ParameterKind::Const(()),
+        })
+        .collect()
+}
+
+impl LowerTypeKind for ExternalPredicateDefn {
+    fn lower_type_kind(&self) -> Result<ir::TypeKind> {
+        Ok(ir::TypeKind {
+            sort: ir::TypeSort::ExternalPredicate,
+            name: self.name.str,
+            binders: ir::Binders {
+                binders: lower_kinds(&self.parameter_kinds),
+                value: (),
+            },
+            const_defaults: vec![],
+        })
+    }
+}
+
+impl LowerTypeKind for SyntheticTyDefn {
+    fn lower_type_kind(&self) -> Result<ir::TypeKind> {
+        Ok(ir::TypeKind {
+            sort: ir::TypeSort::Synthetic,
+            name: self.name.str,
+            binders: ir::Binders {
+                binders: lower_kinds(&self.parameter_kinds),
+                value: (),
+            },
+            const_defaults: vec![],
+        })
+    }
+}
+
+impl LowerTypeKind for ConstDefn {
+    fn lower_type_kind(&self) -> Result<ir::TypeKind> {
+        Ok(ir::TypeKind {
+            sort: ir::TypeSort::Const,
+            name: self.name.str,
+            binders: ir::Binders {
+                binders: self.all_parameters().anonymize(),
+                value: (),
+            },
+            const_defaults: vec![],
         })
     }
 }
@@ -434,7 +944,9 @@ trait LowerWhereClause<T> {
     /// Lower from an AST `where` clause to an internal IR.
     /// Some AST `where` clauses can lower to multiple ones, this is why we return a `Vec`.
     /// As for now, this is the only the case for `where T: Foo<Item = U>` which lowers to
-    /// `Implemented(T: Foo)` and `ProjectionEq(<T as Foo>::Item = U)`.
+    /// `Implemented(T: Foo)` and `ProjectionEq(<T as Foo>::Item = U)`, and for
+    /// `where T: Foo<Item: Bar>` which lowers to `Implemented(T: Foo)` and
+    /// `Implemented(<T as Foo>::Item: Bar)`.
     fn lower(&self, env: &Env) -> Result<Vec<T>>;
 }
 
@@ -456,13 +968,85 @@ impl LowerWhereClause<ir::WhereClause> for WhereClause {
                     projection.trait_ref.lower(env)?
                 ),
             ],
+            WhereClause::AssocTyBounds {
+                trait_ref,
+                assoc_ty_bounds,
+            } => {
+                let mut where_clauses = vec![ir::WhereClause::Implemented(trait_ref.lower(env)?)];
+                for assoc_ty_bound in assoc_ty_bounds {
+                    for where_clause in assoc_ty_bound_where_clauses(trait_ref, assoc_ty_bound) {
+                        where_clauses.extend(where_clause.lower(env)?);
+                    }
+                }
+                where_clauses
+            }
         };
         Ok(where_clauses)
     }
 }
 
+/// The `where` clause that `bound` puts on `self_ty`: `T: Iterator` for
+/// the bound `Iterator` on `T`.
+fn trait_bound_where_clause(self_ty: Ty, bound: &TraitBound) -> WhereClause {
+    let trait_ref = trait_bound_trait_ref(self_ty, bound);
+    if bound.assoc_ty_bounds.is_empty() {
+        WhereClause::Implemented { trait_ref }
+    } else {
+        WhereClause::AssocTyBounds {
+            trait_ref,
+            assoc_ty_bounds: bound.assoc_ty_bounds.clone(),
+        }
+    }
+}
+
+/// The trait reference `self_ty: Trait<..>` of a trait bound.
+fn trait_bound_trait_ref(self_ty: Ty, bound: &TraitBound) -> TraitRef {
+    TraitRef {
+        trait_name: bound.trait_name,
+        args: iter::once(Parameter::Ty(self_ty))
+            .chain(bound.args_no_self.iter().cloned())
+            .collect(),
+    }
+}
+
+/// The `where` clause `self_ty: Bound` for an inline bound.
+fn inline_bound_where_clause(self_ty: Ty, bound: &InlineBound) -> WhereClause {
+    match bound {
+        InlineBound::TraitBound(bound) => trait_bound_where_clause(self_ty, bound),
+        InlineBound::ProjectionEqBound(bound) => WhereClause::ProjectionEq {
+            projection: ProjectionTy {
+                trait_ref: trait_bound_trait_ref(self_ty, &bound.trait_bound),
+                name: bound.name,
+                args: bound.args.clone(),
+            },
+            ty: bound.value.clone(),
+        },
+    }
+}
+
+/// The `where` clauses that the bounds on an associated type of
+/// `trait_ref` are sugar for: `<T as Iterator>::Item: Clone` for the
+/// `Item: Clone` of `T: Iterator<Item: Clone>`. The bounds may have bounds
+/// on associated types of their own, which are desugared in turn when
+/// the clauses are lowered.
+fn assoc_ty_bound_where_clauses(trait_ref: &TraitRef, bound: &AssocTyBound) -> Vec<WhereClause> {
+    let projection = Ty::Projection {
+        proj: ProjectionTy {
+            trait_ref: trait_ref.clone(),
+            name: bound.name,
+            args: bound.args.clone(),
+        },
+    };
+    bound
+        .bounds
+        .iter()
+        .map(|bound| trait_bound_where_clause(projection.clone(), bound))
+        .collect()
+}
+
 impl LowerWhereClause<ir::QuantifiedWhereClause> for QuantifiedWhereClause {
     fn lower(&self, env: &Env) -> Result<Vec<ir::QuantifiedWhereClause>> {
+        env.check_parameter_names(&self.parameter_kinds)?;
         let parameter_kinds = self.parameter_kinds.iter().map(|pk| pk.lower());
         let binders = env.in_binders(parameter_kinds, |env| {
             Ok(self.where_clause.lower(env)?)
@@ -536,6 +1120,40 @@ impl LowerDomainGoal for DomainGoal {
             DomainGoal::DownstreamType { ty } => vec![
                 ir::DomainGoal::DownstreamType(ty.lower(env)?)
             ],
+            DomainGoal::External { predicate, args } => {
+                let id = match env.lookup(*predicate)? {
+                    NameLookup::Type(id) => id,
+                    NameLookup::Parameter(_) => bail!(ErrorKind::NotExternalPredicate(*predicate)),
+                };
+
+                let k = env.type_kind(id);
+                if k.sort != ir::TypeSort::ExternalPredicate {
+                    bail!(ErrorKind::NotExternalPredicate(*predicate));
+                }
+
+                if k.binders.len() != args.len() {
+                    bail!(ErrorKind::IncorrectNumberOfTypeParameters(
+                        *predicate,
+                        k.binders.len(),
+                        args.len()
+                    ))
+                }
+
+                let parameters = args.iter()
+                    .map(|a| Ok(a.lower(env)?))
+                    .collect::<Result<Vec<_>>>()?;
+                check_parameter_kinds(
+                    predicate.str,
+                    Some(predicate.span),
+                    kinds(&k.binders.binders),
+                    kinds(&parameters),
+                )?;
+
+                vec![ir::DomainGoal::External(ir::ExternalGoal {
+                    predicate: id,
+                    parameters,
+                })]
+            }
         };
         Ok(goals)
     }
@@ -562,18 +1180,74 @@ impl LowerLeafGoal for LeafGoal {
                 a: ir::ParameterKind::Lifetime(a.lower(env)?),
                 b: ir::ParameterKind::Lifetime(b.lower(env)?),
             }.cast()],
+            LeafGoal::Reflection { .. } => bail!(ErrorKind::InvalidReflectionGoal(
+                "goals about the program itself must be asked on their own"
+            )),
         };
         Ok(goals)
     }
 }
 
+trait LowerSyntheticTyDefn {
+    fn lower_synthetic_ty(&self, item_id: ir::ItemId, env: &Env)
+        -> Result<ir::SyntheticTyDatum>;
+}
+
+impl LowerSyntheticTyDefn for SyntheticTyDefn {
+    fn lower_synthetic_ty(&self, item_id: ir::ItemId, env: &Env)
+        -> Result<ir::SyntheticTyDatum>
+    {
+        let binders = lower_kinds(&self.parameter_kinds);
+        let self_ty = ir::ApplicationTy {
+            name: synthetic_ty_name(item_id, binders.len()),
+            parameters: binders.iter().zip(0..).map(|p| p.to_parameter()).collect(),
+        };
+
+        // The parameters are unnamed where the type is declared, so
+        // the constituents name them afresh, with the same kinds.
+        let constituents = match self.constituents {
+            Some(ref constituents) => {
+                check_parameter_kinds(
+                    self.name.str,
+                    Some(self.name.span),
+                    self.parameter_kinds.iter().cloned(),
+                    kinds(&constituents.parameter_kinds),
+                )?;
+                env.check_parameter_names(&constituents.parameter_kinds)?;
+                let env = env.introduce(constituents.parameter_kinds.iter().map(|p| p.lower()))?;
+                constituents.tys.iter().map(|ty| ty.lower(&env)).collect::<Result<_>>()?
+            }
+            None => vec![],
+        };
+
+        Ok(ir::SyntheticTyDatum {
+            binders: ir::Binders {
+                binders,
+                value: ir::SyntheticTyDatumBound {
+                    self_ty,
+                    constituents,
+                    upstream: false,
+                },
+            },
+        })
+    }
+}
+
+/// The name of the synthetic type `id`, which takes `arity` parameters.
+fn synthetic_ty_name(id: ir::ItemId, arity: usize) -> ir::TypeName {
+    ir::TypeName::Synthetic {
+        id,
+        kind: ir::SyntheticKind { arity },
+    }
+}
+
 trait LowerStructDefn {
     fn lower_struct(&self, item_id: ir::ItemId, env: &Env) -> Result<ir::StructDatum>;
 }
 
 impl LowerStructDefn for StructDefn {
     fn lower_struct(&self, item_id: ir::ItemId, env: &Env) -> Result<ir::StructDatum> {
-        let binders = env.in_binders(self.all_parameters(), |env| {
+        let binders = env.in_binders(self.checked_parameters(env)?, |env| {
             let self_ty = ir::ApplicationTy {
                 name: ir::TypeName::ItemId(item_id),
                 parameters: self.all_parameters()
@@ -584,20 +1258,39 @@ impl LowerStructDefn for StructDefn {
                     .collect(),
             };
 
-            if self.flags.fundamental && self_ty.len_type_parameters() != 1 {
+            // The `Box` lang item is always fundamental.
+            let fundamental = self.flags.fundamental || self.flags.lang_box;
+
+            if fundamental && self_ty.len_type_parameters() != 1 {
                 bail!("Only fundamental types with a single parameter are supported");
             }
 
             let fields: Result<_> = self.fields.iter().map(|f| f.ty.lower(env)).collect();
             let where_clauses = self.lower_where_clauses(env)?;
 
+            let no_auto_traits = self.no_auto_traits
+                .iter()
+                .map(|&trait_name| {
+                    let id = match env.lookup(trait_name)? {
+                        NameLookup::Type(id) => id,
+                        NameLookup::Parameter(_) => bail!(ErrorKind::NotTrait(trait_name)),
+                    };
+                    if env.type_kind(id).sort != ir::TypeSort::Trait {
+                        bail!(ErrorKind::NotTrait(trait_name));
+                    }
+                    Ok(id)
+                })
+                .collect::<Result<_>>()?;
+
             Ok(ir::StructDatumBound {
                 self_ty,
                 fields: fields?,
                 where_clauses,
                 flags: ir::StructFlags {
                     upstream: self.flags.upstream,
-                    fundamental: self.flags.fundamental,
+                    fundamental,
+                    interior_mut: self.flags.interior_mut,
+                    no_auto_traits,
                 },
             })
         })?;
@@ -625,9 +1318,15 @@ impl LowerTraitRef for TraitRef {
         let without_self = TraitBound {
             trait_name: self.trait_name,
             args_no_self: self.args.iter().cloned().skip(1).collect(),
+            assoc_ty_bounds: vec![],
         }.lower(env)?;
 
         let self_parameter = self.args[0].lower(env)?;
+        check_type_kinds(
+            "incorrect kind for self type",
+            &ir::ParameterKind::<(), (), ()>::Ty(()),
+            &self_parameter,
+        )?;
         Ok(without_self.as_trait_ref(self_parameter.ty().unwrap()))
     }
 }
@@ -638,6 +1337,12 @@ trait LowerTraitBound {
 
 impl LowerTraitBound for TraitBound {
     fn lower(&self, env: &Env) -> Result<ir::TraitBound> {
+        // Supertraits and `where` clauses desugar the bounds on associated
+        // types before they get here; the bounds of associated types do not.
+        if !self.assoc_ty_bounds.is_empty() {
+            bail!("associated type bounds are not allowed in the bounds of an associated type");
+        }
+
         let id = match env.lookup(self.trait_name)? {
             NameLookup::Type(id) => id,
             NameLookup::Parameter(_) => bail!(ErrorKind::NotTrait(self.trait_name)),
@@ -653,17 +1358,13 @@ impl LowerTraitBound for TraitBound {
                              .map(|a| Ok(a.lower(env)?))
                              .collect::<Result<Vec<_>>>()?;
 
-        if parameters.len() != k.binders.len() {
-            bail!(
-                "wrong number of parameters, expected `{:?}`, got `{:?}`",
-                k.binders.len(),
-                parameters.len()
-            )
-        }
-
-        for (binder, param) in k.binders.binders.iter().zip(parameters.iter()) {
-            check_type_kinds("incorrect kind for trait parameter", binder, param)?;
-        }
+        // The self type is not known yet, but counts as a parameter.
+        check_parameter_kinds(
+            self.trait_name.str,
+            Some(self.trait_name.span),
+            iter::once(Kind::Ty).chain(kinds(&k.binders.binders)),
+            iter::once(Kind::Ty).chain(kinds(&parameters)),
+        )?;
 
         Ok(ir::TraitBound {
             trait_id: id,
@@ -685,17 +1386,12 @@ impl LowerProjectionEqBound for ProjectionEqBound {
         };
         let args: Vec<_> = try!(self.args.iter().map(|a| a.lower(env)).collect());
 
-        if args.len() != info.addl_parameter_kinds.len() {
-            bail!(
-                "wrong number of parameters for associated type (expected {}, got {})",
-                info.addl_parameter_kinds.len(),
-                args.len()
-            )
-        }
-
-        for (param, arg) in info.addl_parameter_kinds.iter().zip(args.iter()) {
-            check_type_kinds("incorrect kind for associated type parameter", param, arg)?;
-        }
+        check_parameter_kinds(
+            self.name.str,
+            Some(self.name.span),
+            kinds(&info.addl_parameter_kinds),
+            kinds(&args),
+        )?;
 
         Ok(ir::ProjectionEqBound {
             trait_bound,
@@ -730,6 +1426,7 @@ trait LowerQuantifiedInlineBound {
 
 impl LowerQuantifiedInlineBound for QuantifiedInlineBound {
     fn lower(&self, env: &Env) -> Result<ir::QuantifiedInlineBound> {
+        env.check_parameter_names(&self.parameter_kinds)?;
         let parameter_kinds = self.parameter_kinds.iter().map(|pk| pk.lower());
         let binders = env.in_binders(parameter_kinds, |env| {
             Ok(self.bound.lower(env)?)
@@ -784,17 +1481,12 @@ impl LowerProjectionTy for ProjectionTy {
         };
         let mut args: Vec<_> = try!(args.iter().map(|a| a.lower(env)).collect());
 
-        if args.len() != info.addl_parameter_kinds.len() {
-            bail!(
-                "wrong number of parameters for associated type (expected {}, got {})",
-                info.addl_parameter_kinds.len(),
-                args.len()
-            )
-        }
-
-        for (param, arg) in info.addl_parameter_kinds.iter().zip(args.iter()) {
-            check_type_kinds("incorrect kind for associated type parameter", param, arg)?;
-        }
+        check_parameter_kinds(
+            name.str,
+            Some(name.span),
+            kinds(&info.addl_parameter_kinds),
+            kinds(&args),
+        )?;
 
         args.extend(trait_parameters);
 
@@ -828,21 +1520,8 @@ impl LowerTy for Ty {
     fn lower(&self, env: &Env) -> Result<ir::Ty> {
         match *self {
             Ty::Id { name } => match env.lookup(name)? {
-                NameLookup::Type(id) => {
-                    let k = env.type_kind(id);
-                    if k.binders.len() > 0 {
-                        bail!(ErrorKind::IncorrectNumberOfTypeParameters(
-                            name,
-                            k.binders.len(),
-                            0
-                        ))
-                    }
-
-                    Ok(ir::Ty::Apply(ir::ApplicationTy {
-                        name: ir::TypeName::ItemId(id),
-                        parameters: vec![],
-                    }))
-                }
+                // All of the parameters of the type must have defaults.
+                NameLookup::Type(_) => Ty::Apply { name, args: vec![] }.lower(env),
                 NameLookup::Parameter(d) => Ok(ir::Ty::Var(d)),
             },
 
@@ -852,8 +1531,13 @@ impl LowerTy for Ty {
                     NameLookup::Parameter(_) => bail!(ErrorKind::CannotApplyTypeParameter(name)),
                 };
 
+                // Trailing parameters with defaults may be omitted.
                 let k = env.type_kind(id);
-                if k.binders.len() != args.len() {
+                if k.sort == ir::TypeSort::ExternalPredicate || k.sort == ir::TypeSort::Const {
+                    bail!(ErrorKind::InvalidTypeName(name));
+                }
+                let num_required = k.binders.len() - k.const_defaults.len();
+                if args.len() < num_required || args.len() > k.binders.len() {
                     bail!(ErrorKind::IncorrectNumberOfTypeParameters(
                         name,
                         k.binders.len(),
@@ -861,16 +1545,31 @@ impl LowerTy for Ty {
                     ))
                 }
 
-                let parameters = args.iter()
+                let mut parameters = args.iter()
                     .map(|t| Ok(t.lower(env)?))
                     .collect::<Result<Vec<_>>>()?;
+                parameters.extend(
+                    k.const_defaults[args.len() - num_required..]
+                        .iter()
+                        .map(|c| ir::ParameterKind::Const(c.clone())),
+                );
+
+                check_parameter_kinds(
+                    name.str,
+                    Some(name.span),
+                    kinds(&k.binders.binders),
+                    kinds(&parameters),
+                )?;
 
-                for (param, arg) in k.binders.binders.iter().zip(args.iter()) {
-                    check_type_kinds("incorrect parameter kind", param, arg)?;
-                }
-
+                let name = match k.sort {
+                    ir::TypeSort::Synthetic => synthetic_ty_name(id, k.binders.len()),
+                    ir::TypeSort::Struct
+                    | ir::TypeSort::Trait
+                    | ir::TypeSort::ExternalPredicate
+                    | ir::TypeSort::Const => ir::TypeName::ItemId(id),
+                };
                 Ok(ir::Ty::Apply(ir::ApplicationTy {
-                    name: ir::TypeName::ItemId(id),
+                    name,
                     parameters: parameters,
                 }))
             }
@@ -909,8 +1608,75 @@ trait LowerParameter {
 impl LowerParameter for Parameter {
     fn lower(&self, env: &Env) -> Result<ir::Parameter> {
         match *self {
+            Parameter::Ty(Ty::Id { name }) => match env.lookup_const(name) {
+                Some(d) => Ok(ir::ParameterKind::Const(ir::Const::Var(d))),
+                None => Ok(ir::ParameterKind::Ty(Ty::Id { name }.lower(env)?)),
+            },
             Parameter::Ty(ref t) => Ok(ir::ParameterKind::Ty(t.lower(env)?)),
             Parameter::Lifetime(ref l) => Ok(ir::ParameterKind::Lifetime(l.lower(env)?)),
+            Parameter::Const(ref c) => Ok(ir::ParameterKind::Const(c.lower(env)?)),
+        }
+    }
+}
+
+trait LowerConst {
+    fn lower(&self, env: &Env) -> Result<ir::Const>;
+}
+
+impl LowerConst for Const {
+    fn lower(&self, env: &Env) -> Result<ir::Const> {
+        match *self {
+            Const::Value(ref value) => {
+                Ok(ir::Const::Value(consts::evaluate(&ConstExpr::Value(value.clone()))?))
+            }
+            Const::Expr(ref expr) => Ok(ir::Const::Value(consts::evaluate(expr)?)),
+
+            // Each elided const is a new variable, bound by an `exists`
+            // around the whole goal (see `lower_goal`): that is, it is
+            // bound outside of all of the parameters in scope.
+            Const::Elided => match env.elided_consts {
+                Some(count) => {
+                    let index = count.get();
+                    count.set(index + 1);
+                    Ok(ir::Const::Var(env.parameter_map.len() + index))
+                }
+                None => bail!("`_` is only allowed in goals"),
+            },
+
+            Const::Unevaluated { name, ref args } => {
+                let id = match env.lookup(name)? {
+                    NameLookup::Type(id) => id,
+                    NameLookup::Parameter(_) => bail!(ErrorKind::NotConst(name)),
+                };
+
+                let k = env.type_kind(id);
+                if k.sort != ir::TypeSort::Const {
+                    bail!(ErrorKind::NotConst(name));
+                }
+
+                if k.binders.len() != args.len() {
+                    bail!(ErrorKind::IncorrectNumberOfTypeParameters(
+                        name,
+                        k.binders.len(),
+                        args.len()
+                    ))
+                }
+
+                let parameters = args.iter()
+                    .map(|a| Ok(a.lower(env)?))
+                    .collect::<Result<Vec<_>>>()?;
+                check_parameter_kinds(
+                    name.str,
+                    Some(name.span),
+                    kinds(&k.binders.binders),
+                    kinds(&parameters),
+                )?;
+
+                Ok(ir::Const::Unevaluated(ir::UnevaluatedConst {
+                    const_id: id,
+                    parameters,
+                }))
+            }
         }
     }
 }
@@ -925,6 +1691,7 @@ impl LowerLifetime for Lifetime {
             Lifetime::Id { name } => match env.lookup_lifetime(name)? {
                 LifetimeLookup::Parameter(d) => Ok(ir::Lifetime::Var(d)),
             },
+            Lifetime::Static => Ok(ir::Lifetime::Static),
         }
     }
 }
@@ -935,7 +1702,9 @@ trait LowerImpl {
 
 impl LowerImpl for Impl {
     fn lower_impl(&self, empty_env: &Env) -> Result<ir::ImplDatum> {
-        let binders = empty_env.in_binders(self.all_parameters(), |env| {
+        let parameters = self.checked_parameters(empty_env)?;
+        let impl_binders = parameters.anonymize();
+        let binders = empty_env.in_binders(parameters, |env| {
             let trait_ref = self.trait_ref.lower(env)?;
 
             if !trait_ref.is_positive() && !self.assoc_ty_values.is_empty() {
@@ -947,7 +1716,7 @@ impl LowerImpl for Impl {
             let associated_ty_values = try!(
                 self.assoc_ty_values
                     .iter()
-                    .map(|v| v.lower(trait_id, env))
+                    .map(|v| v.lower(trait_id, &impl_binders, env))
                     .collect()
             );
             Ok(ir::ImplDatumBound {
@@ -972,7 +1741,7 @@ trait LowerClause {
 
 impl LowerClause for Clause {
     fn lower_clause(&self, env: &Env) -> Result<Vec<ir::ProgramClause>> {
-        let implications = env.in_binders(self.all_parameters(), |env| {
+        let implications = env.in_binders(self.checked_parameters(env)?, |env| {
             let consequences: Vec<ir::DomainGoal> = self.consequence.lower(env)?;
 
             let mut conditions: Vec<ir::Goal> = self.conditions
@@ -1010,20 +1779,35 @@ impl LowerClause for Clause {
 }
 
 trait LowerAssocTyValue {
-    fn lower(&self, trait_id: ir::ItemId, env: &Env) -> Result<ir::AssociatedTyValue>;
+    /// Lowers the value in `env`, the environment of its impl, whose
+    /// binders are `impl_binders`.
+    fn lower(
+        &self,
+        trait_id: ir::ItemId,
+        impl_binders: &[ir::ParameterKind<()>],
+        env: &Env,
+    ) -> Result<ir::AssociatedTyValue>;
 }
 
 impl LowerAssocTyValue for AssocTyValue {
-    fn lower(&self, trait_id: ir::ItemId, env: &Env) -> Result<ir::AssociatedTyValue> {
+    fn lower(
+        &self,
+        trait_id: ir::ItemId,
+        impl_binders: &[ir::ParameterKind<()>],
+        env: &Env,
+    ) -> Result<ir::AssociatedTyValue> {
         let info = &env.associated_ty_infos[&(trait_id, self.name.str)];
-        let value = env.in_binders(self.all_parameters(), |env| {
+        let value = env.in_binders(self.checked_parameters(env)?, |env| {
             Ok(ir::AssociatedTyValueBound {
                 ty: self.value.lower(env)?,
             })
         })?;
         Ok(ir::AssociatedTyValue {
             associated_ty_id: info.id,
-            value: value,
+            value: ir::Binders {
+                binders: impl_binders.to_vec(),
+                value,
+            },
         })
     }
 }
@@ -1034,7 +1818,7 @@ trait LowerTrait {
 
 impl LowerTrait for TraitDefn {
     fn lower_trait(&self, trait_id: ir::ItemId, env: &Env) -> Result<ir::TraitDatum> {
-        let binders = env.in_binders(self.all_parameters(), |env| {
+        let binders = env.in_binders(self.checked_parameters(env)?, |env| {
             let trait_ref = ir::TraitRef {
                 trait_id: trait_id,
                 parameters: self.parameter_refs(),
@@ -1044,14 +1828,47 @@ impl LowerTrait for TraitDefn {
                 if trait_ref.parameters.len() > 1 {
                     bail!("auto trait cannot have parameters");
                 }
-                if !self.where_clauses.is_empty() {
+                if !self.where_clauses.is_empty() || !self.supertraits.is_empty() {
                     bail!("auto trait cannot have where clauses");
                 }
             }
 
+            // `trait Foo: Bar` is sugar for `trait Foo where Self: Bar`, and
+            // is lowered as that `where` clause would be.
+            let mut where_clauses = vec![];
+            for supertrait in &self.supertraits {
+                let self_name = Identifier {
+                    str: intern(SELF),
+                    span: self.name.span,
+                };
+                let where_clause = QuantifiedWhereClause {
+                    parameter_kinds: supertrait.parameter_kinds.clone(),
+                    where_clause: inline_bound_where_clause(
+                        Ty::Id { name: self_name },
+                        &supertrait.bound,
+                    ),
+                };
+                where_clauses.extend(where_clause.lower(env)?);
+            }
+            where_clauses.extend(self.lower_where_clauses(env)?);
+
+            let fundamental_non_type = self.parameter_kinds
+                .iter()
+                .zip(&self.fundamental_parameters)
+                .any(|(kind, &fundamental)| fundamental && kind.kind() != Kind::Ty);
+            if fundamental_non_type {
+                bail!("only type parameters can be fundamental");
+            }
+
+            // The `Self` parameter is never fundamental.
+            let fundamental_parameters = iter::once(false)
+                .chain(self.fundamental_parameters.iter().cloned())
+                .collect();
+
             Ok(ir::TraitDatumBound {
                 trait_ref: trait_ref,
-                where_clauses: self.lower_where_clauses(env)?,
+                where_clauses,
+                fundamental_parameters,
                 flags: ir::TraitFlags {
                     auto: self.flags.auto,
                     marker: self.flags.marker,
@@ -1072,41 +1889,272 @@ pub trait LowerGoal<A> {
 
 impl LowerGoal<ir::Program> for Goal {
     fn lower(&self, program: &ir::Program) -> Result<Box<ir::Goal>> {
-        let associated_ty_infos: BTreeMap<_, _> = program
-            .associated_ty_data
-            .iter()
-            .map(|(&associated_ty_id, datum)| {
-                let trait_datum = &program.trait_data[&datum.trait_id];
-                let num_trait_params = trait_datum.binders.len();
-                let num_addl_params = datum.parameter_kinds.len() - num_trait_params;
-                let addl_parameter_kinds = datum.parameter_kinds[..num_addl_params].to_owned();
-                let info = AssociatedTyInfo {
-                    id: associated_ty_id,
-                    addl_parameter_kinds,
-                };
-                ((datum.trait_id, datum.name), info)
-            })
-            .collect();
+        // Universes can only be ascribed to the binders that are peeled.
+        if let Some(universe) = self.first_universe_ascription() {
+            bail!(ErrorKind::InvalidUniverseAscription(
+                universe,
+                "only the leading quantifiers of a peeled goal may have universes"
+            ));
+        }
+        Ok(lower_goal(self, program)?.0)
+    }
+}
+
+/// Lowers a goal, binding the consts elided in it with an `exists`
+/// around the whole goal. Also returns the number of elided consts.
+fn lower_goal(goal: &Goal, program: &ir::Program) -> Result<(Box<ir::Goal>, usize)> {
+    let elided_consts = Cell::new(0);
+    let associated_ty_infos = associated_ty_infos(program);
+    let env = Env {
+        type_ids: &program.type_ids,
+        type_kinds: &program.type_kinds,
+        associated_ty_infos: &associated_ty_infos,
+        parameter_map: BTreeMap::new(),
+        elided_consts: Some(&elided_consts),
+        const_overflow: program.const_overflow,
+    };
+
+    let goal = goal.lower(&env)?;
+    let num_elided = elided_consts.get();
+    if num_elided == 0 {
+        if let Err(err) = goal.check_variables() {
+            bail!("ill-formed goal: {}", err);
+        }
+        return Ok((goal, 0));
+    }
 
+    // The elided consts are bound outside of all the quantifiers of the
+    // goal, so it is only once they are that the goal can be checked.
+    let binders = vec![ir::ParameterKind::Const(()); num_elided];
+    match ir::Goal::quantify_checked(ir::QuantifierKind::Exists, binders, *goal) {
+        Ok(goal) => Ok((Box::new(goal), num_elided)),
+        Err(err) => bail!("ill-formed goal: {}", err),
+    }
+}
+
+/// The associated types of `program`, by trait and name, for lowering
+/// goals about the program.
+fn associated_ty_infos(
+    program: &ir::Program,
+) -> BTreeMap<(ir::ItemId, ir::Identifier), AssociatedTyInfo> {
+    program
+        .associated_ty_data
+        .iter()
+        .map(|(&associated_ty_id, datum)| {
+            let trait_datum = &program.trait_data[&datum.trait_id];
+            let num_trait_params = trait_datum.binders.len();
+            let num_addl_params = datum.parameter_kinds.len() - num_trait_params;
+            let addl_parameter_kinds = datum.parameter_kinds[..num_addl_params].to_owned();
+            let info = AssociatedTyInfo {
+                id: associated_ty_id,
+                addl_parameter_kinds,
+            };
+            ((datum.trait_id, datum.name), info)
+        })
+        .collect()
+}
+
+pub trait LowerReflectionGoal {
+    /// Lowers a goal about the program itself (see
+    /// `ir::ReflectionGoal`), or returns `None` if the goal is an
+    /// ordinary one. Such goals can only be asked on their own, and
+    /// only of programs marked `#[test_reflection]`.
+    fn lower_reflection(&self, program: &ir::Program) -> Result<Option<ir::ReflectionGoal>>;
+}
+
+impl LowerReflectionGoal for Goal {
+    fn lower_reflection(&self, program: &ir::Program) -> Result<Option<ir::ReflectionGoal>> {
+        let goal = if let Goal::Leaf(LeafGoal::Reflection { goal }) = self {
+            goal
+        } else {
+            return Ok(None);
+        };
+        if !program.test_reflection {
+            bail!(ErrorKind::InvalidReflectionGoal(
+                "the program is not marked `#[test_reflection]`"
+            ));
+        }
+
+        let associated_ty_infos = associated_ty_infos(program);
         let env = Env {
             type_ids: &program.type_ids,
             type_kinds: &program.type_kinds,
             associated_ty_infos: &associated_ty_infos,
             parameter_map: BTreeMap::new(),
+            elided_consts: None,
+            const_overflow: program.const_overflow,
+        };
+        let lower_trait = |trait_name: Identifier| -> Result<ir::ItemId> {
+            match env.lookup(trait_name)? {
+                NameLookup::Type(id) if env.type_kind(id).sort == ir::TypeSort::Trait => Ok(id),
+                NameLookup::Type(_) | NameLookup::Parameter(_) => {
+                    bail!(ErrorKind::NotTrait(trait_name))
+                }
+            }
         };
 
-        self.lower(&env)
+        let goal = match goal {
+            ReflectionGoal::HasImpl { trait_name, ty } => {
+                ir::ReflectionGoal::HasImpl(lower_trait(*trait_name)?, ty.lower(&env)?)
+            }
+            ReflectionGoal::ClauseCount { trait_name, count } => {
+                ir::ReflectionGoal::ClauseCount(lower_trait(*trait_name)?, *count)
+            }
+        };
+        Ok(Some(goal))
+    }
+}
+
+pub trait LowerPeeledGoal {
+    /// Lowers a goal and peels it (see `ir::Goal::into_peeled_goal`),
+    /// remembering the names of the `exists<>` binders that are peeled.
+    fn lower_peeled(&self, program: &ir::Program) -> Result<ir::PeeledGoal>;
+}
+
+impl LowerPeeledGoal for Goal {
+    fn lower_peeled(&self, program: &ir::Program) -> Result<ir::PeeledGoal> {
+        // Walk down the same quantifiers and implications that
+        // `into_peeled_goal` strips from the lowered goal, keeping track
+        // of the universe they would create: each `forall` creates a new
+        // one, unless it is ascribed a later universe, and `exists`
+        // binders live in the latest one, unless they are ascribed an
+        // earlier one.
+        let mut existential_names = vec![];
+        let mut universal_names = vec![];
+        let mut universes = vec![];
+        let mut max_universe = 0;
+        let mut goal = self;
+        loop {
+            match goal {
+                Goal::ForAll(binders, g) => {
+                    if !binders.is_empty() {
+                        let universe = ascribed_universe(binders)?;
+                        if let Some((id, ui)) = universe {
+                            if ui <= max_universe {
+                                bail!(ErrorKind::InvalidUniverseAscription(
+                                    id,
+                                    "a `forall` must create a universe later than those before it"
+                                ));
+                            }
+                        }
+                        max_universe = universe.map_or(max_universe + 1, |(_, ui)| ui);
+                        universes.push(universe.map(|(_, ui)| ir::UniverseIndex { counter: ui }));
+                    }
+                    universal_names.extend(
+                        binders.iter().map(|binder| binder.kind.lower().into_inner().to_string()),
+                    );
+                    goal = &**g;
+                }
+                Goal::Implies(_, g) | Goal::Compatible(g) => goal = &**g,
+                Goal::Exists(binders, g) => {
+                    if !binders.is_empty() {
+                        let universe = ascribed_universe(binders)?;
+                        if let Some((id, ui)) = universe {
+                            if ui > max_universe {
+                                bail!(ErrorKind::InvalidUniverseAscription(
+                                    id,
+                                    "an `exists` can only name universes created before it"
+                                ));
+                            }
+                        }
+                        universes.push(universe.map(|(_, ui)| ir::UniverseIndex { counter: ui }));
+                    }
+                    existential_names.extend(
+                        binders.iter().map(|binder| binder.kind.lower().into_inner().to_string()),
+                    );
+                    goal = &**g;
+                }
+                Goal::And(..) | Goal::Not(_) | Goal::Leaf(_) => break,
+            }
+        }
+        if let Some(universe) = goal.first_universe_ascription() {
+            bail!(ErrorKind::InvalidUniverseAscription(
+                universe,
+                "only the leading quantifiers of a peeled goal may have universes"
+            ));
+        }
+
+        // The consts elided in the goal are bound outside of everything else.
+        let (goal, num_elided) = lower_goal(self, program)?;
+        let names = (0..num_elided).map(|_| "_".to_string()).chain(existential_names).collect();
+        if num_elided > 0 {
+            universes.insert(0, None);
+        }
+        Ok(goal.into_named_peeled_goal(names, universal_names, universes))
+    }
+}
+
+/// The universe ascribed to the binders of a quantifier, if any, along
+/// with its name. Universes are named `U0`, `U1`, etc, and all of the
+/// binders of a quantifier live in the same one.
+fn ascribed_universe(binders: &[GoalBinder]) -> Result<Option<(Identifier, usize)>> {
+    let universe = |binder: &GoalBinder| -> Result<Option<(Identifier, usize)>> {
+        let id = match binder.universe {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        let name = id.str.to_string();
+        match name.trim_start_matches('U').parse() {
+            Ok(ui) if name.starts_with('U') => Ok(Some((id, ui))),
+            Ok(_) | Err(_) => bail!(ErrorKind::InvalidUniverseAscription(
+                id,
+                "universes are named `U0`, `U1`, ...",
+            )),
+        }
+    };
+
+    let first = universe(&binders[0])?;
+    for binder in &binders[1..] {
+        let other = universe(binder)?;
+        if other.map(|(_, ui)| ui) != first.map(|(_, ui)| ui) {
+            let id = other.or(first).unwrap().0;
+            bail!(ErrorKind::InvalidUniverseAscription(
+                id,
+                "all the binders of a quantifier live in the same universe"
+            ));
+        }
+    }
+    Ok(first)
+}
+
+trait UniverseAscriptions {
+    /// The first universe ascribed to a binder anywhere in `self`.
+    fn first_universe_ascription(&self) -> Option<Identifier>;
+}
+
+impl UniverseAscriptions for Goal {
+    fn first_universe_ascription(&self) -> Option<Identifier> {
+        match self {
+            Goal::ForAll(binders, g) | Goal::Exists(binders, g) => binders
+                .iter()
+                .filter_map(|binder| binder.universe)
+                .next()
+                .or_else(|| g.first_universe_ascription()),
+            Goal::Implies(clauses, g) => clauses
+                .iter()
+                .flat_map(|clause| &clause.conditions)
+                .filter_map(|condition| condition.first_universe_ascription())
+                .next()
+                .or_else(|| g.first_universe_ascription()),
+            Goal::And(g1, g2) => g1
+                .first_universe_ascription()
+                .or_else(|| g2.first_universe_ascription()),
+            Goal::Not(g) | Goal::Compatible(g) => g.first_universe_ascription(),
+            Goal::Leaf(_) => None,
+        }
     }
 }
 
 impl<'k> LowerGoal<Env<'k>> for Goal {
     fn lower(&self, env: &Env<'k>) -> Result<Box<ir::Goal>> {
         match self {
-            Goal::ForAll(ids, g) => {
-                g.lower_quantified(env, ir::QuantifierKind::ForAll, ids)
+            Goal::ForAll(binders, g) => {
+                let parameter_kinds: Vec<_> = binders.iter().map(|binder| binder.kind).collect();
+                g.lower_quantified(env, ir::QuantifierKind::ForAll, &parameter_kinds)
             }
-            Goal::Exists(ids, g) => {
-                g.lower_quantified(env, ir::QuantifierKind::Exists, ids)
+            Goal::Exists(binders, g) => {
+                let parameter_kinds: Vec<_> = binders.iter().map(|binder| binder.kind).collect();
+                g.lower_quantified(env, ir::QuantifierKind::Exists, &parameter_kinds)
             }
             Goal::Implies(hyp, g) => {
                 // We "elaborate" implied bounds by lowering goals like `T: Trait` and
@@ -1126,8 +2174,22 @@ impl<'k> LowerGoal<Env<'k>> for Goal {
             Goal::Not(g) => Ok(Box::new(ir::Goal::Not(g.lower(env)?))),
             Goal::Compatible(g) => Ok(Box::new(g.lower(env)?.compatible())),
             Goal::Leaf(leaf) => {
+                let leaves = match leaf.lower(env) {
+                    Ok(leaves) => leaves,
+                    Err(err) => {
+                        // Unless the overflow is an error, the leaf can
+                        // be neither proven nor disproven.
+                        if let ErrorKind::OverflowingConst(..) = *err.kind() {
+                            if env.const_overflow == ConstOverflow::CannotProve {
+                                return Ok(Box::new(ir::Goal::CannotProve(())));
+                            }
+                        }
+                        return Err(err);
+                    }
+                };
+
                 // A where clause can lower to multiple leaf goals; wrap these in Goal::And.
-                let leaves = leaf.lower(env)?.into_iter().map(ir::Goal::Leaf);
+                let leaves = leaves.into_iter().map(ir::Goal::Leaf);
                 let goal = leaves.fold1(|goal, leaf| ir::Goal::And(Box::new(goal), Box::new(leaf)))
                                  .expect("at least one goal");
                 Ok(Box::new(goal))
@@ -1156,6 +2218,7 @@ impl LowerQuantifiedGoal for Goal {
             return self.lower(env);
         }
 
+        env.check_parameter_names(parameter_kinds)?;
         let parameter_kinds = parameter_kinds.iter().map(|pk| pk.lower());
         let subgoal = env.in_binders(parameter_kinds, |env| self.lower(env))?;
         Ok(Box::new(ir::Goal::Quantified(quantifier_kind, subgoal)))
@@ -0,0 +1,132 @@
+//! Evaluation of const literals and arithmetic expressions, like the
+//! `255u8 + 1` of `Foo<{255u8 + 1}>`. Expressions are evaluated when
+//! they are lowered: the IR only knows about their values.
+
+use chalk_parse::ast::{ConstBinOp, ConstExpr, ConstValue};
+use errors::*;
+use ir;
+
+/// Evaluates `expr`, in the type of its literals: they must all have
+/// the same one. An integer literal without a suffix has the type of
+/// the others, and otherwise `usize` -- or `isize`, if one of the
+/// literals is negative. Arithmetic is checked for overflow in that
+/// type, as is each literal.
+pub(super) fn evaluate(expr: &ConstExpr) -> Result<ir::ConstValue> {
+    let ty = expr_ty(expr)?;
+    if ty == ir::ConstTy::Bool {
+        if let ConstExpr::Binary { .. } = *expr {
+            bail!("cannot do arithmetic on `bool` consts: `{}`", expr);
+        }
+    }
+
+    match evaluate_in(expr, ty) {
+        Some(value) => Ok(value),
+        None => bail!(ErrorKind::OverflowingConst(expr.to_string(), ty)),
+    }
+}
+
+/// The type of the literals of `expr` (see `evaluate`).
+fn expr_ty(expr: &ConstExpr) -> Result<ir::ConstTy> {
+    let mut literals = vec![];
+    collect_literals(expr, &mut literals);
+
+    let mut ty = None;
+    let mut any_negative = false;
+    for literal in literals {
+        let literal_ty = match *literal {
+            ConstValue::Int {
+                negative,
+                suffix: None,
+                ..
+            } => {
+                any_negative |= negative;
+                continue;
+            }
+            ConstValue::Int {
+                negative,
+                suffix: Some(suffix),
+                ..
+            } => {
+                any_negative |= negative;
+                suffix_ty(suffix)?
+            }
+            ConstValue::Bool(_) => ir::ConstTy::Bool,
+        };
+        match ty {
+            Some(ty) if ty != literal_ty => bail!(ErrorKind::ConstTypeMismatch(ty, literal_ty)),
+            Some(_) => {}
+            None => ty = Some(literal_ty),
+        }
+    }
+
+    Ok(ty.unwrap_or(if any_negative {
+        ir::ConstTy::Isize
+    } else {
+        ir::ConstTy::Usize
+    }))
+}
+
+/// The integer type named by the suffix of a literal.
+fn suffix_ty(suffix: ir::Identifier) -> Result<ir::ConstTy> {
+    let ty = match ir::ConstTy::from_name(&suffix.to_string()) {
+        Some(ty) if ty != ir::ConstTy::Bool => ty,
+        Some(_) | None => bail!("unknown integer type `{}`", suffix),
+    };
+    if ty.bits() == Some(128) && !cfg!(feature = "i128") {
+        bail!("const type `{}` requires the `i128` feature", ty);
+    }
+    Ok(ty)
+}
+
+fn collect_literals<'e>(expr: &'e ConstExpr, literals: &mut Vec<&'e ConstValue>) {
+    match *expr {
+        ConstExpr::Value(ref value) => literals.push(value),
+        ConstExpr::Binary {
+            ref lhs, ref rhs, ..
+        } => {
+            collect_literals(lhs, literals);
+            collect_literals(rhs, literals);
+        }
+    }
+}
+
+/// The value of `expr` in the type `ty`, or `None` if it overflows.
+fn evaluate_in(expr: &ConstExpr, ty: ir::ConstTy) -> Option<ir::ConstValue> {
+    match *expr {
+        ConstExpr::Value(ConstValue::Bool(value)) => Some(ir::ConstValue::bool(value)),
+        ConstExpr::Value(ConstValue::Int {
+            negative: false,
+            magnitude,
+            ..
+        }) => ir::ConstValue::from_u128(ty, magnitude),
+        ConstExpr::Value(ConstValue::Int {
+            negative: true,
+            magnitude,
+            ..
+        }) => {
+            // Negated without overflowing on the smallest `i128`,
+            // whose magnitude is not an `i128`.
+            if magnitude == 0 {
+                return ir::ConstValue::from_u128(ty, 0);
+            }
+            let predecessor = magnitude - 1;
+            if predecessor > i128::max_value() as u128 {
+                return None;
+            }
+            ir::ConstValue::from_i128(ty, -(predecessor as i128) - 1)
+        }
+        ConstExpr::Binary {
+            op,
+            ref lhs,
+            ref rhs,
+        } => {
+            let lhs = evaluate_in(lhs, ty)?;
+            let rhs = evaluate_in(rhs, ty)?;
+            match op {
+                ConstBinOp::Add => lhs.checked_add(rhs),
+                ConstBinOp::Sub => lhs.checked_sub(rhs),
+                ConstBinOp::Mul => lhs.checked_mul(rhs),
+            }
+        }
+    }
+}
@@ -0,0 +1,359 @@
+//! Kind checking: every struct, trait, associated type, named constant
+//! and external predicate must be given parameters of the kinds it
+//! declares. Lowering checks the parameters as it goes, so that it can
+//! point at the offending name, but the whole program is checked again
+//! once it is lowered -- before it is used to check well-formedness or
+//! coherence -- so that no ill-kinded IR can reach the solver.
+
+use std::iter;
+
+use chalk_parse::ast::{Kind, Kinded, Span};
+use errors::*;
+use ir::*;
+
+/// Checks that the `found` parameters given to `item` have the
+/// `expected` kinds, in order, and that there are as many of them.
+pub(super) fn check_parameter_kinds<E, F>(
+    item: Identifier,
+    span: Option<Span>,
+    expected: E,
+    found: F,
+) -> Result<()>
+where
+    E: IntoIterator<Item = Kind>,
+    F: IntoIterator<Item = Kind>,
+{
+    let mut expected = expected.into_iter();
+    let mut found = found.into_iter();
+    for position in 0.. {
+        let (expected, found) = (expected.next(), found.next());
+        if expected.is_none() && found.is_none() {
+            return Ok(());
+        }
+        if expected != found {
+            bail!(ErrorKind::KindError(KindError {
+                item,
+                expected,
+                found,
+                position,
+                span,
+            }));
+        }
+    }
+    unreachable!()
+}
+
+/// The kinds of `parameters`, in order.
+pub(super) fn kinds<'k, K: Kinded + 'k>(parameters: &'k [K]) -> impl Iterator<Item = Kind> + 'k {
+    parameters.iter().map(|parameter| parameter.kind())
+}
+
+impl Program {
+    /// Checks the kinds of the parameters of all of the items named
+    /// in the program.
+    pub(super) fn check_kinds(&self) -> Result<()> {
+        for struct_datum in self.struct_data.values() {
+            struct_datum.check_kinds(self)?;
+        }
+        for synthetic_ty_datum in self.synthetic_ty_data.values() {
+            synthetic_ty_datum.check_kinds(self)?;
+        }
+        for trait_datum in self.trait_data.values() {
+            trait_datum.check_kinds(self)?;
+        }
+        for impl_datum in self.impl_data.values() {
+            impl_datum.check_kinds(self)?;
+        }
+        for associated_ty_datum in self.associated_ty_data.values() {
+            associated_ty_datum.check_kinds(self)?;
+        }
+        self.custom_clauses.check_kinds(self)
+    }
+
+    /// Checks the kinds of the parameters given to the associated type
+    /// of `projection`, but not the kinds of those of the parameters
+    /// themselves.
+    pub(crate) fn check_projection_kinds(&self, projection: &ProjectionTy) -> Result<()> {
+        let datum = &self.associated_ty_data[&projection.associated_ty_id];
+        check_parameter_kinds(
+            datum.name,
+            None,
+            kinds(&datum.parameter_kinds),
+            kinds(&projection.parameters),
+        )
+    }
+
+    /// The kinds of the parameters of a trait, including its self type.
+    fn trait_parameter_kinds<'p>(&'p self, trait_id: ItemId) -> impl Iterator<Item = Kind> + 'p {
+        iter::once(Kind::Ty).chain(kinds(&self.type_kinds[&trait_id].binders.binders))
+    }
+}
+
+trait CheckKinds {
+    fn check_kinds(&self, program: &Program) -> Result<()>;
+}
+
+impl<T: CheckKinds> CheckKinds for Vec<T> {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        for value in self {
+            value.check_kinds(program)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: CheckKinds> CheckKinds for Box<T> {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        (**self).check_kinds(program)
+    }
+}
+
+impl<T: CheckKinds> CheckKinds for Binders<T> {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        self.value.check_kinds(program)
+    }
+}
+
+impl CheckKinds for StructDatum {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        let bound = &self.binders.value;
+        bound.self_ty.check_kinds(program)?;
+        bound.fields.check_kinds(program)?;
+        bound.where_clauses.check_kinds(program)
+    }
+}
+
+impl CheckKinds for SyntheticTyDatum {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        let bound = &self.binders.value;
+        bound.self_ty.check_kinds(program)?;
+        bound.constituents.check_kinds(program)
+    }
+}
+
+impl CheckKinds for TraitDatum {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        let bound = &self.binders.value;
+        bound.trait_ref.check_kinds(program)?;
+        bound.where_clauses.check_kinds(program)
+    }
+}
+
+impl CheckKinds for ImplDatum {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        let bound = &self.binders.value;
+        bound.trait_ref.trait_ref().check_kinds(program)?;
+        bound.where_clauses.check_kinds(program)?;
+        for associated_ty_value in &bound.associated_ty_values {
+            associated_ty_value.in_impl().value.ty.check_kinds(program)?;
+        }
+        Ok(())
+    }
+}
+
+impl CheckKinds for AssociatedTyDatum {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        self.bounds.check_kinds(program)?;
+        self.where_clauses.check_kinds(program)
+    }
+}
+
+impl CheckKinds for InlineBound {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        match self {
+            InlineBound::TraitBound(bound) => bound.check_kinds(program),
+            InlineBound::ProjectionEqBound(bound) => {
+                bound.trait_bound.check_kinds(program)?;
+
+                // Only the parameters of the associated type itself are
+                // given: those of the trait come from the trait bound.
+                let datum = &program.associated_ty_data[&bound.associated_ty_id];
+                let num_trait_parameters = program.trait_parameter_kinds(datum.trait_id).count();
+                let num_own_parameters = datum.parameter_kinds.len() - num_trait_parameters;
+                check_parameter_kinds(
+                    datum.name,
+                    None,
+                    kinds(&datum.parameter_kinds[..num_own_parameters]),
+                    kinds(&bound.parameters),
+                )?;
+                bound.parameters.check_kinds(program)?;
+                bound.value.check_kinds(program)
+            }
+        }
+    }
+}
+
+impl CheckKinds for TraitBound {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        check_parameter_kinds(
+            program.type_kinds[&self.trait_id].name,
+            None,
+            program.trait_parameter_kinds(self.trait_id),
+            iter::once(Kind::Ty).chain(kinds(&self.args_no_self)),
+        )?;
+        self.args_no_self.check_kinds(program)
+    }
+}
+
+impl CheckKinds for TraitRef {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        check_parameter_kinds(
+            program.type_kinds[&self.trait_id].name,
+            None,
+            program.trait_parameter_kinds(self.trait_id),
+            kinds(&self.parameters),
+        )?;
+        self.parameters.check_kinds(program)
+    }
+}
+
+impl CheckKinds for ProjectionTy {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        program.check_projection_kinds(self)?;
+        self.parameters.check_kinds(program)
+    }
+}
+
+impl CheckKinds for Ty {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        match self {
+            Ty::Var(_) => Ok(()),
+            Ty::Apply(application) => application.check_kinds(program),
+            Ty::Projection(projection) => projection.check_kinds(program),
+
+            // The associated type is only known once the projection is
+            // resolved, so only the parameters themselves are checked.
+            Ty::UnselectedProjection(projection) => projection.parameters.check_kinds(program),
+            Ty::ForAll(quantified) => quantified.ty.check_kinds(program),
+        }
+    }
+}
+
+impl CheckKinds for ApplicationTy {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        if let TypeName::ItemId(id) | TypeName::Synthetic { id, .. } = self.name {
+            let type_kind = &program.type_kinds[&id];
+            check_parameter_kinds(
+                type_kind.name,
+                None,
+                kinds(&type_kind.binders.binders),
+                kinds(&self.parameters),
+            )?;
+        }
+        self.parameters.check_kinds(program)
+    }
+}
+
+impl CheckKinds for Const {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        match self {
+            Const::Unevaluated(unevaluated) => {
+                let type_kind = &program.type_kinds[&unevaluated.const_id];
+                check_parameter_kinds(
+                    type_kind.name,
+                    None,
+                    kinds(&type_kind.binders.binders),
+                    kinds(&unevaluated.parameters),
+                )?;
+                unevaluated.parameters.check_kinds(program)
+            }
+            Const::Var(_) | Const::ForAll(_) | Const::Value(_) => Ok(()),
+        }
+    }
+}
+
+impl CheckKinds for Parameter {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        match self {
+            ParameterKind::Ty(ty) => ty.check_kinds(program),
+            ParameterKind::Lifetime(_) => Ok(()),
+            ParameterKind::Const(constant) => constant.check_kinds(program),
+        }
+    }
+}
+
+impl CheckKinds for WhereClause {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        match self {
+            WhereClause::Implemented(trait_ref) => trait_ref.check_kinds(program),
+            WhereClause::ProjectionEq(projection_eq) => {
+                projection_eq.projection.check_kinds(program)?;
+                projection_eq.ty.check_kinds(program)
+            }
+        }
+    }
+}
+
+impl CheckKinds for DomainGoal {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        match self {
+            DomainGoal::Holds(where_clause) => where_clause.check_kinds(program),
+            DomainGoal::WellFormed(WellFormed::Trait(trait_ref))
+            | DomainGoal::FromEnv(FromEnv::Trait(trait_ref))
+            | DomainGoal::LocalImplAllowed(trait_ref) => trait_ref.check_kinds(program),
+            DomainGoal::WellFormed(WellFormed::Ty(ty))
+            | DomainGoal::FromEnv(FromEnv::Ty(ty))
+            | DomainGoal::IsLocal(ty)
+            | DomainGoal::IsUpstream(ty)
+            | DomainGoal::IsFullyVisible(ty)
+            | DomainGoal::DownstreamType(ty) => ty.check_kinds(program),
+            DomainGoal::Normalize(normalize) => {
+                normalize.projection.check_kinds(program)?;
+                normalize.ty.check_kinds(program)
+            }
+            DomainGoal::UnselectedNormalize(normalize) => {
+                normalize.projection.parameters.check_kinds(program)?;
+                normalize.ty.check_kinds(program)
+            }
+            DomainGoal::Derefs(derefs) => {
+                derefs.source.check_kinds(program)?;
+                derefs.target.check_kinds(program)
+            }
+            DomainGoal::External(goal) => {
+                let type_kind = &program.type_kinds[&goal.predicate];
+                check_parameter_kinds(
+                    type_kind.name,
+                    None,
+                    kinds(&type_kind.binders.binders),
+                    kinds(&goal.parameters),
+                )?;
+                goal.parameters.check_kinds(program)
+            }
+            DomainGoal::InScope(_) | DomainGoal::Compatible(()) => Ok(()),
+        }
+    }
+}
+
+impl CheckKinds for Goal {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        match self {
+            Goal::Quantified(_, subgoal) => subgoal.check_kinds(program),
+            Goal::Implies(clauses, subgoal) => {
+                clauses.check_kinds(program)?;
+                subgoal.check_kinds(program)
+            }
+            Goal::And(a, b) => {
+                a.check_kinds(program)?;
+                b.check_kinds(program)
+            }
+            Goal::Not(subgoal) => subgoal.check_kinds(program),
+            Goal::Leaf(LeafGoal::EqGoal(EqGoal { a, b })) => {
+                a.check_kinds(program)?;
+                b.check_kinds(program)
+            }
+            Goal::Leaf(LeafGoal::DomainGoal(domain_goal)) => domain_goal.check_kinds(program),
+            Goal::CannotProve(()) => Ok(()),
+        }
+    }
+}
+
+impl CheckKinds for ProgramClause {
+    fn check_kinds(&self, program: &Program) -> Result<()> {
+        let implication = match self {
+            ProgramClause::Implies(implication) => implication,
+            ProgramClause::ForAll(binders) => &binders.value,
+        };
+        implication.consequence.check_kinds(program)?;
+        implication.conditions.check_kinds(program)
+    }
+}
@@ -1,9 +1,16 @@
 #![cfg(test)]
 
+use chalk_parse;
+use chalk_parse::ast::Kind;
+use errors::{ErrorKind, KindError};
+use ir;
+use ir::lowering::{LowerCrates, LowerProgram, LowerReflectionGoal, LOWERED_ITEMS};
 use ir::tls;
+use lalrpop_intern::intern;
 use solve::SolverChoice;
 use std::sync::Arc;
 use test_util::*;
+use test_util::generate;
 
 #[test]
 fn lower_success() {
@@ -176,6 +183,70 @@ fn goal_quantifiers() {
     });
 }
 
+#[test]
+fn simplify_goals() {
+    let program = Arc::new(
+        parse_and_lower_program(
+            "trait Foo { } struct u32 { } struct Vec<T> { }",
+            SolverChoice::default()
+        ).unwrap()
+    );
+    let rewrites = vec![
+        ("forall<T> { u32: Foo, T: Foo }", "u32: Foo, forall<T> { T: Foo }"),
+        (
+            "forall<T> { T: Foo, Vec<u32>: Foo, Vec<T>: Foo }",
+            "Vec<u32>: Foo, forall<T> { T: Foo, Vec<T>: Foo }",
+        ),
+        ("forall<T> { T: Foo, Vec<T>: Foo }", "forall<T> { T: Foo, Vec<T>: Foo }"),
+        ("forall<T> { u32: Foo }", "forall<T> { u32: Foo }"),
+        ("exists<T> { u32: Foo }", "u32: Foo"),
+        ("exists<T> { T: Foo }", "exists<T> { T: Foo }"),
+        ("exists<T, U> { u32: Foo }", "u32: Foo"),
+        ("forall<T> { exists<U> { T: Foo } }", "forall<T> { T: Foo }"),
+        ("u32: Foo, u32: Foo", "u32: Foo"),
+        ("u32: Foo, Vec<u32>: Foo, u32: Foo", "u32: Foo, Vec<u32>: Foo"),
+        ("not { u32: Foo, u32: Foo }", "not { u32: Foo }"),
+    ];
+    tls::set_current_program(&program, || {
+        for (goal_text, expected_text) in rewrites {
+            let goal = parse_and_lower_goal(&program, goal_text).unwrap();
+            let expected = parse_and_lower_goal(&program, expected_text).unwrap();
+            let context = format!("simplifying `{}`", goal_text);
+            assert_same_terms(&goal.simplify(), &*expected, &context);
+        }
+
+        let goal = parse_and_lower_goal(&program, "u32: Foo").unwrap();
+        let implies = ir::Goal::Implies(vec![], goal.clone());
+        assert_eq!(implies.simplify(), *goal);
+    });
+}
+
+#[test]
+fn modal_goals() {
+    let program = Arc::new(
+        parse_and_lower_program(
+            "trait Foo { } struct Bar<T> { } struct u32 { }",
+            SolverChoice::default()
+        ).unwrap()
+    );
+    tls::set_current_program(&program, || {
+        let goals = vec![
+            ("forall<T> { FromEnv(T: Foo) }", "ForAll<type> { FromEnv(?0 as Foo) }"),
+            ("forall<T> { FromEnv(Bar<T>) }", "ForAll<type> { FromEnv(Bar<?0>) }"),
+            ("forall<T> { WellFormed(T: Foo) }", "ForAll<type> { WellFormed(?0 as Foo) }"),
+            ("forall<T> { WellFormed(Bar<T>) }", "ForAll<type> { WellFormed(Bar<?0>) }"),
+            (
+                "compatible { u32: Foo }",
+                "ForAll<type> { if ([Compatible, DownstreamType(?0)]) { Implemented(u32: Foo) } }",
+            ),
+        ];
+        for (goal_text, expected) in goals {
+            let goal = parse_and_lower_goal(&program, goal_text).unwrap();
+            assert_eq!(format!("{:?}", goal), expected);
+        }
+    });
+}
+
 #[test]
 fn atc_accounting() {
     let program = Arc::new(
@@ -204,20 +275,20 @@ fn atc_accounting() {
             r#"ImplDatum {
     binders: for<type> ImplDatumBound {
         trait_ref: Positive(
-            Vec<?0> as Iterable
+            Vec<?0> as Iterable,
         ),
         where_clauses: [],
         associated_ty_values: [
             AssociatedTyValue {
                 associated_ty_id: (Iterable::Iter),
-                value: for<lifetime> AssociatedTyValueBound {
-                    ty: Iter<'?0, ?1>
-                }
-            }
+                value: for<type> for<lifetime> AssociatedTyValueBound {
+                    ty: Iter<'?0, ?1>,
+                },
+            },
         ],
         specialization_priority: 0,
-        impl_type: Local
-    }
+        impl_type: Local,
+    },
 }"#
         );
         let goal = parse_and_lower_goal(
@@ -251,7 +322,7 @@ fn check_parameter_kinds() {
             impl Bar for Foo<i32> { }
         }
         error_msg {
-            "incorrect parameter kind: expected lifetime, found type"
+            "incorrect parameter 0 of `Foo`: expected lifetime, found type"
         }
     };
 
@@ -262,7 +333,7 @@ fn check_parameter_kinds() {
             impl<'a> Bar for Foo<'a> { }
         }
         error_msg {
-            "incorrect parameter kind: expected type, found lifetime"
+            "incorrect parameter 0 of `Foo`: expected type, found lifetime"
         }
     };
 
@@ -273,7 +344,7 @@ fn check_parameter_kinds() {
             impl<X, T> Foo for <X as Iterator>::Item<T> where X: Iterator { }
         }
         error_msg {
-            "incorrect kind for associated type parameter: expected lifetime, found type"
+            "incorrect parameter 0 of `Item`: expected lifetime, found type"
         }
     };
 
@@ -284,7 +355,7 @@ fn check_parameter_kinds() {
             impl<X, 'a> Foo for <X as Iterator>::Item<'a> where X: Iterator { }
         }
         error_msg {
-            "incorrect kind for associated type parameter: expected type, found lifetime"
+            "incorrect parameter 0 of `Item`: expected type, found lifetime"
         }
     };
 
@@ -295,7 +366,7 @@ fn check_parameter_kinds() {
             impl<'a> Into<'a> for Foo {}
         }
         error_msg {
-            "incorrect kind for trait parameter: expected type, found lifetime"
+            "incorrect parameter 1 of `Into`: expected type, found lifetime"
         }
     }
 
@@ -306,7 +377,248 @@ fn check_parameter_kinds() {
             impl<T> IntoTime<T> for Foo {}
         }
         error_msg {
-            "incorrect kind for trait parameter: expected lifetime, found type"
+            "incorrect parameter 1 of `IntoTime`: expected lifetime, found type"
+        }
+    }
+
+    lowering_error! {
+        program {
+            trait Array<const N> {}
+            struct Foo {}
+            impl Array<Foo> for Foo {}
+        }
+        error_msg {
+            "incorrect parameter 1 of `Array`: expected const, found type"
+        }
+    }
+
+    lowering_error! {
+        program {
+            struct Ref<'a> {}
+            struct Foo { field: Ref<3> }
+        }
+        error_msg {
+            "incorrect parameter 0 of `Ref`: expected lifetime, found const"
+        }
+    }
+
+    lowering_error! {
+        program {
+            trait Into<T> {}
+            struct Foo {}
+            impl Into for Foo {}
+        }
+        error_msg {
+            "incorrect parameter 1 of `Into`: expected type, found nothing"
+        }
+    }
+
+    lowering_error! {
+        program {
+            trait Marker {}
+            struct Foo {}
+            impl Marker<Foo> for Foo {}
+        }
+        error_msg {
+            "incorrect parameter 1 of `Marker`: expected nothing, found type"
+        }
+    }
+
+    lowering_error! {
+        program {
+            trait Iterator { type Item<T>; }
+            trait Foo { }
+            impl<X> Foo for <X as Iterator>::Item where X: Iterator { }
+        }
+        error_msg {
+            "incorrect parameter 0 of `Item`: expected type, found nothing"
+        }
+    }
+}
+
+/// Lowering checks kinds as it goes, so the IR of a program can only
+/// be ill-kinded if it was built by other means.
+#[test]
+fn kind_check_pass() {
+    let program_text = "struct Foo<T> { } struct Bar { field: Foo<Bar> }";
+    let mut program = parse_and_lower_program(program_text, SolverChoice::default()).unwrap();
+    program.check_kinds().unwrap();
+
+    let foo = program.type_ids[&intern("Foo")];
+    let bar = program.type_ids[&intern("Bar")];
+    program.struct_data.get_mut(&bar).unwrap().binders.value.fields = vec![
+        ir::Ty::Apply(ir::ApplicationTy {
+            name: ir::TypeName::ItemId(foo),
+            parameters: vec![ir::ParameterKind::Lifetime(ir::Lifetime::Var(0))],
+        }),
+    ];
+
+    match *program.check_kinds().unwrap_err().kind() {
+        ErrorKind::KindError(ref error) => assert_eq!(
+            *error,
+            KindError {
+                item: intern("Foo"),
+                expected: Some(Kind::Ty),
+                found: Some(Kind::Lifetime),
+                position: 0,
+                span: None,
+            }
+        ),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+#[test]
+fn const_defaults() {
+    lowering_success! {
+        program {
+            struct Foo<T, const N = 0, const M = 1> { }
+            struct Bar { field: Foo<Bar>, other: Foo<Bar, 3> }
+        }
+    }
+
+    lowering_error! {
+        program {
+            struct Foo<const N = 0, T> { }
+        }
+        error_msg {
+            "parameters with defaults must come last"
+        }
+    }
+
+    lowering_error! {
+        program {
+            struct Foo<T, const N = 0> { }
+            struct Bar { field: Foo }
+        }
+        error_msg {
+            "`Foo` takes 2 type parameters, not 0"
+        }
+    }
+
+    lowering_error! {
+        program {
+            struct Foo<const N> { }
+            struct Bar { field: Foo<_> }
+        }
+        error_msg {
+            "`_` is only allowed in goals"
+        }
+    }
+}
+
+#[test]
+fn supertrait_sugar() {
+    let program = |foo: &str| {
+        let text = format!("
+            trait Clone {{ }}
+            trait Iterator {{ type Item; }}
+            struct u32 {{ }}
+            {}
+        ", foo);
+        parse_and_lower_program(&text, SolverChoice::default()).unwrap()
+    };
+
+    // The supertraits come before the `where` clauses.
+    let sugared = program("trait Foo<T>: Iterator<Item = u32> + Clone where T: Clone { }");
+    let explicit = program("
+        trait Foo<T> where Self: Iterator<Item = u32>, Self: Clone, T: Clone { }
+    ");
+    assert_eq!(sugared.trait_data, explicit.trait_data);
+
+    lowering_error! {
+        program {
+            trait Clone { }
+            #[auto] trait Send: Clone { }
+        }
+        error_msg {
+            "auto trait cannot have where clauses"
+        }
+    }
+}
+
+#[test]
+fn assoc_ty_bound_sugar() {
+    let program = |foo: &str| {
+        let text = format!("
+            trait Clone {{ }}
+            trait Iterator {{ type Item; }}
+            trait IntoIterator {{ type IntoIter; }}
+            struct Wrapper<T> {{ }}
+            struct Ref<'a, T> {{ }}
+            {}
+        ", foo);
+        parse_and_lower_program(&text, SolverChoice::default()).unwrap()
+    };
+
+    let sugared = program("trait Foo: Iterator<Item: Clone> { }");
+    let explicit = program("trait Foo where Self: Iterator, <Self as Iterator>::Item: Clone { }");
+    assert_eq!(sugared.trait_data, explicit.trait_data);
+
+    // Bounds on the associated types of the bounds, in turn.
+    let sugared = program("
+        impl<T> Clone for Wrapper<T> where T: IntoIterator<IntoIter: Iterator<Item: Clone>> { }
+    ");
+    let explicit = program("
+        impl<T> Clone for Wrapper<T> where
+            T: IntoIterator,
+            <T as IntoIterator>::IntoIter: Iterator,
+            <<T as IntoIterator>::IntoIter as Iterator>::Item: Clone
+        { }
+    ");
+    assert_eq!(sugared.impl_data, explicit.impl_data);
+
+    // Under `for<'a>`, the bounds on the associated type are as well.
+    let higher_ranked = program("
+        impl<T> Clone for Wrapper<T> where forall<'a> Ref<'a, T>: Iterator<Item: Clone> { }
+    ");
+    let explicit = program("
+        impl<T> Clone for Wrapper<T> where
+            forall<'a> Ref<'a, T>: Iterator,
+            forall<'a> <Ref<'a, T> as Iterator>::Item: Clone
+        { }
+    ");
+    assert_eq!(higher_ranked.impl_data, explicit.impl_data);
+    let higher_ranked = program("
+        trait Foo where forall<'a> Ref<'a, Self>: Iterator<Item: Clone> { }
+    ");
+    let explicit = program("
+        trait Foo where
+            forall<'a> Ref<'a, Self>: Iterator,
+            forall<'a> <Ref<'a, Self> as Iterator>::Item: Clone
+        { }
+    ");
+    assert_eq!(higher_ranked.trait_data, explicit.trait_data);
+
+    let goal = |program: &ir::Program, text: &str| {
+        tls::set_current_program(&Arc::new(program.clone()), || {
+            format!("{:?}", parse_and_lower_goal(program, text).unwrap())
+        })
+    };
+    assert_eq!(
+        goal(&sugared, "forall<T> { T: IntoIterator<IntoIter: Iterator<Item: Clone>> }"),
+        goal(&sugared, "
+            forall<T> {
+                (T: IntoIterator, <T as IntoIterator>::IntoIter: Iterator),
+                <<T as IntoIterator>::IntoIter as Iterator>::Item: Clone
+            }
+        "),
+    );
+
+    // The bounds are printed as they were written.
+    assert_eq!(
+        goal(&sugared, "forall<T> { T: IntoIterator<IntoIter: Iterator<Item: Clone>> }"),
+        "ForAll<type> { (Implemented(?0: IntoIterator<IntoIter: Iterator<Item: Clone>>)) }",
+    );
+
+    lowering_error! {
+        program {
+            trait Clone { }
+            trait Iterator { type Item; }
+            trait Foo { type Assoc: Iterator<Item: Clone>; }
+        }
+        error_msg {
+            "associated type bounds are not allowed in the bounds of an associated type"
         }
     }
 }
@@ -419,6 +731,73 @@ fn duplicate_parameters() {
     }
 }
 
+#[test]
+fn parameters_shadowing_items() {
+    lowering_error! {
+        program {
+            struct Foo { }
+            trait Bar { }
+            impl<Foo> Bar for Foo { }
+        } error_msg {
+            "parameter `Foo` has the same name as an item"
+        }
+    }
+
+    lowering_error! {
+        program {
+            trait Bar { }
+            struct Baz<Bar> { }
+        } error_msg {
+            "parameter `Bar` has the same name as an item"
+        }
+    }
+
+    lowering_error! {
+        program {
+            struct S { }
+            trait Foo<T> { }
+            trait Bar where forall<S> S: Foo<S> { }
+        } error_msg {
+            "parameter `S` has the same name as an item"
+        }
+    }
+
+    lowering_error! {
+        program {
+            struct usize { }
+            const N: usize;
+            struct Array<const N> { }
+        } error_msg {
+            "parameter `N` has the same name as an item"
+        }
+    }
+
+    // Lifetimes live in a namespace of their own.
+    lowering_success! {
+        program {
+            struct a { }
+            struct Ref<'a> { }
+        }
+    }
+}
+
+#[test]
+fn goal_parameters_shadowing_items() {
+    let program = parse_and_lower_program(
+        "struct T { } trait Foo { } impl Foo for T { }",
+        SolverChoice::default(),
+    ).unwrap();
+
+    // The `T` in `T: Foo` used to refer to the bound parameter, so that
+    // the goal asked whether every type implements `Foo`, rather than
+    // the struct `T`.
+    for goal in &["forall<T> { T: Foo }", "exists<T> { T: Foo }"] {
+        let error = parse_and_lower_goal(&program, goal).unwrap_err();
+        assert_eq!(error.to_string(), "parameter `T` has the same name as an item");
+    }
+    assert!(parse_and_lower_goal(&program, "forall<U> { T: Foo }").is_ok());
+}
+
 #[test]
 fn upstream_items() {
     lowering_success! {
@@ -429,6 +808,34 @@ fn upstream_items() {
     }
 }
 
+#[test]
+fn items_of_other_crates_are_upstream() {
+    let crates = vec![
+        chalk_parse::parse_crate("std", "trait Clone { } impl Clone for Vec { } struct Vec { }").unwrap(),
+        chalk_parse::parse_crate("app", "struct Foo { } impl Clone for Foo { }").unwrap(),
+    ];
+    let program = crates.lower_in_crate(intern("app"), SolverChoice::default()).unwrap();
+    let crate_of = |name: &str| program.item_crates[&program.type_ids[&intern(name)]];
+    assert_eq!(program.crate_names[&crate_of("Clone")], intern("std"));
+    assert_eq!(crate_of("Vec"), crate_of("Clone"));
+    assert_eq!(crate_of("Foo"), program.local_crate);
+
+    let trait_datum = &program.trait_data[&program.type_ids[&intern("Clone")]];
+    assert!(trait_datum.binders.value.flags.upstream);
+    let struct_datum = &program.struct_data[&program.type_ids[&intern("Foo")]];
+    assert!(!struct_datum.binders.value.flags.upstream);
+
+    let impl_types: Vec<_> = program
+        .impl_data
+        .values()
+        .map(|impl_datum| impl_datum.binders.value.impl_type.clone())
+        .collect();
+    assert_eq!(impl_types, vec![ir::ImplType::External, ir::ImplType::Local]);
+
+    let error = crates.lower_in_crate(intern("core"), SolverChoice::default()).unwrap_err();
+    assert_eq!(error.to_string(), "invalid crate name `core`");
+}
+
 #[test]
 fn deref_trait() {
     lowering_success! {
@@ -439,8 +846,8 @@ fn deref_trait() {
 
     lowering_error! {
         program {
-            #[lang_deref] trait Deref { }
-            #[lang_deref] trait DerefDupe { }
+            #[lang_deref] trait Deref { type Target; }
+            #[lang_deref] trait DerefDupe { type Target; }
         } error_msg {
             "Duplicate lang item `DerefTrait`"
         }
@@ -448,15 +855,645 @@ fn deref_trait() {
 }
 
 #[test]
-fn fundamental_multiple_type_parameters() {
+fn item_names() {
+    lowering_success! {
+        program {
+            trait Foo { }
+            struct u32 { }
+            #[name = "foo_u32"] impl Foo for u32 { }
+            #[name = "foo_all"] forall<T> { T: Foo }
+        }
+    }
+
     lowering_error! {
         program {
-            #[fundamental]
-            struct Boxes<T, U> { }
+            trait Foo { }
+            struct u32 { }
+            #[name = "foo"] impl Foo for u32 { }
+            #[name = "foo"] forall<T> { T: Foo }
+        } error_msg {
+            "more than one item is named `foo`"
         }
+    }
+}
 
-        error_msg {
+#[test]
+fn box_struct() {
+    lowering_success! {
+        program {
+            #[lang_box] struct Box<T> { }
+        }
+    }
+
+    lowering_error! {
+        program {
+            #[lang_box] struct Box<T> { }
+            #[lang_box] struct BoxDupe<T> { }
+        } error_msg {
+            "Duplicate lang item `BoxStruct`"
+        }
+    }
+
+    lowering_error! {
+        program {
+            #[lang_box] struct Box<T, U> { }
+        } error_msg {
             "Only fundamental types with a single parameter are supported"
         }
     }
 }
+
+#[test]
+fn phantom_data_lang_item() {
+    lowering_success! {
+        program {
+            #[lang_phantom_data] struct PhantomData<T> { }
+        }
+    }
+
+    lowering_error! {
+        program {
+            #[lang_phantom_data] struct PhantomData<T> { }
+            #[lang_phantom_data] struct PhantomDataDupe<T> { }
+        } error_msg {
+            "Duplicate lang item `PhantomDataStruct`"
+        }
+    }
+
+    lowering_error! {
+        program {
+            #[lang_phantom_data] struct PhantomData<T> { value: T }
+        } error_msg {
+            "lang item `PhantomDataStruct` does not have the expected shape"
+        }
+    }
+}
+
+#[test]
+fn phantom_no_auto() {
+    lowering_success! {
+        program {
+            #[auto] trait Send { }
+            #[auto] trait Unpin { }
+            #[phantom_no_auto(Send, Unpin)] struct Marker { }
+        }
+    }
+
+    lowering_error! {
+        program {
+            struct Unpin { }
+            #[phantom_no_auto(Unpin)] struct Marker { }
+        } error_msg {
+            "expected a trait, found `Unpin`, which is not a trait"
+        }
+    }
+}
+
+#[test]
+fn sync_trait() {
+    lowering_success! {
+        program {
+            #[auto] #[lang_sync] trait Sync { }
+            #[interior_mut] struct Cell<T> { value: T }
+        }
+    }
+
+    lowering_error! {
+        program {
+            #[auto] #[lang_sync] trait Sync { }
+            #[auto] #[lang_sync] trait SyncDupe { }
+        } error_msg {
+            "Duplicate lang item `SyncTrait`"
+        }
+    }
+
+    lowering_error! {
+        program {
+            #[lang_sync] trait Sync { }
+        } error_msg {
+            "lang item `SyncTrait` does not have the expected shape"
+        }
+    }
+}
+
+#[test]
+fn fundamental_multiple_type_parameters() {
+    lowering_error! {
+        program {
+            #[fundamental]
+            struct Boxes<T, U> { }
+        }
+
+        error_msg {
+            "Only fundamental types with a single parameter are supported"
+        }
+    }
+}
+
+#[test]
+fn fundamental_trait_parameters() {
+    lowering_success! {
+        program {
+            trait Fn<#[fundamental] Args, Other> { }
+        }
+    }
+
+    lowering_error! {
+        program {
+            trait Foo<#[fundamental] 'a> { }
+        }
+
+        error_msg {
+            "only type parameters can be fundamental"
+        }
+    }
+}
+
+#[test]
+fn external_predicates() {
+    lowering_success! {
+        program {
+            external predicate IsDiscriminant(type);
+            external predicate Outlives(lifetime, lifetime);
+            trait Enum { }
+            struct Vec<T> { }
+            forall<T> { Vec<T>: Enum if IsDiscriminant(T) }
+        }
+    }
+
+    lowering_error! {
+        program {
+            external predicate IsDiscriminant(type);
+            struct Foo { field: IsDiscriminant }
+        }
+        error_msg {
+            "invalid type name `IsDiscriminant`"
+        }
+    }
+
+    lowering_error! {
+        program {
+            external predicate IsDiscriminant(type);
+            trait Enum { }
+            struct Vec<T> { }
+            forall<T> { Vec<T>: Enum if IsDiscriminant(T, T) }
+        }
+        error_msg {
+            "`IsDiscriminant` takes 1 type parameters, not 2"
+        }
+    }
+
+    lowering_error! {
+        program {
+            trait Enum { }
+            struct Vec<T> { }
+            forall<T> { Vec<T>: Enum if Vec(T) }
+        }
+        error_msg {
+            "expected an external predicate, found `Vec`"
+        }
+    }
+}
+
+#[test]
+fn named_consts() {
+    lowering_success! {
+        program {
+            struct usize { }
+            struct Foo<const N> { }
+            const FOO: usize;
+            const SIZE<T>: usize;
+            struct Bar<T> { foo: Foo<{FOO}>, size: Foo<{SIZE<T>}> }
+        }
+    }
+
+    lowering_error! {
+        program {
+            struct usize { }
+            const FOO: usize;
+            struct Bar { foo: FOO }
+        }
+        error_msg {
+            "invalid type name `FOO`"
+        }
+    }
+
+    lowering_error! {
+        program {
+            struct usize { }
+            struct Foo<const N> { }
+            const SIZE<T>: usize;
+            struct Bar { size: Foo<{SIZE}> }
+        }
+        error_msg {
+            "`SIZE` takes 1 type parameters, not 0"
+        }
+    }
+
+    lowering_error! {
+        program {
+            struct usize { }
+            struct Foo<const N> { }
+            struct Bar { foo: Foo<{usize}> }
+        }
+        error_msg {
+            "expected a named constant, found `usize`"
+        }
+    }
+}
+
+#[test]
+fn const_expressions() {
+    lowering_success! {
+        program {
+            struct Foo<const N> { }
+            struct Bar {
+                a: Foo<3u8>,
+                b: Foo<-1i32>,
+                c: Foo<true>,
+                d: Foo<{254u8 + 1}>,
+                e: Foo<{(2 - 3) * 4i8}>
+            }
+        }
+    }
+
+    lowering_error! {
+        program {
+            struct u8 { }
+            struct Array<T, const N> { }
+            struct Bytes { array: Array<u8, {255u8 + 1}> }
+        }
+        error_msg {
+            "evaluating `255u8 + 1` overflows `u8`"
+        }
+    }
+
+    lowering_error! {
+        program {
+            struct Foo<const N> { }
+            struct Bar { foo: Foo<{1 - 2}> }
+        }
+        error_msg {
+            "evaluating `1 - 2` overflows `usize`"
+        }
+    }
+
+    lowering_error! {
+        program {
+            struct Foo<const N> { }
+            struct Bar { foo: Foo<-129i8> }
+        }
+        error_msg {
+            "evaluating `-129i8` overflows `i8`"
+        }
+    }
+
+    lowering_error! {
+        program {
+            struct Foo<const N> { }
+            struct Bar { foo: Foo<{3u8 + 1u16}> }
+        }
+        error_msg {
+            "mismatched const types: expected `u8`, found `u16`"
+        }
+    }
+
+    lowering_error! {
+        program {
+            struct Foo<const N> { }
+            struct Bar { foo: Foo<{true + 1}> }
+        }
+        error_msg {
+            "cannot do arithmetic on `bool` consts: `true + 1`"
+        }
+    }
+}
+
+#[cfg(not(feature = "i128"))]
+#[test]
+fn const_128_bit_integers_are_feature_gated() {
+    lowering_error! {
+        program {
+            struct Foo<const N> { }
+            struct Bar { foo: Foo<3u128> }
+        }
+        error_msg {
+            "const type `u128` requires the `i128` feature"
+        }
+    }
+}
+
+#[test]
+fn const_integer_literals_out_of_range() {
+    // One more than `u128::MAX`.
+    let program = "
+        struct Foo<const N> { }
+        struct Bar { foo: Foo<340282366920938463463374607431768211456> }
+    ";
+    let error = parse_and_lower_program(program, SolverChoice::default()).unwrap_err();
+    assert!(
+        error.to_string().contains("the integer literal is too large for any integer type"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn synthetic_types() {
+    lowering_success! {
+        program {
+            struct Vec<T> { }
+            synthetic type FnDef<type, lifetime>;
+            synthetic type Witness<type> constituents<T> { Vec<T> }
+            trait Foo { }
+            impl<T, 'a> Foo for FnDef<Witness<T>, 'a> { }
+        }
+    }
+
+    lowering_error! {
+        program {
+            synthetic type Witness<type, lifetime> constituents<'a, T> { T }
+        }
+        error_msg {
+            "incorrect parameter 0 of `Witness`: expected type, found lifetime"
+        }
+    }
+
+    lowering_error! {
+        program {
+            struct Foo { }
+            synthetic type FnDef<type>;
+            struct Bar { f: FnDef<Foo, Foo> }
+        }
+        error_msg {
+            "`FnDef` takes 1 type parameters, not 2"
+        }
+    }
+
+    // Synthetic types are printed by name, like structs.
+    let program = Arc::new(
+        parse_and_lower_program(
+            "trait Foo { } struct u32 { } synthetic type FnDef<type, lifetime>;",
+            SolverChoice::default()
+        ).unwrap()
+    );
+    tls::set_current_program(&program, || {
+        let goal = parse_and_lower_goal(&program, "forall<'a> { FnDef<u32, 'a>: Foo }").unwrap();
+        assert_eq!(
+            format!("{:?}", goal),
+            "ForAll<lifetime> { Implemented(FnDef<u32, '?0>: Foo) }"
+        );
+    });
+}
+
+#[test]
+fn ill_kinded_inputs() {
+    lowering_error! {
+        program {
+            trait Foo { }
+            struct S<const N> where N: Foo { }
+        }
+        error_msg {
+            "incorrect kind for self type: expected type, found const"
+        }
+    }
+
+    lowering_error! {
+        program {
+            #[lang_deref]
+            trait Deref { }
+        }
+        error_msg {
+            "lang item `DerefTrait` does not have the expected shape"
+        }
+    }
+
+    lowering_error! {
+        program {
+            #[lang_deref]
+            trait Deref { type Target<T>; }
+        }
+        error_msg {
+            "lang item `DerefTrait` does not have the expected shape"
+        }
+    }
+
+    lowering_error! {
+        program {
+            #[lang_deref]
+            trait Deref<U> { type Target; }
+        }
+        error_msg {
+            "lang item `DerefTrait` does not have the expected shape"
+        }
+    }
+}
+
+#[test]
+fn item_names_survive_reordering() {
+    let before = parse_and_lower_program(
+        "trait Iterator { type Item; } struct Vec<T> { }",
+        SolverChoice::default(),
+    ).unwrap();
+    let after = parse_and_lower_program(
+        "struct Vec<T> { } trait Iterator { type Item; }",
+        SolverChoice::default(),
+    ).unwrap();
+
+    for name in &["Vec", "Iterator", "Iterator::Item"] {
+        let old_id = before.lookup(name).unwrap();
+        let new_id = after.lookup(name).unwrap();
+        assert_eq!(before.name_of(old_id).as_ref().map(|n| &n[..]), Some(*name));
+        assert_eq!(after.name_of(new_id).as_ref().map(|n| &n[..]), Some(*name));
+    }
+
+    // The raw IDs of the two items that swapped places now name each
+    // other.
+    let old_vec = before.lookup("Vec").unwrap();
+    assert_eq!(after.name_of(old_vec), Some("Iterator".to_string()));
+    let old_iterator = before.lookup("Iterator").unwrap();
+    assert_eq!(after.name_of(old_iterator), Some("Vec".to_string()));
+
+    assert_eq!(after.lookup("Option"), None);
+    assert_eq!(after.lookup("Iterator::IntoIter"), None);
+    assert_eq!(after.lookup("Vec::Item"), None);
+    assert_eq!(after.lookup("Iterator::Item::Item"), None);
+}
+
+/// Lowers `text` again, given the program it was lowered to before an
+/// edit, and returns the new program with the number of structs,
+/// traits and impls that had to be lowered.
+fn relower(text: &str, previous: &ir::Program) -> (ir::Program, usize) {
+    LOWERED_ITEMS.with(|c| c.set(0));
+    let program = chalk_parse::parse_program(text)
+        .unwrap()
+        .relower(previous, SolverChoice::default())
+        .unwrap();
+    (program, LOWERED_ITEMS.with(|c| c.get()))
+}
+
+fn solve(program: &ir::Program, goal: &str) -> String {
+    let program = Arc::new(program.clone());
+    let env = Arc::new(program.environment());
+    tls::set_current_program(&program, || {
+        let goal = parse_and_lower_goal(&program, goal).unwrap();
+        let result = SolverChoice::default().solve_root_goal(&env, &goal.into_peeled_goal());
+        format!("{:?}", result)
+    })
+}
+
+#[test]
+fn relower_reuses_unchanged_items() {
+    // One trait, 250 structs and 250 impls.
+    let text = generate::impl_fan_out(250).program;
+    let previous = parse_and_lower_program(&text, SolverChoice::default()).unwrap();
+    let goals = ["S7: Trait", "S8: Trait", "S249: Trait"];
+
+    let edited = text.replace("impl Trait for S7 { }", "impl Trait for S7 where S8: Trait { }");
+    assert_ne!(edited, text);
+    let (program, lowered) = relower(&edited, &previous);
+    assert_eq!(lowered, 1);
+    assert_eq!(program.type_ids, previous.type_ids);
+    assert_eq!(program.impl_data.len(), previous.impl_data.len());
+    for goal in &goals {
+        assert_eq!(solve(&program, goal), solve(&previous, goal));
+    }
+
+    // Items are matched by name, or by content for impls, rather than
+    // by position: moving the trait after everything else changes
+    // nothing.
+    let (first_line, rest) = text.split_at(text.find('\n').unwrap() + 1);
+    let (program, lowered) = relower(&format!("{}{}", rest, first_line), &previous);
+    assert_eq!(lowered, 0);
+    assert_eq!(program.type_ids, previous.type_ids);
+    assert_eq!(program.impl_data, previous.impl_data);
+
+    // A new name could change what the names of any item refer to.
+    let (program, lowered) = relower(&format!("struct New {{ }}\n{}", text), &previous);
+    assert_eq!(lowered, 502);
+    assert_eq!(program.type_ids[&intern("S7")], previous.type_ids[&intern("S7")]);
+    for goal in &goals {
+        assert_eq!(solve(&program, goal), solve(&previous, goal));
+    }
+}
+
+#[test]
+fn goal_errors_at_the_repl() {
+    let program = parse_and_lower_program(
+        "struct u32 { } struct Vec<T> { } trait Clone { }",
+        SolverChoice::default(),
+    ).unwrap();
+    let error = |goal| parse_and_lower_goal(&program, goal).unwrap_err().to_string();
+
+    assert_eq!(
+        error("Vec<u32, u32>: Clone"),
+        "`Vec` takes 1 type parameters, not 2"
+    );
+    assert_eq!(
+        error("Vec<3>: Clone"),
+        "incorrect parameter 0 of `Vec`: expected type, found const"
+    );
+    assert_eq!(error("Vec<u32>: Clnoe"), "invalid type name `Clnoe`");
+
+    // What chalki suggests instead of a name that is not known.
+    assert_eq!(program.similar_name("Clnoe"), Some(intern("Clone")));
+    assert_eq!(program.similar_name("vec"), Some(intern("Vec")));
+    assert_eq!(program.similar_name("Iterator"), None);
+}
+
+#[test]
+fn mis_arity_projections() {
+    let program = Arc::new(parse_and_lower_program(
+        "struct Foo { } trait Iterator { type Item<'a>; }",
+        SolverChoice::default(),
+    ).unwrap());
+    let item = program.lookup("Iterator::Item").unwrap();
+    let foo = ir::ParameterKind::Ty(ir::Ty::Apply(ir::ApplicationTy {
+        name: ir::TypeName::ItemId(program.lookup("Foo").unwrap()),
+        parameters: vec![],
+    }));
+
+    // The lifetime of `Item` itself is missing.
+    let error = ir::ProjectionTy::new(item, vec![foo.clone()], &program).unwrap_err();
+    match *error.kind() {
+        ErrorKind::KindError(ref error) => assert_eq!(
+            *error,
+            KindError {
+                item: intern("Item"),
+                expected: Some(Kind::Lifetime),
+                found: Some(Kind::Ty),
+                position: 0,
+                span: None,
+            }
+        ),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+
+    let projection = ir::ProjectionTy::new(
+        item,
+        vec![ir::ParameterKind::Lifetime(ir::Lifetime::Var(0)), foo.clone()],
+        &program,
+    ).unwrap();
+    assert!(program.split_projection(&projection).is_ok());
+
+    // A projection built by other means is not split, and is printed
+    // as it is.
+    let projection = ir::ProjectionTy {
+        associated_ty_id: item,
+        parameters: vec![foo],
+    };
+    assert!(program.split_projection(&projection).is_err());
+    tls::set_current_program(&program, || {
+        assert_eq!(format!("{:?}", projection), format!("({:?})<Foo>", item));
+    });
+}
+
+#[test]
+fn reflection_goals() {
+    let error = |program, goal| {
+        let program = parse_and_lower_program(program, SolverChoice::default()).unwrap();
+        chalk_parse::parse_goal(goal)
+            .unwrap()
+            .lower_reflection(&program)
+            .unwrap_err()
+            .to_string()
+    };
+
+    assert_eq!(
+        error("struct u32 { } trait Foo { }", "HasImpl(Foo, u32)"),
+        "invalid reflection goal: the program is not marked `#[test_reflection]`"
+    );
+    assert_eq!(
+        error("#[test_reflection] struct u32 { } trait Foo { }", "HasImpl(u32, u32)"),
+        "expected a trait, found `u32`, which is not a trait"
+    );
+
+    // Goals about the program cannot be mixed with ordinary goals.
+    let program = parse_and_lower_program(
+        "#[test_reflection] struct u32 { } trait Foo { }",
+        SolverChoice::default(),
+    ).unwrap();
+    let goal = chalk_parse::parse_goal("u32: Foo, ClauseCount(Foo, 4)").unwrap();
+    assert!(goal.lower_reflection(&program).unwrap().is_none());
+    assert_eq!(
+        parse_and_lower_goal(&program, "u32: Foo, ClauseCount(Foo, 4)").unwrap_err().to_string(),
+        "invalid reflection goal: goals about the program itself must be asked on their own"
+    );
+
+    // A count that does not fit in a `usize` cannot be parsed.
+    let error = chalk_parse::parse_goal("ClauseCount(Foo, 99999999999999999999999)").unwrap_err();
+    assert!(error.to_string().contains("the clause count is too large"), "{}", error);
+}
+
+#[test]
+fn static_is_not_a_binder_name() {
+    // `'static` is a lifetime of its own, not a name that a binder
+    // could introduce.
+    assert!(chalk_parse::parse_goal("forall<'static> { Ref<'static, u32>: Foo }").is_err());
+    assert!(chalk_parse::parse_goal("exists<'static> { Ref<'static, u32>: Foo }").is_err());
+    assert!(chalk_parse::parse_program("impl<'static> Foo for Ref<'static, u32> { }").is_err());
+    assert!(chalk_parse::parse_program("struct Ref<'static, T> { }").is_err());
+
+    // Other names are still fine.
+    assert!(chalk_parse::parse_goal("forall<'a> { Ref<'a, u32>: Foo }").is_ok());
+}
@@ -1,4 +1,33 @@
-//! Useful macros for writing unit tests. They let you gin up dummy types and things.
+//! Useful macros for working with the IR. Most are for writing unit
+//! tests: they let you gin up dummy types and things.
+
+/// Maps the `ParameterKind<T>` `$kind` to the `Parameter` of the same
+/// kind whose value is `$value`. In `$value`, `$data` is bound to the
+/// data carried by `$kind`, and `$V` names the `ParameterValue` type of
+/// its kind, so that code which is the same for every kind of
+/// parameter is written once:
+///
+/// ```ignore
+/// map_kind!(binder, |(), V| V::from_var(index))
+/// ```
+macro_rules! map_kind {
+    ($kind:expr, |$data:pat, $V:ident| $value:expr) => {
+        match $kind {
+            ::ir::ParameterKind::Ty($data) => {
+                type $V = ::ir::Ty;
+                ::ir::ParameterKind::Ty($value)
+            }
+            ::ir::ParameterKind::Lifetime($data) => {
+                type $V = ::ir::Lifetime;
+                ::ir::ParameterKind::Lifetime($value)
+            }
+            ::ir::ParameterKind::Const($data) => {
+                type $V = ::ir::Const;
+                ::ir::ParameterKind::Const($value)
+            }
+        }
+    };
+}
 
 #[cfg(test)]
 macro_rules! ty {
@@ -42,6 +71,10 @@ macro_rules! arg {
         ::ir::ParameterKind::Lifetime(lifetime!($b))
     };
 
+    ((const $b:expr)) => {
+        ::ir::ParameterKind::Const($b)
+    };
+
     ($arg:tt) => {
         ::ir::ParameterKind::Ty(ty!($arg))
     };
@@ -53,8 +86,15 @@ macro_rules! lifetime {
         ::ir::Lifetime::Var($b)
     };
 
+    (static) => {
+        ::ir::Lifetime::Static
+    };
+
     (skol $b:expr) => {
-        ::ir::Lifetime::ForAll(UniverseIndex { counter: $b })
+        ::ir::Lifetime::ForAll(PlaceholderIndex {
+            ui: UniverseIndex { counter: $b },
+            idx: 0,
+        })
     };
 
     (expr $b:expr) => {
@@ -69,5 +109,8 @@ macro_rules! lifetime {
 #[cfg(test)]
 macro_rules! ty_name {
     ((item $n:expr)) => { ::ir::TypeName::ItemId(ItemId { index: $n }) };
-    ((skol $n:expr)) => { ::ir::TypeName::ForAll(UniverseIndex { counter: $n }) }
+    ((skol $n:expr)) => { ::ir::TypeName::ForAll(PlaceholderIndex {
+        ui: UniverseIndex { counter: $n },
+        idx: 0,
+    }) }
 }
@@ -0,0 +1,89 @@
+//! Copy-on-write storage for the tables of a `Program`. Cloning a
+//! program -- to check an edited copy of it, say, or to share its
+//! tables with a `ProgramEnvironment` -- only bumps the reference count
+//! of each table, and a table is only copied when a clone that shares
+//! it is modified. Reading goes straight through to the table.
+//!
+//! The tables are shared whole, rather than kept in an arena of
+//! datums indexed by `ItemId`: that makes clones just as cheap, and
+//! reading a datum already costs no reference count bump, so the
+//! solver runs as fast as with tables held inline (see the benches).
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+#[derive(Default)]
+pub(crate) struct Shared<T> {
+    value: Arc<T>,
+}
+
+impl<T> Shared<T> {
+    pub(crate) fn new(value: T) -> Self {
+        Shared {
+            value: Arc::new(value),
+        }
+    }
+
+    /// True if `self` and `other` share their table, so that they are
+    /// equal without comparing them.
+    pub(crate) fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.value, &other.value)
+    }
+}
+
+impl<T> From<T> for Shared<T> {
+    fn from(value: T) -> Self {
+        Shared::new(value)
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Shared {
+            value: self.value.clone(),
+        }
+    }
+}
+
+impl<T> Deref for Shared<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Clone> DerefMut for Shared<T> {
+    /// Copies the table first if it is shared.
+    fn deref_mut(&mut self) -> &mut T {
+        Arc::make_mut(&mut self.value)
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Shared<T>
+where
+    &'a T: IntoIterator,
+{
+    type Item = <&'a T as IntoIterator>::Item;
+    type IntoIter = <&'a T as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIterator::into_iter(&*self.value)
+    }
+}
+
+impl<T: PartialEq> PartialEq for Shared<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other) || *self.value == *other.value
+    }
+}
+
+impl<T: Eq> Eq for Shared<T> {}
+
+/// Prints the table itself, as if it were not shared.
+impl<T: fmt::Debug> fmt::Debug for Shared<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
@@ -0,0 +1,131 @@
+use fold::shift::Shift;
+use ir::*;
+
+impl Goal {
+    /// Rewrites this goal into an equivalent one which is cheaper to
+    /// solve, because it creates fewer (or smaller) tables:
+    ///
+    /// - `forall<T> { A, B }`, where `T` does not occur in `A`, becomes
+    ///   `A, forall<T> { B }`;
+    /// - `exists<T> { G }`, where `T` does not occur in `G`, becomes `G`;
+    /// - `G, G` becomes `G`;
+    /// - `if () { G }` becomes `G`.
+    ///
+    /// This is only done when asked for by the solver choice (see
+    /// `SolverChoice::simplifies_goals`), so that any difference in
+    /// behavior can be traced back to it.
+    pub fn simplify(&self) -> Goal {
+        match self {
+            Goal::Quantified(kind, subgoal) => simplify_quantified(*kind, subgoal),
+            Goal::Implies(clauses, subgoal) => {
+                let subgoal = subgoal.simplify();
+                if clauses.is_empty() {
+                    subgoal
+                } else {
+                    Goal::Implies(clauses.clone(), Box::new(subgoal))
+                }
+            }
+            Goal::And(..) => {
+                let mut conjuncts = vec![];
+                push_simplified_conjuncts(self, &mut conjuncts);
+                and_all(conjuncts)
+            }
+            Goal::Not(subgoal) => Goal::Not(Box::new(subgoal.simplify())),
+            Goal::Leaf(_) | Goal::CannotProve(()) => self.clone(),
+        }
+    }
+}
+
+impl UCanonical<InEnvironment<Goal>> {
+    /// Simplifies the goal, leaving its variables and environment as
+    /// they are.
+    pub(crate) fn simplify(&self) -> Self {
+        let InEnvironment { environment, goal } = &self.canonical.value;
+        UCanonical {
+            canonical: Canonical {
+                value: InEnvironment::new(environment, goal.simplify()),
+                binders: self.canonical.binders.clone(),
+            },
+            universes: self.universes,
+        }
+    }
+}
+
+fn simplify_quantified(kind: QuantifierKind, subgoal: &Binders<Box<Goal>>) -> Goal {
+    let num_binders = subgoal.binders.len();
+    let goal = subgoal.value.simplify();
+    let quantified = |goal: Goal| Goal::Quantified(kind, Binders {
+        binders: subgoal.binders.clone(),
+        value: Box::new(goal),
+    });
+
+    if num_binders == 0 {
+        return goal;
+    }
+
+    match kind {
+        // Shifting the goal out of the binder only fails if it refers
+        // to one of the bound variables.
+        QuantifierKind::Exists => match goal.down_shift(num_binders) {
+            Ok(shifted) => shifted,
+            Err(_) => quantified(goal),
+        },
+
+        QuantifierKind::ForAll => {
+            let mut conjuncts = vec![];
+            push_conjuncts(goal.clone(), &mut conjuncts);
+
+            let mut independent = vec![];
+            let mut dependent = vec![];
+            for conjunct in conjuncts {
+                match conjunct.down_shift(num_binders) {
+                    Ok(shifted) => independent.push(shifted),
+                    Err(_) => dependent.push(conjunct),
+                }
+            }
+
+            // If none of the conjuncts refer to the bound variables, we
+            // keep the goal as it is rather than dropping the binder, so
+            // that the universes of the goals below it do not change.
+            if independent.is_empty() || dependent.is_empty() {
+                return quantified(goal);
+            }
+
+            independent.push(quantified(and_all(dependent)));
+            and_all(independent)
+        }
+    }
+}
+
+/// Simplifies each conjunct of `goal` and pushes it with `push_conjuncts`.
+fn push_simplified_conjuncts(goal: &Goal, conjuncts: &mut Vec<Goal>) {
+    match goal {
+        Goal::And(left, right) => {
+            push_simplified_conjuncts(left, conjuncts);
+            push_simplified_conjuncts(right, conjuncts);
+        }
+        _ => push_conjuncts(goal.simplify(), conjuncts),
+    }
+}
+
+/// Pushes the conjuncts of an already simplified `goal` which are not
+/// already in `conjuncts`, flattening nested `And` goals.
+fn push_conjuncts(goal: Goal, conjuncts: &mut Vec<Goal>) {
+    match goal {
+        Goal::And(left, right) => {
+            push_conjuncts(*left, conjuncts);
+            push_conjuncts(*right, conjuncts);
+        }
+        goal => if !conjuncts.contains(&goal) {
+            conjuncts.push(goal);
+        },
+    }
+}
+
+/// Builds the conjunction of `conjuncts`, which must not be empty,
+/// nested to the right as the parser would: `A, (B, C)`.
+fn and_all(conjuncts: Vec<Goal>) -> Goal {
+    let mut conjuncts = conjuncts.into_iter().rev();
+    let last = conjuncts.next().expect("at least one goal");
+    conjuncts.fold(last, |goal, conjunct| Goal::And(Box::new(conjunct), Box::new(goal)))
+}
@@ -0,0 +1,499 @@
+#![cfg(test)]
+
+use fold::Subst;
+use ir::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+fn clause(index: usize) -> ProgramClause {
+    ProgramClause::Implies(ProgramClauseImplication {
+        consequence: DomainGoal::IsLocal(ty!(apply (item index))),
+        conditions: vec![],
+    })
+}
+
+#[test]
+fn nested_environments_share_clauses() {
+    // The environment of the innermost goal in
+    // `if (C0) { if (C1) { ... if (C99) { G } } }`
+    let mut envs = vec![Environment::new()];
+    for i in 0..100 {
+        let env = Environment::add_clauses(envs.last().unwrap(), vec![clause(i)]);
+        envs.push(env);
+    }
+
+    let innermost = envs.last().unwrap();
+    assert_eq!(innermost.len(), 100);
+    assert!(innermost.clauses().cloned().eq((0..100).map(clause)));
+
+    // Each environment stores only the clause it adds, and points at
+    // the environment it extends rather than copying it.
+    for (parent, env) in envs.iter().zip(&envs[1..]) {
+        assert_eq!(env.segment().len(), 1);
+        assert!(Arc::ptr_eq(env.parent().unwrap(), parent));
+    }
+
+    // Adding clauses that are already present creates nothing new.
+    let same = Environment::add_clauses(innermost, vec![clause(0), clause(50)]);
+    assert!(Arc::ptr_eq(&same, innermost));
+}
+
+#[test]
+fn folding_shares_unchanged_environments() {
+    // `IsLocal(?0)`, on top of clauses without variables
+    let parent = Environment::add_clauses(&Environment::new(), (0..10).map(clause));
+    let with_var = ProgramClause::Implies(ProgramClauseImplication {
+        consequence: DomainGoal::IsLocal(Ty::Var(0)),
+        conditions: vec![],
+    });
+    let env = Environment::add_clauses(&parent, vec![with_var]);
+
+    // Substituting `?0` only folds the clause added on top of `parent`,
+    // which the folded environment extends as the original does.
+    let folded = Subst::apply(&[ParameterKind::Ty(ty!(apply (item 42)))], &*env);
+    assert!(Arc::ptr_eq(folded.parent().unwrap(), &parent));
+    assert_eq!(folded.segment(), &[clause(42)]);
+    assert_eq!(folded.len(), 11);
+
+    // An environment extending `env` folds into a new chain, as `env`
+    // itself changes, but still shares `parent`.
+    let child = Environment::add_clauses(&env, vec![clause(20)]);
+    let folded = Subst::apply(&[ParameterKind::Ty(ty!(apply (item 42)))], &*child);
+    let folded_env = folded.parent().unwrap();
+    assert!(!Arc::ptr_eq(folded_env, &env));
+    assert!(Arc::ptr_eq(folded_env.parent().unwrap(), &parent));
+    assert_eq!(folded.segment(), &[clause(20)]);
+}
+
+#[test]
+fn environments_compare_as_sets() {
+    let empty = Environment::new();
+    let a = Environment::add_clauses(&empty, vec![clause(0)]);
+    let b = Environment::add_clauses(&empty, vec![clause(1)]);
+    let ab = Environment::add_clauses(&empty, vec![clause(1), clause(0)]);
+    let a_then_b = Environment::add_clauses(&a, vec![clause(1)]);
+    let b_then_a = Environment::add_clauses(&b, vec![clause(0)]);
+
+    assert_eq!(ab, a_then_b);
+    assert_eq!(a_then_b, b_then_a);
+    assert_ne!(a, ab);
+    assert_eq!(format!("{:?}", a_then_b), format!("{:?}", b_then_a));
+
+    // Goals in environments built along different paths must find the
+    // same table.
+    let goal = |env: &Arc<Environment>| {
+        InEnvironment::new(env, DomainGoal::IsLocal(ty!(apply (item 2))))
+    };
+    let mut tables = HashMap::new();
+    tables.insert(goal(&a_then_b), "table");
+    assert_eq!(tables.get(&goal(&b_then_a)), Some(&"table"));
+    assert_eq!(tables.get(&goal(&ab)), Some(&"table"));
+    assert_eq!(tables.get(&goal(&a)), None);
+}
+
+#[test]
+fn environment_clauses_are_indexed() {
+    // `IsLocal(S0)`, ..., `IsLocal(S99)`, added ten at a time, and then
+    // `forall<T> { IsLocal(T) }`, which could prove any of those goals.
+    let mut env = Environment::new();
+    for i in 0..10 {
+        env = Environment::add_clauses(&env, (i * 10..i * 10 + 10).map(clause));
+    }
+    let any_ty = ProgramClause::ForAll(Binders {
+        binders: vec![ParameterKind::Ty(())],
+        value: ProgramClauseImplication {
+            consequence: DomainGoal::IsLocal(Ty::Var(0)),
+            conditions: vec![],
+        },
+    });
+    let env = Environment::add_clauses(&env, vec![any_ty.clone()]);
+
+    let goal = DomainGoal::IsLocal(ty!(apply (item 42)));
+    assert_eq!(env.candidate_clauses(&goal), vec![&clause(42), &any_ty]);
+
+    // A goal whose type is not known yet could use any of the clauses.
+    let goal = DomainGoal::IsLocal(Ty::Var(0));
+    assert!(env.candidate_clauses(&goal).into_iter().eq(env.clauses()));
+
+    let goal = DomainGoal::IsUpstream(ty!(apply (item 42)));
+    assert!(env.candidate_clauses(&goal).is_empty());
+}
+
+#[test]
+fn small_substitutions_do_not_allocate() {
+    use test_util::alloc::count_allocations;
+
+    let identity = |len: usize| Substitution {
+        parameters: (0..len).map(|i| ParameterKind::Ty(Ty::Var(i))).collect(),
+    };
+
+    let (subst, allocations) = count_allocations(|| identity(4));
+    assert_eq!(allocations, 0);
+    assert!(subst.is_identity_subst());
+
+    let (_, allocations) = count_allocations(|| subst.clone());
+    assert_eq!(allocations, 0);
+
+    // Larger substitutions are stored on the heap.
+    let (_, allocations) = count_allocations(|| identity(5));
+    assert!(allocations > 0);
+}
+
+/// The structural code over the IR (folding, zipping, shifting,
+/// debug-printing and lowering) names every variant it matches on,
+/// rather than using a catch-all `_` arm: that way, adding a variant to
+/// `Ty`, `Const`, `Goal` and so on is a compile error everywhere it
+/// needs to be handled. Where several variants are handled alike,
+/// list them (`Ty::Var(_) | Ty::ForAll(_) => ...`), or go through a
+/// helper which does (`ParameterKind::ty`, `Kinded::kind`). Matches over
+/// slices (`match &kinds[..]`) are not over the IR, and may have them.
+#[test]
+fn no_catch_all_match_arms_in_structural_code() {
+    let sources = [
+        ("src/fold.rs", include_str!("../fold.rs")),
+        ("src/fold/occurrences.rs", include_str!("../fold/occurrences.rs")),
+        ("src/fold/shift.rs", include_str!("../fold/shift.rs")),
+        ("src/fold/subst.rs", include_str!("../fold/subst.rs")),
+        ("src/zip.rs", include_str!("../zip.rs")),
+        ("src/ir/debug.rs", include_str!("debug.rs")),
+        ("src/ir/lowering.rs", include_str!("lowering.rs")),
+        ("src/ir/lowering/kinds.rs", include_str!("lowering/kinds.rs")),
+    ];
+
+    let mut catch_alls = vec![];
+    for (path, source) in sources.iter() {
+        let mut over_slice = false;
+        for (index, line) in source.lines().enumerate() {
+            if line.contains("match ") {
+                over_slice = line.contains("[..] {");
+            }
+            let arm = line.trim_start().trim_start_matches("| ");
+            if arm.starts_with("_ =>") && !over_slice {
+                catch_alls.push(format!("{}:{}", path, index + 1));
+            }
+        }
+    }
+    assert!(catch_alls.is_empty(), "catch-all match arms at {:?}", catch_alls);
+}
+
+fn lifetime_eq(environment: &Arc<Environment>, a: Lifetime, b: Lifetime) -> InEnvironment<Constraint> {
+    InEnvironment::new(environment, Constraint::LifetimeEq(a, b))
+}
+
+fn normalized(constraints: Vec<InEnvironment<Constraint>>) -> Vec<InEnvironment<Constraint>> {
+    normalized_with_provenance(constraints.into_iter().map(RegionConstraint::new).collect())
+        .into_iter()
+        .map(|region_constraint| region_constraint.constraint)
+        .collect()
+}
+
+fn normalized_with_provenance(constraints: Vec<RegionConstraint>) -> Vec<RegionConstraint> {
+    let mut subst = ConstrainedSubst {
+        subst: Substitution { parameters: Default::default() },
+        constraints,
+        sources: Default::default(),
+    };
+    subst.normalize_constraints();
+    subst.constraints
+}
+
+#[test]
+fn lifetime_equalities_are_oriented() {
+    let env = &Environment::new();
+
+    // Symmetric pairs and duplicates collapse into one.
+    assert_eq!(
+        normalized(vec![
+            lifetime_eq(env, lifetime!(skol 2), lifetime!(skol 1)),
+            lifetime_eq(env, lifetime!(skol 1), lifetime!(skol 2)),
+            lifetime_eq(env, lifetime!(skol 2), lifetime!(skol 1)),
+        ]),
+        vec![lifetime_eq(env, lifetime!(skol 1), lifetime!(skol 2))]
+    );
+
+    // Variables come before placeholders, and lower variables first.
+    assert_eq!(
+        normalized(vec![
+            lifetime_eq(env, lifetime!(skol 1), lifetime!(var 1)),
+            lifetime_eq(env, lifetime!(var 3), lifetime!(var 2)),
+        ]),
+        vec![
+            lifetime_eq(env, lifetime!(var 1), lifetime!(skol 1)),
+            lifetime_eq(env, lifetime!(var 2), lifetime!(var 3)),
+        ]
+    );
+
+    // A lifetime equal to itself imposes nothing.
+    assert_eq!(normalized(vec![lifetime_eq(env, lifetime!(var 0), lifetime!(var 0))]), vec![]);
+
+    // Neither does `'static` outliving another lifetime, unlike the
+    // converse.
+    let outlives = |a, b| InEnvironment::new(env, Constraint::LifetimeOutlives(a, b));
+    assert_eq!(
+        normalized(vec![
+            outlives(lifetime!(static), lifetime!(skol 1)),
+            outlives(lifetime!(skol 1), lifetime!(static)),
+        ]),
+        vec![outlives(lifetime!(skol 1), lifetime!(static))]
+    );
+}
+
+#[test]
+fn type_outlives_decomposes_structurally() {
+    let outlives = |ty, lifetime| Constraint::TypeOutlives(ty, lifetime).decompose();
+
+    // A type outlives a lifetime if its lifetime and type parameters do;
+    // a type without any outlives every lifetime.
+    let ty = ty!(apply (item 0) (lifetime (skol 1)) (apply (item 1) (var 0)));
+    assert_eq!(
+        outlives(ty, lifetime!(static)),
+        vec![
+            Constraint::LifetimeOutlives(lifetime!(skol 1), lifetime!(static)),
+            Constraint::TypeOutlives(ty!(var 0), lifetime!(static)),
+        ]
+    );
+    assert_eq!(outlives(ty!(apply (item 0)), lifetime!(static)), vec![]);
+    assert_eq!(
+        outlives(ty!(apply (item 0) (lifetime static) (lifetime (skol 1))), lifetime!(skol 1)),
+        vec![]
+    );
+
+    // Placeholder types cannot be broken down.
+    assert_eq!(
+        outlives(ty!(apply (skol 1)), lifetime!(static)),
+        vec![Constraint::TypeOutlives(ty!(apply (skol 1)), lifetime!(static))]
+    );
+
+    // The lifetimes bound by a higher-ranked type impose nothing, but
+    // a projection mentioning them is left as it is.
+    assert_eq!(
+        outlives(ty!(for_all 1 (apply (item 0) (lifetime (var 0)) (var 1))), lifetime!(skol 1)),
+        vec![Constraint::TypeOutlives(ty!(var 0), lifetime!(skol 1))]
+    );
+    let projection = ty!(for_all 1 (apply (item 0) (projection (item 1) (lifetime (var 0)))));
+    assert_eq!(
+        outlives(projection.clone(), lifetime!(static)),
+        vec![Constraint::TypeOutlives(projection, lifetime!(static))]
+    );
+}
+
+#[test]
+fn lifetime_equalities_are_closed() {
+    let env = &Environment::new();
+    let other_env = &Environment::add_clauses(env, vec![clause(0)]);
+
+    // Equalities implied by transitivity are dropped: each member of a
+    // class is equated with its least member.
+    assert_eq!(
+        normalized(vec![
+            lifetime_eq(env, lifetime!(skol 1), lifetime!(skol 2)),
+            lifetime_eq(env, lifetime!(skol 2), lifetime!(var 0)),
+            lifetime_eq(env, lifetime!(var 0), lifetime!(skol 1)),
+            lifetime_eq(env, lifetime!(skol 3), lifetime!(skol 2)),
+        ]),
+        vec![
+            lifetime_eq(env, lifetime!(var 0), lifetime!(skol 1)),
+            lifetime_eq(env, lifetime!(var 0), lifetime!(skol 2)),
+            lifetime_eq(env, lifetime!(var 0), lifetime!(skol 3)),
+        ]
+    );
+
+    // Classes do not span environments, and other constraints are kept
+    // as they are.
+    let outlives = InEnvironment::new(
+        env,
+        Constraint::LifetimeOutlives(lifetime!(skol 2), lifetime!(skol 1)),
+    );
+    assert_eq!(
+        normalized(vec![
+            lifetime_eq(other_env, lifetime!(skol 1), lifetime!(skol 2)),
+            outlives.clone(),
+            lifetime_eq(env, lifetime!(skol 2), lifetime!(skol 3)),
+            lifetime_eq(other_env, lifetime!(skol 3), lifetime!(skol 2)),
+        ]),
+        {
+            let mut expected = vec![
+                outlives,
+                lifetime_eq(env, lifetime!(skol 2), lifetime!(skol 3)),
+                lifetime_eq(other_env, lifetime!(skol 1), lifetime!(skol 2)),
+                lifetime_eq(other_env, lifetime!(skol 1), lifetime!(skol 3)),
+            ];
+            expected.sort();
+            expected
+        }
+    );
+}
+
+#[test]
+fn lifetime_equalities_keep_their_provenance() {
+    let env = &Environment::new();
+    let from = |goal: Goal| RegionConstraint {
+        constraint: lifetime_eq(env, lifetime!(skol 2), lifetime!(skol 1)),
+        provenance: Provenance {
+            goal: Some(Arc::new(Canonical {
+                value: InEnvironment::new(env, goal),
+                binders: vec![],
+            })),
+        },
+    };
+    let first = from(Goal::CannotProve(()));
+    let second = RegionConstraint {
+        constraint: lifetime_eq(env, lifetime!(skol 3), lifetime!(skol 2)),
+        ..from(Goal::Not(Box::new(Goal::CannotProve(()))))
+    };
+
+    // Each equality is tagged with the constraint that first brought
+    // its lifetime into the class; provenances do not affect equality.
+    let normalized = normalized_with_provenance(vec![first.clone(), second, first]);
+    assert_eq!(normalized.len(), 2);
+    let provenances: Vec<_> = normalized
+        .iter()
+        .map(|region_constraint| format!("{:?}", region_constraint))
+        .collect();
+    assert_eq!(
+        provenances,
+        vec![
+            "InEnvironment { environment: Env([]), goal: '!1_0 == '!2_0 } /* from ¯\\_(ツ)_/¯ */",
+            "InEnvironment { environment: Env([]), goal: '!1_0 == '!3_0 } \
+             /* from not { ¯\\_(ツ)_/¯ } */",
+        ]
+    );
+    assert_eq!(
+        normalized[0],
+        RegionConstraint::new(lifetime_eq(env, lifetime!(skol 1), lifetime!(skol 2)))
+    );
+}
+
+#[test]
+fn index_arithmetic_is_checked() {
+    let depth = DebruijnIndex::new(2);
+    assert_eq!(depth.shifted_in(3), DebruijnIndex::new(5));
+    assert_eq!(depth.shifted_out(2), Some(DebruijnIndex::new(0)));
+
+    // The variable is bound by one of the binders.
+    assert_eq!(depth.shifted_out(3), None);
+
+    let universe = UniverseIndex::root().shifted_in(2);
+    assert_eq!(universe.shifted_out(1), Some(UniverseIndex::root().next()));
+    assert_eq!(universe.shifted_out(3), None);
+}
+
+#[test]
+fn variables_bound_within_values_are_not_shifted() {
+    use fallible::NoSolution;
+    use fold::Subst;
+    use fold::shift::Shift;
+
+    // `for<type> Item0<?0, ^0>`, where `^0` is bound by the `for` and
+    // `?0` is free: as seen from inside the `for`, their depths are 1
+    // and 0.
+    let quantified = ty!(for_all 1 (apply (item 0) (var 1) (var 0)));
+    let shifted_in = ty!(for_all 1 (apply (item 0) (var 3) (var 0)));
+    assert_eq!(quantified.up_shift(2), shifted_in);
+    assert_eq!(shifted_in.down_shift(2), Ok(quantified.clone()));
+
+    // A variable cannot be shifted out of the binder which binds it.
+    assert_eq!(ty!(var 0).down_shift(1), Err(NoSolution));
+
+    // Substituting only replaces the free variable.
+    let substituted = Subst::apply(&[ParameterKind::Ty(ty!(apply (item 1)))], &quantified);
+    assert_eq!(substituted, ty!(for_all 1 (apply (item 0) (apply (item 1)) (var 0))));
+}
+
+#[test]
+fn shifting_by_zero_and_substituting_nothing_only_clone() {
+    use fold::Subst;
+    use fold::shift::Shift;
+    use test_util::alloc::count_allocations;
+
+    // Wide enough that folding its parameters allocates more than
+    // cloning them, as the folded vector is grown as it is collected.
+    let ty = ty!(apply (item 0) (var 0) (var 1) (var 2) (var 3) (var 4) (var 5));
+    let (_, cloning) = count_allocations(|| ty.clone());
+
+    let (shifted, allocations) = count_allocations(|| ty.up_shift(0));
+    assert_eq!(shifted, ty);
+    assert_eq!(allocations, cloning);
+
+    let (shifted, allocations) = count_allocations(|| ty.down_shift(0));
+    assert_eq!(shifted, Ok(ty.clone()));
+    assert_eq!(allocations, cloning);
+
+    let (substituted, allocations) = count_allocations(|| Subst::apply(&[], &ty));
+    assert_eq!(substituted, ty);
+    assert_eq!(allocations, cloning);
+
+    let (_, allocations) = count_allocations(|| ty.up_shift(1));
+    assert!(allocations > cloning);
+
+    // An identity substitution does not leave the variables it does not
+    // replace alone.
+    assert_eq!(Subst::apply(&[ParameterKind::Ty(ty!(var 0))], &ty!(var 1)), ty!(var 0));
+}
+
+#[test]
+fn cloned_programs_share_tables_until_modified() {
+    use solve::SolverChoice;
+    use test_util::parse_and_lower_program;
+
+    let program_text = "struct Foo { } trait Bar { } impl Bar for Foo { }";
+    let program = parse_and_lower_program(program_text, SolverChoice::default()).unwrap();
+    let mut edited = program.clone();
+    assert!(edited.impl_data.ptr_eq(&program.impl_data));
+
+    // Only the table which is modified is copied.
+    let impl_id = *program.impl_data.keys().next().unwrap();
+    edited.impl_data.remove(&impl_id);
+    assert!(!edited.impl_data.ptr_eq(&program.impl_data));
+    assert_eq!(program.impl_data.len(), 1);
+    assert!(edited.impl_data.is_empty());
+    assert!(edited.struct_data.ptr_eq(&program.struct_data));
+}
+
+fn eq_goal(a: Parameter, b: Parameter) -> Goal {
+    Goal::Leaf(LeafGoal::EqGoal(EqGoal { a, b }))
+}
+
+#[test]
+fn checked_constructors_reject_malformed_goals() {
+    let ty = ParameterKind::Ty(());
+    let lifetime = ParameterKind::Lifetime(());
+
+    // `exists<T> { T = ^1 }`: `^1` is not bound.
+    let unbound = eq_goal(ParameterKind::Ty(ty!(var 0)), ParameterKind::Ty(ty!(var 1)));
+    assert_eq!(
+        Goal::quantify_checked(QuantifierKind::Exists, vec![ty], unbound.clone()),
+        Err(GoalBuildError::UnboundVariable { depth: 0 })
+    );
+
+    // `exists<'a> { 'a = ^0 }`, with `^0` used as a type.
+    let mismatched = eq_goal(
+        ParameterKind::Lifetime(lifetime!(var 0)),
+        ParameterKind::Ty(ty!(var 0)),
+    );
+    assert_eq!(
+        Goal::quantify_checked(QuantifierKind::Exists, vec![lifetime], mismatched),
+        Err(GoalBuildError::KindMismatch { binder: lifetime, usage: ty })
+    );
+
+    // `exists<U> { forall<T> { T = U } }`: the inner goal refers to the
+    // outer binder, past its own.
+    let inner = unbound.quantify(QuantifierKind::ForAll, vec![ty]);
+    assert!(Goal::quantify_checked(QuantifierKind::Exists, vec![ty], inner.clone()).is_ok());
+    assert!(Goal::quantify_checked(QuantifierKind::Exists, vec![lifetime], inner).is_err());
+
+    assert!(Binders::checked(vec![ty], ty!(apply (item 0) (var 0))).is_ok());
+    assert_eq!(
+        Binders::checked(vec![ty], ty!(apply (item 0) (var 1))).unwrap_err(),
+        GoalBuildError::UnboundVariable { depth: 0 }
+    );
+    // The variables bound within the value are skipped over.
+    assert!(Binders::checked(vec![ty], ty!(for_all 1 (apply (item 0) (var 1)))).is_ok());
+
+    let root = UniverseIndex::ROOT;
+    assert!(Canonical::checked(vec![ParameterKind::Lifetime(root)], lifetime!(var 0)).is_ok());
+    assert_eq!(
+        Canonical::checked(vec![ParameterKind::Ty(root)], lifetime!(var 0)).unwrap_err(),
+        GoalBuildError::KindMismatch { binder: ty, usage: lifetime }
+    );
+}
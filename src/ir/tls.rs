@@ -1,11 +1,21 @@
 use ir;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 thread_local! {
     static PROGRAM: RefCell<Option<Arc<ir::Program>>> = RefCell::new(None)
 }
 
+thread_local! {
+    static LETTERED_VARIABLES: Cell<bool> = Cell::new(false)
+}
+
+thread_local! {
+    static PLACEHOLDER_NAMES: RefCell<BTreeMap<ir::PlaceholderIndex, String>> =
+        RefCell::new(BTreeMap::new())
+}
+
 pub fn with_current_program<OP, R>(op: OP) -> R
 where
     OP: FnOnce(Option<&Arc<ir::Program>>) -> R,
@@ -27,3 +37,45 @@ where
         r
     })
 }
+
+/// While `op` runs, free variables are printed as `?A`, `?B`, ...
+/// rather than `?0`, `?1`, ..., so that they cannot be confused with
+/// positions in a substitution, and placeholders as `!T1`, `!T2`, ...
+/// by their index alone. The latter is only unambiguous for values
+/// renamed by `Solution::renamed`.
+pub fn with_lettered_variables<OP, R>(op: OP) -> R
+where
+    OP: FnOnce() -> R,
+{
+    LETTERED_VARIABLES.with(|cell| {
+        let previous = cell.replace(true);
+        let r = op();
+        cell.set(previous);
+        r
+    })
+}
+
+pub(crate) fn lettered_variables() -> bool {
+    LETTERED_VARIABLES.with(|cell| cell.get())
+}
+
+/// While `op` runs, the placeholders in `names` are printed by their
+/// name, as `!T` rather than `!1_0` (see `PeeledGoal`).
+pub fn with_placeholder_names<OP, R>(
+    names: &BTreeMap<ir::PlaceholderIndex, String>,
+    op: OP,
+) -> R
+where
+    OP: FnOnce() -> R,
+{
+    PLACEHOLDER_NAMES.with(|cell| {
+        let previous = cell.replace(names.clone());
+        let r = op();
+        *cell.borrow_mut() = previous;
+        r
+    })
+}
+
+pub(crate) fn placeholder_name(placeholder: ir::PlaceholderIndex) -> Option<String> {
+    PLACEHOLDER_NAMES.with(|cell| cell.borrow().get(&placeholder).cloned())
+}
@@ -1,45 +1,40 @@
 #![recursion_limit = "1024"]
-#![cfg_attr(test, feature(test))]
-#![feature(catch_expr)]
-#![feature(crate_in_paths)]
-#![feature(crate_visibility_modifier)]
-#![feature(in_band_lifetimes)]
-#![feature(macro_at_most_once_rep)]
-#![feature(macro_vis_matcher)]
-#![feature(specialization)]
-#![feature(step_trait)]
-#![feature(non_modrs_mods)]
-#![feature(underscore_imports)]
+#![cfg_attr(all(test, feature = "bench"), feature(test))]
 
 extern crate chalk_parse;
 #[macro_use]
 extern crate chalk_macros;
 extern crate chalk_engine;
-extern crate diff;
+extern crate diff as text_diff;
 extern crate ena;
 #[macro_use]
 extern crate error_chain;
 extern crate itertools;
 extern crate lalrpop_intern;
 extern crate petgraph;
+extern crate smallvec;
 extern crate stacker;
 
 #[macro_use]
 mod test_util;
 
 #[macro_use]
-crate mod fold;
+pub(crate) mod fold;
 
 #[macro_use]
-crate mod zip;
+pub mod ir;
 
 #[macro_use]
-pub mod ir;
+pub mod zip;
 
-crate mod cast;
-crate mod coherence;
-crate mod rules;
+pub(crate) mod cast;
+pub(crate) mod coherence;
+pub(crate) mod rules;
+pub mod completion;
+pub mod diff;
 pub mod errors;
+pub mod replay;
 pub mod solve;
 
 pub use crate::chalk_engine::fallible;
+pub use coherence::ImplChecker;
@@ -0,0 +1,450 @@
+//! Recordings of chalki sessions, to be attached to bug reports. A
+//! recording lists the goals solved in a session, each with the program
+//! it was solved in, the `SolverChoice` it was solved with and the
+//! solution that was printed, so that the session can be replayed
+//! elsewhere and the solutions compared with the recorded ones (see the
+//! `--record` and `--replay` options of chalki).
+//!
+//! Recordings are text files, starting with the version of the format:
+//!
+//! ```notrust
+//! chalkrec 1
+//! program 5e2a8b1c03d4f796 file
+//! text 49
+//! struct Foo { }
+//! trait Bar { }
+//! impl Bar for Foo { }
+//! solve 5e2a8b1c03d4f796
+//! solver max_size=10 truncation=node-count scheduling=depth-first ...
+//! disabled
+//! goal 8
+//! Foo: Bar
+//! solution 48
+//! Unique; substitution [], lifetime constraints []
+//! ```
+//!
+//! Texts are written after their length in bytes, and followed by a
+//! newline, so that they may contain anything. A program is written out
+//! in full before the first goal solved in it; the goals solved in it
+//! later only give its fingerprint. Recording to an existing recording
+//! appends to it.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io::{self, Write};
+
+use errors::*;
+use solve::{ConstOverflow, Scheduling, SolverChoice, SubgoalOrdering, TruncationMeasure};
+
+mod test;
+
+/// The version of the format written by `Recorder`, the only one that
+/// `Recording::parse` accepts.
+pub const VERSION: u32 = 1;
+
+/// The source text of a program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProgramSource {
+    /// A program in a single file.
+    File(String),
+
+    /// A program split across crates, given by their `(name, text)`.
+    Crates(Vec<(String, String)>),
+}
+
+impl ProgramSource {
+    /// A 64-bit FNV-1a hash of the source, which unlike the hashes of the
+    /// standard library does not depend on the platform or the version
+    /// of the compiler.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash = 0xcbf2_9ce4_8422_2325;
+        {
+            let mut write = |bytes: &[u8]| {
+                for &byte in bytes {
+                    hash ^= u64::from(byte);
+                    hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+                }
+                // `0xff` never appears in UTF-8, so it separates the texts.
+                hash ^= 0xff;
+                hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            };
+            match self {
+                ProgramSource::File(text) => {
+                    write(b"file");
+                    write(text.as_bytes());
+                }
+                ProgramSource::Crates(crates) => {
+                    write(b"crates");
+                    for (name, text) in crates {
+                        write(name.as_bytes());
+                        write(text.as_bytes());
+                    }
+                }
+            }
+        }
+        hash
+    }
+}
+
+/// A goal solved in a recorded session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordedSolve {
+    /// The fingerprint of the program the goal was solved in.
+    pub program: u64,
+    pub solver_choice: SolverChoice,
+
+    /// The names of the impls and clauses that were disabled, sorted.
+    pub disabled_clauses: Vec<String>,
+    pub goal: String,
+
+    /// The solution as it was printed, or the error that was.
+    pub solution: String,
+}
+
+/// A goal whose solution differed from the recorded one when a
+/// recording was replayed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// The position of the goal among the recorded ones.
+    pub index: usize,
+    pub goal: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "goal #{} (`{}`) diverged", self.index, self.goal)?;
+        writeln!(f, "recorded:\n{}", self.expected.trim_end())?;
+        write!(f, "replayed:\n{}", self.actual.trim_end())
+    }
+}
+
+/// The contents of a recording.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Recording {
+    /// The programs, by fingerprint.
+    pub programs: BTreeMap<u64, ProgramSource>,
+    pub solves: Vec<RecordedSolve>,
+}
+
+impl Recording {
+    /// Reads a recording, checking that its programs match their
+    /// fingerprints.
+    pub fn parse(text: &str) -> Result<Recording> {
+        let mut reader = Reader { rest: text, line: 0 };
+        let mut recording = Recording::default();
+
+        match reader.words()?.as_slice() {
+            ["chalkrec", version] if *version == VERSION.to_string() => {}
+            ["chalkrec", version] => bail!(
+                "recordings of version {} cannot be read, only those of version {}",
+                version,
+                VERSION
+            ),
+            _ => bail!("not a recording: it does not start with `chalkrec {}`", VERSION),
+        }
+
+        while !reader.rest.is_empty() {
+            match reader.words()?.as_slice() {
+                ["program", fingerprint, "file"] => {
+                    let fingerprint = parse_fingerprint(fingerprint)?;
+                    let source = ProgramSource::File(reader.text("text")?);
+                    recording.add_program(fingerprint, source)?;
+                }
+                ["program", fingerprint, "crates", count] => {
+                    let fingerprint = parse_fingerprint(fingerprint)?;
+                    let count = parse_number(count)?;
+                    let mut crates = vec![];
+                    for _ in 0..count {
+                        let name = match reader.words()?.as_slice() {
+                            ["crate", name] => name.to_string(),
+                            _ => bail!("expected a crate on line {}", reader.line),
+                        };
+                        crates.push((name, reader.text("text")?));
+                    }
+                    recording.add_program(fingerprint, ProgramSource::Crates(crates))?;
+                }
+                ["solve", fingerprint] => {
+                    let program = parse_fingerprint(fingerprint)?;
+                    if !recording.programs.contains_key(&program) {
+                        bail!("goal on line {} is solved in an unknown program", reader.line);
+                    }
+                    let solver_choice = match reader.words()?.split_first() {
+                        Some((&"solver", parameters)) => parse_solver_choice(parameters)?,
+                        _ => bail!("expected the solver options on line {}", reader.line),
+                    };
+                    let disabled_clauses = match reader.words()?.split_first() {
+                        Some((&"disabled", names)) => {
+                            names.iter().map(|name| name.to_string()).collect()
+                        }
+                        _ => bail!("expected the disabled clauses on line {}", reader.line),
+                    };
+                    let goal = reader.text("goal")?;
+                    let solution = reader.text("solution")?;
+                    recording.solves.push(RecordedSolve {
+                        program,
+                        solver_choice,
+                        disabled_clauses,
+                        goal,
+                        solution,
+                    });
+                }
+                _ => bail!("expected a program or a goal on line {}", reader.line),
+            }
+        }
+
+        Ok(recording)
+    }
+
+    fn add_program(&mut self, fingerprint: u64, source: ProgramSource) -> Result<()> {
+        if source.fingerprint() != fingerprint {
+            bail!("program with fingerprint {:016x} does not match it", fingerprint);
+        }
+        self.programs.insert(fingerprint, source);
+        Ok(())
+    }
+
+    /// Solves the recorded goals again with `solve`, which is given each
+    /// goal with the program it was solved in, and returns those whose
+    /// solution differs from the recorded one.
+    pub fn replay<F>(&self, mut solve: F) -> Vec<Divergence>
+    where
+        F: FnMut(&ProgramSource, &RecordedSolve) -> String,
+    {
+        self.solves
+            .iter()
+            .enumerate()
+            .filter_map(|(index, recorded)| {
+                let actual = solve(&self.programs[&recorded.program], recorded);
+                if actual == recorded.solution {
+                    return None;
+                }
+                Some(Divergence {
+                    index,
+                    goal: recorded.goal.clone(),
+                    expected: recorded.solution.clone(),
+                    actual,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Writes the goals solved in a session to a recording.
+pub struct Recorder<W: Write> {
+    out: W,
+
+    /// The fingerprints of the programs already written out.
+    written: HashSet<u64>,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Creates a recorder appending to `out`, which already holds the
+    /// `previous` recording, if any.
+    pub fn new(mut out: W, previous: Option<&Recording>) -> io::Result<Self> {
+        let written = match previous {
+            Some(previous) => previous.programs.keys().cloned().collect(),
+            None => {
+                writeln!(out, "chalkrec {}", VERSION)?;
+                HashSet::new()
+            }
+        };
+        Ok(Recorder { out, written })
+    }
+
+    /// Records that `solve` was solved in `program`, writing out the
+    /// program first if it is not in the recording yet.
+    pub fn record(&mut self, program: &ProgramSource, solve: &RecordedSolve) -> io::Result<()> {
+        debug_assert_eq!(program.fingerprint(), solve.program);
+        if self.written.insert(solve.program) {
+            match program {
+                ProgramSource::File(text) => {
+                    writeln!(self.out, "program {:016x} file", solve.program)?;
+                    self.write_text("text", text)?;
+                }
+                ProgramSource::Crates(crates) => {
+                    writeln!(self.out, "program {:016x} crates {}", solve.program, crates.len())?;
+                    for (name, text) in crates {
+                        writeln!(self.out, "crate {}", name)?;
+                        self.write_text("text", text)?;
+                    }
+                }
+            }
+        }
+
+        writeln!(self.out, "solve {:016x}", solve.program)?;
+        writeln!(self.out, "solver {}", solver_choice_parameters(solve.solver_choice))?;
+        write!(self.out, "disabled")?;
+        for name in &solve.disabled_clauses {
+            write!(self.out, " {}", name)?;
+        }
+        writeln!(self.out)?;
+        self.write_text("goal", &solve.goal)?;
+        self.write_text("solution", &solve.solution)?;
+        self.out.flush()
+    }
+
+    fn write_text(&mut self, keyword: &str, text: &str) -> io::Result<()> {
+        writeln!(self.out, "{} {}", keyword, text.len())?;
+        writeln!(self.out, "{}", text)
+    }
+
+    /// The recording written to.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+struct Reader<'t> {
+    rest: &'t str,
+
+    /// The number of the line last read, for error messages.
+    line: usize,
+}
+
+impl<'t> Reader<'t> {
+    /// The words of the next line.
+    fn words(&mut self) -> Result<Vec<&'t str>> {
+        let end = match self.rest.find('\n') {
+            Some(end) => end,
+            None => bail!("recording ends in the middle of line {}", self.line + 1),
+        };
+        let line = &self.rest[..end];
+        self.rest = &self.rest[end + 1..];
+        self.line += 1;
+        Ok(line.split_whitespace().collect())
+    }
+
+    /// A text written after `keyword` and its length.
+    fn text(&mut self, keyword: &str) -> Result<String> {
+        let len = match self.words()?.as_slice() {
+            [word, len] if word == &keyword => parse_number(len)?,
+            _ => bail!("expected `{}` on line {}", keyword, self.line),
+        };
+        if self.rest.len() <= len || self.rest.as_bytes()[len] != b'\n' {
+            bail!("text after line {} is not {} bytes long", self.line, len);
+        }
+        let text = &self.rest[..len];
+        self.rest = &self.rest[len + 1..];
+        self.line += text.matches('\n').count() + 1;
+        Ok(text.to_string())
+    }
+}
+
+fn parse_number(word: &str) -> Result<usize> {
+    match word.parse() {
+        Ok(number) => Ok(number),
+        Err(_) => bail!("expected a number, found `{}`", word),
+    }
+}
+
+fn parse_fingerprint(word: &str) -> Result<u64> {
+    match u64::from_str_radix(word, 16) {
+        Ok(fingerprint) => Ok(fingerprint),
+        Err(_) => bail!("expected a fingerprint, found `{}`", word),
+    }
+}
+
+/// The parameters of `solver_choice`, as `name=value` pairs.
+fn solver_choice_parameters(solver_choice: SolverChoice) -> String {
+    match solver_choice {
+        SolverChoice::SLG {
+            max_size,
+            truncation,
+            scheduling,
+            ordering,
+            simplify_goals,
+            max_projection_depth,
+            max_type_depth,
+            track_provenance,
+            const_overflow,
+            ground_fast_path,
+            max_cached_tables,
+        } => format!(
+            "max_size={} truncation={} scheduling={} ordering={} simplify_goals={} \
+             max_projection_depth={} max_type_depth={} track_provenance={} const_overflow={} \
+             ground_fast_path={} max_cached_tables={}",
+            max_size,
+            match truncation {
+                TruncationMeasure::Depth => "depth",
+                TruncationMeasure::NodeCount => "node-count",
+            },
+            match scheduling {
+                Scheduling::DepthFirst => "depth-first",
+                Scheduling::FewestSubgoalsFirst => "fewest-subgoals-first",
+                Scheduling::PreferGroundClauses => "prefer-ground-clauses",
+            },
+            match ordering {
+                SubgoalOrdering::AsWritten => "as-written",
+                SubgoalOrdering::ByPriority => "by-priority",
+            },
+            simplify_goals,
+            max_projection_depth,
+            max_type_depth,
+            track_provenance,
+            match const_overflow {
+                ConstOverflow::Error => "error",
+                ConstOverflow::CannotProve => "cannot-prove",
+            },
+            ground_fast_path,
+            max_cached_tables,
+        ),
+    }
+}
+
+/// The inverse of `solver_choice_parameters`. Parameters which are not
+/// given keep their default value.
+fn parse_solver_choice(parameters: &[&str]) -> Result<SolverChoice> {
+    let mut solver_choice = SolverChoice::default();
+    {
+        let SolverChoice::SLG {
+            max_size,
+            truncation,
+            scheduling,
+            ordering,
+            simplify_goals,
+            max_projection_depth,
+            max_type_depth,
+            track_provenance,
+            const_overflow,
+            ground_fast_path,
+            max_cached_tables,
+        } = &mut solver_choice;
+
+        for parameter in parameters {
+            let mut parts = parameter.splitn(2, '=');
+            let (name, value) = match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => (name, value),
+                _ => bail!("expected a solver option, found `{}`", parameter),
+            };
+            match (name, value) {
+                ("max_size", value) => *max_size = parse_number(value)?,
+                ("truncation", "depth") => *truncation = TruncationMeasure::Depth,
+                ("truncation", "node-count") => *truncation = TruncationMeasure::NodeCount,
+                ("scheduling", "depth-first") => *scheduling = Scheduling::DepthFirst,
+                ("scheduling", "fewest-subgoals-first") => {
+                    *scheduling = Scheduling::FewestSubgoalsFirst
+                }
+                ("scheduling", "prefer-ground-clauses") => {
+                    *scheduling = Scheduling::PreferGroundClauses
+                }
+                ("ordering", "as-written") => *ordering = SubgoalOrdering::AsWritten,
+                ("ordering", "by-priority") => *ordering = SubgoalOrdering::ByPriority,
+                ("simplify_goals", "true") => *simplify_goals = true,
+                ("simplify_goals", "false") => *simplify_goals = false,
+                ("max_projection_depth", value) => *max_projection_depth = parse_number(value)?,
+                ("max_type_depth", value) => *max_type_depth = parse_number(value)?,
+                ("track_provenance", "true") => *track_provenance = true,
+                ("track_provenance", "false") => *track_provenance = false,
+                ("const_overflow", "error") => *const_overflow = ConstOverflow::Error,
+                ("const_overflow", "cannot-prove") => *const_overflow = ConstOverflow::CannotProve,
+                ("ground_fast_path", "true") => *ground_fast_path = true,
+                ("ground_fast_path", "false") => *ground_fast_path = false,
+                ("max_cached_tables", value) => *max_cached_tables = parse_number(value)?,
+                _ => bail!("unknown solver option `{}`", parameter),
+            }
+        }
+    }
+    Ok(solver_choice)
+}
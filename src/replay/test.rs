@@ -0,0 +1,173 @@
+#![cfg(test)]
+
+use std::collections::HashSet;
+use std::io::Write;
+use std::sync::Arc;
+
+use ir;
+use solve::{ConstOverflow, Scheduling, SolverChoice, SubgoalOrdering, TruncationMeasure};
+use test_util::*;
+
+use super::*;
+
+/// Solves `recorded` as chalki would, if it were given a single file.
+fn solve(source: &ProgramSource, recorded: &RecordedSolve) -> String {
+    let text = match source {
+        ProgramSource::File(text) => text,
+        ProgramSource::Crates(_) => panic!("only single files are solved in these tests"),
+    };
+    let program = Arc::new(parse_and_lower_program(text, recorded.solver_choice).unwrap());
+    let disabled: HashSet<_> = recorded.disabled_clauses.iter().cloned().collect();
+    let env = Arc::new(program.environment().without_clauses(&disabled));
+    ir::tls::set_current_program(&program, || {
+        let goal = parse_and_lower_goal(&program, &recorded.goal).unwrap();
+        match recorded.solver_choice.solve_root_goal(&env, &goal.into_closed_goal()) {
+            Ok(Some(solution)) => format!("{}", solution),
+            Ok(None) => format!("No possible solution"),
+            Err(e) => format!("{}", e),
+        }
+    })
+}
+
+/// Solves `goal` in `source` and records it.
+fn record<W: Write>(
+    recorder: &mut Recorder<W>,
+    source: &ProgramSource,
+    solver_choice: SolverChoice,
+    disabled_clauses: &[&str],
+    goal: &str,
+) -> RecordedSolve {
+    let mut recorded = RecordedSolve {
+        program: source.fingerprint(),
+        solver_choice,
+        disabled_clauses: disabled_clauses.iter().map(|name| name.to_string()).collect(),
+        goal: goal.to_string(),
+        solution: String::new(),
+    };
+    recorded.solution = solve(source, &recorded);
+    recorder.record(source, &recorded).unwrap();
+    recorded
+}
+
+fn non_default_solver_choice() -> SolverChoice {
+    SolverChoice::SLG {
+        max_size: 5,
+        truncation: TruncationMeasure::Depth,
+        scheduling: Scheduling::FewestSubgoalsFirst,
+        ordering: SubgoalOrdering::ByPriority,
+        simplify_goals: true,
+        max_projection_depth: 8,
+        max_type_depth: 32,
+        track_provenance: true,
+        const_overflow: ConstOverflow::CannotProve,
+        ground_fast_path: false,
+        max_cached_tables: 100,
+    }
+}
+
+#[test]
+fn record_then_replay() {
+    let first = ProgramSource::File(
+        "struct Foo { }\ntrait Bar { }\n#[name = \"bar_foo\"] impl Bar for Foo { }".to_string(),
+    );
+    let second = ProgramSource::File(
+        "struct Foo { }\nstruct Baz { }\ntrait Bar { }\nimpl Bar for Baz { }\n".to_string(),
+    );
+    let default = SolverChoice::default();
+
+    // The program is reloaded twice: the second time, it is the first
+    // one again, which is not written out again.
+    let mut recorder = Recorder::new(Vec::new(), None).unwrap();
+    let mut solves = vec![
+        record(&mut recorder, &first, default, &[], "Foo: Bar"),
+        record(&mut recorder, &first, default, &["bar_foo"], "Foo: Bar"),
+        record(&mut recorder, &second, non_default_solver_choice(), &[], "exists<T> { T: Bar }"),
+        record(&mut recorder, &first, default, &[], "exists<T> { T: Bar }"),
+    ];
+    assert_ne!(solves[0].solution, solves[1].solution);
+    let text = String::from_utf8(recorder.into_inner()).unwrap();
+    assert!(text.starts_with("chalkrec 1\n"));
+    assert_eq!(text.matches("\nprogram ").count(), 2);
+
+    let recording = Recording::parse(&text).unwrap();
+    assert_eq!(recording.programs.len(), 2);
+    assert_eq!(recording.programs[&first.fingerprint()], first);
+    assert_eq!(recording.programs[&second.fingerprint()], second);
+    assert_eq!(recording.solves, solves);
+    assert_eq!(recording.replay(solve), vec![]);
+
+    // A second session is appended to the first.
+    let mut recorder = Recorder::new(text.into_bytes(), Some(&recording)).unwrap();
+    solves.push(record(&mut recorder, &second, default, &[], "Foo: Bar"));
+    let text = String::from_utf8(recorder.into_inner()).unwrap();
+    assert_eq!(text.matches("chalkrec").count(), 1);
+    assert_eq!(text.matches("\nprogram ").count(), 2);
+
+    let mut recording = Recording::parse(&text).unwrap();
+    assert_eq!(recording.solves, solves);
+    assert_eq!(recording.replay(solve), vec![]);
+
+    recording.solves[1].solution = "Unique".to_string();
+    assert_eq!(
+        recording.replay(solve),
+        vec![Divergence {
+            index: 1,
+            goal: "Foo: Bar".to_string(),
+            expected: "Unique".to_string(),
+            actual: solves[1].solution.clone(),
+        }]
+    );
+}
+
+#[test]
+fn texts_and_solver_choices_round_trip() {
+    let source = ProgramSource::Crates(vec![
+        ("upstream".to_string(), "trait Foo { }\n\n".to_string()),
+        ("downstream".to_string(), "struct Bär { }\nimpl Foo for Bär { }".to_string()),
+    ]);
+    let recorded = RecordedSolve {
+        program: source.fingerprint(),
+        solver_choice: non_default_solver_choice(),
+        disabled_clauses: vec!["a".to_string(), "b".to_string()],
+        goal: "in crate upstream {\n    exists<T> { T: Foo }\n}".to_string(),
+        solution: "Ambiguous; no inference guidance\n\n".to_string(),
+    };
+
+    let mut recorder = Recorder::new(Vec::new(), None).unwrap();
+    recorder.record(&source, &recorded).unwrap();
+    let text = String::from_utf8(recorder.into_inner()).unwrap();
+
+    let recording = Recording::parse(&text).unwrap();
+    assert_eq!(recording.programs[&recorded.program], source);
+    assert_eq!(recording.solves, vec![recorded]);
+}
+
+#[test]
+fn malformed_recordings() {
+    let source = ProgramSource::File("struct Foo { }".to_string());
+    let mut recorder = Recorder::new(Vec::new(), None).unwrap();
+    recorder
+        .record(
+            &source,
+            &RecordedSolve {
+                program: source.fingerprint(),
+                solver_choice: SolverChoice::default(),
+                disabled_clauses: vec![],
+                goal: "Foo = Foo".to_string(),
+                solution: "Unique".to_string(),
+            },
+        )
+        .unwrap();
+    let text = String::from_utf8(recorder.into_inner()).unwrap();
+    assert!(Recording::parse(&text).is_ok());
+
+    let other_version = text.replace("chalkrec 1", "chalkrec 2");
+    assert!(Recording::parse(&other_version).is_err());
+
+    // The program no longer matches its fingerprint.
+    let edited = text.replace("struct Foo", "struct Qux");
+    assert!(Recording::parse(&edited).is_err());
+
+    let truncated = &text[..text.len() - 3];
+    assert!(Recording::parse(truncated).is_err());
+}
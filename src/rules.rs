@@ -2,9 +2,12 @@ use cast::{Cast, Caster};
 use fold::shift::Shift;
 use fold::Subst;
 use ir::*;
+use ir::could_match::NegativeCache;
+use std::collections::BTreeMap;
 use std::iter;
 
 mod default;
+mod elaborate;
 mod wf;
 
 impl Program {
@@ -23,10 +26,15 @@ impl Program {
                 .flat_map(|d| d.to_program_clauses()),
         );
         program_clauses.extend(
-            self.trait_data
+            self.synthetic_ty_data
                 .values()
                 .flat_map(|d| d.to_program_clauses()),
         );
+        program_clauses.extend(
+            self.trait_data
+                .values()
+                .flat_map(|d| d.to_program_clauses(self)),
+        );
         program_clauses.extend(
             self.associated_ty_data
                 .values()
@@ -49,32 +57,44 @@ impl Program {
                 value: ProgramClauseImplication {
                     consequence: DomainGoal::Derefs(Derefs { source: t(), target: u() }),
                     conditions: vec![ProjectionEq {
-                        projection: ProjectionTy {
-                            associated_ty_id,
-                            parameters: vec![t().cast()]
-                        },
+                        projection: ProjectionTy::new(associated_ty_id, vec![t().cast()], self)
+                            .expect("`Deref::Target` takes no parameters of its own"),
                         ty: u(),
                     }.cast()]
                 },
             }.cast());
         }
 
-        for datum in self.impl_data.values() {
-            // If we encounter a negative impl, do not generate any rule. Negative impls
-            // are currently just there to deactivate default impls for auto traits.
-            if datum.binders.value.trait_ref.is_positive() {
-                program_clauses.push(datum.to_program_clause());
-                program_clauses.extend(
-                    datum
-                        .binders
-                        .value
-                        .associated_ty_values
-                        .iter()
-                        .flat_map(|atv| atv.to_program_clauses(self, datum)),
-                );
-            }
+        let mut impl_clauses = BTreeMap::new();
+        let mut impl_clause_ranges = BTreeMap::new();
+        let mut trait_impl_clauses: BTreeMap<ItemId, Vec<_>> = BTreeMap::new();
+        for (&impl_id, datum) in self.impl_data.iter() {
+            let clauses = self.impl_clauses(datum);
+            impl_clauses.extend(clauses.iter().map(|clause| (clause.clone(), impl_id)));
+            let start = program_clauses.len();
+            program_clauses.extend(clauses);
+            let range = start..program_clauses.len();
+            trait_impl_clauses
+                .entry(datum.binders.value.trait_ref.trait_ref().trait_id)
+                .or_default()
+                .push((impl_id, range.clone()));
+            impl_clause_ranges.insert(impl_id, range);
         }
 
+        // The custom clauses come first, so their ranges index
+        // `program_clauses` as well.
+        let named_clauses = self
+            .item_names
+            .iter()
+            .map(|(name, item)| {
+                let range = match item {
+                    NamedItem::Impl(impl_id) => impl_clause_ranges[impl_id].clone(),
+                    NamedItem::CustomClauses(range) => range.clone(),
+                };
+                (name.clone(), range)
+            })
+            .collect();
+
         let trait_data = self.trait_data.clone();
         let associated_ty_data = self.associated_ty_data.clone();
 
@@ -82,8 +102,32 @@ impl Program {
             trait_data,
             associated_ty_data,
             program_clauses,
+            named_clauses,
+            impl_clauses,
+            trait_impl_clauses,
+            negative_cache: NegativeCache::new(),
         }
     }
+
+    /// The clauses of an impl, including those of its associated type
+    /// values.
+    fn impl_clauses(&self, datum: &ImplDatum) -> Vec<ProgramClause> {
+        // If we encounter a negative impl, do not generate any rule. Negative impls
+        // are currently just there to deactivate default impls for auto traits.
+        if !datum.binders.value.trait_ref.is_positive() {
+            return vec![];
+        }
+        iter::once(datum.to_program_clause())
+            .chain(
+                datum
+                    .binders
+                    .value
+                    .associated_ty_values
+                    .iter()
+                    .flat_map(|atv| atv.to_program_clauses(self, datum)),
+            )
+            .collect()
+    }
 }
 
 impl ImplDatum {
@@ -176,15 +220,9 @@ impl AssociatedTyValue {
     /// }
     /// ```
     ///
-    /// and:
-    ///
-    /// ```notrust
-    /// forall<'a, T> {
-    ///     UnselectedNormalize(Vec<T>::IntoIter<'a> -> Iter<'a, T>) :-
-    ///         InScope(Iterable),
-    ///         Normalize(<Vec<T> as Iterable>::IntoIter<'a> -> Iter<'a, T>)
-    /// }
-    /// ```
+    /// The unselected form `Vec<T>::IntoIter<'a>` is normalized through
+    /// the clauses of the associated type (see
+    /// `AssociatedTyDatum::to_program_clauses`).
     fn to_program_clauses(
         &self,
         program: &Program,
@@ -192,27 +230,20 @@ impl AssociatedTyValue {
     ) -> Vec<ProgramClause> {
         let associated_ty = &program.associated_ty_data[&self.associated_ty_id];
 
-        // Begin with the innermost parameters (`'a`) and then add those from impl (`T`).
-        let all_binders: Vec<_> = self.value
-            .binders
-            .iter()
-            .chain(impl_datum.binders.binders.iter())
-            .cloned()
-            .collect();
+        // The value is bound by the innermost parameters (`'a`) and then
+        // those from the impl (`T`).
+        let value = self.value.fuse();
 
+        // The trait-ref of the impl, seen from under the innermost
+        // parameters.
         let impl_trait_ref = impl_datum.binders
                                        .value
                                        .trait_ref
                                        .trait_ref()
-                                       .up_shift(self.value.len());
+                                       .up_shift(self.in_impl().len());
 
-        let all_parameters: Vec<_> =
-            self.value.binders
-                      .iter()
-                      .zip(0..)
-                      .map(|p| p.to_parameter())
-                      .chain(impl_trait_ref.parameters.iter().cloned())
-                      .collect();
+        // `'a, Vec<T>`: the parameters of the projection being normalized
+        let all_parameters = self.projection_parameters(&impl_trait_ref);
 
         // Assemble the full list of conditions for projection to be valid.
         // This comes in two parts, marked as (1) and (2) in example above:
@@ -228,64 +259,27 @@ impl AssociatedTyValue {
 
         let conditions: Vec<Goal> =
             where_clauses
-            .chain(Some(impl_trait_ref.clone().cast()))
+            .chain(Some(impl_trait_ref.cast()))
             .collect();
 
-        // Bound parameters + `Self` type of the trait-ref
-        let parameters: Vec<_> = {
-            // First add refs to the bound parameters (`'a`, in above example)
-            let parameters = self.value.binders.iter().zip(0..).map(|p| p.to_parameter());
-
-            // Then add the `Self` type (`Vec<T>`, in above example)
-            parameters
-                .chain(Some(impl_trait_ref.parameters[0].clone()))
-                .collect()
-        };
-
-        let projection = ProjectionTy {
-            associated_ty_id: self.associated_ty_id,
-
-            // Add the remaining parameters of the trait-ref, if any
-            parameters: parameters.iter()
-                                  .chain(&impl_trait_ref.parameters[1..])
-                                  .cloned()
-                                  .collect(),
-        };
+        let projection = ProjectionTy::new(self.associated_ty_id, all_parameters, program)
+            .expect("associated type values are kind-checked when lowered");
 
         let normalize_goal = DomainGoal::Normalize(Normalize {
-            projection: projection.clone(),
-            ty: self.value.value.ty.clone(),
+            projection: projection,
+            ty: value.value.ty,
         });
 
         // Determine the normalization
         let normalization = Binders {
-            binders: all_binders.clone(),
+            binders: value.binders,
             value: ProgramClauseImplication {
-                consequence: normalize_goal.clone(),
+                consequence: normalize_goal,
                 conditions: conditions,
             },
         }.cast();
 
-        let unselected_projection = UnselectedProjectionTy {
-            type_name: associated_ty.name.clone(),
-            parameters: parameters,
-        };
-
-        let unselected_normalization = Binders {
-            binders: all_binders.clone(),
-            value: ProgramClauseImplication {
-                consequence: DomainGoal::UnselectedNormalize(UnselectedNormalize {
-                    projection: unselected_projection,
-                    ty: self.value.value.ty.clone(),
-                }),
-                conditions: vec![
-                    normalize_goal.cast(),
-                    DomainGoal::InScope(impl_trait_ref.trait_id).cast(),
-                ],
-            },
-        }.cast();
-
-        vec![normalization, unselected_normalization]
+        vec![normalization]
     }
 }
 
@@ -433,8 +427,51 @@ impl StructDatum {
     }
 }
 
-impl TraitDatum {
+impl SyntheticTyDatum {
     fn to_program_clauses(&self) -> Vec<ProgramClause> {
+        // Given:
+        //
+        //    synthetic type FnDef<type, lifetime>;
+        //
+        // we generate the following clauses:
+        //
+        //    forall<T, 'a> { WF(FnDef<T, 'a>) }
+        //    forall<T, 'a> { IsFullyVisible(FnDef<T, 'a>) :- IsFullyVisible(T) }
+        //    forall<T, 'a> { IsLocal(FnDef<T, 'a>) }
+        //
+        // or `IsUpstream` rather than `IsLocal`, if the type is declared
+        // in an upstream crate. The constituents of the type only
+        // matter to auto traits (see `DefaultImplDatum`).
+        let wf = self.binders.map_ref(|bound_datum| ProgramClauseImplication {
+            consequence: WellFormed::Ty(bound_datum.self_ty.clone().cast()).cast(),
+            conditions: vec![],
+        }).cast();
+
+        let is_fully_visible = self.binders.map_ref(|bound_datum| ProgramClauseImplication {
+            consequence: DomainGoal::IsFullyVisible(bound_datum.self_ty.clone().cast()),
+            conditions: bound_datum.self_ty.type_parameters()
+            .map(|ty| DomainGoal::IsFullyVisible(ty).cast())
+            .collect(),
+        }).cast();
+
+        let is_local_or_upstream = self.binders.map_ref(|bound_datum| {
+            let self_ty = bound_datum.self_ty.clone().cast();
+            ProgramClauseImplication {
+                consequence: if bound_datum.upstream {
+                    DomainGoal::IsUpstream(self_ty)
+                } else {
+                    DomainGoal::IsLocal(self_ty)
+                },
+                conditions: vec![],
+            }
+        }).cast();
+
+        vec![wf, is_fully_visible, is_local_or_upstream]
+    }
+}
+
+impl TraitDatum {
+    fn to_program_clauses(&self, program: &Program) -> Vec<ProgramClause> {
         // Given:
         //
         //    trait Ord<T> where Self: Eq<T> { ... }
@@ -450,6 +487,15 @@ impl TraitDatum {
         //    forall<Self, T> { (Self: Ord<T>) :- FromEnv(Self: Ord<T>) }
         //    forall<Self, T> { FromEnv(Self: Eq<T>) :- FromEnv(Self: Ord<T>) }
         //
+        // The reverse rules are generated for the where clauses of the trait elaborated
+        // transitively (see `Program::elaborate_supertraits`), rather than only those
+        // written on the trait: if `Eq<T>` had a supertrait `PartialEq<T>`, we would also
+        // generate `FromEnv(Self: PartialEq<T>) :- FromEnv(Self: Ord<T>)`. The elaboration
+        // is done once per trait and does not expand a trait twice, so mutually recursive
+        // supertraits like `trait A where Self: B` and `trait B where Self: A` give us a
+        // single step from each trait to the other, instead of tables for `FromEnv` goals
+        // that feed each other.
+        //
         // As specified in the orphan rules, if a trait is not marked `#[upstream]`, the current crate
         // can implement it for any type. To represent that, we generate:
         //
@@ -579,6 +625,7 @@ impl TraitDatum {
 
             clauses.push(impl_may_exist);
         }
+        clauses.extend(self.fundamental_impls_may_exist(program));
 
         if !self.binders.value.flags.upstream {
             let impl_allowed = self.binders.map_ref(|bound_datum|
@@ -625,12 +672,13 @@ impl TraitDatum {
 
         let condition = DomainGoal::FromEnv(FromEnv::Trait(trait_ref.clone()));
 
-        for wc in self.binders
-                      .value
-                      .where_clauses
-                      .iter()
-                      .cloned()
-                      .map(|wc| wc.map(|bound| bound.into_from_env_goal()))
+        // A trait which is its own supertrait, through a cycle, elaborates back to
+        // itself; `FromEnv(Self: A) :- FromEnv(Self: A)` would be of no use.
+        let own_trait_ref = WhereClause::Implemented(trait_ref.clone());
+        for wc in trait_ref.elaborated_where_clauses(&program.elaborated_where_clauses)
+                           .into_iter()
+                           .filter(|wc| !(wc.binders.is_empty() && wc.value == own_trait_ref))
+                           .map(|wc| wc.map(|bound| bound.into_from_env_goal()))
         {
             // We move the binders of the where-clause to the left for the reverse rules,
             // cf `StructDatum::to_program_clauses`.
@@ -653,6 +701,103 @@ impl TraitDatum {
 
         clauses
     }
+
+    /// The cases for potential downstream impls that the orphan rules allow through the
+    /// `#[fundamental]` parameters of the trait (see `OrphanSolver::orphan_check`): a downstream
+    /// type may appear directly as a type argument of such a parameter, provided the types
+    /// before it are fully visible. As the parameter may be any type, we generate a clause for
+    /// each type argument of each type constructor of the program:
+    ///
+    ///    // For `#[upstream] trait Fn<#[fundamental] Args>` and `struct Pair<T, U>`
+    ///    forall<Self, Args, T, U> {
+    ///        Implemented(Self: Fn<Args>) :-
+    ///            Compatible,
+    ///            IsFullyVisible(Self),
+    ///            DownstreamType(T),
+    ///            Args = Pair<T, U>,
+    ///            CannotProve,
+    ///    }
+    ///    forall<Self, Args, T, U> {
+    ///        Implemented(Self: Fn<Args>) :-
+    ///            Compatible,
+    ///            IsFullyVisible(Self),
+    ///            IsFullyVisible(T),
+    ///            DownstreamType(U),
+    ///            Args = Pair<T, U>,
+    ///            CannotProve,
+    ///    }
+    ///
+    /// The conditions are proven from last to first, so `Args = Pair<T, U>` binds `T` and `U`
+    /// before they are looked at; `IsFullyVisible` of an unbound type would enumerate every
+    /// type.
+    fn fundamental_impls_may_exist(&self, program: &Program) -> Vec<ProgramClause> {
+        let bound_datum = &self.binders.value;
+        let type_constructors: Vec<Binders<Ty>> = program.struct_data
+            .values()
+            .map(|d| d.binders.map_ref(|bound| bound.self_ty.clone().cast()))
+            .chain(program.synthetic_ty_data.values().map(|d| {
+                d.binders.map_ref(|bound| bound.self_ty.clone().cast())
+            }))
+            .collect();
+
+        let mut clauses = Vec::new();
+        let mut visible = Vec::new();
+        for (parameter, &fundamental) in bound_datum.trait_ref.parameters.iter()
+            .zip(&bound_datum.fundamental_parameters)
+        {
+            let ty = match parameter.clone().ty() {
+                Some(ty) => ty,
+                None => continue,
+            };
+
+            if fundamental {
+                for constructor in &type_constructors {
+                    // The binders of the type constructor come after those of the trait.
+                    let binders: Vec<_> = self.binders.binders.iter()
+                        .chain(&constructor.binders)
+                        .cloned()
+                        .collect();
+                    let self_ty = constructor.value.up_shift(self.binders.len());
+                    let type_parameters: Vec<_> = match self_ty {
+                        Ty::Apply(ref apply) => apply.type_parameters().collect(),
+                        _ => unreachable!(),
+                    };
+                    let parameter_eq = EqGoal { a: ty.clone().cast(), b: self_ty.cast() };
+
+                    for i in 0..type_parameters.len() {
+                        clauses.push(Binders {
+                            binders: binders.clone(),
+                            value: ProgramClauseImplication {
+                                consequence: DomainGoal::Holds(
+                                    WhereClause::Implemented(bound_datum.trait_ref.clone())
+                                ),
+                                conditions: bound_datum.where_clauses
+                                    .iter()
+                                    .cloned()
+                                    .casted()
+                                    .chain(iter::once(DomainGoal::Compatible(()).cast()))
+                                    .chain(visible.iter().cloned())
+                                    .chain((0..i).map(|j| {
+                                        DomainGoal::IsFullyVisible(type_parameters[j].clone())
+                                            .cast()
+                                    }))
+                                    .chain(iter::once(
+                                        DomainGoal::DownstreamType(type_parameters[i].clone())
+                                            .cast()
+                                    ))
+                                    .chain(iter::once(parameter_eq.clone().cast()))
+                                    .chain(iter::once(Goal::CannotProve(())))
+                                    .collect(),
+                            },
+                        }.cast());
+                    }
+                }
+            }
+
+            visible.push(DomainGoal::IsFullyVisible(ty).cast());
+        }
+        clauses
+    }
 }
 
 impl AssociatedTyDatum {
@@ -701,18 +846,25 @@ impl AssociatedTyDatum {
             .map(|pk| pk.map(|_| ()))
             .collect();
         let parameters: Vec<_> = binders.iter().zip(0..).map(|p| p.to_parameter()).collect();
-        let projection = ProjectionTy {
-            associated_ty_id: self.id,
-            parameters: parameters.clone(),
-        };
-
-        // Retrieve the trait ref embedding the associated type
-        let trait_ref = {
-            let (associated_ty_data, trait_params, _) = program.split_projection(&projection);
-            TraitRef {
+        let projection = ProjectionTy::new(self.id, parameters.clone(), program)
+            .expect("parameters are built from the associated type's own kinds");
+
+        // Retrieve the trait ref embedding the associated type, and the
+        // parameters of the unselected projection `Self::Assoc<'a, T>`: the
+        // associated type's own parameters followed by `Self`
+        let (trait_ref, unselected_parameters) = {
+            let (associated_ty_data, trait_params, other_params) =
+                program.split_projection(&projection)
+                    .expect("projection was checked when constructed");
+            let trait_ref = TraitRef {
                 trait_id: associated_ty_data.trait_id,
                 parameters: trait_params.to_owned(),
-            }
+            };
+            let unselected_parameters: Vec<_> = other_params.iter()
+                .chain(Some(&trait_params[0]))
+                .cloned()
+                .collect();
+            (trait_ref, unselected_parameters)
         };
 
         // Construct an application from the projection. So if we have `<T as Iterator>::Item`,
@@ -822,7 +974,7 @@ impl AssociatedTyDatum {
         let normalize = Normalize { projection: projection.clone(), ty: ty.clone() };
 
         // `ProjectionEq(<T as Foo>::Assoc = U)`
-        let projection_eq = ProjectionEq { projection: projection.clone(), ty };
+        let projection_eq = ProjectionEq { projection: projection.clone(), ty: ty.clone() };
 
         // Projection equality rule from above.
         //
@@ -838,6 +990,41 @@ impl AssociatedTyDatum {
             },
         }.cast());
 
+        // Selection of an unselected projection, for each trait in scope
+        // which has an associated type of that name and is implemented.
+        // Going through `ProjectionEq` rather than `Normalize` means that
+        // `T::Assoc` also selects `Foo` when `T: Foo` is only known from
+        // the environment.
+        //
+        //    forall<T, U> {
+        //        UnselectedNormalize(T::Assoc -> U) :-
+        //            InScope(Foo),
+        //            Implemented(T: Foo),
+        //            ProjectionEq(<T as Foo>::Assoc = U).
+        //    }
+        //
+        // When several traits in scope qualify, the projection is
+        // ambiguous: see `SolverChoice::candidates` to list the choices.
+        let unselected_normalize = UnselectedNormalize {
+            projection: UnselectedProjectionTy {
+                type_name: self.name,
+                parameters: unselected_parameters,
+            },
+            ty,
+        };
+        clauses.push(Binders {
+            binders: binders.clone(),
+            value: ProgramClauseImplication {
+                consequence: unselected_normalize.cast(),
+                // (conditions are solved last to first)
+                conditions: vec![
+                    projection_eq.cast(),
+                    trait_ref.clone().cast(),
+                    DomainGoal::InScope(trait_ref.trait_id).cast(),
+                ],
+            },
+        }.cast());
+
         clauses
     }
 }
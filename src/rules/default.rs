@@ -9,10 +9,11 @@ impl Program {
             .values()
             .filter(|t| t.binders.value.flags.auto)
         {
-            for struct_datum in self.struct_data.values() {
+            let auto_trait_id = auto_trait.binders.value.trait_ref.trait_id;
+            for (struct_id, struct_datum) in &self.struct_data {
                 // `MyStruct: MyAutoTrait`
                 let trait_ref = TraitRef {
-                    trait_id: auto_trait.binders.value.trait_ref.trait_id,
+                    trait_id: auto_trait_id,
                     parameters: vec![
                         ParameterKind::Ty(Ty::Apply(struct_datum.binders.value.self_ty.clone())),
                     ],
@@ -20,16 +21,64 @@ impl Program {
 
                 // If a positive or negative impl is already provided for a type family
                 // which includes `MyStruct`, we do not generate a default impl.
-                if self.impl_provided_for(trait_ref.clone(), struct_datum) {
+                if self.impl_provided_for(&struct_datum.binders.map_ref(|_| trait_ref.clone())) {
                     continue;
                 }
 
+                // An `#[interior_mut]` struct, like `UnsafeCell<T>`, is
+                // never `Sync` by default, whatever its contents.
+                let is_sync = self.lang_items.get(&LangItem::SyncTrait)
+                    == Some(&trait_ref.trait_id);
+                if is_sync && struct_datum.binders.value.flags.interior_mut {
+                    continue;
+                }
+
+                // Nor is a struct which opts out of the trait, like
+                // `#[phantom_no_auto(Unpin)] struct PhantomPinned`.
+                if struct_datum.binders.value.flags.no_auto_traits.contains(&auto_trait_id) {
+                    continue;
+                }
+
+                let mut accessible_tys = struct_datum.binders.value.fields.clone();
+
+                // The `Box` and `PhantomData` lang items own their contents
+                // without having a field of that type, so `Box<T>:
+                // MyAutoTrait` holds if and only if `T: MyAutoTrait`.
+                let owns_parameters = [LangItem::BoxStruct, LangItem::PhantomDataStruct]
+                    .iter()
+                    .any(|lang_item| self.lang_items.get(lang_item) == Some(struct_id));
+                if owns_parameters {
+                    accessible_tys.extend(struct_datum.binders.value.self_ty.type_parameters());
+                }
+
                 self.default_impl_data.push(DefaultImplDatum {
                     binders: Binders {
                         binders: struct_datum.binders.binders.clone(),
                         value: DefaultImplDatumBound {
                             trait_ref,
-                            accessible_tys: struct_datum.binders.value.fields.clone(),
+                            accessible_tys,
+                        },
+                    },
+                });
+            }
+
+            // Synthetic types are treated like structs whose fields are
+            // their constituents.
+            for synthetic_ty_datum in self.synthetic_ty_data.values() {
+                let trait_ref = synthetic_ty_datum.binders.map_ref(|bound| TraitRef {
+                    trait_id: auto_trait_id,
+                    parameters: vec![ParameterKind::Ty(Ty::Apply(bound.self_ty.clone()))],
+                });
+                if self.impl_provided_for(&trait_ref) {
+                    continue;
+                }
+
+                self.default_impl_data.push(DefaultImplDatum {
+                    binders: Binders {
+                        binders: trait_ref.binders,
+                        value: DefaultImplDatumBound {
+                            trait_ref: trait_ref.value,
+                            accessible_tys: synthetic_ty_datum.binders.value.constituents.clone(),
                         },
                     },
                 });
@@ -37,12 +86,12 @@ impl Program {
         }
     }
 
-    fn impl_provided_for(&self, trait_ref: TraitRef, struct_datum: &StructDatum) -> bool {
-        let goal: DomainGoal = trait_ref.cast();
-
+    /// Whether an impl, positive or negative, is provided for some of
+    /// the types of which `trait_ref` holds.
+    fn impl_provided_for(&self, trait_ref: &Binders<TraitRef>) -> bool {
         let mut infer = InferenceTable::new();
 
-        let goal = infer.instantiate_binders_existentially(&(&struct_datum.binders.binders, &goal));
+        let goal: DomainGoal = infer.instantiate_binders_existentially(trait_ref).cast();
 
         for impl_datum in self.impl_data.values() {
             // We retrieve the trait ref given by the positive impl (even if the actual impl is negative)
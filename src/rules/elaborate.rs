@@ -0,0 +1,325 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use fallible::*;
+use fold::shift::Shift;
+use fold::{Fold, Subst};
+use ir::could_match::CouldMatch;
+use ir::*;
+use zip::{Zip, Zipper};
+
+#[cfg(test)]
+thread_local! {
+    /// Counts how many times we actually walked a trait hierarchy (as opposed
+    /// to reusing a cached result). Only used in tests.
+    pub(crate) static ELABORATIONS: ::std::cell::Cell<usize> = ::std::cell::Cell::new(0)
+}
+
+impl Program {
+    /// Computes, for each trait, the full set of where clauses implied by its
+    /// header, following supertrait bounds transitively. Given:
+    ///
+    /// ```notrust
+    /// trait Eq<T> { }
+    /// trait Ord<T> where Self: Eq<T> { }
+    /// trait Hash where Self: Ord<u32> { }
+    /// ```
+    ///
+    /// the elaborated where clauses of `Hash` are `Self: Ord<u32>` and
+    /// `Self: Eq<u32>`. The results are expressed in terms of the binders of the
+    /// trait, so that users only have to substitute the parameters of a
+    /// particular trait reference into the cached skeleton.
+    ///
+    /// Only where clauses which are not themselves under binders are
+    /// followed: higher-ranked supertrait bounds are kept as-is.
+    pub(crate) fn elaborate_supertraits(&mut self) {
+        let elaborated: BTreeMap<_, _> = self.trait_data
+            .values()
+            .map(|trait_datum| {
+                let trait_ref = &trait_datum.binders.value.trait_ref;
+                (trait_ref.trait_id, Arc::new(self.elaborate_trait(trait_ref)))
+            })
+            .collect();
+        self.elaborated_where_clauses = elaborated.into();
+    }
+
+    fn elaborate_trait(&self, trait_ref: &TraitRef) -> Vec<QuantifiedWhereClause> {
+        #[cfg(test)]
+        ELABORATIONS.with(|c| c.set(c.get() + 1));
+
+        debug_heading!("elaborate_trait({:?})", trait_ref);
+        let mut result = vec![];
+        self.elaborate_trait_ref(trait_ref, &mut vec![trait_ref.trait_id], &mut result);
+        debug!("elaborate_trait: result={:?}", result);
+        result
+    }
+
+    /// Pushes the where clauses of `trait_ref` onto `result`, then recurses
+    /// into supertraits. We do not recurse into a trait which is already on
+    /// the `stack`: this handles cyclic definitions like `trait A where
+    /// Self: B` and `trait B where Self: A`, and ensures that a bound like
+    /// `trait Foo<T> where Self: Foo<Vec<T>>` does not send us into an
+    /// infinite loop.
+    fn elaborate_trait_ref(
+        &self,
+        trait_ref: &TraitRef,
+        stack: &mut Vec<ItemId>,
+        result: &mut Vec<QuantifiedWhereClause>,
+    ) {
+        let trait_datum = &self.trait_data[&trait_ref.trait_id];
+        for wc in &trait_datum.binders.value.where_clauses {
+            let wc = Subst::apply(&trait_ref.parameters, wc);
+            if result.contains(&wc) {
+                continue;
+            }
+            result.push(wc.clone());
+
+            if !wc.binders.is_empty() {
+                continue;
+            }
+
+            if let WhereClause::Implemented(super_trait_ref) = wc.value {
+                if stack.contains(&super_trait_ref.trait_id) {
+                    continue;
+                }
+                stack.push(super_trait_ref.trait_id);
+                self.elaborate_trait_ref(&super_trait_ref, stack, result);
+                stack.pop();
+            }
+        }
+    }
+}
+
+impl TraitRef {
+    /// Instantiates the cached supertrait elaboration of `self.trait_id` with
+    /// the parameters of `self`. See `Program::elaborate_supertraits`.
+    pub(crate) fn elaborated_where_clauses(
+        &self,
+        elaborated: &BTreeMap<ItemId, Arc<Vec<QuantifiedWhereClause>>>,
+    ) -> Vec<QuantifiedWhereClause> {
+        elaborated[&self.trait_id]
+            .iter()
+            .map(|wc| Subst::apply(&self.parameters, wc))
+            .collect()
+    }
+}
+
+/// How many steps `Environment::implied_domain_goals` takes away from the
+/// facts of the environment. With `trait Foo<T> where Self: Foo<Vec<T>>`,
+/// assuming `T: Foo<U>` implies ever larger facts, so we have to stop
+/// somewhere.
+const MAX_IMPLIED_DEPTH: usize = 10;
+
+impl Environment {
+    /// The facts of this environment, along with everything they imply:
+    /// the supertraits of the traits it assumes, the bounds on their
+    /// associated types, the where clauses of the structs it assumes are
+    /// well-formed, and so on. In the environment of `if (T: Ord) { ... }`,
+    /// with `trait Ord where Self: Eq`, this is:
+    ///
+    /// ```notrust
+    /// Implemented(T: Eq)
+    /// Implemented(T: Ord)
+    /// FromEnv(T as Eq)
+    /// FromEnv(T as Ord)
+    /// ```
+    ///
+    /// This is what the solver may find out about the environment through
+    /// the clauses of `program`; it is meant for debugging, since the
+    /// solver itself only looks at the clauses it needs.
+    pub fn implied_domain_goals(&self, program: &Program) -> Vec<DomainGoal> {
+        self.implied_domain_goals_in(&program.environment())
+    }
+
+    /// As `implied_domain_goals`, with the clauses of `env`.
+    ///
+    /// Only the clauses of the environment which are plain facts, not
+    /// under binders, are followed; and only through program clauses with
+    /// a single condition about the environment (`FromEnv` or
+    /// `Normalize`), which are those that imply bounds. Free variables
+    /// of the facts are taken to be rigid, so that the environment of a
+    /// goal under binders can be elaborated without opening them.
+    pub(crate) fn implied_domain_goals_in(&self, env: &ProgramEnvironment) -> Vec<DomainGoal> {
+        let rules: Vec<_> = env.program_clauses
+            .iter()
+            .filter_map(|clause| {
+                let (num_binders, implication) = match clause {
+                    ProgramClause::Implies(implication) => (0, implication),
+                    ProgramClause::ForAll(clause) => (clause.binders.len(), &clause.value),
+                };
+                match &implication.conditions[..] {
+                    [Goal::Leaf(LeafGoal::DomainGoal(condition @ DomainGoal::FromEnv(_)))]
+                    | [Goal::Leaf(LeafGoal::DomainGoal(condition @ DomainGoal::Normalize(_)))] => {
+                        Some((num_binders, condition, &implication.consequence))
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+
+        let mut implied: BTreeSet<DomainGoal> = BTreeSet::new();
+        let mut frontier: Vec<DomainGoal> = self.clauses()
+            .filter_map(|clause| match clause {
+                ProgramClause::Implies(implication) if implication.conditions.is_empty() => {
+                    Some(implication.consequence.clone())
+                }
+                ProgramClause::Implies(_) | ProgramClause::ForAll(_) => None,
+            })
+            .filter(|fact| implied.insert(fact.clone()))
+            .collect();
+
+        for _ in 0..MAX_IMPLIED_DEPTH {
+            let mut next = vec![];
+            for fact in &frontier {
+                for &(num_binders, condition, consequence) in &rules {
+                    let parameters = match match_fact(num_binders, condition, fact) {
+                        Some(parameters) => parameters,
+                        None => continue,
+                    };
+                    let consequence = Subst::apply(&parameters, consequence);
+                    if implied.insert(consequence.clone()) {
+                        next.push(consequence);
+                    }
+                }
+            }
+            if next.is_empty() {
+                break;
+            }
+            frontier = next;
+        }
+
+        debug!("implied_domain_goals: {:?}", implied);
+        implied.into_iter().collect()
+    }
+}
+
+/// Matches `pattern`, whose free variables are the `num_binders` binders of
+/// a program clause, against `fact`. Returns the values of the binders, if
+/// they all appear in the pattern.
+fn match_fact(
+    num_binders: usize,
+    pattern: &DomainGoal,
+    fact: &DomainGoal,
+) -> Option<Vec<Parameter>> {
+    if !pattern.could_match(fact) {
+        return None;
+    }
+
+    let mut matcher = Matcher {
+        binders: 0,
+        parameters: vec![None; num_binders],
+    };
+    Zip::zip_with(&mut matcher, pattern, fact).ok()?;
+    matcher.parameters.into_iter().collect()
+}
+
+/// Binds the free variables of a pattern to the parts of a fact found at
+/// the same spots, which must otherwise be equal. Unlike unification, a
+/// variable matches a projection type like any other type.
+struct Matcher {
+    /// The number of binders we are under.
+    binders: usize,
+
+    /// The value of each free variable of the pattern, once it is found.
+    parameters: Vec<Option<Parameter>>,
+}
+
+impl Matcher {
+    /// If `depth` is one of the free variables of the pattern, binds it to
+    /// `value` -- or checks that it is bound to it already -- and returns
+    /// true.
+    fn bind<T: Fold<Result = T>>(
+        &mut self,
+        depth: usize,
+        value: &T,
+        kind: fn(T) -> Parameter,
+    ) -> Fallible<bool> {
+        if depth < self.binders {
+            return Ok(false);
+        }
+        let index = depth - self.binders;
+        let value = kind(value.down_shift(self.binders)?);
+        if let Some(bound) = &self.parameters[index] {
+            return if *bound == value { Ok(true) } else { Err(NoSolution) };
+        }
+        self.parameters[index] = Some(value);
+        Ok(true)
+    }
+}
+
+impl Zipper for Matcher {
+    fn zip_tys(&mut self, pattern: &Ty, fact: &Ty) -> Fallible<()> {
+        if let Ty::Var(depth) = *pattern {
+            if self.bind(depth, fact, ParameterKind::Ty)? {
+                return Ok(());
+            }
+        }
+
+        match (pattern, fact) {
+            (Ty::Var(a), Ty::Var(b)) if a == b => Ok(()),
+            (Ty::Apply(a), Ty::Apply(b)) => Zip::zip_with(self, a, b),
+            (Ty::Projection(a), Ty::Projection(b)) => Zip::zip_with(self, a, b),
+            (Ty::UnselectedProjection(a), Ty::UnselectedProjection(b)) => {
+                Zip::zip_with(self, a, b)
+            }
+            (Ty::ForAll(a), Ty::ForAll(b)) if a.num_binders == b.num_binders => {
+                self.binders += a.num_binders;
+                let result = Zip::zip_with(self, &a.ty, &b.ty);
+                self.binders -= a.num_binders;
+                result
+            }
+            (Ty::Var(_), _)
+            | (Ty::Apply(_), _)
+            | (Ty::Projection(_), _)
+            | (Ty::UnselectedProjection(_), _)
+            | (Ty::ForAll(_), _) => Err(NoSolution),
+        }
+    }
+
+    fn zip_lifetimes(&mut self, pattern: &Lifetime, fact: &Lifetime) -> Fallible<()> {
+        if let Lifetime::Var(depth) = *pattern {
+            if self.bind(depth, fact, ParameterKind::Lifetime)? {
+                return Ok(());
+            }
+        }
+
+        if pattern == fact {
+            Ok(())
+        } else {
+            Err(NoSolution)
+        }
+    }
+
+    fn zip_consts(&mut self, pattern: &Const, fact: &Const) -> Fallible<()> {
+        if let Const::Var(depth) = *pattern {
+            if self.bind(depth, fact, ParameterKind::Const)? {
+                return Ok(());
+            }
+        }
+
+        match (pattern, fact) {
+            (Const::Unevaluated(a), Const::Unevaluated(b)) => Zip::zip_with(self, a, b),
+            (Const::Var(_), _) | (Const::ForAll(_), _) | (Const::Value(_), _) => {
+                if pattern == fact {
+                    Ok(())
+                } else {
+                    Err(NoSolution)
+                }
+            }
+            (Const::Unevaluated(_), _) => Err(NoSolution),
+        }
+    }
+
+    fn zip_binders<T>(&mut self, pattern: &Binders<T>, fact: &Binders<T>) -> Fallible<()>
+    where
+        T: Zip + Fold<Result = T>,
+    {
+        if pattern.binders != fact.binders {
+            return Err(NoSolution);
+        }
+        self.binders += pattern.binders.len();
+        let result = Zip::zip_with(self, &pattern.value, &fact.value);
+        self.binders -= pattern.binders.len();
+        result
+    }
+}
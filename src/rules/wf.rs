@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use ir::*;
@@ -12,37 +13,111 @@ mod test;
 
 struct WfSolver {
     env: Arc<ProgramEnvironment>,
+    type_kinds: Shared<BTreeMap<ItemId, TypeKind>>,
+    struct_data: Shared<BTreeMap<ItemId, StructDatum>>,
+    elaborated_where_clauses: Shared<BTreeMap<ItemId, Arc<Vec<QuantifiedWhereClause>>>>,
     solver_choice: SolverChoice,
 }
 
+/// The reason why a goal had to be proven in order for some item to be
+/// well-formed. This is only used to explain WF errors.
+#[derive(Copy, Clone, Debug)]
+enum ObligationCause {
+    /// The input types of the fields and where clauses of a struct
+    /// must be well-formed.
+    StructDecl(ItemId),
+
+    /// A where clause declared on a struct must hold for the struct
+    /// type to be well-formed.
+    StructWhereClause(ItemId),
+
+    /// The input types of the where clauses of an impl must be
+    /// well-formed.
+    ImplWhereClauses,
+
+    /// The value of an associated type must be well-formed and meet
+    /// the bounds declared in the trait.
+    AssociatedTyValue(ItemId),
+
+    /// A where clause of a trait (or of one of its supertraits) must
+    /// hold for an impl of that trait.
+    TraitWhereClause(ItemId),
+
+    /// The trait reference of an impl must hold.
+    ImplHeader,
+
+    /// The projection types appearing in the header of an impl must be
+    /// well-formed, i.e. their trait must be implemented.
+    ImplHeaderProjection,
+}
+
+impl ObligationCause {
+    fn describe(&self, solver: &WfSolver) -> String {
+        match *self {
+            ObligationCause::StructDecl(id) => {
+                format!("the declaration of `{}`", solver.type_kinds[&id].name)
+            }
+            ObligationCause::StructWhereClause(id) => {
+                format!("the where clauses on struct `{}`", solver.type_kinds[&id].name)
+            }
+            ObligationCause::ImplWhereClauses => "the where clauses on the impl".to_string(),
+            ObligationCause::AssociatedTyValue(id) => format!(
+                "the value of associated type `{}`",
+                solver.env.associated_ty_data[&id].name
+            ),
+            ObligationCause::TraitWhereClause(id) => {
+                format!("the where clauses on trait `{}`", solver.type_kinds[&id].name)
+            }
+            ObligationCause::ImplHeader => "the impl header".to_string(),
+            ObligationCause::ImplHeaderProjection => {
+                "the projections in the impl header".to_string()
+            }
+        }
+    }
+}
+
 impl Program {
     pub fn verify_well_formedness(&self, solver_choice: SolverChoice) -> Result<()> {
         tls::set_current_program(&Arc::new(self.clone()), || self.solve_wf_requirements(solver_choice))
     }
 
     fn solve_wf_requirements(&self, solver_choice: SolverChoice) -> Result<()> {
-        let solver = WfSolver {
-            env: Arc::new(self.environment()),
-            solver_choice,
-        };
+        let solver = WfSolver::new(self, Arc::new(self.environment()), solver_choice);
 
         for (id, struct_datum) in &self.struct_data {
-            if !solver.verify_struct_decl(struct_datum) {
+            solver.verify_struct_decl(*id, struct_datum).chain_err(|| {
                 let name = self.type_kinds.get(id).unwrap().name;
-                return Err(Error::from_kind(ErrorKind::IllFormedTypeDecl(name)));
-            }
+                ErrorKind::IllFormedTypeDecl(name)
+            })?;
         }
 
         for impl_datum in self.impl_data.values() {
-            if !solver.verify_trait_impl(impl_datum) {
-                let trait_ref = impl_datum.binders.value.trait_ref.trait_ref();
-                let name = self.type_kinds.get(&trait_ref.trait_id).unwrap().name;
-                return Err(Error::from_kind(ErrorKind::IllFormedTraitImpl(name)));
-            }
+            self.verify_impl_with(&solver, impl_datum)?;
         }
 
         Ok(())
     }
+
+    /// Checks the well-formedness of the impl `impl_datum` alone, as
+    /// `verify_well_formedness` would, in the environment `env` of
+    /// this program.
+    pub(crate) fn verify_impl_well_formedness(
+        &self,
+        env: &Arc<ProgramEnvironment>,
+        solver_choice: SolverChoice,
+        impl_datum: &ImplDatum,
+    ) -> Result<()> {
+        let solver = WfSolver::new(self, env.clone(), solver_choice);
+        self.verify_impl_with(&solver, impl_datum)
+    }
+
+    fn verify_impl_with(&self, solver: &WfSolver, impl_datum: &ImplDatum) -> Result<()> {
+        solver.verify_trait_impl(impl_datum).chain_err(|| {
+            let trait_ref = impl_datum.binders.value.trait_ref.trait_ref();
+            let name = self.type_kinds.get(&trait_ref.trait_id).unwrap().name;
+            ErrorKind::IllFormedTraitImpl(name)
+        })
+    }
 }
 
 /// A trait for retrieving all types appearing in some Chalk construction.
@@ -118,28 +193,48 @@ impl FoldInputTypes for WhereClause {
     }
 }
 
-impl<T: FoldInputTypes> FoldInputTypes for Binders<T> {
-    fn fold(&self, accumulator: &mut Vec<Ty>) {
-        self.value.fold(accumulator);
+/// The goals requiring the input types of `where_clauses` to be well-formed. The input
+/// types of a higher-ranked where clause which refer to its bound variables must be
+/// well-formed for all values of these variables, e.g. `forall<'a> T: Foo<Ref<'a, T>>`
+/// requires `forall<'a> { WellFormed(Ref<'a, T>) }`. The other types are shifted out of
+/// the binder.
+fn where_clause_input_type_goals(where_clauses: &[QuantifiedWhereClause]) -> Vec<Goal> {
+    let mut goals = Vec::new();
+    for wc in where_clauses {
+        let mut input_types = Vec::new();
+        wc.value.fold(&mut input_types);
+        goals.extend(input_types.into_iter().map(|ty| match ty.down_shift(wc.binders.len()) {
+            Ok(ty) => DomainGoal::WellFormed(WellFormed::Ty(ty)).cast(),
+            Err(_) => wc.map_ref(|_| DomainGoal::WellFormed(WellFormed::Ty(ty.clone()))).cast(),
+        }));
     }
+    goals
 }
 
 impl WfSolver {
-    fn verify_struct_decl(&self, struct_datum: &StructDatum) -> bool {
-        // We retrieve all the input types of the struct fields.
+    fn new(program: &Program, env: Arc<ProgramEnvironment>, solver_choice: SolverChoice) -> Self {
+        WfSolver {
+            env,
+            type_kinds: program.type_kinds.clone(),
+            struct_data: program.struct_data.clone(),
+            elaborated_where_clauses: program.elaborated_where_clauses.clone(),
+            solver_choice,
+        }
+    }
+
+    fn verify_struct_decl(&self, id: ItemId, struct_datum: &StructDatum) -> Result<()> {
+        // We retrieve all the input types of the struct fields and where clauses.
         let mut input_types = Vec::new();
         struct_datum.binders.value.fields.fold(&mut input_types);
-        struct_datum.binders.value.where_clauses.fold(&mut input_types);
+        let where_clause_goals =
+            where_clause_input_type_goals(&struct_datum.binders.value.where_clauses);
 
-        if input_types.is_empty() {
-            return true;
-        }
-
-        let goals = input_types.into_iter()
-                               .map(|ty| DomainGoal::WellFormed(WellFormed::Ty(ty)))
-                               .casted();
-        let goal = goals.fold1(|goal, leaf| Goal::And(Box::new(goal), Box::new(leaf)))
-                        .expect("at least one goal");
+        let obligations =
+            input_types.into_iter()
+                       .map(|ty| DomainGoal::WellFormed(WellFormed::Ty(ty)).cast())
+                       .chain(where_clause_goals)
+                       .map(|goal| (goal, ObligationCause::StructDecl(id)))
+                       .collect();
 
         let hypotheses =
             struct_datum.binders
@@ -153,19 +248,13 @@ impl WfSolver {
 
         // We ask that the above input types are well-formed provided that all the where-clauses
         // on the struct definition hold.
-        let goal = Goal::Implies(hypotheses, Box::new(goal))
-            .quantify(QuantifierKind::ForAll, struct_datum.binders.binders.clone());
-
-        match self.solver_choice.solve_root_goal(&self.env, &goal.into_closed_goal()).unwrap() {
-            Some(sol) => sol.is_unique(),
-            None => false,
-        }
+        self.solve_obligations(&struct_datum.binders.binders, hypotheses, obligations)
     }
 
-    fn verify_trait_impl(&self, impl_datum: &ImplDatum) -> bool {
+    fn verify_trait_impl(&self, impl_datum: &ImplDatum) -> Result<()> {
         let trait_ref = match impl_datum.binders.value.trait_ref {
             PolarizedTraitRef::Positive(ref trait_ref) => trait_ref,
-            _ => return true
+            _ => return Ok(())
         };
 
         // We retrieve all the input types of the where clauses appearing on the trait impl,
@@ -175,9 +264,11 @@ impl WfSolver {
         // ```
         // we would retrieve `HashSet<K>`, `Box<T>`, `Vec<Box<T>>`, `(HashSet<K>, Vec<Box<T>>)`.
         // We will have to prove that these types are well-formed (e.g. an additional `K: Hash`
-        // bound would be needed here).
-        let mut input_types = Vec::new();
-        impl_datum.binders.value.where_clauses.fold(&mut input_types);
+        // bound would be needed here). The types under the binders of a higher-ranked where
+        // clause must be well-formed for all values of the bound variables, see
+        // `where_clause_input_type_goals`.
+        let where_clause_goals =
+            where_clause_input_type_goals(&impl_datum.binders.value.where_clauses);
 
         // We retrieve all the input types of the type on which we implement the trait: we will
         // *assume* that these types are well-formed, e.g. we will be able to derive that
@@ -191,8 +282,17 @@ impl WfSolver {
         //     // Inside here, we can rely on the fact that `K: Hash` holds
         // }
         // ```
+        //
+        // Projection types are the exception: `impl Foo for <T as Iterator>::Item` is only
+        // meaningful if `T: Iterator` holds, so rather than assuming that, we require the
+        // projection types to be well-formed, which is the case if their trait is implemented.
         let mut header_input_types = Vec::new();
         trait_ref.fold(&mut header_input_types);
+        let (header_projections, header_input_types): (Vec<_>, Vec<_>) =
+            header_input_types.into_iter().partition(|ty| match ty {
+                Ty::Projection(..) => true,
+                _ => false,
+            });
 
         // Associated type values are special because they can be parametric (independently of
         // the impl), so we issue a special goal which is quantified using the binders of the
@@ -210,30 +310,27 @@ impl WfSolver {
         let compute_assoc_ty_goal = |assoc_ty: &AssociatedTyValue| {
             let assoc_ty_datum = &self.env.associated_ty_data[&assoc_ty.associated_ty_id];
             let bounds = &assoc_ty_datum.bounds;
+            let value = assoc_ty.in_impl();
 
             let mut input_types = Vec::new();
-            assoc_ty.value.value.ty.fold(&mut input_types);
+            value.value.ty.fold(&mut input_types);
 
             let wf_goals =
                 input_types.into_iter()
                            .map(|ty| DomainGoal::WellFormed(WellFormed::Ty(ty)))
                            .casted();
             
-            let trait_ref = trait_ref.up_shift(assoc_ty.value.binders.len());
-
-            let all_parameters: Vec<_> =
-                assoc_ty.value.binders.iter()
-                                      .zip(0..)
-                                      .map(|p| p.to_parameter())
-                                      .chain(trait_ref.parameters.iter().cloned())
-                                      .collect();
+            // The parameters of the trait are those of the impl, which the value sees
+            // from under its own binders.
+            let trait_ref = trait_ref.up_shift(value.binders.len());
+            let all_parameters = assoc_ty.projection_parameters(&trait_ref);
 
             // Add bounds from the trait. Because they are defined on the trait,
             // their parameters must be substituted with those of the impl.
             let bound_goals =
                 bounds.iter()
                       .map(|b| Subst::apply(&all_parameters, b))
-                      .flat_map(|b| b.into_where_clauses(assoc_ty.value.value.ty.clone()))
+                      .flat_map(|b| b.into_where_clauses(value.value.ty.clone()))
                       .map(|wc| wc.map(|bound| bound.into_well_formed_goal()))
                       .casted();
             
@@ -258,7 +355,7 @@ impl WfSolver {
                 Box::new(goal)
             );
 
-            Some(goal.quantify(QuantifierKind::ForAll, assoc_ty.value.binders.clone()))
+            Some(goal.quantify(QuantifierKind::ForAll, value.binders.clone()))
         };
 
         let assoc_ty_goals =
@@ -266,21 +363,42 @@ impl WfSolver {
                       .value
                       .associated_ty_values
                       .iter()
-                      .filter_map(compute_assoc_ty_goal);
+                      .filter_map(|assoc_ty| {
+                          let goal = compute_assoc_ty_goal(assoc_ty)?;
+                          Some((goal, ObligationCause::AssociatedTyValue(assoc_ty.associated_ty_id)))
+                      });
+
+        // Proving `WellFormed(trait_ref)` would make the solver walk the whole supertrait
+        // hierarchy again for each impl. Instead, we substitute the parameters of the impl
+        // into the where clauses elaborated once per trait when the program was lowered,
+        // and require each of them to hold. Higher-ranked where clauses were not elaborated
+        // any further, so for those we still ask for the full `WellFormed` goal.
+        let header_goal: Goal = trait_ref.clone().cast();
+        let trait_ref_goals =
+            trait_ref.elaborated_where_clauses(&self.elaborated_where_clauses)
+                     .into_iter()
+                     .map(|wc| {
+                         let goal: Goal = if wc.binders.is_empty() {
+                             wc.map(|bound| DomainGoal::Holds(bound)).cast()
+                         } else {
+                             wc.map(|bound| bound.into_well_formed_goal()).cast()
+                         };
+                         (goal, ObligationCause::TraitWhereClause(trait_ref.trait_id))
+                     })
+                     .chain(Some((header_goal, ObligationCause::ImplHeader)));
 
         // Things to prove well-formed: input types of the where-clauses, projection types
         // appearing in the header, associated type values, and of course the trait ref.
-        let trait_ref_wf = DomainGoal::WellFormed(
-            WellFormed::Trait(trait_ref.clone())
-        );
-        let goals =
-            input_types.into_iter()
-                       .map(|ty| DomainGoal::WellFormed(WellFormed::Ty(ty)).cast())
-                       .chain(assoc_ty_goals)
-                       .chain(Some(trait_ref_wf).cast());
-
-        let goal = goals.fold1(|goal, leaf| Goal::And(Box::new(goal), Box::new(leaf)))
-                        .expect("at least one goal");
+        let obligations =
+            where_clause_goals.into_iter()
+                              .map(|goal| (goal, ObligationCause::ImplWhereClauses))
+                              .chain(header_projections.into_iter().map(|ty| {
+                                  (DomainGoal::WellFormed(WellFormed::Ty(ty)).cast(),
+                                   ObligationCause::ImplHeaderProjection)
+                              }))
+                              .chain(assoc_ty_goals)
+                              .chain(trait_ref_goals)
+                              .collect();
 
         // Assumptions: types appearing in the header which are not projection types are
         // assumed to be well-formed, and where clauses declared on the impl are assumed
@@ -300,10 +418,94 @@ impl WfSolver {
                       )
                       .collect();
 
-        let goal = Goal::Implies(hypotheses, Box::new(goal))
-            .quantify(QuantifierKind::ForAll, impl_datum.binders.binders.clone());
+        self.solve_obligations(&impl_datum.binders.binders, hypotheses, obligations)
+    }
+
+    /// Checks that all the `obligations` hold for all values of `binders`, assuming
+    /// `hypotheses`. They are first solved together, which is what we want in the common
+    /// case where everything is fine. If that fails, we solve them one by one so that we
+    /// can report the first one that does not hold.
+    fn solve_obligations(
+        &self,
+        binders: &[ParameterKind<()>],
+        hypotheses: Vec<ProgramClause>,
+        obligations: Vec<(Goal, ObligationCause)>,
+    ) -> Result<()> {
+        let goal = match obligations.iter()
+                                    .map(|(goal, _)| goal.clone())
+                                    .fold1(|goal, leaf| Goal::And(Box::new(goal), Box::new(leaf)))
+        {
+            Some(goal) => goal,
+            None => return Ok(()),
+        };
+
+        if self.holds(binders, &hypotheses, goal) {
+            return Ok(());
+        }
+
+        for (goal, cause) in obligations {
+            if !self.holds(binders, &hypotheses, goal.clone()) {
+                let (goal, cause) = self.refine_failure(binders, &hypotheses, goal, cause);
+                let implied = Environment::add_clauses(&Environment::new(), hypotheses.clone())
+                    .implied_domain_goals_in(&self.env);
+                bail!(ErrorKind::UnprovableWfGoal(
+                    format!("{:?}", goal),
+                    cause.describe(self),
+                    format!("{:?}", hypotheses),
+                    format!("{:?}", implied),
+                ));
+            }
+        }
+
+        bail!(ErrorKind::JointlyUnprovableWfGoals)
+    }
+
+    /// If `goal` is `WellFormed(Foo<..>)` for some struct `Foo`, the reason it does not
+    /// hold is that one of the where clauses on `Foo` does not, so we report that where
+    /// clause instead. Otherwise `goal` is already as precise as we can get.
+    fn refine_failure(
+        &self,
+        binders: &[ParameterKind<()>],
+        hypotheses: &[ProgramClause],
+        goal: Goal,
+        cause: ObligationCause,
+    ) -> (Goal, ObligationCause) {
+        let struct_ty = match goal {
+            Goal::Leaf(LeafGoal::DomainGoal(DomainGoal::WellFormed(WellFormed::Ty(
+                Ty::Apply(ref app),
+            )))) => match app.name {
+                TypeName::ItemId(id) if self.struct_data.contains_key(&id) => {
+                    Some((id, app.parameters.clone()))
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let (id, parameters) = match struct_ty {
+            Some(struct_ty) => struct_ty,
+            None => return (goal, cause),
+        };
+
+        let where_clauses = Subst::apply(
+            &parameters,
+            &self.struct_data[&id].binders.value.where_clauses,
+        );
+        for wc in where_clauses {
+            let wc_goal: Goal = wc.cast();
+            if !self.holds(binders, hypotheses, wc_goal.clone()) {
+                return (wc_goal, ObligationCause::StructWhereClause(id));
+            }
+        }
+
+        (goal, cause)
+    }
+
+    fn holds(&self, binders: &[ParameterKind<()>], hypotheses: &[ProgramClause], goal: Goal) -> bool {
+        let goal = Goal::Implies(hypotheses.to_vec(), Box::new(goal))
+            .quantify(QuantifierKind::ForAll, binders.to_vec());
 
-        debug!("WF trait goal: {:?}", goal);
+        debug!("WF goal: {:?}", goal);
 
         match self.solver_choice.solve_root_goal(&self.env, &goal.into_closed_goal()).unwrap() {
             Some(sol) => sol.is_unique(),
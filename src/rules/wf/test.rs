@@ -205,6 +205,95 @@ fn wf_requiremements_for_projection() {
     }
 }
 
+#[test]
+fn ill_formed_projection_in_field() {
+    lowering_error! {
+        program {
+            trait Iterator {
+                type Item;
+            }
+
+            struct u32 { }
+
+            struct Foo {
+                field: <u32 as Iterator>::Item
+            }
+        } error_msg {
+            "type declaration \"Foo\" does not meet well-formedness requirements"
+        }
+    }
+
+    lowering_success! {
+        program {
+            trait Iterator {
+                type Item;
+            }
+
+            struct u32 { }
+
+            struct Foo<T> where T: Iterator {
+                field: <T as Iterator>::Item
+            }
+        }
+    }
+}
+
+#[test]
+fn ill_formed_projection_in_header() {
+    lowering_error! {
+        program {
+            trait Iterator {
+                type Item;
+            }
+
+            trait Foo { }
+
+            // `X: Iterator` is not implied by the impl header.
+            impl<X> Foo for <X as Iterator>::Item { }
+        } error_msg {
+            "trait impl for \"Foo\" does not meet well-formedness requirements"
+        }
+    }
+
+    lowering_error! {
+        program {
+            trait Iterator {
+                type Item;
+            }
+
+            trait Foo<T> { }
+
+            struct u32 { }
+            struct Vec<T> { }
+
+            impl Foo<Vec<<u32 as Iterator>::Item>> for u32 { }
+        } error_msg {
+            "trait impl for \"Foo\" does not meet well-formedness requirements"
+        }
+    }
+
+    lowering_success! {
+        program {
+            trait Iterator {
+                type Item;
+            }
+
+            trait Foo<T> { }
+            trait Bar<T> { }
+
+            struct u32 { }
+            struct Vec<T> { }
+
+            impl Iterator for u32 {
+                type Item = u32;
+            }
+
+            impl<X> Foo<Vec<<X as Iterator>::Item>> for X where X: Iterator { }
+            impl Bar<Vec<<u32 as Iterator>::Item>> for u32 { }
+        }
+    }
+}
+
 #[test]
 fn ill_formed_type_in_header() {
     lowering_error! {
@@ -563,3 +652,166 @@ fn higher_ranked_inline_bound_on_gat() {
         }
     }
 }
+
+#[test]
+fn higher_ranked_where_clause_input_types() {
+    // The types under the binder of a higher-ranked where clause must be well-formed for all
+    // values of the bound variables, so `forall<U> T: Foo<U>` is needed here...
+    lowering_success! {
+        program {
+            trait Foo<T> { }
+            trait Bar<T> { }
+            struct Wrapper<T, U> where T: Foo<U> { }
+            struct i32 { }
+
+            impl<T> Bar<T> for i32 where forall<U> Wrapper<T, U>: Bar<T>, forall<U> T: Foo<U> { }
+        }
+    }
+
+    // ... and `T: Foo<V>`, which only holds for one of them, is not enough.
+    lowering_error! {
+        program {
+            trait Foo<T> { }
+            trait Bar<T> { }
+            struct Wrapper<T, U> where T: Foo<U> { }
+            struct i32 { }
+
+            impl<T, V> Bar<T> for i32 where forall<U> Wrapper<T, U>: Bar<T>, T: Foo<V> { }
+        } error_msg {
+            "trait impl for \"Bar\" does not meet well-formedness requirements"
+        }
+    }
+
+    lowering_success! {
+        program {
+            trait Foo<T> { }
+            trait Bar<T> { }
+            struct Wrapper<T, U> where T: Foo<U> { }
+
+            struct MyType<T> where forall<U> Wrapper<T, U>: Bar<T>, forall<U> T: Foo<U> { }
+        }
+    }
+
+    lowering_error! {
+        program {
+            trait Foo<T> { }
+            trait Bar<T> { }
+            struct Wrapper<T, U> where T: Foo<U> { }
+
+            struct MyType<T, V> where forall<U> Wrapper<T, U>: Bar<T>, T: Foo<V> { }
+        } error_msg {
+            "type declaration \"MyType\" does not meet well-formedness requirements"
+        }
+    }
+}
+
+#[test]
+fn ill_formed_reports_failing_goal() {
+    lowering_error! {
+        program {
+            trait Hash { }
+            struct Set<K> where K: Hash { }
+
+            struct MyType<K> {
+                value: Set<K>
+            }
+        } error_msg {
+            "type declaration \"MyType\" does not meet well-formedness requirements"
+        } caused_by {
+            "`Implemented(?0: Hash)` does not hold (required by the where clauses on struct `Set`), \
+             assuming []; environment contained: []"
+        }
+    }
+
+    lowering_error! {
+        program {
+            trait Copy { }
+            trait Foo where Self: Copy { }
+
+            struct String { }
+
+            impl Foo for String { }
+        } error_msg {
+            "trait impl for \"Foo\" does not meet well-formedness requirements"
+        } caused_by {
+            "`Implemented(String: Copy)` does not hold (required by the where clauses on trait `Foo`), \
+             assuming [FromEnv(String)]; environment contained: [FromEnv(String)]"
+        }
+    }
+
+    lowering_error! {
+        program {
+            trait Copy { }
+            trait Foo<T> { }
+            trait Bar { }
+
+            struct String { }
+            struct Wrapper<T> where T: Copy { }
+
+            impl<T> Bar for T where T: Foo<Wrapper<String>> { }
+        } error_msg {
+            "trait impl for \"Bar\" does not meet well-formedness requirements"
+        } caused_by {
+            "`Implemented(String: Copy)` does not hold (required by the where clauses on struct `Wrapper`), \
+             assuming [FromEnv(?0 as Foo<Wrapper<String>>)]; \
+             environment contained: [Implemented(?0: Foo<Wrapper<String>>), \
+             FromEnv(?0 as Foo<Wrapper<String>>)]"
+        }
+    }
+}
+
+/// The number of impls in the programs of the elaboration tests.
+const NUM_IMPLS: usize = 2000;
+
+/// Lowers `program_text`, returning the program and the number of trait
+/// hierarchies that were walked in doing so.
+fn lower_counting_elaborations(program_text: &str) -> (::ir::Program, usize) {
+    use rules::elaborate::ELABORATIONS;
+    use solve::SolverChoice;
+
+    ELABORATIONS.with(|c| c.set(0));
+    let program = parse_and_lower_program(program_text, SolverChoice::default()).unwrap();
+    (program, ELABORATIONS.with(|c| c.get()))
+}
+
+#[test]
+fn supertraits_elaborated_once_per_trait() {
+    // Both traits are marker traits, so that we do not spend our time checking
+    // the impls pairwise for overlap.
+    let mut program_text = String::from("
+        #[marker] trait Super { }
+        #[marker] trait Sub where Self: Super { }
+    ");
+    for i in 0..NUM_IMPLS {
+        program_text.push_str(&format!("
+            struct S{0} {{ }}
+            impl Super for S{0} {{ }}
+            impl Sub for S{0} {{ }}
+        ", i));
+    }
+
+    let (program, elaborations) = lower_counting_elaborations(&program_text);
+    assert_eq!(program.impl_data.len(), 2 * NUM_IMPLS);
+
+    // One elaboration for `Super` and one for `Sub`, no matter how many impls there are.
+    assert_eq!(elaborations, 2);
+}
+
+#[test]
+fn one_trait_elaborated_once_for_all_its_impls() {
+    // Lowering one impl of the trait, or `NUM_IMPLS` of them, walks its
+    // hierarchy exactly once.
+    for &num_impls in &[1, NUM_IMPLS] {
+        let mut program_text = String::from("#[marker] trait Foo { }");
+        for i in 0..num_impls {
+            program_text.push_str(&format!("
+                struct S{0} {{ }}
+                impl Foo for S{0} {{ }}
+            ", i));
+        }
+
+        let (program, elaborations) = lower_counting_elaborations(&program_text);
+        assert_eq!(program.impl_data.len(), num_impls);
+        assert_eq!(elaborations, 1, "with {} impls", num_impls);
+    }
+}
@@ -1,11 +1,33 @@
-use std::fmt;
 use std::sync::Arc;
+use fallible::*;
+use fold::shift::Shift;
 use ir::*;
+use zip::depth::exceeds_depth;
+use self::infer::InferenceTable;
+use self::infer::ucanonicalize::UCanonicalized;
 
-crate mod infer;
-crate mod slg;
+pub use chalk_engine::CannotProveReason;
+pub use self::ambiguity::{AmbiguitySources, NamedAmbiguitySources};
+pub use chalk_engine::forest::Scheduling;
+pub use self::environment::EnvironmentBuilder;
+pub use self::external::{ConstEval, ConstEvaluation, ExternalAnswers, ExternalSolver, SolverHooks};
+pub use self::hint::{failure_hint, FailureHint, NamedFailureHint};
+pub use ir::ClauseSource;
+pub use self::parts::{Certainty, GoalVariable, GuidanceKind, NamedSolution, SolutionData};
+pub use self::rename::Legend;
+pub use self::truncate::TruncationMeasure;
+
+mod ambiguity;
+mod environment;
+mod external;
+mod hint;
+pub mod infer;
+mod parts;
+mod reflection;
+mod rename;
+pub(crate) mod slg;
 mod test;
-mod truncate;
+pub mod truncate;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 /// A (possible) solution for a proposed goal. Usually packaged in a `Result`,
@@ -13,14 +35,22 @@ mod truncate;
 pub enum Solution {
     /// The goal indeed holds, and there is a unique value for all existential
     /// variables. In this case, we also record a set of lifetime constraints
-    /// which must also hold for the goal to be valid.
-    Unique(Canonical<ConstrainedSubst>),
+    /// which must also hold for the goal to be valid. These constraints are
+    /// universally quantified over the names introduced by `forall` goals
+    /// nested in the goal, if they mention any.
+    Unique(Canonical<SolutionSubst>),
 
     /// The goal may be provable in multiple ways, but regardless we may have some guidance
     /// for type inference. In this case, we don't return any lifetime
     /// constraints, since we have not "committed" to any particular solution
     /// yet.
     Ambig(Guidance),
+
+    /// The goal could be neither proven nor disproven, for a reason
+    /// the caller may want to report: for example, because a negative
+    /// goal closed a cycle through a coinductive goal. Like `Ambig`,
+    /// this carries no lifetime constraints.
+    CannotProve(CannotProveReason),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -41,34 +71,186 @@ pub enum Guidance {
     Unknown,
 }
 
-impl Solution {
-    crate fn is_unique(&self) -> bool {
-        match *self {
-            Solution::Unique(..) => true,
-            _ => false,
+/// What is known about a goal after solving it: whether it holds, does
+/// not hold, or neither could be established. Unlike `Solution`, which
+/// lumps the goals that are known not to hold together with those the
+/// search gave up on (both are `None`), this tells them apart, for the
+/// callers that reason about goals *not* holding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SolveOutcome {
+    /// The goal holds, with a unique value for its existential
+    /// variables, provided the lifetime constraints do (see
+    /// `Solution::Unique`).
+    Provable(Canonical<SolutionSubst>),
+
+    /// The goal does not hold: the search found no answers, and was
+    /// exhaustive -- it neither floundered nor truncated anything
+    /// along the way. If the failure may come as a surprise, the hint
+    /// says why (see `failure_hint`).
+    Disproven(Option<FailureHint>),
+
+    /// The goal may or may not hold.
+    Unknown(UnknownReason),
+}
+
+/// Why the outcome of a goal is `SolveOutcome::Unknown`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UnknownReason {
+    /// The goal may hold in several ways (see `Solution::Ambig`).
+    Ambiguous(Guidance),
+
+    /// The search gave up on the goal (see `Solution::CannotProve`).
+    CannotProve(CannotProveReason),
+}
+
+impl SolveOutcome {
+    /// The outcome of a goal whose solution was `solution`.
+    fn from_solution(solution: Solution) -> Self {
+        match solution {
+            Solution::Unique(constrained) => SolveOutcome::Provable(constrained),
+            Solution::Ambig(guidance) => SolveOutcome::Unknown(UnknownReason::Ambiguous(guidance)),
+            Solution::CannotProve(reason) => {
+                SolveOutcome::Unknown(UnknownReason::CannotProve(reason))
+            }
         }
     }
-}
 
-impl fmt::Display for Solution {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    /// The solution of the goal, as returned by `solve_root_goal`.
+    pub fn into_solution(self) -> Option<Solution> {
         match self {
-            Solution::Unique(constrained) => write!(f, "Unique; {}", constrained,),
-            Solution::Ambig(Guidance::Definite(subst)) => {
-                write!(f, "Ambiguous; definite substitution {}", subst)
+            SolveOutcome::Provable(constrained) => Some(Solution::Unique(constrained)),
+            SolveOutcome::Disproven(_) => None,
+            SolveOutcome::Unknown(UnknownReason::Ambiguous(guidance)) => {
+                Some(Solution::Ambig(guidance))
             }
-            Solution::Ambig(Guidance::Suggested(subst)) => {
-                write!(f, "Ambiguous; suggested substitution {}", subst)
+            SolveOutcome::Unknown(UnknownReason::CannotProve(reason)) => {
+                Some(Solution::CannotProve(reason))
             }
-            Solution::Ambig(Guidance::Unknown) => write!(f, "Ambiguous; no inference guidance"),
         }
     }
 }
 
+impl Solution {
+    pub(crate) fn is_unique(&self) -> bool {
+        match *self {
+            Solution::Unique(..) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The result of `SolverChoice::solve_in_table`, when the goal did not
+/// fail outright.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum SolveResultApplied {
+    /// The solution was unique, and the variables of the goal have been
+    /// bound to the values it gives them; the goal holds if the lifetime
+    /// `constraints` do. As with `Solution::Unique`, they are under
+    /// binders for the names of `forall` goals in the goal.
+    /// `constrained_vars` are the variables of the goal which the
+    /// solution constrained (see `InferenceTable::constrained_vars`).
+    Unique {
+        constraints: Binders<Vec<RegionConstraint>>,
+        constrained_vars: Vec<Parameter>,
+    },
+
+    /// The solution, which was not applied.
+    Ambiguous(Solution),
+}
+
+/// One of the answers enumerated by `SolverChoice::answers_in_table`.
+#[derive(Clone)]
+pub(crate) struct AppliedAnswer {
+    /// A copy of the table, in which the variables of the goal have
+    /// been bound to the values this answer gives them.
+    pub(crate) table: InferenceTable,
+
+    /// The lifetime constraints under which the answer holds.
+    pub(crate) constraints: Vec<RegionConstraint>,
+
+    /// True if the answer could be neither proven nor disproven, or
+    /// could not be applied to the table as it is.
+    pub(crate) ambiguous: bool,
+}
+
 #[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
 pub enum SolverChoice {
-    /// Run the SLG solver, producing a Solution.
-    SLG { max_size: usize },
+    /// Run the SLG solver, producing a Solution. Goals and answers
+    /// whose types are larger than `max_size`, as measured by
+    /// `truncation`, are truncated. `scheduling` and `ordering` only
+    /// affect the order in which answers are discovered, not which
+    /// answers there are. If `simplify_goals` is set, goals are
+    /// simplified (see `Goal::simplify`) before being solved. At most
+    /// `max_projection_depth` goals normalizing projections may be
+    /// nested directly within one another, as when normalizing
+    /// `<<T as A>::X as B>::Y`; the search gives up on those nested
+    /// any deeper, with `CannotProveReason::ProjectionDepth`. Goals
+    /// with types nested more than `max_type_depth` deep are not solved
+    /// at all, but reported with `CannotProveReason::TooDeep`: the
+    /// solver recurses into types, and could overflow the stack on
+    /// them. If `track_provenance` is set, each lifetime constraint of
+    /// a solution records the goal that imposed it (see `Provenance`).
+    /// `const_overflow` is what becomes of goals in which a const
+    /// expression overflows. If `ground_fast_path` is set, goals
+    /// without variables that simply hold by the clauses of the program
+    /// are proven without creating tables for them, with the same
+    /// solution. A `Solver` keeps at most `max_cached_tables` tables
+    /// from one goal to the next, evicting the least recently used ones
+    /// (see `Forest::with_max_cached_tables`).
+    SLG {
+        max_size: usize,
+        truncation: TruncationMeasure,
+        scheduling: Scheduling,
+        ordering: SubgoalOrdering,
+        simplify_goals: bool,
+        max_projection_depth: usize,
+        max_type_depth: usize,
+        track_provenance: bool,
+        const_overflow: ConstOverflow,
+        ground_fast_path: bool,
+        max_cached_tables: usize,
+    },
+}
+
+/// The order in which the conditions of a program clause are proven.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SubgoalOrdering {
+    /// Prove the conditions from left to right, as written.
+    AsWritten,
+
+    /// Prove `Implemented` conditions first, then `ProjectionEq`,
+    /// then `Normalize`, then any other positive condition, and
+    /// negative conditions last; conditions of the same sort are
+    /// proven as written. Proving the traits of a type first tends to
+    /// pin it down before its projections are looked at, which would
+    /// otherwise have to consider every impl.
+    ByPriority,
+}
+
+impl Default for SubgoalOrdering {
+    fn default() -> Self {
+        SubgoalOrdering::AsWritten
+    }
+}
+
+/// What becomes of a goal in which a const expression overflows, like
+/// `Foo<{255u8 + 1}>: Bar`. A program in which one does is always
+/// rejected.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ConstOverflow {
+    /// The goal is rejected when it is lowered, with the error of the
+    /// overflow.
+    Error,
+
+    /// The goal is lowered to one that can be neither proven nor
+    /// disproven.
+    CannotProve,
+}
+
+impl Default for ConstOverflow {
+    fn default() -> Self {
+        ConstOverflow::Error
+    }
 }
 
 impl SolverChoice {
@@ -78,7 +260,9 @@ impl SolverChoice {
     ///
     /// # Returns
     ///
-    /// - `Ok(None)` is the goal cannot be proven.
+    /// - `Ok(None)` is the goal cannot be proven. Note that this is
+    ///   also the case of goals that do not hold, which `decide_root_goal`
+    ///   tells apart.
     /// - `Ok(Some(solution))` if we succeeded in finding *some* answers,
     ///   although `solution` may reflect ambiguity and unknowns.
     /// - `Err` if there was an internal error solving the goal, which does not
@@ -88,18 +272,347 @@ impl SolverChoice {
         env: &Arc<ProgramEnvironment>,
         canonical_goal: &UCanonical<InEnvironment<Goal>>,
     ) -> ::errors::Result<Option<Solution>> {
-        use self::slg::implementation::solve_goal_in_program;
+        self.solve_root_goal_with(env, canonical_goal, &SolverHooks::default())
+    }
+
+    /// Like `solve_root_goal`, but the goals of external predicates are
+    /// answered, and named constants evaluated, by the `hooks` of the
+    /// embedder.
+    pub fn solve_root_goal_with(
+        self,
+        env: &Arc<ProgramEnvironment>,
+        canonical_goal: &UCanonical<InEnvironment<Goal>>,
+        hooks: &SolverHooks,
+    ) -> ::errors::Result<Option<Solution>> {
+        Ok(self.solve(env, canonical_goal, hooks))
+    }
+
+    /// Like `solve_root_goal`, but tells apart the goals which do not
+    /// hold (`SolveOutcome::Disproven`) from those which could not be
+    /// proven for some other reason: a goal is only disproven if the
+    /// search for its answers was exhaustive, and then comes with a hint
+    /// as to why it fails, if there is one.
+    pub fn decide_root_goal(
+        self,
+        env: &Arc<ProgramEnvironment>,
+        canonical_goal: &UCanonical<InEnvironment<Goal>>,
+    ) -> ::errors::Result<SolveOutcome> {
+        self.decide_root_goal_with(env, canonical_goal, &SolverHooks::default())
+    }
+
+    /// Like `decide_root_goal`, with the `hooks` of the embedder (see
+    /// `solve_root_goal_with`).
+    pub fn decide_root_goal_with(
+        self,
+        env: &Arc<ProgramEnvironment>,
+        canonical_goal: &UCanonical<InEnvironment<Goal>>,
+        hooks: &SolverHooks,
+    ) -> ::errors::Result<SolveOutcome> {
+        Ok(match self.solve(env, canonical_goal, hooks) {
+            Some(solution) => SolveOutcome::from_solution(solution),
+            None => SolveOutcome::Disproven(failure_hint(env, canonical_goal)),
+        })
+    }
+
+    /// Solves `goal`, whose free variables are those of `table`, and
+    /// applies its solution to them: the goal is canonicalized and
+    /// solved like a root goal, and if its solution is unique, the
+    /// variables of the goal are unified with the values the solution
+    /// gives them, in `table`.
+    ///
+    /// # Returns
+    ///
+    /// - `Err(NoSolution)` if the goal cannot be proven.
+    /// - `Ok(SolveResultApplied::Unique { constraints, constrained_vars })`
+    ///   if the solution was unique and has been applied; `constraints`
+    ///   must still hold for the goal to hold.
+    /// - `Ok(SolveResultApplied::Ambiguous(solution))` if there was some
+    ///   other solution, or the unique one could not be applied as it
+    ///   is (because its substitution mentions projections, say). The
+    ///   solution is that of the canonicalized goal.
+    ///
+    /// Unless the solution has been applied, `table` is left as it was.
+    ///
+    /// Only the tests call this so far: the variables of an inference
+    /// table cannot be created from outside the crate, so there is no
+    /// point in making it public before those are.
+    #[allow(dead_code)]
+    pub(crate) fn solve_in_table(
+        self,
+        env: &Arc<ProgramEnvironment>,
+        table: &mut InferenceTable,
+        goal: &InEnvironment<Goal>,
+    ) -> Fallible<SolveResultApplied> {
+        let canonicalized = table.canonicalize(goal);
+        let UCanonicalized {
+            quantified,
+            universes,
+        } = table.u_canonicalize(&canonicalized.quantified);
+        let solution = match self.solve(env, &quantified, &SolverHooks::default()) {
+            Some(solution) => solution,
+            None => return Err(NoSolution),
+        };
+        let canonical_solution_subst = match &solution {
+            Solution::Unique(constrained) => universes.map_canonical_from_canonical(constrained),
+            _ => return Ok(SolveResultApplied::Ambiguous(solution)),
+        };
+
+        // As in `apply_answer_subst`, the bindings made here are only
+        // kept if the whole substitution applies.
+        let snapshot = table.snapshot();
+        let SolutionSubst {
+            subst,
+            mut constraints,
+        } = table.instantiate_canonical(&canonical_solution_subst);
+        for (var, value) in canonicalized.free_vars.iter().zip(&subst.parameters) {
+            match table.unify(&goal.environment, &var.to_parameter(), value) {
+                Ok(ref result) if result.goals.is_empty() => {
+                    // The constraints of the answer are under binders,
+                    // which those of the unification must be put under.
+                    let binders = constraints.binders.len();
+                    constraints.value.extend(
+                        result
+                            .constraints
+                            .iter()
+                            .map(|c| RegionConstraint::new(c.up_shift(binders))),
+                    );
+                }
+                _ => {
+                    table.rollback_to(snapshot);
+                    return Ok(SolveResultApplied::Ambiguous(solution));
+                }
+            }
+        }
+        table.commit(snapshot);
+        let constrained_vars = table
+            .constrained_vars(&canonicalized.free_vars)
+            .into_iter()
+            .map(|var| var.to_parameter())
+            .collect();
+        Ok(SolveResultApplied::Unique {
+            constraints,
+            constrained_vars,
+        })
+    }
+
+    /// Like `solve_in_table`, but enumerates the answers to `goal`
+    /// rather than aggregating them into a single solution: at most
+    /// `max_answers` of them, in the order in which the solver finds
+    /// them. Each answer is applied to its own copy of `table`, which
+    /// is itself left as it was; an answer which cannot be applied as
+    /// it is (see `solve_in_table`) comes with an unchanged copy, and
+    /// is ambiguous.
+    pub(crate) fn answers_in_table(
+        self,
+        env: &Arc<ProgramEnvironment>,
+        table: &InferenceTable,
+        goal: &InEnvironment<Goal>,
+        max_answers: usize,
+    ) -> Vec<AppliedAnswer> {
+        let mut table = table.clone();
+        let canonicalized = table.canonicalize(goal);
+        let UCanonicalized {
+            quantified,
+            universes,
+        } = table.u_canonicalize(&canonicalized.quantified);
+
+        self.answers(env, &quantified, max_answers, false)
+            .into_iter()
+            .map(|(answer, ambiguous)| {
+                let mut answer_table = table.clone();
+                let answer = universes.map_canonical_from_canonical(&answer);
+                let ConstrainedSubst {
+                    subst,
+                    mut constraints,
+                    ..
+                } = answer_table.instantiate_canonical(&answer);
+                for (var, value) in canonicalized.free_vars.iter().zip(&subst.parameters) {
+                    match answer_table.unify(&goal.environment, &var.to_parameter(), value) {
+                        Ok(ref result) if result.goals.is_empty() => constraints.extend(
+                            result.constraints.iter().cloned().map(RegionConstraint::new),
+                        ),
+                        _ => {
+                            return AppliedAnswer {
+                                table: table.clone(),
+                                constraints: vec![],
+                                ambiguous: true,
+                            };
+                        }
+                    }
+                }
+                AppliedAnswer {
+                    table: answer_table,
+                    constraints,
+                    ambiguous,
+                }
+            })
+            .collect()
+    }
+
+    /// The distinct substitutions of the first `max_answers` answers to
+    /// a root goal, in the order in which the solver finds them. When
+    /// the solution of the goal is ambiguous, these are the candidates
+    /// it could not choose between: the traits in scope which an
+    /// unselected projection like `T::Item` could refer to, say. The
+    /// lifetime constraints of the answers are dropped.
+    pub fn candidates(
+        self,
+        env: &Arc<ProgramEnvironment>,
+        canonical_goal: &UCanonical<InEnvironment<Goal>>,
+        max_answers: usize,
+    ) -> Vec<Canonical<Substitution>> {
+        let mut candidates = vec![];
+        for (answer, _) in self.answers(env, canonical_goal, max_answers, false) {
+            let candidate = Canonical {
+                value: answer.value.subst,
+                binders: answer.binders,
+            };
+            if !candidates.contains(&candidate) {
+                candidates.push(candidate);
+            }
+        }
+        candidates
+    }
+
+    /// The first `max_answers` answers to a root goal, each with
+    /// whether it is ambiguous, and the clauses it was proven with if
+    /// `record_sources` is set (see `ClauseSources`). There are none if
+    /// the goal is too deep to be solved at all.
+    fn answers(
+        self,
+        env: &Arc<ProgramEnvironment>,
+        canonical_goal: &UCanonical<InEnvironment<Goal>>,
+        max_answers: usize,
+        record_sources: bool,
+    ) -> Vec<(Canonical<ConstrainedSubst>, bool)> {
+        use self::slg::implementation::answers_in_program;
 
         match self {
-            SolverChoice::SLG { max_size } => {
-                Ok(solve_goal_in_program(canonical_goal, env, max_size))
+            SolverChoice::SLG {
+                max_size,
+                truncation,
+                scheduling,
+                ordering,
+                simplify_goals,
+                max_projection_depth,
+                max_type_depth,
+                track_provenance,
+                const_overflow: _,
+                ground_fast_path: _,
+                max_cached_tables,
+            } => {
+                if exceeds_depth(&canonical_goal.canonical.value, max_type_depth) {
+                    return vec![];
+                }
+
+                let canonical_goal = if simplify_goals {
+                    canonical_goal.simplify()
+                } else {
+                    canonical_goal.clone()
+                };
+                answers_in_program(
+                    &canonical_goal,
+                    env,
+                    max_size,
+                    truncation,
+                    scheduling,
+                    ordering,
+                    max_projection_depth,
+                    max_type_depth,
+                    track_provenance,
+                    max_cached_tables,
+                    max_answers,
+                    record_sources,
+                )
             }
         }
     }
 
+    fn solve(
+        self,
+        env: &Arc<ProgramEnvironment>,
+        canonical_goal: &UCanonical<InEnvironment<Goal>>,
+        hooks: &SolverHooks,
+    ) -> Option<Solution> {
+        self.into_solver_with(env, hooks).solve(canonical_goal)
+    }
+
+    /// Creates a solver for the goals of `env`, which keeps the tables
+    /// of the goals it solves from one to the next (see `Solver`).
+    pub fn into_solver(self, env: &Arc<ProgramEnvironment>) -> Solver {
+        self.into_solver_with(env, &SolverHooks::default())
+    }
+
+    /// Like `into_solver`, with the `hooks` of the embedder (see
+    /// `solve_root_goal_with`).
+    pub fn into_solver_with(self, env: &Arc<ProgramEnvironment>, hooks: &SolverHooks) -> Solver {
+        use self::slg::implementation::new_forest;
+
+        let forest = match self {
+            SolverChoice::SLG {
+                max_size,
+                truncation,
+                scheduling,
+                ordering,
+                simplify_goals: _,
+                max_projection_depth,
+                max_type_depth,
+                track_provenance,
+                const_overflow: _,
+                ground_fast_path: _,
+                max_cached_tables,
+            } => new_forest(
+                env,
+                hooks,
+                max_size,
+                truncation,
+                scheduling,
+                ordering,
+                max_projection_depth,
+                max_type_depth,
+                track_provenance,
+                max_cached_tables,
+            ),
+        };
+        Solver {
+            choice: self,
+            env: env.clone(),
+            hooks: hooks.clone(),
+            forest,
+        }
+    }
+
+    /// True if goals are simplified before being solved, including the
+    /// conditions of the custom clauses of the program.
+    pub fn simplifies_goals(self) -> bool {
+        match self {
+            SolverChoice::SLG { simplify_goals, .. } => simplify_goals,
+        }
+    }
+
+    /// What becomes of goals in which a const expression overflows,
+    /// when they are lowered.
+    pub fn const_overflow(self) -> ConstOverflow {
+        match self {
+            SolverChoice::SLG { const_overflow, .. } => const_overflow,
+        }
+    }
+
     /// Returns the default SLG parameters.
     fn slg() -> Self {
-        SolverChoice::SLG { max_size: 10 }
+        SolverChoice::SLG {
+            max_size: 10,
+            truncation: TruncationMeasure::default(),
+            scheduling: Scheduling::default(),
+            ordering: SubgoalOrdering::default(),
+            simplify_goals: false,
+            max_projection_depth: slg::implementation::DEFAULT_MAX_PROJECTION_DEPTH,
+            max_type_depth: slg::implementation::DEFAULT_MAX_TYPE_DEPTH,
+            track_provenance: false,
+            const_overflow: ConstOverflow::default(),
+            ground_fast_path: true,
+            max_cached_tables: usize::MAX,
+        }
     }
 }
 
@@ -108,3 +621,136 @@ impl Default for SolverChoice {
         SolverChoice::slg()
     }
 }
+
+/// A solver for the goals of one program environment, which keeps the
+/// tables it creates from one goal to the next: a goal that was solved
+/// before, or that shares subgoals with one, reuses the answers found
+/// then. Created by `SolverChoice::into_solver`.
+///
+/// The solver keeps at most `max_cached_tables` tables between two goals
+/// (see `SolverChoice::SLG`); an embedder may also drop the tables it
+/// no longer needs with `gc`.
+pub struct Solver {
+    choice: SolverChoice,
+    env: Arc<ProgramEnvironment>,
+    hooks: SolverHooks,
+    forest: slg::implementation::SlgForest,
+}
+
+impl Solver {
+    /// Like `SolverChoice::solve_root_goal`, reusing the tables of the
+    /// goals solved before.
+    pub fn solve_root_goal(
+        &mut self,
+        canonical_goal: &UCanonical<InEnvironment<Goal>>,
+    ) -> ::errors::Result<Option<Solution>> {
+        Ok(self.solve(canonical_goal))
+    }
+
+    /// Like `SolverChoice::decide_root_goal`, reusing the tables of the
+    /// goals solved before.
+    pub fn decide_root_goal(
+        &mut self,
+        canonical_goal: &UCanonical<InEnvironment<Goal>>,
+    ) -> ::errors::Result<SolveOutcome> {
+        Ok(match self.solve(canonical_goal) {
+            Some(solution) => SolveOutcome::from_solution(solution),
+            None => SolveOutcome::Disproven(failure_hint(&self.env, canonical_goal)),
+        })
+    }
+
+    /// Drops the tables that no goal solved during the last
+    /// `keep_generations` calls has used, unless a table that is kept
+    /// depends on them (see `Forest::gc`). The tables of the goals
+    /// given to the solver are kept. Returns the number of tables
+    /// dropped; a goal whose table was dropped is solved anew if it is
+    /// asked again.
+    pub fn gc(&mut self, keep_generations: u64) -> usize {
+        self.forest.gc(keep_generations)
+    }
+
+    /// The number of tables the solver keeps.
+    pub fn num_tables(&self) -> usize {
+        self.forest.num_tables()
+    }
+
+    /// The work the solver has done so far, for all the goals it was
+    /// given. Goals proven without tables (see `ground_fast_path` in
+    /// `SolverChoice::SLG`) do not count.
+    pub fn stats(&self) -> SolverStats {
+        SolverStats {
+            tables_created: self.forest.num_tables_created(),
+            strand_steps: self.forest.num_strand_steps(),
+            subgoal_selections: self.forest.num_subgoal_selections(),
+            duplicate_answers: self.forest.num_duplicate_answers(),
+            evicted_tables: self.forest.num_evicted_tables(),
+        }
+    }
+
+    fn solve(&mut self, canonical_goal: &UCanonical<InEnvironment<Goal>>) -> Option<Solution> {
+        use self::slg::implementation::{solve_goal_in_forest, solve_ground_goal_in_program};
+
+        match self.choice {
+            SolverChoice::SLG {
+                max_size,
+                truncation,
+                simplify_goals,
+                max_type_depth,
+                ground_fast_path,
+                ..
+            } => {
+                // Checked before anything else, which might recurse
+                // into the types of the goal.
+                if exceeds_depth(&canonical_goal.canonical.value, max_type_depth) {
+                    return Some(Solution::CannotProve(CannotProveReason::TooDeep));
+                }
+
+                let canonical_goal = if simplify_goals {
+                    canonical_goal.simplify()
+                } else {
+                    canonical_goal.clone()
+                };
+
+                // The fast path knows nothing of external predicates,
+                // nor of named constants.
+                if ground_fast_path && self.hooks.is_empty() {
+                    let solution = solve_ground_goal_in_program(
+                        &canonical_goal,
+                        &self.env,
+                        max_size,
+                        truncation,
+                        max_type_depth,
+                    );
+                    if solution.is_some() {
+                        return solution;
+                    }
+                }
+
+                solve_goal_in_forest(&canonical_goal, &mut self.forest)
+            }
+        }
+    }
+}
+
+/// Counters of the work a `Solver` has done (see `Solver::stats`), to
+/// compare how much work goals, or solver options, take.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SolverStats {
+    /// The number of tables created, including those evicted or dropped
+    /// by `Solver::gc` since.
+    pub tables_created: u64,
+
+    /// The number of times a strand was pursued.
+    pub strand_steps: u64,
+
+    /// The number of subgoals that strands selected, and looked up in a
+    /// table.
+    pub subgoal_selections: u64,
+
+    /// The number of answers found again, and so discarded.
+    pub duplicate_answers: u64,
+
+    /// The number of tables evicted to keep no more than
+    /// `max_cached_tables` (see `SolverChoice::SLG`).
+    pub evicted_tables: u64,
+}
@@ -0,0 +1,198 @@
+//! The sources of the ambiguity of a goal. A goal is ambiguous when
+//! several clauses prove it with different answers, and which clauses
+//! they are tells why: two where clauses `T: Foo<Assoc = A>` and
+//! `T: Foo<Assoc = B>` make `exists<U> { T: Foo<Assoc = U> }` ambiguous
+//! just as two impls would, but the solution does not say which.
+
+use ir::*;
+use ir::debug::{AssocBound, ImplHeader, WithColon};
+use solve::SolverChoice;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// The most sources listed in `AmbiguitySources`; the others are only
+/// counted.
+const MAX_SOURCES: usize = 4;
+
+/// The most answers looked at for the sources of the ambiguity of a
+/// goal.
+const MAX_ANSWERS: usize = 32;
+
+impl fmt::Display for ClauseSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClauseSource::WhereClause(ProgramClause::Implies(implication))
+                if implication.conditions.is_empty() =>
+            {
+                match &implication.consequence {
+                    DomainGoal::Holds(WhereClause::Implemented(trait_ref))
+                    | DomainGoal::FromEnv(FromEnv::Trait(trait_ref)) => {
+                        write!(f, "where-clause `{:?}`", WithColon(trait_ref))
+                    }
+                    DomainGoal::Holds(WhereClause::ProjectionEq(ProjectionEq { projection, ty }))
+                    | DomainGoal::Normalize(Normalize { projection, ty }) => {
+                        write!(f, "where-clause `{:?}`", AssocBound(projection, ty))
+                    }
+                    consequence => write!(f, "where-clause `{:?}`", consequence),
+                }
+            }
+            ClauseSource::WhereClause(clause) => write!(f, "where-clause `{:?}`", clause),
+            ClauseSource::Impl(_, trait_ref) => write!(f, "impl `{:?}`", ImplHeader(trait_ref)),
+            ClauseSource::Program(clause) => write!(f, "clause `{:?}`", clause),
+        }
+    }
+}
+
+/// The clauses which prove an ambiguous goal with different answers
+/// (see `SolverChoice::ambiguity_sources`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AmbiguitySources {
+    /// The first of the clauses, in the order in which the solver
+    /// considers them: those of the environment first.
+    pub sources: Vec<ClauseSource>,
+
+    /// How many more clauses there are.
+    pub omitted: usize,
+}
+
+impl AmbiguitySources {
+    fn new(mut sources: Vec<ClauseSource>) -> Self {
+        let omitted = sources.len().saturating_sub(MAX_SOURCES);
+        sources.truncate(MAX_SOURCES);
+        AmbiguitySources { sources, omitted }
+    }
+
+    /// Displays the sources with the placeholders printed by the names
+    /// of the `forall<>` binders they come from (see `PeeledGoal`).
+    pub fn display_with_names<'a>(
+        &'a self,
+        names: &'a BTreeMap<PlaceholderIndex, String>,
+    ) -> NamedAmbiguitySources<'a> {
+        NamedAmbiguitySources { sources: self, names }
+    }
+}
+
+impl fmt::Display for AmbiguitySources {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ambiguous between: ")?;
+        for (index, source) in self.sources.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", source)?;
+        }
+        if self.omitted > 0 {
+            write!(f, ", and {} more", self.omitted)?;
+        }
+        Ok(())
+    }
+}
+
+/// See `AmbiguitySources::display_with_names`.
+pub struct NamedAmbiguitySources<'a> {
+    sources: &'a AmbiguitySources,
+    names: &'a BTreeMap<PlaceholderIndex, String>,
+}
+
+impl<'a> fmt::Display for NamedAmbiguitySources<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        tls::with_placeholder_names(self.names, || fmt::Display::fmt(self.sources, f))
+    }
+}
+
+impl SolverChoice {
+    /// The clauses which make `goal` ambiguous in `program`, if they
+    /// can be found. The goal is solved again, with the clauses each
+    /// answer is proven with recorded as the solver chooses them (see
+    /// `ClauseSources`). The answers are told apart by the first
+    /// clauses they were not proven with in the same way: if the goal
+    /// itself is proven with different clauses, these are the sources
+    /// of the ambiguity; if only one clause proves it, the ambiguity is
+    /// in its conditions, and so on. Answers which are the same as
+    /// another are only found once, so the clauses which prove the goal
+    /// with the same answer as another are left out.
+    pub fn ambiguity_sources(
+        self,
+        program: &Arc<ProgramEnvironment>,
+        goal: &UCanonical<InEnvironment<Goal>>,
+    ) -> Option<AmbiguitySources> {
+        let answers: Vec<Vec<Canonical<ClauseSource>>> = self
+            .answers(program, goal, MAX_ANSWERS, true)
+            .into_iter()
+            .map(|(answer, _)| answer.value.sources.sources)
+            .collect();
+        let (first, others) = answers.split_first()?;
+        let longest = answers.iter().map(Vec::len).max()?;
+        let depth = (0..longest)
+            .find(|&depth| others.iter().any(|other| other.get(depth) != first.get(depth)))?;
+
+        let mut sources: Vec<ClauseSource> = vec![];
+        for source in answers.iter().filter_map(|answer| answer.get(depth)) {
+            if !sources.contains(&source.value) {
+                sources.push(source.value.clone());
+            }
+        }
+        if sources.len() < 2 {
+            return None;
+        }
+
+        // The answers are found in no particular order, so the clauses
+        // are put back in the order in which the solver considers them.
+        sources.sort_by(|a, b| match (a, b) {
+            (ClauseSource::WhereClause(a), ClauseSource::WhereClause(b)) => a.cmp(b),
+            (ClauseSource::Impl(a, _), ClauseSource::Impl(b, _)) => a.cmp(b),
+            (a, b) => source_rank(a).cmp(&source_rank(b)),
+        });
+        Some(AmbiguitySources::new(sources))
+    }
+}
+
+/// Where the clauses from each source come among those the solver
+/// considers: the hypotheses of the environment come first.
+fn source_rank(source: &ClauseSource) -> usize {
+    match source {
+        ClauseSource::WhereClause(_) => 0,
+        ClauseSource::Impl(..) => 1,
+        ClauseSource::Program(_) => 2,
+    }
+}
+
+/// Where `clause`, chosen to prove `goal` in `environment`, comes from,
+/// with the trait reference of an impl as it applies to the goal. The
+/// solver records it as it builds the resolvent of the goal with the
+/// clause, if it is asked to (see `ClauseSources`).
+pub(crate) fn clause_source(
+    program: &ProgramEnvironment,
+    environment: &Arc<Environment>,
+    goal: &DomainGoal,
+    clause: &ProgramClause,
+) -> ClauseSource {
+    let impl_id = match program.impl_clauses.get(clause) {
+        Some(&impl_id) => impl_id,
+        None if environment.candidate_clauses(goal).contains(&clause) => {
+            return ClauseSource::WhereClause(clause.clone())
+        }
+        None => return ClauseSource::Program(clause.clone()),
+    };
+    let trait_ref = match goal {
+        DomainGoal::Holds(WhereClause::Implemented(trait_ref)) => trait_ref.clone(),
+        DomainGoal::Holds(WhereClause::ProjectionEq(ProjectionEq { projection, .. }))
+        | DomainGoal::Normalize(Normalize { projection, .. }) => {
+            projection_trait_ref(program, projection)
+        }
+        _ => return ClauseSource::Program(clause.clone()),
+    };
+    ClauseSource::Impl(impl_id, trait_ref)
+}
+
+/// The trait reference of the trait `projection` projects out of.
+fn projection_trait_ref(program: &ProgramEnvironment, projection: &ProjectionTy) -> TraitRef {
+    let trait_id = program.associated_ty_data[&projection.associated_ty_id].trait_id;
+    let num_trait_params = program.trait_data[&trait_id].binders.len();
+    let split_point = projection.parameters.len() - num_trait_params;
+    TraitRef {
+        trait_id,
+        parameters: projection.parameters[split_point..].to_vec(),
+    }
+}
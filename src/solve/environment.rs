@@ -0,0 +1,93 @@
+//! Setting up an environment by hand, as an embedder does when it asks
+//! goals from within a generic item: in the body of `fn foo<T: Clone>`,
+//! say, `T` is a type about which nothing is known but that it is
+//! `Clone`. Such a parameter is modeled as a placeholder, as with
+//! `forall<T> { if (T: Clone) { ... } }`.
+//!
+//! Placeholders live in universes. Each set of binders opened
+//! universally gets a new universe, which can see the names of all the
+//! universes before it; existential variables can only be unified with
+//! the placeholders of their own universe, or of earlier ones. The
+//! goals built here (see `EnvironmentBuilder::goal`) put their
+//! variables in the latest universe, so they can name every
+//! placeholder created so far.
+
+use std::sync::Arc;
+use cast::Cast;
+use ir::*;
+use solve::infer::InferenceTable;
+
+/// Builds up an environment, from assumptions about placeholders.
+#[derive(Clone)]
+pub struct EnvironmentBuilder {
+    table: InferenceTable,
+    environment: Arc<Environment>,
+}
+
+impl EnvironmentBuilder {
+    /// An empty environment, without any placeholders.
+    pub fn new() -> Self {
+        EnvironmentBuilder {
+            table: InferenceTable::new(),
+            environment: Environment::new(),
+        }
+    }
+
+    /// Opens `assumptions` universally, in a new universe, and adds
+    /// them to the environment. Returns the placeholders the variables
+    /// of `assumptions` were replaced with, in the order of its
+    /// binders, so that the caller can refer to them in its goals.
+    pub fn assume(&mut self, assumptions: &Binders<Vec<DomainGoal>>) -> Vec<Parameter> {
+        // The placeholders are found by opening the variables
+        // themselves along with the assumptions.
+        let variables: Vec<Parameter> = assumptions
+            .binders
+            .iter()
+            .enumerate()
+            .map(|(index, kind)| match kind {
+                ParameterKind::Ty(()) => ParameterKind::Ty(Ty::Var(index)),
+                ParameterKind::Lifetime(()) => ParameterKind::Lifetime(Lifetime::Var(index)),
+                ParameterKind::Const(()) => ParameterKind::Const(Const::Var(index)),
+            })
+            .collect();
+        let opened = (&variables, &assumptions.value);
+        let (placeholders, assumptions) =
+            self.table.instantiate_binders_universally(&(&assumptions.binders, &opened));
+        let clauses: Vec<ProgramClause> = assumptions.cast();
+        self.environment = Environment::add_clauses(&self.environment, clauses);
+        placeholders
+    }
+
+    /// The environment, with all the assumptions made so far.
+    pub fn environment(&self) -> &Arc<Environment> {
+        &self.environment
+    }
+
+    /// `goal`, in canonical form, to be solved in the environment. As
+    /// with `Goal::into_peeled_goal`, the outermost `exists<>`
+    /// quantifiers of `goal` are peeled, so that the solution gives
+    /// the values of their variables; these variables can name all of
+    /// the placeholders. The universes are not renumbered, so the
+    /// placeholders in the solution are the ones returned by
+    /// `assume`.
+    pub fn goal(&self, goal: Goal) -> UCanonical<InEnvironment<Goal>> {
+        let mut table = self.table.clone();
+        let mut goal = goal;
+        while let Goal::Quantified(QuantifierKind::Exists, subgoal) = goal {
+            goal = *table.instantiate_binders_existentially(&subgoal);
+        }
+        let canonical = table
+            .canonicalize(&InEnvironment::new(&self.environment, goal))
+            .quantified;
+        UCanonical {
+            canonical,
+            universes: table.max_universe().counter + 1,
+        }
+    }
+}
+
+impl Default for EnvironmentBuilder {
+    fn default() -> Self {
+        EnvironmentBuilder::new()
+    }
+}
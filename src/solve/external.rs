@@ -0,0 +1,198 @@
+use cast::Cast;
+use fold::Subst;
+use ir::*;
+
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+/// The answers of an `ExternalSolver` to an external goal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExternalAnswers {
+    /// The goal holds for each of these answers, and for no other
+    /// values of its variables. Each answer gives a value to every
+    /// variable of the goal, and may require some region constraints
+    /// to hold. An empty vector means that the goal does not hold.
+    Answers(Vec<Canonical<ConstrainedSubst>>),
+
+    /// The goal may hold, but the embedder cannot tell for which
+    /// values of its variables (e.g., because they are not yet
+    /// known).
+    Ambiguous,
+}
+
+/// Answers the goals of external predicates (see `ExternalGoal`) on
+/// behalf of the embedder of the solver: for example, whether a type
+/// is a valid discriminant, or the result of evaluating a constant.
+///
+/// The solver asks for the answers to each external goal at most once
+/// per canonical form, when the goal is first encountered, and caches
+/// them like the answers to any other goal. The answers must therefore
+/// only depend on the goal.
+pub trait ExternalSolver {
+    /// Answers the goal `predicate(parameters)`, where the variables
+    /// of `parameters` are bound by the canonical binders.
+    fn solve(&self, predicate: ItemId, parameters: &Canonical<Vec<Parameter>>) -> ExternalAnswers;
+}
+
+impl<F> ExternalSolver for F
+where
+    F: Fn(ItemId, &Canonical<Vec<Parameter>>) -> ExternalAnswers,
+{
+    fn solve(&self, predicate: ItemId, parameters: &Canonical<Vec<Parameter>>) -> ExternalAnswers {
+        self(predicate, parameters)
+    }
+}
+
+/// The value of a named constant (see `UnevaluatedConst`), as computed
+/// by a `ConstEval`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConstEvaluation {
+    /// The constant has this value. It should be of the type the
+    /// constant is declared with: values of different types are never
+    /// equal.
+    Value(ConstValue),
+
+    /// The value of the constant depends on parameters which are not
+    /// known (e.g., inference variables): the constant is then equal
+    /// to itself, and may or may not be equal to any other constant.
+    TooGeneric,
+}
+
+/// Evaluates named constants on behalf of the embedder of the solver.
+/// The solver evaluates a constant whenever it has to unify it, so
+/// the value must only depend on the constant and its parameters.
+pub trait ConstEval {
+    /// Evaluates the constant `const_id` with the given parameters,
+    /// where the variables of `parameters` are bound by the canonical
+    /// binders.
+    fn evaluate(&self, const_id: ItemId, parameters: &Canonical<Vec<Parameter>>) -> ConstEvaluation;
+}
+
+impl<F> ConstEval for F
+where
+    F: Fn(ItemId, &Canonical<Vec<Parameter>>) -> ConstEvaluation,
+{
+    fn evaluate(&self, const_id: ItemId, parameters: &Canonical<Vec<Parameter>>) -> ConstEvaluation {
+        self(const_id, parameters)
+    }
+}
+
+/// What the embedder of the solver provides to it besides the program:
+/// the answers to external goals and the values of named constants.
+/// Without an external solver, external goals never hold (unless they
+/// are assumed by the environment); without a const evaluator, named
+/// constants are only equal to themselves.
+#[derive(Clone, Default)]
+pub struct SolverHooks {
+    pub(crate) external: Option<Arc<dyn ExternalSolver>>,
+    pub(crate) const_eval: Option<Arc<dyn ConstEval>>,
+}
+
+impl SolverHooks {
+    /// Answers the goals of external predicates with `external`.
+    pub fn with_external_solver(self, external: Arc<dyn ExternalSolver>) -> Self {
+        SolverHooks { external: Some(external), ..self }
+    }
+
+    /// Evaluates named constants with `const_eval` when unifying them.
+    pub fn with_const_eval(self, const_eval: Arc<dyn ConstEval>) -> Self {
+        SolverHooks { const_eval: Some(const_eval), ..self }
+    }
+
+    /// True if the embedder provides nothing.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.external.is_none() && self.const_eval.is_none()
+    }
+
+    pub(crate) fn const_eval(&self) -> Option<&dyn ConstEval> {
+        self.const_eval.as_ref().map(|const_eval| &**const_eval)
+    }
+}
+
+// The hooks are usually closures, which cannot be printed.
+impl Debug for SolverHooks {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("SolverHooks")
+            .field("external", &self.external.is_some())
+            .field("const_eval", &self.const_eval.is_some())
+            .finish()
+    }
+}
+
+/// Converts the answers of an external solver to the goal
+/// `predicate(parameters)` into program clauses, so that the goal can
+/// be solved like any other: each answer becomes a fact (with the
+/// region constraints of the answer as its conditions), and ambiguity
+/// becomes a clause that can never be proven nor disproven.
+pub(crate) fn external_clauses(
+    predicate: ItemId,
+    parameters: &Canonical<Vec<Parameter>>,
+    answers: ExternalAnswers,
+) -> Vec<ProgramClause> {
+    let binders = |kinds: &[ParameterKind<UniverseIndex>]| -> Vec<ParameterKind<()>> {
+        kinds.iter().map(|kind| kind.map(|_| ())).collect()
+    };
+
+    // An answer that does not give one value of the right kind to each
+    // variable of the goal cannot be turned into a clause: rather than
+    // trusting the embedder and panicking, we treat the goal as
+    // ambiguous.
+    let answers = match answers {
+        ExternalAnswers::Answers(ref answers)
+            if !answers.iter().all(|answer| parameters.substitution_kinds_match(&answer.value.subst)) =>
+        {
+            ExternalAnswers::Ambiguous
+        }
+        answers => answers,
+    };
+
+    match answers {
+        ExternalAnswers::Answers(answers) => answers
+            .into_iter()
+            .map(|answer| {
+                let ConstrainedSubst { subst, constraints, .. } = answer.value;
+                let consequence = ExternalGoal {
+                    predicate,
+                    parameters: Subst::apply(&subst.parameters, &parameters.value),
+                };
+                // Outlives constraints which always hold, like `Foo:
+                // 'static`, break down into nothing.
+                let conditions = constraints
+                    .into_iter()
+                    .flat_map(|region_constraint| region_constraint.constraint.goal.decompose())
+                    .map(|constraint| match constraint {
+                        Constraint::LifetimeEq(a, b) => EqGoal {
+                            a: ParameterKind::Lifetime(a),
+                            b: ParameterKind::Lifetime(b),
+                        }.cast(),
+
+                        // There is no goal requiring one lifetime to
+                        // outlive another, so the most we can say is
+                        // that the answer may hold.
+                        Constraint::TypeOutlives(..) | Constraint::LifetimeOutlives(..) => {
+                            Goal::CannotProve(())
+                        }
+                    })
+                    .collect();
+                ProgramClause::ForAll(Binders {
+                    binders: binders(&answer.binders),
+                    value: ProgramClauseImplication {
+                        consequence: consequence.cast(),
+                        conditions,
+                    },
+                })
+            })
+            .collect(),
+
+        ExternalAnswers::Ambiguous => vec![ProgramClause::ForAll(Binders {
+            binders: binders(&parameters.binders),
+            value: ProgramClauseImplication {
+                consequence: ExternalGoal {
+                    predicate,
+                    parameters: parameters.value.clone(),
+                }.cast(),
+                conditions: vec![Goal::CannotProve(())],
+            },
+        })],
+    }
+}
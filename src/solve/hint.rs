@@ -0,0 +1,296 @@
+//! Hints as to why a goal has no solution, for the failures that may
+//! come as a surprise. `forall<T> { Vec<T>: Foo }` does not hold when
+//! the only impl is `impl Foo for Vec<u32>`, though `Vec<u32>: Foo`
+//! does: not because no impl of `Foo` is for a `Vec`, but because `T`
+//! is a placeholder, which can only be equal to itself.
+
+use cast::Cast;
+use fallible::*;
+use fold::shift::Shift;
+use fold::{DefaultTypeFolder, Fold, IdentityExistentialFolder, UniversalFolder};
+use ir::*;
+use ir::could_match::CouldMatch;
+use ir::debug::{BoundImplHeader, WithColon};
+use solve::infer::{InferenceTable, InferenceVariable};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Why a goal without a solution might have been expected to have one
+/// (see `failure_hint`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FailureHint {
+    /// No clause applies to `goal`, but the clause of the impl
+    /// `would_match` would, if `placeholder` -- a name introduced by a
+    /// `forall<>` goal, in the universe of that goal -- were `value`.
+    RigidPlaceholder {
+        goal: TraitRef,
+        placeholder: PlaceholderIndex,
+        value: Ty,
+        would_match: ItemId,
+
+        /// The trait reference of the impl, under its binders, as it is
+        /// written in the header of the impl.
+        impl_header: Binders<TraitRef>,
+    },
+}
+
+impl FailureHint {
+    /// Displays this hint with the placeholders printed by the names of
+    /// the `forall<>` binders they come from (see `PeeledGoal`), as `!T`
+    /// rather than `!1_0`.
+    pub fn display_with_names<'a>(
+        &'a self,
+        names: &'a BTreeMap<PlaceholderIndex, String>,
+    ) -> NamedFailureHint<'a> {
+        NamedFailureHint { hint: self, names }
+    }
+}
+
+impl fmt::Display for FailureHint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FailureHint::RigidPlaceholder {
+                goal,
+                placeholder,
+                value,
+                impl_header,
+                ..
+            } => {
+                write!(
+                    f,
+                    "`{:?}` fails, but `{:?}` would apply if ",
+                    WithColon(goal),
+                    BoundImplHeader(impl_header),
+                )?;
+                match tls::placeholder_name(*placeholder) {
+                    Some(name) => write!(f, "{}", name)?,
+                    None => write!(f, "{:?}", placeholder)?,
+                }
+                write!(f, " were {:?}", value)
+            }
+        }
+    }
+}
+
+/// See `FailureHint::display_with_names`.
+pub struct NamedFailureHint<'a> {
+    hint: &'a FailureHint,
+    names: &'a BTreeMap<PlaceholderIndex, String>,
+}
+
+impl<'a> fmt::Display for NamedFailureHint<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        tls::with_placeholder_names(self.names, || fmt::Display::fmt(self.hint, f))
+    }
+}
+
+/// A hint as to why `goal`, which has no solution in `program`, fails,
+/// if its failure may come as a surprise. Only the goals that trait
+/// references are implemented are looked at, under the quantifiers,
+/// implications and conjunctions of `goal`, and only those to which no
+/// impl or hypothesis applies at all: the first of them that some impl
+/// would apply to, were one of its type placeholders an inference
+/// variable, is the hint. Only the impls of the trait of each goal are
+/// looked at, found by its id, so this is cheap.
+pub fn failure_hint(
+    program: &Arc<ProgramEnvironment>,
+    goal: &UCanonical<InEnvironment<Goal>>,
+) -> Option<FailureHint> {
+    let (infer, _, InEnvironment { environment, goal }) =
+        InferenceTable::from_canonical(goal.universes, &goal.canonical);
+    HintFinder { program, infer }.goal(&environment, &goal)
+}
+
+struct HintFinder<'p> {
+    program: &'p ProgramEnvironment,
+    infer: InferenceTable,
+}
+
+impl<'p> HintFinder<'p> {
+    fn goal(&mut self, environment: &Arc<Environment>, goal: &Goal) -> Option<FailureHint> {
+        match goal {
+            Goal::Quantified(QuantifierKind::ForAll, subgoal) => {
+                let subgoal = self.infer.instantiate_binders_universally(subgoal);
+                self.goal(environment, &subgoal)
+            }
+            Goal::Quantified(QuantifierKind::Exists, subgoal) => {
+                let subgoal = self.infer.instantiate_binders_existentially(subgoal);
+                self.goal(environment, &subgoal)
+            }
+            Goal::Implies(clauses, subgoal) => {
+                let environment = Environment::add_clauses(environment, clauses.iter().cloned());
+                self.goal(&environment, subgoal)
+            }
+            Goal::And(goal1, goal2) => self
+                .goal(environment, goal1)
+                .or_else(|| self.goal(environment, goal2)),
+            Goal::Leaf(LeafGoal::DomainGoal(DomainGoal::Holds(WhereClause::Implemented(
+                trait_ref,
+            )))) => self.implemented(environment, trait_ref),
+            Goal::Not(_) | Goal::Leaf(_) | Goal::CannotProve(()) => None,
+        }
+    }
+
+    fn implemented(
+        &mut self,
+        environment: &Arc<Environment>,
+        trait_ref: &TraitRef,
+    ) -> Option<FailureHint> {
+        let goal = self.infer.normalize_deep(trait_ref);
+        let domain_goal: DomainGoal = goal.clone().cast();
+        // The clauses of the program which are not those of an impl, like
+        // `Implemented(T: Foo) :- FromEnv(T: Foo)`, apply to any trait
+        // reference: they only hold through the hypotheses of the
+        // environment, which are looked at as they are.
+        let program = self.program;
+        let impl_clauses: Vec<(ItemId, &ProgramClause)> = program
+            .trait_impl_clauses
+            .get(&goal.trait_id)
+            .into_iter()
+            .flatten()
+            .flat_map(|(impl_id, range)| {
+                program.program_clauses[range.clone()]
+                    .iter()
+                    .map(move |clause| (*impl_id, clause))
+            })
+            .collect();
+        let clauses: Vec<ProgramClause> = environment
+            .candidate_clauses(&domain_goal)
+            .into_iter()
+            .chain(impl_clauses.iter().map(|&(_, clause)| clause))
+            .filter(|&clause| clause.could_match(&domain_goal))
+            .cloned()
+            .collect();
+        if clauses
+            .iter()
+            .any(|clause| self.applies(environment, &domain_goal, clause))
+        {
+            return None;
+        }
+
+        let mut opener = PlaceholderOpener {
+            infer: &mut self.infer,
+            vars: BTreeMap::new(),
+        };
+        let opened: DomainGoal = goal.fold_with(&mut opener, 0).unwrap().cast();
+        let vars = opener.vars;
+        if vars.is_empty() {
+            return None;
+        }
+
+        for &(would_match, clause) in &impl_clauses {
+            let impl_header = match implemented_header(clause) {
+                Some(impl_header) if clause.could_match(&opened) => impl_header,
+                _ => continue,
+            };
+            let snapshot = self.infer.snapshot();
+            let consequence = self.consequence(clause);
+            let hint = match self.infer.unify(environment, &opened, &consequence) {
+                Ok(_) => vars.iter().find_map(|(&placeholder, var)| {
+                    match self.infer.normalize_deep(&var.to_ty()) {
+                        Ty::Var(_) => None,
+                        value => Some(FailureHint::RigidPlaceholder {
+                            goal: goal.clone(),
+                            placeholder,
+                            value,
+                            would_match,
+                            impl_header: impl_header.clone(),
+                        }),
+                    }
+                }),
+                Err(NoSolution) => None,
+            };
+            self.infer.rollback_to(snapshot);
+            if hint.is_some() {
+                return hint;
+            }
+        }
+        None
+    }
+
+    /// True if `clause` applies to `goal`, whether or not its
+    /// conditions hold.
+    fn applies(
+        &mut self,
+        environment: &Arc<Environment>,
+        goal: &DomainGoal,
+        clause: &ProgramClause,
+    ) -> bool {
+        let snapshot = self.infer.snapshot();
+        let consequence = self.consequence(clause);
+        let applies = self.infer.unify(environment, goal, &consequence).is_ok();
+        self.infer.rollback_to(snapshot);
+        applies
+    }
+
+    fn consequence(&mut self, clause: &ProgramClause) -> DomainGoal {
+        match clause {
+            ProgramClause::Implies(implication) => implication.consequence.clone(),
+            ProgramClause::ForAll(implication) => {
+                self.infer
+                    .instantiate_binders_existentially(implication)
+                    .consequence
+            }
+        }
+    }
+}
+
+/// The trait reference of the impl `clause` is compiled from, under the
+/// binders of the impl, if `clause` is the one that says the impl holds.
+fn implemented_header(clause: &ProgramClause) -> Option<Binders<TraitRef>> {
+    let (binders, implication) = match clause {
+        ProgramClause::Implies(implication) => (&[][..], implication),
+        ProgramClause::ForAll(implication) => (&implication.binders[..], &implication.value),
+    };
+    match &implication.consequence {
+        DomainGoal::Holds(WhereClause::Implemented(trait_ref)) => Some(Binders {
+            binders: binders.to_vec(),
+            value: trait_ref.clone(),
+        }),
+        _ => None,
+    }
+}
+
+/// Replaces the type placeholders of a value with inference variables,
+/// one for each, in the universe of the placeholder.
+struct PlaceholderOpener<'q> {
+    infer: &'q mut InferenceTable,
+    vars: BTreeMap<PlaceholderIndex, InferenceVariable>,
+}
+
+impl<'q> DefaultTypeFolder for PlaceholderOpener<'q> {}
+
+impl<'q> IdentityExistentialFolder for PlaceholderOpener<'q> {}
+
+impl<'q> UniversalFolder for PlaceholderOpener<'q> {
+    fn fold_free_universal_ty(
+        &mut self,
+        placeholder: PlaceholderIndex,
+        binders: usize,
+    ) -> Fallible<Ty> {
+        let infer = &mut self.infer;
+        Ok(self
+            .vars
+            .entry(placeholder)
+            .or_insert_with(|| infer.new_variable(placeholder.ui))
+            .to_ty()
+            .up_shift(binders))
+    }
+
+    fn fold_free_universal_lifetime(
+        &mut self,
+        placeholder: PlaceholderIndex,
+        _binders: usize,
+    ) -> Fallible<Lifetime> {
+        Ok(placeholder.to_lifetime())
+    }
+
+    fn fold_free_universal_const(
+        &mut self,
+        placeholder: PlaceholderIndex,
+        _binders: usize,
+    ) -> Fallible<Const> {
+        Ok(placeholder.to_const())
+    }
+}
@@ -3,26 +3,30 @@ use ir::*;
 use fold::Fold;
 use fold::shift::Shift;
 
-crate mod canonicalize;
-crate mod ucanonicalize;
+pub(crate) mod canonicalize;
+pub(crate) mod ucanonicalize;
 mod normalize_deep;
-crate mod instantiate;
+pub(crate) mod instantiate;
 mod invert;
-crate mod unify;
-crate mod var;
+pub(crate) mod unify;
+pub(crate) mod var;
 #[cfg(test)]
 mod test;
 
 use self::var::*;
+pub(crate) use self::var::InferenceVariable;
 
+/// The state of type inference: the inference variables, and what is
+/// known about them, as well as the universes of the placeholders
+/// created so far (see `new_universe`).
 #[derive(Clone)]
-crate struct InferenceTable {
+pub struct InferenceTable {
     unify: ena::UnificationTable<InferenceVariable>,
     vars: Vec<InferenceVariable>,
     max_universe: UniverseIndex,
 }
 
-crate struct InferenceSnapshot {
+pub(crate) struct InferenceSnapshot {
     unify_snapshot: ena::Snapshot<InferenceVariable>,
     max_universe: UniverseIndex,
     vars: Vec<InferenceVariable>,
@@ -32,7 +36,7 @@ pub(in solve) type ParameterInferenceVariable = ParameterKind<InferenceVariable>
 
 impl InferenceTable {
     /// Create an empty inference table with no variables.
-    crate fn new() -> Self {
+    pub fn new() -> Self {
         InferenceTable {
             unify: ena::UnificationTable::new(),
             vars: vec![],
@@ -47,7 +51,7 @@ impl InferenceTable {
     /// the substitution mapping from each canonical binder to its
     /// corresponding existential variable, along with the
     /// instantiated result.
-    crate fn from_canonical<T>(
+    pub(crate) fn from_canonical<T>(
         num_universes: usize,
         canonical: &Canonical<T>,
     ) -> (Self, Substitution, T)
@@ -85,15 +89,34 @@ impl InferenceTable {
     /// others created within this inference table. This universe is
     /// able to see all previously created universes (though hopefully
     /// it is only brought into contact with its logical *parents*).
-    crate fn new_universe(&mut self) -> UniverseIndex {
+    ///
+    /// Placeholders are created in a universe of their own, after
+    /// which the variables created in earlier universes cannot be
+    /// unified with them: `exists<T> { forall<U> { T = U } }` does not
+    /// hold, while `forall<U> { exists<T> { T = U } }` does. See
+    /// `instantiate_binders_universally`, which creates the universe
+    /// along with the placeholders.
+    pub fn new_universe(&mut self) -> UniverseIndex {
         let u = self.max_universe.next();
         self.max_universe = u;
         debug!("new_universe: {:?}", u);
         u
     }
 
+    /// The universe for the placeholders of a binder of `num_binders`
+    /// parameters: a new one (see `new_universe`), unless the binder
+    /// binds nothing, in which case there are no placeholders to put in
+    /// it and the current maximum universe is returned.
+    pub(crate) fn new_universe_for(&mut self, num_binders: usize) -> UniverseIndex {
+        if num_binders == 0 {
+            self.max_universe
+        } else {
+            self.new_universe()
+        }
+    }
+
     /// Current maximum universe -- one that can see all existing names.
-    crate fn max_universe(&self) -> UniverseIndex {
+    pub(crate) fn max_universe(&self) -> UniverseIndex {
         self.max_universe
     }
 
@@ -113,7 +136,7 @@ impl InferenceTable {
     /// must respect a stack discipline (i.e., rollback or commit
     /// snapshots in reverse order of that with which they were
     /// created).
-    crate fn snapshot(&mut self) -> InferenceSnapshot {
+    pub(crate) fn snapshot(&mut self) -> InferenceSnapshot {
         let unify_snapshot = self.unify.snapshot();
         let vars = self.vars.clone();
         let max_universe = self.max_universe;
@@ -125,14 +148,14 @@ impl InferenceTable {
     }
 
     /// Restore the table to the state it had when the snapshot was taken.
-    crate fn rollback_to(&mut self, snapshot: InferenceSnapshot) {
+    pub(crate) fn rollback_to(&mut self, snapshot: InferenceSnapshot) {
         self.unify.rollback_to(snapshot.unify_snapshot);
         self.vars = snapshot.vars;
         self.max_universe = snapshot.max_universe;
     }
 
     /// Make permanent the changes made since the snapshot was taken.
-    crate fn commit(&mut self, snapshot: InferenceSnapshot) {
+    pub(crate) fn commit(&mut self, snapshot: InferenceSnapshot) {
         self.unify.commit(snapshot.unify_snapshot);
     }
 
@@ -142,36 +165,20 @@ impl InferenceTable {
     /// `binders` is the number of binders under which `leaf` appears;
     /// the return value will also be shifted accordingly so that it
     /// can appear under that same number of binders.
-    crate fn normalize_shallow(&mut self, leaf: &Ty, binders: usize) -> Option<Ty> {
-        leaf.var().and_then(|depth| {
-            if depth < binders {
-                None // bound variable, not an inference var
-            } else {
-                let var = InferenceVariable::from_depth(depth - binders);
-                match self.unify.probe_value(var) {
-                    InferenceValue::Unbound(_) => None,
-                    InferenceValue::Bound(ref val) => {
-                        let ty = val.as_ref().ty().unwrap();
-                        Some(ty.up_shift(binders))
-                    }
-                }
-            }
-        })
-    }
-
-    /// If `leaf` represents an inference variable `X`, and `X` is bound,
-    /// returns `Some(v)` where `v` is the value to which `X` is bound.
-    crate fn normalize_lifetime(&mut self, leaf: &Lifetime, binders: usize) -> Option<Lifetime> {
-        match *leaf {
-            Lifetime::Var(v) => {
-                if v < binders {
-                    return None;
-                }
-                let v1 = self.probe_lifetime_var(InferenceVariable::from_depth(v - binders))?;
-                Some(v1.up_shift(binders))
-            }
-            Lifetime::ForAll(_) => None,
-        }
+    pub(crate) fn normalize_shallow(&mut self, leaf: &Ty, binders: usize) -> Option<Ty> {
+        self.normalize_var(leaf, binders)
+    }
+
+    /// As `normalize_shallow`, but for a parameter of any kind.
+    pub(in solve) fn normalize_var<V>(&mut self, leaf: &V, binders: usize) -> Option<V>
+    where
+        V: InferenceParameterValue,
+    {
+        let depth = leaf.var_depth()?;
+        // A bound variable is not an inference var.
+        let free = DebruijnIndex::new(depth).shifted_out(binders)?;
+        let value = V::probe(self, InferenceVariable::from_depth(free.depth()))?;
+        Some(value.up_shift(binders))
     }
 
     /// Finds the type to which `var` is bound, returning `None` if it is not yet
@@ -183,24 +190,56 @@ impl InferenceTable {
     /// type. If this variable is of a different kind, then the
     /// function may panic.
     fn probe_ty_var(&mut self, var: InferenceVariable) -> Option<Ty> {
-        match self.unify.probe_value(var) {
-            InferenceValue::Unbound(_) => None,
-            InferenceValue::Bound(ref val) => Some(val.as_ref().ty().unwrap().clone()),
-        }
+        self.probe_var::<Ty>(var)
     }
 
-    /// Finds the lifetime to which `var` is bound, returning `None` if it is not yet
+    /// Finds the value to which `var` is bound, returning `None` if it is not yet
     /// bound.
     ///
     /// # Panics
     ///
-    /// This method is only valid for inference variables of kind
-    /// lifetime. If this variable is of a different kind, then the function may panic.
-    fn probe_lifetime_var(&mut self, var: InferenceVariable) -> Option<Lifetime> {
+    /// This method is only valid for inference variables of the kind
+    /// of `V`. If this variable is of a different kind, then the
+    /// function may panic.
+    fn probe_var<V: ParameterValue>(&mut self, var: InferenceVariable) -> Option<V> {
         match self.unify.probe_value(var) {
             InferenceValue::Unbound(_) => None,
-            InferenceValue::Bound(ref val) => Some(val.as_ref().lifetime().unwrap().clone()),
+            InferenceValue::Bound(ref val) => Some(V::from_parameter(val).unwrap().clone()),
+        }
+    }
+
+    /// The root of the unification class of `var`.
+    pub(crate) fn root_var(
+        &mut self,
+        var: ParameterInferenceVariable,
+    ) -> ParameterInferenceVariable {
+        var.map(|var| self.unify.find(var))
+    }
+
+    /// Which of `vars`, the distinct roots of unbound variables at some
+    /// earlier point, have been constrained since: bound, or unified
+    /// with one another. Being unified with other variables, like the
+    /// fresh ones of an instantiated answer, does not constrain a
+    /// variable.
+    pub(crate) fn constrained_vars(
+        &mut self,
+        vars: &[ParameterInferenceVariable],
+    ) -> Vec<ParameterInferenceVariable> {
+        let roots: Vec<_> = vars.iter().map(|var| self.unify.find(var.into_inner())).collect();
+        let mut sorted_roots = roots.clone();
+        sorted_roots.sort();
+        let mut constrained = vec![];
+        for (&var, &root) in vars.iter().zip(&roots) {
+            let bound = match self.unify.probe_value(root) {
+                InferenceValue::Unbound(_) => false,
+                InferenceValue::Bound(_) => true,
+            };
+            let first = sorted_roots.partition_point(|&other| other < root);
+            if bound || sorted_roots.get(first + 1) == Some(&root) {
+                constrained.push(var);
+            }
         }
+        constrained
     }
 
     /// Given an unbound variable, returns its universe.
@@ -216,9 +255,15 @@ impl InferenceTable {
     }
 }
 
+impl Default for InferenceTable {
+    fn default() -> Self {
+        InferenceTable::new()
+    }
+}
+
 impl Ty {
     /// If this is a `Ty::Var(d)`, returns `Some(d)` else `None`.
-    crate fn var(&self) -> Option<usize> {
+    pub(crate) fn var(&self) -> Option<usize> {
         if let Ty::Var(depth) = *self {
             Some(depth)
         } else {
@@ -228,10 +273,33 @@ impl Ty {
 }
 
 impl ParameterInferenceVariable {
-    crate fn to_parameter(self) -> Parameter {
-        match self {
-            ParameterKind::Ty(v) => ParameterKind::Ty(v.to_ty()),
-            ParameterKind::Lifetime(v) => ParameterKind::Lifetime(v.to_lifetime()),
-        }
+    pub(crate) fn to_parameter(self) -> Parameter {
+        map_kind!(self, |v, V| v.to_value::<V>())
+    }
+}
+
+/// A `ParameterValue` that inference variables of its kind can be bound
+/// to in the `InferenceTable`.
+pub(in solve) trait InferenceParameterValue: ParameterValue {
+    /// Finds the value to which `var` is bound, returning `None` if it is
+    /// not yet bound.
+    fn probe(table: &mut InferenceTable, var: InferenceVariable) -> Option<Self>;
+}
+
+impl InferenceParameterValue for Ty {
+    fn probe(table: &mut InferenceTable, var: InferenceVariable) -> Option<Self> {
+        table.probe_ty_var(var)
+    }
+}
+
+impl InferenceParameterValue for Lifetime {
+    fn probe(table: &mut InferenceTable, var: InferenceVariable) -> Option<Self> {
+        table.probe_var(var)
+    }
+}
+
+impl InferenceParameterValue for Const {
+    fn probe(table: &mut InferenceTable, var: InferenceVariable) -> Option<Self> {
+        table.probe_var(var)
     }
 }
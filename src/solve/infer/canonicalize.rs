@@ -1,10 +1,14 @@
 use fallible::*;
-use fold::{DefaultTypeFolder, ExistentialFolder, Fold, UniversalFolder};
+use fold::{
+    DefaultTypeFolder, ExistentialFolder, Fold, IdentityUniversalFolder, UniversalFolder,
+};
 use fold::shift::Shift;
 use ir::*;
 use std::cmp::max;
 
-use super::{InferenceTable, InferenceVariable, ParameterInferenceVariable};
+use super::{
+    InferenceParameterValue, InferenceTable, InferenceVariable, ParameterInferenceVariable,
+};
 
 impl InferenceTable {
     /// Given a value `value` with variables in it, replaces those variables
@@ -25,7 +29,7 @@ impl InferenceTable {
     ///
     /// A substitution mapping from the free variables to their re-bound form is
     /// also returned.
-    crate fn canonicalize<T: Fold>(&mut self, value: &T) -> Canonicalized<T::Result> {
+    pub(crate) fn canonicalize<T: Fold>(&mut self, value: &T) -> Canonicalized<T::Result> {
         debug!("canonicalize({:#?})", value);
         let mut q = Canonicalizer {
             table: self,
@@ -48,9 +52,9 @@ impl InferenceTable {
 }
 
 #[derive(Debug)]
-crate struct Canonicalized<T> {
+pub(crate) struct Canonicalized<T> {
     /// The canonicalized result.
-    crate quantified: Canonical<T>,
+    pub(crate) quantified: Canonical<T>,
 
     /// The free existential variables, along with the universes they inhabit.
     pub(in solve) free_vars: Vec<ParameterInferenceVariable>,
@@ -60,6 +64,123 @@ crate struct Canonicalized<T> {
     max_universe: UniverseIndex,
 }
 
+impl<T> Canonicalized<T> {
+    /// Given an (unbound) inference variable, as a parameter, returns
+    /// the index of the canonical variable that replaced it, or `None`
+    /// if it does not appear in the canonicalized value.
+    pub(crate) fn canonical_index_of(&self, var: &Parameter) -> Option<usize> {
+        self.free_vars
+            .iter()
+            .position(|free_var| free_var.to_parameter() == *var)
+    }
+}
+
+impl Canonical<ConstrainedSubst> {
+    /// Renames the variables of an answer, of all kinds, so that two
+    /// answers which are the same up to the names of their variables
+    /// are the same, syntactically: answers are compared and hashed as
+    /// they are, to find duplicates. `canonicalize` names the variables
+    /// in the order in which it meets them, but the constraints are in
+    /// the order in which they were imposed, until they are normalized.
+    ///
+    /// The variables are renamed in the order in which they first
+    /// appear in the substitution, then in the constraints. As sorting
+    /// the constraints depends on the names of their variables, they
+    /// are first sorted by their shape, those names aside, and this is
+    /// repeated until the answer no longer changes.
+    pub(crate) fn alpha_normalized(mut self) -> Self {
+        self.value.normalize_constraints();
+
+        // Only the variables which appear in nothing but the constraints
+        // are ever renamed, and it commonly takes a round or two; but
+        // constraints of the same shape may keep trading names.
+        for _ in 0..self.binders.len() {
+            let mut answer = self.clone();
+            answer
+                .value
+                .constraints
+                .sort_by_cached_key(|constraint| renumber_vars(constraint, &mut vec![]));
+            let mut answer = answer.renumber_binders();
+            answer.value.normalize_constraints();
+            if answer == self {
+                break;
+            }
+            self = answer;
+        }
+        self
+    }
+
+    fn renumber_binders(&self) -> Self {
+        let mut order = vec![];
+        let value = renumber_vars(&self.value, &mut order);
+        for binder in 0..self.binders.len() {
+            if !order.contains(&binder) {
+                order.push(binder);
+            }
+        }
+        Canonical {
+            value,
+            binders: order.into_iter().map(|binder| self.binders[binder]).collect(),
+        }
+    }
+}
+
+/// `value`, with its free variables renumbered in the order in which
+/// they first appear in it, following those already in `order`, which
+/// gives the index each of them had before.
+fn renumber_vars<T: Fold>(value: &T, order: &mut Vec<usize>) -> T::Result {
+    value
+        .fold_with(&mut VarRenumberer { order }, 0)
+        .expect("VarRenumberer is infallible")
+}
+
+struct VarRenumberer<'o> {
+    order: &'o mut Vec<usize>,
+}
+
+impl<'o> VarRenumberer<'o> {
+    fn renumber<V: ParameterValue>(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<V> {
+        let position = match self.order.iter().position(|&var| var == depth.depth()) {
+            Some(position) => position,
+            None => {
+                self.order.push(depth.depth());
+                self.order.len() - 1
+            }
+        };
+        Ok(V::from_var(position + binders))
+    }
+}
+
+impl<'o> DefaultTypeFolder for VarRenumberer<'o> {}
+
+impl<'o> IdentityUniversalFolder for VarRenumberer<'o> {}
+
+impl<'o> ExistentialFolder for VarRenumberer<'o> {
+    fn fold_free_existential_ty(&mut self, depth: DebruijnIndex, binders: usize) -> Fallible<Ty> {
+        self.renumber(depth, binders)
+    }
+
+    fn fold_free_existential_lifetime(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Lifetime> {
+        self.renumber(depth, binders)
+    }
+
+    fn fold_free_existential_const(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Const> {
+        self.renumber(depth, binders)
+    }
+}
+
 struct Canonicalizer<'q> {
     table: &'q mut InferenceTable,
     free_vars: Vec<ParameterInferenceVariable>,
@@ -93,70 +214,85 @@ impl<'q> Canonicalizer<'q> {
 
 impl<'q> DefaultTypeFolder for Canonicalizer<'q> {}
 
-impl<'q> UniversalFolder for Canonicalizer<'q> {
-    fn fold_free_universal_ty(&mut self, universe: UniverseIndex, _binders: usize) -> Fallible<Ty> {
-        self.max_universe = max(self.max_universe, universe);
-        Ok(TypeName::ForAll(universe).to_ty())
+impl<'q> Canonicalizer<'q> {
+    fn canonicalize_free_universal<V: ParameterValue>(&mut self, universe: PlaceholderIndex) -> V {
+        self.max_universe = max(self.max_universe, universe.ui);
+        V::placeholder(universe)
     }
 
-    fn fold_free_universal_lifetime(
+    fn canonicalize_free_existential<V>(
         &mut self,
-        universe: UniverseIndex,
-        _binders: usize,
-    ) -> Fallible<Lifetime> {
-        self.max_universe = max(self.max_universe, universe);
-        Ok(universe.to_lifetime())
-    }
-}
-
-impl<'q> ExistentialFolder for Canonicalizer<'q> {
-    fn fold_free_existential_ty(&mut self, depth: usize, binders: usize) -> Fallible<Ty> {
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<V>
+    where
+        V: InferenceParameterValue,
+    {
         debug_heading!(
-            "fold_free_existential_ty(depth={:?}, binders={:?})",
+            "canonicalize_free_existential(depth={:?}, binders={:?})",
             depth,
             binders
         );
-        let var = InferenceVariable::from_depth(depth);
-        match self.table.probe_ty_var(var) {
-            Some(ty) => {
-                debug!("bound to {:?}", ty);
-                Ok(ty.fold_with(self, 0)?.up_shift(binders))
+        let var = InferenceVariable::from_depth(depth.depth());
+        match V::probe(self.table, var) {
+            Some(value) => {
+                debug!("bound to {:?}", value);
+                Ok(value.fold_with(self, 0)?.up_shift(binders))
             }
             None => {
                 // If this variable is not yet bound, find its
                 // canonical index `root_var` in the union-find table,
                 // and then map `root_var` to a fresh index that is
                 // unique to this quantification.
-                let free_var = ParameterKind::Ty(self.table.unify.find(var));
+                let free_var = V::kind(self.table.unify.find(var));
                 let position = self.add(free_var);
                 debug!("not yet unified: position={:?}", position);
-                Ok(InferenceVariable::from_depth(position + binders).to_ty())
+                Ok(InferenceVariable::from_depth(position + binders).to_value())
             }
         }
     }
+}
+
+impl<'q> UniversalFolder for Canonicalizer<'q> {
+    fn fold_free_universal_ty(&mut self, universe: PlaceholderIndex, _binders: usize) -> Fallible<Ty> {
+        Ok(self.canonicalize_free_universal(universe))
+    }
+
+    fn fold_free_universal_lifetime(
+        &mut self,
+        universe: PlaceholderIndex,
+        _binders: usize,
+    ) -> Fallible<Lifetime> {
+        Ok(self.canonicalize_free_universal(universe))
+    }
+
+    fn fold_free_universal_const(
+        &mut self,
+        universe: PlaceholderIndex,
+        _binders: usize,
+    ) -> Fallible<Const> {
+        Ok(self.canonicalize_free_universal(universe))
+    }
+}
+
+impl<'q> ExistentialFolder for Canonicalizer<'q> {
+    fn fold_free_existential_ty(&mut self, depth: DebruijnIndex, binders: usize) -> Fallible<Ty> {
+        self.canonicalize_free_existential(depth, binders)
+    }
 
     fn fold_free_existential_lifetime(
         &mut self,
-        depth: usize,
+        depth: DebruijnIndex,
         binders: usize,
     ) -> Fallible<Lifetime> {
-        debug_heading!(
-            "fold_free_existential_lifetime(depth={:?}, binders={:?})",
-            depth,
-            binders
-        );
-        let var = InferenceVariable::from_depth(depth);
-        match self.table.probe_lifetime_var(var) {
-            Some(l) => {
-                debug!("bound to {:?}", l);
-                Ok(l.fold_with(self, 0)?.up_shift(binders))
-            }
-            None => {
-                let free_var = ParameterKind::Lifetime(self.table.unify.find(var));
-                let position = self.add(free_var);
-                debug!("not yet unified: position={:?}", position);
-                Ok(InferenceVariable::from_depth(position + binders).to_lifetime())
-            }
-        }
+        self.canonicalize_free_existential(depth, binders)
+    }
+
+    fn fold_free_existential_const(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Const> {
+        self.canonicalize_free_existential(depth, binders)
     }
 }
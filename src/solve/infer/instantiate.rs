@@ -13,11 +13,16 @@ impl InferenceTable {
         U: IntoIterator<Item = ParameterKind<UniverseIndex>>,
     {
         debug!("instantiate(arg={:?})", arg);
-        let vars: Vec<_> = universes
+        let vars: SubstParameters = universes
             .into_iter()
             .map(|param_kind| self.parameter_kind_to_parameter(param_kind))
             .collect();
         debug!("instantiate: vars={:?}", vars);
+
+        // Most canonical values have no variables to instantiate.
+        if vars.is_empty() {
+            return arg.clone_result();
+        }
         let mut instantiator = Instantiator { vars };
         arg.fold_with(&mut instantiator, 0).expect("")
     }
@@ -26,12 +31,7 @@ impl InferenceTable {
         &mut self,
         param_kind: ParameterKind<UniverseIndex>,
     ) -> Parameter {
-        match param_kind {
-            ParameterKind::Ty(ui) => ParameterKind::Ty(self.new_variable(ui).to_ty()),
-            ParameterKind::Lifetime(ui) => {
-                ParameterKind::Lifetime(self.new_variable(ui).to_lifetime())
-            }
-        }
+        map_kind!(param_kind, |ui, V| self.new_variable(ui).to_value::<V>())
     }
 
     /// Given the binders from a canonicalized value C, returns a
@@ -39,7 +39,7 @@ impl InferenceTable {
     /// inference variable. This substitution can then be applied to
     /// C, which would be equivalent to
     /// `self.instantiate_canonical(v)`.
-    crate fn fresh_subst(&mut self, binders: &[ParameterKind<UniverseIndex>]) -> Substitution {
+    pub(crate) fn fresh_subst(&mut self, binders: &[ParameterKind<UniverseIndex>]) -> Substitution {
         Substitution {
             parameters: binders
                 .iter()
@@ -52,7 +52,7 @@ impl InferenceTable {
     }
 
     /// Variant on `instantiate` that takes a `Canonical<T>`.
-    crate fn instantiate_canonical<T>(&mut self, bound: &Canonical<T>) -> T::Result
+    pub(crate) fn instantiate_canonical<T>(&mut self, bound: &Canonical<T>) -> T::Result
     where
         T: Fold + Debug,
     {
@@ -64,7 +64,7 @@ impl InferenceTable {
     /// `binders`. This is used to apply a universally quantified
     /// clause like `forall X, 'Y. P => Q`. Here the `binders`
     /// argument is referring to `X, 'Y`.
-    crate fn instantiate_in<U, T>(
+    pub(crate) fn instantiate_in<U, T>(
         &mut self,
         universe: UniverseIndex,
         binders: U,
@@ -79,7 +79,7 @@ impl InferenceTable {
 
     /// Variant on `instantiate_in` that takes a `Binders<T>`.
     #[allow(non_camel_case_types)]
-    crate fn instantiate_binders_existentially<T>(
+    pub(crate) fn instantiate_binders_existentially<T>(
         &mut self,
         arg: &impl BindersAndValue<Output = T>,
     ) -> T::Result
@@ -91,8 +91,12 @@ impl InferenceTable {
         self.instantiate_in(max_universe, binders.iter().cloned(), value)
     }
 
+    /// Instantiates `arg` with fresh placeholders, in a new universe
+    /// (see `new_universe`), as when proving `forall<T> { ... }`: the
+    /// placeholders stand for any value at all, and are only equal to
+    /// themselves.
     #[allow(non_camel_case_types)]
-    crate fn instantiate_binders_universally<T>(
+    pub fn instantiate_binders_universally<T>(
         &mut self,
         arg: &impl BindersAndValue<Output = T>,
     ) -> T::Result
@@ -100,27 +104,20 @@ impl InferenceTable {
         T: Fold,
     {
         let (binders, value) = arg.split();
+        let ui = self.new_universe_for(binders.len());
         let parameters: Vec<_> = binders
             .iter()
-            .map(|pk| {
-                let new_universe = self.new_universe();
-                match *pk {
-                    ParameterKind::Lifetime(()) => {
-                        let lt = Lifetime::ForAll(new_universe);
-                        ParameterKind::Lifetime(lt)
-                    }
-                    ParameterKind::Ty(()) => ParameterKind::Ty(Ty::Apply(ApplicationTy {
-                        name: TypeName::ForAll(new_universe),
-                        parameters: vec![],
-                    })),
-                }
+            .enumerate()
+            .map(|(idx, pk)| {
+                let placeholder = PlaceholderIndex { ui, idx };
+                map_kind!(*pk, |(), V| V::placeholder(placeholder))
             })
             .collect();
         Subst::apply(&parameters, value)
     }
 }
 
-crate trait BindersAndValue {
+pub trait BindersAndValue {
     type Output;
 
     fn split(&self) -> (&[ParameterKind<()>], &Self::Output);
@@ -143,7 +140,7 @@ impl<'a, T> BindersAndValue for (&'a Vec<ParameterKind<()>>, &'a T) {
 }
 
 struct Instantiator {
-    vars: Vec<Parameter>,
+    vars: SubstParameters,
 }
 
 impl DefaultTypeFolder for Instantiator {}
@@ -151,27 +148,41 @@ impl DefaultTypeFolder for Instantiator {}
 /// When we encounter a free variable (of any kind) with index
 /// `i`, we want to map anything in the first N binders to
 /// `self.vars[i]`. Everything else stays intact, but we have to
-/// subtract `self.vars.len()` to account for the binders we are
-/// instantiating.
-impl ExistentialFolder for Instantiator {
-    fn fold_free_existential_ty(&mut self, depth: usize, binders: usize) -> Fallible<Ty> {
-        if depth < self.vars.len() {
-            Ok(self.vars[depth].assert_ty_ref().up_shift(binders))
-        } else {
-            Ok(Ty::Var(depth + binders - self.vars.len())) // see comment above
+/// shift it out of the binders we are instantiating.
+impl Instantiator {
+    fn instantiate_free_existential<V: ParameterValue>(
+        &self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> V {
+        match depth.shifted_out(self.vars.len()) {
+            Some(free) => V::from_var(free.shifted_in(binders).depth()),
+            None => V::from_parameter(&self.vars[depth.depth()])
+                .unwrap()
+                .up_shift(binders),
         }
     }
+}
+
+impl ExistentialFolder for Instantiator {
+    fn fold_free_existential_ty(&mut self, depth: DebruijnIndex, binders: usize) -> Fallible<Ty> {
+        Ok(self.instantiate_free_existential(depth, binders))
+    }
 
     fn fold_free_existential_lifetime(
         &mut self,
-        depth: usize,
+        depth: DebruijnIndex,
         binders: usize,
     ) -> Fallible<Lifetime> {
-        if depth < self.vars.len() {
-            Ok(self.vars[depth].assert_lifetime_ref().up_shift(binders))
-        } else {
-            Ok(Lifetime::Var(depth + binders - self.vars.len())) // see comment above
-        }
+        Ok(self.instantiate_free_existential(depth, binders))
+    }
+
+    fn fold_free_existential_const(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Const> {
+        Ok(self.instantiate_free_existential(depth, binders))
     }
 }
 
@@ -70,7 +70,7 @@ impl InferenceTable {
     /// `?T: Clone` in the case where `?T = Vec<i32>`. The current
     /// version would delay processing the negative goal (i.e., return
     /// `None`) until the second unification has occurred.)
-    crate fn invert<T>(&mut self, value: &T) -> Option<T::Result>
+    pub(crate) fn invert<T>(&mut self, value: &T) -> Option<T::Result>
     where
         T: Fold<Result = T>,
     {
@@ -97,8 +97,9 @@ impl InferenceTable {
 
 struct Inverter<'q> {
     table: &'q mut InferenceTable,
-    inverted_ty: HashMap<UniverseIndex, InferenceVariable>,
-    inverted_lifetime: HashMap<UniverseIndex, InferenceVariable>,
+    inverted_ty: HashMap<PlaceholderIndex, InferenceVariable>,
+    inverted_lifetime: HashMap<PlaceholderIndex, InferenceVariable>,
+    inverted_const: HashMap<PlaceholderIndex, InferenceVariable>,
 }
 
 impl<'q> Inverter<'q> {
@@ -107,6 +108,7 @@ impl<'q> Inverter<'q> {
             table,
             inverted_ty: HashMap::new(),
             inverted_lifetime: HashMap::new(),
+            inverted_const: HashMap::new(),
         }
     }
 }
@@ -114,12 +116,12 @@ impl<'q> Inverter<'q> {
 impl<'q> DefaultTypeFolder for Inverter<'q> {}
 
 impl<'q> UniversalFolder for Inverter<'q> {
-    fn fold_free_universal_ty(&mut self, universe: UniverseIndex, binders: usize) -> Fallible<Ty> {
+    fn fold_free_universal_ty(&mut self, universe: PlaceholderIndex, binders: usize) -> Fallible<Ty> {
         let table = &mut self.table;
         Ok(
             self.inverted_ty
                 .entry(universe)
-                .or_insert_with(|| table.new_variable(universe))
+                .or_insert_with(|| table.new_variable(universe.ui))
                 .to_ty()
                 .up_shift(binders),
         )
@@ -127,30 +129,53 @@ impl<'q> UniversalFolder for Inverter<'q> {
 
     fn fold_free_universal_lifetime(
         &mut self,
-        universe: UniverseIndex,
+        universe: PlaceholderIndex,
         binders: usize,
     ) -> Fallible<Lifetime> {
         let table = &mut self.table;
         Ok(
             self.inverted_lifetime
                 .entry(universe)
-                .or_insert_with(|| table.new_variable(universe))
+                .or_insert_with(|| table.new_variable(universe.ui))
                 .to_lifetime()
                 .up_shift(binders),
         )
     }
+
+    fn fold_free_universal_const(
+        &mut self,
+        universe: PlaceholderIndex,
+        binders: usize,
+    ) -> Fallible<Const> {
+        let table = &mut self.table;
+        Ok(
+            self.inverted_const
+                .entry(universe)
+                .or_insert_with(|| table.new_variable(universe.ui))
+                .to_const()
+                .up_shift(binders),
+        )
+    }
 }
 
 impl<'q> ExistentialFolder for Inverter<'q> {
-    fn fold_free_existential_ty(&mut self, _depth: usize, _binders: usize) -> Fallible<Ty> {
+    fn fold_free_existential_ty(&mut self, _depth: DebruijnIndex, _binders: usize) -> Fallible<Ty> {
         panic!("should not be any existentials")
     }
 
     fn fold_free_existential_lifetime(
         &mut self,
-        _depth: usize,
+        _depth: DebruijnIndex,
         _binders: usize,
     ) -> Fallible<Lifetime> {
         panic!("should not be any existentials")
     }
+
+    fn fold_free_existential_const(
+        &mut self,
+        _depth: DebruijnIndex,
+        _binders: usize,
+    ) -> Fallible<Const> {
+        panic!("should not be any existentials")
+    }
 }
@@ -3,7 +3,7 @@ use fold::{DefaultTypeFolder, ExistentialFolder, Fold, IdentityUniversalFolder};
 use fold::shift::Shift;
 use ir::*;
 
-use super::{InferenceTable, InferenceVariable};
+use super::{InferenceParameterValue, InferenceTable, InferenceVariable};
 
 impl InferenceTable {
     /// Given a value `value` with variables in it, replaces those variables
@@ -17,7 +17,7 @@ impl InferenceTable {
     /// See also `InferenceTable::canonicalize`, which -- during real
     /// processing -- is often used to capture the "current state" of
     /// variables.
-    crate fn normalize_deep<T: Fold>(&mut self, value: &T) -> T::Result {
+    pub(crate) fn normalize_deep<T: Fold>(&mut self, value: &T) -> T::Result {
         value
             .fold_with(&mut DeepNormalizer { table: self }, 0)
             .unwrap()
@@ -32,24 +32,37 @@ impl<'table> DefaultTypeFolder for DeepNormalizer<'table> {}
 
 impl<'table> IdentityUniversalFolder for DeepNormalizer<'table> {}
 
-impl<'table> ExistentialFolder for DeepNormalizer<'table> {
-    fn fold_free_existential_ty(&mut self, depth: usize, binders: usize) -> Fallible<Ty> {
-        let var = InferenceVariable::from_depth(depth);
-        match self.table.probe_ty_var(var) {
-            Some(ty) => Ok(ty.fold_with(self, 0)?.up_shift(binders)),
-            None => Ok(InferenceVariable::from_depth(depth + binders).to_ty()),
+impl<'table> DeepNormalizer<'table> {
+    fn normalize_free_existential<V>(&mut self, depth: DebruijnIndex, binders: usize) -> Fallible<V>
+    where
+        V: InferenceParameterValue,
+    {
+        let var = InferenceVariable::from_depth(depth.depth());
+        match V::probe(self.table, var) {
+            Some(value) => Ok(value.fold_with(self, 0)?.up_shift(binders)),
+            None => Ok(InferenceVariable::from_depth(depth.shifted_in(binders).depth()).to_value()),
         }
     }
+}
+
+impl<'table> ExistentialFolder for DeepNormalizer<'table> {
+    fn fold_free_existential_ty(&mut self, depth: DebruijnIndex, binders: usize) -> Fallible<Ty> {
+        self.normalize_free_existential(depth, binders)
+    }
 
     fn fold_free_existential_lifetime(
         &mut self,
-        depth: usize,
+        depth: DebruijnIndex,
         binders: usize,
     ) -> Fallible<Lifetime> {
-        let var = InferenceVariable::from_depth(depth);
-        match self.table.probe_lifetime_var(var) {
-            Some(l) => Ok(l.fold_with(self, 0)?.up_shift(binders)),
-            None => Ok(InferenceVariable::from_depth(depth + binders).to_lifetime()),
-        }
+        self.normalize_free_existential(depth, binders)
+    }
+
+    fn fold_free_existential_const(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Const> {
+        self.normalize_free_existential(depth, binders)
     }
 }
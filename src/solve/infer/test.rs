@@ -1,10 +1,18 @@
+use cast::Cast;
+use chalk_parse;
+use errors::Result;
 use fallible::*;
 use fold::*;
+use ir::lowering::LowerPeeledGoal;
 use super::*;
+use super::ucanonicalize::UCanonicalized;
 use super::unify::UnificationResult;
+use solve::{SolveResultApplied, SolverChoice};
+use std::sync::Arc;
+use test_util::*;
 
 impl InferenceTable {
-    crate fn normalize<T>(&mut self, value: &T) -> T::Result
+    pub(crate) fn normalize<T>(&mut self, value: &T) -> T::Result
     where
         T: Fold,
     {
@@ -19,9 +27,9 @@ struct Normalizer<'a> {
 impl<'q> DefaultTypeFolder for Normalizer<'q> {}
 
 impl<'q> ExistentialFolder for Normalizer<'q> {
-    fn fold_free_existential_ty(&mut self, depth: usize, binders: usize) -> Fallible<Ty> {
+    fn fold_free_existential_ty(&mut self, depth: DebruijnIndex, binders: usize) -> Fallible<Ty> {
         assert_eq!(binders, 0);
-        let var = InferenceVariable::from_depth(depth);
+        let var = InferenceVariable::from_depth(depth.depth());
         match self.table.probe_ty_var(var) {
             Some(ty) => ty.fold_with(self, 0),
             None => Ok(var.to_ty()),
@@ -30,11 +38,20 @@ impl<'q> ExistentialFolder for Normalizer<'q> {
 
     fn fold_free_existential_lifetime(
         &mut self,
-        depth: usize,
+        depth: DebruijnIndex,
         binders: usize,
     ) -> Fallible<Lifetime> {
         assert_eq!(binders, 0);
-        Ok(InferenceVariable::from_depth(depth).to_lifetime())
+        Ok(InferenceVariable::from_depth(depth.depth()).to_lifetime())
+    }
+
+    fn fold_free_existential_const(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Const> {
+        assert_eq!(binders, 0);
+        Ok(InferenceVariable::from_depth(depth.depth()).to_const())
     }
 }
 
@@ -67,6 +84,24 @@ fn universe_error() {
         .unwrap_err();
 }
 
+#[test]
+fn const_type_mismatch() {
+    // `3u8` is not `3`, which is a `usize`, nor is it a variable bound to `3`.
+    let mut table = InferenceTable::new();
+    let environment0 = Environment::new();
+    let three_u8: Parameter = ParameterKind::Const(Const::Value(
+        ConstValue::from_u128(ConstTy::U8, 3).unwrap(),
+    ));
+    let three_usize = ParameterKind::Const(Const::Value(ConstValue::usize(3)));
+    table
+        .unify(&environment0, &three_u8, &three_usize)
+        .unwrap_err();
+
+    let n = ParameterKind::Const(table.new_variable(U0).to_const());
+    table.unify(&environment0, &n, &three_usize).unwrap();
+    table.unify(&environment0, &n, &three_u8).unwrap_err();
+}
+
 #[test]
 fn cycle_error() {
     // exists(A -> A = foo A) ---> error
@@ -265,6 +300,65 @@ fn quantify_ty_under_binder() {
     );
 }
 
+#[test]
+fn quantify_const_vars() {
+    let mut table = make_table();
+    let environment0 = Environment::new();
+
+    let v0 = table.new_variable(U0).to_ty();
+    let n1 = table.new_variable(U1).to_const();
+    let n2 = table.new_variable(U2).to_const();
+
+    table
+        .unify(&environment0, &n2, &Const::Value(ConstValue::usize(3)))
+        .unwrap();
+
+    let value = ty!(apply (item 0) (const n2.clone()) (const n1.clone()) (expr v0));
+    assert_eq!(
+        table.canonicalize(&value).quantified,
+        Canonical {
+            value: ty!(apply (item 0) (const Const::Value(ConstValue::usize(3)))
+                       (const Const::Var(0)) (var 1)),
+            binders: vec![ParameterKind::Const(U1), ParameterKind::Ty(U0)],
+        }
+    );
+    assert_eq!(
+        table.normalize_deep(&value),
+        ty!(apply (item 0) (const Const::Value(ConstValue::usize(3))) (const n1) (expr v0))
+    );
+}
+
+#[test]
+fn instantiate_const_binders() {
+    let mut table = make_table();
+    let _ = table.new_variable(U0);
+    let _ = table.new_variable(U0);
+
+    let canonical = Canonical {
+        value: ty!(apply (item 0) (const Const::Var(0)) (var 1)),
+        binders: vec![ParameterKind::Const(U0), ParameterKind::Ty(U0)],
+    };
+    assert_eq!(
+        table.instantiate_canonical(&canonical),
+        ty!(apply (item 0) (const Const::Var(2)) (var 3))
+    );
+    assert_eq!(
+        &table.fresh_subst(&canonical.binders).parameters[..],
+        &[ParameterKind::Const(Const::Var(4)), ParameterKind::Ty(Ty::Var(5))]
+    );
+
+    let binders = Binders {
+        binders: vec![ParameterKind::Const(()), ParameterKind::Ty(())],
+        value: canonical.value,
+    };
+    let ui = UniverseIndex { counter: 3 };
+    assert_eq!(
+        table.instantiate_binders_universally(&binders),
+        ty!(apply (item 0) (const Const::ForAll(PlaceholderIndex { ui, idx: 0 }))
+            (expr PlaceholderIndex { ui, idx: 1 }.to_ty()))
+    );
+}
+
 #[test]
 fn lifetime_constraint_indirect() {
     let mut table = InferenceTable::new();
@@ -276,24 +370,602 @@ fn lifetime_constraint_indirect() {
     let environment0 = Environment::new();
 
     // Here, we unify '?1 (the lifetime variable in universe 1) with
-    // '!1.
+    // '!1_0.
     let t_a = ty!(apply (item 0) (lifetime (skol 1)));
     let t_b = ty!(apply (item 0) (lifetime (var 1)));
-    let UnificationResult { goals, constraints } = table.unify(&environment0, &t_a, &t_b).unwrap();
+    let UnificationResult { goals, constraints, .. } = table.unify(&environment0, &t_a, &t_b).unwrap();
     assert!(goals.is_empty());
     assert!(constraints.is_empty());
 
     // Here, we try to unify `?0` (the type variable in universe 0)
     // with something that involves `'?1`. Since `'?1` has been
-    // unified with `'!1`, and `'!1` is not visible from universe 0,
-    // we will replace `'!1` with a new variable `'?2` and introduce a
+    // unified with `'!1_0`, and `'!1_0` is not visible from universe 0,
+    // we will replace `'!1_0` with a new variable `'?2` and introduce a
     // (likely unsatisfiable) constraint relating them.
     let t_c = ty!(var 0);
-    let UnificationResult { goals, constraints } = table.unify(&environment0, &t_c, &t_b).unwrap();
+    let UnificationResult { goals, constraints, .. } = table.unify(&environment0, &t_c, &t_b).unwrap();
     assert!(goals.is_empty());
     assert_eq!(constraints.len(), 1);
     assert_eq!(
         format!("{:?}", constraints[0]),
-        "InEnvironment { environment: Env([]), goal: \'?2 == \'!1 }",
+        "InEnvironment { environment: Env([]), goal: \'?2 == \'!1_0 }",
+    );
+}
+
+#[test]
+fn outlives_constraints_survive_canonicalization() {
+    let mut table = make_table();
+    let t = table.new_variable(U0);
+    let l = table.new_variable(U0);
+
+    let environment0 = Environment::new();
+    let t_a = ty!(apply (item 0) (lifetime (var 1)));
+    let UnificationResult { goals, constraints, .. } =
+        table.unify(&environment0, &t.to_ty(), &t_a).unwrap();
+    assert!(goals.is_empty());
+    assert!(constraints.is_empty());
+
+    // `?0: '?1` and `'?1: '!2_0`, where `?0 = Foo<'?1>`.
+    let constraints = vec![
+        InEnvironment::new(&environment0, Constraint::TypeOutlives(t.to_ty(), l.to_lifetime())),
+        InEnvironment::new(&environment0, Constraint::LifetimeOutlives(l.to_lifetime(), lifetime!(skol 2))),
+    ];
+    let canonical = table.canonicalize(&constraints).quantified;
+    assert_eq!(
+        canonical,
+        Canonical {
+            value: vec![
+                InEnvironment::new(
+                    &environment0,
+                    Constraint::TypeOutlives(ty!(apply (item 0) (lifetime (var 0))), lifetime!(var 0)),
+                ),
+                InEnvironment::new(
+                    &environment0,
+                    Constraint::LifetimeOutlives(lifetime!(var 0), lifetime!(skol 2)),
+                ),
+            ],
+            binders: vec![ParameterKind::Lifetime(U0)],
+        }
+    );
+
+    // Mapping the constraints back into another table, as done with
+    // the answers of a subgoal, gives the same constraints again.
+    let mut other = make_table();
+    let _ = other.new_variable(U0);
+    let instantiated = other.instantiate_canonical(&canonical);
+    assert_eq!(other.canonicalize(&instantiated).quantified, canonical);
+}
+
+#[test]
+fn instantiating_nothing_only_clones() {
+    use test_util::alloc::count_allocations;
+
+    let canonical = Canonical {
+        value: ty!(apply (item 0) (apply (item 1)) (apply (item 2)) (apply (item 3))
+                   (apply (item 4)) (apply (item 5))),
+        binders: vec![],
+    };
+    let (_, cloning) = count_allocations(|| canonical.value.clone());
+
+    let mut table = make_table();
+    let (instantiated, allocations) =
+        count_allocations(|| table.instantiate_canonical(&canonical));
+    assert_eq!(instantiated, canonical.value);
+    assert_eq!(allocations, cloning);
+}
+
+fn goal_program() -> Program {
+    parse_and_lower_program(
+        "struct Foo<T> { } struct Bar { } trait Baz<T> { }",
+        SolverChoice::default(),
+    ).unwrap()
+}
+
+#[test]
+fn relate_alpha_equivalent_goals() {
+    let program = goal_program();
+    let mut table = InferenceTable::new();
+    let environment0 = Environment::new();
+
+    let a = parse_and_lower_goal(&program, "forall<T> { Foo<T>: Baz<T> }").unwrap();
+    let b = parse_and_lower_goal(&program, "forall<U> { Foo<U>: Baz<U> }").unwrap();
+    let UnificationResult { goals, constraints, .. } = table.unify(&environment0, &a, &b).unwrap();
+    assert!(goals.is_empty());
+    assert!(constraints.is_empty());
+
+    // forall<T> { Foo<?X>: Baz<T> } == forall<T> { Foo<Bar>: Baz<T> } binds `?X` to `Bar`
+    let c = parse_and_lower_goal(&program, "exists<X> { forall<T> { Foo<X>: Baz<T> } }").unwrap();
+    let c = match *c {
+        Goal::Quantified(QuantifierKind::Exists, ref subgoal) => {
+            table.instantiate_binders_existentially(subgoal)
+        }
+        _ => panic!("expected an `exists` goal"),
+    };
+    let d = parse_and_lower_goal(&program, "forall<T> { Foo<Bar>: Baz<T> }").unwrap();
+    table.unify(&environment0, &c, &d).unwrap();
+    assert_eq!(table.normalize_deep(&c), d);
+}
+
+#[test]
+fn relate_different_goals() {
+    let program = goal_program();
+    let mut table = InferenceTable::new();
+    let environment0 = Environment::new();
+
+    let assert_unrelated = |table: &mut InferenceTable, a: &Goal, b: &str| {
+        let b = parse_and_lower_goal(&program, b).unwrap();
+        table.unify(&environment0, a, &*b).unwrap_err();
+    };
+
+    let a = parse_and_lower_goal(&program, "forall<T> { Foo<T>: Baz<T> }").unwrap();
+    assert_unrelated(&mut table, &a, "exists<T> { Foo<T>: Baz<T> }");
+    assert_unrelated(&mut table, &a, "forall<T> { Foo<T>: Baz<Bar> }");
+    assert_unrelated(&mut table, &a, "forall<T, U> { Foo<T>: Baz<U> }");
+    assert_unrelated(&mut table, &a, "forall<T> { T: Baz<T> }");
+
+    let a = parse_and_lower_goal(&program, "forall<T> { Bar: Baz<Bar> }").unwrap();
+    assert_unrelated(&mut table, &a, "forall<'a> { Bar: Baz<Bar> }");
+
+    // `?X` cannot name the `T` bound inside the `forall`
+    let c = parse_and_lower_goal(&program, "exists<X> { forall<T> { Foo<X>: Baz<T> } }").unwrap();
+    let c = match *c {
+        Goal::Quantified(QuantifierKind::Exists, ref subgoal) => {
+            table.instantiate_binders_existentially(subgoal)
+        }
+        _ => panic!("expected an `exists` goal"),
+    };
+    assert_unrelated(&mut table, &c, "forall<T> { Foo<T>: Baz<T> }");
+}
+
+#[test]
+fn one_universe_per_binder() {
+    let program = goal_program();
+    let mut table = InferenceTable::new();
+
+    // All the parameters of a binder share a single universe, and are
+    // told apart by their index within it.
+    let goal = parse_and_lower_goal(&program, "forall<'a, 'b, 'c> { Bar: Baz<Bar> }").unwrap();
+    match *goal {
+        Goal::Quantified(QuantifierKind::ForAll, ref subgoal) => {
+            table.instantiate_binders_universally(subgoal);
+        }
+        _ => panic!("expected a `forall` goal"),
+    }
+    assert_eq!(table.max_universe(), U1);
+
+    // A binder that binds nothing needs no universe.
+    let binders = Binders {
+        binders: vec![],
+        value: ty!(apply (item 0)),
+    };
+    assert_eq!(table.instantiate_binders_universally(&binders), ty!(apply (item 0)));
+    assert_eq!(table.max_universe(), U1);
+}
+
+#[test]
+fn variable_occurrences() {
+    let program = parse_and_lower_program(
+        "struct Foo<T> { } trait Baz<'a, T, const N> { }",
+        SolverChoice::default(),
+    ).unwrap();
+    let goal = parse_and_lower_goal(
+        &program,
+        "exists<'a, T, const N> { Foo<T>: Baz<'a, T, N> }",
+    ).unwrap();
+    let canonical = goal.into_peeled_goal().canonical;
+
+    // Variables are numbered in order of first occurrence, so `T` comes first.
+    assert_eq!(
+        canonical.variable_occurrences(),
+        vec![
+            VariableInfo {
+                kind: ParameterKind::Ty(U0),
+                occurrences: 2,
+                first_occurrence: Some(0),
+            },
+            VariableInfo {
+                kind: ParameterKind::Lifetime(U0),
+                occurrences: 1,
+                first_occurrence: Some(1),
+            },
+            VariableInfo {
+                kind: ParameterKind::Const(U0),
+                occurrences: 1,
+                first_occurrence: Some(3),
+            },
+        ]
     );
+
+    let ty = ParameterKind::Ty(ty!(apply (item 0)));
+    let lifetime = ParameterKind::Lifetime(lifetime!(skol 1));
+    let konst = ParameterKind::Const(Const::Value(ConstValue::usize(3)));
+    let subst = |parameters: Vec<Parameter>| Substitution {
+        parameters: parameters.into_iter().collect(),
+    };
+
+    assert!(canonical.substitution_kinds_match(&subst(vec![
+        ty.clone(),
+        lifetime.clone(),
+        konst.clone(),
+    ])));
+    assert!(!canonical.substitution_kinds_match(&subst(vec![
+        lifetime.clone(),
+        ty.clone(),
+        konst.clone(),
+    ])));
+    assert!(!canonical.substitution_kinds_match(&subst(vec![ty, lifetime])));
+}
+
+fn peel(program: &Program, text: &str) -> Result<PeeledGoal> {
+    chalk_parse::parse_goal(text)?.lower_peeled(program)
+}
+
+#[test]
+fn universe_ascriptions() {
+    let program = parse_and_lower_program("trait Foo<T, U> { }", SolverChoice::default()).unwrap();
+    let foo = program.lookup("Foo").unwrap();
+    let u3 = UniverseIndex { counter: 3 };
+
+    // The example of `UniverseMap::map_universe_to_canonical`:
+    // `!U1: Foo<?X, !U3>`, where `?X` is in U2.
+    let peeled = peel(
+        &program,
+        "forall<A in U1> { forall<C in U3> { exists<X in U2> { A: Foo<X, C> } } }",
+    ).unwrap();
+    let goal = |a: UniverseIndex, c: UniverseIndex| -> Goal {
+        TraitRef {
+            trait_id: foo,
+            parameters: vec![
+                PlaceholderIndex { ui: a, idx: 0 }.to_ty().cast(),
+                ty!(var 0).cast(),
+                PlaceholderIndex { ui: c, idx: 0 }.to_ty().cast(),
+            ],
+        }.cast()
+    };
+
+    // The universe of `?X` is kept, so no universe is compressed.
+    assert_eq!(peeled.goal.universes, 4);
+    assert_eq!(peeled.goal.canonical.binders, vec![ParameterKind::Ty(U2)]);
+    assert_eq!(peeled.goal.canonical.value.goal, goal(U1, u3));
+
+    // The same goal, canonicalized by hand, maps back to the original
+    // universes.
+    let original = Canonical {
+        binders: vec![ParameterKind::Ty(U2)],
+        value: InEnvironment::new(&Environment::new(), goal(U1, u3)),
+    };
+    let mut table = InferenceTable::new();
+    let UCanonicalized { quantified, universes } = table.u_canonicalize(&original);
+    assert_eq!(quantified, peeled.goal);
+    let mapped_back = universes.map_from_canonical(&quantified.canonical);
+    assert_eq!(mapped_back.value, original.value);
+    assert_eq!(mapped_back.binders, vec![ParameterKind::Ty(U2)]);
+
+    // Ascribing the universes the binders would be in anyway changes nothing.
+    assert_eq!(
+        peel(&program, "forall<A in U1> { exists<X in U1> { A: Foo<X, A> } }").unwrap(),
+        peel(&program, "forall<A> { exists<X> { A: Foo<X, A> } }").unwrap(),
+    );
+}
+
+#[test]
+fn invalid_universe_ascriptions() {
+    let program = parse_and_lower_program("trait Foo<T, U> { }", SolverChoice::default()).unwrap();
+    let assert_invalid = |text: &str, reason: &str| {
+        let error = peel(&program, text).unwrap_err().to_string();
+        assert!(error.contains(reason), "unexpected error: {}", error);
+    };
+
+    assert_invalid(
+        "forall<A in U1> { forall<C in U1> { A: Foo<C, C> } }",
+        "a `forall` must create a universe later than those before it",
+    );
+    assert_invalid(
+        "forall<A> { exists<X in U2> { A: Foo<X, X> } }",
+        "an `exists` can only name universes created before it",
+    );
+    assert_invalid(
+        "forall<A in U1, C in U2> { A: Foo<C, C> }",
+        "all the binders of a quantifier live in the same universe",
+    );
+    assert_invalid("exists<X in V0> { X: Foo<X, X> }", "universes are named `U0`, `U1`, ...");
+    assert_invalid(
+        "forall<A> { A: Foo<A, A>, exists<X in U1> { X: Foo<X, X> } }",
+        "only the leading quantifiers of a peeled goal may have universes",
+    );
+
+    // Goals that are not peeled cannot have universes at all.
+    let error = parse_and_lower_goal(&program, "exists<X in U0> { X: Foo<X, X> }").unwrap_err();
+    assert!(error.to_string().contains("only the leading quantifiers of a peeled goal"));
+}
+
+#[test]
+fn composed_universe_maps_do_not_alias() {
+    let placeholder = |counter: usize| {
+        PlaceholderIndex { ui: UniverseIndex { counter }, idx: 0 }.to_ty()
+    };
+
+    // The outer table has the universes up to U3, but the value only
+    // mentions U1 and U3, which become U1' and U2'.
+    let mut outer_table = make_table();
+    let _ = outer_table.new_universe(); // U3
+    let outer = outer_table.u_canonicalize(&Canonical {
+        value: ty!(apply (item 0) (apply (skol 1)) (apply (skol 3))),
+        binders: vec![],
+    });
+    assert_eq!(outer.quantified.universes, 3);
+
+    // The value is then taken into an inner table, with the universes up
+    // to U2', and canonicalized again; it only mentions U1'.
+    let mut inner_table = make_table();
+    let inner = inner_table.u_canonicalize(&Canonical {
+        value: ty!(apply (item 0) (apply (skol 1))),
+        binders: vec![],
+    });
+    assert_eq!(inner.quantified.universes, 2);
+
+    // An answer comes back with a name from U2'', which neither map
+    // knows. Inventing a universe just past the ones the inner map has
+    // seen would give U2', which the outer map takes to U3: a universe
+    // that the answer has nothing to do with. It is mapped past all the
+    // universes of each table instead, keeping it apart from them.
+    let answer = ty!(apply (item 0) (apply (skol 1)) (apply (skol 2)));
+    let in_inner_table = inner.universes.map_from_canonical(&answer);
+    assert_eq!(
+        in_inner_table,
+        Ty::Apply(ApplicationTy {
+            name: TypeName::ItemId(ItemId { index: 0 }),
+            parameters: vec![placeholder(1).cast(), placeholder(3).cast()],
+        })
+    );
+    let in_outer_table = outer.universes.map_from_canonical(&in_inner_table);
+    assert_eq!(
+        in_outer_table,
+        Ty::Apply(ApplicationTy {
+            name: TypeName::ItemId(ItemId { index: 0 }),
+            parameters: vec![placeholder(1).cast(), placeholder(4).cast()],
+        })
+    );
+
+    // Invented universes keep their order, and stay above the others.
+    let answer = ty!(apply (item 0) (apply (skol 3)) (apply (skol 2)) (apply (skol 1)));
+    let mapped = outer.universes.map_from_canonical(&inner.universes.map_from_canonical(&answer));
+    assert_eq!(
+        mapped,
+        Ty::Apply(ApplicationTy {
+            name: TypeName::ItemId(ItemId { index: 0 }),
+            parameters: vec![placeholder(5).cast(), placeholder(4).cast(), placeholder(1).cast()],
+        })
+    );
+}
+
+/// Lowers `goal`, which must be of the form `exists<X> { .. }`, with
+/// `X` instantiated as a new variable of `table`.
+fn existential_goal(
+    program: &Program,
+    table: &mut InferenceTable,
+    goal: &str,
+) -> InEnvironment<Goal> {
+    let goal = parse_and_lower_goal(program, goal).unwrap();
+    let goal = match *goal {
+        Goal::Quantified(QuantifierKind::Exists, ref subgoal) => {
+            table.instantiate_binders_existentially(subgoal)
+        }
+        _ => panic!("expected an `exists` goal"),
+    };
+    InEnvironment::new(&Environment::new(), *goal)
+}
+
+fn solve_in_table_program() -> Program {
+    parse_and_lower_program(
+        "struct Foo { } struct Bar { } trait Baz { } trait Qux { }
+         impl Baz for Bar { } impl Qux for Foo { } impl Qux for Bar { }",
+        SolverChoice::default(),
+    ).unwrap()
+}
+
+#[test]
+fn solve_in_table_unique() {
+    let program = solve_in_table_program();
+    let env = Arc::new(program.environment());
+    let mut table = InferenceTable::new();
+
+    // `?X` is the only variable of the table.
+    let goal = existential_goal(&program, &mut table, "exists<X> { X: Baz }");
+    let x = InferenceVariable::from_depth(0);
+    assert_eq!(table.probe_ty_var(x), None);
+
+    let result = SolverChoice::default().solve_in_table(&env, &mut table, &goal);
+    match result {
+        Ok(SolveResultApplied::Unique {
+            constraints,
+            constrained_vars,
+        }) => {
+            assert!(constraints.value.is_empty());
+            assert_eq!(constrained_vars, vec![ParameterKind::Ty(x.to_ty())]);
+        }
+        _ => panic!("expected a unique solution, got {:?}", result),
+    }
+    assert!(table.probe_ty_var(x).is_some());
+    assert_eq!(
+        table.normalize(&goal.goal),
+        *parse_and_lower_goal(&program, "Bar: Baz").unwrap()
+    );
+}
+
+#[test]
+fn solve_in_table_ambiguous_or_failing() {
+    let program = solve_in_table_program();
+    let env = Arc::new(program.environment());
+    let mut table = InferenceTable::new();
+    let x = InferenceVariable::from_depth(0);
+
+    // Both `Foo` and `Bar` implement `Qux`, so `?X` stays unbound.
+    let goal = existential_goal(&program, &mut table, "exists<X> { X: Qux }");
+    let result = SolverChoice::default().solve_in_table(&env, &mut table, &goal);
+    match result {
+        Ok(SolveResultApplied::Ambiguous(_)) => {}
+        _ => panic!("expected an ambiguous solution, got {:?}", result),
+    }
+    assert_eq!(table.probe_ty_var(x), None);
+
+    // Neither does a goal which fails.
+    let goal = InEnvironment::new(
+        &Environment::new(),
+        Goal::And(
+            Box::new(goal.goal),
+            parse_and_lower_goal(&program, "Foo: Baz").unwrap(),
+        ),
+    );
+    assert_eq!(
+        SolverChoice::default().solve_in_table(&env, &mut table, &goal),
+        Err(NoSolution)
+    );
+    assert_eq!(table.probe_ty_var(x), None);
+}
+
+/// Only the variables which the solution pins down are reported as
+/// constrained: `?Z` is merely unified with a fresh variable.
+#[test]
+fn solve_in_table_constrained_vars() {
+    let program = parse_and_lower_program(
+        "struct u32 { } struct i32 { } struct Triple<A, B, C> { } trait Baz { }
+         impl<T> Baz for Triple<u32, i32, T> { }",
+        SolverChoice::default(),
+    ).unwrap();
+    let env = Arc::new(program.environment());
+    let mut table = InferenceTable::new();
+
+    let goal = existential_goal(&program, &mut table, "exists<X, Y, Z> { Triple<X, Y, Z>: Baz }");
+    let result = SolverChoice::default().solve_in_table(&env, &mut table, &goal);
+    match result {
+        Ok(SolveResultApplied::Unique {
+            constrained_vars, ..
+        }) => {
+            let x = InferenceVariable::from_depth(0);
+            let y = InferenceVariable::from_depth(1);
+            assert_eq!(
+                constrained_vars,
+                vec![ParameterKind::Ty(x.to_ty()), ParameterKind::Ty(y.to_ty())]
+            );
+        }
+        _ => panic!("expected a unique solution, got {:?}", result),
+    }
+    assert_eq!(table.probe_ty_var(InferenceVariable::from_depth(2)), None);
+}
+
+#[test]
+fn answers_in_table() {
+    let program = solve_in_table_program();
+    let env = Arc::new(program.environment());
+    let mut table = InferenceTable::new();
+    let x = InferenceVariable::from_depth(0);
+
+    // Each answer binds `?X` in its own copy of the table.
+    let goal = existential_goal(&program, &mut table, "exists<X> { X: Qux }");
+    let answers = SolverChoice::default().answers_in_table(&env, &table, &goal, 3);
+    let mut goals: Vec<_> = answers
+        .into_iter()
+        .map(|mut answer| {
+            assert!(!answer.ambiguous);
+            answer.table.normalize(&goal.goal)
+        })
+        .collect();
+    let mut expected = vec![
+        *parse_and_lower_goal(&program, "Bar: Qux").unwrap(),
+        *parse_and_lower_goal(&program, "Foo: Qux").unwrap(),
+    ];
+    goals.sort();
+    expected.sort();
+    assert_eq!(goals, expected);
+    assert_eq!(table.probe_ty_var(x), None);
+
+    // There are no more answers than asked for.
+    let answers = SolverChoice::default().answers_in_table(&env, &table, &goal, 1);
+    assert_eq!(answers.len(), 1);
+}
+
+#[test]
+fn probe_rollback() {
+    let mut table = InferenceTable::new();
+    let environment0 = Environment::new();
+    let a = table.new_variable(U0);
+    let b = table.new_variable(U0);
+    let c = table.new_variable(U0);
+    table
+        .unify(&environment0, &a.to_ty(), &ty!(apply (item 0)))
+        .unwrap();
+
+    // `?B` is unbound when probed, which must not keep it from being
+    // seen bound later on.
+    assert_eq!(table.probe_ty_var(a), Some(ty!(apply (item 0))));
+    assert_eq!(table.probe_ty_var(b), None);
+
+    let snapshot = table.snapshot();
+    table.unify(&environment0, &b.to_ty(), &c.to_ty()).unwrap();
+    table
+        .unify(&environment0, &c.to_ty(), &ty!(apply (item 1)))
+        .unwrap();
+    let d = table.new_variable(U0);
+    table
+        .unify(&environment0, &d.to_ty(), &ty!(apply (item 2)))
+        .unwrap();
+    assert_eq!(table.probe_ty_var(a), Some(ty!(apply (item 0))));
+    assert_eq!(table.probe_ty_var(b), Some(ty!(apply (item 1))));
+    assert_eq!(table.probe_ty_var(c), Some(ty!(apply (item 1))));
+    assert_eq!(table.probe_ty_var(d), Some(ty!(apply (item 2))));
+    table.rollback_to(snapshot);
+
+    // Only the bindings made before the snapshot remain, and the
+    // variable created in the snapshot is gone: a new one takes its
+    // index.
+    assert_eq!(table.probe_ty_var(a), Some(ty!(apply (item 0))));
+    assert_eq!(table.probe_ty_var(b), None);
+    assert_eq!(table.probe_ty_var(c), None);
+    let e = table.new_variable(U0);
+    assert_eq!(table.probe_ty_var(e), None);
+}
+
+#[test]
+fn probe_nested_snapshots() {
+    let mut table = InferenceTable::new();
+    let environment0 = Environment::new();
+    let a = table.new_variable(U0);
+    let b = table.new_variable(U0);
+
+    let outer = table.snapshot();
+    table
+        .unify(&environment0, &a.to_ty(), &ty!(apply (item 0)))
+        .unwrap();
+    assert_eq!(table.probe_ty_var(a), Some(ty!(apply (item 0))));
+
+    // What is bound in a committed snapshot is undone when an enclosing
+    // one is rolled back.
+    let inner = table.snapshot();
+    table
+        .unify(&environment0, &b.to_ty(), &ty!(apply (item 1)))
+        .unwrap();
+    assert_eq!(table.probe_ty_var(b), Some(ty!(apply (item 1))));
+    table.commit(inner);
+    assert_eq!(table.probe_ty_var(b), Some(ty!(apply (item 1))));
+
+    table.rollback_to(outer);
+    assert_eq!(table.probe_ty_var(a), None);
+    assert_eq!(table.probe_ty_var(b), None);
+
+    // And failed unifications, which roll back their own snapshot, do
+    // not undo what was bound before them.
+    table
+        .unify(&environment0, &a.to_ty(), &ty!(apply (item 0)))
+        .unwrap();
+    assert_eq!(table.probe_ty_var(a), Some(ty!(apply (item 0))));
+    table
+        .unify(
+            &environment0,
+            &ty!(apply (item 2) (expr b.to_ty()) (expr a.to_ty())),
+            &ty!(apply (item 2) (apply (item 1)) (apply (item 1))),
+        )
+        .unwrap_err();
+    assert_eq!(table.probe_ty_var(a), Some(ty!(apply (item 0))));
+    assert_eq!(table.probe_ty_var(b), None);
 }
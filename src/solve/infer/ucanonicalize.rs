@@ -1,14 +1,16 @@
 use fallible::*;
 use fold::{DefaultTypeFolder, Fold, IdentityExistentialFolder, UniversalFolder};
+use fold::shift::Shift;
 use ir::*;
 
 use super::InferenceTable;
 
 impl InferenceTable {
-    crate fn u_canonicalize<T: Fold>(&mut self, value0: &Canonical<T>) -> UCanonicalized<T::Result> {
+    pub(crate) fn u_canonicalize<T: Fold>(&mut self, value0: &Canonical<T>) -> UCanonicalized<T::Result> {
         debug!("u_canonicalize({:#?})", value0);
 
-        // First, find all the universes that appear in `value`.
+        // First, find all the universes that appear in `value`, and
+        // those of its variables (see `map_universe_to_canonical`).
         let mut universes = UniverseMap::new();
         value0
             .value
@@ -19,6 +21,10 @@ impl InferenceTable {
                 0,
             )
             .unwrap();
+        for binder in &value0.binders {
+            universes.add(binder.into_inner());
+        }
+        universes.reserve_up_to(self.max_universe);
 
         // Now re-map the universes found in value. We have to do this
         // in a second pass because it is only then that we know the
@@ -52,12 +58,12 @@ impl InferenceTable {
 }
 
 #[derive(Debug)]
-crate struct UCanonicalized<T> {
+pub(crate) struct UCanonicalized<T> {
     /// The canonicalized result.
-    crate quantified: UCanonical<T>,
+    pub(crate) quantified: UCanonical<T>,
 
     /// A map between the universes in `quantified` and the original universes
-    pub(in solve) universes: UniverseMap,
+    pub(crate) universes: UniverseMap,
 }
 
 /// Maps the universes found in the `u_canonicalize` result (the
@@ -66,17 +72,26 @@ crate struct UCanonicalized<T> {
 /// outside this module -- converts from "canonical" universes to the
 /// original (but see the `UMapToCanonical` folder).
 #[derive(Clone, Debug)]
-pub(in solve) struct UniverseMap {
+pub(crate) struct UniverseMap {
     /// A reverse map -- for each universe Ux that appears in
     /// `quantified`, the corresponding universe in the original was
     /// `universes[x]`.
     universes: Vec<UniverseIndex>,
+
+    /// The highest universe of the original space: not only of those
+    /// found in the value, but of the table it was taken from. The
+    /// universes invented by `map_universe_from_canonical` are put
+    /// above it, so that they cannot be any of the original universes
+    /// -- including those that are not in `universes`, but which a map
+    /// composed with this one may map to.
+    max_universe: UniverseIndex,
 }
 
 impl UniverseMap {
     fn new() -> Self {
         UniverseMap {
             universes: vec![UniverseIndex::root()],
+            max_universe: UniverseIndex::root(),
         }
     }
 
@@ -89,6 +104,13 @@ impl UniverseMap {
         if let Err(i) = self.universes.binary_search(&universe) {
             self.universes.insert(i, universe);
         }
+        self.max_universe = self.max_universe.max(universe);
+    }
+
+    /// Records that the original space has the universes up to
+    /// `universe`, whether or not they appear in the value.
+    fn reserve_up_to(&mut self, universe: UniverseIndex) {
+        self.max_universe = self.max_universe.max(universe);
     }
 
     /// Given a universe U that appeared in our original value, return
@@ -137,6 +159,13 @@ impl UniverseMap {
     /// versa). Moreover, since there are no universally bound names
     /// from U2 in the original query, there is no way we would have
     /// equated `?0` with such a name.
+    ///
+    /// It is not an approximation the answers can live with, though: a
+    /// lifetime that only appears in the constraints of an answer comes
+    /// back in U1, and `?X` would be moved there with it. So
+    /// `u_canonicalize` collects the universes of the binders too, as
+    /// `compact_universes` does, giving `exists<U2'> { !U1': Foo<?0,
+    /// !U3'> }`.
     fn map_universe_to_canonical(&self, universe: UniverseIndex) -> UniverseIndex {
         match self.universes.binary_search(&universe) {
             Ok(index) => UniverseIndex { counter: index },
@@ -158,22 +187,29 @@ impl UniverseMap {
         }
     }
 
+    /// Like `map_universe_to_canonical`, but only for the universes
+    /// that appear in the original value: `None` for any other.
+    pub(crate) fn canonical_universe_of(&self, universe: UniverseIndex) -> Option<UniverseIndex> {
+        self.universes
+            .binary_search(&universe)
+            .ok()
+            .map(|index| UniverseIndex { counter: index })
+    }
+
     /// Given a "canonical universe" -- one found in the
     /// `u_canonicalize` result -- returns the original universe that
     /// it corresponded to.
     fn map_universe_from_canonical(&self, universe: UniverseIndex) -> UniverseIndex {
-        if universe.counter < self.universes.len() {
-            self.universes[universe.counter]
-        } else {
-            // If this universe is out of bounds, we assume an
-            // implicit `forall` binder, effectively, and map to a
-            // "big enough" universe in the original space. See
-            // comments on `map_from_canonical` for a detailed
-            // explanation.
-            let difference = universe.counter - self.universes.len();
-            let max_counter = self.universes.last().unwrap().counter;
-            let new_counter = max_counter + difference + 1;
-            UniverseIndex { counter: new_counter }
+        match universe.shifted_out(self.universes.len()) {
+            None => self.universes[universe.counter],
+            Some(difference) => {
+                // If this universe is out of bounds, we assume an
+                // implicit `forall` binder, effectively, and map to a
+                // "big enough" universe in the original space. See
+                // comments on `map_from_canonical` for a detailed
+                // explanation.
+                self.max_universe.shifted_in(difference.counter + 1)
+            }
         }
     }
 
@@ -195,22 +231,201 @@ impl UniverseMap {
     /// If we encounter universes like this, which are "out of bounds"
     /// from our original set of universes, we map them to a distinct
     /// universe in the original space that is greater than all the
-    /// other universes in the map. That is, if we encounter a
-    /// canonical universe `Ux` where our canonical vector is (say)
-    /// `[U0, U3]`, we would compute the difference `d = x - 2` and
-    /// then return the universe `3 + d + 1`.
+    /// other universes in the original space. That is, if we encounter
+    /// a canonical universe `Ux` where our canonical vector is (say)
+    /// `[U0, U3]`, and the table the value was taken from had the
+    /// universes up to U5, we would compute the difference `d = x - 2`
+    /// and then return the universe `5 + d + 1`.
     ///
     /// The important thing is that we preserve (a) the relative order
     /// of universes, since that determines visibility, and (b) that
     /// the universe we produce does not correspond to any of the
-    /// other original universes.
-    crate fn map_from_canonical<T: Fold>(&self, value: &T) -> T::Result {
+    /// other original universes. For (b), it is not enough to go past
+    /// the universes in the map: going past U3 alone, we would return
+    /// U4 for `d = 0`, which the table also has. When maps are
+    /// composed -- as when an answer is passed out through nested
+    /// canonicalizations -- the universes of one map are the canonical
+    /// universes of the next, so U4 could then be mapped to an
+    /// original universe, rather than to one invented in turn.
+    pub(crate) fn map_from_canonical<T: Fold>(&self, value: &T) -> T::Result {
         debug!("map_from_canonical(value={:?})", value);
         debug!("map_from_canonical: universes = {:?}", self.universes);
         value.fold_with(&mut UMapFromCanonical { universes: self }, 0).unwrap()
     }
+
+    /// Like `map_from_canonical`, but the universes of the binders of
+    /// `canonical` are mapped too, so that it can be instantiated in
+    /// the table whose universes these are.
+    pub(crate) fn map_canonical_from_canonical<T: Fold>(
+        &self,
+        canonical: &Canonical<T>,
+    ) -> Canonical<T::Result> {
+        Canonical {
+            value: self.map_from_canonical(&canonical.value),
+            binders: canonical
+                .binders
+                .iter()
+                .map(|pk| pk.map(|ui| self.map_universe_from_canonical(ui)))
+                .collect(),
+        }
+    }
+}
+
+impl<T: Fold> Canonical<T> {
+    /// The number of universes of this value, which must be the
+    /// canonical part of a `UCanonical` value. Its universes are then
+    /// numbered contiguously, so this is one more than the highest
+    /// universe it mentions.
+    pub(crate) fn num_universes(&self) -> usize {
+        let mut universes = UniverseMap::new();
+        self.value
+            .fold_with(
+                &mut UCollector {
+                    universes: &mut universes,
+                },
+                0,
+            )
+            .unwrap();
+        for binder in &self.binders {
+            universes.add(binder.into_inner());
+        }
+        universes.num_canonical_universes()
+    }
+
+    /// Renumbers the universes of this value -- those of its names and
+    /// of its binders -- consecutively, preserving their order, except
+    /// for the first `fixed_universes` universes, which are kept as
+    /// they are. Two values that are the same but for an
+    /// order-preserving renaming of their other universes are
+    /// renumbered to the same thing.
+    ///
+    /// The fixed universes are those of the goal the value answers:
+    /// `!1` and `!2` are different names of that goal, and an answer
+    /// equating `?X` with one of them must not be confused with one
+    /// equating it with the other.
+    pub(crate) fn compact_universes(&self, fixed_universes: usize) -> Canonical<T::Result> {
+        let mut universes = UniverseMap::new();
+        for counter in 1..fixed_universes {
+            universes.add(UniverseIndex { counter });
+        }
+        self.value
+            .fold_with(
+                &mut UCollector {
+                    universes: &mut universes,
+                },
+                0,
+            )
+            .unwrap();
+        for binder in &self.binders {
+            universes.add(binder.into_inner());
+        }
+        Canonical {
+            value: self.value
+                .fold_with(
+                    &mut UMapToCanonical {
+                        universes: &universes,
+                    },
+                    0,
+                )
+                .unwrap(),
+            binders: self.binders
+                .iter()
+                .map(|pk| pk.map(|ui| universes.map_universe_to_canonical(ui)))
+                .collect(),
+        }
+    }
 }
 
+impl ConstrainedSubst {
+    /// Converts an answer to a root goal with `universes` universes into
+    /// the value of a `Solution`.
+    ///
+    /// As explained on `map_from_canonical`, the region constraints may
+    /// mention names from universes beyond those of the root goal, like
+    /// `!2 == ?0` for `for<'a> 'a == 'b`: there is an implicit `forall`
+    /// binder around the constraints for each of them. We make those
+    /// binders explicit, since the caller has no way to make sense of a
+    /// universe it has never seen, giving `for<lifetime> ?0 == ?1`.
+    pub(crate) fn into_solution_subst(self, universes: usize) -> SolutionSubst {
+        let mut binder = PlaceholderBinder {
+            universes,
+            placeholders: vec![],
+        };
+
+        // First find the names to bind, so that we know how far the
+        // inference variables must be shifted to make room for them.
+        self.constraints.fold_with(&mut binder, 0).unwrap();
+        let num_binders = binder.placeholders.len();
+        let constraints = self.constraints
+            .up_shift(num_binders)
+            .fold_with(&mut binder, 0)
+            .unwrap();
+
+        // Binding the names changes how the lifetimes compare, so the
+        // constraints are normalized again, to keep each equality
+        // oriented least lifetime first.
+        let mut constrained = ConstrainedSubst {
+            subst: self.subst,
+            constraints,
+            sources: self.sources,
+        };
+        constrained.normalize_constraints();
+
+        SolutionSubst {
+            subst: constrained.subst,
+            constraints: Binders {
+                binders: vec![ParameterKind::Lifetime(()); num_binders],
+                value: constrained.constraints,
+            },
+        }
+    }
+}
+
+/// Replaces the lifetime placeholders from universes beyond `universes`
+/// with variables bound just outside of the folded value, numbered in
+/// order of appearance.
+struct PlaceholderBinder {
+    universes: usize,
+    placeholders: Vec<PlaceholderIndex>,
+}
+
+impl DefaultTypeFolder for PlaceholderBinder {}
+
+impl UniversalFolder for PlaceholderBinder {
+    fn fold_free_universal_ty(&mut self, universe: PlaceholderIndex, _binders: usize) -> Fallible<Ty> {
+        Ok(universe.to_ty())
+    }
+
+    fn fold_free_universal_lifetime(
+        &mut self,
+        universe: PlaceholderIndex,
+        binders: usize,
+    ) -> Fallible<Lifetime> {
+        if universe.ui.counter < self.universes {
+            return Ok(universe.to_lifetime());
+        }
+
+        let index = match self.placeholders.iter().position(|&p| p == universe) {
+            Some(index) => index,
+            None => {
+                self.placeholders.push(universe);
+                self.placeholders.len() - 1
+            }
+        };
+        Ok(Lifetime::Var(index + binders))
+    }
+
+    fn fold_free_universal_const(
+        &mut self,
+        universe: PlaceholderIndex,
+        _binders: usize,
+    ) -> Fallible<Const> {
+        Ok(universe.to_const())
+    }
+}
+
+impl IdentityExistentialFolder for PlaceholderBinder {}
+
 /// The `UCollector` is a "no-op" in terms of the value, but along the
 /// way it collects all universes that were found into a vector.
 struct UCollector<'q> {
@@ -220,19 +435,28 @@ struct UCollector<'q> {
 impl<'q> DefaultTypeFolder for UCollector<'q> {}
 
 impl<'q> UniversalFolder for UCollector<'q> {
-    fn fold_free_universal_ty(&mut self, universe: UniverseIndex, _binders: usize) -> Fallible<Ty> {
-        self.universes.add(universe);
-        Ok(TypeName::ForAll(universe).to_ty())
+    fn fold_free_universal_ty(&mut self, universe: PlaceholderIndex, _binders: usize) -> Fallible<Ty> {
+        self.universes.add(universe.ui);
+        Ok(universe.to_ty())
     }
 
     fn fold_free_universal_lifetime(
         &mut self,
-        universe: UniverseIndex,
+        universe: PlaceholderIndex,
         _binders: usize,
     ) -> Fallible<Lifetime> {
-        self.universes.add(universe);
+        self.universes.add(universe.ui);
         Ok(universe.to_lifetime())
     }
+
+    fn fold_free_universal_const(
+        &mut self,
+        universe: PlaceholderIndex,
+        _binders: usize,
+    ) -> Fallible<Const> {
+        self.universes.add(universe.ui);
+        Ok(universe.to_const())
+    }
 }
 
 impl<'q> IdentityExistentialFolder for UCollector<'q> {}
@@ -246,21 +470,39 @@ impl<'q> DefaultTypeFolder for UMapToCanonical<'q> {}
 impl<'q> UniversalFolder for UMapToCanonical<'q> {
     fn fold_free_universal_ty(
         &mut self,
-        universe0: UniverseIndex,
+        universe0: PlaceholderIndex,
         _binders: usize,
     ) -> Fallible<Ty> {
-        let universe = self.universes.map_universe_to_canonical(universe0);
-        Ok(TypeName::ForAll(universe).to_ty())
+        let universe = PlaceholderIndex {
+            ui: self.universes.map_universe_to_canonical(universe0.ui),
+            idx: universe0.idx,
+        };
+        Ok(universe.to_ty())
     }
 
     fn fold_free_universal_lifetime(
         &mut self,
-        universe0: UniverseIndex,
+        universe0: PlaceholderIndex,
         _binders: usize,
     ) -> Fallible<Lifetime> {
-        let universe = self.universes.map_universe_to_canonical(universe0);
+        let universe = PlaceholderIndex {
+            ui: self.universes.map_universe_to_canonical(universe0.ui),
+            idx: universe0.idx,
+        };
         Ok(universe.to_lifetime())
     }
+
+    fn fold_free_universal_const(
+        &mut self,
+        universe0: PlaceholderIndex,
+        _binders: usize,
+    ) -> Fallible<Const> {
+        let universe = PlaceholderIndex {
+            ui: self.universes.map_universe_to_canonical(universe0.ui),
+            idx: universe0.idx,
+        };
+        Ok(universe.to_const())
+    }
 }
 
 impl<'q> IdentityExistentialFolder for UMapToCanonical<'q> {}
@@ -274,21 +516,39 @@ impl<'q> DefaultTypeFolder for UMapFromCanonical<'q> {}
 impl<'q> UniversalFolder for UMapFromCanonical<'q> {
     fn fold_free_universal_ty(
         &mut self,
-        universe0: UniverseIndex,
+        universe0: PlaceholderIndex,
         _binders: usize,
     ) -> Fallible<Ty> {
-        let universe = self.universes.map_universe_from_canonical(universe0);
-        Ok(TypeName::ForAll(universe).to_ty())
+        let universe = PlaceholderIndex {
+            ui: self.universes.map_universe_from_canonical(universe0.ui),
+            idx: universe0.idx,
+        };
+        Ok(universe.to_ty())
     }
 
     fn fold_free_universal_lifetime(
         &mut self,
-        universe0: UniverseIndex,
+        universe0: PlaceholderIndex,
         _binders: usize,
     ) -> Fallible<Lifetime> {
-        let universe = self.universes.map_universe_from_canonical(universe0);
+        let universe = PlaceholderIndex {
+            ui: self.universes.map_universe_from_canonical(universe0.ui),
+            idx: universe0.idx,
+        };
         Ok(universe.to_lifetime())
     }
+
+    fn fold_free_universal_const(
+        &mut self,
+        universe0: PlaceholderIndex,
+        _binders: usize,
+    ) -> Fallible<Const> {
+        let universe = PlaceholderIndex {
+            ui: self.universes.map_universe_from_canonical(universe0.ui),
+            idx: universe0.idx,
+        };
+        Ok(universe.to_const())
+    }
 }
 
 impl<'q> IdentityExistentialFolder for UMapFromCanonical<'q> {}
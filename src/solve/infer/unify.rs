@@ -1,6 +1,7 @@
 use cast::Cast;
 use fallible::*;
 use fold::{DefaultTypeFolder, ExistentialFolder, Fold, UniversalFolder};
+use solve::external::{ConstEval, ConstEvaluation};
 use std::sync::Arc;
 use zip::{Zip, Zipper};
 
@@ -8,12 +9,27 @@ use super::*;
 use super::var::*;
 
 impl InferenceTable {
-    crate fn unify<T>(
+    pub(crate) fn unify<T>(
         &mut self,
         environment: &Arc<Environment>,
         a: &T,
         b: &T,
     ) -> Fallible<UnificationResult>
+    where
+        T: ?Sized + Zip,
+    {
+        self.unify_with_const_eval(environment, a, b, None)
+    }
+
+    /// Like `unify`, but named constants (see `UnevaluatedConst`) are
+    /// evaluated with `const_eval`, if any, before being compared.
+    pub(crate) fn unify_with_const_eval<T>(
+        &mut self,
+        environment: &Arc<Environment>,
+        a: &T,
+        b: &T,
+        const_eval: Option<&dyn ConstEval>,
+    ) -> Fallible<UnificationResult>
     where
         T: ?Sized + Zip,
     {
@@ -24,7 +40,7 @@ impl InferenceTable {
             b
         );
         let snapshot = self.snapshot();
-        match Unifier::new(self, environment).unify(a, b) {
+        match Unifier::new(self, environment, const_eval).unify(a, b) {
             Ok(r) => {
                 self.commit(snapshot);
                 Ok(r)
@@ -40,23 +56,37 @@ impl InferenceTable {
 struct Unifier<'t> {
     table: &'t mut InferenceTable,
     environment: &'t Arc<Environment>,
+    const_eval: Option<&'t dyn ConstEval>,
     goals: Vec<InEnvironment<DomainGoal>>,
     constraints: Vec<InEnvironment<Constraint>>,
+    ambiguous: bool,
 }
 
 #[derive(Debug)]
-crate struct UnificationResult {
-    crate goals: Vec<InEnvironment<DomainGoal>>,
-    crate constraints: Vec<InEnvironment<Constraint>>,
+pub(crate) struct UnificationResult {
+    pub(crate) goals: Vec<InEnvironment<DomainGoal>>,
+    pub(crate) constraints: Vec<InEnvironment<Constraint>>,
+
+    /// True if the values may or may not be equal, depending on what
+    /// is not yet known: a named constant too generic to be evaluated
+    /// may or may not be equal to another constant. The goals and
+    /// constraints are then those of the parts that could be unified.
+    pub(crate) ambiguous: bool,
 }
 
 impl<'t> Unifier<'t> {
-    fn new(table: &'t mut InferenceTable, environment: &'t Arc<Environment>) -> Self {
+    fn new(
+        table: &'t mut InferenceTable,
+        environment: &'t Arc<Environment>,
+        const_eval: Option<&'t dyn ConstEval>,
+    ) -> Self {
         Unifier {
             environment: environment,
             table: table,
+            const_eval,
             goals: vec![],
             constraints: vec![],
+            ambiguous: false,
         }
     }
 
@@ -71,6 +101,7 @@ impl<'t> Unifier<'t> {
         Ok(UnificationResult {
             goals: self.goals,
             constraints: self.constraints,
+            ambiguous: self.ambiguous,
         })
     }
 
@@ -80,10 +111,11 @@ impl<'t> Unifier<'t> {
     where
         T: Zip + Fold,
     {
-        let sub_unifier = Unifier::new(self.table, &self.environment);
-        let UnificationResult { goals, constraints } = sub_unifier.unify(&ty1, &ty2)?;
+        let sub_unifier = Unifier::new(self.table, &self.environment, self.const_eval);
+        let UnificationResult { goals, constraints, ambiguous } = sub_unifier.unify(&ty1, &ty2)?;
         self.goals.extend(goals);
         self.constraints.extend(constraints);
+        self.ambiguous |= ambiguous;
         Ok(())
     }
 
@@ -146,6 +178,10 @@ impl<'t> Unifier<'t> {
                 )
             }
 
+            // A projection is equal to itself, whether or not it can
+            // be normalized.
+            (&Ty::Projection(ref proj1), &Ty::Projection(ref proj2)) if proj1 == proj2 => Ok(()),
+
             (ty @ &Ty::Apply(_), &Ty::Projection(ref proj))
             | (ty @ &Ty::ForAll(_), &Ty::Projection(ref proj))
             | (ty @ &Ty::Var(_), &Ty::Projection(ref proj))
@@ -175,11 +211,9 @@ impl<'t> Unifier<'t> {
 
         debug!("unify_forall_tys({:?}, {:?})", ty1, ty2);
 
+        let ui = self.table.new_universe_for(ty1.num_binders);
         let lifetimes1: Vec<_> = (0..ty1.num_binders)
-            .map(|_| {
-                let new_universe = self.table.new_universe();
-                Lifetime::ForAll(new_universe).cast()
-            })
+            .map(|idx| Lifetime::ForAll(PlaceholderIndex { ui, idx }).cast())
             .collect();
 
         let max_universe = self.table.max_universe;
@@ -239,11 +273,9 @@ impl<'t> Unifier<'t> {
     }
 
     fn unify_forall_apply(&mut self, ty1: &QuantifiedTy, ty2: &Ty) -> Fallible<()> {
+        let ui = self.table.new_universe_for(ty1.num_binders);
         let lifetimes1: Vec<_> = (0..ty1.num_binders)
-            .map(|_| {
-                let new_universe = self.table.new_universe();
-                Lifetime::ForAll(new_universe).cast()
-            })
+            .map(|idx| Lifetime::ForAll(PlaceholderIndex { ui, idx }).cast())
             .collect();
 
         let ty1 = ty1.substitute(&lifetimes1);
@@ -274,9 +306,9 @@ impl<'t> Unifier<'t> {
     }
 
     fn unify_lifetime_lifetime(&mut self, a: &Lifetime, b: &Lifetime) -> Fallible<()> {
-        if let Some(n_a) = self.table.normalize_lifetime(a, 0) {
+        if let Some(n_a) = self.table.normalize_var(a, 0) {
             return self.unify_lifetime_lifetime(&n_a, b);
-        } else if let Some(n_b) = self.table.normalize_lifetime(b, 0) {
+        } else if let Some(n_b) = self.table.normalize_var(b, 0) {
             return self.unify_lifetime_lifetime(a, &n_b);
         }
 
@@ -294,16 +326,16 @@ impl<'t> Unifier<'t> {
                 Ok(())
             }
 
-            (&Lifetime::Var(depth), &Lifetime::ForAll(ui))
-            | (&Lifetime::ForAll(ui), &Lifetime::Var(depth)) => {
+            (&Lifetime::Var(depth), &Lifetime::ForAll(placeholder))
+            | (&Lifetime::ForAll(placeholder), &Lifetime::Var(depth)) => {
                 let var = InferenceVariable::from_depth(depth);
                 let var_ui = self.table.universe_of_unbound_var(var);
-                if var_ui.can_see(ui) {
+                if var_ui.can_see(placeholder.ui) {
                     debug!(
                         "unify_lifetime_lifetime: {:?} in {:?} can see {:?}; unifying",
-                        var, var_ui, ui
+                        var, var_ui, placeholder
                     );
-                    let v = Lifetime::ForAll(ui);
+                    let v = Lifetime::ForAll(placeholder);
                     self.table
                         .unify
                         .unify_var_value(var, InferenceValue::from(v))
@@ -312,13 +344,29 @@ impl<'t> Unifier<'t> {
                 } else {
                     debug!(
                         "unify_lifetime_lifetime: {:?} in {:?} cannot see {:?}; pushing constraint",
-                        var, var_ui, ui
+                        var, var_ui, placeholder
                     );
                     Ok(self.push_lifetime_eq_constraint(*a, *b))
                 }
             }
 
-            (&Lifetime::ForAll(_), &Lifetime::ForAll(_)) => if a != b {
+            // `'static` is in the root universe, which every variable
+            // can see.
+            (&Lifetime::Var(depth), &Lifetime::Static)
+            | (&Lifetime::Static, &Lifetime::Var(depth)) => {
+                let var = InferenceVariable::from_depth(depth);
+                debug!("unify_lifetime_lifetime: unifying {:?} with 'static", var);
+                self.table
+                    .unify
+                    .unify_var_value(var, InferenceValue::from(Lifetime::Static))
+                    .unwrap();
+                Ok(())
+            }
+
+            (&Lifetime::ForAll(_), &Lifetime::ForAll(_))
+            | (&Lifetime::ForAll(_), &Lifetime::Static)
+            | (&Lifetime::Static, &Lifetime::ForAll(_))
+            | (&Lifetime::Static, &Lifetime::Static) => if a != b {
                 Ok(self.push_lifetime_eq_constraint(*a, *b))
             } else {
                 Ok(())
@@ -326,6 +374,110 @@ impl<'t> Unifier<'t> {
         }
     }
 
+    fn unify_const_const(&mut self, a: &Const, b: &Const) -> Fallible<()> {
+        if let Some(n_a) = self.table.normalize_var(a, 0) {
+            return self.unify_const_const(&n_a, b);
+        } else if let Some(n_b) = self.table.normalize_var(b, 0) {
+            return self.unify_const_const(a, &n_b);
+        }
+
+        let evaluation_a = self.evaluate_const(a);
+        if let Some(ConstEvaluation::Value(value_a)) = evaluation_a {
+            return self.unify_const_const(&Const::Value(value_a), b);
+        }
+        let evaluation_b = self.evaluate_const(b);
+        if let Some(ConstEvaluation::Value(value_b)) = evaluation_b {
+            return self.unify_const_const(a, &Const::Value(value_b));
+        }
+        let too_generic = evaluation_a == Some(ConstEvaluation::TooGeneric)
+            || evaluation_b == Some(ConstEvaluation::TooGeneric);
+
+        debug_heading!("unify_const_const({:?}, {:?})", a, b);
+
+        match (a, b) {
+            (&Const::Var(depth_a), &Const::Var(depth_b)) => {
+                let var_a = InferenceVariable::from_depth(depth_a);
+                let var_b = InferenceVariable::from_depth(depth_b);
+                debug!("unify_const_const: var_a={:?} var_b={:?}", var_a, var_b);
+                self.table.unify.unify_var_var(var_a, var_b).unwrap();
+                Ok(())
+            }
+
+            (&Const::Var(depth), c @ &Const::ForAll(_))
+            | (c @ &Const::ForAll(_), &Const::Var(depth))
+            | (&Const::Var(depth), c @ &Const::Value(_))
+            | (c @ &Const::Value(_), &Const::Var(depth))
+            | (&Const::Var(depth), c @ &Const::Unevaluated(_))
+            | (c @ &Const::Unevaluated(_), &Const::Var(depth)) => {
+                self.unify_var_const(InferenceVariable::from_depth(depth), c)
+            }
+
+            // Constants that could not be evaluated are equal if they
+            // are the same constant, with the same parameters.
+            (&Const::Unevaluated(ref unevaluated_a), &Const::Unevaluated(ref unevaluated_b))
+                if unevaluated_a.const_id == unevaluated_b.const_id =>
+            {
+                Zip::zip_with(self, unevaluated_a, unevaluated_b)
+            }
+
+            // Otherwise, a constant too generic to be evaluated may
+            // still turn out to be equal to the other once its
+            // parameters are known.
+            _ if too_generic => {
+                debug!("unify_const_const: too generic to tell");
+                self.ambiguous = true;
+                Ok(())
+            }
+
+            // Values are only compared once they are known to be of
+            // the same type: `3u8` is not `3u16`.
+            (&Const::Value(value_a), &Const::Value(value_b)) if value_a.ty != value_b.ty => {
+                Err(NoSolution)
+            }
+
+            (&Const::ForAll(_), _) | (&Const::Value(_), _) | (&Const::Unevaluated(_), _) => {
+                if a != b {
+                    Err(NoSolution)
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Evaluates `c` with the `ConstEval` of the unifier, if `c` is a
+    /// named constant and there is one.
+    fn evaluate_const(&mut self, c: &Const) -> Option<ConstEvaluation> {
+        let unevaluated = match c {
+            Const::Unevaluated(unevaluated) => unevaluated,
+            Const::Var(_) | Const::ForAll(_) | Const::Value(_) => return None,
+        };
+        let const_eval = self.const_eval?;
+
+        let parameters = self.table.canonicalize(&unevaluated.parameters).quantified;
+        let evaluation = const_eval.evaluate(unevaluated.const_id, &parameters);
+        debug!("evaluate_const: {:?} evaluates to {:?}", c, evaluation);
+        Some(evaluation)
+    }
+
+    fn unify_var_const(&mut self, var: InferenceVariable, c: &Const) -> Fallible<()> {
+        debug!("unify_var_const(var={:?}, c={:?})", var, c);
+
+        // Unlike lifetimes, there is no way to defer an equality
+        // between consts as a region-style constraint: either the
+        // variable can name the placeholders in `c`, or there is no
+        // solution. The occurs check takes care of both that and of
+        // the parameters of named constants.
+        let universe_index = self.table.universe_of_unbound_var(var);
+        let c1 = c.fold_with(&mut OccursCheck::new(self, var, universe_index), 0)?;
+
+        self.table
+            .unify
+            .unify_var_value(var, InferenceValue::from(c1))
+            .unwrap();
+        Ok(())
+    }
+
     fn push_lifetime_eq_constraint(&mut self, a: Lifetime, b: Lifetime) {
         self.constraints.push(InEnvironment::new(
             self.environment,
@@ -343,11 +495,40 @@ impl<'t> Zipper for Unifier<'t> {
         self.unify_lifetime_lifetime(a, b)
     }
 
-    fn zip_binders<T>(&mut self, _: &Binders<T>, _: &Binders<T>) -> Fallible<()>
+    fn zip_consts(&mut self, a: &Const, b: &Const) -> Fallible<()> {
+        self.unify_const_const(a, b)
+    }
+
+    fn zip_binders<T>(&mut self, a: &Binders<T>, b: &Binders<T>) -> Fallible<()>
     where
         T: Zip + Fold<Result = T>,
     {
-        panic!("cannot unify things with binders (other than types)")
+        // for<A...> T == for<B...> U
+        //
+        // if the binders have the same kinds and:
+        //
+        // for<A...> exists<B...> T == U &&
+        // for<B...> exists<A...> T == U
+        //
+        // This is the same strategy as `unify_forall_tys`, just
+        // extended to binders of any kind.
+        debug!("zip_binders({:?}, {:?})", a, b);
+
+        if a.binders != b.binders {
+            return Err(NoSolution);
+        }
+
+        {
+            let a_universal = self.table.instantiate_binders_universally(a);
+            let b_existential = self.table.instantiate_binders_existentially(b);
+            Zip::zip_with(self, &a_universal, &b_existential)?;
+        }
+
+        {
+            let b_universal = self.table.instantiate_binders_universally(b);
+            let a_existential = self.table.instantiate_binders_existentially(a);
+            Zip::zip_with(self, &a_existential, &b_universal)
+        }
     }
 }
 
@@ -374,20 +555,20 @@ impl<'u, 't> OccursCheck<'u, 't> {
 impl<'u, 't> DefaultTypeFolder for OccursCheck<'u, 't> {}
 
 impl<'u, 't> UniversalFolder for OccursCheck<'u, 't> {
-    fn fold_free_universal_ty(&mut self, universe: UniverseIndex, _binders: usize) -> Fallible<Ty> {
-        if self.universe_index < universe {
+    fn fold_free_universal_ty(&mut self, universe: PlaceholderIndex, _binders: usize) -> Fallible<Ty> {
+        if self.universe_index < universe.ui {
             Err(NoSolution)
         } else {
-            Ok(TypeName::ForAll(universe).to_ty()) // no need to shift, not relative to depth
+            Ok(universe.to_ty()) // no need to shift, not relative to depth
         }
     }
 
     fn fold_free_universal_lifetime(
         &mut self,
-        ui: UniverseIndex,
+        ui: PlaceholderIndex,
         binders: usize,
     ) -> Fallible<Lifetime> {
-        if self.universe_index < ui {
+        if self.universe_index < ui.ui {
             // Scenario is like:
             //
             // exists<T> forall<'b> ?T = Foo<'b>
@@ -411,11 +592,23 @@ impl<'u, 't> UniversalFolder for OccursCheck<'u, 't> {
             Ok(ui.to_lifetime()) // no need to shift, not relative to depth
         }
     }
+
+    fn fold_free_universal_const(
+        &mut self,
+        universe: PlaceholderIndex,
+        _binders: usize,
+    ) -> Fallible<Const> {
+        if self.universe_index < universe.ui {
+            Err(NoSolution)
+        } else {
+            Ok(universe.to_const()) // no need to shift, not relative to depth
+        }
+    }
 }
 
 impl<'u, 't> ExistentialFolder for OccursCheck<'u, 't> {
-    fn fold_free_existential_ty(&mut self, depth: usize, binders: usize) -> Fallible<Ty> {
-        let v = InferenceVariable::from_depth(depth);
+    fn fold_free_existential_ty(&mut self, depth: DebruijnIndex, binders: usize) -> Fallible<Ty> {
+        let v = InferenceVariable::from_depth(depth.depth());
         match self.unifier.table.unify.probe_value(v) {
             // If this variable already has a value, fold over that value instead.
             InferenceValue::Bound(normalized_ty) => {
@@ -445,19 +638,19 @@ impl<'u, 't> ExistentialFolder for OccursCheck<'u, 't> {
                         .unwrap();
                 }
 
-                Ok(Ty::Var(depth)) // depth already includes binders
+                Ok(Ty::Var(depth.shifted_in(binders).depth()))
             }
         }
     }
 
     fn fold_free_existential_lifetime(
         &mut self,
-        depth: usize,
+        depth: DebruijnIndex,
         binders: usize,
     ) -> Fallible<Lifetime> {
         // a free existentially bound region; find the
         // inference variable it corresponds to
-        let v = InferenceVariable::from_depth(depth);
+        let v = InferenceVariable::from_depth(depth.depth());
         match self.unifier.table.unify.probe_value(v) {
             InferenceValue::Unbound(ui) => {
                 if self.universe_index < ui {
@@ -473,7 +666,7 @@ impl<'u, 't> ExistentialFolder for OccursCheck<'u, 't> {
                         .unify_var_value(v, InferenceValue::Unbound(self.universe_index))
                         .unwrap();
                 }
-                Ok(Lifetime::Var(depth).up_shift(binders))
+                Ok(Lifetime::Var(depth.shifted_in(binders).depth()))
             }
 
             InferenceValue::Bound(l) => {
@@ -482,4 +675,32 @@ impl<'u, 't> ExistentialFolder for OccursCheck<'u, 't> {
             }
         }
     }
+
+    fn fold_free_existential_const(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Const> {
+        let v = InferenceVariable::from_depth(depth.depth());
+        match self.unifier.table.unify.probe_value(v) {
+            InferenceValue::Unbound(ui) => {
+                if self.universe_index < ui {
+                    // As with types, `?A = Foo<?N>` where `?N` is in a
+                    // higher universe than `?A` is fine, provided `?N` is
+                    // promoted to the universe of `?A`.
+                    self.unifier
+                        .table
+                        .unify
+                        .unify_var_value(v, InferenceValue::Unbound(self.universe_index))
+                        .unwrap();
+                }
+                Ok(Const::Var(depth.shifted_in(binders).depth()))
+            }
+
+            InferenceValue::Bound(c) => {
+                let c = c.constant().unwrap();
+                Ok(c.fold_with(self, 0)?.up_shift(binders))
+            }
+        }
+    }
 }
@@ -31,7 +31,7 @@ use std::u32;
 ///     "downcast" the resulting variable using
 ///     e.g. `value.ty().unwrap()`.
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub(in solve) struct InferenceVariable {
+pub(crate) struct InferenceVariable {
     index: u32,
 }
 
@@ -43,7 +43,7 @@ impl InferenceVariable {
     /// enclosing binders B, then it refers to an inference variable,
     /// and the inference variable can be created via
     /// `InferenceVariable::from_depth(D - B)`.
-    crate fn from_depth(depth: usize) -> InferenceVariable {
+    pub(crate) fn from_depth(depth: usize) -> InferenceVariable {
         assert!(depth < u32::MAX as usize);
         InferenceVariable {
             index: depth as u32,
@@ -53,16 +53,29 @@ impl InferenceVariable {
     /// Convert this inference variable into a type. When using this
     /// method, naturally you should know from context that the kind
     /// of this inference variable is a type (we can't check it).
-    crate fn to_ty(self) -> Ty {
+    pub(crate) fn to_ty(self) -> Ty {
         Ty::Var(self.index as usize)
     }
 
     /// Convert this inference variable into a lifetime. When using this
     /// method, naturally you should know from context that the kind
     /// of this inference variable is a lifetime (we can't check it).
-    crate fn to_lifetime(self) -> Lifetime {
+    pub(crate) fn to_lifetime(self) -> Lifetime {
         Lifetime::Var(self.index as usize)
     }
+
+    /// Convert this inference variable into a const. When using this
+    /// method, naturally you should know from context that the kind
+    /// of this inference variable is a const (we can't check it).
+    pub(crate) fn to_const(self) -> Const {
+        Const::Var(self.index as usize)
+    }
+
+    /// Convert this inference variable into a value of kind `V`; as
+    /// `to_ty` and the like, for code generic over the kind.
+    pub(crate) fn to_value<V: ParameterValue>(self) -> V {
+        V::from_var(self.index as usize)
+    }
 }
 
 impl UnifyKey for InferenceVariable {
@@ -85,7 +98,7 @@ impl UnifyKey for InferenceVariable {
 /// universe index; when the inference variable is assigned a value, it becomes
 /// bound and records that value. See `InferenceVariable` for more details.
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub(in solve) enum InferenceValue {
+pub(crate) enum InferenceValue {
     Unbound(UniverseIndex),
     Bound(Parameter),
 }
@@ -102,6 +115,12 @@ impl From<Lifetime> for InferenceValue {
     }
 }
 
+impl From<Const> for InferenceValue {
+    fn from(constant: Const) -> Self {
+        InferenceValue::Bound(ParameterKind::Const(constant))
+    }
+}
+
 impl UnifyValue for InferenceValue {
     fn unify_values(
         a: &InferenceValue,
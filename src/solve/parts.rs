@@ -0,0 +1,282 @@
+//! Solutions taken apart, for callers which need more of a solution
+//! than to print it: the value of each variable of the goal, the
+//! lifetime constraints, and what is known of an ambiguous goal, as
+//! data rather than as a string to pick apart. Solutions are printed
+//! from these parts as well, so that the two cannot drift apart.
+
+use ir::*;
+use ir::debug::VariableName;
+use solve::{CannotProveReason, Guidance, Legend, Solution};
+use std::fmt;
+
+/// How much is known about a goal (see `SolutionData`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Certainty {
+    /// The goal holds, with the values of the bindings, provided the
+    /// lifetime constraints do (see `Solution::Unique`).
+    Unique,
+
+    /// The goal may or may not hold; the bindings are the guidance
+    /// there is, if any (see `Solution::Ambig`), and the reason says
+    /// why the solver gave up, if it did (see `Solution::CannotProve`).
+    Ambiguous,
+
+    /// The solver found no solution to the goal.
+    NoSolution,
+}
+
+/// The kind of guidance of an ambiguous solution (see `Guidance`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GuidanceKind {
+    Definite,
+    Suggested,
+}
+
+/// A variable of a goal, in the bindings of a `SolutionData`: by the
+/// name the user gave it, or by its index if it has none (see
+/// `PeeledGoal`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GoalVariable {
+    Named(String),
+    Index(usize),
+}
+
+impl fmt::Display for GoalVariable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GoalVariable::Named(name) => write!(f, "{}", name),
+            GoalVariable::Index(index) => write!(f, "?{}", index),
+        }
+    }
+}
+
+/// The parts of a solution (see `Solution::into_parts`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SolutionData {
+    pub certainty: Certainty,
+
+    /// The kinds and universes of the variables bound by the solution,
+    /// which its bindings may refer to.
+    pub binders: Vec<ParameterKind<UniverseIndex>>,
+
+    /// The value of each variable of the goal, in order. Empty unless
+    /// the solution is unique, or ambiguous with some guidance.
+    pub bindings: Vec<(GoalVariable, Parameter)>,
+
+    /// The lifetime constraints of a unique solution, under binders
+    /// for the names of `forall` goals in the goal (see
+    /// `Solution::Unique`). Empty for any other solution.
+    pub constraints: Binders<Vec<RegionConstraint>>,
+
+    /// The kind of guidance the bindings are, for an ambiguous
+    /// solution which has some.
+    pub guidance: Option<GuidanceKind>,
+
+    /// Why the solver gave up on the goal, if it did.
+    pub reason: Option<CannotProveReason>,
+
+    /// True if the variables bound by the solution are printed as `?A`,
+    /// `?B`, etc (see `Solution::display_with_names`), rather than by
+    /// their universes.
+    lettered: bool,
+}
+
+impl SolutionData {
+    fn new(solution: Solution, names: &[Option<String>], lettered: bool) -> Self {
+        let bindings = |subst: Substitution| {
+            subst
+                .parameters
+                .into_iter()
+                .enumerate()
+                .map(|(index, value)| {
+                    let variable = match names.get(index) {
+                        Some(Some(name)) => GoalVariable::Named(name.clone()),
+                        _ => GoalVariable::Index(index),
+                    };
+                    (variable, value)
+                })
+                .collect()
+        };
+        let mut data = SolutionData::empty(Certainty::Ambiguous, lettered);
+        match solution {
+            Solution::Unique(Canonical {
+                binders,
+                value: SolutionSubst { subst, constraints },
+            }) => {
+                data.certainty = Certainty::Unique;
+                data.binders = binders;
+                data.bindings = bindings(subst);
+                data.constraints = constraints;
+            }
+            Solution::Ambig(Guidance::Definite(subst)) => {
+                data.binders = subst.binders;
+                data.bindings = bindings(subst.value);
+                data.guidance = Some(GuidanceKind::Definite);
+            }
+            Solution::Ambig(Guidance::Suggested(subst)) => {
+                data.binders = subst.binders;
+                data.bindings = bindings(subst.value);
+                data.guidance = Some(GuidanceKind::Suggested);
+            }
+            Solution::Ambig(Guidance::Unknown) => {}
+            Solution::CannotProve(reason) => data.reason = Some(reason),
+        }
+        data
+    }
+
+    /// Parts with nothing but `certainty`.
+    fn empty(certainty: Certainty, lettered: bool) -> Self {
+        SolutionData {
+            certainty,
+            binders: vec![],
+            bindings: vec![],
+            constraints: Binders {
+                binders: vec![],
+                value: vec![],
+            },
+            guidance: None,
+            reason: None,
+            lettered,
+        }
+    }
+
+    /// Like the `Display` impl of `Canonical`: the bindings, under the
+    /// variables bound by the solution, if there are any.
+    fn fmt_canonical<F>(&self, f: &mut fmt::Formatter, fmt_value: F) -> fmt::Result
+    where
+        F: FnOnce(&mut fmt::Formatter) -> fmt::Result,
+    {
+        if self.binders.is_empty() {
+            return fmt_value(f);
+        }
+
+        write!(f, "for<")?;
+        for (i, binder) in self.binders.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            if self.lettered {
+                write!(f, "?{}", VariableName(i))?;
+            } else {
+                write!(f, "?{}", binder.into_inner())?;
+            }
+        }
+        write!(f, "> {{ ")?;
+        fmt_value(f)?;
+        write!(f, " }}")
+    }
+
+    fn fmt_bindings(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        for (index, (variable, value)) in self.bindings.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} := {:?}", variable, value)?;
+        }
+        write!(f, "]")
+    }
+
+    fn fmt_parts(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.certainty {
+            Certainty::Unique => {
+                write!(f, "Unique; ")?;
+                self.fmt_canonical(f, |f| {
+                    write!(f, "substitution ")?;
+                    self.fmt_bindings(f)?;
+                    write!(f, ", lifetime constraints {:?}", self.constraints)
+                })
+            }
+            Certainty::Ambiguous => match (&self.reason, self.guidance) {
+                (Some(reason), _) => write!(f, "Ambiguous; cannot prove: {:?}", reason),
+                (None, Some(GuidanceKind::Definite)) => {
+                    write!(f, "Ambiguous; definite substitution ")?;
+                    self.fmt_canonical(f, |f| self.fmt_bindings(f))
+                }
+                (None, Some(GuidanceKind::Suggested)) => {
+                    write!(f, "Ambiguous; suggested substitution ")?;
+                    self.fmt_canonical(f, |f| self.fmt_bindings(f))
+                }
+                (None, None) => write!(f, "Ambiguous; no inference guidance"),
+            },
+            Certainty::NoSolution => write!(f, "No possible solution"),
+        }
+    }
+}
+
+/// The parts of the solution of a goal as returned by
+/// `solve_root_goal`, in which `None` means there is no solution.
+impl From<Option<Solution>> for SolutionData {
+    fn from(solution: Option<Solution>) -> Self {
+        match solution {
+            Some(solution) => solution.into_parts(),
+            None => SolutionData::empty(Certainty::NoSolution, false),
+        }
+    }
+}
+
+impl fmt::Display for SolutionData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.lettered {
+            tls::with_lettered_variables(|| self.fmt_parts(f))
+        } else {
+            self.fmt_parts(f)
+        }
+    }
+}
+
+impl Solution {
+    /// Takes this solution apart. The variables of the goal are
+    /// referred to by their index; the variables bound by the solution
+    /// keep their numbering.
+    pub fn into_parts(self) -> SolutionData {
+        SolutionData::new(self, &[], false)
+    }
+
+    /// Takes this solution apart, in terms of the names of the variables
+    /// of the goal, after renaming it (see `Solution::renamed`), as
+    /// `display_with_names` prints it.
+    pub fn into_named_parts(self, names: &[Option<String>]) -> (SolutionData, Legend) {
+        let (solution, legend) = self.renamed();
+        (SolutionData::new(solution, names, true), legend)
+    }
+
+    /// Displays this solution in terms of the names of the variables
+    /// of the goal (see `PeeledGoal`): rather than `[?0 := Vec<?1>]`,
+    /// we print `[T := Vec<?A>]`. The solution is renamed first (see
+    /// `Solution::renamed`), and the variables bound by it are then
+    /// lettered `?A`, `?B`, etc, so that they cannot clash with the
+    /// names of the user; placeholders are printed as `!T1`, `!T2`,
+    /// etc. Variables of the goal without a name are still referred to
+    /// by their index.
+    pub fn display_with_names(&self, names: &[Option<String>]) -> NamedSolution {
+        let (parts, legend) = self.clone().into_named_parts(names);
+        NamedSolution { parts, legend }
+    }
+}
+
+impl fmt::Display for Solution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.clone().into_parts(), f)
+    }
+}
+
+/// See `Solution::display_with_names`.
+pub struct NamedSolution {
+    parts: SolutionData,
+    legend: Legend,
+}
+
+impl NamedSolution {
+    /// The kinds and universes of the lettered variables and of the
+    /// placeholders.
+    pub fn legend(&self) -> &Legend {
+        &self.legend
+    }
+}
+
+impl fmt::Display for NamedSolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.parts, f)
+    }
+}
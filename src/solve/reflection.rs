@@ -0,0 +1,55 @@
+//! Goals about the program itself (see `ReflectionGoal`). These are
+//! answered by looking at the impls and clauses of the program, rather
+//! than by solving, so that tests can check what a program lowers to
+//! without depending on how the solver gets on with it.
+
+use ir::could_match::CouldMatch;
+use ir::*;
+use solve::Solution;
+
+impl ReflectionGoal {
+    /// Answers the goal for `program`, whose clauses are those of
+    /// `env`. Like the solver, this yields a unique solution -- with
+    /// nothing to substitute -- if the goal holds, and `None` if it
+    /// does not.
+    pub fn solve(&self, program: &Program, env: &ProgramEnvironment) -> Option<Solution> {
+        let holds = match self {
+            ReflectionGoal::HasImpl(trait_id, ty) => {
+                let self_ty = ParameterKind::Ty(ty.clone());
+                program.impl_data.values().any(|impl_datum| {
+                    // Only the header of the impl is looked at, so the
+                    // parameters of the impl may be anything.
+                    let trait_ref = &impl_datum.binders.value.trait_ref;
+                    trait_ref.is_positive()
+                        && trait_ref.trait_ref().trait_id == *trait_id
+                        && trait_ref.trait_ref().parameters[0].could_match(&self_ty)
+                })
+            }
+            ReflectionGoal::ClauseCount(trait_id, count) => {
+                // The clauses about a trait are those the clause index
+                // files under it.
+                let clauses = env
+                    .program_clauses
+                    .iter()
+                    .filter(|clause| clause.match_key().item == Some(*trait_id));
+                clauses.count() == *count
+            }
+        };
+
+        if !holds {
+            return None;
+        }
+        Some(Solution::Unique(Canonical {
+            value: SolutionSubst {
+                subst: Substitution {
+                    parameters: SubstParameters::new(),
+                },
+                constraints: Binders {
+                    binders: vec![],
+                    value: vec![],
+                },
+            },
+            binders: vec![],
+        }))
+    }
+}
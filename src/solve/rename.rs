@@ -0,0 +1,208 @@
+//! Renaming of solutions for display. The variables bound by a
+//! canonical solution are numbered however the solver happened to
+//! find them, and placeholders carry the universe of the `forall` that
+//! introduced them, so two solutions that are the same up to the
+//! names of their variables may still print differently. Before a
+//! solution is rendered, we walk it from left to right and number its
+//! variables and placeholders in the order in which they first occur;
+//! the kinds and universes this hides are kept in a `Legend`.
+//!
+//! This is only ever done to print a solution: the renamed solution
+//! must not be handed back to the solver.
+
+use fallible::*;
+use fold::{DefaultTypeFolder, ExistentialFolder, Fold, UniversalFolder};
+use ir::*;
+use ir::debug::VariableName;
+use solve::{Guidance, Solution};
+use std::fmt;
+
+/// The kinds and universes of the variables and placeholders of a
+/// renamed solution, in the order in which they are named.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Legend {
+    entries: Vec<LegendEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct LegendEntry {
+    /// The name under which the variable or placeholder is printed,
+    /// e.g. `?A` or `!T1`.
+    name: String,
+    kind: ParameterKind<UniverseIndex>,
+}
+
+impl Legend {
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl fmt::Display for Legend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, entry) in self.entries.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            match entry.kind {
+                ParameterKind::Ty(ui) => write!(f, "{}: Ty({:?})", entry.name, ui)?,
+                ParameterKind::Lifetime(ui) => write!(f, "{}: Lifetime({:?})", entry.name, ui)?,
+                ParameterKind::Const(ui) => write!(f, "{}: Const({:?})", entry.name, ui)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Solution {
+    /// Renames the variables bound by this solution, and the
+    /// placeholders it mentions, in the order in which they first
+    /// occur, so that solutions which only differ by the numbering of
+    /// their variables are renamed to the same thing. Placeholders are
+    /// numbered from 0 regardless of their universe; see the module
+    /// docs.
+    pub fn renamed(&self) -> (Solution, Legend) {
+        match self {
+            Solution::Unique(constrained) => {
+                let (constrained, legend) = rename_canonical(constrained);
+                (Solution::Unique(constrained), legend)
+            }
+            Solution::Ambig(Guidance::Definite(subst)) => {
+                let (subst, legend) = rename_canonical(subst);
+                (Solution::Ambig(Guidance::Definite(subst)), legend)
+            }
+            Solution::Ambig(Guidance::Suggested(subst)) => {
+                let (subst, legend) = rename_canonical(subst);
+                (Solution::Ambig(Guidance::Suggested(subst)), legend)
+            }
+            Solution::Ambig(Guidance::Unknown) | Solution::CannotProve(_) => {
+                (self.clone(), Legend::default())
+            }
+        }
+    }
+}
+
+fn rename_canonical<T>(canonical: &Canonical<T>) -> (Canonical<T>, Legend)
+where
+    T: Fold<Result = T>,
+{
+    let mut renamer = Renamer {
+        variables: vec![None; canonical.binders.len()],
+        order: vec![],
+        placeholders: vec![],
+    };
+    let value = canonical.value.fold_with(&mut renamer, 0).unwrap();
+
+    // Variables which do not occur at all keep their relative order,
+    // after all of those which do.
+    for depth in 0..canonical.binders.len() {
+        renamer.variable(DebruijnIndex::new(depth));
+    }
+
+    let binders: Vec<_> = renamer.order.iter().map(|&depth| canonical.binders[depth]).collect();
+    let variables = binders.iter().enumerate().map(|(index, &kind)| LegendEntry {
+        name: tls::with_lettered_variables(|| format!("?{}", VariableName(index))),
+        kind,
+    });
+    let placeholders =
+        renamer.placeholders.iter().enumerate().map(|(index, &(placeholder, kind))| LegendEntry {
+            name: format!("!T{}", index + 1),
+            kind: kind.map(|()| placeholder.ui),
+        });
+    let legend = Legend {
+        entries: variables.chain(placeholders).collect(),
+    };
+    (Canonical { binders, value }, legend)
+}
+
+struct Renamer {
+    /// The new index of each bound variable, once it has been seen.
+    variables: Vec<Option<usize>>,
+
+    /// The bound variables in the order in which they were seen.
+    order: Vec<usize>,
+
+    /// The placeholders in the order in which they were seen, and
+    /// their kinds.
+    placeholders: Vec<(PlaceholderIndex, ParameterKind<()>)>,
+}
+
+impl Renamer {
+    fn variable(&mut self, depth: DebruijnIndex) -> DebruijnIndex {
+        let depth = depth.depth();
+        let index = match self.variables.get(depth) {
+            Some(&Some(index)) => index,
+            Some(None) => {
+                let index = self.order.len();
+                self.variables[depth] = Some(index);
+                self.order.push(depth);
+                index
+            }
+            // Not bound by the canonical value; there should be no
+            // such variables, but we leave them alone if there are.
+            None => depth,
+        };
+        DebruijnIndex::new(index)
+    }
+
+    fn placeholder(
+        &mut self,
+        placeholder: PlaceholderIndex,
+        kind: ParameterKind<()>,
+    ) -> PlaceholderIndex {
+        let idx = match self.placeholders.iter().position(|&(p, _)| p == placeholder) {
+            Some(idx) => idx,
+            None => {
+                self.placeholders.push((placeholder, kind));
+                self.placeholders.len() - 1
+            }
+        };
+        PlaceholderIndex { ui: placeholder.ui, idx }
+    }
+}
+
+impl DefaultTypeFolder for Renamer {}
+
+impl ExistentialFolder for Renamer {
+    fn fold_free_existential_ty(&mut self, depth: DebruijnIndex, binders: usize) -> Fallible<Ty> {
+        Ok(Ty::Var(self.variable(depth).shifted_in(binders).depth()))
+    }
+
+    fn fold_free_existential_lifetime(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Lifetime> {
+        Ok(Lifetime::Var(self.variable(depth).shifted_in(binders).depth()))
+    }
+
+    fn fold_free_existential_const(
+        &mut self,
+        depth: DebruijnIndex,
+        binders: usize,
+    ) -> Fallible<Const> {
+        Ok(Const::Var(self.variable(depth).shifted_in(binders).depth()))
+    }
+}
+
+impl UniversalFolder for Renamer {
+    fn fold_free_universal_ty(&mut self, universe: PlaceholderIndex, _binders: usize) -> Fallible<Ty> {
+        Ok(self.placeholder(universe, ParameterKind::Ty(())).to_ty())
+    }
+
+    fn fold_free_universal_lifetime(
+        &mut self,
+        universe: PlaceholderIndex,
+        _binders: usize,
+    ) -> Fallible<Lifetime> {
+        Ok(self.placeholder(universe, ParameterKind::Lifetime(())).to_lifetime())
+    }
+
+    fn fold_free_universal_const(
+        &mut self,
+        universe: PlaceholderIndex,
+        _binders: usize,
+    ) -> Fallible<Const> {
+        Ok(self.placeholder(universe, ParameterKind::Const(())).to_const())
+    }
+}
@@ -1,3 +1,3 @@
-crate mod implementation;
+pub(crate) mod implementation;
 
 #[cfg(test)] mod test;
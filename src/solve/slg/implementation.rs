@@ -1,51 +1,255 @@
 use crate::cast::{Cast, Caster};
+use crate::fold::Subst;
 use crate::fallible::Fallible;
 use crate::ir::could_match::CouldMatch;
 use crate::ir::*;
 use crate::solve::infer::ucanonicalize::{UCanonicalized, UniverseMap};
 use crate::solve::infer::unify::UnificationResult;
 use crate::solve::infer::InferenceTable;
-use crate::solve::truncate::{self, Truncated};
-use crate::solve::Solution;
+use crate::solve::external::{self, SolverHooks};
+use crate::solve::truncate::{self, Truncated, TruncationMeasure};
+use crate::solve::{Solution, SubgoalOrdering};
+use crate::zip::depth::exceeds_depth;
 
-use chalk_engine::context;
-use chalk_engine::forest::Forest;
+use chalk_engine::context::{self, AnswerStream};
+use chalk_engine::forest::{Forest, Scheduling};
 use chalk_engine::hh::HhGoal;
-use chalk_engine::{DelayedLiteral, ExClause, Literal};
+use chalk_engine::{CannotProveReason, DelayedLiteral, ExClause, Literal};
 
-use std::fmt::Debug;
+use std::cell::Cell;
+use std::fmt::{self, Debug};
+use std::iter;
 use std::sync::Arc;
 
 mod aggregate;
+mod ground;
 mod resolvent;
 
+thread_local! {
+    /// The number of tables created by `solve_goal_in_forest` on this
+    /// thread so far.
+    static TABLES_CREATED: Cell<usize> = Cell::new(0);
+}
+
+/// The number of tables the SLG solver has created on this thread so
+/// far, to measure how much work it was spared.
+#[cfg(test)]
+pub(crate) fn tables_created() -> usize {
+    TABLES_CREATED.with(|tables| tables.get())
+}
+
+/// The forest of the SLG solver, in which goals of the given `program`
+/// environment are solved (see `solve_goal_in_forest`).
+pub(crate) type SlgForest = Forest<SlgContext, SlgContext>;
+
+/// Creates an empty forest for the goals of the given `program`
+/// environment, with the parameters of `SolverChoice::SLG`.
+pub(crate) fn new_forest(
+    program: &Arc<ProgramEnvironment>,
+    hooks: &SolverHooks,
+    max_size: usize,
+    truncation: TruncationMeasure,
+    scheduling: Scheduling,
+    ordering: SubgoalOrdering,
+    max_projection_depth: usize,
+    max_type_depth: usize,
+    track_provenance: bool,
+    max_cached_tables: usize,
+) -> SlgForest {
+    let context = SlgContext::new(program, max_size)
+        .with_truncation_measure(truncation)
+        .with_hooks(hooks.clone())
+        .with_subgoal_ordering(ordering)
+        .with_max_projection_depth(max_projection_depth)
+        .with_max_type_depth(max_type_depth)
+        .with_provenance_tracking(track_provenance);
+    Forest::with_scheduling(context, scheduling).with_max_cached_tables(max_cached_tables)
+}
+
 /// Entry point for the chalk solver implementation.
-/// Solve a canonical goal `root_goal` in the given `program` environment.
-pub fn solve_goal_in_program(root_goal: &UCanonical<InEnvironment<Goal>>, program: &Arc<ProgramEnvironment>, max_size: usize) -> Option<Solution> {
-    Forest::new(SlgContext::new(program, max_size)).solve(root_goal)
+/// Solve a canonical goal `root_goal` in `forest`, reusing the tables
+/// it has from the goals solved before.
+pub(crate) fn solve_goal_in_forest(
+    root_goal: &UCanonical<InEnvironment<Goal>>,
+    forest: &mut SlgForest,
+) -> Option<Solution> {
+    let tables_created = forest.num_tables_created();
+    let solution = forest.solve(root_goal);
+    let tables_created = (forest.num_tables_created() - tables_created) as usize;
+    TABLES_CREATED.with(|tables| tables.set(tables.get() + tables_created));
+    solution
+}
+
+/// Solves `root_goal` without the SLG solver, if it is ground and
+/// simple enough to be proven directly (see the `ground` module).
+/// Returns `None` if it must be solved with `solve_goal_in_forest`
+/// instead, which would find the same solutions as this does.
+pub(crate) fn solve_ground_goal_in_program(
+    root_goal: &UCanonical<InEnvironment<Goal>>,
+    program: &Arc<ProgramEnvironment>,
+    max_size: usize,
+    truncation: TruncationMeasure,
+    max_type_depth: usize,
+) -> Option<Solution> {
+    ground::GroundSolver::new(program, max_size, truncation, max_type_depth).solve(root_goal)
+}
+
+/// The first `max_answers` answers to `root_goal` in the given
+/// `program` environment, each with whether it is ambiguous. The
+/// parameters are those of `new_forest`, without the hooks;
+/// the clauses each answer was proven with are recorded if
+/// `record_sources` is set.
+pub(crate) fn answers_in_program(
+    root_goal: &UCanonical<InEnvironment<Goal>>,
+    program: &Arc<ProgramEnvironment>,
+    max_size: usize,
+    truncation: TruncationMeasure,
+    scheduling: Scheduling,
+    ordering: SubgoalOrdering,
+    max_projection_depth: usize,
+    max_type_depth: usize,
+    track_provenance: bool,
+    max_cached_tables: usize,
+    max_answers: usize,
+    record_sources: bool,
+) -> Vec<(Canonical<ConstrainedSubst>, bool)> {
+    let context = SlgContext::new(program, max_size)
+        .with_truncation_measure(truncation)
+        .with_subgoal_ordering(ordering)
+        .with_max_projection_depth(max_projection_depth)
+        .with_max_type_depth(max_type_depth)
+        .with_provenance_tracking(track_provenance)
+        .with_source_recording(record_sources);
+    let mut forest =
+        Forest::with_scheduling(context, scheduling).with_max_cached_tables(max_cached_tables);
+    let mut answers = forest.iter_answers(root_goal);
+    let mut result = vec![];
+    while result.len() < max_answers {
+        match answers.next_answer() {
+            Some(answer) => result.push((answer.subst, answer.ambiguous)),
+            None => break,
+        }
+    }
+    result
 }
 
-#[derive(Clone, Debug)]
-pub(super) struct SlgContext {
+/// How many projection goals may be nested directly within one another,
+/// unless the solver is told otherwise.
+pub(crate) const DEFAULT_MAX_PROJECTION_DEPTH: usize = 64;
+
+/// How deeply the types of goals may be nested, unless the solver is
+/// told otherwise.
+pub(crate) const DEFAULT_MAX_TYPE_DEPTH: usize = 256;
+
+#[derive(Clone)]
+pub(crate) struct SlgContext {
     program: Arc<ProgramEnvironment>,
+    hooks: SolverHooks,
     max_size: usize,
+    truncation: TruncationMeasure,
+    ordering: SubgoalOrdering,
+    max_projection_depth: usize,
+    max_type_depth: usize,
+    track_provenance: bool,
+    record_sources: bool,
 }
 
 pub(super) struct TruncatingInferenceTable {
     program: Arc<ProgramEnvironment>,
+    hooks: SolverHooks,
     max_size: usize,
+    truncation: TruncationMeasure,
+    ordering: SubgoalOrdering,
+    track_provenance: bool,
+    record_sources: bool,
     infer: InferenceTable,
 }
 
 impl SlgContext {
-    crate fn new(program: &Arc<ProgramEnvironment>, max_size: usize) -> SlgContext {
+    pub(crate) fn new(program: &Arc<ProgramEnvironment>, max_size: usize) -> SlgContext {
         SlgContext {
             program: program.clone(),
+            hooks: SolverHooks::default(),
             max_size,
+            truncation: TruncationMeasure::default(),
+            ordering: SubgoalOrdering::default(),
+            max_projection_depth: DEFAULT_MAX_PROJECTION_DEPTH,
+            max_type_depth: DEFAULT_MAX_TYPE_DEPTH,
+            track_provenance: false,
+            record_sources: false,
+        }
+    }
+
+    /// Measures the size of types with `truncation` when deciding
+    /// whether to truncate them.
+    pub(crate) fn with_truncation_measure(self, truncation: TruncationMeasure) -> Self {
+        SlgContext { truncation, ..self }
+    }
+
+    /// Answers external goals, and evaluates named constants, with the
+    /// `hooks` of the embedder.
+    pub(crate) fn with_hooks(self, hooks: SolverHooks) -> Self {
+        SlgContext { hooks, ..self }
+    }
+
+    /// Proves the conditions of program clauses in the given order.
+    pub(crate) fn with_subgoal_ordering(self, ordering: SubgoalOrdering) -> Self {
+        SlgContext { ordering, ..self }
+    }
+
+    /// Gives up on projection goals nested directly within more than
+    /// `max_projection_depth` others (`DEFAULT_MAX_PROJECTION_DEPTH` by
+    /// default).
+    pub(crate) fn with_max_projection_depth(self, max_projection_depth: usize) -> Self {
+        SlgContext { max_projection_depth, ..self }
+    }
+
+    /// Gives up on subgoals with types nested more than
+    /// `max_type_depth` deep (`DEFAULT_MAX_TYPE_DEPTH` by default).
+    pub(crate) fn with_max_type_depth(self, max_type_depth: usize) -> Self {
+        SlgContext { max_type_depth, ..self }
+    }
+
+    /// Tags each region constraint with the goal that imposed it (see
+    /// `Provenance`).
+    pub(crate) fn with_provenance_tracking(self, track_provenance: bool) -> Self {
+        SlgContext { track_provenance, ..self }
+    }
+
+    /// Records the clauses each answer was proven with (see
+    /// `ClauseSources`).
+    pub(crate) fn with_source_recording(self, record_sources: bool) -> Self {
+        SlgContext { record_sources, ..self }
+    }
+
+    fn truncating_table(&self, infer: InferenceTable) -> TruncatingInferenceTable {
+        TruncatingInferenceTable {
+            program: self.program.clone(),
+            hooks: self.hooks.clone(),
+            max_size: self.max_size,
+            truncation: self.truncation,
+            ordering: self.ordering,
+            track_provenance: self.track_provenance,
+            record_sources: self.record_sources,
+            infer,
         }
     }
 }
 
+impl Debug for SlgContext {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("SlgContext")
+            .field("program", &self.program)
+            .field("hooks", &self.hooks)
+            .field("max_size", &self.max_size)
+            .field("truncation", &self.truncation)
+            .field("ordering", &self.ordering)
+            .field("track_provenance", &self.track_provenance)
+            .field("record_sources", &self.record_sources)
+            .finish()
+    }
+}
+
 impl context::Context for SlgContext {
     type CanonicalGoalInEnvironment = Canonical<InEnvironment<Goal>>;
     type CanonicalExClause = Canonical<ExClause<Self>>;
@@ -60,11 +264,12 @@ impl context::Context for SlgContext {
     type Parameter = Parameter;
     type ProgramClause = ProgramClause;
     type ProgramClauses = Vec<ProgramClause>;
+    type ClauseSources = ClauseSources;
     type UnificationResult = UnificationResult;
     type CanonicalConstrainedSubst = Canonical<ConstrainedSubst>;
     type GoalInEnvironment = InEnvironment<Goal>;
     type Substitution = Substitution;
-    type RegionConstraint = InEnvironment<Constraint>;
+    type RegionConstraint = RegionConstraint;
 
     fn goal_in_environment(environment: &Arc<Environment>, goal: Goal) -> InEnvironment<Goal> {
         InEnvironment::new(environment, goal)
@@ -84,6 +289,18 @@ impl context::ContextOps<SlgContext> for SlgContext {
         goal.is_coinductive(&self.program)
     }
 
+    fn is_projection_goal(&self, goal: &UCanonical<InEnvironment<Goal>>) -> bool {
+        goal.is_projection_goal()
+    }
+
+    fn max_projection_depth(&self) -> usize {
+        self.max_projection_depth
+    }
+
+    fn is_too_deep(&self, goal: &UCanonical<InEnvironment<Goal>>) -> bool {
+        exceeds_depth(&goal.canonical.value, self.max_type_depth)
+    }
+
     fn instantiate_ucanonical_goal<R>(
         &self,
         arg: &UCanonical<InEnvironment<Goal>>,
@@ -91,7 +308,7 @@ impl context::ContextOps<SlgContext> for SlgContext {
     ) -> R {
         let (infer, subst, InEnvironment { environment, goal }) =
             InferenceTable::from_canonical(arg.universes, &arg.canonical);
-        let dyn_infer = &mut TruncatingInferenceTable::new(&self.program, self.max_size, infer);
+        let dyn_infer = &mut self.truncating_table(infer);
         op.with(dyn_infer, subst, environment, goal)
     }
 
@@ -103,7 +320,7 @@ impl context::ContextOps<SlgContext> for SlgContext {
     ) -> R {
         let (infer, _subst, ex_cluse) =
             InferenceTable::from_canonical(num_universes, canonical_ex_clause);
-        let dyn_infer = &mut TruncatingInferenceTable::new(&self.program, self.max_size, infer);
+        let dyn_infer = &mut self.truncating_table(infer);
         op.with(dyn_infer, ex_cluse)
     }
 
@@ -113,6 +330,14 @@ impl context::ContextOps<SlgContext> for SlgContext {
         &canon_ex_clause.value.subst
     }
 
+    fn num_subgoals(canon_ex_clause: &Canonical<ExClause<SlgContext>>) -> usize {
+        canon_ex_clause.value.subgoals.len()
+    }
+
+    fn is_ground_ex_clause(canon_ex_clause: &Canonical<ExClause<SlgContext>>) -> bool {
+        canon_ex_clause.binders.is_empty()
+    }
+
     fn empty_constraints(ccs: &Canonical<ConstrainedSubst>) -> bool {
         ccs.value.constraints.is_empty()
     }
@@ -141,28 +366,109 @@ impl context::ContextOps<SlgContext> for SlgContext {
         map.map_from_canonical(value)
     }
 
+    /// The variables of the answer are mapped too: those which only
+    /// appear in its constraints, like a lifetime created while proving
+    /// the goal, may be in any of its universes.
     fn map_subst_from_canonical(
         map: &UniverseMap,
         value: &Canonical<ConstrainedSubst>,
     ) -> Canonical<ConstrainedSubst> {
-        map.map_from_canonical(value)
+        map.map_canonical_from_canonical(value)
+    }
+
+    fn u_canonicalize_answer(
+        num_universes: usize,
+        value: &Canonical<ConstrainedSubst>,
+    ) -> Canonical<ConstrainedSubst> {
+        value.compact_universes(num_universes)
     }
 }
 
 impl TruncatingInferenceTable {
-    fn new(program: &Arc<ProgramEnvironment>, max_size: usize, infer: InferenceTable) -> Self {
+    pub(super) fn new(
+        program: &Arc<ProgramEnvironment>,
+        max_size: usize,
+        infer: InferenceTable,
+    ) -> Self {
         Self {
             program: program.clone(),
+            hooks: SolverHooks::default(),
             max_size,
+            truncation: TruncationMeasure::default(),
+            ordering: SubgoalOrdering::default(),
+            track_provenance: false,
+            record_sources: false,
             infer,
         }
     }
 }
 
+impl TruncatingInferenceTable {
+    /// The clauses for the external goal `goal`, built from the answers
+    /// of the external solver (see `external::external_clauses`). The
+    /// solver is asked about the canonical form of the goal, so that
+    /// its answers do not depend on the inference variables in scope.
+    fn external_clauses(&self, goal: &ExternalGoal) -> Vec<ProgramClause> {
+        let external = match &self.hooks.external {
+            Some(external) => external,
+            None => return vec![],
+        };
+
+        // `program_clauses` only has shared access to the table, but
+        // canonicalizing may have to resolve variables: do that on a
+        // copy.
+        let parameters = self.infer.clone().canonicalize(&goal.parameters).quantified;
+        let answers = external.solve(goal.predicate, &parameters);
+        external::external_clauses(goal.predicate, &parameters, answers)
+    }
+
+    /// The clause for `goal`, which is `WellFormed(ty)`, if `ty` is a
+    /// higher-ranked type or a projection. Unlike for applications,
+    /// whose rules come from the struct declarations, the rules depend
+    /// on the structure of `ty`, so the clause is built for the goal at
+    /// hand:
+    ///
+    /// ```notrust
+    /// WellFormed(for<'a> T) :- forall<'a> { WellFormed(T) }
+    /// WellFormed(<T as Foo>::Assoc) :- (T: Foo), WC
+    /// ```
+    ///
+    /// where `WC` are the where clauses of the associated type.
+    fn well_formed_ty_clause(&self, goal: &DomainGoal, ty: &Ty) -> Option<ProgramClause> {
+        let conditions = match ty {
+            Ty::ForAll(quantified) => {
+                let body: Goal = WellFormed::Ty(quantified.ty.clone()).cast();
+                vec![body.quantify(
+                    QuantifierKind::ForAll,
+                    vec![ParameterKind::Lifetime(()); quantified.num_binders],
+                )]
+            }
+            Ty::Projection(projection) => {
+                let datum = &self.program.associated_ty_data[&projection.associated_ty_id];
+                let num_trait_parameters = self.program.trait_data[&datum.trait_id].binders.len();
+                let split_point = projection.parameters.len() - num_trait_parameters;
+                let trait_ref = TraitRef {
+                    trait_id: datum.trait_id,
+                    parameters: projection.parameters[split_point..].to_vec(),
+                };
+                let where_clauses = Subst::apply(&projection.parameters, &datum.where_clauses);
+                iter::once(trait_ref.cast())
+                    .chain(where_clauses.into_iter().casted())
+                    .collect()
+            }
+            Ty::Var(_) | Ty::Apply(_) | Ty::UnselectedProjection(_) => return None,
+        };
+        Some(ProgramClause::Implies(ProgramClauseImplication {
+            consequence: goal.clone(),
+            conditions,
+        }))
+    }
+}
+
 impl context::TruncateOps<SlgContext, SlgContext> for TruncatingInferenceTable {
     fn truncate_goal(&mut self, subgoal: &InEnvironment<Goal>) -> Option<InEnvironment<Goal>> {
         let Truncated { overflow, value } =
-            truncate::truncate(&mut self.infer, self.max_size, subgoal);
+            truncate::truncate(&mut self.infer, self.truncation, self.max_size, subgoal);
         if overflow {
             Some(value)
         } else {
@@ -172,7 +478,7 @@ impl context::TruncateOps<SlgContext, SlgContext> for TruncatingInferenceTable {
 
     fn truncate_answer(&mut self, subst: &Substitution) -> Option<Substitution> {
         let Truncated { overflow, value } =
-            truncate::truncate(&mut self.infer, self.max_size, subst);
+            truncate::truncate(&mut self.infer, self.truncation, self.max_size, subst);
         if overflow {
             Some(value)
         } else {
@@ -211,11 +517,13 @@ impl context::UnificationOps<SlgContext, SlgContext> for TruncatingInferenceTabl
         environment: &Arc<Environment>,
         goal: &DomainGoal,
     ) -> Vec<ProgramClause> {
-        let environment_clauses = environment
-            .clauses
-            .iter()
+        let mut clauses: Vec<_> = environment
+            .candidate_clauses(goal)
+            .into_iter()
             .filter(|&env_clause| env_clause.could_match(goal))
-            .cloned();
+            .cloned()
+            .collect();
+        let num_environment_clauses = clauses.len();
 
         let program_clauses = self.program
             .program_clauses
@@ -223,7 +531,31 @@ impl context::UnificationOps<SlgContext, SlgContext> for TruncatingInferenceTabl
             .filter(|&clause| clause.could_match(goal))
             .cloned();
 
-        environment_clauses.chain(program_clauses).collect()
+        clauses.extend(program_clauses);
+        match goal {
+            DomainGoal::External(goal) => clauses.extend(self.external_clauses(goal)),
+            DomainGoal::WellFormed(WellFormed::Ty(ty @ Ty::ForAll(_))) => {
+                // The rule built for a higher-ranked type closes over its
+                // binder. The rules of the structs, and any other clause
+                // about an application type, would apply too, to the type
+                // with its binder instantiated, but only under constraints
+                // on the lifetimes it binds. Custom clauses about any type
+                // still apply.
+                let program_clauses = clauses.split_off(num_environment_clauses);
+                clauses.extend(
+                    program_clauses
+                        .into_iter()
+                        .filter(|clause| !is_well_formed_application(clause)),
+                );
+                clauses.extend(self.well_formed_ty_clause(goal, ty))
+            }
+            DomainGoal::WellFormed(WellFormed::Ty(ty)) => {
+                clauses.extend(self.well_formed_ty_clause(goal, ty))
+            }
+            _ => {}
+        }
+        remove_excluded_fallback(&self.program, &self.infer, environment, goal, &mut clauses);
+        clauses
     }
 
     fn instantiate_binders_universally(&mut self, arg: &Binders<Box<Goal>>) -> Goal {
@@ -234,7 +566,7 @@ impl context::UnificationOps<SlgContext, SlgContext> for TruncatingInferenceTabl
         *self.infer.instantiate_binders_existentially(arg)
     }
 
-    fn debug_ex_clause(
+    fn debug_ex_clause<'v>(
         &mut self,
         value: &'v ExClause<SlgContext>,
     ) -> Box<dyn Debug + 'v> {
@@ -255,11 +587,24 @@ impl context::UnificationOps<SlgContext, SlgContext> for TruncatingInferenceTabl
     fn canonicalize_constrained_subst(
         &mut self,
         subst: Substitution,
-        constraints: Vec<InEnvironment<Constraint>>,
+        constraints: Vec<RegionConstraint>,
+        sources: ClauseSources,
     ) -> Canonical<ConstrainedSubst> {
-        self.infer
-            .canonicalize(&ConstrainedSubst { subst, constraints })
+        // The table compares answers by their canonical form: make sure
+        // that answers which only differ in the order of their
+        // constraints, or in the names of their variables, are seen as
+        // duplicates.
+        let answer = self
+            .infer
+            .canonicalize(&ConstrainedSubst {
+                subst,
+                constraints,
+                sources,
+            })
             .quantified
+            .alpha_normalized();
+        debug_assert!(answer.is_closed(), "free variables in answer {:?}", answer);
+        answer
     }
 
     fn u_canonicalize_goal(
@@ -267,7 +612,7 @@ impl context::UnificationOps<SlgContext, SlgContext> for TruncatingInferenceTabl
         value: &Canonical<InEnvironment<Goal>>,
     ) -> (
         UCanonical<InEnvironment<Goal>>,
-        ::crate::solve::infer::ucanonicalize::UniverseMap,
+        crate::solve::infer::ucanonicalize::UniverseMap,
     ) {
         let UCanonicalized {
             quantified,
@@ -280,13 +625,53 @@ impl context::UnificationOps<SlgContext, SlgContext> for TruncatingInferenceTabl
         self.infer.invert(value)
     }
 
+    /// Only goals that a trait is implemented are looked at, and only
+    /// once the constructor of their self type is known: none of the
+    /// hypotheses of the environment may be about the trait, and no
+    /// program clause about the trait may be for that constructor (see
+    /// `NegativeCache`).
+    fn never_applies(&mut self, goal: &InEnvironment<Goal>) -> bool {
+        let trait_ref = match &goal.goal {
+            Goal::Leaf(LeafGoal::DomainGoal(DomainGoal::Holds(WhereClause::Implemented(
+                trait_ref,
+            )))) => trait_ref,
+            _ => return false,
+        };
+        let self_ty = trait_ref.parameters[0].assert_ty_ref();
+        let head = match self.infer.normalize_shallow(self_ty, 0) {
+            Some(Ty::Apply(application)) => application.name,
+            Some(_) => return false,
+            None => match self_ty {
+                Ty::Apply(application) => application.name,
+                _ => return false,
+            },
+        };
+
+        // The hypotheses prove the goal directly, or through
+        // `Implemented(T: Foo) :- FromEnv(T: Foo)`; in the `Compatible`
+        // modality, the impls of a compatible world may prove it too.
+        let implemented: DomainGoal = trait_ref.clone().cast();
+        let from_env: DomainGoal = FromEnv::Trait(trait_ref.clone()).cast();
+        let compatible = DomainGoal::Compatible(());
+        if !goal.environment.candidate_clauses(&implemented).is_empty()
+            || !goal.environment.candidate_clauses(&from_env).is_empty()
+            || !goal.environment.candidate_clauses(&compatible).is_empty()
+        {
+            return false;
+        }
+
+        self.program
+            .negative_cache
+            .never_implemented(&self.program.program_clauses, trait_ref.trait_id, head)
+    }
+
     fn unify_parameters(
         &mut self,
         environment: &Arc<Environment>,
         a: &Parameter,
         b: &Parameter,
     ) -> Fallible<UnificationResult> {
-        self.infer.unify(environment, a, b)
+        self.infer.unify_with_const_eval(environment, a, b, self.hooks.const_eval())
     }
 
     /// Since we do not have distinct types for the inference context and the slg-context,
@@ -315,10 +700,44 @@ fn into_ex_clause(
     result: UnificationResult,
     ex_clause: &mut ExClause<SlgContext>,
 ) {
-    ex_clause
-        .subgoals
-        .extend(result.goals.into_iter().casted().map(Literal::Positive));
-    ex_clause.constraints.extend(result.constraints);
+    for goal in result.goals {
+        push_subgoal(ex_clause, Literal::Positive(goal.cast()));
+    }
+    for constraint in result.constraints {
+        push_constraint(ex_clause, RegionConstraint::new(constraint));
+    }
+    if result.ambiguous {
+        let cannot_prove = DelayedLiteral::CannotProve(CannotProveReason::Floundered);
+        if !ex_clause.delayed_literals.contains(&cannot_prove) {
+            ex_clause.delayed_literals.push(cannot_prove);
+        }
+    }
+}
+
+/// Adds `subgoal` to the end of the subgoals of `ex_clause`, unless an
+/// identical subgoal is already there: the same obligation often
+/// appears in several conditions of a clause, and proving it again
+/// within the same strand would only cost us more table lookups.
+///
+/// Subgoals are compared structurally, without canonicalizing them, so
+/// `?0: Sized` and `?1: Sized` are not deduplicated even if `?0` and
+/// `?1` have since been unified.
+fn push_subgoal(ex_clause: &mut ExClause<SlgContext>, subgoal: Literal<SlgContext>) {
+    let is_duplicate = ex_clause.subgoals.iter().any(|existing| match (existing, &subgoal) {
+        (Literal::Positive(a), Literal::Positive(b))
+        | (Literal::Negative(a), Literal::Negative(b)) => a == b,
+        _ => false,
+    });
+    if !is_duplicate {
+        ex_clause.subgoals.push(subgoal);
+    }
+}
+
+/// Like `push_subgoal`, for region constraints.
+fn push_constraint(ex_clause: &mut ExClause<SlgContext>, constraint: RegionConstraint) {
+    if !ex_clause.constraints.contains(&constraint) {
+        ex_clause.constraints.push(constraint);
+    }
 }
 
 impl Substitution {
@@ -340,7 +759,12 @@ impl MayInvalidate {
             (ParameterKind::Lifetime(l1), ParameterKind::Lifetime(l2)) => {
                 self.aggregate_lifetimes(l1, l2)
             }
-            (ParameterKind::Ty(_), _) | (ParameterKind::Lifetime(_), _) => panic!(
+            (ParameterKind::Const(c1), ParameterKind::Const(c2)) => {
+                self.aggregate_consts(c1, c2)
+            }
+            (ParameterKind::Ty(_), _)
+            | (ParameterKind::Lifetime(_), _)
+            | (ParameterKind::Const(_), _) => panic!(
                 "mismatched parameter kinds: new={:?} current={:?}",
                 new, current
             ),
@@ -392,6 +816,24 @@ impl MayInvalidate {
         true
     }
 
+    // Returns true if the two consts could be unequal.
+    fn aggregate_consts(&mut self, new: &Const, current: &Const) -> bool {
+        match (new, current) {
+            (_, Const::Var(_)) => false,
+            (Const::Var(_), _) => true,
+            (Const::Value(v1), Const::Value(v2)) => v1 != v2,
+            (Const::Unevaluated(new), Const::Unevaluated(current)) => {
+                self.aggregate_name_and_substs(
+                    new.const_id,
+                    &new.parameters,
+                    current.const_id,
+                    &current.parameters,
+                )
+            }
+            (Const::ForAll(_), _) | (Const::Value(_), _) | (Const::Unevaluated(_), _) => true,
+        }
+    }
+
     fn aggregate_application_tys(&mut self, new: &ApplicationTy, current: &ApplicationTy) -> bool {
         let ApplicationTy {
             name: new_name,
@@ -473,6 +915,7 @@ struct_fold!(ExClauseSlgContext {
     delayed_literals,
     constraints,
     subgoals,
+    sources,
 });
 
 type LiteralSlgContext = Literal<SlgContext>;
@@ -483,6 +926,7 @@ enum_fold!(LiteralSlgContext {
 });
 
 copy_fold!(::chalk_engine::TableIndex);
+copy_fold!(::chalk_engine::CannotProveReason);
 
 type DelayedLiteralSlgContext = DelayedLiteral<SlgContext>;
 enum_fold!(DelayedLiteralSlgContext {
@@ -490,3 +934,116 @@ enum_fold!(DelayedLiteralSlgContext {
         CannotProve(a), Negative(a), Positive(a, b)
     }
 });
+
+/// True if `projection` has no unresolved variables, and something
+/// normalizes projections like it: a `Normalize` hypothesis of the
+/// environment (see `WhereClause::into_from_env_goal`), a bound implied
+/// by `FromEnv` hypotheses of the environment, or an impl. The fallback
+/// rule for the projection does not apply then: the normalization takes
+/// precedence over it. Unresolved variables may yet be resolved to
+/// types nothing normalizes, so both rules apply to the projection
+/// until they are. The fallback rule is left out then (see
+/// `remove_excluded_fallback`).
+fn can_normalize(
+    program: &ProgramEnvironment,
+    infer: &InferenceTable,
+    environment: &Arc<Environment>,
+    projection: &ProjectionTy,
+) -> bool {
+    let canonical = infer.clone().canonicalize(projection).quantified;
+    if !canonical.binders.is_empty() {
+        return false;
+    }
+    let normalize: DomainGoal = Normalize {
+        projection: canonical.value,
+        ty: Ty::Var(0),
+    }.cast();
+    let is_hypothesis = |goal: &DomainGoal| {
+        environment
+            .candidate_clauses(goal)
+            .into_iter()
+            .any(|clause| clause.could_match(goal))
+    };
+    if is_hypothesis(&normalize) {
+        return true;
+    }
+    program.program_clauses.iter().any(|clause| {
+        if !clause.could_match(&normalize) {
+            return false;
+        }
+        if program.impl_clauses.contains_key(clause) {
+            return true;
+        }
+        let conditions = match clause {
+            ProgramClause::Implies(implication) => &implication.conditions,
+            ProgramClause::ForAll(implication) => &implication.value.conditions,
+        };
+        !conditions.is_empty() && conditions.iter().all(|condition| match condition {
+            Goal::Leaf(LeafGoal::DomainGoal(from_env @ DomainGoal::FromEnv(_))) => {
+                is_hypothesis(from_env)
+            }
+            _ => false,
+        })
+    })
+}
+
+/// Removes the fallback rule for the projection of `goal` from
+/// `clauses`, if `goal` is a `ProjectionEq` goal the rule does not apply
+/// to (see `excludes_fallback`).
+fn remove_excluded_fallback(
+    program: &ProgramEnvironment,
+    infer: &InferenceTable,
+    environment: &Arc<Environment>,
+    goal: &DomainGoal,
+    clauses: &mut Vec<ProgramClause>,
+) {
+    if let DomainGoal::Holds(WhereClause::ProjectionEq(projection_eq)) = goal {
+        if excludes_fallback(program, infer, environment, projection_eq) {
+            clauses.retain(|clause| !is_projection_fallback(clause));
+        }
+    }
+}
+
+/// True if the fallback rule for the projection of `projection_eq` does
+/// not apply to it, as something normalizes the projection (see
+/// `can_normalize`). Equating the projection with its own placeholder
+/// type is left to the fallback rule all the same: no normalization
+/// yields the placeholder, and unifying a `FromEnv` hypothesis on the
+/// projection with the placeholder of a rule relies on it.
+fn excludes_fallback(
+    program: &ProgramEnvironment,
+    infer: &InferenceTable,
+    environment: &Arc<Environment>,
+    projection_eq: &ProjectionEq,
+) -> bool {
+    match infer.clone().normalize_deep(&projection_eq.ty) {
+        Ty::Apply(ApplicationTy { name: TypeName::AssociatedType(id), .. })
+            if id == projection_eq.projection.associated_ty_id => false,
+        _ => can_normalize(program, infer, environment, &projection_eq.projection),
+    }
+}
+
+/// True if `clause` proves that an application type is well-formed,
+/// like the rule of a struct: `WellFormed(Foo<T>) :- ...`.
+fn is_well_formed_application(clause: &ProgramClause) -> bool {
+    match clause.consequence() {
+        DomainGoal::WellFormed(WellFormed::Ty(Ty::Apply(_))) => true,
+        _ => false,
+    }
+}
+
+/// True if `clause` is the fallback rule of a projection, which equates
+/// it with its placeholder type: `ProjectionEq(<T as Foo>::Assoc =
+/// (Foo::Assoc)<T>)` (see `AssociatedTyDatum::to_program_clauses`).
+fn is_projection_fallback(clause: &ProgramClause) -> bool {
+    match clause.consequence() {
+        DomainGoal::Holds(WhereClause::ProjectionEq(ProjectionEq {
+            ty: Ty::Apply(ApplicationTy {
+                name: TypeName::AssociatedType(_),
+                ..
+            }),
+            ..
+        })) => true,
+        _ => false,
+    }
+}
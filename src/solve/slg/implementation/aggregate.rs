@@ -4,8 +4,9 @@ use crate::solve::{Guidance, Solution};
 use crate::solve::infer::InferenceTable;
 
 use chalk_engine::context;
-use chalk_engine::SimplifiedAnswer;
+use chalk_engine::{AnswerStreamState, CannotProveReason, SimplifiedAnswer};
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use super::SlgContext;
 
@@ -17,15 +18,60 @@ impl context::AggregateOps<SlgContext> for SlgContext {
         root_goal: &Canonical<InEnvironment<Goal>>,
         mut simplified_answers: impl context::AnswerStream<SlgContext>,
     ) -> Option<Solution> {
-        // No answers at all?
+        // No answers at all? Then the search was exhaustive: giving up
+        // on a strand always leaves an ambiguous answer behind. Callers
+        // take `None` to mean that the goal does not hold (see
+        // `SolveOutcome::Disproven`), so should that ever not be the
+        // case, we say why we could not prove the goal instead.
         if simplified_answers.peek_answer().is_none() {
-            return None;
+            return match simplified_answers.end_state() {
+                Some(AnswerStreamState::Complete) | None => None,
+                Some(AnswerStreamState::Floundered(reason)) => Some(Solution::CannotProve(reason)),
+                Some(AnswerStreamState::TruncatedAnswersPossible) => {
+                    Some(Solution::CannotProve(CannotProveReason::Truncated))
+                }
+            };
+        }
+        let SimplifiedAnswer { subst, ambiguous, .. } = simplified_answers.next_answer().unwrap();
+
+        // Exactly 1 answer, which floundered on a negative coinductive
+        // cycle, on projections nested too deeply, or on types nested
+        // too deeply? Report that,
+        // rather than some guidance which might lead the caller to
+        // believe that the goal could hold, or that it is merely
+        // ambiguous. (Other reasons for floundering, such as
+        // truncation, are expected to go away with more type
+        // information, so we give guidance for those as usual.)
+        if simplified_answers.peek_answer().is_none() {
+            match simplified_answers.end_state() {
+                Some(AnswerStreamState::Floundered(reason @ CannotProveReason::NegativeCoinductive))
+                | Some(AnswerStreamState::Floundered(reason @ CannotProveReason::ProjectionDepth))
+                | Some(AnswerStreamState::Floundered(reason @ CannotProveReason::TooDeep)) => {
+                    return Some(Solution::CannotProve(reason));
+                }
+                _ => {}
+            }
         }
-        let SimplifiedAnswer { subst, ambiguous } = simplified_answers.next_answer().unwrap();
 
-        // Exactly 1 unconditional answer?
+        // Exactly 1 unconditional answer? If this is a trivial answer,
+        // the solution is `Unique` with an identity substitution.
         if simplified_answers.peek_answer().is_none() && !ambiguous {
-            return Some(Solution::Unique(subst));
+            let Canonical { mut value, binders } = subst;
+
+            // The constraints of unknown provenance were imposed by the
+            // root goal itself.
+            if self.track_provenance {
+                let goal = Arc::new(root_goal.clone());
+                for constraint in &mut value.constraints {
+                    if constraint.provenance.goal.is_none() {
+                        constraint.provenance.goal = Some(goal.clone());
+                    }
+                }
+            }
+            return Some(Solution::Unique(Canonical {
+                value: value.into_solution_subst(root_goal.num_universes()),
+                binders,
+            }));
         }
 
         // Otherwise, we either have >1 answer, or else we have
@@ -91,14 +137,14 @@ fn merge_into_guidance(
     let Canonical {
         value: ConstrainedSubst {
             subst: subst1,
-            constraints: _,
+            ..
         },
         binders: _,
     } = answer;
 
     // Collect the types that the two substitutions have in
     // common.
-    let aggr_parameters: Vec<_> = guidance
+    let aggr_parameters: SubstParameters = guidance
         .value
         .parameters
         .iter()
@@ -117,6 +163,13 @@ fn merge_into_guidance(
                     // creating guidance here anyway.
                     return infer.new_variable(universe).to_lifetime().cast();
                 }
+                ParameterKind::Const(c) => {
+                    let mut aggr = AntiUnifier {
+                        infer: &mut infer,
+                        universe,
+                    };
+                    return aggr.aggregate_consts(c, value1.assert_const_ref()).cast();
+                }
             };
 
             let ty1 = value1.assert_ty_ref();
@@ -156,6 +209,12 @@ fn is_trivial(subst: &Canonical<Substitution>) -> bool {
             // And no lifetime mappings. (This is too strict, but we never
             // product substs with lifetimes.)
             ParameterKind::Lifetime(_) => false,
+
+            // Consts, like types, must be mapped to distinct variables.
+            ParameterKind::Const(c) => match c {
+                Const::Var(depth) => *depth == index,
+                Const::ForAll(_) | Const::Value(_) | Const::Unevaluated(_) => false,
+            },
         })
 }
 
@@ -308,7 +367,12 @@ impl<'infer> AntiUnifier<'infer> {
             (ParameterKind::Lifetime(l1), ParameterKind::Lifetime(l2)) => {
                 ParameterKind::Lifetime(self.aggregate_lifetimes(l1, l2))
             }
-            (ParameterKind::Ty(_), _) | (ParameterKind::Lifetime(_), _) => {
+            (ParameterKind::Const(c1), ParameterKind::Const(c2)) => {
+                ParameterKind::Const(self.aggregate_consts(c1, c2))
+            }
+            (ParameterKind::Ty(_), _)
+            | (ParameterKind::Lifetime(_), _)
+            | (ParameterKind::Const(_), _) => {
                 panic!("mismatched parameter kinds: p1={:?} p2={:?}", p1, p2)
             }
         }
@@ -323,9 +387,57 @@ impl<'infer> AntiUnifier<'infer> {
             } else {
                 self.new_lifetime_variable()
             },
+
+            (Lifetime::Static, Lifetime::Static) => Lifetime::Static,
+
+            (Lifetime::ForAll(_), Lifetime::Static) | (Lifetime::Static, Lifetime::ForAll(_)) => {
+                self.new_lifetime_variable()
+            }
         }
     }
 
+    fn aggregate_consts(&mut self, c1: &Const, c2: &Const) -> Const {
+        match (c1, c2) {
+            (Const::Var(_), _) | (_, Const::Var(_)) => self.new_const_variable(),
+
+            (Const::Unevaluated(unevaluated1), Const::Unevaluated(unevaluated2)) => {
+                self.aggregate_unevaluated_consts(unevaluated1, unevaluated2)
+            }
+
+            (Const::ForAll(_), _) | (Const::Value(_), _) | (Const::Unevaluated(_), _) => {
+                if c1 == c2 {
+                    c1.clone()
+                } else {
+                    self.new_const_variable()
+                }
+            }
+        }
+    }
+
+    fn aggregate_unevaluated_consts(
+        &mut self,
+        unevaluated1: &UnevaluatedConst,
+        unevaluated2: &UnevaluatedConst,
+    ) -> Const {
+        let UnevaluatedConst {
+            const_id: name1,
+            parameters: parameters1,
+        } = unevaluated1;
+        let UnevaluatedConst {
+            const_id: name2,
+            parameters: parameters2,
+        } = unevaluated2;
+
+        self.aggregate_name_and_substs(name1, parameters1, name2, parameters2)
+            .map(|(&const_id, parameters)| {
+                Const::Unevaluated(UnevaluatedConst {
+                    const_id,
+                    parameters,
+                })
+            })
+            .unwrap_or_else(|| self.new_const_variable())
+    }
+
     fn new_variable(&mut self) -> Ty {
         self.infer.new_variable(self.universe).to_ty()
     }
@@ -333,6 +445,10 @@ impl<'infer> AntiUnifier<'infer> {
     fn new_lifetime_variable(&mut self) -> Lifetime {
         self.infer.new_variable(self.universe).to_lifetime()
     }
+
+    fn new_const_variable(&mut self) -> Const {
+        self.infer.new_variable(self.universe).to_const()
+    }
 }
 
 /// Test the equivalent of `Vec<i32>` vs `Vec<u32>`
@@ -0,0 +1,269 @@
+//! A fast path for goals which are ground -- they have no variables
+//! -- such as the well-formedness goals of items without parameters.
+//! These commonly hold by the clauses of the program without any
+//! choice to make, and proving them directly spares canonicalizing
+//! them and creating a table, with its strands, for them and for each
+//! of their conditions.
+//!
+//! The fast path only answers goals whose solution it knows to be the
+//! one the SLG solver would find: `Unique`, with no substitution and no
+//! lifetime constraints. Anything that might make a difference -- a
+//! clause whose proof would need inference, impose lifetime
+//! constraints, or go through a cycle, a goal that would be truncated
+//! or that is too deep, a projection -- and the goal is left to the SLG
+//! solver.
+
+use crate::fallible::NoSolution;
+use crate::ir::*;
+use crate::solve::infer::InferenceTable;
+use crate::solve::truncate::{self, TruncationMeasure};
+use crate::solve::Solution;
+use crate::zip::depth::exceeds_depth;
+
+use chalk_engine::context::UnificationOps;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::TruncatingInferenceTable;
+
+/// What the fast path knows about a ground goal.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Outcome {
+    /// The goal holds unconditionally: the SLG solver would find it a
+    /// single, unambiguous answer with no lifetime constraints.
+    Proven,
+
+    /// The goal does not hold: no clause applies to it, or to one of
+    /// the conditions of each of the clauses that do.
+    Disproven,
+
+    /// Only the SLG solver can tell.
+    Unknown,
+}
+
+impl Outcome {
+    /// The outcome of a conjunction of goals with the given outcomes,
+    /// which are only computed as far as needed: a conjunction with a
+    /// goal that does not hold does not hold either.
+    fn all(outcomes: impl IntoIterator<Item = Outcome>) -> Outcome {
+        let mut all = Outcome::Proven;
+        for outcome in outcomes {
+            match outcome {
+                Outcome::Proven => {}
+                Outcome::Disproven => return Outcome::Disproven,
+                Outcome::Unknown => all = Outcome::Unknown,
+            }
+        }
+        all
+    }
+}
+
+pub(super) struct GroundSolver {
+    /// Finds the clauses for domain goals, as the SLG solver does.
+    clauses: TruncatingInferenceTable,
+    max_size: usize,
+    truncation: TruncationMeasure,
+    max_type_depth: usize,
+
+    /// The number of universes of the root goal, whose placeholders
+    /// may appear in the goals being proven.
+    universes: usize,
+
+    /// The outcomes of the domain goals decided so far. Neither
+    /// `Proven` nor `Disproven` depends on the goals on the `stack`, so
+    /// they stay valid; `Unknown` may, and is not recorded.
+    outcomes: HashMap<InEnvironment<DomainGoal>, Outcome>,
+
+    /// The domain goals being proven, innermost last. A goal that
+    /// depends on itself is left to the SLG solver, which knows how to
+    /// deal with cycles.
+    stack: Vec<InEnvironment<DomainGoal>>,
+}
+
+impl GroundSolver {
+    pub(super) fn new(
+        program: &Arc<ProgramEnvironment>,
+        max_size: usize,
+        truncation: TruncationMeasure,
+        max_type_depth: usize,
+    ) -> Self {
+        GroundSolver {
+            clauses: TruncatingInferenceTable::new(program, max_size, InferenceTable::new()),
+            max_size,
+            truncation,
+            max_type_depth,
+            universes: 1,
+            outcomes: HashMap::new(),
+            stack: vec![],
+        }
+    }
+
+    /// The solution of `root_goal`, if it is ground and proven by the
+    /// fast path; `None` if it must be left to the SLG solver.
+    pub(super) fn solve(
+        &mut self,
+        root_goal: &UCanonical<InEnvironment<Goal>>,
+    ) -> Option<Solution> {
+        if !root_goal.canonical.binders.is_empty() {
+            return None;
+        }
+        self.universes = root_goal.universes;
+
+        let InEnvironment { environment, goal } = &root_goal.canonical.value;
+        match self.prove(environment, goal) {
+            Outcome::Proven => {
+                let answer = ConstrainedSubst {
+                    subst: Substitution { parameters: SubstParameters::new() },
+                    constraints: vec![],
+                    sources: ClauseSources::default(),
+                };
+                Some(Solution::Unique(Canonical {
+                    value: answer.into_solution_subst(root_goal.universes),
+                    binders: vec![],
+                }))
+            }
+
+            // Even a goal that does not hold is left to the SLG
+            // solver, which tells it apart from those it gives up on.
+            Outcome::Disproven | Outcome::Unknown => None,
+        }
+    }
+
+    fn prove(&mut self, environment: &Arc<Environment>, goal: &Goal) -> Outcome {
+        match goal {
+            Goal::Leaf(LeafGoal::DomainGoal(domain_goal)) => {
+                self.prove_domain_goal(environment, domain_goal)
+            }
+            Goal::And(goal1, goal2) => Outcome::all(
+                vec![goal1, goal2]
+                    .into_iter()
+                    .map(|goal| self.prove(environment, goal)),
+            ),
+            Goal::Implies(clauses, goal) => {
+                let environment = Environment::add_clauses(environment, clauses.iter().cloned());
+                self.prove(&environment, goal)
+            }
+
+            // Binding nothing, as in the well-formedness goals of items
+            // without parameters, leaves the goal ground.
+            Goal::Quantified(_, subgoal) if subgoal.binders.is_empty() => {
+                self.prove(environment, &subgoal.value)
+            }
+
+            Goal::Quantified(..)
+            | Goal::Not(_)
+            | Goal::Leaf(LeafGoal::EqGoal(_))
+            | Goal::CannotProve(()) => Outcome::Unknown,
+        }
+    }
+
+    fn prove_domain_goal(
+        &mut self,
+        environment: &Arc<Environment>,
+        goal: &DomainGoal,
+    ) -> Outcome {
+        match goal {
+            // The SLG solver limits how deeply projections are
+            // normalized within one another, and the clauses of external
+            // goals come from the external solver.
+            DomainGoal::Holds(WhereClause::ProjectionEq(_))
+            | DomainGoal::Normalize(_)
+            | DomainGoal::UnselectedNormalize(_)
+            | DomainGoal::External(_) => return Outcome::Unknown,
+            _ => {}
+        }
+
+        let key = InEnvironment::new(environment, goal.clone());
+        if let Some(&outcome) = self.outcomes.get(&key) {
+            return outcome;
+        }
+        // The SLG solver gives up on goals that are too deep, and
+        // proving them here could overflow the stack.
+        if self.stack.contains(&key)
+            || exceeds_depth(&key, self.max_type_depth)
+            || self.is_truncated(&key)
+        {
+            return Outcome::Unknown;
+        }
+
+        self.stack.push(key);
+        let outcome = self.resolve(environment, goal);
+        let key = self.stack.pop().unwrap();
+        if outcome != Outcome::Unknown {
+            self.outcomes.insert(key, outcome);
+        }
+        outcome
+    }
+
+    /// True if the SLG solver would truncate `goal` before solving it,
+    /// which makes its answers ambiguous.
+    fn is_truncated(&self, goal: &InEnvironment<DomainGoal>) -> bool {
+        truncate::truncate(&mut self.new_table(), self.truncation, self.max_size, goal).overflow
+    }
+
+    /// An inference table in which the placeholders of the root goal
+    /// can be named.
+    fn new_table(&self) -> InferenceTable {
+        let mut infer = InferenceTable::new();
+        for _ in 1..self.universes {
+            infer.new_universe();
+        }
+        infer
+    }
+
+    /// The outcome of `goal`, from those of the clauses that could be
+    /// used to prove it. The SLG solver finds the same answer by each
+    /// of the clauses that prove it, and keeps one, so it does not
+    /// matter how many there are.
+    fn resolve(&mut self, environment: &Arc<Environment>, goal: &DomainGoal) -> Outcome {
+        let mut outcome = Outcome::Disproven;
+        for clause in self.clauses.program_clauses(environment, goal) {
+            match self.resolve_with(environment, goal, &clause) {
+                Outcome::Proven => outcome = Outcome::Proven,
+                Outcome::Disproven => {}
+                Outcome::Unknown => return Outcome::Unknown,
+            }
+        }
+        outcome
+    }
+
+    /// The outcome of proving `goal` with `clause`, which is
+    /// `Disproven` if the clause does not apply.
+    fn resolve_with(
+        &mut self,
+        environment: &Arc<Environment>,
+        goal: &DomainGoal,
+        clause: &ProgramClause,
+    ) -> Outcome {
+        let mut infer = self.new_table();
+        let ProgramClauseImplication {
+            consequence,
+            conditions,
+        } = match clause {
+            ProgramClause::Implies(implication) => implication.clone(),
+            ProgramClause::ForAll(implication) => {
+                infer.instantiate_binders_existentially(implication)
+            }
+        };
+
+        match infer.unify(environment, goal, &consequence) {
+            Ok(ref result) if result.goals.is_empty() && result.constraints.is_empty() => {}
+            Ok(_) => return Outcome::Unknown,
+            Err(NoSolution) => return Outcome::Disproven,
+        }
+
+        // The variables of the clause which its consequence does not
+        // determine would be left for the SLG solver to find.
+        let conditions = infer.normalize_deep(&conditions);
+        if !infer.canonicalize(&conditions).quantified.binders.is_empty() {
+            return Outcome::Unknown;
+        }
+
+        Outcome::all(
+            conditions
+                .iter()
+                .map(|condition| self.prove(environment, condition)),
+        )
+    }
+}
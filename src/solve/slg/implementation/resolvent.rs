@@ -2,12 +2,20 @@ use crate::fallible::Fallible;
 use crate::fold::shift::Shift;
 use crate::fold::Fold;
 use crate::ir::*;
-use crate::solve::infer::InferenceTable;
+use crate::solve::ambiguity;
+use crate::solve::external::ConstEval;
+use crate::solve::infer::{
+    InferenceParameterValue, InferenceTable, InferenceVariable, ParameterInferenceVariable,
+};
 use crate::solve::slg::implementation::{self, SlgContext, TruncatingInferenceTable};
-use crate::zip::{Zip, Zipper};
+use crate::solve::SubgoalOrdering;
+use crate::diff::{display_diff, diff_within};
+use crate::zip::{PathStep, Zip, Zipper};
 
 use chalk_engine::context;
 use chalk_engine::{ExClause, Literal};
+use std::borrow::Cow;
+use std::cmp::Reverse;
 use std::sync::Arc;
 
 ///////////////////////////////////////////////////////////////////////////
@@ -62,12 +70,6 @@ impl context::ResolventOps<SlgContext, SlgContext> for TruncatingInferenceTable
         subst: &Substitution,
         clause: &ProgramClause,
     ) -> Fallible<Canonical<ExClause<SlgContext>>> {
-        // Relating the above description to our situation:
-        //
-        // - `goal` G, except with binders for any existential variables.
-        //   - Also, we always select the first literal in `ex_clause.literals`, so `i` is 0.
-        // - `clause` is C, except with binders for any existential variables.
-
         debug_heading!(
             "resolvent_clause(\
              \n    goal={:?},\
@@ -76,50 +78,15 @@ impl context::ResolventOps<SlgContext, SlgContext> for TruncatingInferenceTable
             clause,
         );
 
+        // The resolvent is returned in canonical form, so nothing done
+        // to the inference table while computing it is needed
+        // afterwards. Roll it all back -- on failure too, so that the
+        // variables instantiated for this clause are not left behind
+        // when the next clause is tried.
         let snapshot = self.infer.snapshot();
-
-        // C' in the description above is `consequence :- conditions`.
-        //
-        // Note that G and C' have no variables in common.
-        let ProgramClauseImplication {
-            consequence,
-            conditions,
-        } = match clause {
-            ProgramClause::Implies(implication) => implication.clone(),
-            ProgramClause::ForAll(implication) => {
-                self.infer.instantiate_binders_existentially(implication)
-            }
-        };
-        debug!("consequence = {:?}", consequence);
-        debug!("conditions = {:?}", conditions);
-
-        // Unify the selected literal Li with C'.
-        let unification_result = self.infer.unify(environment, goal, &consequence)?;
-
-        // Final X-clause that we will return.
-        let mut ex_clause = ExClause {
-            subst: subst.clone(),
-            delayed_literals: vec![],
-            constraints: vec![],
-            subgoals: vec![],
-        };
-
-        // Add the subgoals/region-constraints that unification gave us.
-        implementation::into_ex_clause(unification_result, &mut ex_clause);
-
-        // Add the `conditions` from the program clause into the result too.
-        ex_clause
-            .subgoals
-            .extend(conditions.into_iter().map(|c| match c {
-                Goal::Not(c) => Literal::Negative(InEnvironment::new(environment, *c)),
-                c => Literal::Positive(InEnvironment::new(environment, c)),
-            }));
-
-        let canonical_ex_clause = self.infer.canonicalize(&ex_clause).quantified;
-
+        let result = self.resolvent_clause_in_snapshot(environment, goal, subst, clause);
         self.infer.rollback_to(snapshot);
-
-        Ok(canonical_ex_clause)
+        result
     }
 
     ///////////////////////////////////////////////////////////////////////////
@@ -199,6 +166,15 @@ impl context::ResolventOps<SlgContext, SlgContext> for TruncatingInferenceTable
     // that with `Vec<?X>` from the pending goal. We will attempt to unify
     // `Vec<?X>` with `u32` (from the substitution), which will fail. That
     // failure will get propagated back up.
+    //
+    // Finally, we report which variables of `selected_goal` the answer
+    // constrained. As it zips, `AnswerSubstitutor` records the unbound
+    // variables of `selected_goal` it unifies with the answer, as the
+    // roots of their unification classes at the time. Those which are
+    // now either bound or unified with another of them are reported. In
+    // the example, `?X` is reported, as it is bound to `u32`. Had the
+    // answer been `for<type> [?0 = ?0]`, `?X` would only have been
+    // unified with a fresh variable, and not reported.
 
     fn apply_answer_subst(
         &mut self,
@@ -206,7 +182,7 @@ impl context::ResolventOps<SlgContext, SlgContext> for TruncatingInferenceTable
         selected_goal: &InEnvironment<Goal>,
         answer_table_goal: &Canonical<InEnvironment<Goal>>,
         canonical_answer_subst: &Canonical<ConstrainedSubst>,
-    ) -> Fallible<ExClause<SlgContext>> {
+    ) -> Fallible<(ExClause<SlgContext>, Vec<Parameter>)> {
         debug_heading!("apply_answer_subst()");
         debug!("ex_clause={:?}", ex_clause);
         debug!(
@@ -216,6 +192,11 @@ impl context::ResolventOps<SlgContext, SlgContext> for TruncatingInferenceTable
         debug!("answer_table_goal={:?}", answer_table_goal);
         debug!("canonical_answer_subst={:?}", canonical_answer_subst);
 
+        // The bindings made here are only kept if the answer applies:
+        // otherwise the table is left as it was, so that the failed
+        // attempt cannot affect whatever is tried next with it.
+        let snapshot = self.infer.snapshot();
+
         // C' is now `answer`. No variables in commmon with G.
         let ConstrainedSubst {
             subst: answer_subst,
@@ -226,65 +207,246 @@ impl context::ResolventOps<SlgContext, SlgContext> for TruncatingInferenceTable
             // to be true) winds up being true, and otherwise (if the
             // answer is false or unknown) it doesn't matter.
             constraints: answer_constraints,
+
+            // The clauses the answer was proven with, if recorded, are
+            // among those the ex-clause will have been proven with.
+            sources: answer_sources,
         } = self.infer.instantiate_canonical(&canonical_answer_subst);
 
-        let mut ex_clause = AnswerSubstitutor::substitute(
+        let result = AnswerSubstitutor::substitute(
             &mut self.infer,
+            self.hooks.const_eval(),
             &selected_goal.environment,
             &answer_subst,
             ex_clause,
             &answer_table_goal.value,
             selected_goal,
+        );
+        let (mut ex_clause, pending_vars) = match result {
+            Ok(result) => {
+                self.infer.commit(snapshot);
+                result
+            }
+            Err(e) => {
+                self.infer.rollback_to(snapshot);
+                return Err(e);
+            }
+        };
+        // The constraints that the answer does not know the provenance
+        // of were imposed by the proof of its own goal.
+        let provenance = Provenance {
+            goal: if self.track_provenance {
+                Some(Arc::new(answer_table_goal.clone()))
+            } else {
+                None
+            },
+        };
+        for mut constraint in answer_constraints {
+            if constraint.provenance.goal.is_none() {
+                constraint.provenance = provenance.clone();
+            }
+            implementation::push_constraint(&mut ex_clause, constraint);
+        }
+        ex_clause.sources.sources.extend(answer_sources.sources);
+
+        let constrained: Vec<Parameter> = self.infer
+            .constrained_vars(&pending_vars)
+            .into_iter()
+            .map(|var| var.to_parameter())
+            .collect();
+        debug!("constrained={:?}", constrained);
+        Ok((ex_clause, constrained))
+    }
+}
+
+impl TruncatingInferenceTable {
+    /// The body of `resolvent_clause`, which leaves the bindings it
+    /// makes in the inference table.
+    fn resolvent_clause_in_snapshot(
+        &mut self,
+        environment: &Arc<Environment>,
+        goal: &DomainGoal,
+        subst: &Substitution,
+        clause: &ProgramClause,
+    ) -> Fallible<Canonical<ExClause<SlgContext>>> {
+        // Relating the description at the top of this file to our situation:
+        //
+        // - `goal` G, except with binders for any existential variables.
+        //   - Also, we always select the first literal in `ex_clause.literals`, so `i` is 0.
+        // - `clause` is C, except with binders for any existential variables.
+
+        // C' in the description above is `consequence :- conditions`.
+        //
+        // Note that G and C' have no variables in common. A clause without
+        // binders is used as it is, rather than cloned, and the conditions
+        // of an instantiated one are moved into the resolvent.
+        let implication = match clause {
+            ProgramClause::Implies(implication) => Cow::Borrowed(implication),
+            ProgramClause::ForAll(implication) => {
+                Cow::Owned(self.infer.instantiate_binders_existentially(implication))
+            }
+        };
+        debug!("consequence = {:?}", implication.consequence);
+        debug!("conditions = {:?}", implication.conditions);
+
+        // Unify the selected literal Li with C'.
+        let unification_result = self.infer.unify_with_const_eval(
+            environment,
+            goal,
+            &implication.consequence,
+            self.hooks.const_eval(),
         )?;
-        ex_clause.constraints.extend(answer_constraints);
-        Ok(ex_clause)
+
+        // Final X-clause that we will return.
+        let mut ex_clause: ExClause<SlgContext> = ExClause {
+            subst: subst.clone(),
+            delayed_literals: vec![],
+            constraints: vec![],
+            subgoals: vec![],
+            sources: ClauseSources::default(),
+        };
+
+        // Record where the clause comes from, with the goal as the
+        // clause applies to it.
+        if self.record_sources {
+            let goal = self.infer.normalize_deep(goal);
+            let source = ambiguity::clause_source(&self.program, environment, &goal, clause);
+            let source = self.infer.canonicalize(&source).quantified;
+            ex_clause.sources.sources.push(source);
+        }
+
+        // Add the subgoals/region-constraints that unification gave us.
+        implementation::into_ex_clause(unification_result, &mut ex_clause);
+
+        // Add the `conditions` from the program clause into the result too.
+        let num_unification_subgoals = ex_clause.subgoals.len();
+        let mut push_condition = |c: Goal| {
+            let subgoal = match c {
+                Goal::Not(c) => Literal::Negative(InEnvironment::new(environment, *c)),
+                c => Literal::Positive(InEnvironment::new(environment, c)),
+            };
+            implementation::push_subgoal(&mut ex_clause, subgoal);
+        };
+        match implication {
+            Cow::Borrowed(implication) => {
+                implication.conditions.iter().cloned().for_each(&mut push_condition)
+            }
+            Cow::Owned(implication) => implication.conditions.into_iter().for_each(push_condition),
+        }
+
+        // The last subgoal is the one selected first, so the conditions
+        // to prove first go at the end. The sort is stable: conditions
+        // of the same priority keep their order.
+        if self.ordering == SubgoalOrdering::ByPriority {
+            ex_clause.subgoals[num_unification_subgoals..]
+                .sort_by_key(|subgoal| Reverse(subgoal_priority(subgoal)));
+        }
+
+        Ok(self.infer.canonicalize(&ex_clause).quantified)
+    }
+}
+
+/// The rank of a condition under `SubgoalOrdering::ByPriority`; lower
+/// ranks are proven first.
+fn subgoal_priority(subgoal: &Literal<SlgContext>) -> usize {
+    match subgoal {
+        Literal::Positive(InEnvironment { goal, .. }) => match goal {
+            Goal::Leaf(LeafGoal::DomainGoal(DomainGoal::Holds(WhereClause::Implemented(_)))) => 0,
+            Goal::Leaf(LeafGoal::DomainGoal(DomainGoal::Holds(WhereClause::ProjectionEq(_)))) => 1,
+            Goal::Leaf(LeafGoal::DomainGoal(DomainGoal::Normalize(_))) => 2,
+            _ => 3,
+        },
+        Literal::Negative(_) => 4,
     }
 }
 
 struct AnswerSubstitutor<'t> {
     table: &'t mut InferenceTable,
+    const_eval: Option<&'t dyn ConstEval>,
     environment: &'t Arc<Environment>,
     answer_subst: &'t Substitution,
     answer_binders: usize,
     pending_binders: usize,
     ex_clause: ExClause<SlgContext>,
+
+    /// The unbound variables of the pending goal unified with the
+    /// answer, each as the root of its unification class at the time.
+    pending_vars: Vec<ParameterInferenceVariable>,
+
+    /// Where we are within the answer and the pending goal, to report
+    /// structural mismatches.
+    path: Vec<PathStep>,
 }
 
 impl<'t> AnswerSubstitutor<'t> {
     fn substitute<T: Zip>(
         table: &mut InferenceTable,
+        const_eval: Option<&dyn ConstEval>,
         environment: &Arc<Environment>,
         answer_subst: &Substitution,
         ex_clause: ExClause<SlgContext>,
         answer: &T,
         pending: &T,
-    ) -> Fallible<ExClause<SlgContext>> {
+    ) -> Fallible<(ExClause<SlgContext>, Vec<ParameterInferenceVariable>)> {
         let mut this = AnswerSubstitutor {
             table,
+            const_eval,
             environment,
             answer_subst,
             ex_clause,
             answer_binders: 0,
             pending_binders: 0,
+            pending_vars: vec![],
+            path: vec![],
         };
         Zip::zip_with(&mut this, answer, pending)?;
-        Ok(this.ex_clause)
+
+        // A variable which occurs several times in the pending goal is
+        // unified with the answer as many times.
+        let mut pending_vars = this.pending_vars;
+        pending_vars.sort();
+        pending_vars.dedup();
+        Ok((this.ex_clause, pending_vars))
+    }
+
+    /// The start of zipping `answer` with `pending`, which is the same
+    /// for each kind of parameter. Returns `Ok(true)` if that has done
+    /// all there is to do.
+    fn zip_answer_var<V>(&mut self, answer: &V, pending: &V) -> Fallible<bool>
+    where
+        V: InferenceParameterValue,
+    {
+        if let Some(pending) = self.table.normalize_var(pending, self.pending_binders) {
+            Zip::zip_with(self, answer, &pending)?;
+            return Ok(true);
+        }
+
+        // If the answer has a variable here, then this is one of the
+        // "inputs" to the subgoal table. We need to extract the
+        // resulting answer that the subgoal found and unify it with
+        // the value from our "pending subgoal".
+        match answer.var_depth() {
+            Some(depth) => self.unify_free_answer_var(DebruijnIndex::new(depth), pending),
+            None => Ok(false),
+        }
     }
 
-    fn unify_free_answer_var(
+    fn unify_free_answer_var<V: ParameterValue>(
         &mut self,
-        answer_depth: usize,
-        pending: ParameterKind<&Ty, &Lifetime>,
+        answer_depth: DebruijnIndex,
+        pending: &V,
     ) -> Fallible<bool> {
-        // This variable is bound in the answer, not free, so it
-        // doesn't represent a reference into the answer substitution.
-        if answer_depth < self.answer_binders {
-            return Ok(false);
-        }
+        let answer_index = match answer_depth.shifted_out(self.answer_binders) {
+            Some(free) => free.depth(),
 
-        let answer_param = &self.answer_subst.parameters[answer_depth - self.answer_binders];
+            // This variable is bound in the answer, not free, so it
+            // doesn't represent a reference into the answer substitution.
+            None => return Ok(false),
+        };
+
+        let answer_param = &self.answer_subst.parameters[answer_index];
 
-        let pending_shifted = &pending
+        let pending_shifted = pending
             .down_shift(self.pending_binders)
             .unwrap_or_else(|_| {
                 panic!(
@@ -293,9 +455,29 @@ impl<'t> AnswerSubstitutor<'t> {
                 )
             });
 
+        // The pending value is usually an unbound variable, as
+        // `zip_answer_var` normalized it, unless the table goal was
+        // truncated here.
+        match pending_shifted.var_depth() {
+            Some(depth) => {
+                let var = V::kind(InferenceVariable::from_depth(depth));
+                let root = self.table.root_var(var);
+                self.pending_vars.push(root);
+            }
+            None => {
+                let free_vars = self.table.canonicalize(&pending_shifted).free_vars;
+                self.pending_vars.extend(free_vars);
+            }
+        }
+        let pending_shifted = &pending_shifted.into_parameter();
+
         implementation::into_ex_clause(
-            self.table
-                .unify(&self.environment, answer_param, pending_shifted)?,
+            self.table.unify_with_const_eval(
+                &self.environment,
+                answer_param,
+                pending_shifted,
+                self.const_eval,
+            )?,
             &mut self.ex_clause,
         );
 
@@ -317,22 +499,24 @@ impl<'t> AnswerSubstitutor<'t> {
         );
         Ok(())
     }
+
+    /// The answer and the pending goal are instances of the same
+    /// goal, so they can only differ where the answer has a variable.
+    fn structural_mismatch<T: Zip>(&self, answer: &T, pending: &T) -> ! {
+        let diff = diff_within(self.path.clone(), self.answer_binders, answer, pending);
+        panic!(
+            "structural mismatch between answer `{:?}` and pending goal `{:?}`:\n{}",
+            answer,
+            pending,
+            display_diff(&diff),
+        )
+    }
 }
 
 impl<'t> Zipper for AnswerSubstitutor<'t> {
     fn zip_tys(&mut self, answer: &Ty, pending: &Ty) -> Fallible<()> {
-        if let Some(pending) = self.table.normalize_shallow(pending, self.pending_binders) {
-            return Zip::zip_with(self, answer, &pending);
-        }
-
-        // If the answer has a variable here, then this is one of the
-        // "inputs" to the subgoal table. We need to extract the
-        // resulting answer that the subgoal found and unify it with
-        // the value from our "pending subgoal".
-        if let Ty::Var(answer_depth) = answer {
-            if self.unify_free_answer_var(*answer_depth, ParameterKind::Ty(pending))? {
-                return Ok(());
-            }
+        if self.zip_answer_var(answer, pending)? {
+            return Ok(());
         }
 
         // Otherwise, the answer and the selected subgoal ought to be a perfect match for
@@ -365,22 +549,13 @@ impl<'t> Zipper for AnswerSubstitutor<'t> {
             | (Ty::Apply(_), _)
             | (Ty::Projection(_), _)
             | (Ty::UnselectedProjection(_), _)
-            | (Ty::ForAll(_), _) => panic!(
-                "structural mismatch between answer `{:?}` and pending goal `{:?}`",
-                answer, pending,
-            ),
+            | (Ty::ForAll(_), _) => self.structural_mismatch(answer, pending),
         }
     }
 
     fn zip_lifetimes(&mut self, answer: &Lifetime, pending: &Lifetime) -> Fallible<()> {
-        if let Some(pending) = self.table.normalize_lifetime(pending, self.pending_binders) {
-            return Zip::zip_with(self, answer, &pending);
-        }
-
-        if let Lifetime::Var(answer_depth) = answer {
-            if self.unify_free_answer_var(*answer_depth, ParameterKind::Lifetime(pending))? {
-                return Ok(());
-            }
+        if self.zip_answer_var(answer, pending)? {
+            return Ok(());
         }
 
         match (answer, pending) {
@@ -393,10 +568,37 @@ impl<'t> Zipper for AnswerSubstitutor<'t> {
                 Ok(())
             }
 
-            (Lifetime::Var(_), _) | (Lifetime::ForAll(_), _) => panic!(
-                "structural mismatch between answer `{:?}` and pending goal `{:?}`",
-                answer, pending,
-            ),
+            (Lifetime::Static, Lifetime::Static) => Ok(()),
+
+            (Lifetime::Var(_), _) | (Lifetime::ForAll(_), _) | (Lifetime::Static, _) => {
+                self.structural_mismatch(answer, pending)
+            }
+        }
+    }
+
+    fn zip_consts(&mut self, answer: &Const, pending: &Const) -> Fallible<()> {
+        if self.zip_answer_var(answer, pending)? {
+            return Ok(());
+        }
+
+        match (answer, pending) {
+            (Const::Var(answer_depth), Const::Var(pending_depth)) => {
+                self.assert_matching_vars(*answer_depth, *pending_depth)
+            }
+
+            (Const::ForAll(_), Const::ForAll(_)) | (Const::Value(_), Const::Value(_)) => {
+                assert_eq!(answer, pending);
+                Ok(())
+            }
+
+            (Const::Unevaluated(answer), Const::Unevaluated(pending)) => {
+                Zip::zip_with(self, answer, pending)
+            }
+
+            (Const::Var(_), _)
+            | (Const::ForAll(_), _)
+            | (Const::Value(_), _)
+            | (Const::Unevaluated(_), _) => self.structural_mismatch(answer, pending),
         }
     }
 
@@ -411,4 +613,14 @@ impl<'t> Zipper for AnswerSubstitutor<'t> {
         self.pending_binders -= pending.binders.len();
         Ok(())
     }
+
+    fn zip_child<T>(&mut self, step: PathStep, answer: &T, pending: &T) -> Fallible<()>
+    where
+        T: Zip + ?Sized,
+    {
+        self.path.push(step);
+        let result = Zip::zip_with(self, answer, pending);
+        self.path.pop();
+        result
+    }
 }
@@ -1,12 +1,18 @@
 #![cfg(test)]
 
 use crate::ir;
-use crate::solve::slg::implementation::SlgContext;
+use crate::solve::infer::InferenceTable;
+use crate::solve::slg::implementation::{SlgContext, TruncatingInferenceTable};
 
+use chalk_engine::context::{AnswerStream, ResolventOps, UnificationOps};
 use chalk_engine::forest::Forest;
+use chalk_engine::{
+    AnswerStreamState, CannotProveReason, DelayedLiteral, ExClause, Literal, TableIndex,
+};
+use lalrpop_intern::intern;
 use std::sync::Arc;
 use test_util::*;
-use solve::SolverChoice;
+use solve::{SolverChoice, SubgoalOrdering};
 
 macro_rules! test {
     (program $program:tt $(goal $goal:tt first $n:tt with max $depth:tt { $expected:expr })*) => {
@@ -265,13 +271,13 @@ fn flounder() {
                             constraints: []
                         },
                         binders: [
-                            Ty(U0)
+                            U0
                         ]
                     },
                     delayed_literals: DelayedLiteralSet {
                         delayed_literals: {
                             CannotProve(
-                                ()
+                                Floundered
                             )
                         }
                     }
@@ -328,7 +334,7 @@ fn only_draw_so_many_blow_up() {
         goal {
             exists<T> { T: Foo }
         } fixed 2 with max 10 {
-            "Some(Ambig(Definite(Canonical { value: [?0 := Vec<?0>], binders: [Ty(U0)] })))"
+            "Some(Ambig(Definite(Canonical { value: [?0 := Vec<?0>], binders: [U0] })))"
         }
     }
 }
@@ -447,7 +453,7 @@ fn subgoal_cycle_uninhabited() {
                     delayed_literals: DelayedLiteralSet {
                         delayed_literals: {
                             CannotProve(
-                                ()
+                                Floundered
                             )
                         }
                     }
@@ -483,7 +489,7 @@ fn subgoal_cycle_uninhabited() {
                 Answer {
                     subst: Canonical {
                         value: ConstrainedSubst {
-                            subst: [?0 := !1],
+                            subst: [?0 := !1_0],
                             constraints: []
                         },
                         binders: []
@@ -551,7 +557,7 @@ fn basic_region_constraint_from_positive_impl() {
                             constraints: [
                                 InEnvironment {
                                     environment: Env([]),
-                                    goal: '!2 == '!1
+                                    goal: '!1_0 == '!1_1
                                 }
                             ]
                         },
@@ -744,10 +750,10 @@ fn example_3_3_EWFS() {
                     delayed_literals: DelayedLiteralSet {
                         delayed_literals: {
                             Negative(
-                                TableIndex(1)
+                                TableIndex(6)
                             ),
                             Negative(
-                                TableIndex(6)
+                                TableIndex(1)
                             )
                         }
                     }
@@ -879,13 +885,13 @@ fn cached_answers_1() {
                             constraints: []
                         },
                         binders: [
-                            Ty(U0)
+                            U0
                         ]
                     },
                     delayed_literals: DelayedLiteralSet {
                         delayed_literals: {
                             CannotProve(
-                                ()
+                                Truncated
                             )
                         }
                     }
@@ -969,13 +975,13 @@ fn cached_answers_2() {
                             constraints: []
                         },
                         binders: [
-                            Ty(U0)
+                            U0
                         ]
                     },
                     delayed_literals: DelayedLiteralSet {
                         delayed_literals: {
                             CannotProve(
-                                ()
+                                Truncated
                             )
                         }
                     }
@@ -1047,13 +1053,13 @@ fn cached_answers_3() {
                             constraints: []
                         },
                         binders: [
-                            Ty(U0)
+                            U0
                         ]
                     },
                     delayed_literals: DelayedLiteralSet {
                         delayed_literals: {
                             CannotProve(
-                                ()
+                                Truncated
                             )
                         }
                     }
@@ -1114,3 +1120,597 @@ fn negative_answer_delayed_literal() {
         }
     }
 }
+
+/// Enumerates the answers to `goal_text` through the answer iterator,
+/// returning whether each one is trivial.
+fn trivial_answer_flags(program_text: &str, goal_text: &str) -> Vec<bool> {
+    let program = &Arc::new(parse_and_lower_program(program_text, SolverChoice::default()).unwrap());
+    let env = &Arc::new(program.environment());
+    ir::tls::set_current_program(&program, || {
+        let goal = parse_and_lower_goal(&program, goal_text).unwrap();
+        let peeled_goal = goal.into_peeled_goal();
+        let mut forest = Forest::new(SlgContext::new(env, 10));
+        let mut answers = forest.iter_answers(&peeled_goal);
+        let mut flags = vec![];
+        while let Some(answer) = answers.next_answer() {
+            flags.push(answer.trivial);
+        }
+        flags
+    })
+}
+
+/// Enumerates all the answers to `goal_text`, with the given maximum
+/// size, returning the state the answer iterator ends in.
+fn answer_stream_end_state(
+    program_text: &str,
+    goal_text: &str,
+    max_size: usize,
+) -> Option<AnswerStreamState> {
+    let program = &Arc::new(parse_and_lower_program(program_text, SolverChoice::default()).unwrap());
+    let env = &Arc::new(program.environment());
+    ir::tls::set_current_program(&program, || {
+        let goal = parse_and_lower_goal(&program, goal_text).unwrap();
+        let peeled_goal = goal.into_peeled_goal();
+        let mut forest = Forest::new(SlgContext::new(env, max_size));
+        let mut answers = forest.iter_answers(&peeled_goal);
+        assert_eq!(answers.end_state(), None);
+        while answers.next_answer().is_some() {}
+        answers.end_state()
+    })
+}
+
+#[test]
+fn answer_stream_end_states() {
+    let program_text = "
+        #[auto] trait Send { }
+        trait Sour { }
+        trait Sweet { }
+        struct Lemon { }
+        struct Sugar { }
+        struct HotSauce<T> { }
+        struct Foo { }
+        impl !Send for Foo { }
+
+        forall<> { Lemon: Sour }
+        forall<> { Sugar: Sweet }
+        forall<T> { HotSauce<T>: Sour if T: Sour }
+        forall { Foo: Send if not { Foo: Send } }
+    ";
+
+    assert_eq!(
+        answer_stream_end_state(program_text, "exists<T> { T: Sweet }", 10),
+        Some(AnswerStreamState::Complete)
+    );
+    assert_eq!(
+        answer_stream_end_state(program_text, "Foo: Sour", 10),
+        Some(AnswerStreamState::Complete)
+    );
+
+    // A negative goal with free existential variables flounders.
+    assert_eq!(
+        answer_stream_end_state(program_text, "exists<T> { not { T: Sour } }", 10),
+        Some(AnswerStreamState::Floundered(CannotProveReason::Floundered))
+    );
+    assert_eq!(
+        answer_stream_end_state(program_text, "Foo: Send", 10),
+        Some(AnswerStreamState::Floundered(CannotProveReason::NegativeCoinductive))
+    );
+
+    // `HotSauce<HotSauce<Lemon>>` is too large, and stands for all
+    // the larger answers.
+    assert_eq!(
+        answer_stream_end_state(program_text, "exists<T> { T: Sour }", 2),
+        Some(AnswerStreamState::TruncatedAnswersPossible)
+    );
+}
+
+#[test]
+fn trivial_answers() {
+    let program_text = "
+        trait Foo { }
+        struct u32 { }
+        struct Vec<T> { }
+        impl Foo for u32 { }
+        impl<T> Foo for Vec<T> { }
+    ";
+
+    // `[?0 := ?0]` says nothing about `T`.
+    assert_eq!(trivial_answer_flags(program_text, "exists<T> { T = T }"), vec![true]);
+
+    // Neither `[?0 := u32]` nor `[?0 := Vec<?0>]` is trivial.
+    assert_eq!(trivial_answer_flags(program_text, "exists<T> { T: Foo }"), vec![false, false]);
+
+    // No variables at all: the empty substitution is trivial.
+    assert_eq!(trivial_answer_flags(program_text, "u32: Foo"), vec![true]);
+}
+
+/// A condition that appears twice in a clause is only added once to
+/// the resolvent, so the strand only proves it once.
+#[test]
+fn redundant_conditions() {
+    let program = &Arc::new(
+        parse_and_lower_program(
+            "
+            trait Sized { }
+            trait Foo { }
+            struct u32 { }
+            impl Sized for u32 { }
+
+            forall<T> { T: Foo if T: Sized, T: Sized, T = T, T: Sized }
+            ",
+            SolverChoice::default()
+        ).unwrap()
+    );
+    let env = &Arc::new(program.environment());
+    ir::tls::set_current_program(&program, || {
+        let goal = match *parse_and_lower_goal(&program, "u32: Foo").unwrap() {
+            ir::Goal::Leaf(ir::LeafGoal::DomainGoal(goal)) => goal,
+            goal => panic!("unexpected goal {:?}", goal),
+        };
+        let mut table = TruncatingInferenceTable::new(env, 10, InferenceTable::new());
+        let ex_clause = table.resolvent_clause(
+            &ir::Environment::new(),
+            &goal,
+            &ir::Substitution { parameters: Default::default() },
+            &program.custom_clauses[0],
+        ).unwrap();
+        assert_eq!(ex_clause.value.subgoals.len(), 2);
+    });
+}
+
+/// Repeating a condition does not make the strands of a clause select
+/// more subgoals, while each distinct condition does.
+#[test]
+fn redundant_conditions_spare_subgoal_selections() {
+    let subgoal_selections = |conditions: &str| {
+        let program_text = format!(
+            "
+            trait Sized {{ }}
+            trait Clone {{ }}
+            trait Copy {{ }}
+            trait Foo {{ }}
+            struct u32 {{ }}
+            impl Sized for u32 {{ }}
+            impl Clone for u32 {{ }}
+            impl Copy for u32 {{ }}
+
+            forall<T> {{ T: Foo if {} }}
+            ",
+            conditions
+        );
+        let program =
+            &Arc::new(parse_and_lower_program(&program_text, SolverChoice::default()).unwrap());
+        let env = &Arc::new(program.environment());
+        ir::tls::set_current_program(&program, || {
+            let goal = parse_and_lower_goal(&program, "u32: Foo").unwrap().into_peeled_goal();
+            let mut forest = Forest::new(SlgContext::new(env, 10));
+            let solution = format!("{:?}", forest.solve(&goal));
+            assert!(solution.starts_with("Some(Unique("), "{}", solution);
+            forest.num_subgoal_selections()
+        })
+    };
+
+    let single = subgoal_selections("T: Sized");
+    assert_eq!(subgoal_selections("T: Sized, T: Sized, T: Sized"), single);
+    assert!(subgoal_selections("T: Sized, T: Clone, T: Copy") > single);
+}
+
+/// A clause that fails to unify with the goal leaves nothing behind in
+/// the inference table, so it cannot affect the clauses tried after it.
+#[test]
+fn failed_resolvent_is_rolled_back() {
+    let program = &Arc::new(
+        parse_and_lower_program(
+            "
+            trait Foo<T> { }
+            struct u32 { }
+            struct i32 { }
+
+            forall<T> { i32: Foo<T> }
+            forall<T> { T: Foo<T> }
+            ",
+            SolverChoice::default()
+        ).unwrap()
+    );
+    let env = &Arc::new(program.environment());
+    ir::tls::set_current_program(&program, || {
+        let goal = match *parse_and_lower_goal(&program, "u32: Foo<u32>").unwrap() {
+            ir::Goal::Leaf(ir::LeafGoal::DomainGoal(goal)) => goal,
+            goal => panic!("unexpected goal {:?}", goal),
+        };
+        let exists = match *parse_and_lower_goal(&program, "exists<T> { T: Foo<T> }").unwrap() {
+            ir::Goal::Quantified(ir::QuantifierKind::Exists, binders) => binders,
+            goal => panic!("unexpected goal {:?}", goal),
+        };
+        let subst = &ir::Substitution { parameters: Default::default() };
+
+        let mut table = TruncatingInferenceTable::new(env, 10, InferenceTable::new());
+        assert!(table.resolvent_clause(
+            &ir::Environment::new(),
+            &goal,
+            subst,
+            &program.custom_clauses[0],
+        ).is_err());
+        let ex_clause = table.resolvent_clause(
+            &ir::Environment::new(),
+            &goal,
+            subst,
+            &program.custom_clauses[1],
+        ).unwrap();
+        assert!(ex_clause.value.subgoals.is_empty());
+
+        // The variable instantiated for the first clause is gone, so the
+        // next fresh variable is the same as in an unused table.
+        let mut unused = TruncatingInferenceTable::new(env, 10, InferenceTable::new());
+        assert_eq!(
+            format!("{:?}", table.instantiate_binders_existentially(&exists)),
+            format!("{:?}", unused.instantiate_binders_existentially(&exists)),
+        );
+    });
+}
+
+/// Applying an answer reports the variables of the pending goal which
+/// it constrained: the answer pins `?X` and `?Y`, but only unifies `?Z`
+/// with a fresh variable.
+#[test]
+fn answer_constrained_variables() {
+    let program = &Arc::new(
+        parse_and_lower_program(
+            "
+            trait Baz { }
+            struct u32 { }
+            struct i32 { }
+            struct Triple<A, B, C> { }
+            ",
+            SolverChoice::default()
+        ).unwrap()
+    );
+    let env = &Arc::new(program.environment());
+    ir::tls::set_current_program(&program, || {
+        let goal = "exists<X, Y, Z> { Triple<X, Y, Z>: Baz }";
+        let exists = match *parse_and_lower_goal(&program, goal).unwrap() {
+            ir::Goal::Quantified(ir::QuantifierKind::Exists, binders) => binders,
+            goal => panic!("unexpected goal {:?}", goal),
+        };
+        let ty = |name: &str| ir::Ty::Apply(ir::ApplicationTy {
+            name: ir::TypeName::ItemId(program.type_ids[&intern(name)]),
+            parameters: vec![],
+        });
+
+        let mut table = TruncatingInferenceTable::new(env, 10, InferenceTable::new());
+        let goal = table.instantiate_binders_existentially(&exists);
+        let goal = ir::InEnvironment::new(&ir::Environment::new(), goal);
+        let table_goal = table.canonicalize_goal(&goal);
+
+        // `for<type> [?0 := u32, ?1 := i32, ?2 := ?0]`
+        let answer = ir::Canonical {
+            value: ir::ConstrainedSubst {
+                subst: ir::Substitution {
+                    parameters: vec![
+                        ir::ParameterKind::Ty(ty("u32")),
+                        ir::ParameterKind::Ty(ty("i32")),
+                        ir::ParameterKind::Ty(ir::Ty::Var(0)),
+                    ].into_iter().collect(),
+                },
+                constraints: vec![],
+                sources: Default::default(),
+            },
+            binders: vec![ir::ParameterKind::Ty(ir::UniverseIndex::root())],
+        };
+        let ex_clause = ExClause {
+            subst: ir::Substitution { parameters: Default::default() },
+            delayed_literals: vec![],
+            constraints: vec![],
+            subgoals: vec![],
+            sources: Default::default(),
+        };
+        let (_, constrained) = table
+            .apply_answer_subst(ex_clause, &goal, &table_goal, &answer)
+            .unwrap();
+        assert_eq!(
+            constrained,
+            vec![ir::ParameterKind::Ty(ir::Ty::Var(0)), ir::ParameterKind::Ty(ir::Ty::Var(1))]
+        );
+    });
+}
+
+/// Proving the trait conditions of a clause before its projections
+/// finds the same solution, but pins the type down before every impl of
+/// `Iterator` has to be tried.
+#[test]
+fn prioritized_subgoals() {
+    let program = &Arc::new(
+        parse_and_lower_program(
+            "
+            trait Iterator { type Item; }
+            trait Special { }
+            trait Foo<U> { }
+            struct A { }
+            struct B { }
+            struct C { }
+            struct D { }
+            struct E { }
+            impl Iterator for A { type Item = A; }
+            impl Iterator for B { type Item = B; }
+            impl Iterator for C { type Item = C; }
+            impl Iterator for D { type Item = D; }
+            impl Iterator for E { type Item = E; }
+            impl Special for C { }
+
+            forall<T, U> { T: Foo<U> if Normalize(<T as Iterator>::Item -> U), T: Special }
+            ",
+            SolverChoice::default()
+        ).unwrap()
+    );
+    let env = &Arc::new(program.environment());
+    ir::tls::set_current_program(&program, || {
+        let goal = parse_and_lower_goal(&program, "exists<T, U> { T: Foo<U> }").unwrap();
+        let peeled_goal = goal.into_peeled_goal();
+        let solve = |ordering| {
+            let context = SlgContext::new(env, 10).with_subgoal_ordering(ordering);
+            let mut forest = Forest::new(context);
+            let solution = format!("{:?}", forest.solve(&peeled_goal));
+            (solution, forest.num_strand_steps())
+        };
+
+        let (as_written, as_written_steps) = solve(SubgoalOrdering::AsWritten);
+        let (by_priority, by_priority_steps) = solve(SubgoalOrdering::ByPriority);
+        assert_eq!(as_written, by_priority);
+        assert!(
+            by_priority_steps < as_written_steps,
+            "{} strand steps by priority, {} as written",
+            by_priority_steps,
+            as_written_steps,
+        );
+    });
+}
+
+/// The two clauses give the same answer, but the name that `'a` is
+/// equated with lands in a different universe for each, depending on
+/// how many `forall` goals are instantiated before it. The answers are
+/// the same up to the numbering of their universes, so the second is
+/// discarded.
+#[test]
+fn answers_modulo_universes() {
+    let program = &Arc::new(
+        parse_and_lower_program(
+            "
+            trait Foo<'a> { }
+            struct S { }
+
+            forall<'a> { S: Foo<'a> if forall<'b> { 'a = 'b } }
+            forall<'a> { S: Foo<'a> if forall<'c> { forall<'b> { 'a = 'b } } }
+            ",
+            SolverChoice::default()
+        ).unwrap()
+    );
+    let env = &Arc::new(program.environment());
+    ir::tls::set_current_program(&program, || {
+        let goal = parse_and_lower_goal(&program, "exists<'a> { S: Foo<'a> }").unwrap();
+        let peeled_goal = goal.into_peeled_goal();
+        let mut forest = Forest::new(SlgContext::new(env, 10));
+        let solution = format!("{:?}", forest.solve(&peeled_goal));
+        assert!(solution.starts_with("Some(Unique("), "{}", solution);
+        assert_eq!(forest.num_cached_answers_for_goal(&peeled_goal), 1);
+        assert_eq!(forest.num_duplicate_answers(), 1);
+    });
+}
+
+/// `A` and `B` are names of the goal, in universes of their own: the
+/// answers `?X := A` and `?X := B` differ only in the universe of the
+/// name, but they are different answers nonetheless.
+#[test]
+fn answers_modulo_universes_of_the_goal() {
+    let program = &Arc::new(
+        parse_and_lower_program(
+            "
+            trait Choose<A, B, X> { }
+            struct Unit { }
+
+            forall<A, B> { Unit: Choose<A, B, A> }
+            forall<A, B> { Unit: Choose<A, B, B> }
+            ",
+            SolverChoice::default()
+        ).unwrap()
+    );
+    let env = &Arc::new(program.environment());
+    ir::tls::set_current_program(&program, || {
+        let goal = "forall<A> { forall<B> { exists<X> { Unit: Choose<A, B, X> } } }";
+        let goal = parse_and_lower_goal(&program, goal).unwrap();
+        let peeled_goal = goal.into_peeled_goal();
+        let mut forest = Forest::new(SlgContext::new(env, 10));
+        let solution = format!("{:?}", forest.solve(&peeled_goal));
+        assert_eq!(solution, "Some(Ambig(Unknown))");
+        assert_eq!(forest.num_cached_answers_for_goal(&peeled_goal), 2);
+        assert_eq!(forest.num_duplicate_answers(), 0);
+    });
+}
+
+/// Canonicalizing an ex-clause folds all of its fields at once, so
+/// instantiating the result gives back the same ex-clause, up to the
+/// names of its variables.
+#[test]
+fn ex_clause_canonical_round_trip() {
+    let program = &Arc::new(
+        parse_and_lower_program(
+            "
+            struct Vec<T> { }
+            ",
+            SolverChoice::default()
+        ).unwrap()
+    );
+    ir::tls::set_current_program(&program, || {
+        let vec = |ty| ir::Ty::Apply(ir::ApplicationTy {
+            name: ir::TypeName::ItemId(program.type_ids[&intern("Vec")]),
+            parameters: vec![ir::ParameterKind::Ty(ty)],
+        });
+        let environment = &ir::Environment::new();
+
+        let mut infer = InferenceTable::new();
+        let a = infer.new_variable(ir::UniverseIndex::root()).to_ty();
+        let b = infer.new_variable(ir::UniverseIndex::root()).to_ty();
+        let c = infer.new_variable(ir::UniverseIndex::root()).to_ty();
+        let l = infer.new_variable(ir::UniverseIndex::root()).to_lifetime();
+
+        // `for<type> [?0 := Vec<?0>]`
+        let answer = ir::Canonical {
+            value: ir::ConstrainedSubst {
+                subst: ir::Substitution {
+                    parameters: vec![ir::ParameterKind::Ty(vec(ir::Ty::Var(0)))]
+                        .into_iter()
+                        .collect(),
+                },
+                constraints: vec![],
+                sources: Default::default(),
+            },
+            binders: vec![ir::ParameterKind::Ty(ir::UniverseIndex::root())],
+        };
+        let ex_clause: ExClause<SlgContext> = ExClause {
+            subst: ir::Substitution {
+                parameters: vec![ir::ParameterKind::Ty(vec(c.clone()))].into_iter().collect(),
+            },
+            delayed_literals: vec![
+                DelayedLiteral::CannotProve(CannotProveReason::Truncated),
+                DelayedLiteral::Negative(TableIndex::from(0)),
+                DelayedLiteral::Positive(TableIndex::from(1), answer),
+            ],
+            constraints: vec![ir::RegionConstraint::new(ir::InEnvironment::new(
+                environment,
+                ir::Constraint::TypeOutlives(vec(b.clone()), l),
+            ))],
+            subgoals: vec![
+                Literal::Positive(ir::InEnvironment::new(
+                    environment,
+                    ir::Goal::Leaf(ir::LeafGoal::EqGoal(ir::EqGoal {
+                        a: ir::ParameterKind::Ty(a.clone()),
+                        b: ir::ParameterKind::Ty(vec(b)),
+                    })),
+                )),
+                Literal::Negative(ir::InEnvironment::new(
+                    environment,
+                    ir::Goal::Leaf(ir::LeafGoal::EqGoal(ir::EqGoal {
+                        a: ir::ParameterKind::Ty(c),
+                        b: ir::ParameterKind::Ty(a),
+                    })),
+                )),
+            ],
+            sources: Default::default(),
+        };
+
+        let canonical = infer.canonicalize(&ex_clause).quantified;
+        assert_eq!(canonical.binders.len(), 4);
+
+        let (mut instantiated_infer, _, instantiated) =
+            InferenceTable::from_canonical(1, &canonical);
+        let round_trip = instantiated_infer.canonicalize(&instantiated).quantified;
+        assert_eq!(round_trip.binders, canonical.binders);
+        assert_eq!(round_trip.value.subst, canonical.value.subst);
+        assert_eq!(round_trip.value.delayed_literals, ex_clause.delayed_literals);
+        assert_eq!(round_trip.value.constraints, canonical.value.constraints);
+        assert_eq!(round_trip.value.subgoals, canonical.value.subgoals);
+    });
+}
+
+/// The variables of an answer are named the same whatever the order in
+/// which they were created and its constraints imposed, so that the
+/// same answer found in different ways is a duplicate.
+#[test]
+fn answers_alpha_normalized() {
+    let program = &Arc::new(parse_and_lower_program("", SolverChoice::default()).unwrap());
+    let env = &Arc::new(program.environment());
+    ir::tls::set_current_program(&program, || {
+        let environment = &ir::Environment::new();
+        let answer = |reversed: bool| {
+            let mut infer = InferenceTable::new();
+            let universe = infer.new_universe();
+            let ty = infer.new_variable(ir::UniverseIndex::root()).to_ty();
+            let konst = infer.new_variable(ir::UniverseIndex::root()).to_const();
+            let (a, b) = if reversed {
+                let b = infer.new_variable(ir::UniverseIndex::root()).to_lifetime();
+                let a = infer.new_variable(ir::UniverseIndex::root()).to_lifetime();
+                (a, b)
+            } else {
+                let a = infer.new_variable(ir::UniverseIndex::root()).to_lifetime();
+                let b = infer.new_variable(ir::UniverseIndex::root()).to_lifetime();
+                (a, b)
+            };
+            let placeholder = |idx| ir::PlaceholderIndex { ui: universe, idx }.to_lifetime();
+            let outlives = |a, b| {
+                ir::RegionConstraint::new(ir::InEnvironment::new(
+                    environment,
+                    ir::Constraint::LifetimeOutlives(a, b),
+                ))
+            };
+
+            // `[?0 := ?T, ?1 := ?C]`, with `?A: !1_0` and `?B: !1_1`.
+            let subst = ir::Substitution {
+                parameters: vec![ir::ParameterKind::Ty(ty), ir::ParameterKind::Const(konst)]
+                    .into_iter()
+                    .collect(),
+            };
+            let mut constraints = vec![outlives(a, placeholder(0)), outlives(b, placeholder(1))];
+            if reversed {
+                constraints.reverse();
+            }
+            let mut table = TruncatingInferenceTable::new(env, 10, infer);
+            table.canonicalize_constrained_subst(subst, constraints, Default::default())
+        };
+
+        let canonical_answer = answer(false);
+        assert_eq!(format!("{:?}", canonical_answer), format!("{:?}", answer(true)));
+        assert_eq!(canonical_answer.binders.len(), 4);
+
+        // Instantiating the answer and canonicalizing it again gives it
+        // back as it was.
+        let (infer, _, instantiated) = InferenceTable::from_canonical(2, &canonical_answer);
+        let mut table = TruncatingInferenceTable::new(env, 10, infer);
+        let round_trip = table.canonicalize_constrained_subst(
+            instantiated.subst,
+            instantiated.constraints,
+            instantiated.sources,
+        );
+        assert_eq!(round_trip, canonical_answer);
+    });
+}
+
+/// A forest reused for many goals, with a bound on the tables it keeps,
+/// evicts those of the goals asked longest ago, and solves them again
+/// if they are asked again.
+#[test]
+fn bounded_table_cache() {
+    let structs: Vec<String> = (0..10).map(|i| format!("S{}", i)).collect();
+    let mut program_text = "trait Foo { }".to_string();
+    for name in &structs {
+        program_text.push_str(&format!(" struct {0} {{ }} impl Foo for {0} {{ }}", name));
+    }
+    let program =
+        &Arc::new(parse_and_lower_program(&program_text, SolverChoice::default()).unwrap());
+    let env = &Arc::new(program.environment());
+    ir::tls::set_current_program(&program, || {
+        let goal = |name: &str| {
+            parse_and_lower_goal(&program, &format!("{}: Foo", name)).unwrap().into_peeled_goal()
+        };
+        let mut forest = Forest::new(SlgContext::new(env, 10)).with_max_cached_tables(3);
+        let mut fresh_forest = Forest::new(SlgContext::new(env, 10));
+        let expected = format!("{:?}", fresh_forest.solve(&goal("S0")));
+        assert!(expected.starts_with("Some(Unique("), "{}", expected);
+
+        // The tables of the last query are only evicted as the next one
+        // starts.
+        let tables_per_query = fresh_forest.num_tables();
+
+        for name in &structs {
+            assert_eq!(format!("{:?}", forest.solve(&goal(name))), expected);
+            assert!(
+                forest.num_tables() <= 3 + tables_per_query,
+                "{} tables",
+                forest.num_tables()
+            );
+        }
+        assert!(forest.num_evicted_tables() > 0);
+
+        let evicted = forest.num_evicted_tables();
+        assert_eq!(format!("{:?}", forest.solve(&goal("S0"))), expected);
+        assert!(forest.num_evicted_tables() > evicted);
+    });
+}
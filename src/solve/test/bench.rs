@@ -1,17 +1,35 @@
-//! Benchmarking tests.
+//! Benchmarking tests. Each benchmark also prints the work the solver
+//! does for its goal (see `SolverStats`), which `--nocapture` shows.
 
 extern crate test;
 use self::test::Bencher;
 
 use ir;
-use solve::SolverChoice;
+use solve::{ConstOverflow, Scheduling, SolverChoice, SubgoalOrdering, TruncationMeasure};
 use std::sync::Arc;
+use test_util::generate::{self, GeneratedProgram};
 
 use super::{parse_and_lower_program,
             parse_and_lower_goal,
-            assert_result};
+            assert_result,
+            with_solver_options,
+            CYCLEY};
+
+const CYCLEY_GOAL: &str = "
+forall<T> {
+    if (
+        <Slice<T> as SliceExt>::Item: Clone;
+        <Slice<T> as SliceExt>::Item: Sized;
+        T: Clone;
+        T: Sized
+    ) {
+        T: Sized
+    }
+}
+";
 
 fn run_bench(
+    name: &str,
     program_text: &str,
     solver_choice: SolverChoice,
     goal_text: &str,
@@ -28,84 +46,107 @@ fn run_bench(
         let result = solver_choice.solve_root_goal(&env, &peeled_goal);
 
         // Check expectation.
-        assert_result(&result, expected);
+        assert_result(&result, expected, false);
+
+        // Record the work of the SLG solver alongside the time it takes:
+        // there is none for the goals proven by the fast path.
+        let slg_choice = with_solver_options(solver_choice, &[("ground_fast_path", "false")]);
+        let mut solver = slg_choice.into_solver(&env);
+        solver.solve_root_goal(&peeled_goal).unwrap();
+        println!("{}: {:?}", name, solver.stats());
 
         // Then do it many times to measure time.
         bencher.iter(|| solver_choice.solve_root_goal(&env, &peeled_goal));
     });
 }
 
-const CYCLEY: &str = "
-trait AsRef<T> { }
-trait Clone { }
-trait Copy where Self: Clone { }
-trait Sized { }
-
-struct i32 { }
-impl Copy for i32 { }
-impl Clone for i32 { }
-impl Sized for i32 { }
-
-struct u32 { }
-impl Copy for u32 { }
-impl Clone for u32 { }
-impl Sized for u32 { }
-
-struct Rc<T> { }
-impl<T> Clone for Rc<T> { }
-impl<T> Sized for Rc<T> { }
-
-struct Box<T> { }
-impl<T> AsRef<T> for Box<T> where T: Sized { }
-impl<T> Clone for Box<T> where T: Clone { }
-impl<T> Sized for Box<T> { }
-
-// Meant to be [T]
-struct Slice<T> where T: Sized { }
-impl<T> Sized for Slice<T> { }
-impl<T> AsRef<Slice<T>> for Slice<T> where T: Sized { }
-
-struct Vec<T> where T: Sized { }
-impl<T> AsRef<Slice<T>> for Vec<T> where T: Sized { }
-impl<T> AsRef<Vec<T>> for Vec<T> where T: Sized { }
-impl<T> Clone for Vec<T> where T: Clone, T: Sized { }
-impl<T> Sized for Vec<T> where T: Sized { }
-
-trait SliceExt
-  where <Self as SliceExt>::Item: Clone
-{
-  type Item;
-}
-
-impl<T> SliceExt for Slice<T>
-  where T: Clone
-{
-  type Item = T;
-}
-";
-
-const CYCLEY_GOAL: &str = "
-forall<T> {
-    if (
-        <Slice<T> as SliceExt>::Item: Clone;
-        <Slice<T> as SliceExt>::Item: Sized;
-        T: Clone;
-        T: Sized
-    ) {
-        T: Sized
-    }
-}
-";
-
 #[bench]
 fn cycley_slg(b: &mut Bencher) {
     run_bench(
+        "cycley_slg",
         CYCLEY,
         SolverChoice::SLG {
             max_size: 20,
+            truncation: TruncationMeasure::default(),
+            scheduling: Scheduling::default(),
+            ordering: SubgoalOrdering::default(),
+            simplify_goals: false,
+            max_projection_depth: 64,
+            max_type_depth: 256,
+            track_provenance: false,
+            const_overflow: ConstOverflow::default(),
+            ground_fast_path: true,
+            max_cached_tables: usize::MAX,
         },
         CYCLEY_GOAL,
         b,
         "Unique"
     );
 }
+
+fn run_generated_bench(name: &str, generated: GeneratedProgram, bencher: &mut Bencher) {
+    let GeneratedProgram { program, goal, expected } = generated;
+    run_bench(
+        name,
+        &program,
+        SolverChoice::SLG {
+            max_size: 20,
+            truncation: TruncationMeasure::default(),
+            scheduling: Scheduling::default(),
+            ordering: SubgoalOrdering::default(),
+            simplify_goals: false,
+            max_projection_depth: 64,
+            max_type_depth: 256,
+            track_provenance: false,
+            const_overflow: ConstOverflow::default(),
+            ground_fast_path: true,
+            max_cached_tables: usize::MAX,
+        },
+        &goal,
+        bencher,
+        expected
+    );
+}
+
+#[bench]
+fn impl_fan_out_slg(b: &mut Bencher) {
+    run_generated_bench("impl_fan_out_slg", generate::impl_fan_out(500), b);
+}
+
+#[bench]
+fn clause_chain_slg(b: &mut Bencher) {
+    run_generated_bench("clause_chain_slg", generate::clause_chain(50), b);
+}
+
+#[bench]
+fn projection_nesting_slg(b: &mut Bencher) {
+    run_generated_bench("projection_nesting_slg", generate::projection_nesting(15), b);
+}
+
+#[bench]
+fn environment_size_slg(b: &mut Bencher) {
+    run_generated_bench("environment_size_slg", generate::environment_size(100), b);
+}
+
+#[bench]
+fn nested_environment_slg(b: &mut Bencher) {
+    run_generated_bench("nested_environment_slg", generate::nested_environment(100), b);
+}
+
+#[bench]
+fn universe_count_slg(b: &mut Bencher) {
+    run_generated_bench("universe_count_slg", generate::universe_count(20), b);
+}
+
+#[bench]
+fn answer_multiplicity_slg(b: &mut Bencher) {
+    run_generated_bench("answer_multiplicity_slg", generate::answer_multiplicity(50), b);
+}
+
+/// Cloning a program only shares its tables, however many impls it has.
+#[bench]
+fn program_clone(b: &mut Bencher) {
+    let generated = generate::impl_fan_out(500);
+    let program = parse_and_lower_program(&generated.program, SolverChoice::default()).unwrap();
+    b.iter(|| program.clone());
+}
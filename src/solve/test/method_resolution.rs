@@ -0,0 +1,107 @@
+//! Tests of the selection of unselected projections like `T::Output`,
+//! as method resolution would use it: the traits in scope are given by
+//! `InScope` hypotheses, and any of them which is implemented and has
+//! an associated type of that name may be selected.
+
+use super::*;
+
+/// The program all the tests of this module solve their goals in.
+const PROGRAM: &str = "
+    trait Read { type Output; }
+    trait Write { type Output; }
+
+    struct File { }
+    struct Socket { }
+    struct Pipe { }
+    struct Bytes { }
+    struct Count { }
+
+    impl Read for File { type Output = Bytes; }
+    impl Write for File { type Output = Count; }
+    impl Read for Socket { type Output = Bytes; }
+";
+
+/// Solves each goal, given as `{ G }`, in `PROGRAM`, as `test!` would,
+/// and checks that it yields the result paired with it.
+fn solve_in_program(goals: &[(&str, &str)]) {
+    let goals = goals
+        .iter()
+        .map(|&(goal_text, expected)| (goal_text, SolverChoice::default(), expected, false))
+        .collect();
+    solve_goal(&format!("{{{}}}", PROGRAM), goals);
+}
+
+#[test]
+fn one_trait_in_scope() {
+    solve_in_program(&[
+        (
+            "{ exists<T> { if (InScope(Read)) { File::Output = T } } }",
+            "Unique; substitution [?0 := Bytes]",
+        ),
+        (
+            "{ exists<T> { if (InScope(Write)) { File::Output = T } } }",
+            "Unique; substitution [?0 := Count]",
+        ),
+        ("{ exists<T> { File::Output = T } }", "No possible solution"),
+    ]);
+}
+
+#[test]
+fn only_implemented_traits_are_selected() {
+    solve_in_program(&[
+        (
+            "{ exists<T> { if (InScope(Read); InScope(Write)) { Socket::Output = T } } }",
+            "Unique; substitution [?0 := Bytes]",
+        ),
+        (
+            "{ exists<T> { if (InScope(Read); InScope(Write)) { Pipe::Output = T } } }",
+            "No possible solution",
+        ),
+    ]);
+}
+
+#[test]
+fn selection_from_environment() {
+    // Only `Write` is known to be implemented by `U`, though both
+    // traits are in scope.
+    solve_in_program(&[(
+        "{ forall<U> { if (U: Write; InScope(Read); InScope(Write)) { \
+             exists<T> { U::Output = T } \
+         } } }",
+        "Unique; substitution [?0 := (Write::Output)<!1_0>]",
+    )]);
+}
+
+#[test]
+fn ambiguous_candidates() {
+    solve_in_program(&[(
+        "{ exists<T> { if (InScope(Read); InScope(Write)) { File::Output = T } } }",
+        "Ambiguous; no inference guidance",
+    )]);
+
+    let solver_choice = SolverChoice::default();
+    let program = Arc::new(parse_and_lower_program(PROGRAM, solver_choice).unwrap());
+    let env = Arc::new(program.environment());
+    ir::tls::set_current_program(&program, || {
+        let candidates = |goal_text: &str| {
+            let goal = parse_and_lower_goal(&program, goal_text).unwrap().into_peeled_goal();
+            let mut candidates: Vec<_> = solver_choice
+                .candidates(&env, &goal, 10)
+                .iter()
+                .map(|candidate| candidate.to_string())
+                .collect();
+            candidates.sort();
+            candidates
+        };
+
+        assert_eq!(
+            candidates("exists<T> { if (InScope(Read); InScope(Write)) { File::Output = T } }"),
+            vec!["[?0 := Bytes]", "[?0 := Count]"]
+        );
+        assert_eq!(
+            candidates("exists<T> { if (InScope(Read); InScope(Write)) { Socket::Output = T } }"),
+            vec!["[?0 := Bytes]"]
+        );
+        assert!(candidates("exists<T> { if (InScope(Write)) { Pipe::Output = T } }").is_empty());
+    });
+}
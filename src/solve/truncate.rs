@@ -1,79 +1,185 @@
+//! Truncation keeps the goals and answers of the solver from growing
+//! without bound: a value whose types get too large is replaced by a
+//! smaller one, in which the subtrees that do not fit are replaced by
+//! fresh inference variables.
 //!
+//! Truncation must only ever *generalize*: the original value must be
+//! an instance of the truncated one (it must unify with it), so that
+//! whatever holds for the truncated value covers the original too. It
+//! must never specialize -- e.g. replace a subtree by some smaller but
+//! unrelated type -- or the solver could give wrong answers rather than
+//! merely ambiguous ones.
+//!
+//! How the size of a type is measured is up to a `SizeMeasure`.
 
 use fallible::*;
 use fold::{self, Fold, IdentityExistentialFolder, IdentityUniversalFolder, TypeFolder};
 use fold::shift::Shift;
 use ir::*;
 use solve::infer::InferenceTable;
+use std::cmp::max;
+use std::mem;
+
+/// Measures the size of types, for truncation. A type is measured as
+/// it is walked, outermost first: each type adds its `node_size` to the
+/// size of the types enclosing it.
+pub trait SizeMeasure {
+    /// The size of `ty` itself, not counting its parameters. This must
+    /// be at least 1.
+    fn node_size(&self, ty: &Ty) -> usize;
+
+    /// True if the sizes of the parameters of a type (and of the types
+    /// that follow it in the value) add up; false if only the largest
+    /// one counts.
+    fn adds_up_parameters(&self) -> bool;
+}
+
+/// Measures the depth of types: `Vec<Vec<T>>` has size 3, and so does
+/// `HashMap<Vec<K>, Vec<V>>`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Depth;
+
+impl SizeMeasure for Depth {
+    fn node_size(&self, _ty: &Ty) -> usize {
+        1
+    }
+
+    fn adds_up_parameters(&self) -> bool {
+        false
+    }
+}
+
+/// Counts the types making up a value: `Vec<Vec<T>>` has size 3, and
+/// `HashMap<Vec<K>, Vec<V>>` has size 5. Lifetimes and constants are
+/// not counted.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NodeCount;
+
+impl SizeMeasure for NodeCount {
+    fn node_size(&self, _ty: &Ty) -> usize {
+        1
+    }
 
-crate fn truncate<T>(
+    fn adds_up_parameters(&self) -> bool {
+        true
+    }
+}
+
+/// The `SizeMeasure` used by the solver; see `SolverChoice`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TruncationMeasure {
+    /// See `Depth`.
+    Depth,
+
+    /// See `NodeCount`.
+    NodeCount,
+}
+
+impl Default for TruncationMeasure {
+    fn default() -> Self {
+        TruncationMeasure::NodeCount
+    }
+}
+
+impl SizeMeasure for TruncationMeasure {
+    fn node_size(&self, ty: &Ty) -> usize {
+        match self {
+            TruncationMeasure::Depth => Depth.node_size(ty),
+            TruncationMeasure::NodeCount => NodeCount.node_size(ty),
+        }
+    }
+
+    fn adds_up_parameters(&self) -> bool {
+        match self {
+            TruncationMeasure::Depth => Depth.adds_up_parameters(),
+            TruncationMeasure::NodeCount => NodeCount.adds_up_parameters(),
+        }
+    }
+}
+
+/// Truncates `value` so that none of its types is larger than
+/// `max_size` according to `measure`.
+pub(crate) fn truncate<T, M>(
     infer: &mut InferenceTable,
+    measure: M,
     max_size: usize,
     value: &T,
 ) -> Truncated<T::Result>
 where
     T: Fold,
+    M: SizeMeasure,
 {
-    debug_heading!("truncate(max_size={}, value={:?})", max_size, value);
-
-    let mut truncater = Truncater::new(infer, max_size);
-    let value = value
-        .fold_with(&mut truncater, 0)
-        .expect("Truncater is infallible");
-    debug!(
-        "truncate: overflow={} value={:?}",
-        truncater.overflow, value
-    );
-    Truncated {
-        overflow: truncater.overflow,
-        value,
-    }
+    Truncator::new(infer, measure, max_size).truncate(value)
 }
 
 /// Result from `truncate`.
-crate struct Truncated<T> {
+pub(crate) struct Truncated<T> {
     /// If true, then `value` was truncated relative to the original
     /// (e.g., fresh inference variables were introduced). If false,
     /// then it is effectively a clone of the original.
-    crate overflow: bool,
+    pub(crate) overflow: bool,
 
     /// Possibly truncate value.
-    crate value: T,
+    pub(crate) value: T,
 }
 
-struct Truncater<'infer> {
+/// Truncates values, creating the variables that replace the subtrees
+/// that are too large in `infer`.
+pub(crate) struct Truncator<'infer, M: SizeMeasure> {
     infer: &'infer mut InferenceTable,
+    measure: M,
+
+    /// The size of the types enclosing the current one, together with
+    /// the types before it if the measure adds those up.
     current_size: usize,
+
+    /// The largest size reached within the current type.
+    peak_size: usize,
     max_size: usize,
     overflow: bool,
 }
 
-impl<'infer> Truncater<'infer> {
-    fn new(infer: &'infer mut InferenceTable, max_size: usize) -> Self {
-        Truncater {
+impl<'infer, M: SizeMeasure> Truncator<'infer, M> {
+    pub(crate) fn new(infer: &'infer mut InferenceTable, measure: M, max_size: usize) -> Self {
+        Truncator {
             infer,
+            measure,
             current_size: 0,
+            peak_size: 0,
             max_size,
             overflow: false,
         }
     }
 
-    fn overflow(&mut self, pre_size: usize) -> Ty {
+    pub(crate) fn truncate<T: Fold>(mut self, value: &T) -> Truncated<T::Result> {
+        debug_heading!("truncate(max_size={}, value={:?})", self.max_size, value);
+
+        let value = value
+            .fold_with(&mut self, 0)
+            .expect("Truncator is infallible");
+        debug!("truncate: overflow={} value={:?}", self.overflow, value);
+        Truncated {
+            overflow: self.overflow,
+            value,
+        }
+    }
+
+    fn overflow(&mut self) -> Ty {
         self.overflow = true;
-        self.current_size = pre_size + 1;
         let universe = self.infer.max_universe();
         self.infer.new_variable(universe).to_ty()
     }
 }
 
-impl<'infer> TypeFolder for Truncater<'infer> {
+impl<'infer, M: SizeMeasure> TypeFolder for Truncator<'infer, M> {
     fn fold_ty(&mut self, ty: &Ty, binders: usize) -> Fallible<Ty> {
         if let Some(normalized_ty) = self.infer.normalize_shallow(ty, binders) {
             return self.fold_ty(&normalized_ty, binders);
         }
 
         let pre_size = self.current_size;
-        self.current_size += 1;
+        self.current_size += self.measure.node_size(ty);
+        let outer_peak_size = mem::replace(&mut self.peak_size, self.current_size);
 
         let result = fold::super_fold_ty(self, ty, binders)?;
 
@@ -85,18 +191,39 @@ impl<'infer> TypeFolder for Truncater<'infer> {
         // Presuming that `pre_size < self.max_size`, then the
         // invariant is in jeopardy if `post_size > self.max_size`.
         // To repair the situation, we replace the entire subtree with
-        // a fresh existential variable (in the innermost universe).
-        let post_size = self.current_size;
-        let result = if pre_size < self.max_size && post_size > self.max_size {
-            self.overflow(pre_size).up_shift(binders)
+        // a fresh existential variable (in the innermost universe) --
+        // unless the subtree refers to a binder within the value being
+        // truncated (e.g., the `'a` in `for<'a> Foo<&'a u32>`), which
+        // the variable could not name. In that case, the subtree is left
+        // alone, and the replacement falls to an enclosing subtree.
+        let mut post_size = if self.measure.adds_up_parameters() {
+            self.current_size
+        } else {
+            self.peak_size
+        };
+        let result = if pre_size < self.max_size
+            && post_size > self.max_size
+            && result.down_shift(binders).is_ok()
+        {
+            post_size = pre_size + 1;
+            self.overflow().up_shift(binders)
         } else {
             result
         };
 
+        // Leave the sizes as the next type should see them.
+        self.current_size = if self.measure.adds_up_parameters() {
+            post_size
+        } else {
+            pre_size
+        };
+        self.peak_size = max(outer_peak_size, post_size);
+
         // When we get back to the first invocation, clear the counters.
         // We process each type independently.
         if pre_size == 0 {
             self.current_size = 0;
+            self.peak_size = 0;
         }
 
         Ok(result)
@@ -105,11 +232,15 @@ impl<'infer> TypeFolder for Truncater<'infer> {
     fn fold_lifetime(&mut self, lifetime: &Lifetime, binders: usize) -> Fallible<Lifetime> {
         fold::super_fold_lifetime(self, lifetime, binders)
     }
+
+    fn fold_const(&mut self, constant: &Const, binders: usize) -> Fallible<Const> {
+        fold::super_fold_const(self, constant, binders)
+    }
 }
 
-impl<'infer> IdentityExistentialFolder for Truncater<'infer> {}
+impl<'infer, M: SizeMeasure> IdentityExistentialFolder for Truncator<'infer, M> {}
 
-impl<'infer> IdentityUniversalFolder for Truncater<'infer> {}
+impl<'infer, M: SizeMeasure> IdentityUniversalFolder for Truncator<'infer, M> {}
 
 #[test]
 fn truncate_types() {
@@ -128,7 +259,7 @@ fn truncate_types() {
     let Truncated {
         overflow,
         value: ty_no_overflow,
-    } = truncate(&mut table, 5, &ty0);
+    } = truncate(&mut table, NodeCount, 5, &ty0);
     assert!(!overflow);
     assert_eq!(ty0, ty_no_overflow);
 
@@ -139,7 +270,7 @@ fn truncate_types() {
     let Truncated {
         overflow,
         value: ty_overflow,
-    } = truncate(&mut table, 3, &ty0);
+    } = truncate(&mut table, NodeCount, 3, &ty0);
     assert!(overflow);
     assert_eq!(ty_expect, ty_overflow);
 
@@ -170,7 +301,7 @@ fn truncate_multiple_types() {
     let Truncated {
         overflow,
         value: ty_no_overflow,
-    } = truncate(&mut table, 5, &ty0_3);
+    } = truncate(&mut table, NodeCount, 5, &ty0_3);
     assert!(!overflow);
     assert_eq!(ty0_3, ty_no_overflow);
 
@@ -179,7 +310,7 @@ fn truncate_multiple_types() {
     let Truncated {
         overflow,
         value: ty_no_overflow,
-    } = truncate(&mut table, 6, &ty0_3);
+    } = truncate(&mut table, NodeCount, 6, &ty0_3);
     assert!(!overflow);
     assert_eq!(ty0_3, ty_no_overflow);
 
@@ -188,7 +319,7 @@ fn truncate_multiple_types() {
     let Truncated {
         overflow,
         value: ty_overflow,
-    } = truncate(&mut table, 3, &ty0_3);
+    } = truncate(&mut table, NodeCount, 3, &ty0_3);
     assert!(overflow);
     assert_eq!(
         vec![
@@ -219,7 +350,7 @@ fn truncate_normalizes() {
                    (apply (skol 1))));
 
     // test: truncating *before* unifying has no effect
-    assert!(!truncate(&mut table, 3, &ty0).overflow);
+    assert!(!truncate(&mut table, NodeCount, 3, &ty0).overflow);
 
     // unify X and ty1
     table.unify(environment0, &v0.to_ty(), &ty1).unwrap();
@@ -228,7 +359,7 @@ fn truncate_normalizes() {
     let Truncated {
         overflow,
         value: ty_overflow,
-    } = truncate(&mut table, 3, &ty0);
+    } = truncate(&mut table, NodeCount, 3, &ty0);
     assert!(overflow);
     assert_eq!(
         ty!(apply (item 0)
@@ -254,5 +385,96 @@ fn truncate_normalizes_under_binders() {
                     (var 1))));
 
     // the index in `(var 1)` should be adjusted to account for binders
-    assert!(!truncate(&mut table, 4, &ty0).overflow);
+    assert!(!truncate(&mut table, NodeCount, 4, &ty0).overflow);
+}
+
+#[test]
+fn truncate_keeps_subtrees_referencing_inner_binders() {
+    let mut table = InferenceTable::new();
+
+    // ty0 = for<'a> Vec<Vec<Vec<Ref<'a>>>>
+    let ty0 = ty!(for_all 1
+                  (apply (item 0)
+                   (apply (item 0)
+                    (apply (item 0)
+                     (apply (item 1) (lifetime (var 0)))))));
+
+    // test: none of the `Vec`s can be replaced by a variable, as they
+    // all name `'a`, so the whole type is replaced instead
+    let Truncated {
+        overflow,
+        value: ty_overflow,
+    } = truncate(&mut table, NodeCount, 3, &ty0);
+    assert!(overflow);
+    assert_eq!(ty!(var 0), ty_overflow);
+}
+
+/// Truncates `ty` with `max_size` 3 and checks that the result is more
+/// general than `ty`. Returns whether it was truncated.
+#[cfg(test)]
+fn truncates_to_more_general_type<M: SizeMeasure>(measure: M, ty: &Ty) -> bool {
+    let mut table = InferenceTable::new();
+    let environment0 = &Environment::new();
+    let _u1 = table.new_universe();
+
+    let Truncated { overflow, value } = truncate(&mut table, measure, 3, ty);
+    assert_eq!(overflow, value != *ty);
+    table.unify(environment0, &value, ty).unwrap();
+    overflow
+}
+
+#[test]
+fn truncation_generalizes() {
+    // Vec<Vec<Vec<Vec<T>>>>
+    let deep = ty!(apply (item 0)
+                   (apply (item 0)
+                    (apply (item 0)
+                     (apply (item 0)
+                      (apply (skol 1))))));
+
+    // Pair<Vec<T>, Vec<T>>
+    let wide = ty!(apply (item 1)
+                   (apply (item 0) (apply (skol 1)))
+                   (apply (item 0) (apply (skol 1))));
+
+    // for<'a> Vec<Vec<Vec<Ref<'a, T>>>>
+    let binder = ty!(for_all 1
+                     (apply (item 0)
+                      (apply (item 0)
+                       (apply (item 0)
+                        (apply (item 2) (lifetime (var 0)) (apply (skol 1)))))));
+
+    assert!(truncates_to_more_general_type(Depth, &deep));
+    assert!(truncates_to_more_general_type(NodeCount, &deep));
+
+    // `wide` is only 3 deep, but made of 5 types.
+    assert!(!truncates_to_more_general_type(Depth, &wide));
+    assert!(truncates_to_more_general_type(NodeCount, &wide));
+
+    assert!(truncates_to_more_general_type(Depth, &binder));
+    assert!(truncates_to_more_general_type(NodeCount, &binder));
+}
+
+#[test]
+fn truncate_by_depth() {
+    let mut table = InferenceTable::new();
+    let _u1 = table.new_universe();
+
+    // Pair<Vec<Vec<T>>, Vec<T>>
+    let ty0 = ty!(apply (item 1)
+                  (apply (item 0) (apply (item 0) (apply (skol 1))))
+                  (apply (item 0) (apply (skol 1))));
+
+    // test: only the parameter that is too deep is truncated
+    let Truncated {
+        overflow,
+        value: ty_overflow,
+    } = truncate(&mut table, Depth, 3, &ty0);
+    assert!(overflow);
+    assert_eq!(
+        ty!(apply (item 1)
+            (apply (item 0) (var 0))
+            (apply (item 0) (apply (skol 1)))),
+        ty_overflow
+    );
 }
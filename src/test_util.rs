@@ -1,23 +1,63 @@
 #![cfg(test)]
 
-use diff;
+use text_diff;
 use chalk_parse;
 use itertools::Itertools;
+use std::collections::BTreeMap;
 use std::fmt::Write;
-use ir::lowering::{LowerProgram, LowerGoal};
-use ir::{Goal, Program};
+use ir::lowering::{LowerCrates, LowerProgram, LowerGoal};
+use ir::{Goal, Identifier, Program};
 use solve::SolverChoice;
 use errors::Result;
+use zip::Zip;
+use diff::{diff, display_diff};
+
+pub(crate) mod alloc;
+pub(crate) mod compare;
+pub(crate) mod generate;
 
 pub fn parse_and_lower_program(text: &str, solver_choice: SolverChoice) -> Result<Program> {
     chalk_parse::parse_program(text)?.lower(solver_choice)
 }
 
+/// Lowers a program split across the given `(name, text)` crates, once
+/// as seen from each of them.
+pub fn parse_and_lower_crates(
+    crates: &[(&str, &str)],
+    solver_choice: SolverChoice,
+) -> Result<BTreeMap<Identifier, Program>> {
+    let crates = crates
+        .iter()
+        .map(|&(name, text)| chalk_parse::parse_crate(name, text))
+        .collect::<chalk_parse::errors::Result<Vec<_>>>()?;
+    crates.lower_all_crates(solver_choice)
+}
+
+/// Strips the braces around a program or goal written inside a macro.
+pub(crate) fn unbraced(text: &str) -> &str {
+    assert!(text.starts_with("{"));
+    assert!(text.ends_with("}"));
+    &text[1..text.len() - 1]
+}
+
 pub fn parse_and_lower_goal(program: &Program, text: &str) -> Result<Box<Goal>> {
     chalk_parse::parse_goal(text)?.lower(program)
 }
 
 macro_rules! lowering_success {
+    (crates { $($krate:ident $program:tt)* }) => {
+        let result = parse_and_lower_crates(
+            &[$((stringify!($krate), $crate::test_util::unbraced(stringify!($program)))),*],
+            $crate::solve::SolverChoice::default()
+        );
+        if let Err(ref e) = result {
+            println!("lowering error: {}", e);
+        }
+        assert!(
+            result.is_ok()
+        );
+    };
+
     (program $program:tt) => {
         let program_text = stringify!($program);
         assert!(program_text.starts_with("{"));
@@ -36,6 +76,18 @@ macro_rules! lowering_success {
 }
 
 macro_rules! lowering_error {
+    (crates { $($krate:ident $program:tt)* } error_msg { $expected:expr }) => {
+        let error = parse_and_lower_crates(
+            &[$((stringify!($krate), $crate::test_util::unbraced(stringify!($program)))),*],
+            $crate::solve::SolverChoice::default()
+        ).unwrap_err();
+        let expected = $crate::errors::Error::from($expected);
+        assert_eq!(
+            error.to_string(),
+            expected.to_string()
+        );
+    };
+
     (program $program:tt error_msg { $expected:expr }) => {
         let program_text = stringify!($program);
         assert!(program_text.starts_with("{"));
@@ -49,19 +101,41 @@ macro_rules! lowering_error {
             error.to_string(),
             expected.to_string()
         );
+    };
+
+    (program $program:tt error_msg { $expected:expr } caused_by { $cause:expr }) => {
+        let program_text = stringify!($program);
+        assert!(program_text.starts_with("{"));
+        assert!(program_text.ends_with("}"));
+        let error = parse_and_lower_program(
+            &program_text[1..program_text.len()-1],
+            $crate::solve::SolverChoice::default()
+        ).unwrap_err();
+        let expected = $crate::errors::Error::from($expected);
+        assert_eq!(
+            error.to_string(),
+            expected.to_string()
+        );
+        let cause = error.iter().nth(1).expect("error has no cause");
+        assert_eq!(
+            cause.to_string(),
+            $cause
+        );
     }
 }
 
-crate fn assert_test_result_eq(expected: &str, actual: &str) {
+pub(crate) fn assert_test_result_eq(expected: &str, actual: &str) {
+    // Pretty-printed `Debug` output ends the last field of a struct with a
+    // trailing comma on newer compilers, so trailing commas are ignored.
     let expected_trimmed: String = expected
         .lines()
-        .map(|l| l.trim())
+        .map(|l| l.trim().trim_end_matches(','))
         .intersperse("\n")
         .collect();
 
     let actual_trimmed: String = actual
         .lines()
-        .map(|l| l.trim())
+        .map(|l| l.trim().trim_end_matches(','))
         .intersperse("\n")
         .collect();
 
@@ -72,38 +146,116 @@ crate fn assert_test_result_eq(expected: &str, actual: &str) {
     println!("expected:\n{}", expected);
     println!("actual:\n{}", actual);
 
-    let diff = diff::lines(
+    let diff = text_diff::lines(
         &expected_trimmed,
         &actual_trimmed,
     );
 
     // Skip to the first error:
     let diff = diff.iter().skip_while(|r| match r {
-        diff::Result::Both(..) => true,
+        text_diff::Result::Both(..) => true,
         _ => false,
     });
 
     let mut final_diff = String::new();
     let mut accumulator = vec![];
+    let mut removed = vec![];
+    let mut added = vec![];
     for result in diff {
-        let (prefix, s) = match result {
-            diff::Result::Both(a, _b) => {
+        match result {
+            text_diff::Result::Both(a, _b) => {
                 // When we see things that are the same, don't print
                 // them right away; wait until we see another line of
                 // diff.
+                write_hunk(&mut final_diff, &mut removed, &mut added);
                 accumulator.push(a);
-                continue;
             }
-            diff::Result::Left(a) => ("- ", a),
-            diff::Result::Right(a) => ("+ ", a),
-        };
-
-        for l in accumulator.drain(..) {
-            writeln!(&mut final_diff, "  {}", l).unwrap();
+            text_diff::Result::Left(a) => removed.push(a),
+            text_diff::Result::Right(a) => added.push(a),
+        }
+        if !removed.is_empty() || !added.is_empty() {
+            for l in accumulator.drain(..) {
+                writeln!(&mut final_diff, "  {}", l).unwrap();
+            }
         }
-
-        writeln!(&mut final_diff, "{}{}", prefix, s).unwrap();
     }
+    write_hunk(&mut final_diff, &mut removed, &mut added);
 
     assert!(false, "expected did not match actual, diff:\n{}", final_diff);
 }
+
+/// Writes the lines `removed` from the expected output and those
+/// `added` in their place, followed by the words that changed between
+/// them.
+fn write_hunk(out: &mut String, removed: &mut Vec<&str>, added: &mut Vec<&str>) {
+    for l in removed.iter() {
+        writeln!(out, "- {}", l).unwrap();
+    }
+    for l in added.iter() {
+        writeln!(out, "+ {}", l).unwrap();
+    }
+    if !removed.is_empty() && !added.is_empty() {
+        let removed = removed.iter().join(" ");
+        let added = added.iter().join(" ");
+        writeln!(out, "~ {}", compare::full_word_diff(&removed, &added)).unwrap();
+    }
+    removed.clear();
+    added.clear();
+}
+
+/// Asserts that `actual` and `expected` are equal, listing the spots
+/// where they diverge if they are not.
+pub(crate) fn assert_same_terms<T: Zip + PartialEq>(actual: &T, expected: &T, context: &str) {
+    if actual != expected {
+        panic!(
+            "{}: expected `{:?}`, found `{:?}`; they diverge at:\n{}",
+            context,
+            expected,
+            actual,
+            display_diff(&diff(expected, actual)),
+        );
+    }
+}
+
+/// The crates are meant to build on a stable compiler. The only
+/// unstable feature we accept is the `test` crate used by the
+/// benchmarks, which are behind the `bench` cargo feature. If you
+/// really need another feature, add it here and explain why.
+const ALLOWED_FEATURES: &[&str] = &["test"];
+
+/// Returns the features enabled by the `#![feature(..)]` and
+/// `#![cfg_attr(.., feature(..))]` attributes at the top of `source`.
+fn enabled_features(source: &str) -> Vec<String> {
+    source
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| line.starts_with("#!["))
+        .filter_map(|line| line.find("feature(").map(|i| &line[i + "feature(".len()..]))
+        .flat_map(|features| {
+            let end = features.find(')').unwrap_or(features.len());
+            features[..end].split(',').map(|f| f.trim().to_string())
+        })
+        .filter(|feature| !feature.is_empty())
+        .collect()
+}
+
+#[test]
+fn no_unstable_features() {
+    let crates = vec![
+        ("chalk", include_str!("lib.rs")),
+        ("chalk-engine", include_str!("../chalk-engine/src/lib.rs")),
+        ("chalk-macros", include_str!("../chalk-macros/src/lib.rs")),
+        ("chalk-parse", include_str!("../chalk-parse/src/lib.rs")),
+    ];
+
+    for (name, source) in crates {
+        for feature in enabled_features(source) {
+            assert!(
+                ALLOWED_FEATURES.contains(&&feature[..]),
+                "crate `{}` enables the unstable feature `{}`",
+                name,
+                feature
+            );
+        }
+    }
+}
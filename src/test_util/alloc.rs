@@ -0,0 +1,36 @@
+//! A global allocator for the tests which counts the allocations made
+//! by each thread, so that tests can check how often some operation
+//! allocates without being disturbed by the tests running alongside.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct CountingAllocator;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = Cell::new(0);
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // The counter is unavailable while the thread is torn down.
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Runs `op`, returning its result along with the number of allocations
+/// it made.
+pub(crate) fn count_allocations<R>(op: impl FnOnce() -> R) -> (R, usize) {
+    let before = ALLOCATIONS.with(|count| count.get());
+    let result = op();
+    let after = ALLOCATIONS.with(|count| count.get());
+    (result, after - before)
+}
@@ -0,0 +1,213 @@
+//! Comparison of the solutions expected by the solver tests with the
+//! ones actually produced. Expected solutions are a prefix of the
+//! actual ones, ignoring whitespace; when they do not match, we render
+//! a word-level diff and check whether the two differ only by the
+//! numbering of their inference variables.
+
+use text_diff;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Splits `text` into tokens, ignoring whitespace: each inference
+/// variable (`?0`, `?A`, ...) is a single token, and every other
+/// character is a token of its own.
+fn tokens(text: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+
+        let mut end = start + c.len_utf8();
+        if c == '?' {
+            while let Some(&(i, c)) = chars.peek() {
+                if !c.is_alphanumeric() && c != '_' {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+        }
+        tokens.push(&text[start..end]);
+    }
+    tokens
+}
+
+/// True if the token at `index` is an inference variable. The `?0` in
+/// `?0 := Foo` names a variable of the goal rather than the solution,
+/// so it is not a variable in this sense.
+fn is_variable(tokens: &[&str], index: usize) -> bool {
+    let token = tokens[index];
+    token.len() > 1 && token.starts_with('?') && tokens.get(index + 1) != Some(&":")
+}
+
+/// True if `expected` is a prefix of `actual`, ignoring whitespace.
+pub(crate) fn is_prefix(expected: &str, actual: &str) -> bool {
+    let expected = tokens(expected);
+    !expected.is_empty() && tokens(actual).starts_with(&expected)
+}
+
+/// True if `expected` is a prefix of `actual`, ignoring whitespace,
+/// once the inference variables of `expected` are consistently
+/// renamed. Distinct variables must stay distinct: `[?0 := ?0, ?1 :=
+/// ?1]` is not a renaming of `[?0 := ?0, ?1 := ?0]`.
+pub(crate) fn is_prefix_modulo_renaming(expected: &str, actual: &str) -> bool {
+    let expected = tokens(expected);
+    let actual = tokens(actual);
+    if expected.is_empty() || expected.len() > actual.len() {
+        return false;
+    }
+
+    let mut renaming = HashMap::new();
+    let mut inverse = HashMap::new();
+    (0..expected.len()).all(|i| {
+        let (e, a) = (expected[i], actual[i]);
+        match (is_variable(&expected, i), is_variable(&actual, i)) {
+            (true, true) => {
+                *renaming.entry(e).or_insert(a) == a && *inverse.entry(a).or_insert(e) == e
+            }
+            (false, false) => e == a,
+            _ => false,
+        }
+    })
+}
+
+/// Renders the difference between `expected` and `actual` word by
+/// word, line by line: removed words are shown as `[-word-]` and
+/// added ones as `{+word+}`. Since `expected` only has to be a prefix
+/// of `actual`, whatever `actual` has beyond the end of `expected` is
+/// not shown.
+pub(crate) fn word_diff(expected: &str, actual: &str) -> String {
+    let expected_lines: Vec<Vec<&str>> =
+        expected.lines().map(|line| line.split_whitespace().collect()).collect();
+    let expected_words: Vec<&str> = expected.split_whitespace().collect();
+    let actual_words: Vec<&str> = actual.split_whitespace().collect();
+
+    // The words added at the very end are the part of `actual` beyond
+    // `expected`, except for those replacing words removed at the end.
+    let mut results = text_diff::slice(&expected_words, &actual_words);
+    let changed = results.iter().rev().take_while(|result| match result {
+        text_diff::Result::Both(..) => false,
+        _ => true,
+    }).count();
+    let common = results.len() - changed;
+    let tail = results.split_off(common);
+    let removed = tail.iter().filter(|result| match result {
+        text_diff::Result::Left(_) => true,
+        _ => false,
+    }).count();
+    let mut added = 0;
+    results.extend(tail.into_iter().filter(|result| match result {
+        text_diff::Result::Right(_) => {
+            added += 1;
+            added <= removed
+        }
+        _ => true,
+    }));
+
+    // Break the rendered diff at the line breaks of `expected`, so
+    // that it keeps the layout of the test. Added words go on the
+    // line of the word before them.
+    let mut line_lengths = expected_lines.iter().map(|line| line.len());
+    let mut remaining = line_lengths.next().unwrap_or(0);
+    let mut lines = vec![vec![]];
+    for result in results {
+        let word = match result {
+            text_diff::Result::Right(word) => {
+                lines.last_mut().unwrap().push(format!("{{+{}+}}", word));
+                continue;
+            }
+            text_diff::Result::Both(word, _) => word.to_string(),
+            text_diff::Result::Left(word) => format!("[-{}-]", word),
+        };
+        while remaining == 0 {
+            match line_lengths.next() {
+                Some(length) => {
+                    remaining = length;
+                    lines.push(vec![]);
+                }
+                None => break,
+            }
+        }
+        remaining = remaining.saturating_sub(1);
+        lines.last_mut().unwrap().push(word);
+    }
+
+    let mut rendered = String::new();
+    for (index, line) in lines.iter().enumerate() {
+        if index > 0 {
+            writeln!(rendered).unwrap();
+        }
+        write!(rendered, "{}", line.join(" ")).unwrap();
+    }
+    rendered
+}
+
+/// Like `word_diff`, on a single line and for texts which should be
+/// equal: the words `actual` has beyond the end of `expected` are shown
+/// as added.
+pub(crate) fn full_word_diff(expected: &str, actual: &str) -> String {
+    let expected_words: Vec<&str> = expected.split_whitespace().collect();
+    let actual_words: Vec<&str> = actual.split_whitespace().collect();
+    let words: Vec<String> = text_diff::slice(&expected_words, &actual_words)
+        .into_iter()
+        .map(|result| match result {
+            text_diff::Result::Both(word, _) => word.to_string(),
+            text_diff::Result::Left(word) => format!("[-{}-]", word),
+            text_diff::Result::Right(word) => format!("{{+{}+}}", word),
+        })
+        .collect();
+    words.join(" ")
+}
+
+#[test]
+fn prefix_ignores_whitespace() {
+    assert!(is_prefix("Unique; substitution [?0 := Foo]", "Unique;substitution[?0:=Foo], lifetime constraints []"));
+    assert!(!is_prefix("", "Unique"));
+    assert!(!is_prefix("Unique; substitution [?0 := Foo]", "Unique; substitution [?0 := Bar]"));
+}
+
+#[test]
+fn renaming_is_detected() {
+    assert!(is_prefix_modulo_renaming(
+        "Unique; for<?U0> { substitution [?0 := Vec<?1>, ?1 := ?1] }",
+        "Unique; for<?U0> { substitution [?0 := Vec<?0>, ?1 := ?0] }, lifetime constraints []"
+    ));
+    assert!(is_prefix_modulo_renaming(
+        "Ambiguous; definite substitution for<?U0> { [?0 := ^0] }",
+        "Ambiguous; definite substitution for<?U0> { [?0 := ^0] }"
+    ));
+    assert!(is_prefix_modulo_renaming("[?0 := ?1, ?1 := ?0]", "[?0 := ?0, ?1 := ?1]"));
+}
+
+#[test]
+fn renaming_must_be_consistent() {
+    // Two distinct variables cannot become the same one...
+    assert!(!is_prefix_modulo_renaming("[?0 := ?1, ?1 := ?2]", "[?0 := ?1, ?1 := ?1]"));
+    // ...and the same variable cannot become two distinct ones.
+    assert!(!is_prefix_modulo_renaming("[?0 := ?1, ?1 := ?1]", "[?0 := ?1, ?1 := ?2]"));
+    // Only variables may be renamed, not the variables of the goal.
+    assert!(!is_prefix_modulo_renaming("[?0 := Foo]", "[?0 := ?1]"));
+    assert!(!is_prefix_modulo_renaming("[?0 := Foo]", "[?1 := Foo]"));
+    assert!(!is_prefix_modulo_renaming("[?0 := Foo]", "[?0 := Bar]"));
+}
+
+#[test]
+fn word_diff_highlights_bindings() {
+    assert_eq!(
+        word_diff(
+            "Unique; substitution [?0 := Foo, ?1 := Bar]",
+            "Unique; substitution [?0 := Foo, ?1 := Baz], lifetime constraints []"
+        ),
+        "Unique; substitution [?0 := Foo, ?1 := [-Bar]-] {+Baz],+}"
+    );
+    assert_eq!(
+        word_diff("Unique;\nsubstitution [?0 := Foo]", "Unique; substitution [?0 := Foo]"),
+        "Unique;\nsubstitution [?0 := Foo]"
+    );
+    assert_eq!(
+        full_word_diff("answers: [ Foo ]", "answers: [ Bar, Foo ], done"),
+        "answers: [ {+Bar,+} Foo [-]-] {+],+} {+done+}"
+    );
+}
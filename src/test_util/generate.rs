@@ -0,0 +1,375 @@
+//! Generators for synthetic programs. Each generator scales along a single
+//! dimension of the solver's work (number of impls, depth of clause chains,
+//! and so on) and produces a goal together with the result it should have, so
+//! that the same programs can be used to benchmark or fuzz the solver.
+
+use std::fmt::Write;
+
+pub(crate) struct GeneratedProgram {
+    pub(crate) program: String,
+    pub(crate) goal: String,
+
+    /// A prefix of the expected result, in the same format as the
+    /// `yields` clauses of solver tests.
+    pub(crate) expected: &'static str,
+}
+
+/// `n` structs, each with its own impl of the same trait. The goal
+/// asks whether the last struct implements the trait.
+///
+/// The trait is a marker trait so that lowering does not check the
+/// impls pairwise for overlap, which would dominate the setup time.
+pub(crate) fn impl_fan_out(n: usize) -> GeneratedProgram {
+    let mut program = String::from("#[marker] trait Trait { }\n");
+    for i in 0..n {
+        writeln!(program, "struct S{0} {{ }} impl Trait for S{0} {{ }}", i).unwrap();
+    }
+
+    GeneratedProgram {
+        program,
+        goal: format!("S{}: Trait", n - 1),
+        expected: "Unique",
+    }
+}
+
+/// A chain of traits `T0`, ..., `T{depth}`, with a blanket impl of
+/// each trait for types implementing the next one. Only the last trait
+/// is implemented directly, so proving `Foo: T0` requires walking the
+/// entire chain.
+pub(crate) fn clause_chain(depth: usize) -> GeneratedProgram {
+    let mut program = String::from("struct Foo { }\n");
+    for i in 0..depth {
+        writeln!(program, "trait T{} {{ }}", i).unwrap();
+        writeln!(program, "impl<X> T{} for X where X: T{} {{ }}", i, i + 1).unwrap();
+    }
+    writeln!(program, "trait T{0} {{ }} impl T{0} for Foo {{ }}", depth).unwrap();
+
+    GeneratedProgram {
+        program,
+        goal: "Foo: T0".to_string(),
+        expected: "Unique",
+    }
+}
+
+/// Structs `S0`, ..., `S{depth}` where the associated type `Next` of
+/// each struct is the following one. The goal normalizes a projection
+/// nested `depth` levels deep.
+pub(crate) fn projection_nesting(depth: usize) -> GeneratedProgram {
+    let mut program = String::from("trait Step { type Next; }\n");
+    for i in 0..depth {
+        writeln!(program, "struct S{} {{ }}", i).unwrap();
+        writeln!(program, "impl Step for S{} {{ type Next = S{}; }}", i, i + 1).unwrap();
+    }
+    writeln!(program, "struct S{} {{ }}", depth).unwrap();
+
+    let mut projection = String::from("S0");
+    for _ in 0..depth {
+        projection = format!("<{} as Step>::Next", projection);
+    }
+
+    GeneratedProgram {
+        program,
+        goal: format!("{} = S{}", projection, depth),
+        expected: "Unique",
+    }
+}
+
+/// A goal proven in an environment containing `n` hypotheses, only the
+/// last of which is relevant.
+pub(crate) fn environment_size(n: usize) -> GeneratedProgram {
+    let mut program = String::new();
+    for i in 0..n {
+        writeln!(program, "trait P{} {{ }}", i).unwrap();
+    }
+
+    let hypotheses: Vec<_> = (0..n).map(|i| format!("T: P{}", i)).collect();
+
+    GeneratedProgram {
+        program,
+        goal: format!("forall<T> {{ if ({}) {{ T: P{} }} }}", hypotheses.join("; "), n - 1),
+        expected: "Unique",
+    }
+}
+
+/// Like `environment_size`, but each hypothesis is introduced by its
+/// own nested `if`, so the environment grows one clause at a time.
+pub(crate) fn nested_environment(n: usize) -> GeneratedProgram {
+    let mut program = String::new();
+    for i in 0..n {
+        writeln!(program, "trait P{} {{ }}", i).unwrap();
+    }
+
+    let mut goal = format!("T: P{}", n - 1);
+    for i in (0..n).rev() {
+        goal = format!("if (T: P{}) {{ {} }}", i, goal);
+    }
+
+    GeneratedProgram {
+        program,
+        goal: format!("forall<T> {{ {} }}", goal),
+        expected: "Unique",
+    }
+}
+
+/// A goal under `n` nested `forall` binders, each of which introduces
+/// a new universe.
+pub(crate) fn universe_count(n: usize) -> GeneratedProgram {
+    let program = String::from("
+        trait Any { }
+        struct Box<T> { }
+        impl<T> Any for T { }
+    ");
+
+    let leaves: Vec<_> = (0..n).map(|i| format!("Box<T{}>: Any", i)).collect();
+    let mut goal = leaves.join(", ");
+    for i in (0..n).rev() {
+        goal = format!("forall<T{}> {{ {} }}", i, goal);
+    }
+
+    GeneratedProgram {
+        program,
+        goal,
+        expected: "Unique",
+    }
+}
+
+/// `n` structs implementing the same trait, and a goal with one answer
+/// for each of them.
+pub(crate) fn answer_multiplicity(n: usize) -> GeneratedProgram {
+    let GeneratedProgram { program, .. } = impl_fan_out(n);
+
+    GeneratedProgram {
+        program,
+        goal: "exists<T> { T: Trait }".to_string(),
+        expected: "Ambiguous",
+    }
+}
+
+/// The program that the goals of `random_goal` are written against. It
+/// has a bit of everything: lifetime and const parameters, associated
+/// types, an auto trait and a negative impl.
+pub(crate) const RANDOM_GOAL_PROGRAM: &str = "
+    struct Foo { }
+    struct Bar { }
+    struct Vec<T> { }
+    struct Pair<T, U> { }
+    struct Ref<'a, T> { }
+    struct Array<T, const N> { }
+
+    trait Clone { }
+    trait Iterator { type Item; }
+    #[auto] trait Send { }
+
+    impl Clone for Foo { }
+    impl<T> Clone for Vec<T> where T: Clone { }
+    impl<T, U> Clone for Pair<T, U> where T: Clone, U: Clone { }
+    impl<T, const N> Clone for Array<T, N> where T: Clone { }
+    impl<T> Iterator for Vec<T> { type Item = T; }
+    impl<T, const N> Iterator for Array<T, N> { type Item = T; }
+    impl !Send for Bar { }
+";
+
+/// A random goal for `RANDOM_GOAL_PROGRAM`, nested at most `depth`
+/// levels deep. The same seed always yields the same goal. The goals
+/// always lower, but are otherwise arbitrary: they mix quantifiers,
+/// implications and negations over types that may be higher-ranked,
+/// projections, or large enough to be truncated.
+pub(crate) fn random_goal(seed: u64, depth: usize) -> String {
+    let mut generator = GoalGenerator {
+        // Xorshift never leaves zero, so make sure not to start there.
+        state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+        types: vec![],
+        lifetimes: vec![],
+        consts: vec![],
+        next_name: 0,
+    };
+    let mut goal = String::new();
+    generator.goal(depth, &mut goal);
+    goal
+}
+
+struct GoalGenerator {
+    state: u64,
+
+    /// The names of the variables in scope, by kind.
+    types: Vec<String>,
+    lifetimes: Vec<String>,
+    consts: Vec<String>,
+
+    /// Used to give each variable a fresh name.
+    next_name: usize,
+}
+
+impl GoalGenerator {
+    /// A random number in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state % n as u64) as usize
+    }
+
+    fn fresh_name(&mut self, prefix: &str) -> String {
+        self.next_name += 1;
+        format!("{}{}", prefix, self.next_name)
+    }
+
+    fn goal(&mut self, depth: usize, out: &mut String) {
+        if depth == 0 {
+            return self.leaf_goal(out);
+        }
+
+        match self.below(8) {
+            0 | 1 => self.leaf_goal(out),
+            2 => {
+                out.push('(');
+                self.goal(depth - 1, out);
+                out.push_str("), (");
+                self.goal(depth - 1, out);
+                out.push(')');
+            }
+            3 => {
+                out.push_str("not { ");
+                self.goal(depth - 1, out);
+                out.push_str(" }");
+            }
+            4 => {
+                out.push_str("if (");
+                self.ty(depth, out);
+                out.push_str(": Clone) { ");
+                self.goal(depth - 1, out);
+                out.push_str(" }");
+            }
+            quantifier => {
+                out.push_str(if quantifier == 5 { "exists<" } else { "forall<" });
+                let (name, kind) = match self.below(3) {
+                    0 => {
+                        let name = self.fresh_name("T");
+                        out.push_str(&name);
+                        (name, 0)
+                    }
+                    1 => {
+                        let name = self.fresh_name("'l");
+                        out.push_str(&name);
+                        (name, 1)
+                    }
+                    _ => {
+                        let name = self.fresh_name("N");
+                        write!(out, "const {}", name).unwrap();
+                        (name, 2)
+                    }
+                };
+                out.push_str("> { ");
+                self.scope(kind).push(name);
+                self.goal(depth - 1, out);
+                self.scope(kind).pop();
+                out.push_str(" }");
+            }
+        }
+    }
+
+    fn scope(&mut self, kind: usize) -> &mut Vec<String> {
+        match kind {
+            0 => &mut self.types,
+            1 => &mut self.lifetimes,
+            _ => &mut self.consts,
+        }
+    }
+
+    fn leaf_goal(&mut self, out: &mut String) {
+        let depth = 3;
+        match self.below(6) {
+            0 => {
+                self.ty(depth, out);
+                out.push_str(": Clone");
+            }
+            1 => {
+                self.ty(depth, out);
+                out.push_str(": Send");
+            }
+            2 => {
+                self.ty(depth, out);
+                out.push_str(": Iterator");
+            }
+            3 => {
+                self.ty(depth, out);
+                out.push_str(" = ");
+                self.ty(depth, out);
+            }
+            4 => {
+                out.push_str("WellFormed(");
+                self.ty(depth, out);
+                out.push(')');
+            }
+            _ => {
+                out.push_str("Normalize(<");
+                self.ty(depth, out);
+                out.push_str(" as Iterator>::Item -> ");
+                self.ty(depth, out);
+                out.push(')');
+            }
+        }
+    }
+
+    fn ty(&mut self, depth: usize, out: &mut String) {
+        let choice = if depth == 0 { self.below(3) } else { self.below(9) };
+        match choice {
+            0 => out.push_str("Foo"),
+            1 => out.push_str("Bar"),
+            2 => match self.pick(0) {
+                Some(name) => out.push_str(&name),
+                None => out.push_str("Foo"),
+            },
+            3 | 4 => {
+                out.push_str("Vec<");
+                self.ty(depth - 1, out);
+                out.push('>');
+            }
+            5 => {
+                out.push_str("Pair<");
+                self.ty(depth - 1, out);
+                out.push_str(", ");
+                self.ty(depth - 1, out);
+                out.push('>');
+            }
+            6 => {
+                let lifetime = match self.pick(1) {
+                    Some(name) => name,
+                    None => {
+                        let name = self.fresh_name("'l");
+                        write!(out, "for<{}> ", name).unwrap();
+                        name
+                    }
+                };
+                self.lifetimes.push(lifetime.clone());
+                write!(out, "Ref<{}, ", lifetime).unwrap();
+                self.ty(depth - 1, out);
+                out.push('>');
+                self.lifetimes.pop();
+            }
+            7 => {
+                out.push_str("Array<");
+                self.ty(depth - 1, out);
+                match self.pick(2) {
+                    Some(name) => write!(out, ", {}>", name).unwrap(),
+                    None => write!(out, ", {}>", self.below(3)).unwrap(),
+                }
+            }
+            _ => {
+                out.push('<');
+                self.ty(depth - 1, out);
+                out.push_str(" as Iterator>::Item");
+            }
+        }
+    }
+
+    /// A random variable of the given kind, if there is one in scope.
+    fn pick(&mut self, kind: usize) -> Option<String> {
+        let len = self.scope(kind).len();
+        if len == 0 {
+            return None;
+        }
+        let index = self.below(len);
+        Some(self.scope(kind)[index].clone())
+    }
+}
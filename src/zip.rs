@@ -1,9 +1,12 @@
 use fallible::*;
 use fold::Fold;
 use ir::*;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 use std::sync::Arc;
 
+pub(crate) mod depth;
+mod test;
+
 /// When we zip types, we basically traverse the structure, ensuring
 /// that it matches.  When we come to types/lifetimes, we invoke the
 /// callback methods in the zipper to match them up. Primarily used
@@ -20,7 +23,7 @@ use std::sync::Arc;
 /// represented by two distinct `ItemId` values, and the impl for
 /// `ItemId` requires that all `ItemId` in the two zipped values match
 /// up.
-crate trait Zipper {
+pub trait Zipper {
     /// Indicates that the two types `a` and `b` were found in
     /// matching spots, beneath `binders` levels of binders.
     fn zip_tys(&mut self, a: &Ty, b: &Ty) -> Fallible<()>;
@@ -29,10 +32,43 @@ crate trait Zipper {
     /// matching spots, beneath `binders` levels of binders.
     fn zip_lifetimes(&mut self, a: &Lifetime, b: &Lifetime) -> Fallible<()>;
 
+    /// Indicates that the two consts `a` and `b` were found in
+    /// matching spots, beneath `binders` levels of binders.
+    fn zip_consts(&mut self, a: &Const, b: &Const) -> Fallible<()>;
+
     /// Zips two values appearing beneath binders.
     fn zip_binders<T>(&mut self, a: &Binders<T>, b: &Binders<T>) -> Fallible<()>
     where
         T: Zip + Fold<Result = T>;
+
+    /// Zips `a` and `b`, which are found at `step` within the values
+    /// being zipped. Only zippers that keep track of where they are
+    /// (see `diff`) need to override this.
+    fn zip_child<T>(&mut self, step: PathStep, a: &T, b: &T) -> Fallible<()>
+    where
+        T: Zip + ?Sized,
+        Self: Sized,
+    {
+        let _ = step;
+        Zip::zip_with(self, a, b)
+    }
+}
+
+/// A step from a value to one of its children: a named field, or an
+/// element of a list.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PathStep {
+    Field(&'static str),
+    Index(usize),
+}
+
+impl fmt::Display for PathStep {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PathStep::Field(name) => write!(fmt, ".{}", name),
+            PathStep::Index(index) => write!(fmt, "[{}]", index),
+        }
+    }
 }
 
 impl<'f, Z: Zipper> Zipper for &'f mut Z {
@@ -44,12 +80,23 @@ impl<'f, Z: Zipper> Zipper for &'f mut Z {
         (**self).zip_lifetimes(a, b)
     }
 
+    fn zip_consts(&mut self, a: &Const, b: &Const) -> Fallible<()> {
+        (**self).zip_consts(a, b)
+    }
+
     fn zip_binders<T>(&mut self, a: &Binders<T>, b: &Binders<T>) -> Fallible<()>
     where
         T: Zip + Fold<Result = T>,
     {
         (**self).zip_binders(a, b)
     }
+
+    fn zip_child<T>(&mut self, step: PathStep, a: &T, b: &T) -> Fallible<()>
+    where
+        T: Zip + ?Sized,
+    {
+        (**self).zip_child(step, a, b)
+    }
 }
 
 /// The `Zip` trait walks two values, invoking the `Zipper` methods where
@@ -59,7 +106,7 @@ impl<'f, Z: Zipper> Zipper for &'f mut Z {
 ///
 /// To implement the trait, typically you would use one of the macros
 /// like `eq_zip!`, `struct_zip!`, or `enum_zip!`.
-crate trait Zip: Debug {
+pub trait Zip: Debug {
     fn zip_with<Z: Zipper>(zipper: &mut Z, a: &Self, b: &Self) -> Fallible<()>;
 }
 
@@ -87,8 +134,8 @@ impl<T: Zip> Zip for [T] {
             return Err(NoSolution);
         }
 
-        for (a_elem, b_elem) in a.iter().zip(b) {
-            Zip::zip_with(zipper, a_elem, b_elem)?;
+        for (index, (a_elem, b_elem)) in a.iter().zip(b).enumerate() {
+            zipper.zip_child(PathStep::Index(index), a_elem, b_elem)?;
         }
 
         Ok(())
@@ -109,8 +156,8 @@ impl<T: Zip> Zip for Box<T> {
 
 impl<T: Zip, U: Zip> Zip for (T, U) {
     fn zip_with<Z: Zipper>(zipper: &mut Z, a: &Self, b: &Self) -> Fallible<()> {
-        Zip::zip_with(zipper, &a.0, &b.0)?;
-        Zip::zip_with(zipper, &a.1, &b.1)?;
+        zipper.zip_child(PathStep::Index(0), &a.0, &b.0)?;
+        zipper.zip_child(PathStep::Index(1), &a.1, &b.1)?;
         Ok(())
     }
 }
@@ -127,6 +174,12 @@ impl Zip for Lifetime {
     }
 }
 
+impl Zip for Const {
+    fn zip_with<Z: Zipper>(zipper: &mut Z, a: &Self, b: &Self) -> Fallible<()> {
+        zipper.zip_consts(a, b)
+    }
+}
+
 impl<T: Zip + Fold<Result = T>> Zip for Binders<T> {
     fn zip_with<Z: Zipper>(zipper: &mut Z, a: &Self, b: &Self) -> Fallible<()> {
         zipper.zip_binders(a, b)
@@ -160,7 +213,7 @@ macro_rules! struct_zip {
                 // Validate that we have indeed listed all fields
                 let $t { $($field: _),* } = *a;
                 $(
-                    Zip::zip_with(zipper, &a.$field, &b.$field)?;
+                    zipper.zip_child(PathStep::Field(stringify!($field)), &a.$field, &b.$field)?;
                 )*
                 Ok(())
             }
@@ -189,11 +242,30 @@ struct_zip!(UnselectedNormalize { projection, ty });
 struct_zip!(EqGoal { a, b });
 struct_zip!(ProgramClauseImplication { consequence, conditions });
 struct_zip!(Derefs { source, target });
+struct_zip!(ExternalGoal { predicate, parameters });
+struct_zip!(UnevaluatedConst { const_id, parameters });
 
+/// Sorted environments are zipped in the order of their clauses.
+/// Folded ones need not be sorted, but keep the segments of the
+/// environment they were folded from, so they are zipped segment by
+/// segment with the environments of the same shape.
 impl Zip for Environment {
     fn zip_with<Z: Zipper>(zipper: &mut Z, a: &Self, b: &Self) -> Fallible<()> {
-        assert_eq!(a.clauses.len(), b.clauses.len()); // or different numbers of clauses
-        Zip::zip_with(zipper, &a.clauses, &b.clauses)?;
+        if a.len() != b.len() {
+            return Err(NoSolution);
+        }
+        let same_shape = || a.segments().map(<[_]>::len).eq(b.segments().map(<[_]>::len));
+        if !(a.is_sorted() && b.is_sorted()) && same_shape() {
+            let a_clauses = a.segments().flat_map(|segment| segment.iter());
+            let b_clauses = b.segments().flat_map(|segment| segment.iter());
+            for (index, (a_clause, b_clause)) in a_clauses.zip(b_clauses).enumerate() {
+                zipper.zip_child(PathStep::Index(index), a_clause, b_clause)?;
+            }
+        } else {
+            for (index, (a_clause, b_clause)) in a.clauses().zip(b.clauses()).enumerate() {
+                zipper.zip_child(PathStep::Index(index), a_clause, b_clause)?;
+            }
+        }
         Ok(())
     }
 }
@@ -238,7 +310,8 @@ enum_zip!(DomainGoal {
     IsFullyVisible,
     LocalImplAllowed,
     Compatible,
-    DownstreamType
+    DownstreamType,
+    External
 });
 enum_zip!(LeafGoal { DomainGoal, EqGoal });
 enum_zip!(ProgramClause { Implies, ForAll });
@@ -250,16 +323,16 @@ impl Zip for Goal {
     fn zip_with<Z: Zipper>(zipper: &mut Z, a: &Self, b: &Self) -> Fallible<()> {
         match (a, b) {
             (&Goal::Quantified(ref f_a, ref g_a), &Goal::Quantified(ref f_b, ref g_b)) => {
-                Zip::zip_with(zipper, f_a, f_b)?;
-                Zip::zip_with(zipper, g_a, g_b)
+                zipper.zip_child(PathStep::Index(0), f_a, f_b)?;
+                zipper.zip_child(PathStep::Index(1), g_a, g_b)
             }
             (&Goal::Implies(ref f_a, ref g_a), &Goal::Implies(ref f_b, ref g_b)) => {
-                Zip::zip_with(zipper, f_a, f_b)?;
-                Zip::zip_with(zipper, g_a, g_b)
+                zipper.zip_child(PathStep::Index(0), f_a, f_b)?;
+                zipper.zip_child(PathStep::Index(1), g_a, g_b)
             }
             (&Goal::And(ref f_a, ref g_a), &Goal::And(ref f_b, ref g_b)) => {
-                Zip::zip_with(zipper, f_a, f_b)?;
-                Zip::zip_with(zipper, g_a, g_b)
+                zipper.zip_child(PathStep::Index(0), f_a, f_b)?;
+                zipper.zip_child(PathStep::Index(1), g_a, g_b)
             }
             (&Goal::Not(ref f_a), &Goal::Not(ref f_b)) => Zip::zip_with(zipper, f_a, f_b),
             (&Goal::Leaf(ref f_a), &Goal::Leaf(ref f_b)) => Zip::zip_with(zipper, f_a, f_b),
@@ -277,15 +350,23 @@ impl Zip for Goal {
 }
 
 // I'm too lazy to make `enum_zip` support type parameters.
-impl<T: Zip, L: Zip> Zip for ParameterKind<T, L> {
+impl<T: Zip, L: Zip, C: Zip> Zip for ParameterKind<T, L, C> {
     fn zip_with<Z: Zipper>(zipper: &mut Z, a: &Self, b: &Self) -> Fallible<()> {
         match (a, b) {
             (&ParameterKind::Ty(ref a), &ParameterKind::Ty(ref b)) => Zip::zip_with(zipper, a, b),
             (&ParameterKind::Lifetime(ref a), &ParameterKind::Lifetime(ref b)) => {
                 Zip::zip_with(zipper, a, b)
             }
-            (&ParameterKind::Ty(_), _) | (&ParameterKind::Lifetime(_), _) => {
-                panic!("zipping things of mixed kind")
+            (&ParameterKind::Const(ref a), &ParameterKind::Const(ref b)) => {
+                Zip::zip_with(zipper, a, b)
+            }
+            (&ParameterKind::Ty(_), _)
+            | (&ParameterKind::Lifetime(_), _)
+            | (&ParameterKind::Const(_), _) => {
+                // Only reachable from ill-kinded input (e.g., a projection
+                // whose arguments were never kind-checked): treat it as a
+                // mismatch rather than an internal error.
+                Err(NoSolution)
             }
         }
     }
@@ -0,0 +1,76 @@
+//! Checking how deeply the types of a value are nested. Most operations
+//! on the IR recurse into types, so a type nested a few thousand levels
+//! deep -- as produced by fuzzers or by generated code -- overflows the
+//! stack. The check gives up as soon as it goes past its limit, so it
+//! never recurses deeper than that itself; the solver uses it to reject
+//! goals with such types, the root goal before doing anything else with
+//! it and each subgoal before creating a table for it (see
+//! `SolverChoice`).
+//!
+//! The value is zipped with itself rather than folded: that walks it
+//! without building a copy of it, which matters as every subgoal of the
+//! solver is checked.
+//!
+//! Growing the stack on demand with `stacker`, as the engine does when
+//! pursuing strands, would be the alternative. It is not done here: for
+//! deep types to be handled, every recursive operation would have to do
+//! it -- not only folding, but also zipping, `Debug`, the derived
+//! `Clone`, `Hash` and `Eq` impls, and dropping -- and the solver would
+//! still spend its time on goals it cannot make sense of.
+
+use fallible::*;
+use fold::Fold;
+use ir::*;
+use super::{Zip, Zipper};
+
+/// True if `value` has types nested more than `limit` deep.
+pub(crate) fn exceeds_depth<T: Zip>(value: &T, limit: usize) -> bool {
+    let mut zipper = DepthZipper { depth: 0, limit };
+    Zip::zip_with(&mut zipper, value, value).is_err()
+}
+
+/// Walks a value zipped with itself, failing as soon as its types are
+/// nested more than `limit` deep.
+struct DepthZipper {
+    /// The number of types we are within.
+    depth: usize,
+    limit: usize,
+}
+
+impl Zipper for DepthZipper {
+    fn zip_tys(&mut self, ty: &Ty, _: &Ty) -> Fallible<()> {
+        if self.depth == self.limit {
+            return Err(NoSolution);
+        }
+        self.depth += 1;
+        let result = match *ty {
+            Ty::Var(_) => Ok(()),
+            Ty::Apply(ref apply) => Zip::zip_with(self, apply, apply),
+            Ty::Projection(ref proj) => Zip::zip_with(self, proj, proj),
+            Ty::UnselectedProjection(ref proj) => Zip::zip_with(self, proj, proj),
+            Ty::ForAll(ref quantified_ty) => {
+                Zip::zip_with(self, &quantified_ty.ty, &quantified_ty.ty)
+            }
+        };
+        self.depth -= 1;
+        result
+    }
+
+    fn zip_lifetimes(&mut self, _: &Lifetime, _: &Lifetime) -> Fallible<()> {
+        Ok(())
+    }
+
+    fn zip_consts(&mut self, constant: &Const, _: &Const) -> Fallible<()> {
+        match *constant {
+            Const::Unevaluated(ref unevaluated) => Zip::zip_with(self, unevaluated, unevaluated),
+            Const::Var(_) | Const::ForAll(_) | Const::Value(_) => Ok(()),
+        }
+    }
+
+    fn zip_binders<T>(&mut self, binders: &Binders<T>, _: &Binders<T>) -> Fallible<()>
+    where
+        T: Zip + Fold<Result = T>,
+    {
+        Zip::zip_with(self, &binders.value, &binders.value)
+    }
+}
@@ -0,0 +1,91 @@
+#![cfg(test)]
+
+use ir::*;
+use zip::Zip;
+use diff::diff;
+use zip::PathStep::{self, Field, Index};
+
+fn trait_ref(parameters: Vec<Parameter>) -> TraitRef {
+    TraitRef {
+        trait_id: ItemId { index: 0 },
+        parameters,
+    }
+}
+
+fn paths<T: Zip>(a: &T, b: &T) -> Vec<Vec<PathStep>> {
+    diff(a, b).into_iter().map(|entry| entry.path).collect()
+}
+
+#[test]
+fn identical_terms_have_no_diff() {
+    let goal = DomainGoal::Holds(WhereClause::Implemented(trait_ref(vec![
+        arg!((apply (item 1) (apply (item 2)))),
+        arg!((lifetime (var 0))),
+    ])));
+    assert_eq!(diff(&goal, &goal.clone()), vec![]);
+}
+
+#[test]
+fn diff_records_every_divergence() {
+    // `Vec<A>: Foo<B>` against `Vec<C>: Foo<D>`
+    let a = trait_ref(vec![arg!((apply (item 1) (apply (item 2)))), arg!((apply (item 3)))]);
+    let b = trait_ref(vec![arg!((apply (item 1) (apply (item 4)))), arg!((apply (item 5)))]);
+    assert_eq!(
+        paths(&a, &b),
+        vec![
+            vec![Field("parameters"), Index(0), Field("parameters"), Index(0), Field("name")],
+            vec![Field("parameters"), Index(1), Field("name")],
+        ]
+    );
+
+    let entry = &diff(&a, &b)[1];
+    assert_eq!(entry.a, format!("{:?}", TypeName::ItemId(ItemId { index: 3 })));
+    assert_eq!(entry.b, format!("{:?}", TypeName::ItemId(ItemId { index: 5 })));
+    assert!(!entry.under_binder);
+    assert!(entry.to_string().starts_with(".parameters[1].name: "));
+}
+
+#[test]
+fn diff_stops_at_mismatched_shapes() {
+    // A type against a lifetime: the parameters differ as a whole.
+    let a = trait_ref(vec![arg!((apply (item 1)))]);
+    let b = trait_ref(vec![arg!((lifetime (var 0)))]);
+    assert_eq!(paths(&a, &b), vec![vec![Field("parameters"), Index(0)]]);
+
+    // Lists of different lengths.
+    let b = trait_ref(vec![arg!((apply (item 1))), arg!((apply (item 1)))]);
+    assert_eq!(paths(&a, &b), vec![vec![Field("parameters")]]);
+
+    // Different variants at the root.
+    let a = DomainGoal::IsLocal(ty!(apply (item 1)));
+    let b = DomainGoal::IsUpstream(ty!(apply (item 1)));
+    let entries = diff(&a, &b);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].path, vec![]);
+    assert_eq!(entries[0].a, format!("{:?}", a));
+    assert!(entries[0].to_string().starts_with("(root): "));
+}
+
+#[test]
+fn diff_notes_divergences_under_binders() {
+    let a = Binders {
+        binders: vec![ParameterKind::Ty(())],
+        value: trait_ref(vec![arg!((var 0)), arg!((apply (item 1)))]),
+    };
+    let b = Binders {
+        binders: vec![ParameterKind::Ty(())],
+        value: trait_ref(vec![arg!((var 0)), arg!((apply (item 2)))]),
+    };
+    let entries = diff(&a, &b);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].path, vec![Field("value"), Field("parameters"), Index(1), Field("name")]);
+    assert!(entries[0].under_binder);
+
+    // `for<T> Foo<T>` against `for<T> Foo<?0>`
+    let a = ty!(for_all 1 (apply (item 1) (var 0)));
+    let b = ty!(for_all 1 (apply (item 1) (var 1)));
+    let entries = diff(&a, &b);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].path, vec![Field("ty"), Field("parameters"), Index(0)]);
+    assert!(entries[0].under_binder);
+}